@@ -0,0 +1,14 @@
+// Anything at all can come in over a connection, so reading it must never panic, hang or allocate
+// more than it was sent. Whatever's read is written back out too, which mustn't panic either
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use patchwork::models::packet;
+use std::io::{self, Cursor};
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((state, bytes)) = patchwork_fuzz::split(data) {
+        if let Ok(packet) = packet::read(&mut Cursor::new(bytes), state) {
+            packet::write(&mut io::sink(), packet);
+        }
+    }
+});
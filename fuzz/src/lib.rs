@@ -0,0 +1,16 @@
+use patchwork::models::packet::PACKETS;
+
+// Every state there are packets in, with 99 for everything outgoing. Fuzz inputs start with a byte
+// picking one of them, and the rest is a packet's id and body, the way packet::read is given them
+pub fn states() -> Vec<i32> {
+    let mut states: Vec<i32> = PACKETS.iter().filter_map(|(state, _, _)| *state).collect();
+    states.sort_unstable();
+    states.dedup();
+    states
+}
+
+pub fn split(data: &[u8]) -> Option<(i32, &[u8])> {
+    let (selector, packet) = data.split_first()?;
+    let states = states();
+    Some((states[*selector as usize % states.len()], packet))
+}
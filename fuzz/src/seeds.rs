@@ -0,0 +1,50 @@
+// Writes a seed for every packet patchwork knows to corpus/packet_read, so the fuzzer starts out
+// from packets that read and only has to find its way into the corners from there
+use patchwork::models::minecraft_protocol::{MinecraftProtocolReader, MinecraftProtocolWriter};
+use patchwork::models::packet::{self, PACKETS};
+
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+// The longest run of zeroes tried as a packet's body
+const MAX_BODY: usize = 256;
+
+fn main() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus/packet_read");
+    fs::create_dir_all(&directory).unwrap();
+    let states = patchwork_fuzz::states();
+    for (state, id, name) in PACKETS {
+        // Packets read in any state are only seeded in play
+        let state = &state.unwrap_or(3);
+        let selector = states.iter().position(|known| known == state).unwrap() as u8;
+        let mut seed = vec![selector];
+        seed.extend(smallest_packet(*state, *id));
+        fs::write(directory.join(format!("{}-{}", state, name)), seed).unwrap();
+    }
+    println!("Wrote {} seeds to {:?}", PACKETS.len(), directory);
+}
+
+// Zeroes are a valid value for most fields, so the packet written back after reading the shortest
+// run of them that reads is about as small as it gets. Packets that nothing reads as are left with
+// the longest run, which at least gets the fuzzer into their fields
+fn smallest_packet(state: i32, id: i32) -> Vec<u8> {
+    let mut header = Cursor::new(Vec::new());
+    header.write_var_int(id);
+    let header = header.into_inner();
+    for length in 0..=MAX_BODY {
+        let mut bytes = header.clone();
+        bytes.resize(header.len() + length, 0);
+        if let Ok(read) = packet::read(&mut Cursor::new(&bytes), state) {
+            let mut written = Cursor::new(Vec::new());
+            packet::write(&mut written, read);
+            // Without the length in front
+            written.set_position(0);
+            written.read_var_int().unwrap();
+            return written.get_ref()[written.position() as usize..].to_vec();
+        }
+    }
+    let mut bytes = header;
+    bytes.resize(bytes.len() + MAX_BODY, 0);
+    bytes
+}
@@ -0,0 +1,499 @@
+use super::config::PeerKey;
+use super::constants::REPLY_TIMEOUT;
+use super::error::OrLog;
+use super::interfaces::messenger::{self, Messenger};
+use super::interfaces::patchwork::{self, PatchworkState};
+use super::interfaces::peer_auth::{self, PeerAuth};
+use super::interfaces::player::{self, PlayerState, Position};
+use super::models::map::{map_width, Peer, Position as MapPosition};
+use super::services::instance;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+// JSON over HTTP for tooling to look at and manage the node with, doing what the console can:
+//   GET /maps                  every map in the quilt, who owns it and its block of entity ids
+//   GET /players               everyone playing here, and where
+//   POST /peers                {"address": ..., "port": ...} lays out a peer's map next to ours
+//   DELETE /connections/{id}   kicks the player on that connection
+//   POST /peer-keys            {"id": ..., "secret": ...} stages a key peers can sign with
+//   POST /peer-keys/{id}/promote
+//                              {"grace_period": ...} signs with the key from then on, and
+//                              retires the others after that many seconds
+// There's no authentication, so it's only served on localhost. That doesn't keep out browsers on the
+// same host, which will send simple cross-origin requests without asking first, so anything carrying
+// an Origin is refused and bodies have to be declared as JSON, which a page can't do without a
+// preflight we never answer. Pages on a domain rebound to 127.0.0.1 are same-origin to the browser,
+// so requests have to be addressed to 127.0.0.1 or localhost by name too
+const KICK_MESSAGE: &str = "Kicked by an operator";
+
+// Requests are small, anything bigger is refused rather than read
+const MAX_BODY_LENGTH: usize = 4096;
+
+#[derive(Debug, Serialize)]
+struct MapEntry {
+    position: MapPosition,
+    name: Option<String>,
+    owner: Option<Peer>,
+    connected: bool,
+    latency_ms: Option<u128>,
+    entity_id_block: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Promotion {
+    grace_period: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PlayerEntry {
+    conn_id: Uuid,
+    name: String,
+    map: MapPosition,
+    position: Position,
+}
+
+// Answers requests one at a time, like metrics scrapes
+pub fn serve(
+    port: u16,
+    player_state: Sender<player::Operations>,
+    patchwork_state: Sender<patchwork::Operations>,
+    messenger: Sender<messenger::Operations>,
+    peer_auth: Sender<peer_auth::Operations>,
+) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to serve the admin API on port {}: {}", port, e);
+            return;
+        }
+    };
+    info!("Serving the admin API on port {}", port);
+    instance::spawn("admin-api", move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(
+                stream,
+                port,
+                &player_state,
+                &patchwork_state,
+                &messenger,
+                &peer_auth,
+            ) {
+                trace!("Failed to answer admin API request: {}", e);
+            }
+        }
+    });
+}
+
+fn respond<P: PlayerState, PA: PatchworkState, M: Messenger, A: PeerAuth>(
+    mut stream: TcpStream,
+    port: u16,
+    player_state: &P,
+    patchwork_state: &PA,
+    messenger: &M,
+    peer_auth: &A,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    let mut host = None;
+    let mut origin = None;
+    let mut content_type = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.trim().eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("origin") {
+                origin = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.trim().to_string());
+            }
+        }
+    }
+    let method = request_line.split_whitespace().next().unwrap_or_default();
+    let refused = refusal(
+        method,
+        host.as_deref(),
+        port,
+        origin.as_deref(),
+        content_type.as_deref(),
+    );
+    let (status, body) = match (refused, content_length) {
+        (Some(refusal), _) => refusal,
+        (None, length) if length > MAX_BODY_LENGTH => (
+            "413 Payload Too Large",
+            error(format!("Bodies can be at most {} bytes", MAX_BODY_LENGTH)),
+        ),
+        (None, length) => {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+                [method, path] => route(
+                    method,
+                    path,
+                    &String::from_utf8_lossy(&body),
+                    player_state,
+                    patchwork_state,
+                    messenger,
+                    peer_auth,
+                ),
+                _ => ("400 Bad Request", error("Malformed request line")),
+            }
+        }
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+// Why a request can't be trusted to have come from tooling, if it can't
+fn refusal(
+    method: &str,
+    host: Option<&str>,
+    port: u16,
+    origin: Option<&str>,
+    content_type: Option<&str>,
+) -> Option<(&'static str, String)> {
+    let local = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    if !host.is_some_and(|host| local.iter().any(|local| host.eq_ignore_ascii_case(local))) {
+        return Some((
+            "403 Forbidden",
+            error(format!(
+                "Requests have to be for 127.0.0.1:{} or localhost:{}",
+                port, port
+            )),
+        ));
+    }
+    if let Some(origin) = origin {
+        return Some((
+            "403 Forbidden",
+            error(format!("Requests from {} aren't accepted", origin)),
+        ));
+    }
+    let json = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"));
+    match method {
+        "POST" if json != Some(true) => Some((
+            "415 Unsupported Media Type",
+            error("Bodies have to be sent as application/json"),
+        )),
+        _ => None,
+    }
+}
+
+fn route<P: PlayerState, PA: PatchworkState, M: Messenger, A: PeerAuth>(
+    method: &str,
+    path: &str,
+    body: &str,
+    player_state: &P,
+    patchwork_state: &PA,
+    messenger: &M,
+    peer_auth: &A,
+) -> (&'static str, String) {
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+    match (method, &segments[..]) {
+        ("GET", ["maps"]) => match maps(patchwork_state) {
+            Some(maps) => ("200 OK", json!(maps).to_string()),
+            None => unavailable("Patchwork state"),
+        },
+        ("GET", ["players"]) => match players(player_state) {
+            Some(players) => ("200 OK", json!(players).to_string()),
+            None => unavailable("Player state"),
+        },
+        ("POST", ["peers"]) => match serde_json::from_str::<Peer>(body) {
+            Ok(peer) => {
+                info!("Adding {}'s map through the admin API", peer);
+                patchwork_state.new_map(peer.clone()).or_log();
+                ("202 Accepted", json!(peer).to_string())
+            }
+            Err(e) => ("400 Bad Request", error(format!("Not a peer: {}", e))),
+        },
+        ("DELETE", ["connections", conn_id]) => {
+            let conn_id = match Uuid::parse_str(conn_id) {
+                Ok(conn_id) => conn_id,
+                Err(_) => {
+                    return (
+                        "400 Bad Request",
+                        error(format!("{} isn't a connection id", conn_id)),
+                    )
+                }
+            };
+            match players(player_state) {
+                Some(players) if players.iter().any(|player| player.conn_id == conn_id) => {
+                    messenger.kick(conn_id, String::from(KICK_MESSAGE)).or_log();
+                    ("204 No Content", String::new())
+                }
+                Some(_) => (
+                    "404 Not Found",
+                    error(format!("Nobody's playing on connection {}", conn_id)),
+                ),
+                None => unavailable("Player state"),
+            }
+        }
+        // The secret's never sent back
+        ("POST", ["peer-keys"]) => match serde_json::from_str::<PeerKey>(body) {
+            Ok(key) => {
+                let id = key.id.clone();
+                peer_auth.stage(key).or_log();
+                ("202 Accepted", json!({ "id": id }).to_string())
+            }
+            Err(e) => ("400 Bad Request", error(format!("Not a peer key: {}", e))),
+        },
+        ("POST", ["peer-keys", id, "promote"]) => match serde_json::from_str::<Promotion>(body) {
+            Ok(promotion) => {
+                peer_auth
+                    .promote(String::from(*id), promotion.grace_period)
+                    .or_log();
+                (
+                    "202 Accepted",
+                    json!({ "id": id, "grace_period": promotion.grace_period }).to_string(),
+                )
+            }
+            Err(e) => ("400 Bad Request", error(format!("Not a promotion: {}", e))),
+        },
+        (_, ["maps"])
+        | (_, ["players"])
+        | (_, ["peers"])
+        | (_, ["connections", _])
+        | (_, ["peer-keys"])
+        | (_, ["peer-keys", _, "promote"]) => (
+            "405 Method Not Allowed",
+            error(format!("{} isn't allowed on {}", method, path)),
+        ),
+        _ => (
+            "404 Not Found",
+            error(format!("There's nothing at {}", path)),
+        ),
+    }
+}
+
+fn maps<PA: PatchworkState>(patchwork_state: &PA) -> Option<Vec<MapEntry>> {
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.describe_maps(reply_sender).or_log();
+    let maps = reply_receiver
+        .recv_timeout(Duration::from_secs(REPLY_TIMEOUT))
+        .ok()?;
+    Some(
+        maps.into_iter()
+            .map(|map| MapEntry {
+                position: map.position,
+                name: map.name,
+                owner: map.owner,
+                connected: map.connected,
+                latency_ms: map.latency.map(|latency| latency.as_millis()),
+                entity_id_block: map.entity_id_block,
+            })
+            .collect(),
+    )
+}
+
+fn players<P: PlayerState>(player_state: &P) -> Option<Vec<PlayerEntry>> {
+    let (reply_sender, reply_receiver) = channel();
+    player_state.online_players(reply_sender).or_log();
+    let online = reply_receiver
+        .recv_timeout(Duration::from_secs(REPLY_TIMEOUT))
+        .ok()?;
+    Some(
+        online
+            .into_iter()
+            .map(|(conn_id, name, position, dimension)| PlayerEntry {
+                conn_id,
+                name,
+                map: MapPosition {
+                    x: (position.x / map_width() as f64).floor() as i32,
+                    z: (position.z / map_width() as f64).floor() as i32,
+                    dimension,
+                },
+                position,
+            })
+            .collect(),
+    )
+}
+
+fn unavailable(service: &str) -> (&'static str, String) {
+    (
+        "503 Service Unavailable",
+        error(format!("{} is unavailable", service)),
+    )
+}
+
+fn error<S: Into<String>>(message: S) -> String {
+    json!({ "error": message.into() }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::messenger::Operations as MessengerOperations;
+    use crate::interfaces::patchwork::{MapDescription, Operations as PatchworkOperations};
+    use crate::interfaces::peer_auth::Operations as PeerAuthOperations;
+    use crate::interfaces::player::Operations as PlayerOperations;
+    use crate::interfaces::{MockMessenger, MockPatchworkState, MockPeerAuth, MockPlayerState};
+    use crate::models::map::Dimension;
+
+    #[test]
+    fn requests_are_routed_to_the_services_behind_them() {
+        let conn_id = Uuid::from_u128(7);
+        let player_state = MockPlayerState::responding(move |msg| {
+            if let PlayerOperations::Online(msg) = msg {
+                let _ = msg.reply.send(vec![(
+                    conn_id,
+                    String::from("alex"),
+                    Position {
+                        x: f64::from(map_width()) + 1.0,
+                        y: 64.0,
+                        z: -1.0,
+                    },
+                    Dimension::Nether,
+                )]);
+            }
+        });
+        let patchwork_state = MockPatchworkState::responding(|msg| {
+            if let PatchworkOperations::DescribeMaps(msg) = msg {
+                let _ = msg.reply.send(vec![MapDescription {
+                    position: MapPosition::default(),
+                    name: Some(String::from("spawn")),
+                    owner: None,
+                    connected: true,
+                    latency: None,
+                    entity_id_block: 3,
+                }]);
+            }
+        });
+        let messenger = MockMessenger::new();
+        let peer_auth = MockPeerAuth::new();
+        let request = |method: &str, path: &str, body: &str| {
+            route(
+                method,
+                path,
+                body,
+                &player_state,
+                &patchwork_state,
+                &messenger,
+                &peer_auth,
+            )
+        };
+
+        let (status, maps) = request("GET", "/maps", "");
+        assert_eq!(status, "200 OK");
+        assert!(maps.contains(r#""entity_id_block":3"#), "{}", maps);
+        let (status, players) = request("GET", "/players", "");
+        assert_eq!(status, "200 OK");
+        assert!(
+            players.contains(r#""map":{"dimension":"nether","x":1,"z":-1}"#),
+            "{}",
+            players
+        );
+
+        let (status, _) = request(
+            "POST",
+            "/peers",
+            r#"{"address": "10.0.0.2", "port": 25565}"#,
+        );
+        assert_eq!(status, "202 Accepted");
+        assert!(matches!(
+            &patchwork_state.take()[..],
+            [_, PatchworkOperations::New(msg)] if msg.peer.port == 25565
+        ));
+        assert_eq!(request("POST", "/peers", "25565").0, "400 Bad Request");
+
+        assert_eq!(
+            request("DELETE", &format!("/connections/{}", Uuid::nil()), "").0,
+            "404 Not Found"
+        );
+        assert_eq!(
+            request("DELETE", &format!("/connections/{}", conn_id), "").0,
+            "204 No Content"
+        );
+        assert!(matches!(
+            &messenger.take()[..],
+            [MessengerOperations::Kick(msg)] if msg.conn_id == conn_id
+        ));
+
+        let (status, staged) =
+            request("POST", "/peer-keys", r#"{"id": "k2", "secret": "hunter2"}"#);
+        assert_eq!(status, "202 Accepted");
+        assert!(!staged.contains("hunter2"));
+        assert_eq!(
+            request("POST", "/peer-keys/k2/promote", r#"{"grace_period": 60}"#).0,
+            "202 Accepted"
+        );
+        assert!(matches!(
+            &peer_auth.take()[..],
+            [PeerAuthOperations::Stage(stage), PeerAuthOperations::Promote(promote)]
+                if stage.key.secret == "hunter2"
+                    && promote.key_id == "k2"
+                    && promote.grace_period == 60
+        ));
+        assert_eq!(
+            request("POST", "/peer-keys", r#"{"id": "k3"}"#).0,
+            "400 Bad Request"
+        );
+        assert_eq!(request("GET", "/peer-keys", "").0, "405 Method Not Allowed");
+
+        assert_eq!(request("PUT", "/maps", "").0, "405 Method Not Allowed");
+        assert_eq!(request("GET", "/nowhere", "").0, "404 Not Found");
+    }
+
+    #[test]
+    fn requests_a_browser_could_send_are_refused() {
+        let refusal = |method, origin, content_type| {
+            refusal(method, Some("127.0.0.1:25575"), 25575, origin, content_type)
+        };
+        assert!(refusal("GET", None, None).is_none());
+        assert!(refusal("POST", None, Some("application/json; charset=utf-8")).is_none());
+        assert_eq!(
+            refusal("POST", None, Some("text/plain")).unwrap().0,
+            "415 Unsupported Media Type"
+        );
+        assert_eq!(
+            refusal("POST", None, None).unwrap().0,
+            "415 Unsupported Media Type"
+        );
+        assert_eq!(
+            refusal(
+                "POST",
+                Some("https://example.com"),
+                Some("application/json")
+            )
+            .unwrap()
+            .0,
+            "403 Forbidden"
+        );
+        assert_eq!(
+            refusal("GET", Some("null"), None).unwrap().0,
+            "403 Forbidden"
+        );
+    }
+
+    #[test]
+    fn requests_for_other_hosts_are_refused() {
+        let host = |host| super::refusal("GET", host, 25575, None, None).map(|refused| refused.0);
+        assert_eq!(host(Some("127.0.0.1:25575")), None);
+        assert_eq!(host(Some("LOCALHOST:25575")), None);
+        // A rebound domain, the right host on another port, and no host at all
+        assert_eq!(host(Some("attacker.example:25575")), Some("403 Forbidden"));
+        assert_eq!(host(Some("localhost:8080")), Some("403 Forbidden"));
+        assert_eq!(host(Some("localhost")), Some("403 Forbidden"));
+        assert_eq!(host(None), Some("403 Forbidden"));
+    }
+}
@@ -0,0 +1,37 @@
+// Checks sessions between a client and server against patchwork's packets, printing every packet it
+// couldn't read or write back the same way. Sessions go in <directory>/404/*.jsonl, one packet a
+// line, as described in patchwork::conformance. The ones checked in are put together from the
+// protocol's documentation, not recorded from vanilla, see conformance/404/README.md
+use patchwork::conformance;
+
+use std::env;
+use std::process;
+
+// The protocol patchwork itself speaks, 1.13.2
+const PROTOCOL: u16 = 404;
+
+fn main() {
+    let directory = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("conformance"));
+    let report = match conformance::check_directory(&directory, PROTOCOL) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    report
+        .failures
+        .iter()
+        .for_each(|failure| println!("{}", failure));
+    println!(
+        "{} packets written back identically, {} unknown to patchwork, {} failed",
+        report.checked,
+        report.unknown,
+        report.failures.len()
+    );
+    if !report.failures.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,50 @@
+// Boots two nodes in this one process, each with the other's map next to its own, then has a bot
+// join the first node and walk over the seam onto the second node's map. Exits with an error if the
+// bot never turns up on the second node, so it doubles as a smoke test for releases
+use patchwork::config;
+use patchwork::flight_recorder;
+use patchwork::interfaces::player::Position;
+use patchwork::simulation::{self, Simulation};
+use patchwork::test_client::Step;
+
+use std::env;
+use std::process;
+use tracing::level_filters::LevelFilter;
+
+const BOT_NAME: &str = "demo_bot";
+
+fn main() {
+    let level = match env::var("LOG").as_deref() {
+        Ok("info") => LevelFilter::INFO,
+        Ok("trace") => LevelFilter::TRACE,
+        _ => LevelFilter::WARN,
+    };
+    flight_recorder::init(level).unwrap();
+
+    // Both nodes save into a directory of their own, so the demo never touches a real server's
+    let simulation = Simulation::start(&config::load(), 2);
+    let result = cross_the_seam(&simulation);
+    drop(simulation);
+    match result {
+        Ok(position) => println!(
+            "{} crossed onto the second node's map and is at {:?}",
+            BOT_NAME, position
+        ),
+        Err(e) => {
+            eprintln!("Crossing failed: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn cross_the_seam(simulation: &Simulation) -> Result<Position, String> {
+    simulation.wait_for_links()?;
+    let bot = simulation.join(0, BOT_NAME)?;
+
+    // Head for the middle of the second node's map
+    let (x, z) = simulation.map_center(1);
+    bot.run(&[Step::WalkTo { x, z }]);
+
+    simulation::wait_for(|| simulation.positions(1).into_iter().next())
+        .ok_or_else(|| String::from("the bot never turned up on the second node"))
+}
@@ -0,0 +1,44 @@
+// Feeds a patchwork service message log back to a fresh patchwork service, printing each message
+// along with what the service sent the rest of the node when it handled it. Run it with the same
+// CONFIG and PORT as the node that recorded the log, so the service starts out the same way
+use patchwork::flight_recorder;
+use patchwork::models::map::Peer;
+use patchwork::{config, node};
+
+use std::env;
+use std::process;
+use tracing::level_filters::LevelFilter;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: patchwork-replay <message log>");
+            process::exit(2);
+        }
+    };
+    let level = match env::var("LOG").as_deref() {
+        Ok("info") => LevelFilter::INFO,
+        Ok("trace") => LevelFilter::TRACE,
+        // Logs go to stdout along with the replay, which they'd only get in the way of
+        _ => LevelFilter::ERROR,
+    };
+    flight_recorder::init(level).unwrap();
+
+    let config = config::load();
+    node::configure(&config);
+    let local_peer = Peer {
+        port: env::var("PORT")
+            .ok()
+            .and_then(|port| port.parse::<u16>().ok())
+            .unwrap_or(25565),
+        address: config.advertise_address.clone(),
+    };
+    match node::replay_patchwork(config, local_peer, &path) {
+        Ok(described) => described.iter().for_each(|line| println!("{}", line)),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
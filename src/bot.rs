@@ -0,0 +1,158 @@
+use super::interfaces::player::Position;
+use super::models::map::Peer;
+use super::models::minecraft_protocol::MinecraftProtocolReader;
+use super::models::packet::{
+    self, ChatMessage, Handshake, LoginStart, Packet, PlayerPosition, ServerboundKeepAlive,
+};
+use super::services::instance;
+
+use std::io::{self, Cursor, Read};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+// The protocol bots speak, 1.13.2
+const BOT_PROTOCOL: i32 = 404;
+
+type Callback = Box<dyn FnMut(&Packet) + Send>;
+
+// A client for anything that plays without a person behind it: load tests, integration tests and
+// automation. It answers keep alives and goes wherever it's teleported on its own, and hands every
+// packet it's sent to the callbacks registered with on_packet, in the order they were registered.
+// Callbacks run on the bot's reader, so a slow one holds up the rest
+pub struct Bot {
+    peer: Peer,
+    writer: Arc<Mutex<TcpStream>>,
+    position: Arc<Mutex<Option<Position>>>,
+    callbacks: Arc<Mutex<Vec<Callback>>>,
+}
+
+impl Bot {
+    // Nothing's sent until the bot logs in, so callbacks registered in between see every packet
+    pub fn connect(peer: &Peer) -> io::Result<Bot> {
+        let stream = TcpStream::connect((peer.address.as_str(), peer.port))?;
+        let mut reader = stream.try_clone()?;
+        let writer = Arc::new(Mutex::new(stream));
+        let position = Arc::new(Mutex::new(None));
+        let callbacks = Arc::new(Mutex::new(Vec::<Callback>::new()));
+        let (answers, teleports, handlers) = (writer.clone(), position.clone(), callbacks.clone());
+        instance::spawn("bot-reader", move || {
+            while let Ok(length) = reader.read_var_int() {
+                let mut bytes = vec![0; length.max(0) as usize];
+                if reader.read_exact(&mut bytes).is_err() {
+                    break;
+                }
+                // Everything the node sends is read as if it were in play, which the login
+                // packets we get are numbered apart from. Anything we can't read is skipped
+                let packet = match packet::read(&mut Cursor::new(bytes), 99) {
+                    Ok(packet) => packet,
+                    Err(_) => continue,
+                };
+                match &packet {
+                    Packet::KeepAlive(keep_alive) => packet::write(
+                        &mut *answers.lock().unwrap(),
+                        Packet::ServerboundKeepAlive(ServerboundKeepAlive { id: keep_alive.id }),
+                    ),
+                    Packet::ClientboundPlayerPositionAndLook(teleport) => {
+                        *teleports.lock().unwrap() = Some(Position {
+                            x: teleport.x,
+                            y: teleport.y,
+                            z: teleport.z,
+                        })
+                    }
+                    _ => {}
+                }
+                handlers
+                    .lock()
+                    .unwrap()
+                    .iter_mut()
+                    .for_each(|callback| callback(&packet));
+            }
+        });
+        Ok(Bot {
+            peer: peer.clone(),
+            writer,
+            position,
+            callbacks,
+        })
+    }
+
+    // Bots are let in like any offline mode client, by name alone
+    pub fn login(&self, name: &str) {
+        self.send(Packet::Handshake(Handshake {
+            protocol_version: BOT_PROTOCOL,
+            server_address: self.peer.address.clone(),
+            server_port: self.peer.port,
+            next_state: 2,
+        }));
+        self.send(Packet::LoginStart(LoginStart {
+            username: String::from(name),
+        }));
+    }
+
+    pub fn on_packet<F: FnMut(&Packet) + Send + 'static>(&self, callback: F) {
+        self.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+
+    // Where the bot is, once it's been told where it spawned
+    pub fn position(&self) -> Option<Position> {
+        *self.position.lock().unwrap()
+    }
+
+    pub fn send(&self, packet: Packet) {
+        packet::write(&mut *self.writer.lock().unwrap(), packet);
+    }
+
+    pub fn move_to(&self, position: Position) {
+        self.send(Packet::PlayerPosition(PlayerPosition {
+            x: position.x,
+            feet_y: position.y,
+            z: position.z,
+            on_ground: true,
+        }));
+        *self.position.lock().unwrap() = Some(position);
+    }
+
+    pub fn send_chat(&self, message: &str) {
+        self.send(Packet::ChatMessage(ChatMessage {
+            message: String::from(message),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::simulation::{self, Simulation};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn callbacks_see_everything_from_login_on() {
+        let simulation = Simulation::start(
+            &Config {
+                offline: true,
+                ..Config::default()
+            },
+            1,
+        );
+        let bot = simulation::wait_for(|| Bot::connect(&simulation.nodes[0].peer).ok()).unwrap();
+        let (sender, packets) = channel();
+        bot.on_packet(move |packet| {
+            let _ = sender.send(packet.name());
+        });
+        bot.login("automaton");
+        simulation::wait_for(|| bot.position()).expect("the bot never spawned");
+
+        bot.send_chat("beep");
+        let mut names = vec![];
+        while let Ok(name) = packets.recv_timeout(Duration::from_secs(10)) {
+            names.push(name);
+            if name == "ClientboundChatMessage" {
+                break;
+            }
+        }
+        assert_eq!(names.first(), Some(&"LoginSuccess"));
+        assert_eq!(names.last(), Some(&"ClientboundChatMessage"));
+    }
+}
@@ -0,0 +1,128 @@
+use super::models::world_generator::{WorldGenerator, CHUNK_BLOCKS};
+use super::services::instance;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex};
+
+// Generating chunks is left to a small pool of workers so that slow generators never hold up the
+// block state's event loop. Chunks someone is waiting on are generated first, then the ones along
+// the seams that peers will want, then everything else
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Background,
+    BorderPrefetch,
+    Requested,
+}
+
+pub struct ChunkGenPool {
+    queue: Arc<(Mutex<JobQueue>, Condvar)>,
+}
+
+struct JobQueue {
+    jobs: BinaryHeap<Job>,
+    submitted: u64,
+}
+
+struct Job {
+    priority: Priority,
+    sequence: u64,
+    chunk: usize,
+    chunk_x: i32,
+    chunk_z: i32,
+    generator: Arc<dyn WorldGenerator>,
+}
+
+// Highest priority first, and oldest first within a priority
+impl Ord for Job {
+    fn cmp(&self, other: &Job) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Job) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Job) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Job {}
+
+impl ChunkGenPool {
+    // on_generated is called from the worker with the chunk's index and its blocks
+    pub fn new<F: 'static + Fn(usize, Vec<i32>) + Send + Clone>(
+        size: usize,
+        on_generated: F,
+    ) -> ChunkGenPool {
+        let queue = Arc::new((
+            Mutex::new(JobQueue {
+                jobs: BinaryHeap::new(),
+                submitted: 0,
+            }),
+            Condvar::new(),
+        ));
+        (0..size).for_each(|index| {
+            let queue = queue.clone();
+            let on_generated = on_generated.clone();
+            instance::spawn(&format!("chunk-gen-{}", index), move || loop {
+                let job = {
+                    let (jobs, available) = &*queue;
+                    let mut jobs = available
+                        .wait_while(jobs.lock().unwrap(), |queue| queue.jobs.is_empty())
+                        .unwrap();
+                    jobs.jobs.pop().unwrap()
+                };
+                on_generated(
+                    job.chunk,
+                    generate(job.generator.as_ref(), job.chunk_x, job.chunk_z),
+                );
+            });
+        });
+        ChunkGenPool { queue }
+    }
+
+    pub fn submit(
+        &self,
+        priority: Priority,
+        chunk: usize,
+        (chunk_x, chunk_z): (i32, i32),
+        generator: Arc<dyn WorldGenerator>,
+    ) {
+        let (jobs, available) = &*self.queue;
+        let mut jobs = jobs.lock().unwrap();
+        jobs.submitted += 1;
+        let sequence = jobs.submitted;
+        jobs.jobs.push(Job {
+            priority,
+            sequence,
+            chunk,
+            chunk_x,
+            chunk_z,
+            generator,
+        });
+        available.notify_one();
+    }
+}
+
+// Generators are someone else's code, so don't trust them to get the size right
+fn generate(generator: &dyn WorldGenerator, chunk_x: i32, chunk_z: i32) -> Vec<i32> {
+    let mut blocks = generator.generate(chunk_x, chunk_z);
+    if blocks.len() != CHUNK_BLOCKS {
+        warn!(
+            "World generator {:?} made {:?} blocks for a chunk instead of {:?}",
+            generator,
+            blocks.len(),
+            CHUNK_BLOCKS
+        );
+        blocks.resize(CHUNK_BLOCKS, 0);
+    }
+    blocks
+}
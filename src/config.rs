@@ -0,0 +1,574 @@
+use super::interfaces::player::{Position, DEFAULT_SPAWN};
+use super::models::forwarding::ForwardingMode;
+use super::models::map::{Dimension, Peer};
+use super::models::world_generator::DEFAULT_GENERATOR;
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+
+// Settings are read from the JSON file named by the CONFIG environment variable. Anything missing
+// from the file (or the file itself, if CONFIG isn't set) falls back to the defaults below
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub difficulty: Difficulty,
+    pub hardcore: bool,
+    pub reduced_debug_info: bool,
+    // Where granted advancements are kept between restarts
+    pub advancements_file: String,
+    // Where players are saved when they leave, and every so often while they're playing
+    pub players_directory: String,
+    // Where the map's blocks are saved on shutdown and loaded from on startup
+    pub world_file: String,
+    // Where bans and pardons are kept, including those heard of from peers
+    pub bans_file: String,
+    // Each operator's permission level, by name. Everyone who isn't in there is at level 0
+    pub operators_file: String,
+    // Holds a <protocol>/blocks.json from vanilla's data generator for each protocol we speak
+    pub registry_directory: String,
+    // The address other nodes reach us at, which is how we're known to them in gossip, topologies
+    // and handoffs. Loopback will do for nodes that all run on one host
+    pub advertise_address: String,
+    // Register with and discover peers through Consul, if set. etcd isn't supported
+    pub peer_registry: Option<PeerRegistryConfig>,
+    // Lay the quilt out from an exported topology instead of the PEER_PORT peer
+    pub topology_file: Option<String>,
+    // Peers whose maps are added to the quilt, on top of the PEER_PORT one. Picked up again when
+    // the config file changes or on SIGHUP, so peers can come and go without a restart. See the
+    // config_reload service
+    pub peers: Vec<Peer>,
+    // Shared secrets peers authenticate with. The first is the one we sign with
+    pub peer_keys: Vec<PeerKey>,
+    // Split our map off to a standby peer once it gets too crowded, if set
+    pub split: Option<SplitConfig>,
+    // Local address outbound peer connections are made from, if not the default route's
+    pub outbound_bind_address: Option<IpAddr>,
+    // Proxy outbound peer connections are tunnelled through, for peers only reachable via a bastion
+    pub peer_proxy: Option<ProxyConfig>,
+    // Take players' addresses and uuids from the proxy they connect through, if set. See the
+    // forwarding module
+    pub forwarding: Option<ForwardingConfig>,
+    // Plugin channels whose messages are relayed to players on every peer
+    pub plugin_channels: Vec<String>,
+    // How many chunks along each side every map is. Has to match across the whole quilt
+    pub map_size: i32,
+    // The world generator used for chunks not covered by any of the generator regions
+    pub generator: String,
+    pub generator_regions: Vec<GeneratorRegion>,
+    // Block changes within this many blocks of a neighbouring peer's map are shown to that peer's
+    // players
+    pub seam_width: i32,
+    // How close players have to be to an entity to see it, in blocks
+    pub tracking_ranges: TrackingRanges,
+    // Nodes without a map of their own are proxies: every player is anchored to a peer's map, and
+    // peers aren't told about us. Nobody gossips to a proxy, so it only knows the PEER_PORT peer's
+    // map unless it's given a topology file or a peer registry
+    pub local_map: bool,
+    // The dimension our map is in. Peers in other dimensions are reached through portals
+    pub dimension: Dimension,
+    // The world spawn, in the overworld. Should be the same on every node
+    pub spawn: Position,
+    // How fast players can chat before they're warned, then muted, then kicked
+    pub chat_limit: ChatLimit,
+    // Who can join, see the whitelist service
+    pub whitelist: WhitelistConfig,
+    // How many connections are let in at once, see server::listen
+    pub connection_limits: ConnectionLimits,
+    // How fast each client can send the rest of its packets
+    pub rate_limits: PacketRateLimits,
+    // When to warn that a peer link is falling behind, see the link_watermarks module
+    pub peer_link_watermarks: PeerLinkWatermarks,
+    // How much of our bandwidth and time each peer gets, see the peer_quotas module
+    pub peer_quotas: PeerQuotas,
+    // Serve Prometheus metrics over HTTP on this port, if set
+    pub metrics_port: Option<u16>,
+    // Answer server list trackers over the UDP query protocol on this port, if set. See the query
+    // module
+    pub query_port: Option<u16>,
+    // Serve the admin API over HTTP on this port of localhost, if set. See the admin_api module
+    pub admin_port: Option<u16>,
+    // How connections in play are kept alive, see the keep_alive service
+    pub keep_alive: KeepAliveConfig,
+    // How long each spell of weather lasts. Should be the same on every node, as each works out the
+    // next spell for itself
+    pub weather: WeatherConfig,
+    // Record the messages sent to services that support it here, one <service>.jsonl file each,
+    // for patchwork-replay to feed back in. See the message_log module
+    pub message_log_directory: Option<String>,
+    // What peers know us as, along with the id kept in the instance id file. Defaults to the
+    // address peers reach us at
+    pub instance_name: Option<String>,
+    pub instance_id_file: String,
+    // Run as a standalone server on our own map, without looking for peers or splitting off to
+    // them, for working on gameplay without a quilt to run it on
+    pub offline: bool,
+    // Fill our map with mobs and bots that keep moving, for profiling under load. See the stress
+    // module
+    pub stress: Option<StressConfig>,
+    // Cores to pin threads to, by thread name, such as messenger or block_state, so that the busiest
+    // services don't have to share with the rest. Threads not named here go wherever the OS puts
+    // them
+    pub pinned_threads: BTreeMap<String, usize>,
+}
+
+impl Config {
+    // The same settings with every secret blanked out, safe to hand to someone else
+    pub fn redacted(&self) -> Config {
+        let redacted = String::from("<redacted>");
+        Config {
+            peer_keys: self
+                .peer_keys
+                .iter()
+                .map(|key| PeerKey {
+                    id: key.id.clone(),
+                    secret: redacted.clone(),
+                })
+                .collect(),
+            peer_proxy: self.peer_proxy.clone().map(|proxy| ProxyConfig {
+                password: proxy.password.map(|_| redacted.clone()),
+                ..proxy
+            }),
+            forwarding: self.forwarding.clone().map(|forwarding| ForwardingConfig {
+                secret: redacted.clone(),
+                ..forwarding
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            difficulty: Difficulty::Peaceful,
+            hardcore: false,
+            reduced_debug_info: false,
+            advancements_file: String::from("advancements.json"),
+            players_directory: String::from("players"),
+            world_file: String::from("world.json"),
+            bans_file: String::from("bans.json"),
+            operators_file: String::from("ops.json"),
+            registry_directory: String::from("registries"),
+            advertise_address: String::from("127.0.0.1"),
+            peer_registry: None,
+            topology_file: None,
+            peers: Vec::new(),
+            peer_keys: Vec::new(),
+            split: None,
+            outbound_bind_address: None,
+            peer_proxy: None,
+            forwarding: None,
+            plugin_channels: Vec::new(),
+            map_size: 1,
+            generator: String::from(DEFAULT_GENERATOR),
+            generator_regions: Vec::new(),
+            seam_width: 16,
+            tracking_ranges: TrackingRanges::default(),
+            local_map: true,
+            dimension: Dimension::default(),
+            spawn: DEFAULT_SPAWN,
+            chat_limit: ChatLimit::default(),
+            whitelist: WhitelistConfig::default(),
+            connection_limits: ConnectionLimits::default(),
+            rate_limits: PacketRateLimits::default(),
+            peer_link_watermarks: PeerLinkWatermarks::default(),
+            peer_quotas: PeerQuotas::default(),
+            metrics_port: None,
+            query_port: None,
+            admin_port: None,
+            keep_alive: KeepAliveConfig::default(),
+            weather: WeatherConfig::default(),
+            message_log_directory: None,
+            instance_name: None,
+            instance_id_file: String::from("instance_id"),
+            offline: false,
+            stress: None,
+            pinned_threads: BTreeMap::new(),
+        }
+    }
+}
+
+// Players also show up to a neighbouring peer's players once they're within the player range of
+// that peer's map
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TrackingRanges {
+    pub players: i32,
+    pub mobs: i32,
+    pub items: i32,
+}
+
+impl Default for TrackingRanges {
+    fn default() -> TrackingRanges {
+        TrackingRanges {
+            players: 64,
+            mobs: 48,
+            items: 32,
+        }
+    }
+}
+
+// Each player can send a burst of messages, and then per_second more every second. Messages over
+// that are refused with a warning, and a player who's been warned too many times is muted. Too many
+// mutes and they're kicked
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ChatLimit {
+    pub burst: u32,
+    pub per_second: f64,
+    pub warnings: u32,
+    pub mute_seconds: u64,
+    pub mutes_before_kick: u32,
+}
+
+impl Default for ChatLimit {
+    fn default() -> ChatLimit {
+        ChatLimit {
+            burst: 5,
+            per_second: 1.0,
+            warnings: 3,
+            mute_seconds: 30,
+            mutes_before_kick: 3,
+        }
+    }
+}
+
+// While the whitelist's on, only the players listed in the file can join. Anyone else is kicked
+// with the message
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WhitelistConfig {
+    pub enabled: bool,
+    pub file: String,
+    pub message: String,
+}
+
+impl Default for WhitelistConfig {
+    fn default() -> WhitelistConfig {
+        WhitelistConfig {
+            enabled: false,
+            file: String::from("whitelist.json"),
+            message: String::from("You are not whitelisted on this server"),
+        }
+    }
+}
+
+// The most connections open at once, and from any one address. Peers' connections count too.
+// Loopback has no limit of its own, as nodes on the same host as us and anything tunnelled to us
+// connect from it, and neither do the unlimited addresses, such as that of the proxy players
+// connect through
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectionLimits {
+    pub max_connections: usize,
+    pub max_per_address: usize,
+    pub unlimited_addresses: Vec<IpAddr>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections: 512,
+            max_per_address: 32,
+            unlimited_addresses: Vec::new(),
+        }
+    }
+}
+
+// Clients can send a burst of each category of packet, then per_second more every second. Packets
+// over that are dropped, and a client that has drops_before_kick of one category dropped before it
+// slows down enough for that category's burst to fill back up is kicked
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PacketRateLimits {
+    pub movement: PacketRate,
+    pub interaction: PacketRate,
+    pub other: PacketRate,
+    pub drops_before_kick: u32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct PacketRate {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl Default for PacketRateLimits {
+    fn default() -> PacketRateLimits {
+        // Clients move at most once a tick, but catch up in bursts after a lag spike
+        PacketRateLimits {
+            movement: PacketRate {
+                burst: 100,
+                per_second: 40.0,
+            },
+            interaction: PacketRate {
+                burst: 60,
+                per_second: 30.0,
+            },
+            other: PacketRate {
+                burst: 60,
+                per_second: 20.0,
+            },
+            drops_before_kick: 100,
+        }
+    }
+}
+
+// A peer link is flagged once this many packets are waiting to be sent to the peer, or once packets
+// from the peer wait this long before they're handled, until it's back down to half of that
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PeerLinkWatermarks {
+    pub outbound_backlog: usize,
+    pub inbound_lag_ms: u64,
+}
+
+impl Default for PeerLinkWatermarks {
+    fn default() -> PeerLinkWatermarks {
+        PeerLinkWatermarks {
+            outbound_backlog: 1024,
+            inbound_lag_ms: 250,
+        }
+    }
+}
+
+// What any one peer instance can have us do, across all of its connections. Packets passed on from
+// its map come in bursts of up to relayed_burst, then relayed_per_second. Requests for our whole
+// state, chunks and all, come in bursts of state_report_burst, then state_reports_per_minute
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PeerQuotas {
+    pub relayed_per_second: f64,
+    pub relayed_burst: u32,
+    pub anchored_players: usize,
+    pub state_reports_per_minute: f64,
+    pub state_report_burst: u32,
+}
+
+impl Default for PeerQuotas {
+    fn default() -> PeerQuotas {
+        PeerQuotas {
+            relayed_per_second: 500.0,
+            relayed_burst: 2000,
+            anchored_players: 50,
+            state_reports_per_minute: 6.0,
+            state_report_burst: 4,
+        }
+    }
+}
+
+// Mobs go round in rings about the spawn point, radius blocks across, and bots named stress_<n>
+// join and walk in circles of their own. Each of them moves every tick
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StressConfig {
+    pub mobs: usize,
+    pub players: usize,
+    pub radius: f64,
+}
+
+impl Default for StressConfig {
+    fn default() -> StressConfig {
+        StressConfig {
+            mobs: 200,
+            players: 20,
+            radius: 24.0,
+        }
+    }
+}
+
+// Clients are sent a keep-alive every period and have timeout seconds to answer it. Anchors from our
+// peers aren't sent anything, as nobody reads what's sent down them, but they pass on their player's
+// answers to the keep-alives of the node the player's on, so their period is how often one of those
+// is expected and should be no shorter than the clients' period anywhere on the quilt. Either is
+// dropped once it's missed more than allowed_misses in a row
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KeepAlivePolicy {
+    pub period_seconds: u64,
+    pub timeout_seconds: u64,
+    pub allowed_misses: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KeepAliveConfig {
+    pub clients: KeepAlivePolicy,
+    pub anchors: KeepAlivePolicy,
+}
+
+// The same as vanilla's, which clients are used to
+impl Default for KeepAlivePolicy {
+    fn default() -> KeepAlivePolicy {
+        KeepAlivePolicy {
+            period_seconds: 15,
+            timeout_seconds: 30,
+            allowed_misses: 1,
+        }
+    }
+}
+
+// Clear skies are followed by rain, or a thunderstorm thunder_percent of the time, and those by
+// clear skies again. Each spell lasts somewhere between its min and max ticks
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WeatherConfig {
+    // Off keeps the skies clear for good
+    pub cycle: bool,
+    pub clear: TickRange,
+    pub rain: TickRange,
+    pub thunder: TickRange,
+    pub thunder_percent: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TickRange {
+    pub min: i64,
+    pub max: i64,
+}
+
+// About as long as vanilla's spells
+impl Default for WeatherConfig {
+    fn default() -> WeatherConfig {
+        WeatherConfig {
+            cycle: true,
+            clear: TickRange {
+                min: 12000,
+                max: 180000,
+            },
+            rain: TickRange {
+                min: 12000,
+                max: 24000,
+            },
+            thunder: TickRange {
+                min: 3600,
+                max: 15600,
+            },
+            thunder_percent: 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SplitConfig {
+    pub max_players: usize,
+    // Idle nodes we can recruit to take on part of our players
+    pub standby_peers: Vec<Peer>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProxyConfig {
+    pub protocol: ProxyProtocol,
+    pub address: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ForwardingConfig {
+    pub mode: ForwardingMode,
+    // What modern forwarding is signed with, the same as the proxy's. Legacy forwarding isn't
+    // signed at all
+    #[serde(default)]
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+    Socks5,
+    Http,
+}
+
+// Chunks in our map from one corner to the other (inclusive) are made by the named generator, which
+// can be registered with the block state at any point, the chunks are left empty until it is
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GeneratorRegion {
+    pub generator: String,
+    pub from_chunk_x: i32,
+    pub from_chunk_z: i32,
+    pub to_chunk_x: i32,
+    pub to_chunk_z: i32,
+}
+
+impl GeneratorRegion {
+    pub fn contains(&self, chunk_x: i32, chunk_z: i32) -> bool {
+        chunk_x >= min(self.from_chunk_x, self.to_chunk_x)
+            && chunk_x <= max(self.from_chunk_x, self.to_chunk_x)
+            && chunk_z >= min(self.from_chunk_z, self.to_chunk_z)
+            && chunk_z <= max(self.from_chunk_z, self.to_chunk_z)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PeerKey {
+    pub id: String,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PeerRegistryConfig {
+    pub consul_address: String,
+    pub consul_port: u16,
+    pub service_name: String,
+    // The address other nodes should find us at in the registry, if not the advertise_address
+    // they know us by otherwise
+    pub advertise_address: Option<String>,
+    pub poll_period: u64, //seconds
+}
+
+impl Default for PeerRegistryConfig {
+    fn default() -> PeerRegistryConfig {
+        PeerRegistryConfig {
+            consul_address: String::from("127.0.0.1"),
+            consul_port: 8500,
+            service_name: String::from("patchwork"),
+            advertise_address: None,
+            poll_period: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Peaceful,
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn id(self) -> u8 {
+        match self {
+            Difficulty::Peaceful => 0,
+            Difficulty::Easy => 1,
+            Difficulty::Normal => 2,
+            Difficulty::Hard => 3,
+        }
+    }
+}
+
+pub fn load() -> Config {
+    match path() {
+        Some(path) => read(&path).unwrap_or_else(|e| panic!("{}", e)),
+        None => Config::default(),
+    }
+}
+
+// The config file, if we were given one
+pub fn path() -> Option<String> {
+    env::var("CONFIG").ok()
+}
+
+pub fn read(path: &str) -> Result<Config, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {:?}", path, e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse config file {}: {:?}", path, e))
+}
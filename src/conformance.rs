@@ -0,0 +1,270 @@
+use super::models::minecraft_protocol::MinecraftProtocolReader;
+use super::models::packet::{self, Packet};
+
+use serde::Deserialize;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+// Clientbound packets patchwork reads outside of play, with the state it keeps them under. Play
+// packets with the same ids aren't these, and these aren't anything else in their own state
+const OUTSIDE_PLAY: &[(ProtocolState, i32, i32)] = &[
+    (ProtocolState::Status, 0, 99),
+    (ProtocolState::Status, 1, 99),
+    (ProtocolState::Login, 0, 98),
+    (ProtocolState::Login, 2, 99),
+];
+
+// Clientbound play packets that peers forward to their subscribers as they are are kept under the
+// subscription state rather than 99
+const SUBSCRIPTION_STATE: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolState {
+    Handshaking,
+    Status,
+    Login,
+    Play,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+// One packet out of a session between a client and server, with its id and body in hex the way they
+// would be on the wire before compression, without the length in front
+#[derive(Debug, Deserialize)]
+struct CapturedPacket {
+    state: ProtocolState,
+    direction: Direction,
+    hex: String,
+}
+
+// How a capture went. Packets patchwork has no layout for are only counted, since it passes those
+// on untouched anyway
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checked: usize,
+    pub unknown: usize,
+    pub failures: Vec<String>,
+}
+
+// Checks every capture (*.jsonl) in the protocol's directory, for example conformance/404
+pub fn check_directory(directory: &str, protocol: u16) -> Result<Report, String> {
+    let path = Path::new(directory).join(protocol.to_string());
+    let mut captures: Vec<_> = fs::read_dir(&path)
+        .map_err(|_| format!("No captures at {:?}", path))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|capture| {
+            capture
+                .extension()
+                .is_some_and(|extension| extension == "jsonl")
+        })
+        .collect();
+    captures.sort();
+
+    let mut report = Report::default();
+    for capture in captures {
+        let contents = fs::read_to_string(&capture)
+            .map_err(|e| format!("Failed to read capture {:?}: {}", capture, e))?;
+        check_capture(&capture.to_string_lossy(), &contents, &mut report);
+    }
+    Ok(report)
+}
+
+pub fn check_capture(name: &str, contents: &str, report: &mut Report) {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .for_each(|(index, line)| {
+            let checked = serde_json::from_str::<CapturedPacket>(line)
+                .map_err(|e| format!("not a captured packet: {}", e))
+                .and_then(|captured| {
+                    let bytes =
+                        hex::decode(captured.hex.trim()).map_err(|e| format!("bad hex: {}", e))?;
+                    check_packet(captured.state, captured.direction, &bytes)
+                });
+            match checked {
+                Ok(true) => report.checked += 1,
+                Ok(false) => report.unknown += 1,
+                Err(e) => report
+                    .failures
+                    .push(format!("{}:{}: {}", name, index + 1, e)),
+            }
+        });
+}
+
+// Reads the packet and writes it back out, which has to give the same bytes. Whether patchwork
+// knew the packet at all comes back on success
+pub fn check_packet(
+    state: ProtocolState,
+    direction: Direction,
+    bytes: &[u8],
+) -> Result<bool, String> {
+    let id = Cursor::new(bytes)
+        .read_var_int()
+        .map_err(|e| format!("no packet id: {}", e))?;
+    let mut packet = None;
+    for patchwork_state in patchwork_states(state, direction, id) {
+        match packet::read(&mut Cursor::new(bytes), patchwork_state)
+            .map_err(|e| format!("{:?} {:?}: {}", direction, state, e))?
+        {
+            Packet::Unknown(_) => continue,
+            known => {
+                packet = Some(known);
+                break;
+            }
+        }
+    }
+    let packet = match packet {
+        Some(packet) => packet,
+        None => return Ok(false),
+    };
+
+    let name = packet.name();
+    let mut written = Cursor::new(Vec::new());
+    packet::write(&mut written, packet);
+    written.set_position(0);
+    // Everything after the length is what was captured
+    written.read_var_int().unwrap();
+    let written = &written.get_ref()[written.position() as usize..];
+    if written != bytes {
+        return Err(format!(
+            "{} was written back as {} instead of {}",
+            name,
+            hex::encode(written),
+            hex::encode(bytes)
+        ));
+    }
+    Ok(true)
+}
+
+// The states a packet could be kept under, in the order to try them. Serverbound packets are kept
+// under their own state, and clientbound ones under 99 (or 98 where another outgoing packet has
+// their id already), or the subscription state if they're play packets peers forward
+fn patchwork_states(state: ProtocolState, direction: Direction, id: i32) -> Vec<i32> {
+    if direction == Direction::Serverbound {
+        return vec![state as i32];
+    }
+    if let Some((_, _, patchwork_state)) = OUTSIDE_PLAY
+        .iter()
+        .find(|(outside, outside_id, _)| *outside == state && *outside_id == id)
+    {
+        return vec![*patchwork_state];
+    }
+    if state != ProtocolState::Play {
+        return Vec::new();
+    }
+    let claimed = OUTSIDE_PLAY
+        .iter()
+        .any(|(_, outside_id, _)| *outside_id == id);
+    if claimed {
+        vec![SUBSCRIPTION_STATE]
+    } else {
+        vec![99, SUBSCRIPTION_STATE]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::SERVER_PROTOCOL;
+    use crate::models::packet::{Handshake, KeepAlive, LoginSuccess, StatusResponse};
+
+    fn captured(state: &str, direction: &str, packet: Packet) -> String {
+        let mut written = Cursor::new(Vec::new());
+        packet::write(&mut written, packet);
+        written.set_position(0);
+        written.read_var_int().unwrap();
+        let body = &written.get_ref()[written.position() as usize..];
+        format!(
+            r#"{{"state": "{}", "direction": "{}", "hex": "{}"}}"#,
+            state,
+            direction,
+            hex::encode(body)
+        )
+    }
+
+    #[test]
+    fn packets_are_read_in_the_state_they_were_captured_in() {
+        let capture = [
+            captured(
+                "handshaking",
+                "serverbound",
+                Packet::Handshake(Handshake {
+                    protocol_version: 404,
+                    server_address: String::from("localhost"),
+                    server_port: 25565,
+                    next_state: 2,
+                }),
+            ),
+            captured(
+                "status",
+                "clientbound",
+                Packet::StatusResponse(StatusResponse {
+                    json_response: String::from("{}"),
+                }),
+            ),
+            captured(
+                "login",
+                "clientbound",
+                Packet::LoginSuccess(LoginSuccess {
+                    uuid: String::from("00000000-0000-0000-0000-000000000000"),
+                    username: String::from("someone"),
+                }),
+            ),
+            captured(
+                "play",
+                "clientbound",
+                Packet::KeepAlive(KeepAlive { id: 1234 }),
+            ),
+            // Spawn Global Entity, which has LoginSuccess's id
+            String::from(r#"{"state": "play", "direction": "clientbound", "hex": "0201"}"#),
+            // A keep alive with a byte too many
+            String::from(
+                r#"{"state": "play", "direction": "clientbound", "hex": "2100000000000004d200"}"#,
+            ),
+        ]
+        .join("\n");
+
+        let mut report = Report::default();
+        check_capture("capture", &capture, &mut report);
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.unknown, 1);
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].starts_with("capture:6:"));
+    }
+
+    // Vanilla clients reuse the ids of clientbound packets we read from peers, which must not be
+    // mistaken for them
+    #[test]
+    fn serverbound_play_packets_sharing_clientbound_ids_are_read_as_unknown() {
+        let capture = [
+            // Player Block Placement, with EntityLookAndMove's id
+            r#"{"state": "play", "direction": "serverbound", "hex": "290000028103fffffb01003f0000003f8000003f000000"}"#,
+            // Tab-Complete of "/tp ", with SpawnPlayer's id
+            r#"{"state": "play", "direction": "serverbound", "hex": "0501042f747020"}"#,
+        ]
+        .join("\n");
+
+        let mut report = Report::default();
+        check_capture("capture", &capture, &mut report);
+        assert!(report.failures.is_empty(), "{:#?}", report.failures);
+        assert_eq!(report.unknown, 2);
+    }
+
+    // Sessions live in conformance/<protocol>, see the README there. They're put together from the
+    // protocol's documentation rather than recorded from vanilla, so they only hold patchwork to that
+    #[test]
+    fn documented_sessions_are_read_and_written_back_identically() {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/conformance");
+        let report = check_directory(directory, SERVER_PROTOCOL).unwrap();
+        assert!(report.failures.is_empty(), "{:#?}", report.failures);
+        assert!(report.checked > 0, "Nothing in the captures was checked");
+    }
+}
@@ -0,0 +1,175 @@
+// Commands typed into the node's terminal, for running it without a game client to hand. Most are
+// run by the command service the way in-game ones are, as an operator at the highest level, and
+// their answers are printed back out
+use super::constants::REPLY_TIMEOUT;
+use super::error::OrLog;
+use super::interfaces::command::CommandService;
+use super::interfaces::patchwork::PatchworkState;
+use super::models::map::Peer;
+use super::models::operator_store::MAX_PERMISSION_LEVEL;
+use super::node::Node;
+use super::services::instance;
+
+use std::io::{self, BufRead};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+
+const USAGE: &str = "Commands: stop, addpeer <host:port>, list, kick <player> [reason], \
+                     whitelist add|remove|list|on|off [player or uuid], op <player> [level], \
+                     deop <player>, ban <player, uuid or address> [duration] [reason], \
+                     pardon <player, uuid or address>, report <file>";
+
+// Those handed to the command service as they were typed
+const COMMANDS: [&str; 8] = [
+    "list",
+    "kick",
+    "whitelist",
+    "op",
+    "deop",
+    "ban",
+    "pardon",
+    "report",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleCommand {
+    Stop,
+    AddPeer(Peer),
+    Command(String),
+}
+
+// Reads commands from stdin until it's closed. Stop is passed on to whoever's waiting to shut the
+// node down, everything else is dealt with here
+pub fn start(node: &Node, stop: Sender<()>) {
+    let patchwork_state = node.patchwork_state.clone();
+    let command_service = node.command_service.clone();
+    instance::spawn("console", move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let command = match parse(&line) {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            trace!("Running console command {:?}", command);
+            match command {
+                ConsoleCommand::Stop => {
+                    let _ = stop.send(());
+                    break;
+                }
+                ConsoleCommand::AddPeer(peer) => {
+                    println!("Adding {}'s map", peer);
+                    patchwork_state.new_map(peer).or_log();
+                }
+                ConsoleCommand::Command(command) => println!("{}", run(command, &command_service)),
+            }
+        }
+    });
+}
+
+pub fn parse(line: &str) -> Result<ConsoleCommand, String> {
+    let args: Vec<&str> = line
+        .trim()
+        .trim_start_matches('/')
+        .split_whitespace()
+        .collect();
+    match args.as_slice() {
+        ["stop"] => Ok(ConsoleCommand::Stop),
+        ["addpeer", peer] => {
+            let (address, port) = peer
+                .rsplit_once(':')
+                .ok_or_else(|| format!("{} isn't a host:port", peer))?;
+            let port = port.parse().map_err(|_| format!("{} isn't a port", port))?;
+            Ok(ConsoleCommand::AddPeer(Peer {
+                address: String::from(address),
+                port,
+            }))
+        }
+        [command, ..] if COMMANDS.contains(command) => Ok(ConsoleCommand::Command(args.join(" "))),
+        _ => Err(String::from(USAGE)),
+    }
+}
+
+fn run<C: CommandService>(command: String, command_service: &C) -> String {
+    let (reply_sender, reply_receiver) = channel();
+    command_service
+        .run(command, MAX_PERMISSION_LEVEL, reply_sender)
+        .or_log();
+    match reply_receiver.recv_timeout(Duration::from_secs(REPLY_TIMEOUT)) {
+        Ok(Ok(feedback)) | Ok(Err(feedback)) => feedback,
+        Err(_) => String::from("The command service is unavailable"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::command::Operations;
+
+    #[test]
+    fn commands_are_parsed_with_their_arguments() {
+        assert_eq!(parse(" stop "), Ok(ConsoleCommand::Stop));
+        assert_eq!(
+            parse("kick  Griefer stop that"),
+            Ok(ConsoleCommand::Command(String::from(
+                "kick Griefer stop that"
+            )))
+        );
+        assert_eq!(
+            parse("whitelist add Steve"),
+            Ok(ConsoleCommand::Command(String::from("whitelist add Steve")))
+        );
+        assert_eq!(
+            parse("op Steve 3"),
+            Ok(ConsoleCommand::Command(String::from("op Steve 3")))
+        );
+        assert_eq!(
+            parse("ban Griefer 7d stop that"),
+            Ok(ConsoleCommand::Command(String::from(
+                "ban Griefer 7d stop that"
+            )))
+        );
+        assert_eq!(
+            parse("pardon 10.0.0.1"),
+            Ok(ConsoleCommand::Command(String::from("pardon 10.0.0.1")))
+        );
+        assert_eq!(
+            parse("/report bundle.json"),
+            Ok(ConsoleCommand::Command(String::from("report bundle.json")))
+        );
+        assert_eq!(
+            parse("addpeer example.com:25566"),
+            Ok(ConsoleCommand::AddPeer(Peer {
+                address: String::from("example.com"),
+                port: 25566,
+            }))
+        );
+        assert!(parse("addpeer example.com").is_err());
+        assert_eq!(parse("teleport"), Err(String::from(USAGE)));
+    }
+
+    #[test]
+    fn commands_are_run_as_an_operator_at_the_highest_level() {
+        let (sender, receiver) = channel();
+        let command_service = std::thread::spawn(move || match receiver.recv() {
+            Ok(Operations::Run(msg)) => {
+                let _ = msg.reply.send(Ok(format!(
+                    "Ran {} at level {}",
+                    msg.command, msg.permission_level
+                )));
+            }
+            _ => panic!("Expected the command to be run"),
+        });
+        assert_eq!(
+            run(String::from("list"), &sender),
+            format!("Ran list at level {}", MAX_PERMISSION_LEVEL)
+        );
+        command_service.join().unwrap();
+    }
+}
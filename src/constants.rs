@@ -5,3 +5,136 @@ pub const SERVER_MAX_CAPACITY: u16 = 50;
 pub const SERVER_VERSION: &str = "1.13.2";
 pub const SERVER_PROTOCOL: u16 = 404;
 pub const SERVER_DESCRIPTION: &str = "Welcome to the jungle.";
+
+// Entity ids for non-player entities start here within this server's entity id block so that
+// they never collide with player ids. Player ids start from 1, as a player with an id of 0 is yet
+// to be leased one. Ids after 949 are reserved for anchored players, whose id is 949 plus their id
+// on the server they're anchored from, which leaves room for 50 players of our own.
+pub const MOB_ENTITY_ID_START: i32 = 500;
+pub const ANCHORED_PLAYER_ENTITY_ID_START: i32 = 949;
+
+// Peers are sent a heartbeat every period, and their map is considered unavailable once this many
+// heartbeats in a row go unanswered
+pub const PEER_HEARTBEAT_PERIOD: u64 = 5;
+pub const PEER_HEARTBEAT_MISS_THRESHOLD: u32 = 3;
+
+// How often, in seconds, the config file's checked for changes to reload
+pub const CONFIG_RELOAD_PERIOD: u64 = 2;
+
+// Milliseconds between refreshes of the HUD players can turn on with /hud
+pub const HUD_PERIOD: u64 = 500;
+
+// How often we tell our peers about every peer we know of
+pub const GOSSIP_PERIOD: u64 = 10;
+
+// Relayed packets are dropped once they've been passed on by this many peers
+pub const MAX_RELAY_HOPS: u8 = 8;
+
+// How many of the relayed packets we've seen are remembered, so that copies arriving over other
+// routes through the quilt can be dropped
+pub const RELAY_SEEN_LIMIT: usize = 4096;
+
+// Clients are sent this when the server shuts down, and services that haven't stopped this many
+// seconds after being told to are left behind
+pub const SHUTDOWN_MESSAGE: &str = "Server closed";
+pub const SHUTDOWN_TIMEOUT: u64 = 10;
+
+// Packets longer than these are refused before being read, see ConnectionClass::policy. Players are
+// held to vanilla's limit, while peers hand whole maps over in a single packet. Clients who send one,
+// or a packet that can't be read, are kicked with the message
+pub const PLAYER_MAX_PACKET_LENGTH: i32 = 1 << 21;
+pub const PEER_LINK_MAX_PACKET_LENGTH: i32 = 1 << 24;
+
+// Packets at least this long are compressed on peer links, like vanilla's default threshold. See
+// ConnectionClass::policy
+pub const PEER_LINK_COMPRESSION_THRESHOLD: usize = 256;
+pub const MALFORMED_PACKET_MESSAGE: &str = "Received a malformed packet";
+
+// How deeply lists and compounds can be nested in nbt we read, like vanilla, so that a packet can't
+// recurse us out of stack
+pub const MAX_NBT_DEPTH: usize = 512;
+
+// Connections over the limits are told this before they're closed
+pub const SERVER_FULL_MESSAGE: &str = "The server is full";
+
+// Clients who keep sending packets faster than their rate limits allow are kicked with this
+pub const RATE_LIMITED_MESSAGE: &str = "Sending packets too quickly";
+
+// Banned players are kicked with this, followed by the reason they were banned
+pub const BANNED_MESSAGE: &str = "You are banned from this server";
+
+// Shown to players who reach us without going through the proxy we're set up to be behind
+pub const UNFORWARDED_MESSAGE: &str = "Please connect through the server's proxy";
+
+// How many seconds a kick waits on the packets a client has yet to be sent before it disconnects them
+pub const KICK_FLUSH_TIMEOUT: u64 = 1;
+
+// Players who fall below this height have fallen out of the world, and die
+pub const VOID_DEPTH: f64 = -64.0;
+
+// A service that panics more than this many times in this many seconds is given up on, see the
+// instance module
+pub const SERVICE_MAX_RESTARTS: usize = 5;
+pub const SERVICE_RESTART_WINDOW: u64 = 60;
+
+// Seconds between saves of every player
+pub const PLAYER_AUTOSAVE_PERIOD: u64 = 60;
+
+// How many event loops player state is spread over
+pub const PLAYER_STATE_SHARDS: usize = 4;
+
+// Milliseconds between entity ticks, 20 a second like vanilla
+pub const ENTITY_TICK_PERIOD: u64 = 50;
+
+// Milliseconds between world time ticks, which go at the same rate. Players are told the time every
+// TIME_UPDATE_TICKS ticks like in vanilla, and peers every PEER_TIME_SYNC_TICKS
+pub const WORLD_TICK_PERIOD: u64 = 50;
+pub const TIME_UPDATE_TICKS: i64 = 20;
+pub const PEER_TIME_SYNC_TICKS: i64 = 100;
+
+// Ticks before a dropped item can be picked up, and before it despawns
+pub const ITEM_PICKUP_DELAY: i16 = 40;
+pub const ITEM_DESPAWN_AGE: i32 = 6000;
+
+// Seconds the console and admin API wait on the services before giving up on an answer
+pub const REPLY_TIMEOUT: u64 = 5;
+
+// How long to wait on peers to say whether they own an entity we're looking for, for peers that go
+// before they answer
+pub const ENTITY_OWNER_QUERY_TIMEOUT: u64 = 2;
+
+// How often we check whether our map has gotten crowded enough to split
+pub const LOAD_CHECK_PERIOD: u64 = 30;
+
+// How many of the most recent log entries are kept for support bundles
+pub const FLIGHT_RECORDER_ENTRIES: usize = 1000;
+
+// Peer credentials are only accepted within this many seconds of when they were issued
+pub const PEER_AUTH_MAX_CLOCK_SKEW: i64 = 60;
+
+// Threads the messenger hands expensive outgoing packets to, see the transform_pool module
+pub const TRANSFORM_POOL_WORKERS: usize = 2;
+
+// How many packets each class of connection can have waiting on the transform pool, see
+// ConnectionClass::policy
+pub const PLAYER_MAX_QUEUED: usize = 4096;
+pub const PEER_LINK_MAX_QUEUED: usize = 16384;
+
+// Threads generating chunks for the block state
+pub const CHUNK_GEN_WORKERS: usize = 2;
+
+// How many chunks pregeneration hands the chunk generation pool at once, unless told otherwise
+pub const PREGENERATION_BATCH_SIZE: usize = 4;
+
+// In milliseconds, how often a replayed service that's waiting on another service has what it sent
+// answered, which during a replay means dropped
+pub const REPLAY_POLL_PERIOD: u64 = 10;
+
+// In seconds, how far back players and peers joining are caught up on chat and block changes, and
+// at most how many of each they're sent
+pub const RECENT_EVENT_WINDOW: u64 = 30;
+pub const RECENT_EVENT_LIMIT: usize = 100;
+
+// In seconds, how long after reporting our chunks to a connection we only send the ones that have
+// changed if asked again
+pub const REPEATED_REPORT_WINDOW: u64 = 2;
@@ -0,0 +1,343 @@
+// A live view of the node for whoever's running it, in place of the console. Most of it's fed by
+// the metrics' events, the rest is looked at each time it's drawn
+use super::flight_recorder;
+use super::interfaces::patchwork::MapDescription;
+use super::link_watermarks::{LinkAlert, Pressure};
+use super::metrics::{self, Event};
+use super::models::map::Position as MapPosition;
+use super::services::instance;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use ratatui::crossterm::event::{
+    self, Event as TerminalEvent, KeyCode, KeyEventKind, KeyModifiers,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, List, Row, Table};
+use ratatui::Frame;
+use uuid::Uuid;
+
+// How often it's redrawn, and how often it checks whether it's been closed
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const RECENT_CROSSINGS: usize = 20;
+const RECENT_LOG_ENTRIES: usize = 50;
+
+// Stops drawing and gives the terminal back when closed
+pub struct Dashboard {
+    closing: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Crossing {
+    conn_id: Uuid,
+    from: MapPosition,
+    to: MapPosition,
+    at: SystemTime,
+}
+
+// Everything the events have told us so far
+#[derive(Debug, Default)]
+struct View {
+    map_players: BTreeMap<(i32, i32), usize>,
+    maps: Vec<MapDescription>,
+    // Newest first
+    crossings: VecDeque<Crossing>,
+    // The peer link watermarks that are raised, by link
+    link_alerts: BTreeMap<(String, Pressure), LinkAlert>,
+}
+
+// Takes over the terminal until closed. Pressing q or ctrl-c stops the node, like the console's stop
+pub fn start(stop: Sender<()>) -> Dashboard {
+    let events = metrics::subscribe();
+    let closing = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let closing = closing.clone();
+        instance::spawn("dashboard", move || run(events, stop, closing))
+    };
+    Dashboard { closing, thread }
+}
+
+impl Dashboard {
+    pub fn close(self) {
+        self.closing.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+fn run(events: Receiver<Event>, stop: Sender<()>, closing: Arc<AtomicBool>) {
+    let mut terminal = ratatui::init();
+    let mut view = View::default();
+    while !closing.load(Ordering::Relaxed) {
+        events.try_iter().for_each(|event| view.apply(event));
+        let queues = instance::queue_depths();
+        let log = flight_recorder::entries();
+        let log = &log[log.len().saturating_sub(RECENT_LOG_ENTRIES)..];
+        if let Err(e) = terminal.draw(|frame| render(&view, &queues, log, frame)) {
+            warn!("Failed to draw the dashboard: {}", e);
+            break;
+        }
+        match event::poll(REFRESH_INTERVAL).and_then(|ready| match ready {
+            true => event::read().map(Some),
+            false => Ok(None),
+        }) {
+            Ok(Some(TerminalEvent::Key(key))) if key.kind == KeyEventKind::Press => {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.code == KeyCode::Char('q') || ctrl_c {
+                    let _ = stop.send(());
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Failed to read from the terminal: {}", e);
+                break;
+            }
+        }
+    }
+    ratatui::restore();
+}
+
+impl View {
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::MapPlayers(map_players) => self.map_players = map_players,
+            Event::Maps(maps) => self.maps = maps,
+            Event::BorderCrossed {
+                conn_id,
+                from,
+                to,
+                at,
+            } => {
+                self.crossings.push_front(Crossing {
+                    conn_id,
+                    from,
+                    to,
+                    at,
+                });
+                self.crossings.truncate(RECENT_CROSSINGS);
+            }
+            Event::LinkAlert { alert, raised } => {
+                let key = (alert.conn_id.clone(), alert.pressure);
+                match raised {
+                    true => self.link_alerts.insert(key, alert),
+                    false => self.link_alerts.remove(&key),
+                };
+            }
+        }
+    }
+
+    // Position, name, owner, players, and whether its link is up and how quick it is
+    fn map_rows(&self) -> Vec<[String; 5]> {
+        self.maps
+            .iter()
+            .map(|map| {
+                let position = (map.position.x, map.position.z);
+                let health = match (&map.owner, map.connected, map.latency) {
+                    (None, _, _) => String::from("local"),
+                    (Some(_), false, _) => String::from("down"),
+                    (Some(_), true, Some(latency)) => format!("up, {}ms", latency.as_millis()),
+                    (Some(_), true, None) => String::from("up"),
+                };
+                [
+                    format!("{}, {}", position.0, position.1),
+                    map.name.clone().unwrap_or_default(),
+                    map.owner
+                        .as_ref()
+                        .map_or_else(|| String::from("us"), |owner| owner.to_string()),
+                    self.map_players
+                        .get(&position)
+                        .copied()
+                        .unwrap_or(0)
+                        .to_string(),
+                    health,
+                ]
+            })
+            .collect()
+    }
+}
+
+fn render(view: &View, queues: &[(&str, usize)], log: &[String], frame: &mut Frame) {
+    let [top, middle, bottom] = Layout::vertical([
+        Constraint::Percentage(40),
+        Constraint::Percentage(30),
+        Constraint::Percentage(30),
+    ])
+    .areas(frame.area());
+    let [queues_area, alerts_area, crossings_area] = Layout::horizontal([
+        Constraint::Percentage(25),
+        Constraint::Percentage(35),
+        Constraint::Percentage(40),
+    ])
+    .areas(middle);
+
+    let maps = Table::new(
+        view.map_rows().into_iter().map(|row| {
+            let style = match row[4].as_str() {
+                "down" => Style::default().fg(Color::Red),
+                _ => Style::default(),
+            };
+            Row::new(row).style(style)
+        }),
+        [
+            Constraint::Length(12),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(12),
+        ],
+    )
+    .header(Row::new(["Position", "Name", "Owner", "Players", "Link"]))
+    .block(Block::bordered().title(" Maps (q to stop) "));
+    frame.render_widget(maps, top);
+
+    let queues = Table::new(
+        queues
+            .iter()
+            .map(|(service, depth)| Row::new([service.to_string(), depth.to_string()])),
+        [Constraint::Fill(1), Constraint::Length(8)],
+    )
+    .block(Block::bordered().title(" Queued messages "));
+    frame.render_widget(queues, queues_area);
+
+    let alerts = List::new(view.link_alerts.values().map(|alert| {
+        let peer = alert
+            .peer
+            .as_ref()
+            .map_or_else(|| String::from("unidentified"), |peer| peer.to_string());
+        let reading = match alert.pressure {
+            Pressure::OutboundBacklog => format!("backlog {}", alert.reading),
+            Pressure::InboundLag => format!("lag {} ms", alert.reading),
+        };
+        format!("{}  {}  {}", &alert.conn_id[..8], peer, reading)
+    }))
+    .style(Style::default().fg(Color::Red))
+    .block(Block::bordered().title(" Falling behind "));
+    frame.render_widget(alerts, alerts_area);
+
+    let crossings = List::new(view.crossings.iter().map(|crossing| {
+        let ago = crossing.at.elapsed().unwrap_or_default().as_secs();
+        format!(
+            "{}s ago  {}  {}, {} -> {}, {}",
+            ago,
+            &crossing.conn_id.to_string()[..8],
+            crossing.from.x,
+            crossing.from.z,
+            crossing.to.x,
+            crossing.to.z
+        )
+    }))
+    .block(Block::bordered().title(" Border crossings "));
+    frame.render_widget(crossings, crossings_area);
+
+    // As many of the newest entries as fit
+    let shown = bottom.height.saturating_sub(2) as usize;
+    let log = List::new(
+        log[log.len().saturating_sub(shown)..]
+            .iter()
+            .map(String::as_str),
+    )
+    .block(Block::bordered().title(" Log "));
+    frame.render_widget(log, bottom);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::map::Peer;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn map(x: i32, owner: Option<u16>, connected: bool) -> MapDescription {
+        MapDescription {
+            position: MapPosition {
+                x,
+                ..MapPosition::default()
+            },
+            name: Some(format!("map{}", x)),
+            owner: owner.map(|port| Peer {
+                address: String::from("10.0.0.2"),
+                port,
+            }),
+            connected,
+            latency: Some(Duration::from_millis(12)),
+            entity_id_block: x,
+        }
+    }
+
+    #[test]
+    fn events_are_kept_up_to_date_and_drawn() {
+        let mut view = View::default();
+        view.apply(Event::Maps(vec![
+            map(0, None, true),
+            map(1, Some(25566), true),
+            map(2, Some(25567), false),
+        ]));
+        view.apply(Event::MapPlayers(BTreeMap::from([((1, 0), 3)])));
+        for x in 0..RECENT_CROSSINGS + 5 {
+            view.apply(Event::BorderCrossed {
+                conn_id: Uuid::from_u128(x as u128),
+                from: MapPosition::default(),
+                to: MapPosition {
+                    x: 1,
+                    ..MapPosition::default()
+                },
+                at: SystemTime::now(),
+            });
+        }
+        assert_eq!(view.crossings.len(), RECENT_CROSSINGS);
+        assert_eq!(
+            view.crossings[0].conn_id.as_u128(),
+            RECENT_CROSSINGS as u128 + 4
+        );
+
+        let rows = view.map_rows();
+        assert_eq!(rows[0][2], "us");
+        assert_eq!(rows[0][4], "local");
+        assert_eq!(rows[1][3], "3");
+        assert_eq!(rows[1][4], "up, 12ms");
+        assert_eq!(rows[2][4], "down");
+
+        let alert = |conn_id: u128, pressure, reading| LinkAlert {
+            conn_id: Uuid::from_u128(conn_id).to_string(),
+            peer: Some(Peer {
+                address: String::from("10.0.0.3"),
+                port: 25570,
+            }),
+            pressure,
+            reading,
+        };
+        for (conn_id, pressure, raised) in [
+            (1, Pressure::InboundLag, true),
+            (2, Pressure::OutboundBacklog, true),
+            (2, Pressure::OutboundBacklog, false),
+        ] {
+            view.apply(Event::LinkAlert {
+                alert: alert(conn_id, pressure, 480),
+                raised,
+            });
+        }
+        assert_eq!(view.link_alerts.len(), 1);
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal
+            .draw(|frame| render(&view, &[("player_state", 7)], &[], frame))
+            .unwrap();
+        let drawn: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(drawn.contains("10.0.0.2:25566"));
+        assert!(drawn.contains("player_state"));
+        assert!(drawn.contains("10.0.0.3:25570  lag 480 ms"));
+    }
+}
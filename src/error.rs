@@ -0,0 +1,47 @@
+use super::models::map::Position;
+
+use std::io;
+use thiserror::Error;
+use uuid::Uuid;
+
+// Things that can go wrong that shouldn't take a whole service down with them. Whoever runs into
+// one either hands it back to their caller or logs it and carries on
+#[derive(Debug, Error)]
+pub enum PatchworkError {
+    #[error("{0} has stopped")]
+    ServiceStopped(&'static str),
+    #[error("no map at {0:?}")]
+    NoMap(Position),
+    #[error("no player for conn_id {0}")]
+    NoPlayer(Uuid),
+    #[error("conn_id {conn_id} sent a {packet} packet, which isn't handled here")]
+    UnexpectedPacket { conn_id: Uuid, packet: &'static str },
+    #[error("conn_id {conn_id} is in unknown state {state}")]
+    UnknownState { conn_id: Uuid, state: i32 },
+    #[error("connection {conn_id} failed: {source}")]
+    Connection {
+        conn_id: Uuid,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+// Most callers can carry on without whoever they were telling about something once it's stopped,
+// and say so where it happened. Callers that can't get anything done without it match on the error
+// themselves
+pub trait OrLog {
+    fn or_log(self);
+}
+
+impl OrLog for Result<(), PatchworkError> {
+    #[track_caller]
+    fn or_log(self) {
+        if let Err(e) = self {
+            error!("{} ({})", e, std::panic::Location::caller());
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::constants::FLIGHT_RECORDER_ENTRIES;
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+use tracing_subscriber::Layer;
+
+// Sits next to the real logger, keeping the most recent entries around along with the spans they
+// happened in, so they can be included in support bundles after the fact
+static ENTRIES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+struct FlightRecorder;
+
+// A span's fields, written out once when it's created rather than for every entry inside it
+struct SpanFields(String);
+
+// Writes fields out as `name=value`, apart from an event's message which goes in as it is
+struct FieldWriter<'a>(&'a mut String);
+
+pub fn init(level: LevelFilter) -> Result<(), TryInitError> {
+    install(level, true)
+}
+
+// Only records, for when something else has the terminal, like the dashboard
+pub fn init_quietly(level: LevelFilter) -> Result<(), TryInitError> {
+    install(level, false)
+}
+
+fn install(level: LevelFilter, print: bool) -> Result<(), TryInitError> {
+    tracing_subscriber::registry()
+        .with(level)
+        .with(print.then(|| {
+            tracing_subscriber::fmt::layer()
+                .without_time()
+                .with_target(false)
+                .with_ansi(false)
+        }))
+        .with(FlightRecorder)
+        .try_init()
+}
+
+pub fn entries() -> Vec<String> {
+    ENTRIES.lock().unwrap().iter().cloned().collect()
+}
+
+impl Visit for FieldWriter<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() && !self.0.ends_with(' ') {
+            self.0.push(' ');
+        }
+        let _ = match field.name() {
+            "message" => write!(self.0, "{:?}", value),
+            name => write!(self.0, "{}={:?}", name, value),
+        };
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for FlightRecorder {
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        let mut fields = String::new();
+        attrs.record(&mut FieldWriter(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(&mut FieldWriter(&mut fields.0));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut entry = format!(
+            "{}.{:03} {} [{}] ",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            event.metadata().level(),
+            event.metadata().target()
+        );
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let fields = extensions.get::<SpanFields>().map_or("", |f| f.0.as_str());
+                let _ = write!(entry, "{}{{{}}}: ", span.name(), fields);
+            }
+        }
+        event.record(&mut FieldWriter(&mut entry));
+
+        let mut entries = ENTRIES.lock().unwrap();
+        if entries.len() >= FLIGHT_RECORDER_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
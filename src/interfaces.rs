@@ -1,13 +1,280 @@
 #[macro_use]
 mod interface_macro;
+pub mod bans;
 pub mod block;
+pub mod chat;
+pub mod command;
 pub mod connection;
+pub mod entity;
+pub mod entity_ids;
+pub mod game_rules;
+pub mod hud;
+pub mod interest;
+pub mod keep_alive;
 pub mod messenger;
 pub mod packet_processor;
 pub mod patchwork;
+pub mod peer_auth;
 pub mod player;
+pub mod whitelist;
+pub mod world_time;
 
+use super::config;
+use super::constants;
+use super::link_watermarks;
+use super::models::ban_store;
+use super::models::identity;
 use super::models::map;
+use super::models::minecraft_protocol;
 use super::models::minecraft_types;
 use super::models::packet;
+use super::models::player_store;
+use super::models::protocol_adapter;
+use super::models::topology;
 use super::models::translation;
+use super::models::weather;
+use super::models::world_generator;
+
+use minecraft_protocol::MinecraftProtocolReader;
+use packet::Packet;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+// Counts the messages sent to a service that it hasn't picked up yet
+pub trait Queued {
+    fn queue_depth() -> &'static AtomicUsize;
+}
+
+// Services that are restarted should they panic keep a snapshot of the state that the messages
+// sent to them have built up, which is replayed to the restarted service before anything else
+pub trait Restartable: Sized {
+    type Snapshot: Default + Send;
+
+    fn record(&self, snapshot: &mut Self::Snapshot);
+    fn replay(snapshot: &Self::Snapshot) -> Vec<Self>;
+}
+
+// Services that can have the messages they're sent written to a message log as they handle them,
+// for patchwork-replay to feed back to a fresh instance in the same order. Replies to replayed
+// messages go to a channel nobody reads
+pub trait Recordable: Sized {
+    type Record: Serialize + DeserializeOwned;
+
+    fn to_record(&self) -> Self::Record;
+    fn from_record(record: Self::Record) -> Option<Self>;
+}
+
+// How each field of a recorded message is written to the message log and read back. Interfaces
+// defined with a record type get a Recordable implementation made up of these, see define_interface
+pub trait RecordField: Sized {
+    type Record: Serialize + DeserializeOwned;
+
+    fn to_record(&self) -> Self::Record;
+    fn from_record(record: Self::Record) -> Option<Self>;
+}
+
+// Fields that can be written out as they are
+macro_rules! recorded_as_is {
+    ($( $field_type:ty ),*) => {
+        $(
+            impl RecordField for $field_type {
+                type Record = $field_type;
+
+                fn to_record(&self) -> $field_type {
+                    self.clone()
+                }
+
+                fn from_record(record: $field_type) -> Option<$field_type> {
+                    Some(record)
+                }
+            }
+        )*
+    };
+}
+
+recorded_as_is!(
+    bool,
+    u8,
+    i16,
+    i32,
+    i64,
+    u128,
+    usize,
+    String,
+    Uuid,
+    map::Dimension,
+    map::GossipedMap,
+    map::Peer,
+    map::PeerConnection,
+    map::Position,
+    minecraft_types::Description,
+    minecraft_types::ItemStack,
+    minecraft_types::Version,
+    topology::Topology,
+    block::BlockPosition,
+    entity::DroppedItem,
+    messenger::Origin,
+    patchwork::EntityQuery,
+    player::Angle,
+    player::Player,
+    player::Position
+);
+
+impl<T: RecordField> RecordField for Option<T> {
+    type Record = Option<T::Record>;
+
+    fn to_record(&self) -> Self::Record {
+        self.as_ref().map(T::to_record)
+    }
+
+    fn from_record(record: Self::Record) -> Option<Self> {
+        match record {
+            Some(record) => T::from_record(record).map(Some),
+            None => Some(None),
+        }
+    }
+}
+
+impl<T: RecordField> RecordField for Vec<T> {
+    type Record = Vec<T::Record>;
+
+    fn to_record(&self) -> Self::Record {
+        self.iter().map(T::to_record).collect()
+    }
+
+    fn from_record(record: Self::Record) -> Option<Self> {
+        record.into_iter().map(T::from_record).collect()
+    }
+}
+
+impl<A: RecordField, B: RecordField> RecordField for (A, B) {
+    type Record = (A::Record, B::Record);
+
+    fn to_record(&self) -> Self::Record {
+        (self.0.to_record(), self.1.to_record())
+    }
+
+    fn from_record(record: Self::Record) -> Option<Self> {
+        Some((A::from_record(record.0)?, B::from_record(record.1)?))
+    }
+}
+
+// Replies to replayed messages go to a channel nobody reads, so there's nothing to keep of them
+impl<T> RecordField for Sender<T> {
+    type Record = ();
+
+    fn to_record(&self) {}
+
+    fn from_record(_: ()) -> Option<Sender<T>> {
+        Some(channel().0)
+    }
+}
+
+// Packets are kept as the bytes they'd be sent as, in hex, along with the state they're read back
+// in. Packets we don't know the layout of are read back in a state nothing's known in
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PacketRecord {
+    state: i32,
+    bytes: String,
+}
+
+impl RecordField for Packet {
+    type Record = PacketRecord;
+
+    fn to_record(&self) -> PacketRecord {
+        let state = packet::PACKETS
+            .iter()
+            .find(|(_, _, name)| *name == self.name())
+            .map_or(-1, |(state, _, _)| state.unwrap_or(3));
+        let mut bytes = Vec::new();
+        packet::write(&mut bytes, self.clone());
+        PacketRecord {
+            state,
+            bytes: hex::encode(bytes),
+        }
+    }
+
+    fn from_record(record: PacketRecord) -> Option<Packet> {
+        let mut bytes = Cursor::new(hex::decode(record.bytes).ok()?);
+        bytes.read_var_int().ok()?;
+        packet::read(&mut bytes, record.state).ok()
+    }
+}
+
+// Stands in for a service in tests, implementing its interface by keeping every message it's sent
+// for the test to look through afterwards. Services that are expected to reply are given a
+// responder, which sees each message as it comes in and can answer it. Clones share what's been
+// sent, like senders to the same service do
+pub struct Mock<O> {
+    sent: Arc<Mutex<Vec<O>>>,
+    responder: Option<Responder<O>>,
+}
+
+type Responder<O> = Arc<dyn Fn(&O) + Send + Sync>;
+
+impl<O> Mock<O> {
+    pub fn new() -> Mock<O> {
+        Mock {
+            sent: Arc::new(Mutex::new(Vec::new())),
+            responder: None,
+        }
+    }
+
+    pub fn responding<F: Fn(&O) + Send + Sync + 'static>(responder: F) -> Mock<O> {
+        Mock {
+            responder: Some(Arc::new(responder)),
+            ..Mock::new()
+        }
+    }
+
+    // Everything sent since the last time, in the order it was sent
+    pub fn take(&self) -> Vec<O> {
+        self.sent.lock().unwrap().drain(..).collect()
+    }
+
+    fn receive(&self, msg: O) {
+        if let Some(responder) = &self.responder {
+            responder(&msg);
+        }
+        self.sent.lock().unwrap().push(msg);
+    }
+}
+
+impl<O> Default for Mock<O> {
+    fn default() -> Mock<O> {
+        Mock::new()
+    }
+}
+
+impl<O> Clone for Mock<O> {
+    fn clone(&self) -> Mock<O> {
+        Mock {
+            sent: self.sent.clone(),
+            responder: self.responder.clone(),
+        }
+    }
+}
+
+pub type MockBanList = Mock<bans::Operations>;
+pub type MockBlockState = Mock<block::Operations>;
+pub type MockChatService = Mock<chat::Operations>;
+pub type MockCommandService = Mock<command::Operations>;
+pub type MockConnectionService = Mock<connection::Operations>;
+pub type MockEntityState = Mock<entity::Operations>;
+pub type MockEntityIdAllocator = Mock<entity_ids::Operations>;
+pub type MockGameRuleState = Mock<game_rules::Operations>;
+pub type MockHud = Mock<hud::Operations>;
+pub type MockInterestManager = Mock<interest::Operations>;
+pub type MockKeepAliveService = Mock<keep_alive::Operations>;
+pub type MockMessenger = Mock<messenger::Operations>;
+pub type MockPacketProcessor = Mock<packet_processor::Operations>;
+pub type MockPatchworkState = Mock<patchwork::Operations>;
+pub type MockPeerAuth = Mock<peer_auth::Operations>;
+pub type MockPlayerState = Mock<player::Operations>;
+pub type MockWhitelist = Mock<whitelist::Operations>;
+pub type MockWorldTimeState = Mock<world_time::Operations>;
@@ -0,0 +1,25 @@
+use super::ban_store::Ban;
+
+use std::net::IpAddr;
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    BanList,
+    // Why the player can't join, if they're banned
+    (
+        Check,
+        check,
+        [name: String, uuid: Uuid, address: Option<IpAddr>, reply: Sender<Option<String>>]
+    ),
+    // Banned for good, or for so many seconds
+    (
+        Add,
+        add,
+        [target: String, reason: String, duration: Option<u64>]
+    ),
+    (Pardon, pardon, [target: String, reply: Sender<bool>]),
+    (List, list, [reply: Sender<Vec<Ban>>]),
+    (Report, report, [conn_id: Uuid]),
+    (PeerUpdate, peer_update, [ban: Ban])
+);
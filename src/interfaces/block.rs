@@ -1,4 +1,67 @@
+use super::map::Dimension;
+use super::world_generator::WorldGenerator;
+use serde::{Deserialize, Serialize};
 use std::sync::mpsc::Sender;
 use uuid::Uuid;
 
-define_interface!(BlockState, (Report, report, [conn_id: Uuid]));
+define_interface!(
+    BlockState,
+    (Report, report, [conn_id: Uuid]),
+    (
+        Fill,
+        fill,
+        [from: BlockPosition, to: BlockPosition, block_id: i32]
+    ),
+    (Export, export, [reply: Sender<Vec<i32>>]),
+    // The blocks of a map in the given dimension, which we serve from then on
+    (Load, load, [dimension: Dimension, block_ids: Vec<i32>]),
+    // Queries for services that need to know about the world without keeping a copy of it. Block
+    // positions are in our map's own coordinates, and anything outside of it is None
+    (
+        GetBlock,
+        get_block,
+        [position: BlockPosition, reply: Sender<Option<i32>>]
+    ),
+    (
+        IsSolid,
+        is_solid,
+        [position: BlockPosition, reply: Sender<Option<bool>>]
+    ),
+    // The lowest y with nothing solid at or above it, where something standing in the column
+    // would have its feet
+    (
+        GetHeight,
+        get_height,
+        [x: i32, z: i32, reply: Sender<Option<i32>>]
+    ),
+    (
+        Pregenerate,
+        pregenerate,
+        [conn_id: Uuid, batch_size: usize]
+    ),
+    // Which ways around our map, in maps, there's a peer's map to share a seam with
+    (
+        SetNeighbours,
+        set_neighbours,
+        [neighbours: Vec<(i32, i32)>]
+    ),
+    // Generates the chunks afresh, keeping what's there until they're done
+    (Regenerate, regenerate, [chunks: Vec<usize>]),
+    (
+        ChunkGenerated,
+        chunk_generated,
+        [chunk: usize, block_ids: Vec<i32>]
+    ),
+    (
+        RegisterGenerator,
+        register_generator,
+        [name: String, generator: Box<dyn WorldGenerator>]
+    )
+);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BlockPosition {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
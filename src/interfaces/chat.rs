@@ -0,0 +1,13 @@
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    ChatService,
+    (Join, join, [conn_id: Uuid, name: String]),
+    (Leave, leave, [conn_id: Uuid]),
+    // Sends a player who's just joined the chat they missed, see RecentEvents
+    (Replay, replay, [conn_id: Uuid]),
+    (Say, say, [conn_id: Uuid, message: String]),
+    // Like Say but from an operator's /say, so it stands out and isn't rate limited
+    (Announce, announce, [conn_id: Uuid, message: String])
+);
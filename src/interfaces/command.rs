@@ -0,0 +1,18 @@
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    CommandService,
+    (Execute, execute, [conn_id: Uuid, command: String]),
+    // Commands from outside the game, like the console's, run at the level they're given and are
+    // answered with their feedback rather than in chat
+    (
+        Run,
+        run,
+        [
+            command: String,
+            permission_level: u8,
+            reply: Sender<Result<String, String>>
+        ]
+    )
+);
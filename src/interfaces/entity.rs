@@ -0,0 +1,90 @@
+use super::minecraft_types::ItemStack;
+use super::packet::ItemTransfer;
+use super::player::{Position, Velocity};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    EntityState,
+    (Report, report, [conn_id: Uuid]),
+    (
+        Summon,
+        summon,
+        [entity_type: i32, position: Position]
+    ),
+    // Summons a mob that goes round and round the center, starting at angle radians from +x
+    (
+        SummonCircling,
+        summon_circling,
+        [entity_type: i32, center: Position, radius: f64, angle: f64]
+    ),
+    (DropItem, drop_item, [item: DroppedItem]),
+    (Kill, kill, [entity_id: i32]),
+    (Find, find_entity, [uuid: Uuid, reply: Sender<Option<i32>>]),
+    (Tick, tick, [])
+);
+
+// An item lying on the ground or flying through the air. Everything but its entity id goes along
+// with it when it's carried over a seam, so the peer picks up exactly where we left off and the
+// item is never in two places or none
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DroppedItem {
+    pub uuid: Uuid,
+    pub item: ItemStack,
+    pub position: Position,
+    // In blocks per tick
+    pub velocity: Velocity,
+    // Items don't collide with blocks yet, so they come to rest at the height whoever dropped them
+    // was standing at
+    pub floor_y: f64,
+    // Ticks until it can be picked up
+    pub pickup_delay: i16,
+    // Ticks since it was dropped
+    pub age: i32,
+    // Whoever dropped it, if anyone
+    pub thrower: Option<Uuid>,
+}
+
+impl DroppedItem {
+    pub fn item_transfer_packet(&self) -> ItemTransfer {
+        ItemTransfer {
+            uuid: self.uuid.as_u128(),
+            item: Some(self.item.clone()),
+            x: self.position.x,
+            y: self.position.y,
+            z: self.position.z,
+            velocity_x: self.velocity.x,
+            velocity_y: self.velocity.y,
+            velocity_z: self.velocity.z,
+            floor_y: self.floor_y,
+            pickup_delay: self.pickup_delay,
+            age: self.age,
+            thrower: self.thrower.map_or(0, |thrower| thrower.as_u128()),
+        }
+    }
+
+    // Transfers without an item have nothing to drop
+    pub fn from_item_transfer(packet: ItemTransfer) -> Option<DroppedItem> {
+        Some(DroppedItem {
+            uuid: Uuid::from_u128(packet.uuid),
+            item: packet.item?,
+            position: Position {
+                x: packet.x,
+                y: packet.y,
+                z: packet.z,
+            },
+            velocity: Velocity {
+                x: packet.velocity_x,
+                y: packet.velocity_y,
+                z: packet.velocity_z,
+            },
+            floor_y: packet.floor_y,
+            pickup_delay: packet.pickup_delay,
+            age: packet.age,
+            thrower: Some(packet.thrower)
+                .filter(|&thrower| thrower != 0)
+                .map(Uuid::from_u128),
+        })
+    }
+}
@@ -0,0 +1,53 @@
+use super::constants::{
+    ANCHORED_PLAYER_ENTITY_ID_START, ENTITY_ID_BLOCK_SIZE, MOB_ENTITY_ID_START,
+};
+use std::sync::mpsc::Sender;
+
+define_interface!(
+    EntityIdAllocator,
+    (
+        Lease,
+        lease,
+        [range: EntityIdRange, reply: Sender<Option<i32>>]
+    ),
+    (Claim, claim, [entity_id: i32, reply: Sender<bool>]),
+    (Release, release, [entity_id: i32])
+);
+
+// Every entity id we hand out lives inside our own block of ENTITY_ID_BLOCK_SIZE ids, split up by
+// what the id is for. Anchored players aren't leased an id, they claim the one matching their id
+// on the server they're anchored from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntityIdRange {
+    Player,
+    Mob,
+    AnchoredPlayer,
+}
+
+impl EntityIdRange {
+    pub fn all() -> Vec<EntityIdRange> {
+        vec![
+            EntityIdRange::Player,
+            EntityIdRange::Mob,
+            EntityIdRange::AnchoredPlayer,
+        ]
+    }
+
+    // Start inclusive, end exclusive
+    pub fn bounds(self) -> (i32, i32) {
+        match self {
+            EntityIdRange::Player => (1, ENTITY_ID_BLOCK_SIZE - ANCHORED_PLAYER_ENTITY_ID_START),
+            EntityIdRange::Mob => (MOB_ENTITY_ID_START, ANCHORED_PLAYER_ENTITY_ID_START),
+            EntityIdRange::AnchoredPlayer => {
+                (ANCHORED_PLAYER_ENTITY_ID_START, ENTITY_ID_BLOCK_SIZE)
+            }
+        }
+    }
+
+    pub fn of(entity_id: i32) -> Option<EntityIdRange> {
+        EntityIdRange::all().into_iter().find(|range| {
+            let (start, end) = range.bounds();
+            entity_id >= start && entity_id < end
+        })
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    GameRuleState,
+    (Report, report, [conn_id: Uuid]),
+    (Get, get, [rule: GameRule, reply: Sender<bool>]),
+    (Set, set, [rule: GameRule, value: bool]),
+    (
+        PeerUpdate,
+        peer_update,
+        [rule: GameRule, value: bool, version: i64]
+    )
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameRule {
+    KeepInventory,
+    DoDaylightCycle,
+    Pvp,
+    MobSpawning,
+}
+
+impl GameRule {
+    pub fn all() -> Vec<GameRule> {
+        vec![
+            GameRule::KeepInventory,
+            GameRule::DoDaylightCycle,
+            GameRule::Pvp,
+            GameRule::MobSpawning,
+        ]
+    }
+
+    pub fn from_name(name: &str) -> Option<GameRule> {
+        GameRule::all().into_iter().find(|rule| rule.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameRule::KeepInventory => "keepInventory",
+            GameRule::DoDaylightCycle => "doDaylightCycle",
+            GameRule::Pvp => "pvp",
+            GameRule::MobSpawning => "mobSpawning",
+        }
+    }
+
+    pub fn default_value(self) -> bool {
+        match self {
+            GameRule::KeepInventory => false,
+            GameRule::DoDaylightCycle => true,
+            GameRule::Pvp => true,
+            GameRule::MobSpawning => true,
+        }
+    }
+}
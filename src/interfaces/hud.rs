@@ -0,0 +1,4 @@
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(Hud, (Toggle, toggle, [conn_id: Uuid, reply: Sender<bool>]));
@@ -0,0 +1,38 @@
+use super::packet::Packet;
+use super::player::Position;
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    InterestManager,
+    (
+        Track,
+        track,
+        [
+            entity_id: i32,
+            kind: EntityKind,
+            position: Position,
+            viewer: Option<Uuid>,
+            introduction: Vec<Packet>
+        ]
+    ),
+    (
+        Move,
+        move_entity,
+        [
+            entity_id: i32,
+            position: Position,
+            packets: Vec<Packet>,
+            introduction: Vec<Packet>
+        ]
+    ),
+    (Untrack, untrack, [entity_id: i32])
+);
+
+// Each kind of entity is seen from as far away as its tracking range in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityKind {
+    Player,
+    Mob,
+    Item,
+}
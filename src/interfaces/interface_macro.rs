@@ -1,29 +1,120 @@
 macro_rules! define_interface {
+    // Interfaces given a record type can have the messages they're sent written to a message log,
+    // each one as a variant of the record type with its fields as they're recorded, see RecordField
+    ($name:ident, record $record:ident,
+            $( ( $op:ident, $op_method:ident, [ $( $field_name:ident: $field_type:ty ),* ] ) ),*
+    ) => {
+        define_interface!($name, $( ($op, $op_method, [ $( $field_name: $field_type ),* ]) ),*);
+
+        #[derive(Debug, serde::Serialize, serde::Deserialize)]
+        pub enum $record {
+            $(
+                $op {
+                    $( $field_name: <$field_type as $crate::interfaces::RecordField>::Record, )*
+                },
+            )*
+        }
+
+        impl $crate::interfaces::Recordable for Operations {
+            type Record = $record;
+
+            fn to_record(&self) -> $record {
+                match self {
+                    $(
+                        Operations::$op(_msg) => $record::$op {
+                            $(
+                                $field_name: $crate::interfaces::RecordField::to_record(
+                                    &_msg.$field_name
+                                ),
+                            )*
+                        },
+                    )*
+                }
+            }
+
+            fn from_record(record: $record) -> Option<Operations> {
+                Some(match record {
+                    $(
+                        $record::$op { $( $field_name, )* } => Operations::$op($op {
+                            $(
+                                $field_name: <$field_type as $crate::interfaces::RecordField>
+                                    ::from_record($field_name)?,
+                            )*
+                            span: tracing::Span::none(),
+                        }),
+                    )*
+                })
+            }
+        }
+    };
     ($name:ident,
             $( ( $op:ident, $op_method:ident, [ $( $field_name:ident: $field_type:ty ),* ] ) ),*
     ) => {
         pub trait $name {
-            $( fn $op_method(&self, $( $field_name: $field_type ),*); )*
+            $(
+                fn $op_method(&self, $( $field_name: $field_type ),*)
+                    -> Result<(), $crate::error::PatchworkError>;
+            )*
+        }
+
+        static QUEUE_DEPTH: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        impl super::Queued for Operations {
+            fn queue_depth() -> &'static std::sync::atomic::AtomicUsize {
+                &QUEUE_DEPTH
+            }
         }
 
         impl $name for Sender<Operations> {
             $(
-                fn $op_method(&self, $( $field_name: $field_type ),*) {
-                    self.send(Operations::$op($op { $( $field_name ),* }))
-                        .unwrap();
+                // Nobody's left to hear about it once a service has stopped, which the caller is
+                // told about rather than being taken down too
+                fn $op_method(&self, $( $field_name: $field_type ),*)
+                    -> Result<(), $crate::error::PatchworkError> {
+                    QUEUE_DEPTH.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    let span = tracing::Span::current();
+                    self.send(Operations::$op($op { $( $field_name, )* span })).map_err(|_| {
+                        QUEUE_DEPTH.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+                        $crate::error::PatchworkError::ServiceStopped(stringify!($name))
+                    })
+                }
+            )*
+        }
+
+        // Mocks keep what they're sent rather than handing it on, answering it first if they've
+        // been told how
+        impl $name for super::Mock<Operations> {
+            $(
+                fn $op_method(&self, $( $field_name: $field_type ),*)
+                    -> Result<(), $crate::error::PatchworkError> {
+                    let span = tracing::Span::current();
+                    self.receive(Operations::$op($op { $( $field_name, )* span }));
+                    Ok(())
                 }
             )*
         }
 
+        #[derive(Debug)]
         pub enum Operations {
             $( $op($op), )*
         }
 
+        impl Operations {
+            // Whatever the sender was in the middle of, for the service to carry on inside of, so
+            // that a packet can be followed from one service's thread to the next
+            pub fn span(&self) -> &tracing::Span {
+                match self {
+                    $( Operations::$op(op) => &op.span, )*
+                }
+            }
+        }
+
         $(
             #[derive(Debug)]
             pub struct $op {
                 $( pub $field_name: $field_type, )*
+                pub span: tracing::Span,
             }
         )*
-    }
+    };
 }
@@ -0,0 +1,17 @@
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    KeepAliveService,
+    (Watch, watch, [conn_id: Uuid, kind: KeepAliveKind]),
+    (Unwatch, unwatch, [conn_id: Uuid]),
+    (Answer, answer, [conn_id: Uuid, id: i64])
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepAliveKind {
+    // Playing on this node, and sent keep-alives of our own
+    Client,
+    // Kept to our map by a peer for one of its players, passing on that player's answers
+    Anchor,
+}
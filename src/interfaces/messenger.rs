@@ -1,7 +1,19 @@
-use super::map::Map;
+use super::constants::{
+    PEER_LINK_COMPRESSION_THRESHOLD, PEER_LINK_MAX_PACKET_LENGTH, PEER_LINK_MAX_QUEUED,
+    PLAYER_MAX_PACKET_LENGTH, PLAYER_MAX_QUEUED,
+};
+use super::identity::Identity;
+use super::map::{Map, Peer};
 use super::packet::Packet;
+use super::protocol_adapter::ProtocolAdapter;
+use super::Restartable;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::mpsc::Sender;
+use std::sync::OnceLock;
+use tracing::Span;
 use uuid::Uuid;
 
 define_interface!(
@@ -16,19 +28,248 @@ define_interface!(
             subscriber_type: SubscriberType
         ]
     ),
+    (
+        Relay,
+        relay,
+        [
+            packet: Packet,
+            origin: Origin,
+            via: Option<Uuid>,
+            subscriber_type: SubscriberType,
+            // For our own players, the first time the packet reaches us
+            local: Option<Box<Packet>>
+        ]
+    ),
     (Subscribe, subscribe, [conn_id: Uuid, typ: SubscriberType]),
-    (New, new_connection, [conn_id: Uuid, socket: TcpStream]),
+    (IdentifyPeer, identify_peer, [conn_id: Uuid, peer: Peer]),
+    (
+        IdentifyInstance,
+        identify_instance,
+        [conn_id: Uuid, instance: Identity]
+    ),
+    (
+        New,
+        new_connection,
+        [conn_id: Uuid, socket: TcpStream, class: ConnectionClass]
+    ),
+    (Classify, classify, [conn_id: Uuid, class: ConnectionClass]),
     (
         UpdateTranslation,
         update_translation,
         [conn_id: Uuid, map: Map]
     ),
-    (Close, close, [conn_id: Uuid])
+    (
+        SetProtocol,
+        set_protocol,
+        [conn_id: Uuid, adapter: ProtocolAdapter]
+    ),
+    (Close, close, [conn_id: Uuid]),
+    (Kick, kick, [conn_id: Uuid, reason: String]),
+    (CloseAll, close_all, [reply: Sender<()>])
 );
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum SubscriberType {
     All,
     Local,
     Remote,
 }
+
+// What's at the other end of a connection, which decides how the messenger treats it. Connections we
+// accept are taken to be players until their handshake says otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionClass {
+    Player,
+    // To or from another node, for a subscription or an anchor
+    PeerLink,
+}
+
+pub struct ConnectionPolicy {
+    // How many packets can be waiting on the transform pool before the connection is cut off for
+    // not keeping up
+    pub max_queued: usize,
+    // Longer packets coming in are refused before being read
+    pub max_packet_length: i32,
+    // Packets at least this long are compressed once the handshake's over, in both directions. See
+    // the compression module
+    pub compression_threshold: Option<usize>,
+    // Names the class in logs and metrics
+    pub label: &'static str,
+}
+
+impl ConnectionClass {
+    pub fn policy(self) -> ConnectionPolicy {
+        match self {
+            // Clients are never sent Set Compression, so they're left uncompressed
+            ConnectionClass::Player => ConnectionPolicy {
+                max_queued: PLAYER_MAX_QUEUED,
+                max_packet_length: PLAYER_MAX_PACKET_LENGTH,
+                compression_threshold: None,
+                label: "player",
+            },
+            // Peers are sent whole maps at a time, and sit on the same network as us
+            ConnectionClass::PeerLink => ConnectionPolicy {
+                max_queued: PEER_LINK_MAX_QUEUED,
+                max_packet_length: PEER_LINK_MAX_PACKET_LENGTH,
+                compression_threshold: Some(PEER_LINK_COMPRESSION_THRESHOLD),
+                label: "peer_link",
+            },
+        }
+    }
+}
+
+// Where a relayed packet was first sent from, and how many peers have passed it on since. Every
+// packet a node sends out has its own sequence number, which is how copies of it are told apart
+// from new ones as they come in from more than one peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Origin {
+    pub node: Peer,
+    pub hops: u8,
+    pub sequence: i64,
+}
+
+// Numbered from somewhere random, so that peers still remembering the packets from before we
+// restarted don't take new ones for copies
+static NEXT_SEQUENCE: OnceLock<AtomicI64> = OnceLock::new();
+
+impl Origin {
+    // For a packet we're sending out ourselves
+    pub fn local(node: Peer) -> Origin {
+        let sequence = NEXT_SEQUENCE
+            .get_or_init(|| AtomicI64::new(Uuid::new_v4().as_u128() as i64))
+            .fetch_add(1, Ordering::Relaxed);
+        Origin {
+            node,
+            hops: 0,
+            sequence,
+        }
+    }
+}
+
+// Everything the messenger has been told about a connection, for handing back should it restart
+pub struct ConnectionSnapshot {
+    socket: TcpStream,
+    class: ConnectionClass,
+    adapter: Option<ProtocolAdapter>,
+    map: Option<Map>,
+    subscriptions: Vec<SubscriberType>,
+    peer: Option<Peer>,
+    instance: Option<Identity>,
+}
+
+impl Restartable for Operations {
+    type Snapshot = HashMap<Uuid, ConnectionSnapshot>;
+
+    fn record(&self, connections: &mut HashMap<Uuid, ConnectionSnapshot>) {
+        match self {
+            Operations::New(msg) => match msg.socket.try_clone() {
+                Ok(socket) => {
+                    connections.insert(
+                        msg.conn_id,
+                        ConnectionSnapshot {
+                            socket,
+                            class: msg.class,
+                            adapter: None,
+                            map: None,
+                            subscriptions: vec![],
+                            peer: None,
+                            instance: None,
+                        },
+                    );
+                }
+                Err(e) => warn!(
+                    "Couldn't keep conn_id {:?} for a restart of the messenger: {}",
+                    msg.conn_id, e
+                ),
+            },
+            Operations::Classify(msg) => {
+                if let Some(connection) = connections.get_mut(&msg.conn_id) {
+                    connection.class = msg.class;
+                }
+            }
+            Operations::SetProtocol(msg) => {
+                if let Some(connection) = connections.get_mut(&msg.conn_id) {
+                    connection.adapter = Some(msg.adapter);
+                }
+            }
+            Operations::UpdateTranslation(msg) => {
+                if let Some(connection) = connections.get_mut(&msg.conn_id) {
+                    connection.map = Some(msg.map.clone());
+                }
+            }
+            Operations::Subscribe(msg) => {
+                if let Some(connection) = connections.get_mut(&msg.conn_id) {
+                    connection.subscriptions.push(msg.typ);
+                }
+            }
+            Operations::IdentifyPeer(msg) => {
+                if let Some(connection) = connections.get_mut(&msg.conn_id) {
+                    connection.peer = Some(msg.peer.clone());
+                }
+            }
+            Operations::IdentifyInstance(msg) => {
+                if let Some(connection) = connections.get_mut(&msg.conn_id) {
+                    connection.instance = Some(msg.instance.clone());
+                }
+            }
+            Operations::Close(msg) => {
+                connections.remove(&msg.conn_id);
+            }
+            Operations::CloseAll(_) => connections.clear(),
+            _ => {}
+        }
+    }
+
+    fn replay(connections: &HashMap<Uuid, ConnectionSnapshot>) -> Vec<Operations> {
+        let mut replayed = vec![];
+        for (conn_id, connection) in connections {
+            let conn_id = *conn_id;
+            let socket = match connection.socket.try_clone() {
+                Ok(socket) => socket,
+                Err(_) => continue,
+            };
+            replayed.push(Operations::New(New {
+                conn_id,
+                socket,
+                class: connection.class,
+                span: Span::none(),
+            }));
+            if let Some(adapter) = connection.adapter {
+                replayed.push(Operations::SetProtocol(SetProtocol {
+                    conn_id,
+                    adapter,
+                    span: Span::none(),
+                }));
+            }
+            if let Some(map) = connection.map.clone() {
+                replayed.push(Operations::UpdateTranslation(UpdateTranslation {
+                    conn_id,
+                    map,
+                    span: Span::none(),
+                }));
+            }
+            for typ in &connection.subscriptions {
+                replayed.push(Operations::Subscribe(Subscribe {
+                    conn_id,
+                    typ: *typ,
+                    span: Span::none(),
+                }));
+            }
+            if let Some(instance) = connection.instance.clone() {
+                replayed.push(Operations::IdentifyInstance(IdentifyInstance {
+                    conn_id,
+                    instance,
+                    span: Span::none(),
+                }));
+            }
+            if let Some(peer) = connection.peer.clone() {
+                replayed.push(Operations::IdentifyPeer(IdentifyPeer {
+                    conn_id,
+                    peer,
+                    span: Span::none(),
+                }));
+            }
+        }
+        replayed
+    }
+}
@@ -1,15 +1,48 @@
+use super::link_watermarks::LinkAlert;
+use super::map::{Dimension, Peer};
+use super::player::Position;
 use super::translation::TranslationUpdates;
 
 use std::io::Cursor;
+use std::net::IpAddr;
 use std::sync::mpsc::Sender;
+use std::time::Instant;
 use uuid::Uuid;
 
 define_interface!(
     PacketProcessor,
-    (Inbound, inbound, [conn_id: Uuid, cursor: Cursor<Vec<u8>>]),
+    // Where a player's connection is from, before anything's read from it
+    (Accept, accept, [conn_id: Uuid, address: IpAddr]),
+    (
+        Inbound,
+        inbound,
+        [conn_id: Uuid, cursor: Cursor<Vec<u8>>, received_at: Instant]
+    ),
     (
         SetTranslationData,
         set_translation_data,
         [conn_id: Uuid, updates: Vec<TranslationUpdates>]
-    )
+    ),
+    (Reject, reject, [conn_id: Uuid, reason: String]),
+    // Who's online, for clients that ping us the way they did before 1.7, which never get as far as
+    // sending a packet. Passed straight on to player state
+    (
+        LegacyPing,
+        legacy_ping,
+        [reply: Sender<Vec<(Uuid, String, Position, Dimension)>>]
+    ),
+    // Sends the player the chunks peers' maps have reported to us, which they may leave out when
+    // they're asked to report again soon after
+    (CatchUp, catch_up, [conn_id: Uuid]),
+    // How many packets are waiting to be sent over a peer link, from the messenger, as it changes.
+    // See LinkWatermarks
+    (
+        ObserveBacklog,
+        observe_backlog,
+        [conn_id: Uuid, backlog: u64]
+    ),
+    (IdentifyLink, identify_link, [conn_id: Uuid, peer: Peer]),
+    // The peer links that are falling behind
+    (LinkAlerts, link_alerts, [reply: Sender<Vec<LinkAlert>>]),
+    (Close, close, [conn_id: Uuid])
 );
@@ -1,12 +1,30 @@
-use super::map::{Peer, PeerConnection};
+use super::block::BlockPosition;
+use super::entity::DroppedItem;
+use super::map::{Dimension, GossipedMap, Peer, PeerConnection, Position as MapPosition};
+use super::messenger::Origin;
 use super::packet::Packet;
+use super::player::Position;
+use super::topology::Topology;
+use serde::{Deserialize, Serialize};
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 use uuid::Uuid;
 
 define_interface!(
     PatchworkState,
-    (Report, report, []),
+    record PatchworkRecord,
+    // Has every peer's map report itself to us again, for the player's sake
+    (Report, report, [conn_id: Uuid]),
     (New, new_map, [peer: Peer]),
+    (Remove, remove_map, [peer: Peer]),
+    // Anchors a player who's just logged in to the map they're on
+    (
+        PlacePlayer,
+        place_player,
+        [conn_id: Uuid, position: Position, dimension: Dimension]
+    ),
+    // Lets go of whatever a player who's left was anchored to
+    (RemovePlayer, remove_player, [conn_id: Uuid]),
     (
         RoutePlayerPacket,
         route_player_packet,
@@ -15,6 +33,142 @@ define_interface!(
     (
         ConnectMap,
         connect_map,
-        [map_index: usize, peer_connection: PeerConnection]
-    )
+        [map_index: usize, peer_connection: PeerConnection, challenge: u128]
+    ),
+    (
+        SummonEntity,
+        summon_entity,
+        [entity_type: i32, position: Position]
+    ),
+    (KillEntity, kill_entity, [entity_id: i32]),
+    // Replies whether the item was handed to the peer whose map it's now over
+    (
+        TransferItem,
+        transfer_item,
+        [item: DroppedItem, reply: Sender<bool>]
+    ),
+    (
+        FillBlocks,
+        fill_blocks,
+        [from: BlockPosition, to: BlockPosition, block_id: i32]
+    ),
+    (
+        PeerUnreachable,
+        peer_unreachable,
+        [map_index: usize, peer: Peer, reason: String]
+    ),
+    (
+        AnchorReady,
+        anchor_ready,
+        [
+            conn_id: Uuid,
+            map_index: usize,
+            anchor_conn_id: Uuid,
+            challenge: u128
+        ]
+    ),
+    (
+        AnchorFailed,
+        anchor_failed,
+        [conn_id: Uuid, map_index: usize]
+    ),
+    (
+        RetryAnchor,
+        retry_anchor,
+        [conn_id: Uuid, map_index: usize]
+    ),
+    (Gossip, gossip, []),
+    (MergeGossip, merge_gossip, [maps: Vec<GossipedMap>]),
+    (Heartbeat, heartbeat, []),
+    (HeartbeatAck, heartbeat_ack, [conn_id: Uuid]),
+    (
+        ProposeMapPosition,
+        propose_map_position,
+        [
+            conn_id: Uuid,
+            peer: Peer,
+            position: MapPosition,
+            entity_id_block: i32
+        ]
+    ),
+    (
+        AgreeMapPosition,
+        agree_map_position,
+        [conn_id: Uuid, position: MapPosition, entity_id_block: i32]
+    ),
+    (HandOff, hand_off, [peer: Peer]),
+    (CheckLoad, check_load, []),
+    (
+        AdoptMap,
+        adopt_map,
+        [
+            previous_owner: Option<Peer>,
+            topology: Topology,
+            // The peer that split its map onto us, if that's why, with the entity id block it's
+            // given ours
+            recruiter: Option<(Peer, i32)>
+        ]
+    ),
+    (
+        ChangeMapOwner,
+        change_map_owner,
+        [conn_id: Uuid, peer: Peer]
+    ),
+    (
+        RelayPluginMessage,
+        relay_plugin_message,
+        [origin: Origin, via: Uuid, channel: String, data: Vec<u8>]
+    ),
+    (
+        LocateEntity,
+        locate_entity,
+        [query: EntityQuery, reply: Sender<EntityOwner>]
+    ),
+    (
+        DescribeMaps,
+        describe_maps,
+        [reply: Sender<Vec<MapDescription>>]
+    ),
+    (
+        EntityOwnerReply,
+        entity_owner_reply,
+        [conn_id: Uuid, query_id: i64, entity_id: Option<i32>]
+    ),
+    // The owner of the map on the connection wants a player anchored there sent back, or
+    // disconnected if there's a reason
+    (
+        KickBack,
+        kick_back,
+        [conn_id: Uuid, uuid: Uuid, reason: Option<String>]
+    ),
+    (ExportTopology, export_topology, [reply: Sender<Topology>]),
+    (ImportTopology, import_topology, [topology: Topology]),
+    (ShutDown, shut_down, [reply: Sender<()>])
 );
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum EntityQuery {
+    Id(i32),
+    Uuid(Uuid),
+}
+
+// What we know about one of the maps in the quilt. Our own map has no owner
+#[derive(Debug, Clone)]
+pub struct MapDescription {
+    pub position: MapPosition,
+    pub name: Option<String>,
+    pub owner: Option<Peer>,
+    pub connected: bool,
+    // Round trip of the last heartbeat the owner answered
+    pub latency: Option<Duration>,
+    // Which block of entity ids the map's entities are given theirs from
+    pub entity_id_block: i32,
+}
+
+// Which instance is in charge of an entity, along with the entity's id as we know it
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntityOwner {
+    Local { entity_id: i32 },
+    Peer { peer: Peer, entity_id: i32 },
+    Unknown,
+}
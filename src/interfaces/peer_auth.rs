@@ -0,0 +1,34 @@
+use super::config::PeerKey;
+use super::identity::Identity;
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    PeerAuth,
+    // Replies with a challenge for the peer on the connection to sign
+    (Challenge, challenge, [conn_id: Uuid, reply: Sender<u128>]),
+    (
+        Sign,
+        sign,
+        [next_state: i32, challenge: u128, reply: Sender<PeerAuthToken>]
+    ),
+    // Checks the token against the challenge the connection was sent, which can't be used again
+    (
+        Verify,
+        verify,
+        [conn_id: Uuid, token: PeerAuthToken, reply: Sender<bool>]
+    ),
+    (Stage, stage, [key: PeerKey]),
+    (Promote, promote, [key_id: String, grace_period: u64])
+);
+
+// Proves to a peer that we hold one of its keys before it lets us into a peer state, and tells it
+// which instance we are
+#[derive(Debug, Clone)]
+pub struct PeerAuthToken {
+    pub next_state: i32,
+    pub key_id: String,
+    pub timestamp: i64,
+    pub mac: String,
+    pub instance: Identity,
+}
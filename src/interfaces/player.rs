@@ -1,11 +1,20 @@
-use super::minecraft_types::{Description, Version};
+use super::map::{Dimension, Position as MapPosition};
+use super::minecraft_types::{Description, ItemStack, Version};
 use super::packet::Packet;
+use super::player_store::SavedPlayer;
+use super::Restartable;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::mpsc::Sender;
+use std::sync::OnceLock;
+use tracing::Span;
 use uuid::Uuid;
 
 define_interface!(
     PlayerState,
+    record PlayerRecord,
     (Report, report, [conn_id: Uuid]),
+    (ListPlayers, list_players, [conn_id: Uuid]),
     (New, new_player, [conn_id: Uuid, player: Player]),
     (Delete, delete_player, [conn_id: Uuid]),
     (
@@ -37,6 +46,84 @@ define_interface!(
         [entity_id: i32, packet: Packet]
     ),
     (Reintroduce, reintroduce, [conn_id: Uuid]),
+    (Positions, positions, [reply: Sender<Vec<(Uuid, Position)>>]),
+    // Our own players, leaving out those anchored here from a peer, by conn_id
+    (
+        Online,
+        online_players,
+        [reply: Sender<Vec<(Uuid, String, Position, Dimension)>>]
+    ),
+    (Teleport, teleport, [conn_id: Uuid, position: Position]),
+    (
+        Respawn,
+        respawn,
+        [
+            conn_id: Uuid,
+            position: Position,
+            dimension: Dimension,
+            reply: Sender<()>
+        ]
+    ),
+    (Find, find_player, [uuid: Uuid, reply: Sender<Option<i32>>]),
+    // Like Find, but replies with the player's conn_id
+    (
+        FindConnection,
+        find_connection,
+        [uuid: Uuid, reply: Sender<Option<Uuid>>]
+    ),
+    // Asks the peer a player anchored here came from to take them back, or to disconnect them if
+    // there's a reason. Replies whether anyone by that name was visiting
+    (
+        KickVisitor,
+        kick_visitor,
+        [name: String, reason: Option<String>, reply: Sender<bool>]
+    ),
+    // Teleports the player to wherever's nearest on the map
+    (PullBack, pull_back, [conn_id: Uuid, onto: MapPosition]),
+    (HoldItem, hold_item, [conn_id: Uuid, slot: i16]),
+    (
+        AddSeam,
+        add_seam,
+        [conn_id: Uuid, neighbour: MapPosition]
+    ),
+    (
+        SetSlot,
+        set_slot,
+        [conn_id: Uuid, slot: i16, item: Option<ItemStack>]
+    ),
+    // The item only leaves the inventory unless spawn is set, for keeping the copy of players
+    // anchored elsewhere in step with the peer that sees them drop it
+    (
+        DropItem,
+        drop_item,
+        [conn_id: Uuid, whole_stack: bool, spawn: bool]
+    ),
+    (ThrowItem, throw_item, [conn_id: Uuid, item: ItemStack]),
+    // Replies with the player's entity id if the item fit in their inventory
+    (
+        PickUp,
+        pick_up,
+        [conn_id: Uuid, item: ItemStack, reply: Sender<Option<i32>>]
+    ),
+    (
+        Saved,
+        saved_player,
+        [name: String, reply: Sender<Option<SavedPlayer>>]
+    ),
+    // What the player's allowed to do, see OperatorStore
+    (
+        PermissionLevel,
+        permission_level,
+        [conn_id: Uuid, reply: Sender<u8>]
+    ),
+    // Replies whether the player's level changed. Players who are online are told straight away
+    (
+        SetOperator,
+        set_operator,
+        [name: String, level: u8, reply: Sender<bool>]
+    ),
+    (Autosave, autosave, []),
+    (SaveAll, save_all, [reply: Sender<usize>]),
     (
         StatusResponse,
         status_response,
@@ -44,25 +131,170 @@ define_interface!(
     )
 );
 
-#[derive(Debug, Clone)]
+pub const PLAYER_INVENTORY_SLOTS: usize = 46;
+// The first slot of the main inventory, the hotbar, and the slot past its end
+pub const MAIN_INVENTORY_START: usize = 9;
+pub const HOTBAR_START: usize = 36;
+pub const HOTBAR_END: usize = 45;
+
+// Where new players join, where everyone respawns after dying and where compasses point. Has to be
+// the same on every node, so it's set once at startup
+static SPAWN: OnceLock<Position> = OnceLock::new();
+
+pub const DEFAULT_SPAWN: Position = Position {
+    x: 5.0,
+    y: 16.0,
+    z: 5.0,
+};
+
+pub fn set_spawn(position: Position) {
+    if SPAWN.set(position).is_err() {
+        warn!("Spawn is already set");
+    }
+}
+
+pub fn spawn_position() -> Position {
+    *SPAWN.get().unwrap_or(&DEFAULT_SPAWN)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
     pub conn_id: Uuid,
     pub uuid: Uuid,
     pub name: String,
     pub position: Position,
     pub angle: Angle,
+    pub velocity: Velocity,
     pub entity_id: i32,
+    // Indexed by slot in the player's inventory window, see https://wiki.vg/Inventory
+    pub inventory: Vec<Option<ItemStack>>,
+    // Which of the nine hotbar slots is selected
+    pub held_item_slot: i16,
+    pub health: Health,
+    pub experience: Experience,
+    pub dimension: Dimension,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Position {
     pub x: f64,
     pub y: f64,
     pub z: f64,
 }
 
-#[derive(Debug, Clone)]
+// In blocks per tick, worked out from how far the player moved since their last position update
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Velocity {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Health {
+    pub health: f32,
+    pub food: i32,
+    pub food_saturation: f32,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health {
+            health: 20.0,
+            food: 20,
+            food_saturation: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Experience {
+    // Progress towards the next level, from 0 to 1
+    pub bar: f32,
+    pub level: i32,
+    pub total: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Angle {
     pub pitch: f32,
     pub yaw: f32,
 }
+
+// Players are put back as they were when they joined, but where they've since moved to and with the
+// items they've since been given. Their entity ids are leased again
+impl Restartable for Operations {
+    type Snapshot = HashMap<Uuid, Player>;
+
+    fn record(&self, players: &mut HashMap<Uuid, Player>) {
+        match self {
+            Operations::New(msg) => {
+                players.insert(msg.conn_id, msg.player.clone());
+            }
+            Operations::Delete(msg) => {
+                players.remove(&msg.conn_id);
+            }
+            Operations::MoveAndLook(MoveAndLook {
+                conn_id,
+                new_position,
+                new_angle,
+                ..
+            })
+            | Operations::AnchoredMoveAndLook(AnchoredMoveAndLook {
+                conn_id,
+                new_position,
+                new_angle,
+                ..
+            }) => {
+                if let Some(player) = players.get_mut(conn_id) {
+                    if let Some(position) = new_position {
+                        player.position = *position;
+                    }
+                    if let Some(angle) = new_angle {
+                        player.angle = angle.clone();
+                    }
+                }
+            }
+            Operations::Teleport(Teleport {
+                conn_id, position, ..
+            })
+            | Operations::Respawn(Respawn {
+                conn_id, position, ..
+            }) => {
+                if let Some(player) = players.get_mut(conn_id) {
+                    player.position = *position;
+                }
+            }
+            Operations::SetSlot(msg) => {
+                if let Some(slot) = players
+                    .get_mut(&msg.conn_id)
+                    .and_then(|player| player.inventory.get_mut(msg.slot as usize))
+                {
+                    *slot = msg.item.clone();
+                }
+            }
+            Operations::HoldItem(msg) => {
+                if let Some(player) = players.get_mut(&msg.conn_id) {
+                    player.held_item_slot = msg.slot;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn replay(players: &HashMap<Uuid, Player>) -> Vec<Operations> {
+        players
+            .iter()
+            .map(|(conn_id, player)| {
+                Operations::New(New {
+                    conn_id: *conn_id,
+                    player: Player {
+                        entity_id: 0,
+                        ..player.clone()
+                    },
+                    span: Span::none(),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,16 @@
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+define_interface!(
+    Whitelist,
+    // Why the player can't join, if they can't
+    (
+        Check,
+        check,
+        [name: String, uuid: Uuid, reply: Sender<Option<String>>]
+    ),
+    (Add, add, [entry: String, reply: Sender<bool>]),
+    (Remove, remove, [entry: String, reply: Sender<bool>]),
+    (List, list, [reply: Sender<Vec<String>>]),
+    (Enable, enable, [enabled: bool])
+);
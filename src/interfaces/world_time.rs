@@ -0,0 +1,16 @@
+use super::weather::Weather;
+
+use uuid::Uuid;
+
+use std::sync::mpsc::Sender;
+
+define_interface!(
+    WorldTimeState,
+    (Tick, tick, []),
+    (Report, report, [conn_id: Uuid]),
+    (
+        PeerUpdate,
+        peer_update,
+        [world_age: i64, time_of_day: i64, weather: Weather]
+    )
+);
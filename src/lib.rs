@@ -0,0 +1,34 @@
+#[macro_use]
+mod services;
+mod admin_api;
+pub mod bot;
+mod chunk_gen_pool;
+pub mod config;
+pub mod conformance;
+pub mod console;
+mod constants;
+pub mod dashboard;
+pub mod error;
+pub mod flight_recorder;
+pub mod interfaces;
+mod link_watermarks;
+mod message_log;
+mod metrics;
+pub mod models;
+pub mod node;
+mod packet_capture;
+mod packet_handlers;
+mod peer_quotas;
+mod query;
+pub mod self_check;
+mod server;
+pub mod shutdown;
+pub mod simulation;
+mod stress;
+pub mod test_client;
+mod transform_pool;
+
+#[macro_use]
+extern crate tracing;
+extern crate serde;
+extern crate serde_json;
@@ -0,0 +1,179 @@
+use super::config::PeerLinkWatermarks;
+use super::metrics;
+use super::models::map::Peer;
+use super::models::watermark::{Crossing, Watermark};
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+// Early warning that a peer link is falling behind, before the seam it carries becomes unplayable.
+// Links are flagged when they've got too much waiting to be sent to the peer, or when what the peer
+// sends us waits too long before it's handled. Crossing a watermark either way is logged and passed
+// on to the metrics, and the links that are over one are kept for the HUD and support bundles. Each
+// node's inbound packet processor keeps its own, which the messenger tells about its backlogs
+pub struct LinkWatermarks {
+    watermarks: PeerLinkWatermarks,
+    links: BTreeMap<Uuid, Link>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Pressure {
+    // Packets waiting on the transform pool to be sent to the peer
+    OutboundBacklog,
+    // Milliseconds between reading a packet from the peer and handling it
+    InboundLag,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkAlert {
+    pub conn_id: String,
+    pub peer: Option<Peer>,
+    pub pressure: Pressure,
+    pub reading: u64,
+}
+
+struct Link {
+    peer: Option<Peer>,
+    outbound: Watermark,
+    inbound: Watermark,
+    outbound_reading: u64,
+    inbound_reading: u64,
+}
+
+impl LinkWatermarks {
+    pub fn new(watermarks: PeerLinkWatermarks) -> LinkWatermarks {
+        LinkWatermarks {
+            watermarks,
+            links: BTreeMap::new(),
+        }
+    }
+
+    fn link(&mut self, conn_id: Uuid) -> &mut Link {
+        let watermarks = self.watermarks;
+        self.links.entry(conn_id).or_insert_with(|| Link {
+            peer: None,
+            outbound: Watermark::new(watermarks.outbound_backlog as u64),
+            inbound: Watermark::new(watermarks.inbound_lag_ms),
+            outbound_reading: 0,
+            inbound_reading: 0,
+        })
+    }
+
+    pub fn observe(&mut self, conn_id: Uuid, pressure: Pressure, reading: u64) {
+        let link = self.link(conn_id);
+        let (watermark, last_reading) = match pressure {
+            Pressure::OutboundBacklog => (&mut link.outbound, &mut link.outbound_reading),
+            Pressure::InboundLag => (&mut link.inbound, &mut link.inbound_reading),
+        };
+        *last_reading = reading;
+        let crossing = match watermark.check(reading) {
+            Some(crossing) => crossing,
+            None => return,
+        };
+        let peer = match &link.peer {
+            Some(peer) => format!("{}:{}", peer.address, peer.port),
+            None => String::from("an unidentified peer"),
+        };
+        match crossing {
+            Crossing::Raised => warn!(
+                "Peer link {:?} to {} is falling behind, {:?} is at {}",
+                conn_id, peer, pressure, reading
+            ),
+            Crossing::Cleared => info!(
+                "Peer link {:?} to {} has caught up, {:?} is back down to {}",
+                conn_id, peer, pressure, reading
+            ),
+        }
+        let alert = LinkAlert {
+            conn_id: conn_id.to_string(),
+            peer: link.peer.clone(),
+            pressure,
+            reading,
+        };
+        metrics::link_alert(alert, crossing == Crossing::Raised);
+    }
+
+    pub fn identify(&mut self, conn_id: Uuid, peer: Peer) {
+        self.link(conn_id).peer = Some(peer);
+    }
+
+    // Whatever it was raising goes with it
+    pub fn forget(&mut self, conn_id: &Uuid) {
+        let link = match self.links.remove(conn_id) {
+            Some(link) => link,
+            None => return,
+        };
+        link.alerts(conn_id)
+            .into_iter()
+            .for_each(|alert| metrics::link_alert(alert, false));
+    }
+
+    // Every watermark that's currently raised
+    pub fn alerts(&self) -> Vec<LinkAlert> {
+        self.links
+            .iter()
+            .flat_map(|(conn_id, link)| link.alerts(conn_id))
+            .collect()
+    }
+}
+
+impl Link {
+    fn alerts(&self, conn_id: &Uuid) -> Vec<LinkAlert> {
+        let raised = [
+            (
+                Pressure::OutboundBacklog,
+                &self.outbound,
+                self.outbound_reading,
+            ),
+            (Pressure::InboundLag, &self.inbound, self.inbound_reading),
+        ];
+        let mut alerts = vec![];
+        for (pressure, watermark, reading) in raised {
+            if watermark.is_raised() {
+                alerts.push(LinkAlert {
+                    conn_id: conn_id.to_string(),
+                    peer: self.peer.clone(),
+                    pressure,
+                    reading,
+                });
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_flagged_until_they_catch_up_or_close() {
+        let mut links = LinkWatermarks::new(PeerLinkWatermarks {
+            outbound_backlog: 100,
+            inbound_lag_ms: 500,
+        });
+        let (slow, fast) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        links.identify(
+            slow,
+            Peer {
+                address: String::from("10.0.0.4"),
+                port: 25565,
+            },
+        );
+        links.observe(slow, Pressure::InboundLag, 800);
+        links.observe(slow, Pressure::OutboundBacklog, 120);
+        links.observe(fast, Pressure::OutboundBacklog, 3);
+        let alerts = links.alerts();
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts.iter().all(|alert| alert.conn_id == slow.to_string()));
+        assert_eq!(alerts[0].peer.as_ref().map(|peer| peer.port), Some(25565));
+
+        // Still over half the watermark, so it stays raised
+        links.observe(slow, Pressure::OutboundBacklog, 60);
+        links.observe(slow, Pressure::InboundLag, 100);
+        assert_eq!(links.alerts()[0].pressure, Pressure::OutboundBacklog);
+        links.forget(&slow);
+        assert!(links.alerts().is_empty());
+    }
+}
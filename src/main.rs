@@ -1,184 +1,71 @@
-#[macro_use]
-mod services;
-mod constants;
-mod interfaces;
-mod models;
-mod packet_handlers;
-mod server;
-
-use interfaces::patchwork::PatchworkState;
-
-use services::instance::ServiceInstance;
+use patchwork::models::map::Peer;
+use patchwork::{config, console, dashboard, flight_recorder, node, self_check, shutdown};
 
 use std::env;
+use std::process;
+use std::sync::mpsc::channel;
 use std::thread;
+use tracing::level_filters::LevelFilter;
 
-#[macro_use]
-extern crate log;
-extern crate simplelog;
-use simplelog::{ConfigBuilder, LevelFilter, SimpleLogger};
-extern crate serde;
-extern crate serde_json;
-
-const DEFAULT_LOGGING_LEVEL: LevelFilter = LevelFilter::Info;
+const DEFAULT_LOGGING_LEVEL: LevelFilter = LevelFilter::INFO;
 
 fn main() {
     let level = match env::var("LOG") {
         Ok(level) => match level.as_str() {
-            "info" => LevelFilter::Info,
-            "trace" => LevelFilter::Trace,
-            "error" => LevelFilter::Error,
+            "info" => LevelFilter::INFO,
+            "trace" => LevelFilter::TRACE,
+            "error" => LevelFilter::ERROR,
             _ => DEFAULT_LOGGING_LEVEL,
         },
         Err(_) => DEFAULT_LOGGING_LEVEL,
     };
 
-    let logger_config = ConfigBuilder::new()
-        .set_max_level(LevelFilter::Off)
-        .set_thread_level(LevelFilter::Off)
-        .set_target_level(LevelFilter::Off)
-        .build();
-
-    SimpleLogger::init(level, logger_config).unwrap();
-
-    define_services!(
-        (
-            module: services::player::start,
-            name: player_state,
-            dependencies: [messenger]
-        ),
-        (
-            module: services::block::start,
-            name: block_state,
-            dependencies: [messenger]
-        ),
-        (
-            module: services::patchwork::start,
-            name: patchwork_state,
-            dependencies: [messenger, inbound_packet_processor, player_state]
-        ),
-        (
-            module: services::messenger::start,
-            name: messenger,
-            dependencies: []
-        ),
-        (
-            module: services::packet_processor::start_inbound,
-            name: inbound_packet_processor,
-            dependencies: [messenger, player_state, block_state, patchwork_state],
-            extras: [None]
-        ),
-        (
-            module: services::connection::start,
-            name: connection_service,
-            dependencies: [messenger, player_state, patchwork_state, inbound_packet_processor]
-        ),
-        (
-            module: services::keep_alive::start,
-            name: keep_alive,
-            dependencies: [messenger]
-        )
-    );
-
-    trace!("Services Started");
-
-    // the stuff below this should also probably be moved to a service model
-    let peer_address = String::from("127.0.0.1");
-    let peer_port = env::var("PEER_PORT").unwrap().parse::<u16>().unwrap();
-
-    patchwork_state.sender().new_map(models::map::Peer {
-        port: peer_port,
-        address: peer_address,
-    });
-
-    server::listen(
-        inbound_packet_processor.sender(),
-        connection_service.sender(),
-        messenger.sender(),
-    );
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::*;
-
-    fn start_trace() {
-        let logger_config = ConfigBuilder::new()
-            .set_max_level(LevelFilter::Off)
-            .set_thread_level(LevelFilter::Off)
-            .set_target_level(LevelFilter::Off)
-            .build();
-        SimpleLogger::init(LevelFilter::Trace, logger_config).unwrap();
+    // The dashboard has the terminal to itself, so logs are only recorded, and shown in it
+    let show_dashboard = env::var_os("TUI").is_some();
+    match show_dashboard {
+        true => flight_recorder::init_quietly(level),
+        false => flight_recorder::init(level),
     }
-
-    #[test]
-    fn test() {
-        start_trace();
-
-        // Since servers handle connection in their own thread, create a channel
-        // to retrieve information
-        let (router_sender, router_receiver) = std::sync::mpsc::channel();
-        let optional_router_sender = Some(router_sender.clone());
-
-        define_services!(
-            (
-                module: services::player::start,
-                name: player_state,
-                dependencies: [messenger]
-            ),
-            (
-                module: services::block::start,
-                name: block_state,
-                dependencies: [messenger]
-            ),
-            (
-                module: services::patchwork::start,
-                name: patchwork_state,
-                dependencies: [messenger, inbound_packet_processor, player_state]
-            ),
-            (
-                module: services::messenger::start,
-                name: messenger,
-                dependencies: []
-            ),
-            (
-                module: services::packet_processor::start_inbound,
-                name: inbound_packet_processor,
-                dependencies: [messenger, player_state, block_state, patchwork_state],
-                extras: [optional_router_sender]
-            ),
-            (
-                module: services::connection::start,
-                name: connection_service,
-                dependencies: [messenger, player_state, patchwork_state, inbound_packet_processor]
-            ),
-            (
-                module: services::keep_alive::start,
-                name: keep_alive,
-                dependencies: [messenger]
-            )
-        );
-        trace!("Services Started");
-
-        // the stuff below this should also probably be moved to a service model
-        let peer_address = String::from("127.0.0.1");
-        let peer_port = std::env::var("PEER_PORT").unwrap().parse::<u16>().unwrap();
-
-        patchwork_state.sender().new_map(models::map::Peer {
-            port: peer_port,
-            address: peer_address,
-        });
-
-        std::thread::spawn(move || {
-            server::listen(
-                inbound_packet_processor.sender(),
-                connection_service.sender(),
-                messenger.sender(),
-            );
-        });
-
-        while let Ok((state, packet)) = router_receiver.recv() {
-            trace!("==[Received]== {:?}, {:?}", state, packet);
+    .unwrap();
+
+    let config = config::load();
+    // Better to refuse to start than to find out part of the way through running
+    let problems = self_check::check(&config);
+    if !problems.is_empty() {
+        eprintln!("Not starting, the config has problems:");
+        problems
+            .iter()
+            .for_each(|problem| eprintln!("  {}", problem));
+        process::exit(1);
+    }
+    node::configure(&config);
+    let local_peer = Peer {
+        port: env::var("PORT").unwrap().parse::<u16>().unwrap(),
+        address: config.advertise_address.clone(),
+    };
+    // Only needed when there's no topology file to lay the quilt out from, and ignored offline
+    let peer = env::var("PEER_PORT").ok().map(|port| Peer {
+        port: port.parse::<u16>().unwrap(),
+        address: String::from("127.0.0.1"),
+    });
+    let node = node::start(config, local_peer, peer);
+
+    // Stopped by a signal or from the console, whichever comes first
+    let (stop_sender, stop) = channel();
+    let dashboard = match show_dashboard {
+        true => Some(dashboard::start(stop_sender.clone())),
+        false => {
+            console::start(&node, stop_sender.clone());
+            None
         }
+    };
+    thread::spawn(move || {
+        shutdown::wait_for_signal();
+        let _ = stop_sender.send(());
+    });
+    let _ = stop.recv();
+    if let Some(dashboard) = dashboard {
+        dashboard.close();
     }
+    node.shut_down();
 }
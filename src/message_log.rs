@@ -0,0 +1,154 @@
+use super::interfaces::Recordable;
+
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+
+// The messages a service was sent, one JSON object per line in the order it handled them. Races
+// between services come down to the order their messages arrive in, so feeding a log back to a
+// fresh instance of the service (see patchwork-replay) plays the race out the same way every time
+pub struct MessageLog {
+    service: &'static str,
+    file: LineWriter<File>,
+}
+
+impl MessageLog {
+    // Starts the service's log in the directory over, if it can be written to
+    pub fn create(directory: &str, service: &'static str) -> Option<MessageLog> {
+        let path = Path::new(directory).join(format!("{}.jsonl", service));
+        match fs::create_dir_all(directory).and_then(|_| File::create(&path)) {
+            Ok(file) => {
+                info!("Recording {} messages to {:?}", service, path);
+                Some(MessageLog {
+                    service,
+                    file: LineWriter::new(file),
+                })
+            }
+            Err(e) => {
+                warn!("Failed to record {} messages to {:?}: {}", service, path, e);
+                None
+            }
+        }
+    }
+
+    pub fn record<O: Recordable>(&mut self, msg: &O) {
+        let written = serde_json::to_string(&msg.to_record())
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(self.file, "{}", line).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            warn!("Failed to record {} message: {}", self.service, e);
+        }
+    }
+}
+
+pub fn load<O: Recordable>(path: &str) -> Result<Vec<O>, String> {
+    let log = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    log.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line)
+                .ok()
+                .and_then(O::from_record)
+                .ok_or_else(|| format!("Line {} of {} isn't a message", number + 1, path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::patchwork::{AnchorReady, Operations, RoutePlayerPacket};
+    use crate::models::packet::{Packet, PlayerPosition};
+    use std::env;
+    use std::process;
+    use tracing::Span;
+    use uuid::Uuid;
+
+    #[test]
+    fn patchwork_messages_are_read_back_as_they_were_recorded() {
+        let directory = env::temp_dir().join(format!("patchwork-message-log-{}", process::id()));
+        let directory = directory.to_str().unwrap();
+        let (conn_id, anchor_conn_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut message_log = MessageLog::create(directory, "patchwork_state").unwrap();
+        message_log.record(&Operations::RoutePlayerPacket(RoutePlayerPacket {
+            packet: Packet::PlayerPosition(PlayerPosition {
+                x: 12.5,
+                feet_y: 64.0,
+                z: -3.0,
+                on_ground: true,
+            }),
+            conn_id,
+            span: Span::none(),
+        }));
+        message_log.record(&Operations::AnchorReady(AnchorReady {
+            conn_id,
+            map_index: 1,
+            anchor_conn_id,
+            challenge: u128::MAX - 1,
+            span: Span::none(),
+        }));
+
+        let path = Path::new(directory).join("patchwork_state.jsonl");
+        let messages = load::<Operations>(path.to_str().unwrap()).unwrap();
+        let _ = fs::remove_dir_all(directory);
+        match &messages[..] {
+            [Operations::RoutePlayerPacket(routed), Operations::AnchorReady(ready)] => {
+                assert_eq!(routed.conn_id, conn_id);
+                match &routed.packet {
+                    Packet::PlayerPosition(position) => {
+                        assert_eq!((position.x, position.z), (12.5, -3.0))
+                    }
+                    other => panic!("Read back {:?}", other),
+                }
+                assert_eq!(ready.map_index, 1);
+                assert_eq!(ready.anchor_conn_id, anchor_conn_id);
+                assert_eq!(ready.challenge, u128::MAX - 1);
+            }
+            other => panic!("Read back {:?}", other),
+        }
+    }
+
+    #[test]
+    fn player_messages_are_read_back_as_they_were_recorded() {
+        use crate::interfaces::player::{BroadcastAnchoredEvent, KickVisitor, Operations};
+        use crate::models::packet::EntityHeadLook;
+
+        let directory = env::temp_dir().join(format!("patchwork-player-log-{}", process::id()));
+        let directory = directory.to_str().unwrap();
+        let mut message_log = MessageLog::create(directory, "player_state").unwrap();
+        message_log.record(&Operations::BroadcastAnchoredEvent(
+            BroadcastAnchoredEvent {
+                entity_id: 7,
+                packet: Packet::EntityHeadLook(EntityHeadLook {
+                    entity_id: 7,
+                    angle: 64,
+                }),
+                span: Span::none(),
+            },
+        ));
+        message_log.record(&Operations::KickVisitor(KickVisitor {
+            name: String::from("visitor"),
+            reason: None,
+            reply: std::sync::mpsc::channel().0,
+            span: Span::none(),
+        }));
+
+        let path = Path::new(directory).join("player_state.jsonl");
+        let messages = load::<Operations>(path.to_str().unwrap()).unwrap();
+        let _ = fs::remove_dir_all(directory);
+        match &messages[..] {
+            [Operations::BroadcastAnchoredEvent(event), Operations::KickVisitor(kick)] => {
+                match &event.packet {
+                    Packet::EntityHeadLook(head_look) => {
+                        assert_eq!((head_look.entity_id, head_look.angle), (7, 64))
+                    }
+                    other => panic!("Read back {:?}", other),
+                }
+                assert_eq!(kick.name, "visitor");
+                assert_eq!(kick.reason, None);
+            }
+            other => panic!("Read back {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use super::interfaces::patchwork::MapDescription;
+use super::link_watermarks::{LinkAlert, Pressure};
+use super::models::map::Position as MapPosition;
+use super::models::packet::Packet;
+use super::services::instance;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use uuid::Uuid;
+
+// Counters, gauges and histograms for Prometheus to scrape from /metrics, in its text format, see
+// https://prometheus.io/docs/instrumenting/exposition_formats/. Written out by hand like the
+// support bundle's zip, as a client library would be more code than the metrics themselves. Like
+// the flight recorder, they're shared by every node in the process
+static PACKETS_READ: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+static PACKETS_WRITTEN: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+static CONNECTIONS: Mutex<BTreeMap<&'static str, usize>> = Mutex::new(BTreeMap::new());
+static ANCHORS: AtomicUsize = AtomicUsize::new(0);
+static BORDER_CROSSINGS: AtomicU64 = AtomicU64::new(0);
+static MAP_PLAYERS: Mutex<BTreeMap<(i32, i32), usize>> = Mutex::new(BTreeMap::new());
+static PACKET_HANDLING: Mutex<Histogram> = Mutex::new(Histogram::new());
+// The peer link watermarks that are raised, by link
+static LINK_ALERTS: Mutex<BTreeMap<(String, Pressure), LinkAlert>> = Mutex::new(BTreeMap::new());
+static SUBSCRIBERS: Mutex<Vec<Sender<Event>>> = Mutex::new(Vec::new());
+
+// What's happened, as it happens, for whoever'd rather watch than scrape, like the dashboard
+#[derive(Debug, Clone)]
+pub enum Event {
+    MapPlayers(BTreeMap<(i32, i32), usize>),
+    // Every map in the quilt, with how its link is holding up
+    Maps(Vec<MapDescription>),
+    BorderCrossed {
+        conn_id: Uuid,
+        from: MapPosition,
+        to: MapPosition,
+        at: SystemTime,
+    },
+    // A peer link's watermark was raised, or cleared again
+    LinkAlert {
+        alert: LinkAlert,
+        raised: bool,
+    },
+}
+
+// Upper bounds of the packet handling histogram's buckets, in seconds
+const HANDLING_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+struct Histogram {
+    buckets: [u64; HANDLING_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+        Histogram {
+            buckets: [0; HANDLING_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = HANDLING_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    // Prometheus buckets are cumulative, each counting everything at or under its bound
+    fn render(&self, name: &str, out: &mut String) {
+        let mut cumulative = 0;
+        for (le, count) in HANDLING_BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+pub fn packet_read(packet: &Packet) {
+    *PACKETS_READ
+        .lock()
+        .unwrap()
+        .entry(packet.name())
+        .or_insert(0) += 1;
+}
+
+pub fn packet_written(packet: &Packet) {
+    *PACKETS_WRITTEN
+        .lock()
+        .unwrap()
+        .entry(packet.name())
+        .or_insert(0) += 1;
+}
+
+// From reading a packet off its connection to having handled it
+pub fn packet_handled(elapsed: Duration) {
+    PACKET_HANDLING
+        .lock()
+        .unwrap()
+        .observe(elapsed.as_secs_f64());
+}
+
+// How many connections of each class are open, by their class's label
+pub fn set_connections(connections: BTreeMap<&'static str, usize>) {
+    *CONNECTIONS.lock().unwrap() = connections;
+}
+
+pub fn set_anchors(anchors: usize) {
+    ANCHORS.store(anchors, Ordering::Relaxed);
+}
+
+pub fn border_crossed(conn_id: Uuid, from: MapPosition, to: MapPosition) {
+    BORDER_CROSSINGS.fetch_add(1, Ordering::Relaxed);
+    publish(|| Event::BorderCrossed {
+        conn_id,
+        from,
+        to,
+        at: SystemTime::now(),
+    });
+}
+
+pub fn set_map_players(players: BTreeMap<(i32, i32), usize>) {
+    publish(|| Event::MapPlayers(players.clone()));
+    *MAP_PLAYERS.lock().unwrap() = players;
+}
+
+pub fn link_alert(alert: LinkAlert, raised: bool) {
+    let key = (alert.conn_id.clone(), alert.pressure);
+    match raised {
+        true => LINK_ALERTS.lock().unwrap().insert(key, alert.clone()),
+        false => LINK_ALERTS.lock().unwrap().remove(&key),
+    };
+    publish(|| Event::LinkAlert { alert, raised });
+}
+
+pub fn maps_described(maps: Vec<MapDescription>) {
+    publish(|| Event::Maps(maps));
+}
+
+// Events from then on. Subscribers that have gone away are dropped the next time there's one
+pub fn subscribe() -> Receiver<Event> {
+    let (sender, receiver) = channel();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    receiver
+}
+
+// The event's only made if someone's listening
+fn publish<F: FnOnce() -> Event>(event: F) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if subscribers.is_empty() {
+        return;
+    }
+    let event = event();
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+fn render() -> String {
+    let mut out = String::new();
+    let counters = [
+        ("patchwork_packets_read_total", &PACKETS_READ),
+        ("patchwork_packets_written_total", &PACKETS_WRITTEN),
+    ];
+    for (name, packets) in counters {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (packet, count) in packets.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{{type=\"{}\"}} {}", name, packet, count);
+        }
+    }
+
+    let _ = writeln!(out, "# TYPE patchwork_queue_depth gauge");
+    for (service, depth) in instance::queue_depths() {
+        let _ = writeln!(
+            out,
+            "patchwork_queue_depth{{service=\"{}\"}} {}",
+            service, depth
+        );
+    }
+
+    let _ = writeln!(out, "# TYPE patchwork_connections gauge");
+    for (class, count) in CONNECTIONS.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "patchwork_connections{{class=\"{}\"}} {}",
+            class, count
+        );
+    }
+
+    let _ = writeln!(out, "# TYPE patchwork_anchors gauge");
+    let _ = writeln!(out, "patchwork_anchors {}", ANCHORS.load(Ordering::Relaxed));
+    let _ = writeln!(out, "# TYPE patchwork_border_crossings_total counter");
+    let _ = writeln!(
+        out,
+        "patchwork_border_crossings_total {}",
+        BORDER_CROSSINGS.load(Ordering::Relaxed)
+    );
+
+    let _ = writeln!(out, "# TYPE patchwork_map_players gauge");
+    for ((x, z), players) in MAP_PLAYERS.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "patchwork_map_players{{x=\"{}\",z=\"{}\"}} {}",
+            x, z, players
+        );
+    }
+
+    // How many links to each peer are over each watermark
+    let mut link_alerts = BTreeMap::new();
+    for alert in LINK_ALERTS.lock().unwrap().values() {
+        let peer = alert
+            .peer
+            .as_ref()
+            .map_or_else(|| String::from("unidentified"), |peer| peer.to_string());
+        *link_alerts.entry((peer, alert.pressure)).or_insert(0) += 1;
+    }
+    let _ = writeln!(out, "# TYPE patchwork_peer_link_alert gauge");
+    for ((peer, pressure), links) in link_alerts {
+        let _ = writeln!(
+            out,
+            "patchwork_peer_link_alert{{peer=\"{}\",pressure=\"{:?}\"}} {}",
+            peer, pressure, links
+        );
+    }
+
+    let _ = writeln!(out, "# TYPE patchwork_packet_handling_seconds histogram");
+    PACKET_HANDLING
+        .lock()
+        .unwrap()
+        .render("patchwork_packet_handling_seconds", &mut out);
+    out
+}
+
+// Answers GET /metrics on the port, and 404s anything else. Scrapes are few and far between, so
+// they're answered one at a time
+pub fn serve(port: u16) {
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to serve metrics on port {}: {}", port, e);
+            return;
+        }
+    };
+    info!("Serving metrics on port {}", port);
+    instance::spawn("metrics", move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream) {
+                trace!("Failed to answer metrics request: {}", e);
+            }
+        }
+    });
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::map::Peer;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new();
+        histogram.observe(0.0002);
+        histogram.observe(0.003);
+        histogram.observe(2.0);
+        let mut out = String::new();
+        histogram.render("handling", &mut out);
+        assert!(out.contains("handling_bucket{le=\"0.0005\"} 1\n"));
+        assert!(out.contains("handling_bucket{le=\"0.005\"} 2\n"));
+        assert!(out.contains("handling_bucket{le=\"1\"} 2\n"));
+        assert!(out.contains("handling_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("handling_count 3\n"));
+    }
+
+    #[test]
+    fn raised_link_alerts_are_gauged_by_peer() {
+        let peer = Some(Peer {
+            address: String::from("10.0.0.9"),
+            port: 25590,
+        });
+        let alert = |conn_id: &str, pressure| LinkAlert {
+            conn_id: String::from(conn_id),
+            peer: peer.clone(),
+            pressure,
+            reading: 900,
+        };
+        let events = subscribe();
+        link_alert(alert("a", Pressure::InboundLag), true);
+        link_alert(alert("b", Pressure::InboundLag), true);
+        link_alert(alert("a", Pressure::OutboundBacklog), true);
+        link_alert(alert("a", Pressure::OutboundBacklog), false);
+        let gauge = "patchwork_peer_link_alert{peer=\"10.0.0.9:25590\"";
+        assert!(render().contains(&format!("{},pressure=\"InboundLag\"}} 2\n", gauge)));
+        assert!(!render().contains(&format!("{},pressure=\"OutboundBacklog\"", gauge)));
+        let raised: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                // Other tests' nodes share the metrics
+                Event::LinkAlert { alert, raised } if alert.peer == peer => Some(raised),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(raised, vec![true, true, true, false]);
+    }
+}
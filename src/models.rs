@@ -1,11 +1,45 @@
 #[macro_use]
 mod packet_macros;
+#[cfg(test)]
+#[macro_use]
+mod packet_strategies;
+pub mod advancements;
+pub mod ban_store;
+pub mod block_registry;
+pub mod chat_limiter;
+pub mod chunk_cache;
+pub mod compression;
+pub mod forwarding;
+pub mod identity;
+pub mod item_registry;
 pub mod map;
 pub mod minecraft_protocol;
 pub mod minecraft_types;
+pub mod operator_store;
 pub mod packet;
+pub mod player_store;
+pub mod position_delta;
+pub mod protocol_adapter;
+pub mod quota;
+pub mod rate_limiter;
+pub mod recent_events;
+pub mod section_hash;
+pub mod support_bundle;
+pub mod token_bucket;
+pub mod topology;
 pub mod translation;
+pub mod uuid_source;
+pub mod versioned;
+pub mod watermark;
+pub mod weather;
+pub mod whitelist_store;
+pub mod world_generator;
+pub mod world_store;
 
+use super::config;
 use super::constants;
+use super::error;
 use super::interfaces;
+use super::link_watermarks;
 use super::server;
+use super::services;
@@ -0,0 +1,165 @@
+use super::minecraft_types::{
+    Advancement, AdvancementDisplay, AdvancementProgress, AdvancementsData, ChatComponent,
+    CriterionProgress, ADVANCEMENT_HAS_BACKGROUND, ADVANCEMENT_SHOW_TOAST,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const ROOT: &str = "patchwork:root";
+pub const QUILT_WALKER: &str = "patchwork:quilt_walker";
+
+// Every advancement has a single criterion of the same name, granted all at once
+const CRITERION: &str = "granted";
+const BACKGROUND_TEXTURE: &str = "minecraft:textures/gui/advancements/backgrounds/stone.png";
+// Item ids as of protocol 404
+const COMPASS_ITEM_ID: i32 = 545;
+const MAP_ITEM_ID: i32 = 608;
+const TASK_FRAME: i32 = 0;
+
+fn advancement(
+    id: &str,
+    parent: Option<&str>,
+    title: &str,
+    description: &str,
+    icon: i32,
+    flags: i32,
+    x: f32,
+) -> Advancement {
+    Advancement {
+        id: String::from(id),
+        parent: parent.map(String::from),
+        display: Some(AdvancementDisplay {
+            title: ChatComponent::new(title).to_json(),
+            description: ChatComponent::new(description).to_json(),
+            icon,
+            frame_type: TASK_FRAME,
+            flags,
+            background_texture: if flags & ADVANCEMENT_HAS_BACKGROUND != 0 {
+                Some(String::from(BACKGROUND_TEXTURE))
+            } else {
+                None
+            },
+            x,
+            y: 0.0,
+        }),
+        criteria: vec![String::from(CRITERION)],
+        requirements: vec![vec![String::from(CRITERION)]],
+    }
+}
+
+fn progress(id: &str, achieved_at: Option<i64>) -> AdvancementProgress {
+    AdvancementProgress {
+        id: String::from(id),
+        criteria: vec![CriterionProgress {
+            id: String::from(CRITERION),
+            achieved_at,
+        }],
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// The full tree sent on join. The root is always granted so the tab is visible
+pub fn tree(granted: &HashSet<String>) -> AdvancementsData {
+    let joined_at = Some(now());
+    AdvancementsData {
+        reset: true,
+        advancements: vec![
+            advancement(
+                ROOT,
+                None,
+                "Patchwork",
+                "One world, many servers",
+                MAP_ITEM_ID,
+                ADVANCEMENT_HAS_BACKGROUND,
+                0.0,
+            ),
+            advancement(
+                QUILT_WALKER,
+                Some(ROOT),
+                "Quilt Walker",
+                "Cross the border to another server",
+                COMPASS_ITEM_ID,
+                ADVANCEMENT_SHOW_TOAST,
+                1.0,
+            ),
+        ],
+        removed: Vec::new(),
+        progress: vec![
+            progress(ROOT, joined_at),
+            progress(
+                QUILT_WALKER,
+                if granted.contains(QUILT_WALKER) {
+                    joined_at
+                } else {
+                    None
+                },
+            ),
+        ],
+    }
+}
+
+// An update completing a single advancement, which is what makes the client show the toast
+pub fn grant(id: &str) -> AdvancementsData {
+    AdvancementsData {
+        reset: false,
+        advancements: Vec::new(),
+        removed: Vec::new(),
+        progress: vec![progress(id, Some(now()))],
+    }
+}
+
+// Granted advancements per player name, written through to a JSON file
+pub struct AdvancementStore {
+    path: String,
+    granted: HashMap<String, HashSet<String>>,
+}
+
+impl AdvancementStore {
+    pub fn load(path: &str) -> AdvancementStore {
+        let granted = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("Failed to parse advancements file {}: {:?}", path, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        AdvancementStore {
+            path: String::from(path),
+            granted,
+        }
+    }
+
+    pub fn granted(&self, player_name: &str) -> HashSet<String> {
+        self.granted.get(player_name).cloned().unwrap_or_default()
+    }
+
+    // Returns false if the player already had the advancement
+    pub fn grant(&mut self, player_name: &str, id: &str) -> bool {
+        let newly_granted = self
+            .granted
+            .entry(String::from(player_name))
+            .or_default()
+            .insert(String::from(id));
+        if newly_granted {
+            self.save();
+        }
+        newly_granted
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string(&self.granted)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|contents| fs::write(&self.path, contents).map_err(|e| format!("{:?}", e)));
+        if let Err(e) = result {
+            error!("Failed to save advancements to {}: {}", self.path, e);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+// A player's name, their uuid or an address, turned away until the ban expires if it ever does
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Ban {
+    pub target: String,
+    pub reason: String,
+    // Seconds since the epoch
+    pub expires_at: Option<u64>,
+    // Bumped whenever the target's banned or pardoned here, so the latest change wins wherever
+    // it's heard of
+    pub version: i64,
+    // Pardons are kept like bans, so that they're passed on in the same way
+    pub active: bool,
+}
+
+impl Ban {
+    pub fn in_force(&self, now: u64) -> bool {
+        self.active && self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+// Every ban and pardon we know of, kept in a JSON file
+#[derive(Debug, Clone, Default)]
+pub struct BanStore {
+    path: String,
+    bans: BTreeMap<String, Ban>,
+}
+
+impl BanStore {
+    // A missing file is an empty ban list, which is written out the first time it's changed
+    pub fn load(path: &str) -> BanStore {
+        let bans: Vec<Ban> = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("Failed to parse ban list {}: {:?}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        BanStore {
+            path: String::from(path),
+            bans: bans
+                .into_iter()
+                .map(|ban| (normalize(&ban.target), ban))
+                .collect(),
+        }
+    }
+
+    // The ban that's keeping the player out, if there is one
+    pub fn refusal(
+        &self,
+        name: &str,
+        uuid: Uuid,
+        address: Option<IpAddr>,
+        now: u64,
+    ) -> Option<&Ban> {
+        let targets = [
+            Some(normalize(name)),
+            Some(normalize(&uuid.to_string())),
+            address.map(|address| address.to_string()),
+        ];
+        targets
+            .iter()
+            .flatten()
+            .filter_map(|target| self.bans.get(target))
+            .find(|ban| ban.in_force(now))
+    }
+
+    // Returns the ban, to be passed on to peers
+    pub fn ban(&mut self, target: &str, reason: &str, expires_at: Option<u64>) -> Ban {
+        let target = normalize(target);
+        let ban = Ban {
+            target: target.clone(),
+            reason: String::from(reason),
+            expires_at,
+            version: self.version(&target) + 1,
+            active: true,
+        };
+        self.bans.insert(target, ban.clone());
+        self.save();
+        ban
+    }
+
+    // Returns the pardon, to be passed on to peers, if the target was banned
+    pub fn pardon(&mut self, target: &str) -> Option<Ban> {
+        let ban = self.bans.get_mut(&normalize(target))?;
+        if !ban.active {
+            return None;
+        }
+        ban.active = false;
+        ban.version += 1;
+        let pardon = ban.clone();
+        self.save();
+        Some(pardon)
+    }
+
+    // A ban or pardon from a peer, kept if it's newer than ours. Returns whether it was
+    pub fn merge(&mut self, ban: Ban) -> bool {
+        let target = normalize(&ban.target);
+        if ban.version <= self.version(&target) {
+            return false;
+        }
+        self.bans.insert(target, ban);
+        self.save();
+        true
+    }
+
+    pub fn in_force(&self, now: u64) -> Vec<Ban> {
+        self.bans
+            .values()
+            .filter(|ban| ban.in_force(now))
+            .cloned()
+            .collect()
+    }
+
+    // Pardons included, for peers to catch up on
+    pub fn all(&self) -> Vec<Ban> {
+        self.bans.values().cloned().collect()
+    }
+
+    fn version(&self, target: &str) -> i64 {
+        self.bans.get(target).map_or(0, |ban| ban.version)
+    }
+
+    fn save(&self) {
+        let contents = serde_json::to_string_pretty(&self.all()).unwrap();
+        if let Err(e) = fs::write(&self.path, contents) {
+            error!("Failed to save ban list {}: {:?}", self.path, e);
+        }
+    }
+}
+
+// Addresses and uuids are kept in their usual form and names in lower case, so each target is only
+// ever in there once
+fn normalize(target: &str) -> String {
+    if let Ok(address) = target.parse::<IpAddr>() {
+        return address.to_string();
+    }
+    match Uuid::parse_str(target) {
+        Ok(uuid) => uuid.to_hyphenated().to_string(),
+        Err(_) => target.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("patchwork-{}-{}.json", name, process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn bans_keep_players_out_until_they_expire_or_are_pardoned() {
+        let path = temp_path("bans");
+        let uuid = Uuid::from_u128(42);
+        let address: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut bans = BanStore::load(&path);
+        bans.ban("Griefer", "Griefing", None);
+        bans.ban(&uuid.to_simple().to_string(), "Spamming", Some(100));
+        bans.ban("10.0.0.1", "Alts", None);
+        assert!(bans.pardon("10.0.0.1").is_some());
+        assert!(bans.pardon("10.0.0.1").is_none());
+
+        let bans = BanStore::load(&path);
+        let _ = fs::remove_file(&path);
+        let reason = |name, uuid, now| bans.refusal(name, uuid, Some(address), now);
+        assert_eq!(
+            reason("GRIEFER", Uuid::nil(), 0).unwrap().reason,
+            "Griefing"
+        );
+        assert_eq!(reason("alex", uuid, 99).unwrap().reason, "Spamming");
+        assert!(reason("alex", uuid, 100).is_none());
+        assert_eq!(bans.in_force(0).len(), 2);
+        assert_eq!(bans.all().len(), 3);
+    }
+
+    #[test]
+    fn only_newer_changes_from_peers_are_kept() {
+        let (path, peer_path) = (temp_path("our-bans"), temp_path("peer-bans"));
+        let mut bans = BanStore::load(&path);
+        let ban = bans.ban("griefer", "Griefing", None);
+        let pardon = bans.pardon("griefer").unwrap();
+
+        let mut peer = BanStore::load(&peer_path);
+        assert!(peer.merge(ban.clone()));
+        assert!(peer.merge(pardon.clone()));
+        assert!(!peer.merge(ban));
+        assert!(!peer.merge(pardon));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&peer_path);
+        assert!(peer.refusal("griefer", Uuid::nil(), None, 0).is_none());
+    }
+}
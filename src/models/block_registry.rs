@@ -0,0 +1,245 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Block state ids change from one protocol version to the next, so blocks are referred to by the
+// names vanilla's data generator gives them and looked up here. Each protocol's registry is read
+// from <registry directory>/<protocol>/blocks.json, the blocks report written by
+// `java -cp server.jar net.minecraft.data.Main --reports`. Without one, only the handful of blocks
+// patchwork uses itself are known
+#[derive(Debug, Default)]
+pub struct BlockRegistry {
+    states: HashMap<BlockStateName, i32>,
+    defaults: HashMap<String, i32>,
+    names: HashMap<i32, BlockStateName>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlockStateName {
+    pub name: String,
+    pub properties: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ReportedBlock {
+    #[serde(default)]
+    states: Vec<ReportedState>,
+}
+
+#[derive(Deserialize)]
+struct ReportedState {
+    id: i32,
+    #[serde(default)]
+    default: bool,
+    #[serde(default)]
+    properties: BTreeMap<String, String>,
+}
+
+// Name, properties, id and whether it's the block's default state
+type BuiltinState = (
+    &'static str,
+    &'static [(&'static str, &'static str)],
+    i32,
+    bool,
+);
+
+// As of protocol 404
+const BUILTIN_404: &[BuiltinState] = &[
+    ("minecraft:air", &[], 0, true),
+    ("minecraft:stone", &[], 1, true),
+    ("minecraft:grass_block", &[("snowy", "true")], 8, false),
+    ("minecraft:grass_block", &[("snowy", "false")], 9, true),
+    ("minecraft:dirt", &[], 10, true),
+    ("minecraft:bedrock", &[], 33, true),
+    ("minecraft:stripped_jungle_log", &[("axis", "y")], 97, true),
+    (
+        "minecraft:stripped_dark_oak_log",
+        &[("axis", "y")],
+        103,
+        true,
+    ),
+    (
+        "minecraft:birch_leaves",
+        &[("distance", "5"), ("persistent", "true")],
+        180,
+        false,
+    ),
+    (
+        "minecraft:birch_leaves",
+        &[("distance", "7"), ("persistent", "false")],
+        185,
+        true,
+    ),
+];
+
+// Blocks that can be walked or fallen through, whatever their state
+const PASSABLE_BLOCKS: &[&str] = &[
+    "minecraft:air",
+    "minecraft:cave_air",
+    "minecraft:void_air",
+    "minecraft:water",
+    "minecraft:lava",
+];
+
+static BLOCK_REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+// Set once at startup, for the protocol version we speak
+pub fn set_block_registry(registry: BlockRegistry) {
+    if BLOCK_REGISTRY.set(registry).is_err() {
+        warn!("Block registry is already set");
+    }
+}
+
+pub fn block_registry() -> &'static BlockRegistry {
+    BLOCK_REGISTRY.get_or_init(BlockRegistry::builtin)
+}
+
+impl BlockRegistry {
+    pub fn load(directory: &str, protocol: u16) -> BlockRegistry {
+        BlockRegistry::read_report(directory, protocol).unwrap_or_else(|e| {
+            warn!("{}, only patchwork's own blocks are known", e);
+            BlockRegistry::builtin()
+        })
+    }
+
+    // Unlike load, doesn't fall back to the builtin blocks, which only have protocol 404's ids
+    pub fn read_report(directory: &str, protocol: u16) -> Result<BlockRegistry, String> {
+        let path = Path::new(directory)
+            .join(protocol.to_string())
+            .join("blocks.json");
+        let contents =
+            fs::read_to_string(&path).map_err(|_| format!("No blocks report at {:?}", path))?;
+        BlockRegistry::from_report(&contents)
+            .map_err(|e| format!("Failed to parse blocks report {:?}: {}", path, e))
+    }
+
+    pub fn from_report(contents: &str) -> Result<BlockRegistry, String> {
+        let report: HashMap<String, ReportedBlock> =
+            serde_json::from_str(contents).map_err(|e| format!("{:?}", e))?;
+        let mut registry = BlockRegistry::default();
+        report.into_iter().for_each(|(name, block)| {
+            block
+                .states
+                .into_iter()
+                .for_each(|state| registry.insert(&name, state.properties, state.id, state.default))
+        });
+        Ok(registry)
+    }
+
+    fn builtin() -> BlockRegistry {
+        let mut registry = BlockRegistry::default();
+        BUILTIN_404
+            .iter()
+            .for_each(|(name, properties, id, default)| {
+                let properties = properties
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect();
+                registry.insert(name, properties, *id, *default)
+            });
+        registry
+    }
+
+    fn insert(&mut self, name: &str, properties: BTreeMap<String, String>, id: i32, default: bool) {
+        let state = BlockStateName {
+            name: String::from(name),
+            properties,
+        };
+        if default {
+            self.defaults.insert(String::from(name), id);
+        }
+        self.names.insert(id, state.clone());
+        self.states.insert(state, id);
+    }
+
+    // The block's default state. The minecraft: namespace can be left off
+    pub fn id(&self, name: &str) -> Option<i32> {
+        self.defaults.get(&namespaced(name)).copied()
+    }
+
+    // Properties that aren't given are taken from the block's default state
+    pub fn state_id(&self, name: &str, properties: &[(&str, &str)]) -> Option<i32> {
+        let mut state = self.names.get(&self.id(name)?)?.clone();
+        properties.iter().for_each(|(key, value)| {
+            state.properties.insert(key.to_string(), value.to_string());
+        });
+        self.states.get(&state).copied()
+    }
+
+    pub fn name(&self, id: i32) -> Option<&BlockStateName> {
+        self.names.get(&id)
+    }
+
+    // Blocks we don't know the name of are taken to be solid, apart from air's id, which is the
+    // same in every protocol
+    pub fn is_solid(&self, id: i32) -> bool {
+        match self.name(id) {
+            Some(state) => !PASSABLE_BLOCKS.contains(&state.name.as_str()),
+            None => id != 0,
+        }
+    }
+
+    // What each of our state ids is in the other registry, for the states both of them have
+    pub fn id_map(&self, other: &BlockRegistry) -> HashMap<i32, i32> {
+        self.names
+            .iter()
+            .filter_map(|(id, state)| Some((*id, *other.states.get(state)?)))
+            .collect()
+    }
+}
+
+fn namespaced(name: &str) -> String {
+    if name.contains(':') {
+        String::from(name)
+    } else {
+        format!("minecraft:{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = r#"{
+        "minecraft:air": {"states": [{"id": 0, "default": true}]},
+        "minecraft:grass_block": {
+            "properties": {"snowy": ["true", "false"]},
+            "states": [
+                {"properties": {"snowy": "true"}, "id": 8},
+                {"properties": {"snowy": "false"}, "id": 9, "default": true}
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn blocks_are_looked_up_by_name_and_state() {
+        let registry = BlockRegistry::from_report(REPORT).unwrap();
+        assert_eq!(registry.id("minecraft:air"), Some(0));
+        assert_eq!(registry.id("grass_block"), Some(9));
+        assert_eq!(
+            registry.state_id("grass_block", &[("snowy", "true")]),
+            Some(8)
+        );
+        assert_eq!(
+            registry.state_id("grass_block", &[("snowy", "maybe")]),
+            None
+        );
+        assert_eq!(registry.name(8).unwrap().name, "minecraft:grass_block");
+        assert_eq!(registry.id("stone"), None);
+        assert!(!registry.is_solid(0));
+        assert!(registry.is_solid(8));
+        assert!(registry.is_solid(1));
+    }
+
+    #[test]
+    fn builtin_blocks_keep_their_protocol_404_ids() {
+        let registry = BlockRegistry::builtin();
+        assert_eq!(registry.id("bedrock"), Some(33));
+        assert_eq!(
+            registry.state_id("birch_leaves", &[("distance", "5"), ("persistent", "true")]),
+            Some(180)
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use super::config::ChatLimit;
+
+use std::time::{Duration, Instant};
+
+// What to do with a message a player just sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    Warned,
+    // Just muted, for this long
+    Muted(Duration),
+    // Already muted, for this much longer
+    StillMuted(Duration),
+    Kicked,
+}
+
+// A token bucket for one player's chat. Warnings are only forgotten once the bucket has filled back
+// up, so someone sending just over the limit is still muted eventually
+#[derive(Debug, Clone)]
+pub struct ChatLimiter {
+    limit: ChatLimit,
+    tokens: f64,
+    refilled_at: Instant,
+    warnings: u32,
+    mutes: u32,
+    muted_until: Option<Instant>,
+}
+
+impl ChatLimiter {
+    pub fn new(limit: ChatLimit, now: Instant) -> ChatLimiter {
+        ChatLimiter {
+            limit,
+            tokens: limit.burst as f64,
+            refilled_at: now,
+            warnings: 0,
+            mutes: 0,
+            muted_until: None,
+        }
+    }
+
+    pub fn check(&mut self, now: Instant) -> Verdict {
+        match self.muted_until {
+            Some(until) if now < until => return Verdict::StillMuted(until - now),
+            Some(_) => {
+                self.muted_until = None;
+                self.tokens = self.limit.burst as f64;
+                self.refilled_at = now;
+            }
+            None => {}
+        }
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.per_second)
+            .min(self.limit.burst as f64);
+        self.refilled_at = now;
+        if self.tokens >= self.limit.burst as f64 {
+            self.warnings = 0;
+        }
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Verdict::Allowed;
+        }
+        self.warnings += 1;
+        if self.warnings <= self.limit.warnings {
+            return Verdict::Warned;
+        }
+        self.warnings = 0;
+        self.mutes += 1;
+        if self.mutes >= self.limit.mutes_before_kick {
+            return Verdict::Kicked;
+        }
+        let mute = Duration::from_secs(self.limit.mute_seconds);
+        self.muted_until = Some(now + mute);
+        Verdict::Muted(mute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit() -> ChatLimit {
+        ChatLimit {
+            burst: 2,
+            per_second: 1.0,
+            warnings: 1,
+            mute_seconds: 10,
+            mutes_before_kick: 2,
+        }
+    }
+
+    #[test]
+    fn spammers_are_warned_then_muted_then_kicked() {
+        let start = Instant::now();
+        let mut limiter = ChatLimiter::new(limit(), start);
+        assert_eq!(limiter.check(start), Verdict::Allowed);
+        assert_eq!(limiter.check(start), Verdict::Allowed);
+        assert_eq!(limiter.check(start), Verdict::Warned);
+        assert_eq!(
+            limiter.check(start),
+            Verdict::Muted(Duration::from_secs(10))
+        );
+        let later = start + Duration::from_secs(4);
+        assert_eq!(
+            limiter.check(later),
+            Verdict::StillMuted(Duration::from_secs(6))
+        );
+
+        // Let back in with a full bucket, which they spend straight away
+        let unmuted = start + Duration::from_secs(10);
+        assert_eq!(limiter.check(unmuted), Verdict::Allowed);
+        assert_eq!(limiter.check(unmuted), Verdict::Allowed);
+        assert_eq!(limiter.check(unmuted), Verdict::Warned);
+        assert_eq!(limiter.check(unmuted), Verdict::Kicked);
+    }
+
+    #[test]
+    fn players_within_the_rate_are_never_warned() {
+        let start = Instant::now();
+        let mut limiter = ChatLimiter::new(limit(), start);
+        for second in 0..20 {
+            assert_eq!(
+                limiter.check(start + Duration::from_secs(second)),
+                Verdict::Allowed
+            );
+        }
+    }
+
+    #[test]
+    fn warnings_are_forgotten_once_the_bucket_refills() {
+        let start = Instant::now();
+        let mut limiter = ChatLimiter::new(limit(), start);
+        limiter.check(start);
+        limiter.check(start);
+        assert_eq!(limiter.check(start), Verdict::Warned);
+        let rested = start + Duration::from_secs(2);
+        limiter.check(rested);
+        limiter.check(rested);
+        assert_eq!(limiter.check(rested), Verdict::Warned);
+    }
+}
@@ -0,0 +1,116 @@
+use super::constants::CHUNK_SIZE;
+use super::packet::Packet;
+
+use std::collections::HashMap;
+
+// Changes kept to a chunk on top of its ChunkData before it's dropped from the cache instead. It
+// has changed since it was reported, so the peer sends it again the next time it's asked to report
+const CHANGE_LIMIT: usize = 64;
+
+// The chunks a peer's map has sent us over our subscription to it, each followed by the changes to
+// its blocks since. Peers only resend the chunks that have changed when they're asked to report
+// again soon after, so players joining in the meantime are caught up from here on the rest
+#[derive(Debug, Default)]
+pub struct ChunkCache {
+    chunks: HashMap<(i32, i32), Vec<Packet>>,
+}
+
+impl ChunkCache {
+    // Anything other than a chunk or a change to one is passed over, as are changes to chunks we
+    // haven't been sent
+    pub fn keep(&mut self, packet: &Packet) {
+        let chunk = match packet {
+            Packet::ChunkData(chunk_data) => {
+                self.chunks.insert(
+                    (chunk_data.chunk_x, chunk_data.chunk_z),
+                    vec![packet.clone()],
+                );
+                return;
+            }
+            Packet::BlockChange(change) => (
+                change.location.x.div_euclid(CHUNK_SIZE),
+                change.location.z.div_euclid(CHUNK_SIZE),
+            ),
+            Packet::MultiBlockChange(change) => (change.chunk_x, change.chunk_z),
+            _ => return,
+        };
+        if let Some(packets) = self.chunks.get_mut(&chunk) {
+            if packets.len() > CHANGE_LIMIT {
+                self.chunks.remove(&chunk);
+            } else {
+                packets.push(packet.clone());
+            }
+        }
+    }
+
+    // Each chunk's changes come after it, in the order they were made
+    pub fn packets(&self) -> impl Iterator<Item = &Packet> {
+        self.chunks.values().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::minecraft_types::{ChunkSection, Location};
+    use crate::models::packet::{BlockChange, ChunkData, KeepAlive};
+
+    fn chunk_data(chunk_x: i32, chunk_z: i32, size: i32) -> Packet {
+        Packet::ChunkData(ChunkData {
+            chunk_x,
+            chunk_z,
+            full_chunk: true,
+            primary_bit_mask: 0,
+            size,
+            data: ChunkSection {
+                bits_per_block: 14,
+                data_array_length: 0,
+                block_ids: Vec::new(),
+                block_light: Vec::new(),
+                sky_light: Vec::new(),
+            },
+            biomes: vec![0; 256],
+            number_of_block_entities: 0,
+        })
+    }
+
+    fn block_change(x: i32, z: i32) -> Packet {
+        Packet::BlockChange(BlockChange {
+            location: Location { x, y: 64, z },
+            block_id: 1,
+        })
+    }
+
+    fn sizes(cache: &ChunkCache) -> Vec<i32> {
+        let mut sizes: Vec<_> = cache
+            .packets()
+            .filter_map(|packet| match packet {
+                Packet::ChunkData(chunk_data) => Some(chunk_data.size),
+                _ => None,
+            })
+            .collect();
+        sizes.sort_unstable();
+        sizes
+    }
+
+    #[test]
+    fn the_latest_copy_of_each_chunk_is_kept_with_its_changes() {
+        let mut cache = ChunkCache::default();
+        cache.keep(&chunk_data(1, -1, 1));
+        cache.keep(&chunk_data(2, -1, 2));
+        cache.keep(&chunk_data(1, -1, 3));
+        cache.keep(&Packet::KeepAlive(KeepAlive { id: 1 }));
+        assert_eq!(sizes(&cache), vec![2, 3]);
+
+        // The first change is to a chunk we haven't been sent
+        cache.keep(&block_change(0, 0));
+        cache.keep(&block_change(17, -3));
+        let packets: Vec<_> = cache.packets().map(Packet::name).collect();
+        assert_eq!(packets.len(), 3);
+        let change = packets.iter().position(|name| *name == "BlockChange");
+        assert_eq!(change.map(|index| packets[index - 1]), Some("ChunkData"));
+
+        (0..CHANGE_LIMIT).for_each(|_| cache.keep(&block_change(17, -3)));
+        assert_eq!(sizes(&cache), vec![2]);
+    }
+}
@@ -0,0 +1,131 @@
+use super::minecraft_protocol::{MinecraftProtocolReader, MinecraftProtocolWriter};
+use super::packet::Packet;
+use super::protocol_adapter::ProtocolAdapter;
+
+use flate2::read::{ZlibDecoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{self, Cursor, ErrorKind::InvalidData, Read, Write};
+
+// Compressed connections frame packets the way vanilla does once it's sent Set Compression: the
+// length of the frame, then the length of the packet uncompressed, or 0 if it's short enough to be
+// left as it is, then the packet, zlib compressed if it's long enough
+pub fn write<S: Write>(
+    stream: &mut S,
+    adapter: ProtocolAdapter,
+    packet: Packet,
+    threshold: Option<usize>,
+) {
+    let threshold = match threshold {
+        Some(threshold) => threshold,
+        None => return adapter.write(stream, packet),
+    };
+    let mut frame = Cursor::new(Vec::new());
+    adapter.write(&mut frame, packet);
+    frame.set_position(0);
+    let _ = frame.read_var_int();
+    let start = frame.position() as usize;
+    let uncompressed = &frame.get_ref()[start..];
+
+    let mut body = Vec::new();
+    if uncompressed.len() < threshold {
+        body.write_var_int(0);
+        body.extend_from_slice(uncompressed);
+    } else {
+        body.write_var_int(uncompressed.len() as i32);
+        let _ = ZlibEncoder::new(uncompressed, Compression::default()).read_to_end(&mut body);
+    }
+    let mut compressed = Vec::new();
+    compressed.write_var_int(body.len() as i32);
+    compressed.extend_from_slice(&body);
+    let _ = stream.write_all(&compressed);
+}
+
+// Turns the body of a compressed frame back into the packet it holds. Packets that would be longer
+// than max_length uncompressed are refused before they're decompressed
+pub fn decompress(body: Vec<u8>, max_length: i32) -> io::Result<Vec<u8>> {
+    let mut body = Cursor::new(body);
+    let length = body.read_var_int()?;
+    let data = &body.get_ref()[body.position() as usize..];
+    if length == 0 {
+        return Ok(data.to_vec());
+    }
+    if !(0..=max_length).contains(&length) {
+        return Err(io::Error::new(
+            InvalidData,
+            format!("Uncompressed packet length {} is out of bounds", length),
+        ));
+    }
+    let mut packet = Vec::with_capacity(length as usize);
+    ZlibDecoder::new(data)
+        .take(length as u64)
+        .read_to_end(&mut packet)?;
+    if packet.len() != length as usize {
+        return Err(io::Error::new(
+            InvalidData,
+            format!(
+                "Packet decompressed to {} bytes rather than {}",
+                packet.len(),
+                length
+            ),
+        ));
+    }
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::packet::{self, KeepAlive, PluginMessage};
+
+    fn written(packet: Packet, threshold: Option<usize>) -> Vec<u8> {
+        let mut stream = Vec::new();
+        write(&mut stream, ProtocolAdapter::default(), packet, threshold);
+        stream
+    }
+
+    #[test]
+    fn packets_are_only_compressed_from_the_threshold_on() {
+        let short = Packet::KeepAlive(KeepAlive { id: 1 });
+        let long = Packet::PluginMessage(PluginMessage {
+            channel: String::from("patchwork:test"),
+            data: vec![7; 4096],
+        });
+        assert_eq!(written(short.clone(), None), {
+            let mut stream = Vec::new();
+            packet::write(&mut stream, short.clone());
+            stream
+        });
+
+        for (packet, threshold, compressed) in [(short, 256, false), (long, 256, true)] {
+            let mut frame = Cursor::new(written(packet.clone(), Some(threshold)));
+            let length = frame.read_var_int().unwrap();
+            let mut body = Vec::new();
+            frame.read_to_end(&mut body).unwrap();
+            assert_eq!(body.len(), length as usize);
+            assert_eq!(body[0] != 0, compressed);
+
+            let uncompressed = decompress(body, 1 << 21).unwrap();
+            let mut original = Cursor::new(Vec::new());
+            packet::write(&mut original, packet);
+            original.set_position(0);
+            original.read_var_int().unwrap();
+            assert_eq!(
+                uncompressed,
+                original.get_ref()[original.position() as usize..]
+            );
+        }
+    }
+
+    #[test]
+    fn packets_too_long_uncompressed_are_refused() {
+        let long = Packet::PluginMessage(PluginMessage {
+            channel: String::from("patchwork:test"),
+            data: vec![7; 4096],
+        });
+        let mut frame = Cursor::new(written(long, Some(256)));
+        frame.read_var_int().unwrap();
+        let mut body = Vec::new();
+        frame.read_to_end(&mut body).unwrap();
+        assert_eq!(decompress(body, 1024).unwrap_err().kind(), InvalidData);
+    }
+}
@@ -0,0 +1,121 @@
+use super::minecraft_protocol::MinecraftProtocolReader;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Cursor;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// The channel Velocity answers with its player info on, and the only version of it we understand
+pub const MODERN_FORWARDING_CHANNEL: &str = "velocity:player_info";
+pub const MODERN_FORWARDING_VERSION: u8 = 1;
+// Each connection only ever asks once, so the message id doesn't have to tell requests apart
+pub const MODERN_FORWARDING_MESSAGE_ID: i32 = 0;
+
+// How a proxy in front of us tells us who's really connecting, instead of itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardingMode {
+    // BungeeCord's, tacked onto the handshake's server address. Anyone who can reach us can claim
+    // to be anyone, so we should only be reachable through the proxy
+    Legacy,
+    // Velocity's, asked for while logging in and signed with the secret we share with it
+    Modern,
+}
+
+// The player's address and uuid as the proxy saw them. Legacy forwarding leaves the name to the
+// player's login
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardedPlayer {
+    pub address: IpAddr,
+    pub uuid: Uuid,
+    pub name: Option<String>,
+}
+
+// host\0address\0uuid, optionally followed by \0 and the player's properties, which we don't use
+pub fn parse_legacy(server_address: &str) -> Result<ForwardedPlayer, String> {
+    let fields: Vec<_> = server_address.split('\0').collect();
+    let (address, uuid) = match fields[..] {
+        [_, address, uuid, ..] => (address, uuid),
+        _ => return Err(String::from("Missing forwarded player info")),
+    };
+    Ok(ForwardedPlayer {
+        address: address
+            .parse()
+            .map_err(|_| format!("Forwarded address {:?} isn't an address", address))?,
+        uuid: Uuid::parse_str(uuid)
+            .map_err(|_| format!("Forwarded uuid {:?} isn't a uuid", uuid))?,
+        name: None,
+    })
+}
+
+// A signature of the rest, then the version, address, uuid, name and properties
+pub fn parse_modern(data: &[u8], secret: &str) -> Result<ForwardedPlayer, String> {
+    if data.len() < 32 {
+        return Err(String::from(
+            "Forwarded player info is too short to be signed",
+        ));
+    }
+    let (signature, info) = data.split_at(32);
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(info);
+    mac.verify_slice(signature)
+        .map_err(|_| String::from("Forwarded player info isn't signed with our secret"))?;
+
+    let mut cursor = Cursor::new(info);
+    let malformed = |e| format!("Malformed forwarded player info: {}", e);
+    let version = cursor.read_var_int().map_err(malformed)?;
+    if version != i32::from(MODERN_FORWARDING_VERSION) {
+        return Err(format!("Unsupported forwarding version {}", version));
+    }
+    let address = cursor.read_string().map_err(malformed)?;
+    let uuid = cursor.read_u_128().map_err(malformed)?;
+    let name = cursor.read_string().map_err(malformed)?;
+    Ok(ForwardedPlayer {
+        address: address
+            .parse()
+            .map_err(|_| format!("Forwarded address {:?} isn't an address", address))?,
+        uuid: Uuid::from_u128(uuid),
+        name: Some(name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::minecraft_protocol::MinecraftProtocolWriter;
+
+    #[test]
+    fn forwarded_players_are_only_believed_if_the_proxy_signed_them() {
+        let uuid = Uuid::from_u128(42);
+        let legacy = format!("example.com\x0010.0.0.1\x00{}\x00[]", uuid.to_simple());
+        assert_eq!(
+            parse_legacy(&legacy),
+            Ok(ForwardedPlayer {
+                address: "10.0.0.1".parse().unwrap(),
+                uuid,
+                name: None
+            })
+        );
+        assert!(parse_legacy("example.com").is_err());
+
+        let mut info = vec![];
+        info.write_var_int(i32::from(MODERN_FORWARDING_VERSION));
+        info.write_string(String::from("10.0.0.1"));
+        info.write_u_128(uuid.as_u128());
+        info.write_string(String::from("alex"));
+        info.write_var_int(0);
+        let mut mac = HmacSha256::new_from_slice(b"secret").unwrap();
+        mac.update(&info);
+        let data = [&mac.finalize().into_bytes()[..], &info].concat();
+        assert_eq!(
+            parse_modern(&data, "secret").unwrap().name.as_deref(),
+            Some("alex")
+        );
+        assert!(parse_modern(&data, "not the secret").is_err());
+    }
+}
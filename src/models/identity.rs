@@ -0,0 +1,70 @@
+use super::map::Peer;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use uuid::Uuid;
+
+// Who a node is, as told to its peers when it authenticates. The id is kept between restarts, so
+// whatever a peer logs about us stays attributed to the same instance even when we reach it from
+// another address, through NAT or a proxy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    pub id: Uuid,
+    pub name: String,
+}
+
+impl Identity {
+    // The id is read from the file, or made up and written there the first time. Without a name
+    // we go by the address peers reach us at
+    pub fn load(id_file: &str, name: Option<String>, local_peer: &Peer) -> Identity {
+        let id = match fs::read_to_string(id_file) {
+            Ok(contents) => Uuid::parse_str(contents.trim()).unwrap_or_else(|e| {
+                panic!("Failed to parse instance id file {}: {:?}", id_file, e)
+            }),
+            Err(_) => {
+                let id = Uuid::new_v4();
+                if let Err(e) = fs::write(id_file, id.to_string()) {
+                    warn!(
+                        "Failed to save instance id to {:?}, it will change on restart: {:?}",
+                        id_file, e
+                    );
+                }
+                id
+            }
+        };
+        Identity {
+            id,
+            name: name.unwrap_or_else(|| local_peer.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn ids_are_kept_between_restarts() {
+        let id_file = env::temp_dir().join(format!("patchwork-instance-{}", process::id()));
+        let id_file = id_file.to_string_lossy();
+        let local_peer = Peer {
+            address: String::from("127.0.0.1"),
+            port: 25565,
+        };
+        let first = Identity::load(&id_file, None, &local_peer);
+        let restarted = Identity::load(&id_file, Some(String::from("spawn")), &local_peer);
+        let _ = fs::remove_file(id_file.as_ref());
+        assert_eq!(first.name, "127.0.0.1:25565");
+        assert_eq!(restarted.id, first.id);
+        assert_eq!(restarted.name, "spawn");
+    }
+}
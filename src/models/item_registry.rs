@@ -0,0 +1,133 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Item ids change between protocol versions just like block state ids, see the block_registry
+// module. They're read from the item registry in <registry directory>/<protocol>/registries.json,
+// the registries report from vanilla's data generator
+#[derive(Debug, Default)]
+pub struct ItemRegistry {
+    names: HashMap<i32, String>,
+}
+
+#[derive(Deserialize)]
+struct ReportedRegistry {
+    entries: HashMap<String, ReportedEntry>,
+}
+
+#[derive(Deserialize)]
+struct ReportedEntry {
+    protocol_id: i32,
+}
+
+// As of protocol 404
+const BUILTIN_404: &[(&str, i32)] = &[
+    ("minecraft:air", 0),
+    ("minecraft:stone", 1),
+    ("minecraft:grass_block", 8),
+    ("minecraft:dirt", 9),
+    ("minecraft:cobblestone", 12),
+];
+
+static ITEM_REGISTRY: OnceLock<ItemRegistry> = OnceLock::new();
+
+// Set once at startup, for the protocol version we speak
+pub fn set_item_registry(registry: ItemRegistry) {
+    if ITEM_REGISTRY.set(registry).is_err() {
+        warn!("Item registry is already set");
+    }
+}
+
+pub fn item_registry() -> &'static ItemRegistry {
+    ITEM_REGISTRY.get_or_init(ItemRegistry::builtin)
+}
+
+impl ItemRegistry {
+    pub fn load(directory: &str, protocol: u16) -> ItemRegistry {
+        ItemRegistry::read_report(directory, protocol).unwrap_or_else(|e| {
+            warn!("{}, only a few items are known", e);
+            ItemRegistry::builtin()
+        })
+    }
+
+    // Unlike load, doesn't fall back to the builtin items, which only have protocol 404's ids
+    pub fn read_report(directory: &str, protocol: u16) -> Result<ItemRegistry, String> {
+        let path = Path::new(directory)
+            .join(protocol.to_string())
+            .join("registries.json");
+        let contents =
+            fs::read_to_string(&path).map_err(|_| format!("No registries report at {:?}", path))?;
+        ItemRegistry::from_report(&contents)
+            .map_err(|e| format!("Failed to parse registries report {:?}: {}", path, e))
+    }
+
+    pub fn from_report(contents: &str) -> Result<ItemRegistry, String> {
+        let mut report: HashMap<String, ReportedRegistry> =
+            serde_json::from_str(contents).map_err(|e| format!("{:?}", e))?;
+        let items = report
+            .remove("minecraft:item")
+            .ok_or_else(|| String::from("No item registry"))?;
+        let mut registry = ItemRegistry::default();
+        items
+            .entries
+            .into_iter()
+            .for_each(|(name, entry)| registry.insert(name, entry.protocol_id));
+        Ok(registry)
+    }
+
+    fn builtin() -> ItemRegistry {
+        let mut registry = ItemRegistry::default();
+        BUILTIN_404
+            .iter()
+            .for_each(|(name, id)| registry.insert(String::from(*name), *id));
+        registry
+    }
+
+    fn insert(&mut self, name: String, id: i32) {
+        self.names.insert(id, name);
+    }
+
+    pub fn name(&self, id: i32) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    // What each of our item ids is in the other registry, for the items both of them have
+    pub fn id_map(&self, other: &ItemRegistry) -> HashMap<i32, i32> {
+        let other_ids: HashMap<&str, i32> = other
+            .names
+            .iter()
+            .map(|(id, name)| (name.as_str(), *id))
+            .collect();
+        self.names
+            .iter()
+            .filter_map(|(id, name)| Some((*id, *other_ids.get(name.as_str())?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_are_named_from_the_item_registry() {
+        let registry = ItemRegistry::from_report(
+            r#"{
+                "minecraft:block": {"entries": {"minecraft:stone": {"protocol_id": 1}}},
+                "minecraft:item": {
+                    "default": "minecraft:air",
+                    "entries": {
+                        "minecraft:air": {"protocol_id": 0},
+                        "minecraft:diamond": {"protocol_id": 536}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(registry.name(536), Some("minecraft:diamond"));
+        assert_eq!(registry.name(0), Some("minecraft:air"));
+        assert_eq!(registry.name(1), None);
+    }
+}
@@ -1,53 +1,180 @@
-use super::interfaces::messenger::Messenger;
+use super::constants::{CHUNK_SIZE, SERVER_PROTOCOL};
+use super::error::{OrLog, PatchworkError};
+use super::interfaces::block::BlockPosition;
+use super::interfaces::messenger::{ConnectionClass, Messenger};
 use super::interfaces::packet_processor::PacketProcessor;
 use super::interfaces::patchwork::PatchworkState;
 use super::packet::{Handshake, Packet};
 use super::server;
-use super::translation::TranslationUpdates;
+use super::services::instance;
+use super::topology::Seed;
+use super::translation::{EntityIdTable, TranslationUpdates};
 
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, min};
+use std::fmt;
 use std::net::TcpStream;
-use std::thread;
+use std::sync::OnceLock;
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+// Every map in the quilt is this many chunks along each side, so it has to be the same on every
+// node, which peers check as they authenticate. Set once at startup, from a config that self_check
+// has already made sure is at least 1. Nodes sharing a process share it too, so setting it again is
+// only allowed if it's to the same size
+static MAP_SIZE: OnceLock<i32> = OnceLock::new();
+
+pub fn set_map_size(chunks: i32) {
+    if chunks < 1 {
+        panic!("Maps must be at least one chunk wide, not {}", chunks);
+    }
+    let set = *MAP_SIZE.get_or_init(|| chunks);
+    if set != chunks {
+        panic!(
+            "Maps in this process are already {} chunks wide, they can't also be {}",
+            set, chunks
+        );
+    }
+}
+
+pub fn map_size() -> i32 {
+    *MAP_SIZE.get().unwrap_or(&1)
+}
+
+// The width of a map in blocks
+pub fn map_width() -> i32 {
+    map_size() * CHUNK_SIZE
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerConnection {
     pub peer: Peer,
     pub conn_id: Uuid,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Peer {
     pub port: u16,
     pub address: String,
 }
 
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.address, self.port)
+    }
+}
+
+// A peer's map as it's described to other peers during gossip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GossipedMap {
+    pub peer: Peer,
+    pub position: Position,
+    // Peers from before seeds were gossiped leave it out
+    #[serde(default)]
+    pub seed: Option<Seed>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Map {
     pub position: Position,
     pub entity_id_block: i32,
     pub peer_connection: Option<PeerConnection>,
+    pub entity_ids: EntityIdTable,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+// Each dimension has a quilt of its own, laid out on the same grid, so a map's position says which
+// one it's in. Peers that predate dimensions only have overworld maps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub x: i32,
     pub z: i32,
+    #[serde(default)]
+    pub dimension: Dimension,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    Nether,
+    #[default]
+    Overworld,
+    TheEnd,
+}
+
+impl Dimension {
+    // As the client knows it in Join Game and Respawn
+    pub fn id(self) -> i32 {
+        match self {
+            Dimension::Nether => -1,
+            Dimension::Overworld => 0,
+            Dimension::TheEnd => 1,
+        }
+    }
+
+    pub fn from_id(id: i32) -> Option<Dimension> {
+        match id {
+            -1 => Some(Dimension::Nether),
+            0 => Some(Dimension::Overworld),
+            1 => Some(Dimension::TheEnd),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Dimension> {
+        match name {
+            "nether" | "the_nether" => Some(Dimension::Nether),
+            "overworld" => Some(Dimension::Overworld),
+            "end" | "the_end" => Some(Dimension::TheEnd),
+            _ => None,
+        }
+    }
 }
 
 impl Map {
     pub fn report<M: Messenger>(&self, messenger: M) {
         if let Some(peer_connection) = &self.peer_connection {
             trace!("Reporting map {:?}", self);
-            messenger.send_packet(
-                peer_connection.conn_id,
-                Packet::Handshake(Handshake {
-                    protocol_version: 404,
-                    server_address: String::from(""), //Neither of these fields are actually used
-                    server_port: 0,
-                    next_state: 5,
-                }),
-            );
+            messenger
+                .send_packet(
+                    peer_connection.conn_id,
+                    Packet::Handshake(Handshake {
+                        protocol_version: SERVER_PROTOCOL as i32,
+                        server_address: String::from(""), //Neither of these fields are actually used
+                        server_port: 0,
+                        next_state: 5,
+                    }),
+                )
+                .or_log();
+        }
+    }
+
+    // Clips a region given in world coordinates to the blocks in this map, returning the corners
+    // of the clipped region in the map's local coordinates
+    pub fn clip_region(
+        &self,
+        from: BlockPosition,
+        to: BlockPosition,
+    ) -> Option<(BlockPosition, BlockPosition)> {
+        let x_origin = self.position.x * map_width();
+        let z_origin = self.position.z * map_width();
+        let min_x = max(min(from.x, to.x), x_origin);
+        let max_x = min(max(from.x, to.x), x_origin + map_width() - 1);
+        let min_z = max(min(from.z, to.z), z_origin);
+        let max_z = min(max(from.z, to.z), z_origin + map_width() - 1);
+        if min_x > max_x || min_z > max_z {
+            return None;
         }
+        Some((
+            BlockPosition {
+                x: min_x - x_origin,
+                y: min(from.y, to.y),
+                z: min_z - z_origin,
+            },
+            BlockPosition {
+                x: max_x - x_origin,
+                y: max(from.y, to.y),
+                z: max_z - z_origin,
+            },
+        ))
     }
 
     pub fn new(position: Position, entity_id_block: i32) -> Map {
@@ -55,6 +182,7 @@ impl Map {
             position,
             entity_id_block,
             peer_connection: None,
+            entity_ids: EntityIdTable::new(),
         }
     }
 
@@ -71,46 +199,122 @@ impl Map {
         map_index: usize,
     ) {
         let conn_id = Uuid::new_v4();
+        // Whatever we learned about the previous owner's entities doesn't apply to this one
+        self.entity_ids.clear();
         let translation_updates = vec![
             TranslationUpdates::State(5),
             TranslationUpdates::EntityIdBlock(self.entity_id_block),
+            TranslationUpdates::EntityIds(self.entity_ids.clone()),
             TranslationUpdates::XOrigin(self.position.x),
+            TranslationUpdates::ZOrigin(self.position.z),
         ];
         let peer_clone = peer.clone();
-        let on_connection = move |stream: TcpStream| {
-            messenger.new_connection(conn_id, stream.try_clone().unwrap());
-            inbound_packet_processor.set_translation_data(conn_id, translation_updates);
+        let patchwork_state_clone = patchwork_state.clone();
+        let on_connection = move |mut stream: TcpStream| {
+            let challenge = match server::request_peer_state(&mut stream, 6) {
+                Ok(challenge) => challenge,
+                Err(e) => {
+                    patchwork_state
+                        .peer_unreachable(map_index, peer_clone, format!("{:?}", e))
+                        .or_log();
+                    return;
+                }
+            };
+            let stream_clone = match stream.try_clone() {
+                Ok(stream_clone) => stream_clone,
+                Err(source) => {
+                    let e = PatchworkError::Connection { conn_id, source };
+                    patchwork_state
+                        .peer_unreachable(map_index, peer_clone, e.to_string())
+                        .or_log();
+                    return;
+                }
+            };
+            messenger
+                .new_connection(conn_id, stream_clone, ConnectionClass::PeerLink)
+                .or_log();
+            inbound_packet_processor
+                .set_translation_data(conn_id, translation_updates)
+                .or_log();
 
             let messenger_clone = messenger.clone();
             let inbound_packet_processor_clone = inbound_packet_processor.clone();
-            thread::spawn(move || {
+            let name = format!("peer-{}", &conn_id.to_simple().to_string()[..8]);
+            instance::spawn(&name, move || {
                 server::handle_connection(
-                    stream.try_clone().unwrap(),
+                    stream,
                     inbound_packet_processor_clone,
                     messenger_clone,
                     conn_id,
+                    ConnectionClass::PeerLink,
                     || {},
                 );
             });
-            messenger.send_packet(
-                conn_id,
-                Packet::Handshake(Handshake {
-                    protocol_version: 404,
-                    server_address: String::from(""),
-                    server_port: 0,
-                    next_state: 6,
-                }),
-            );
-            patchwork_state.connect_map(
-                map_index,
-                PeerConnection {
-                    peer: peer_clone,
-                    conn_id,
-                },
-            );
+            patchwork_state
+                .connect_map(
+                    map_index,
+                    PeerConnection {
+                        peer: peer_clone,
+                        conn_id,
+                    },
+                    challenge,
+                )
+                .or_log();
         };
-        thread::spawn(move || {
-            server::wait_for_connection(peer.address.clone(), peer.port, on_connection);
+        let address = peer.address.clone();
+        let port = peer.port;
+        let on_failure = move |e| {
+            patchwork_state_clone
+                .peer_unreachable(map_index, peer, format!("{:?}", e))
+                .or_log();
+        };
+        instance::spawn("peer-connect", move || {
+            server::wait_for_connection(
+                address,
+                port,
+                &server::PEER_MAP_RETRY_POLICY,
+                on_connection,
+                on_failure,
+            );
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whatever size the process's other tests set is left as it was, since a conflicting one isn't
+    // taken
+    #[test]
+    #[should_panic(expected = "already")]
+    fn maps_cant_change_size_once_its_set() {
+        set_map_size(map_size());
+        set_map_size(map_size());
+        set_map_size(map_size() + 1);
+    }
+
+    #[test]
+    fn maps_from_peers_without_dimensions_are_in_the_overworld() {
+        let gossiped: GossipedMap = serde_json::from_str(
+            r#"{"peer": {"address": "10.0.0.2", "port": 25565}, "position": {"x": 1, "z": -2}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            gossiped.position,
+            Position {
+                x: 1,
+                z: -2,
+                dimension: Dimension::Overworld
+            }
+        );
+        let nether: Position =
+            serde_json::from_str(r#"{"x": 1, "z": -2, "dimension": "nether"}"#).unwrap();
+        assert_ne!(nether, gossiped.position);
+        assert_eq!(
+            Dimension::from_id(nether.dimension.id()),
+            Some(Dimension::Nether)
+        );
+        assert_eq!(Dimension::from_name("the_end"), Some(Dimension::TheEnd));
+    }
+}
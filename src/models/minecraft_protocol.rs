@@ -1,29 +1,41 @@
 extern crate byteorder;
 
-use super::minecraft_types::ChunkSection;
+use super::constants::MAX_NBT_DEPTH;
+use super::minecraft_types::{
+    Advancement, AdvancementDisplay, AdvancementProgress, AdvancementsData, BlockChangeRecord,
+    ChunkSection, CriterionProgress, ItemStack, Location, ADVANCEMENT_HAS_BACKGROUND,
+};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::cmp::{max, min};
-use std::io::{Error, Read, Write};
+use std::io::ErrorKind::{InvalidData, UnexpectedEof};
+use std::io::{self, Error, Read, Write};
 
 const PALETTE_SIZE: i64 = 14; // We don't define our own palette, so we just use the default all blocks palette which is 14 bits
 
+// Everything read comes from whoever is at the other end of a connection, so nothing is trusted.
+// Anything that doesn't make sense is an InvalidData error, and a stream that ends early is an
+// UnexpectedEof error
 pub trait MinecraftProtocolReader {
-    fn read_unsigned_short(&mut self) -> u16;
-    fn read_short(&mut self) -> i16;
-    fn read_var_int(&mut self) -> i32;
-    fn try_read_var_int(&mut self) -> Result<i32, Error>;
-    fn read_long(&mut self) -> i64;
-    fn read_string(&mut self) -> String;
-    fn read_u_128(&mut self) -> u128;
-    fn read_int(&mut self) -> i32;
-    fn read_int_array(&mut self, length: u32) -> Vec<i32>;
-    fn read_var_int_array(&mut self, length: u32) -> Vec<i32>;
-    fn read_chunk_section(&mut self) -> ChunkSection;
-    fn read_float(&mut self) -> f32;
-    fn read_double(&mut self) -> f64;
-    fn read_byte(&mut self) -> i8;
-    fn read_u_byte(&mut self) -> u8;
-    fn read_boolean(&mut self) -> bool;
+    fn read_unsigned_short(&mut self) -> Result<u16, Error>;
+    fn read_short(&mut self) -> Result<i16, Error>;
+    fn read_var_int(&mut self) -> Result<i32, Error>;
+    fn read_long(&mut self) -> Result<i64, Error>;
+    fn read_string(&mut self) -> Result<String, Error>;
+    fn read_u_128(&mut self) -> Result<u128, Error>;
+    fn read_int(&mut self) -> Result<i32, Error>;
+    fn read_int_array(&mut self, length: u32) -> Result<Vec<i32>, Error>;
+    fn read_var_int_array(&mut self, length: u32) -> Result<Vec<i32>, Error>;
+    fn read_chunk_section(&mut self) -> Result<ChunkSection, Error>;
+    fn read_block_change_records(&mut self) -> Result<Vec<BlockChangeRecord>, Error>;
+    fn read_advancements(&mut self) -> Result<AdvancementsData, Error>;
+    fn read_slot(&mut self) -> Result<Option<ItemStack>, Error>;
+    fn read_location(&mut self) -> Result<Location, Error>;
+    fn read_float(&mut self) -> Result<f32, Error>;
+    fn read_double(&mut self) -> Result<f64, Error>;
+    fn read_byte(&mut self) -> Result<i8, Error>;
+    fn read_u_byte(&mut self) -> Result<u8, Error>;
+    fn read_boolean(&mut self) -> Result<bool, Error>;
+    fn read_remaining_bytes(&mut self) -> Result<Vec<u8>, Error>;
 }
 
 pub trait MinecraftProtocolWriter {
@@ -37,96 +49,142 @@ pub trait MinecraftProtocolWriter {
     fn write_int_array(&mut self, v: Vec<i32>);
     fn write_var_int_array(&mut self, v: Vec<i32>);
     fn write_chunk_section(&mut self, v: ChunkSection);
+    fn write_block_change_records(&mut self, v: Vec<BlockChangeRecord>);
+    fn write_advancements(&mut self, v: AdvancementsData);
+    fn write_slot(&mut self, v: Option<ItemStack>);
+    fn write_location(&mut self, v: Location);
     fn write_float(&mut self, v: f32);
     fn write_double(&mut self, v: f64);
     fn write_byte(&mut self, v: i8);
     fn write_u_byte(&mut self, v: u8);
     fn write_boolean(&mut self, v: bool);
+    fn write_bytes(&mut self, v: Vec<u8>);
 }
 
 impl<T: Read> MinecraftProtocolReader for T {
-    fn read_long(&mut self) -> i64 {
-        self.read_i64::<BigEndian>().unwrap()
+    fn read_long(&mut self) -> Result<i64, Error> {
+        self.read_i64::<BigEndian>()
     }
 
-    fn read_var_int(&mut self) -> i32 {
-        self.try_read_var_int().unwrap()
+    fn read_var_int(&mut self) -> Result<i32, Error> {
+        read_var_int(self)
     }
 
-    fn try_read_var_int(&mut self) -> Result<i32, Error> {
-        read_var_int(self)
+    fn read_unsigned_short(&mut self) -> Result<u16, Error> {
+        self.read_u16::<BigEndian>()
     }
 
-    fn read_unsigned_short(&mut self) -> u16 {
-        self.read_u16::<BigEndian>().unwrap()
+    fn read_short(&mut self) -> Result<i16, Error> {
+        self.read_i16::<BigEndian>()
     }
 
-    fn read_short(&mut self) -> i16 {
-        self.read_i16::<BigEndian>().unwrap()
+    fn read_string(&mut self) -> Result<String, Error> {
+        let size = self.read_var_int()?;
+        let buffer = read_exactly(self, size)?;
+        String::from_utf8(buffer).map_err(|e| Error::new(InvalidData, e))
     }
 
-    fn read_string(&mut self) -> String {
-        let size = self.read_var_int();
+    fn read_u_128(&mut self) -> Result<u128, Error> {
+        self.read_u128::<BigEndian>()
+    }
 
-        let mut buffer = vec![0; size as usize];
-        self.read_exact(&mut buffer)
-            .expect("didn't find enough characters");
-        String::from_utf8(buffer).unwrap()
+    fn read_int(&mut self) -> Result<i32, Error> {
+        self.read_i32::<BigEndian>()
     }
 
-    fn read_u_128(&mut self) -> u128 {
-        self.read_u128::<BigEndian>().unwrap()
+    fn read_int_array(&mut self, length: u32) -> Result<Vec<i32>, Error> {
+        (0..length).map(|_| self.read_i32::<BigEndian>()).collect()
     }
 
-    fn read_int(&mut self) -> i32 {
-        self.read_i32::<BigEndian>().unwrap()
+    fn read_var_int_array(&mut self, length: u32) -> Result<Vec<i32>, Error> {
+        (0..length).map(|_| self.read_var_int()).collect()
     }
 
-    fn read_int_array(&mut self, length: u32) -> Vec<i32> {
-        let mut v = Vec::<i32>::new();
-        for _ in 0..length {
-            v.push(self.read_i32::<BigEndian>().unwrap());
-        }
-        v
+    fn read_float(&mut self) -> Result<f32, Error> {
+        self.read_f32::<BigEndian>()
     }
 
-    fn read_var_int_array(&mut self, length: u32) -> Vec<i32> {
-        let mut v = Vec::<i32>::new();
-        for _ in 0..length {
-            v.push(self.read_var_int());
-        }
-        v
+    fn read_chunk_section(&mut self) -> Result<ChunkSection, Error> {
+        read_chunk_section(self)
     }
 
-    fn read_float(&mut self) -> f32 {
-        self.read_f32::<BigEndian>().unwrap()
+    fn read_block_change_records(&mut self) -> Result<Vec<BlockChangeRecord>, Error> {
+        let length = self.read_var_int()?;
+        (0..length)
+            .map(|_| {
+                Ok(BlockChangeRecord {
+                    horizontal_position: self.read_u_byte()?,
+                    y: self.read_u_byte()?,
+                    block_id: self.read_var_int()?,
+                })
+            })
+            .collect()
     }
 
-    fn read_chunk_section(&mut self) -> ChunkSection {
-        read_chunk_section(self)
+    fn read_advancements(&mut self) -> Result<AdvancementsData, Error> {
+        read_advancements(self)
     }
 
-    fn read_double(&mut self) -> f64 {
-        self.read_f64::<BigEndian>().unwrap()
+    // x in the top 26 bits, then y in the next 12, then z in the last 26, all signed
+    fn read_location(&mut self) -> Result<Location, Error> {
+        let v = self.read_long()?;
+        Ok(Location {
+            x: (v >> 38) as i32,
+            y: ((v << 26) >> 52) as i32,
+            z: ((v << 38) >> 38) as i32,
+        })
     }
 
-    fn read_byte(&mut self) -> i8 {
-        self.read_i8().unwrap()
+    fn read_slot(&mut self) -> Result<Option<ItemStack>, Error> {
+        if !self.read_boolean()? {
+            return Ok(None);
+        }
+        let item_id = self.read_var_int()?;
+        let count = self.read_byte()?;
+        let tag_type = self.read_u_byte()?;
+        let nbt = if tag_type == 0 {
+            None
+        } else {
+            let mut recorder = Recorder {
+                stream: self,
+                bytes: vec![tag_type],
+            };
+            skip_nbt_string(&mut recorder)?; // the root tag's name
+            skip_nbt_payload(&mut recorder, tag_type, 0)?;
+            Some(recorder.bytes)
+        };
+        Ok(Some(ItemStack {
+            item_id,
+            count,
+            nbt,
+        }))
     }
 
-    fn read_u_byte(&mut self) -> u8 {
-        self.read_u8().unwrap()
+    fn read_double(&mut self) -> Result<f64, Error> {
+        self.read_f64::<BigEndian>()
     }
 
-    fn read_boolean(&mut self) -> bool {
-        match self.read_u8().unwrap() {
-            1 => true,
-            0 => false,
-            _ => {
-                panic!("Error while unwrapping boolean");
-            }
+    fn read_byte(&mut self) -> Result<i8, Error> {
+        self.read_i8()
+    }
+
+    fn read_u_byte(&mut self) -> Result<u8, Error> {
+        self.read_u8()
+    }
+
+    fn read_boolean(&mut self) -> Result<bool, Error> {
+        match self.read_u8()? {
+            1 => Ok(true),
+            0 => Ok(false),
+            v => Err(Error::new(InvalidData, format!("{} isn't a boolean", v))),
         }
     }
+
+    fn read_remaining_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let mut v = Vec::new();
+        self.read_to_end(&mut v)?;
+        Ok(v)
+    }
 }
 
 impl<T: Write> MinecraftProtocolWriter for T {
@@ -148,7 +206,7 @@ impl<T: Write> MinecraftProtocolWriter for T {
 
     fn write_string(&mut self, v: String) {
         let string_bytes = v.into_bytes();
-        self.write_var_int(string_bytes.clone().into_iter().count() as i32);
+        self.write_var_int(string_bytes.len() as i32);
         self.write_all(&string_bytes).unwrap();
     }
 
@@ -173,6 +231,42 @@ impl<T: Write> MinecraftProtocolWriter for T {
         write_chunk_section(self, v);
     }
 
+    fn write_block_change_records(&mut self, v: Vec<BlockChangeRecord>) {
+        self.write_var_int(v.len() as i32);
+        v.into_iter().for_each(|record| {
+            self.write_u_byte(record.horizontal_position);
+            self.write_u_byte(record.y);
+            self.write_var_int(record.block_id);
+        });
+    }
+
+    fn write_advancements(&mut self, v: AdvancementsData) {
+        write_advancements(self, v);
+    }
+
+    fn write_location(&mut self, v: Location) {
+        self.write_long(
+            ((v.x as i64 & 0x3FFFFFF) << 38)
+                | ((v.y as i64 & 0xFFF) << 26)
+                | (v.z as i64 & 0x3FFFFFF),
+        );
+    }
+
+    fn write_slot(&mut self, v: Option<ItemStack>) {
+        match v {
+            Some(item) => {
+                self.write_boolean(true);
+                self.write_var_int(item.item_id);
+                self.write_byte(item.count);
+                match item.nbt {
+                    Some(nbt) => self.write_bytes(nbt),
+                    None => self.write_u_byte(0), // TAG_End, no nbt
+                }
+            }
+            None => self.write_boolean(false),
+        }
+    }
+
     fn write_float(&mut self, v: f32) {
         self.write_f32::<BigEndian>(v).unwrap();
     }
@@ -196,6 +290,10 @@ impl<T: Write> MinecraftProtocolWriter for T {
             self.write_u8(0).unwrap()
         }
     }
+
+    fn write_bytes(&mut self, v: Vec<u8>) {
+        self.write_all(&v).unwrap();
+    }
 }
 
 fn read_var_int<S: Read>(stream: &mut S) -> Result<i32, Error> {
@@ -203,12 +301,12 @@ fn read_var_int<S: Read>(stream: &mut S) -> Result<i32, Error> {
     let mut result: i32 = 0;
 
     loop {
+        if num_read == 5 {
+            return Err(Error::new(InvalidData, "VarInt is longer than 5 bytes"));
+        }
         let value = i32::from(stream.read_u8()?);
         result |= (value & 0b0111_1111) << (7 * num_read);
         num_read += 1;
-        if num_read > 5 {
-            panic!("VarInt is too big");
-        }
         if (value & 0b1000_0000) == 0 {
             break;
         }
@@ -217,8 +315,47 @@ fn read_var_int<S: Read>(stream: &mut S) -> Result<i32, Error> {
     Ok(result)
 }
 
+// Lengths come off the wire, so rather than allocating however much they claim up front, only what
+// actually arrives is kept
+fn read_exactly<S: Read>(stream: &mut S, length: i32) -> Result<Vec<u8>, Error> {
+    if length < 0 {
+        return Err(Error::new(
+            InvalidData,
+            format!("Negative length {}", length),
+        ));
+    }
+    let mut buffer = Vec::new();
+    stream.take(length as u64).read_to_end(&mut buffer)?;
+    if buffer.len() < length as usize {
+        return Err(Error::new(
+            UnexpectedEof,
+            format!("Expected {} bytes, only got {}", length, buffer.len()),
+        ));
+    }
+    Ok(buffer)
+}
+
+fn skip<S: Read>(stream: &mut S, length: i64) -> Result<(), Error> {
+    if length < 0 {
+        return Err(Error::new(
+            InvalidData,
+            format!("Negative length {}", length),
+        ));
+    }
+    let skipped = io::copy(&mut stream.take(length as u64), &mut io::sink())?;
+    if skipped < length as u64 {
+        return Err(Error::new(
+            UnexpectedEof,
+            format!("Expected {} bytes, only got {}", length, skipped),
+        ));
+    }
+    Ok(())
+}
+
+// Negative numbers take all five bytes. They're shifted unsigned, since shifting them signed never
+// gets down to zero
 fn write_var_int<S: Write>(stream: &mut S, v: i32) {
-    let mut value = v;
+    let mut value = v as u32;
     loop {
         let mut temp = value & 0b0111_1111;
         value >>= 7;
@@ -236,16 +373,7 @@ fn write_var_int<S: Write>(stream: &mut S, v: i32) {
 fn write_chunk_section<S: Write>(stream: &mut S, v: ChunkSection) {
     stream.write_u_byte(v.bits_per_block);
     stream.write_var_int(v.data_array_length);
-    let mut long: i64 = 0;
-    for i in 0..4096 {
-        let block_to_place = i64::from(v.block_ids[i as usize]);
-        let offset = (PALETTE_SIZE * i) % 64;
-        long += block_to_place << offset;
-        if ((i * PALETTE_SIZE) % 64) >= 64 - PALETTE_SIZE {
-            stream.write_long(long);
-            long = block_to_place >> (64 - offset);
-        }
-    }
+    write_block_ids(stream, &v.block_ids);
     for _ in 0..2048 {
         stream
             .write_u8(!0b0)
@@ -258,17 +386,35 @@ fn write_chunk_section<S: Write>(stream: &mut S, v: ChunkSection) {
     }
 }
 
-fn read_chunk_section<S: Read>(stream: &mut S) -> ChunkSection {
-    let bits_per_block = stream.read_u_byte();
+// Packs a section's 4096 block ids into longs of the global palette, as every version up to 1.15
+// has them
+pub fn write_block_ids<S: Write>(stream: &mut S, block_ids: &[i32]) {
+    let mut long: i64 = 0;
+    for i in 0..4096 {
+        let block_to_place = i64::from(block_ids[i as usize]);
+        let offset = (PALETTE_SIZE * i) % 64;
+        long += block_to_place << offset;
+        if ((i * PALETTE_SIZE) % 64) >= 64 - PALETTE_SIZE {
+            stream.write_long(long);
+            long = block_to_place >> (64 - offset);
+        }
+    }
+}
+
+fn read_chunk_section<S: Read>(stream: &mut S) -> Result<ChunkSection, Error> {
+    let bits_per_block = stream.read_u_byte()?;
     if bits_per_block != PALETTE_SIZE as u8 {
-        panic!("Cannot read palettes");
+        return Err(Error::new(InvalidData, "Cannot read palettes"));
     }
-    let data_array_length = stream.read_var_int();
+    let data_array_length = stream.read_var_int()?;
     if data_array_length != 896 {
-        panic!("Got unexpected data array length");
+        return Err(Error::new(
+            InvalidData,
+            format!("Unexpected data array length {}", data_array_length),
+        ));
     }
     let mut block_ids = Vec::<i32>::new();
-    let mut long = stream.read_u64::<BigEndian>().unwrap();
+    let mut long = stream.read_u64::<BigEndian>()?;
     let mut index = 0;
     for i in 0..4096 {
         let bits_to_read = min(64 - (index % 64), 14);
@@ -276,7 +422,7 @@ fn read_chunk_section<S: Read>(stream: &mut S) -> ChunkSection {
         let right_shift = left_shift + (index % 64);
         let mut block_id = (long << left_shift) >> right_shift;
         if left_shift == 0 && i != 4095 {
-            long = stream.read_u64::<BigEndian>().unwrap();
+            long = stream.read_u64::<BigEndian>()?;
         }
         if bits_to_read < 14 {
             let remainder_to_read = 14 - bits_to_read;
@@ -287,17 +433,318 @@ fn read_chunk_section<S: Read>(stream: &mut S) -> ChunkSection {
         index += 14;
     }
     //Still ignoring these values for now
-    for _ in 0..2048 {
-        stream.read_u8().unwrap();
-    }
-    for _ in 0..2048 {
-        stream.read_u8().unwrap();
-    }
-    ChunkSection {
+    skip(stream, 2048)?; // block light
+    skip(stream, 2048)?; // sky light
+    Ok(ChunkSection {
         bits_per_block,
         data_array_length,
         block_ids,
         block_light: Vec::<u64>::new(),
         sky_light: Vec::<u64>::new(),
+    })
+}
+
+fn read_optional_string<S: Read>(stream: &mut S) -> Result<Option<String>, Error> {
+    if stream.read_boolean()? {
+        Ok(Some(stream.read_string()?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn write_optional_string<S: Write>(stream: &mut S, v: Option<String>) {
+    stream.write_boolean(v.is_some());
+    if let Some(v) = v {
+        stream.write_string(v);
+    }
+}
+
+fn read_string_array<S: Read>(stream: &mut S) -> Result<Vec<String>, Error> {
+    let length = stream.read_var_int()?;
+    (0..length).map(|_| stream.read_string()).collect()
+}
+
+fn write_string_array<S: Write>(stream: &mut S, v: Vec<String>) {
+    stream.write_var_int(v.len() as i32);
+    v.into_iter()
+        .for_each(|element| stream.write_string(element));
+}
+
+fn read_advancements<S: Read>(stream: &mut S) -> Result<AdvancementsData, Error> {
+    let reset = stream.read_boolean()?;
+    let advancement_count = stream.read_var_int()?;
+    let advancements = (0..advancement_count)
+        .map(|_| {
+            let id = stream.read_string()?;
+            let parent = read_optional_string(stream)?;
+            let display = if stream.read_boolean()? {
+                let title = stream.read_string()?;
+                let description = stream.read_string()?;
+                let icon = read_single_item_slot(stream)?;
+                let frame_type = stream.read_var_int()?;
+                let flags = MinecraftProtocolReader::read_int(stream)?;
+                let background_texture = if flags & ADVANCEMENT_HAS_BACKGROUND != 0 {
+                    Some(stream.read_string()?)
+                } else {
+                    None
+                };
+                Some(AdvancementDisplay {
+                    title,
+                    description,
+                    icon,
+                    frame_type,
+                    flags,
+                    background_texture,
+                    x: stream.read_float()?,
+                    y: stream.read_float()?,
+                })
+            } else {
+                None
+            };
+            let criteria = read_string_array(stream)?;
+            let requirement_count = stream.read_var_int()?;
+            let requirements = (0..requirement_count)
+                .map(|_| read_string_array(stream))
+                .collect::<Result<_, _>>()?;
+            Ok(Advancement {
+                id,
+                parent,
+                display,
+                criteria,
+                requirements,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    let removed = read_string_array(stream)?;
+    let progress_count = stream.read_var_int()?;
+    let progress = (0..progress_count)
+        .map(|_| {
+            let id = stream.read_string()?;
+            let criterion_count = stream.read_var_int()?;
+            let criteria = (0..criterion_count)
+                .map(|_| {
+                    let id = stream.read_string()?;
+                    let achieved_at = if stream.read_boolean()? {
+                        Some(stream.read_long()?)
+                    } else {
+                        None
+                    };
+                    Ok(CriterionProgress { id, achieved_at })
+                })
+                .collect::<Result<_, Error>>()?;
+            Ok(AdvancementProgress { id, criteria })
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(AdvancementsData {
+        reset,
+        advancements,
+        removed,
+        progress,
+    })
+}
+
+fn write_advancements<S: Write>(stream: &mut S, v: AdvancementsData) {
+    stream.write_boolean(v.reset);
+    stream.write_var_int(v.advancements.len() as i32);
+    v.advancements.into_iter().for_each(|advancement| {
+        stream.write_string(advancement.id);
+        write_optional_string(stream, advancement.parent);
+        stream.write_boolean(advancement.display.is_some());
+        if let Some(display) = advancement.display {
+            stream.write_string(display.title);
+            stream.write_string(display.description);
+            write_single_item_slot(stream, display.icon);
+            stream.write_var_int(display.frame_type);
+            MinecraftProtocolWriter::write_int(stream, display.flags);
+            if let Some(background_texture) = display.background_texture {
+                stream.write_string(background_texture);
+            }
+            stream.write_float(display.x);
+            stream.write_float(display.y);
+        }
+        // Criteria are a map of identifier to nothing
+        write_string_array(stream, advancement.criteria);
+        stream.write_var_int(advancement.requirements.len() as i32);
+        advancement
+            .requirements
+            .into_iter()
+            .for_each(|requirement| write_string_array(stream, requirement));
+    });
+    write_string_array(stream, v.removed);
+    stream.write_var_int(v.progress.len() as i32);
+    v.progress.into_iter().for_each(|progress| {
+        stream.write_string(progress.id);
+        stream.write_var_int(progress.criteria.len() as i32);
+        progress.criteria.into_iter().for_each(|criterion| {
+            stream.write_string(criterion.id);
+            stream.write_boolean(criterion.achieved_at.is_some());
+            if let Some(achieved_at) = criterion.achieved_at {
+                stream.write_long(achieved_at);
+            }
+        });
+    });
+}
+
+// Advancement icons are slots, but we only ever use a single item without any nbt
+fn read_single_item_slot<S: Read>(stream: &mut S) -> Result<i32, Error> {
+    if !stream.read_boolean()? {
+        return Ok(0);
+    }
+    let item_id = stream.read_var_int()?;
+    stream.read_byte()?; // count
+    if stream.read_u_byte()? != 0 {
+        return Err(Error::new(InvalidData, "Cannot read slot nbt"));
+    }
+    Ok(item_id)
+}
+
+fn write_single_item_slot<S: Write>(stream: &mut S, item_id: i32) {
+    stream.write_boolean(true);
+    stream.write_var_int(item_id);
+    stream.write_byte(1);
+    stream.write_u_byte(0); // TAG_End, no nbt
+}
+
+// Keeps a copy of everything read through it, so that nbt can be kept without being parsed
+struct Recorder<'a, S> {
+    stream: &'a mut S,
+    bytes: Vec<u8>,
+}
+
+impl<S: Read> Read for Recorder<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let read = self.stream.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+// NBT strings are prefixed with an unsigned short length rather than a VarInt
+fn skip_nbt_string<S: Read>(stream: &mut S) -> Result<(), Error> {
+    let length = stream.read_unsigned_short()?;
+    skip(stream, i64::from(length))
+}
+
+// Reads past the payload of a tag of the given type, nested depth lists and compounds deep, see
+// https://wiki.vg/NBT
+fn skip_nbt_payload<S: Read>(stream: &mut S, tag_type: u8, depth: usize) -> Result<(), Error> {
+    if depth > MAX_NBT_DEPTH {
+        return Err(Error::new(
+            InvalidData,
+            format!("Nbt nested more than {} deep", MAX_NBT_DEPTH),
+        ));
+    }
+    match tag_type {
+        1 => skip(stream, 1),
+        2 => skip(stream, 2),
+        3 | 5 => skip(stream, 4),
+        4 | 6 => skip(stream, 8),
+        7 => {
+            let length = MinecraftProtocolReader::read_int(stream)?;
+            skip(stream, i64::from(length))
+        }
+        8 => skip_nbt_string(stream),
+        9 => {
+            let element_type = stream.read_u_byte()?;
+            let length = MinecraftProtocolReader::read_int(stream)?;
+            (0..length).try_for_each(|_| skip_nbt_payload(stream, element_type, depth + 1))
+        }
+        10 => loop {
+            let tag_type = stream.read_u_byte()?;
+            if tag_type == 0 {
+                break Ok(());
+            }
+            skip_nbt_string(stream)?;
+            skip_nbt_payload(stream, tag_type, depth + 1)?;
+        },
+        11 => {
+            let length = MinecraftProtocolReader::read_int(stream)?;
+            skip(stream, i64::from(length) * 4)
+        }
+        12 => {
+            let length = MinecraftProtocolReader::read_int(stream)?;
+            skip(stream, i64::from(length) * 8)
+        }
+        _ => Err(Error::new(
+            InvalidData,
+            format!("Unknown nbt tag type {}", tag_type),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn slots_keep_their_nbt() {
+        // {Damage: 5} in an unnamed root compound
+        let nbt = vec![
+            10, 0, 0, 3, 0, 6, b'D', b'a', b'm', b'a', b'g', b'e', 0, 0, 0, 5, 0,
+        ];
+        let item = ItemStack {
+            item_id: 478,
+            count: 1,
+            nbt: Some(nbt),
+        };
+        let mut bytes = Vec::new();
+        bytes.write_slot(Some(item.clone()));
+        bytes.write_slot(None);
+        let mut cursor = Cursor::new(bytes);
+        assert_eq!(cursor.read_slot().unwrap(), Some(item));
+        assert_eq!(cursor.read_slot().unwrap(), None);
+    }
+
+    #[test]
+    fn deeply_nested_nbt_is_an_error_rather_than_a_stack_overflow() {
+        // Compounds each holding an unnamed compound, far deeper than anything vanilla accepts
+        let depth = 100_000;
+        let mut bytes = Vec::new();
+        bytes.write_boolean(true);
+        bytes.write_var_int(478);
+        bytes.write_byte(1);
+        bytes.write_bytes(vec![10, 0, 0]);
+        (0..depth).for_each(|_| bytes.write_bytes(vec![10, 0, 0]));
+        bytes.write_bytes(vec![0; depth + 1]);
+        let mut cursor = Cursor::new(bytes);
+        assert_eq!(cursor.read_slot().unwrap_err().kind(), InvalidData);
+
+        // Nested exactly as deeply as is allowed is fine
+        let mut bytes = Vec::new();
+        bytes.write_boolean(true);
+        bytes.write_var_int(478);
+        bytes.write_byte(1);
+        bytes.write_bytes(vec![10, 0, 0]);
+        (0..MAX_NBT_DEPTH).for_each(|_| bytes.write_bytes(vec![10, 0, 0]));
+        bytes.write_bytes(vec![0; MAX_NBT_DEPTH + 1]);
+        assert!(Cursor::new(bytes).read_slot().unwrap().is_some());
+    }
+
+    #[test]
+    fn garbage_is_an_error_rather_than_a_panic() {
+        let mut cursor = Cursor::new(vec![0xFF; 6]);
+        assert_eq!(cursor.read_var_int().unwrap_err().kind(), InvalidData);
+
+        let mut cursor = Cursor::new(vec![0xFF]);
+        assert_eq!(cursor.read_var_int().unwrap_err().kind(), UnexpectedEof);
+
+        // Says it's a billion bytes long, then ends
+        let mut bytes = Vec::new();
+        bytes.write_var_int(1_000_000_000);
+        bytes.write_bytes(b"abc".to_vec());
+        let mut cursor = Cursor::new(bytes);
+        assert_eq!(cursor.read_string().unwrap_err().kind(), UnexpectedEof);
+
+        let mut cursor = Cursor::new(vec![2]);
+        assert_eq!(cursor.read_boolean().unwrap_err().kind(), InvalidData);
+    }
+
+    #[test]
+    fn negative_var_ints_take_five_bytes() {
+        let mut bytes = Vec::new();
+        bytes.write_var_int(-1);
+        assert_eq!(bytes, vec![0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+        assert_eq!(Cursor::new(bytes).read_var_int().unwrap(), -1);
     }
 }
@@ -1,3 +1,4 @@
+use super::item_registry::item_registry;
 use serde::{Deserialize, Serialize};
 
 pub fn float_to_angle(f: f32) -> u8 {
@@ -13,7 +14,82 @@ pub struct ChunkSection {
     pub sky_light: Vec<u64>,   //2048 bytes (all 1s)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// The contents of an inventory slot. Item nbt (enchantments, names and the like) is kept exactly
+// as the client sent it, root tag included, without being parsed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemStack {
+    pub item_id: i32,
+    pub count: i8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbt: Option<Vec<u8>>,
+}
+
+impl ItemStack {
+    pub fn name(&self) -> Option<&'static str> {
+        item_registry().name(self.item_id)
+    }
+}
+
+// A block's position, packed into a long on the wire
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockChangeRecord {
+    pub horizontal_position: u8, // x in the high nibble, z in the low nibble
+    pub y: u8,
+    pub block_id: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdvancementsData {
+    pub reset: bool,
+    pub advancements: Vec<Advancement>,
+    pub removed: Vec<String>,
+    pub progress: Vec<AdvancementProgress>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Advancement {
+    pub id: String,
+    pub parent: Option<String>,
+    pub display: Option<AdvancementDisplay>,
+    pub criteria: Vec<String>,
+    pub requirements: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdvancementDisplay {
+    pub title: String,       //json chat
+    pub description: String, //json chat
+    pub icon: i32,           //item id, always a stack of one without nbt
+    pub frame_type: i32,
+    pub flags: i32,
+    pub background_texture: Option<String>,
+    pub x: f32,
+    pub y: f32,
+}
+
+pub const ADVANCEMENT_HAS_BACKGROUND: i32 = 0x1;
+pub const ADVANCEMENT_SHOW_TOAST: i32 = 0x2;
+
+#[derive(Debug, Clone)]
+pub struct AdvancementProgress {
+    pub id: String,
+    pub criteria: Vec<CriterionProgress>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CriterionProgress {
+    pub id: String,
+    pub achieved_at: Option<i64>, //milliseconds since the epoch
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Version {
     pub name: String,
     pub protocol: u16,
@@ -32,7 +108,7 @@ pub struct PingPlayersInfo {
     pub sample: Vec<PingSamplePlayer>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Description {
     pub text: String,
 }
@@ -43,3 +119,20 @@ pub struct StatusResponse {
     pub players: PingPlayersInfo,
     pub description: Description,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatComponent {
+    pub text: String,
+}
+
+impl ChatComponent {
+    pub fn new(text: &str) -> ChatComponent {
+        ChatComponent {
+            text: text.to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+// The highest permission level, which can run every command
+pub const MAX_PERMISSION_LEVEL: u8 = 4;
+
+// How much each operator is trusted with, by name, kept in a JSON object. Levels go as in vanilla:
+// 1 bypasses spawn protection, 2 can cheat, 3 can manage players and 4 can manage the server.
+// Everyone else is at 0
+#[derive(Debug, Clone, Default)]
+pub struct OperatorStore {
+    path: String,
+    levels: BTreeMap<String, u8>,
+}
+
+impl OperatorStore {
+    // A missing file means there are no operators, and is written out the first time one's made
+    pub fn load(path: &str) -> OperatorStore {
+        let levels = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("Failed to parse operators {}: {:?}", path, e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        OperatorStore {
+            path: String::from(path),
+            levels,
+        }
+    }
+
+    pub fn level(&self, name: &str) -> u8 {
+        self.levels
+            .get(&name.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Setting a level of 0 takes the player off the list. Returns whether their level changed
+    pub fn set(&mut self, name: &str, level: u8) -> bool {
+        let name = name.to_ascii_lowercase();
+        let level = level.min(MAX_PERMISSION_LEVEL);
+        let changed = match level {
+            0 => self.levels.remove(&name).is_some(),
+            level => self.levels.insert(name, level) != Some(level),
+        };
+        if changed {
+            self.save();
+        }
+        changed
+    }
+
+    fn save(&self) {
+        let contents = serde_json::to_string_pretty(&self.levels).unwrap();
+        if let Err(e) = fs::write(&self.path, contents) {
+            error!("Failed to save operators {}: {:?}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn levels_are_kept_by_name_whatever_its_case() {
+        let path = env::temp_dir()
+            .join(format!("patchwork-operators-{}.json", process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut operators = OperatorStore::load(&path);
+        assert!(operators.set("Notch", 9));
+        assert!(!operators.set("notch", MAX_PERMISSION_LEVEL));
+        assert!(operators.set("jeb_", 2));
+        assert!(operators.set("JEB_", 0));
+        assert!(!operators.set("jeb_", 0));
+
+        let operators = OperatorStore::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(operators.level("NOTCH"), MAX_PERMISSION_LEVEL);
+        assert_eq!(operators.level("jeb_"), 0);
+    }
+}
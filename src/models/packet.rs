@@ -1,13 +1,63 @@
 #![allow(unused_variables)]
 //The macro is much cleaner if we allow for unused variables
-use super::constants::{CHUNK_SIZE, ENTITY_ID_BLOCK_SIZE};
+use super::map::{map_size, map_width};
 use super::minecraft_protocol::{MinecraftProtocolReader, MinecraftProtocolWriter};
-use super::minecraft_types::ChunkSection;
+use super::minecraft_types::{
+    AdvancementsData, BlockChangeRecord, ChunkSection, ItemStack, Location,
+};
 use super::translation::TranslationInfo;
 use std::any::type_name;
-use std::io::{Cursor, Read, Write};
+use std::fmt;
+use std::io::{self, Cursor, Error, ErrorKind, Read, Write};
+
+// Why a packet couldn't be read. Whoever sent it is the one to blame for anything but Io
+#[derive(Debug)]
+pub enum PacketError {
+    Io(Error),
+    TooLong { length: i32 },
+    Malformed { state: i32, id: i32, cause: Error },
+    TrailingBytes { state: i32, id: i32, remaining: u64 },
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketError::Io(e) => write!(f, "Failed to read packet: {}", e),
+            PacketError::TooLong { length } => {
+                write!(f, "Packet length {} is out of bounds", length)
+            }
+            PacketError::Malformed { state, id, cause } => write!(
+                f,
+                "Malformed packet with id {:#04x} in state {}: {}",
+                id, state, cause
+            ),
+            PacketError::TrailingBytes {
+                state,
+                id,
+                remaining,
+            } => write!(
+                f,
+                "Packet with id {:#04x} in state {} has {} bytes left over",
+                id, state, remaining
+            ),
+        }
+    }
+}
+
+// A packet we don't know the layout of, with its id and everything after it as they were read. It's
+// written back out the same way, which lets us pass on packets we don't understand to whoever
+// might. Nothing in it is translated, so it's only any use between connections that agree on where
+// everything is
+#[derive(Debug, Clone)]
+pub struct Unknown {
+    pub id: i32,
+    pub data: Vec<u8>,
+}
 
 // Format: (state (99 is outgoing), name, id, [ list of (field name, field type) ]
+// Outgoing packets whose id another outgoing packet already has use 98 instead. Clientbound play
+// packets that peers also pass to each other are read in all of (5 | 6 | 99), but never in play,
+// where vanilla clients use their ids for serverbound packets of their own
 #[rustfmt::skip::macros(packet_boilerplate)]
 packet_boilerplate!(
     (
@@ -24,7 +74,15 @@ packet_boilerplate!(
     (1, StatusRequest, 0, []),
     (1, Ping, 1, [(payload, Long)]),
     (2, LoginStart, 0, [(username, String)]),
-    (3, KeepAlive, 0x21, [(id, Long)]),
+    (2, LoginPluginResponse, 2, [(message_id, VarInt), (successful, Boolean), (data, RemainingBytes)]),
+    (99, KeepAlive, 0x21, [(id, Long)]),
+    (3, HeldItemChange, 0x21, [(slot, Short)]),
+    (3, ServerboundKeepAlive, 0x0E, [(id, Long)]),
+    (3, CreativeInventoryAction, 0x24, [(slot, Short), (clicked_item, Slot)]),
+    (3, ChatMessage, 0x02, [(message, String)]),
+    (3, ClientStatus, 0x03, [(action, VarInt)]),
+    (3, PlayerDigging, 0x18, [(status, VarInt), (location, Location, XZBlock), (face, Byte)]),
+    (3, PluginMessage, 0x0A, [(channel, String), (data, RemainingBytes)]),
     (
         3,
         PlayerPosition,
@@ -32,7 +90,7 @@ packet_boilerplate!(
         [
             (x, Double, XEntity),
             (feet_y, Double),
-            (z, Double),
+            (z, Double, ZEntity),
             (on_ground, Boolean)
         ]
     ),
@@ -43,7 +101,7 @@ packet_boilerplate!(
         [
             (x, Double, XEntity),
             (feet_y, Double),
-            (z, Double),
+            (z, Double, ZEntity),
             (yaw, Float),
             (pitch, Float),
             (on_ground, Boolean)
@@ -63,16 +121,177 @@ packet_boilerplate!(
     (_, BorderCrossLogin, 0xA0, [
             (x, Double, XEntity),
             (feet_y, Double),
-            (z, Double),
+            (z, Double, ZEntity),
             (yaw, Float),
             (pitch, Float),
             (on_ground, Boolean),
+            (velocity_x, Double),
+            (velocity_y, Double),
+            (velocity_z, Double),
             (username, String),
-            (entity_id, Int, EntityId)
+            (uuid, u128),
+            (entity_id, Int, EntityId),
+            (inventory, String), // json list of the player's inventory slots
+            (held_item_slot, Short),
+            (health, Float),
+            (food, VarInt),
+            (food_saturation, Float),
+            (experience_bar, Float),
+            (level, VarInt),
+            (total_experience, VarInt)
+    ]),
+    (6, SummonEntity, 0xA1, [
+            (entity_type, VarInt),
+            (x, Double, XEntity),
+            (y, Double),
+            (z, Double, ZEntity)
+    ]),
+    (6, KillEntity, 0xA2, [(entity_id, Int)]),
+    (_, PeerHeartbeat, 0xA3, [(id, Long)]),
+    (6, PeerGossip, 0xA6, [(maps, String)]),
+    (5, GameRuleUpdate, 0xA5, [(rule, String), (value, Boolean), (version, Long)]),
+    // The weather goes along with the time, as each node works it out from the world's age
+    (5, PeerTimeUpdate, 0xB8, [
+        (world_age, Long),
+        (time_of_day, Long),
+        (weather, UByte),
+        (weather_remaining, Long)
+    ]),
+    (6, MapPositionProposal, 0xA7, [
+            (peer_address, String),
+            (peer_port, UShort),
+            (x, Int),
+            (z, Int),
+            (dimension, Int),
+            (entity_id_block, Int)
+    ]),
+    (5, MapPositionAgreement, 0xA8, [(x, Int), (z, Int), (dimension, Int), (entity_id_block, Int)]),
+    (6, MapHandoff, 0xA9, [
+            (peer_address, String),
+            (peer_port, UShort),
+            (topology, String),
+            (block_ids, LengthPrefixedArray(VarInt))
+    ]),
+    (6, MapSplit, 0xAC, [
+            (topology, String),
+            (block_ids, LengthPrefixedArray(VarInt)),
+            (dimension, Int),
+            (peer_address, String),
+            (peer_port, UShort),
+            (entity_id_block, Int)
+    ]),
+    (5, MapOwnerChange, 0xAA, [(peer_address, String), (peer_port, UShort)]),
+    (5, PeerPluginMessage, 0xAD, [
+            (origin_address, String),
+            (origin_port, UShort),
+            (hops, UByte),
+            (sequence, Long),
+            (channel, String),
+            (data, RemainingBytes)
+    ]),
+    (6, EntityOwnerQuery, 0xAE, [(query_id, Long), (uuid, u128)]),
+    // Every peer asked answers, found or not, so the lookup is over once they all have
+    (5, EntityOwnerReply, 0xAF, [(query_id, Long), (found, Boolean), (entity_id, Int, EntityId)]),
+    (7, PeerAuth, 0xAB, [
+            (next_state, VarInt),
+            (key_id, String),
+            (timestamp, Long),
+            (mac, String),
+            (instance_id, u128),
+            (instance_name, String),
+            (map_size, VarInt)
+    ]),
+    // Sent to a peer as soon as it asks for a peer state, for it to sign along with the rest of its
+    // credentials. Each is only good for the connection it was sent on, so credentials can't be
+    // replayed. Both sides say how many chunks wide their maps are, as peers can't share a quilt
+    // unless they agree
+    (5, PeerAuthChallenge, 0xB9, [(challenge, u128), (map_size, VarInt)]),
+    // Who we are, sent back to a peer that's just subscribed to us
+    (5, PeerIdentity, 0xB3, [(instance_id, u128), (instance_name, String)]),
+    // From the owner of a map, for a player anchored there from the receiving peer. They're pulled
+    // back onto the receiving peer's own map, or disconnected if there's a reason
+    (5, PeerKickback, 0xB4, [(uuid, u128), (reason, String)]),
+    // A ban or pardon, passed on from peer to peer until it's everywhere, see the bans service.
+    // Bans that never expire do so at 0
+    (5, PeerBan, 0xB7, [
+            (target, String),
+            (reason, String),
+            (expires_at, Long),
+            (version, Long),
+            (active, Boolean)
+    ]),
+    // How far an anchored player's moved since their last position, in place of the absolute
+    // position they sent us, see the position_delta module
+    (3, PeerPositionDelta, 0xB5, [
+            (delta_x, Short),
+            (delta_y, Short),
+            (delta_z, Short),
+            (on_ground, Boolean)
+    ]),
+    (3, PeerPositionAndLookDelta, 0xB6, [
+            (delta_x, Short),
+            (delta_y, Short),
+            (delta_z, Short),
+            (yaw, Float),
+            (pitch, Float),
+            (on_ground, Boolean)
+    ]),
+    (_, PeerShutdown, 0xB0, [(peer_address, String), (peer_port, UShort)]),
+    (5, PeerChatMessage, 0xB2, [
+            (origin_address, String),
+            (origin_port, UShort),
+            (hops, UByte),
+            (sequence, Long),
+            (json_data, String)
+    ]),
+    // A dropped item that's been carried over the seam onto the receiving peer's map
+    (6, ItemTransfer, 0xB1, [
+            (uuid, u128),
+            (item, Slot),
+            (x, Double, XEntity),
+            (y, Double),
+            (z, Double, ZEntity),
+            (velocity_x, Double),
+            (velocity_y, Double),
+            (velocity_z, Double),
+            (floor_y, Double),
+            (pickup_delay, Short),
+            (age, Int),
+            (thrower, u128) // 0 if nobody threw it
+    ]),
+    (6, FillBlocks, 0xA4, [
+            (from_x, Int),
+            (from_y, Int),
+            (from_z, Int),
+            (to_x, Int),
+            (to_y, Int),
+            (to_z, Int),
+            (block_id, VarInt)
     ]),
     (99, Pong, 1, [(payload, Long)]),
     (99, StatusResponse, 0, [(json_response, String)]),
+    (98, LoginDisconnect, 0, [(reason, String)]),
     (99, LoginSuccess, 2, [(uuid, String), (username, String)]),
+    (99, LoginPluginRequest, 4, [(message_id, VarInt), (channel, String), (data, RemainingBytes)]),
+    (99, ClientboundChatMessage, 0x0E, [(json_data, String), (position, Byte)]),
+    (99, Disconnect, 0x1B, [(reason, String)]),
+    // Only ever sent about the player themselves, to tell them their permission level
+    (99, EntityStatus, 0x1C, [(entity_id, Int), (status, Byte)]),
+    // Only the actions that carry text (0 title, 1 subtitle and 2 action bar) fit this layout
+    (99, Title, 0x4B, [(action, VarInt), (text, String)]),
+    // A negative time of day stops the client moving the sun along on its own
+    (99, TimeUpdate, 0x4A, [(world_age, Long), (time_of_day, Long)]),
+    (99, SpawnPosition, 0x49, [(location, Location)]),
+    // Only used for the weather so far, see the weather module for the reasons
+    (99, ChangeGameState, 0x20, [(reason, UByte), (value, Float)]),
+    (99, ServerDifficulty, 0x0D, [(difficulty, UByte)]),
+    (99, ClientboundPluginMessage, 0x19, [(channel, String), (data, RemainingBytes)]),
+    (99, Advancements, 0x51, [(data, Advancements)]),
+    (99, SetSlot, 0x17, [(window_id, Byte), (slot, Short), (slot_data, Slot)]),
+    (99, ClientboundHeldItemChange, 0x3D, [(slot, Byte)]),
+    (99, UpdateHealth, 0x44, [(health, Float), (food, VarInt), (food_saturation, Float)]),
+    (99, Respawn, 0x38, [(dimension, Int), (difficulty, UByte), (gamemode, UByte), (level_type, String)]),
+    (99, SetExperience, 0x43, [(experience_bar, Float), (level, VarInt), (total_experience, VarInt)]),
     (
         99,
         JoinGame,
@@ -102,12 +321,12 @@ packet_boilerplate!(
         ]
     ),
     (
-        _,
+        (5 | 6 | 99),
         ChunkData,
         0x22,
         [
             (chunk_x, Int, XChunk),
-            (chunk_z, Int),
+            (chunk_z, Int, ZChunk),
             (full_chunk, Boolean), //always true
             (primary_bit_mask, VarInt),
             (size, VarInt),
@@ -117,7 +336,7 @@ packet_boilerplate!(
         ]
     ),
     (
-        _,
+        (5 | 6 | 99),
         PlayerInfo,
         0x30,
         [
@@ -132,7 +351,7 @@ packet_boilerplate!(
         ]
     ),
     (
-        _,
+        (5 | 6 | 99),
         SpawnPlayer,
         0x05,
         [
@@ -140,14 +359,87 @@ packet_boilerplate!(
             (uuid, u128),
             (x, Double, XEntity),
             (y, Double),
-            (z, Double),
+            (z, Double, ZEntity),
             (yaw, UByte), // represents angle * (360/256). Might want to eventually make this its own type
             (pitch, UByte),
             (entity_metadata_terminator, UByte)  // always 0xff until we implement entity metadata
         ]
     ),
+    (5, BlockChange, 0x0B, [(location, Location, XZBlock), (block_id, VarInt)]),
+    (
+        5,
+        MultiBlockChange,
+        0x0F,
+        [
+            (chunk_x, Int, XChunk),
+            (chunk_z, Int, ZChunk),
+            (records, BlockChangeRecords)
+        ]
+    ),
     (
-        _,
+        5,
+        SpawnMob,
+        0x03,
+        [
+            (entity_id, VarInt, EntityId),
+            (uuid, u128),
+            (entity_type, VarInt),
+            (x, Double, XEntity),
+            (y, Double),
+            (z, Double, ZEntity),
+            (yaw, UByte),
+            (pitch, UByte),
+            (head_pitch, UByte),
+            (velocity_x, Short),
+            (velocity_y, Short),
+            (velocity_z, Short),
+            (entity_metadata_terminator, UByte)
+        ]
+    ),
+    (
+        5,
+        SpawnObject,
+        0x00,
+        [
+            (entity_id, VarInt, EntityId),
+            (uuid, u128),
+            (object_type, Byte),
+            (x, Double, XEntity),
+            (y, Double),
+            (z, Double, ZEntity),
+            (pitch, UByte),
+            (yaw, UByte),
+            (data, Int),
+            (velocity_x, Short),
+            (velocity_y, Short),
+            (velocity_z, Short)
+        ]
+    ),
+    // Only a single item stack fits this layout, which is all item entities need
+    (
+        5,
+        EntityMetadata,
+        0x3F,
+        [
+            (entity_id, VarInt, EntityId),
+            (index, UByte),
+            (metadata_type, VarInt),
+            (item, Slot),
+            (terminator, UByte)
+        ]
+    ),
+    (
+        5,
+        CollectItem,
+        0x4F,
+        [
+            (collected_entity_id, VarInt, EntityId),
+            (collector_entity_id, VarInt, EntityId),
+            (count, VarInt)
+        ]
+    ),
+    (
+        (5 | 6 | 99),
         EntityHeadLook,
         0x39,
         [
@@ -156,7 +448,7 @@ packet_boilerplate!(
         ]
     ),
     (
-        _,
+        (5 | 6 | 99),
         DestroyEntities,
         0x35,
         [
@@ -164,7 +456,55 @@ packet_boilerplate!(
         ]
     ),
     (
-        _,
+        5,
+        EntityRelativeMove,
+        0x28,
+        [
+            (entity_id, VarInt, EntityId),
+            (delta_x, Short),
+            (delta_y, Short),
+            (delta_z, Short),
+            (on_ground, Boolean)
+        ]
+    ),
+    (
+        5,
+        EntityLook,
+        0x2A,
+        [
+            (entity_id, VarInt, EntityId),
+            (yaw, UByte),
+            (pitch, UByte),
+            (on_ground, Boolean)
+        ]
+    ),
+    (
+        5,
+        EntityVelocity,
+        0x41,
+        [
+            (entity_id, VarInt, EntityId),
+            (velocity_x, Short),
+            (velocity_y, Short),
+            (velocity_z, Short)
+        ]
+    ),
+    (
+        5,
+        EntityTeleport,
+        0x50,
+        [
+            (entity_id, VarInt, EntityId),
+            (x, Double, XEntity),
+            (y, Double),
+            (z, Double, ZEntity),
+            (yaw, UByte),
+            (pitch, UByte),
+            (on_ground, Boolean)
+        ]
+    ),
+    (
+        (5 | 6 | 99),
         EntityLookAndMove,
         0x29,
         [
@@ -1,46 +1,68 @@
 macro_rules! packet_boilerplate {
-    ( $( ( $state:pat, $name:ident, $id:expr,
+    ( $( ( $state:tt, $name:ident, $id:expr,
            [$(($fieldname:ident, $datatype:ident$(($($typearg:tt),*))* $(, $transtype:tt$(($($transarg:tt),*))*),* ) ),*]
     )),*) => (
         //Create an enum with a struct variant for each packet we've defined
         //and a special variant for a packet we haven't defined
         #[derive(Debug, Clone)]
         pub enum Packet {
-            Unknown,
+            Unknown(Unknown),
             $($name($name)),*
         }
 
+        // Every packet we know the layout of, as (state, id, name), for anything that has to go
+        // through all of them. Packets read in any state have no state, and packets read in
+        // several are listed under the first
+        pub const PACKETS: &[(Option<i32>, i32, &str)] = &[$((packet_state!($state), $id, stringify!($name))),*];
+
         impl<'a> Packet {
             //Only used for debugging purposes
             pub fn debug_print_type(&self) -> &'a str {
                 match self {
                     $(Packet::$name(_) => type_name::<$name>()),*,
-                    Packet::Unknown => "Unknown"
+                    Packet::Unknown(_) => "Unknown"
+                }
+            }
+
+            // Just the packet's own name, for labelling metrics and spans
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Packet::$name(_) => stringify!($name)),*,
+                    Packet::Unknown(_) => "Unknown"
                 }
             }
         }
 
-        pub fn read<S: MinecraftProtocolReader + Read>(stream: &mut S, state: i32) -> Packet {
-            let id = stream.read_var_int();
+        // The stream is a single packet's worth of bytes, without its length. Packets read in
+        // several states have their states in parentheses, which the match doesn't need
+        #[allow(unused_parens)]
+        pub fn read<S: MinecraftProtocolReader + Read>(
+            stream: &mut S,
+            state: i32,
+        ) -> Result<Packet, PacketError> {
+            let id = stream.read_var_int().map_err(PacketError::Io)?;
 
             //call the initializer method of the packet class associated with
             //this state and packet id combination
-            match (state,id) {
+            let packet = match (state,id) {
                 $( ($state, $id) => {
-                    let packet = Packet::$name($name::new(stream));
-                    if stream.bytes().next().is_some() {
-                        panic!(
-                            "Failed to read entire buffer for packet with id {:?} in state {:?}",
-                            id,
-                            state
-                        );
-                    }
-                    packet
+                    Packet::$name($name::new(stream).map_err(|cause| PacketError::Malformed {
+                        state,
+                        id,
+                        cause,
+                    })?)
                 } )*
                 _ => {
-                    Packet::Unknown
+                    //Kept as it is, so that it can still be passed on to whoever does know it
+                    let data = stream.read_remaining_bytes().map_err(PacketError::Io)?;
+                    return Ok(Packet::Unknown(Unknown { id, data }));
                 }
+            };
+            let remaining = io::copy(stream, &mut io::sink()).map_err(PacketError::Io)?;
+            if remaining > 0 {
+                return Err(PacketError::TrailingBytes { state, id, remaining });
             }
+            Ok(packet)
         }
 
         pub fn write<S: MinecraftProtocolWriter + Write>(stream: &mut S, packet: Packet) {
@@ -51,7 +73,10 @@ macro_rules! packet_boilerplate {
                     cursor.write_var_int($name::ID);
                     packet.write_fields(&mut cursor)
                 })*
-                _ => { panic!("I don't know how to write this packet {:?}", packet) }
+                Packet::Unknown(packet) => {
+                    cursor.write_var_int(packet.id);
+                    cursor.write_bytes(packet.data)
+                }
             }
 
             //Measure what we've written so far to determine packet length
@@ -78,7 +103,7 @@ macro_rules! packet_boilerplate {
                 $(Packet::$name(packet) => {
                     Packet::$name(packet.translate(translation_info))
                 })*
-                Packet::Unknown => { Packet::Unknown }
+                Packet::Unknown(packet) => { Packet::Unknown(packet) }
             }
         }
 
@@ -87,23 +112,66 @@ macro_rules! packet_boilerplate {
                 $(Packet::$name(packet) => {
                     Packet::$name(packet.translate_outgoing(translation_info))
                 })*
-                Packet::Unknown => { Packet::Unknown }
+                Packet::Unknown(packet) => { Packet::Unknown(packet) }
             }
         }
 
         //Define the packet struct
         $(packet!{$name, $id, [ $( ( $fieldname, $datatype$(($($typearg),*))* $(, $transtype$(($($transarg),*))*),* ) ),* ]})*
+
+        // Every packet written out has to read back as the same bytes. Packets read in any state
+        // are read in play
+        #[cfg(test)]
+        mod round_trips {
+            use super::*;
+            use proptest::prelude::*;
+
+            fn round_trip(packet: Packet, state: i32) -> Result<(), TestCaseError> {
+                let mut written = Cursor::new(Vec::new());
+                write(&mut written, packet);
+                written.set_position(0);
+                written.read_var_int().unwrap();
+                let read = read(&mut written.clone(), state)
+                    .map_err(|e| TestCaseError::fail(e.to_string()))?;
+                let mut rewritten = Vec::new();
+                write(&mut rewritten, read);
+                prop_assert_eq!(written.into_inner(), rewritten);
+                Ok(())
+            }
+
+            proptest! {
+                $(
+                    #[test]
+                    #[allow(non_snake_case)]
+                    fn $name(packet in $name::strategy()) {
+                        round_trip(Packet::$name(packet), packet_state!($state).unwrap_or(3))?;
+                    }
+                )*
+            }
+        }
     )
 }
 
+macro_rules! packet_state {
+    (_) => {
+        None
+    };
+    ($state:literal) => {
+        Some($state)
+    };
+    (($first:literal $(| $rest:literal)*)) => {
+        Some($first)
+    };
+}
+
 macro_rules! packet {
     ($name:ident, $id:expr, [ $( ($fieldname:ident, $datatype:ident$(($($typearg:tt),*))* $(, $transtype:tt$(($($transarg:tt),*))*),* )),+]) => (
         #[derive(Debug, Clone)]
         pub struct $name { $(pub $fieldname: mc_to_rust_datatype!($datatype$(($($typearg),*))*)),* }
         impl $name {
             const ID: i32 = $id;
-            pub fn new<S: MinecraftProtocolReader>(stream: &mut S) -> $name {
-                $name { $( $fieldname: read_packet_field!(stream, $datatype$(($($typearg),*))*) ),* }
+            pub fn new<S: MinecraftProtocolReader>(stream: &mut S) -> Result<$name, Error> {
+                Ok($name { $( $fieldname: read_packet_field!(stream, $datatype$(($($typearg),*))*)? ),* })
             }
             pub fn write_fields<S: MinecraftProtocolWriter>(&self, stream: &mut S) {
                 $( write_packet_field!(stream, self.$fieldname.clone(), $datatype$(($($typearg),*))*) );*
@@ -127,14 +195,22 @@ macro_rules! packet {
                 translated
             }
         }
+        #[cfg(test)]
+        impl $name {
+            pub fn strategy() -> impl proptest::strategy::Strategy<Value = $name> {
+                use proptest::prelude::*;
+                nested_strategy!($(packet_field_strategy!($datatype$(($($typearg),*))*)),+)
+                    .prop_map(|nested_pattern!($($fieldname),+)| $name { $($fieldname),+ })
+            }
+        }
     );
     ($name:ident, $id:expr, []) => (
         #[derive(Debug, Clone)]
         pub struct $name {}
         impl $name {
             const ID: i32 = $id;
-            pub fn new<S: MinecraftProtocolReader>(stream: &mut S) -> $name {
-                $name {}
+            pub fn new<S: MinecraftProtocolReader>(stream: &mut S) -> Result<$name, Error> {
+                Ok($name {})
             }
             pub fn write_fields<S: MinecraftProtocolWriter>(&self, stream: &mut S) {}
             pub fn translate(&self, translation_data: TranslationInfo) -> $name {
@@ -144,6 +220,12 @@ macro_rules! packet {
                 self.clone()
             }
         }
+        #[cfg(test)]
+        impl $name {
+            pub fn strategy() -> impl proptest::strategy::Strategy<Value = $name> {
+                proptest::strategy::Just($name {})
+            }
+        }
     )
 }
 
@@ -196,6 +278,21 @@ macro_rules! mc_to_rust_datatype {
     (ChunkSection) => {
         ChunkSection
     };
+    (BlockChangeRecords) => {
+        Vec<BlockChangeRecord>
+    };
+    (Advancements) => {
+        AdvancementsData
+    };
+    (Slot) => {
+        Option<ItemStack>
+    };
+    (Location) => {
+        Location
+    };
+    (RemainingBytes) => {
+        Vec<u8>
+    };
 }
 
 macro_rules! read_packet_field {
@@ -223,10 +320,17 @@ macro_rules! read_packet_field {
     ($stream:ident, Array($type:ident, $length:expr)) => {
         $stream.read_int_array($length)
     };
-    ($stream:ident, LengthPrefixedArray($type:ident)) => {{
-        let length = $stream.read_var_int();
-        $stream.read_var_int_array(length as u32)
-    }};
+    ($stream:ident, LengthPrefixedArray($type:ident)) => {
+        $stream.read_var_int().and_then(|length| {
+            if length < 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Negative array length {}", length),
+                ));
+            }
+            $stream.read_var_int_array(length as u32)
+        })
+    };
     ($stream:ident, Float) => {
         $stream.read_float()
     };
@@ -245,6 +349,21 @@ macro_rules! read_packet_field {
     ($stream:ident, ChunkSection) => {
         $stream.read_chunk_section()
     };
+    ($stream:ident, BlockChangeRecords) => {
+        $stream.read_block_change_records()
+    };
+    ($stream:ident, Advancements) => {
+        $stream.read_advancements()
+    };
+    ($stream:ident, Slot) => {
+        $stream.read_slot()
+    };
+    ($stream:ident, Location) => {
+        $stream.read_location()
+    };
+    ($stream:ident, RemainingBytes) => {
+        $stream.read_remaining_bytes()
+    };
 }
 
 macro_rules! write_packet_field {
@@ -294,22 +413,33 @@ macro_rules! write_packet_field {
     ($stream:ident, $value:expr, ChunkSection) => {
         $stream.write_chunk_section($value)
     };
+    ($stream:ident, $value:expr, BlockChangeRecords) => {
+        $stream.write_block_change_records($value)
+    };
+    ($stream:ident, $value:expr, Advancements) => {
+        $stream.write_advancements($value)
+    };
+    ($stream:ident, $value:expr, Slot) => {
+        $stream.write_slot($value)
+    };
+    ($stream:ident, $value:expr, Location) => {
+        $stream.write_location($value)
+    };
+    ($stream:ident, $value:expr, RemainingBytes) => {
+        $stream.write_bytes($value)
+    };
 }
 
 macro_rules! translate_incoming_packet_field {
     ($value:expr, $transdata:expr) => {
         $value
     };
-    ($value:expr, $transdata:expr, EntityId) => {{
-        //For now this is hardcoded to assume that the block of anchor ids associated to our
-        //server is 950 to 1000. Later, in settings with three servers, we will need to
-        //determine this range when initially setting up the connection to the peer
-        if $value % ENTITY_ID_BLOCK_SIZE >= 950 {
-            ($value % 1000) - 950
-        } else {
-            $value + ($transdata.map.entity_id_block * ENTITY_ID_BLOCK_SIZE)
-        }
-    }};
+    ($value:expr, $transdata:expr, EntityId) => {
+        $transdata
+            .map
+            .entity_ids
+            .to_local($value, $transdata.map.entity_id_block)
+    };
     ($value:expr, $transdata:expr, Array($type:ident)) => {
         $value
             .into_iter()
@@ -317,25 +447,54 @@ macro_rules! translate_incoming_packet_field {
             .collect()
     };
     ($value:expr, $transdata:expr, XChunk) => {
-        $transdata.map.position.x
+        $value + $transdata.map.position.x * map_size()
+    };
+    ($value:expr, $transdata:expr, ZChunk) => {
+        $value + $transdata.map.position.z * map_size()
     };
     ($value:expr, $transdata:expr, XEntity) => {
-        $value + ($transdata.map.position.x * CHUNK_SIZE) as f64
+        $value + ($transdata.map.position.x * map_width()) as f64
+    };
+    ($value:expr, $transdata:expr, ZEntity) => {
+        $value + ($transdata.map.position.z * map_width()) as f64
+    };
+    ($value:expr, $transdata:expr, XZBlock) => {
+        Location {
+            x: $value.x + $transdata.map.position.x * map_width(),
+            z: $value.z + $transdata.map.position.z * map_width(),
+            ..$value
+        }
     };
 }
 
 macro_rules! translate_outgoing_packet_field {
     ($value:expr, $transdata:expr, XEntity) => {
-        $value - ($transdata.map.position.x * CHUNK_SIZE) as f64
+        $value - ($transdata.map.position.x * map_width()) as f64
+    };
+    ($value:expr, $transdata:expr, ZEntity) => {
+        $value - ($transdata.map.position.z * map_width()) as f64
     };
     ($value:expr, $transdata:expr) => {
         $value
     };
     ($value:expr, $transdata:expr, EntityId) => {
-        $value
+        $transdata
+            .map
+            .entity_ids
+            .to_peer($value, $transdata.map.entity_id_block)
     };
     ($value:expr, $transdata:expr, XChunk) => {
-        $value
+        $value - $transdata.map.position.x * map_size()
+    };
+    ($value:expr, $transdata:expr, ZChunk) => {
+        $value - $transdata.map.position.z * map_size()
+    };
+    ($value:expr, $transdata:expr, XZBlock) => {
+        Location {
+            x: $value.x - $transdata.map.position.x * map_width(),
+            z: $value.z - $transdata.map.position.z * map_width(),
+            ..$value
+        }
     };
     ($value:expr, $transdata:expr, Array($type:ident)) => {
         $value
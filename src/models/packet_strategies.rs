@@ -0,0 +1,223 @@
+// Proptest strategies for every field type the packet macro knows, so that each packet it defines
+// gets a strategy of its own. Values are kept to what the wire format can carry, since anything
+// else couldn't be read back the same anyway
+use super::minecraft_types::{
+    Advancement, AdvancementDisplay, AdvancementProgress, AdvancementsData, BlockChangeRecord,
+    ChunkSection, CriterionProgress, ItemStack, Location, ADVANCEMENT_HAS_BACKGROUND,
+};
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+// The longest any generated list gets, to keep packets small enough to shrink quickly
+pub const MAX_LENGTH: usize = 8;
+
+// Builds the strategy for a field from its type in the packet definition
+macro_rules! packet_field_strategy {
+    (VarInt) => {
+        any::<i32>()
+    };
+    (UShort) => {
+        any::<u16>()
+    };
+    (Short) => {
+        any::<i16>()
+    };
+    (Long) => {
+        any::<i64>()
+    };
+    (String) => {
+        crate::models::packet_strategies::string()
+    };
+    (u128) => {
+        any::<u128>()
+    };
+    (Int) => {
+        any::<i32>()
+    };
+    (Array($type:ident, $length:expr)) => {
+        proptest::collection::vec(packet_field_strategy!($type), $length as usize)
+    };
+    (LengthPrefixedArray($type:ident)) => {
+        proptest::collection::vec(
+            packet_field_strategy!($type),
+            0..crate::models::packet_strategies::MAX_LENGTH,
+        )
+    };
+    (Float) => {
+        any::<f32>()
+    };
+    (Double) => {
+        any::<f64>()
+    };
+    (Byte) => {
+        any::<i8>()
+    };
+    (UByte) => {
+        any::<u8>()
+    };
+    (Boolean) => {
+        any::<bool>()
+    };
+    (ChunkSection) => {
+        crate::models::packet_strategies::chunk_section()
+    };
+    (BlockChangeRecords) => {
+        crate::models::packet_strategies::block_change_records()
+    };
+    (Advancements) => {
+        crate::models::packet_strategies::advancements()
+    };
+    (Slot) => {
+        crate::models::packet_strategies::slot()
+    };
+    (Location) => {
+        crate::models::packet_strategies::location()
+    };
+    (RemainingBytes) => {
+        proptest::collection::vec(any::<u8>(), 0..64)
+    };
+}
+
+// Pairs up strategies, and the values they make, one inside the next, as tuples only go so far
+macro_rules! nested_strategy {
+    () => {
+        Just(())
+    };
+    ($first:expr $(, $rest:expr)*) => {
+        ($first, nested_strategy!($($rest),*))
+    };
+}
+
+macro_rules! nested_pattern {
+    () => {
+        ()
+    };
+    ($first:ident $(, $rest:ident)*) => {
+        ($first, nested_pattern!($($rest),*))
+    };
+}
+
+pub fn string() -> impl Strategy<Value = String> {
+    "\\PC{0,16}"
+}
+
+// Sections are only ever the global palette, which is all we read
+pub fn chunk_section() -> impl Strategy<Value = ChunkSection> {
+    vec(0..1 << 14, 4096).prop_map(|block_ids| ChunkSection {
+        bits_per_block: 14,
+        data_array_length: 896,
+        block_ids,
+        block_light: Vec::new(),
+        sky_light: Vec::new(),
+    })
+}
+
+pub fn block_change_records() -> impl Strategy<Value = Vec<BlockChangeRecord>> {
+    vec(
+        (any::<u8>(), any::<u8>(), any::<i32>()).prop_map(|(horizontal_position, y, block_id)| {
+            BlockChangeRecord {
+                horizontal_position,
+                y,
+                block_id,
+            }
+        }),
+        0..MAX_LENGTH,
+    )
+}
+
+// Only as much nbt as it takes to check it's carried through untouched: a compound of ints
+pub fn slot() -> impl Strategy<Value = Option<ItemStack>> {
+    let nbt = vec((string(), any::<i32>()), 0..4).prop_map(|tags| {
+        let mut bytes = vec![10, 0, 0];
+        tags.into_iter().for_each(|(name, value)| {
+            bytes.push(3);
+            bytes.extend((name.len() as u16).to_be_bytes());
+            bytes.extend(name.into_bytes());
+            bytes.extend(value.to_be_bytes());
+        });
+        bytes.push(0);
+        bytes
+    });
+    option::of(
+        (any::<i32>(), any::<i8>(), option::of(nbt)).prop_map(|(item_id, count, nbt)| ItemStack {
+            item_id,
+            count,
+            nbt,
+        }),
+    )
+}
+
+pub fn location() -> impl Strategy<Value = Location> {
+    (
+        -(1 << 25)..1 << 25,
+        -(1 << 11)..1 << 11,
+        -(1 << 25)..1 << 25,
+    )
+        .prop_map(|(x, y, z)| Location { x, y, z })
+}
+
+pub fn advancements() -> impl Strategy<Value = AdvancementsData> {
+    let display = (
+        (string(), string(), any::<i32>(), any::<i32>()),
+        (
+            any::<i32>(),
+            option::of(string()),
+            any::<f32>(),
+            any::<f32>(),
+        ),
+    )
+        .prop_map(
+            |((title, description, icon, frame_type), (flags, background_texture, x, y))| {
+                // Whether there's a background is in the flags
+                let flags = match background_texture {
+                    Some(_) => flags | ADVANCEMENT_HAS_BACKGROUND,
+                    None => flags & !ADVANCEMENT_HAS_BACKGROUND,
+                };
+                AdvancementDisplay {
+                    title,
+                    description,
+                    icon,
+                    frame_type,
+                    flags,
+                    background_texture,
+                    x,
+                    y,
+                }
+            },
+        );
+    let advancement = (
+        string(),
+        option::of(string()),
+        option::of(display),
+        vec(string(), 0..4),
+        vec(vec(string(), 0..4), 0..4),
+    )
+        .prop_map(
+            |(id, parent, display, criteria, requirements)| Advancement {
+                id,
+                parent,
+                display,
+                criteria,
+                requirements,
+            },
+        );
+    let criterion = (string(), option::of(any::<i64>()))
+        .prop_map(|(id, achieved_at)| CriterionProgress { id, achieved_at });
+    let progress = (string(), vec(criterion, 0..4))
+        .prop_map(|(id, criteria)| AdvancementProgress { id, criteria });
+    (
+        any::<bool>(),
+        vec(advancement, 0..4),
+        vec(string(), 0..4),
+        vec(progress, 0..4),
+    )
+        .prop_map(
+            |(reset, advancements, removed, progress)| AdvancementsData {
+                reset,
+                advancements,
+                removed,
+                progress,
+            },
+        )
+}
@@ -0,0 +1,199 @@
+use super::interfaces::player::{
+    Angle, Experience, Health, Player, Position, PLAYER_INVENTORY_SLOTS,
+};
+use super::map::Dimension;
+use super::minecraft_types::ItemStack;
+use super::versioned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// What's kept of a player between visits, so they pick up where they left off
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPlayer {
+    pub uuid: String,
+    pub position: Position,
+    pub angle: Angle,
+    pub inventory: Vec<Option<ItemStack>>,
+    pub held_item_slot: i16,
+    pub health: Health,
+    pub experience: Experience,
+    pub dimension: Dimension,
+}
+
+impl SavedPlayer {
+    pub fn apply_to(self, player: &mut Player) {
+        match Uuid::parse_str(&self.uuid) {
+            Ok(uuid) => player.uuid = uuid,
+            Err(e) => warn!(
+                "Ignoring saved uuid {:?} of {:?}: {:?}",
+                self.uuid, player.name, e
+            ),
+        }
+        player.position = self.position;
+        player.angle = self.angle;
+        player.inventory = self.inventory;
+        player.inventory.resize(PLAYER_INVENTORY_SLOTS, None);
+        player.held_item_slot = self.held_item_slot;
+        player.health = self.health;
+        player.experience = self.experience;
+        player.dimension = self.dimension;
+    }
+}
+
+// Players are saved one JSON file each, named after the player, in the players directory
+#[derive(Debug, Clone)]
+pub struct PlayerStore {
+    directory: PathBuf,
+}
+
+impl PlayerStore {
+    pub fn new(directory: &str) -> PlayerStore {
+        PlayerStore {
+            directory: PathBuf::from(directory),
+        }
+    }
+
+    pub fn load(&self, name: &str) -> Option<SavedPlayer> {
+        let path = self.path(name)?;
+        let contents = fs::read_to_string(&path).ok()?;
+        versioned::PLAYER
+            .from_str(&contents)
+            .map_err(|e| error!("Failed to parse saved player {:?}: {}", path, e))
+            .ok()
+    }
+
+    pub fn save(&self, player: &Player) {
+        let path = match self.path(&player.name) {
+            Some(path) => path,
+            None => return,
+        };
+        let saved = SavedPlayer {
+            uuid: player.uuid.to_hyphenated().to_string(),
+            position: player.position,
+            angle: player.angle.clone(),
+            inventory: player.inventory.clone(),
+            held_item_slot: player.held_item_slot,
+            health: player.health,
+            experience: player.experience,
+            dimension: player.dimension,
+        };
+        let result = fs::create_dir_all(&self.directory)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|_| versioned::PLAYER.to_string(&saved))
+            .and_then(|contents| fs::write(&path, contents).map_err(|e| format!("{:?}", e)));
+        if let Err(e) = result {
+            error!("Failed to save player to {:?}: {}", path, e);
+        }
+    }
+
+    // Names come straight from the client, so only ones that are valid Minecraft names get a file
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        let valid = (3..=16).contains(&name.len())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            warn!("Not persisting player with unusual name {:?}", name);
+            return None;
+        }
+        Some(self.directory.join(format!("{}.json", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::player::Velocity;
+    use std::env;
+    use std::process;
+
+    fn store(name: &str) -> PlayerStore {
+        let directory =
+            env::temp_dir().join(format!("patchwork-players-{}-{}", name, process::id()));
+        PlayerStore::new(&directory.to_string_lossy())
+    }
+
+    fn player(name: &str) -> Player {
+        Player {
+            conn_id: Uuid::new_v4(),
+            uuid: Uuid::new_v4(),
+            name: String::from(name),
+            position: Position {
+                x: 0.0,
+                y: 64.0,
+                z: 0.0,
+            },
+            angle: Angle {
+                pitch: 0.0,
+                yaw: 0.0,
+            },
+            velocity: Velocity::default(),
+            entity_id: 1,
+            inventory: vec![None; PLAYER_INVENTORY_SLOTS],
+            held_item_slot: 0,
+            health: Health::default(),
+            experience: Experience::default(),
+            dimension: Dimension::Overworld,
+        }
+    }
+
+    #[test]
+    fn players_pick_up_where_they_left_off() {
+        let store = store("round-trip");
+        let mut saved = player("Notch");
+        saved.position = Position {
+            x: 12.5,
+            y: 70.0,
+            z: -3.25,
+        };
+        saved.angle.yaw = 90.0;
+        saved.inventory[36] = Some(ItemStack {
+            item_id: 1,
+            count: 5,
+            nbt: None,
+        });
+        saved.held_item_slot = 4;
+        saved.health.food = 7;
+        saved.experience.level = 3;
+        saved.dimension = Dimension::Nether;
+        store.save(&saved);
+
+        let mut loaded = player("Notch");
+        store.load("Notch").unwrap().apply_to(&mut loaded);
+        let _ = fs::remove_dir_all(&store.directory);
+        assert_eq!(loaded.uuid, saved.uuid);
+        assert_eq!(
+            (loaded.position.x, loaded.position.y, loaded.position.z),
+            (12.5, 70.0, -3.25)
+        );
+        assert_eq!(loaded.angle.yaw, 90.0);
+        assert_eq!(loaded.inventory, saved.inventory);
+        assert_eq!(loaded.held_item_slot, 4);
+        assert_eq!(loaded.health, saved.health);
+        assert_eq!(loaded.experience, saved.experience);
+        assert_eq!(loaded.dimension, Dimension::Nether);
+    }
+
+    #[test]
+    fn players_that_were_never_saved_start_afresh() {
+        let store = store("missing");
+        assert!(store.load("Notch").is_none());
+        // Nor are players with names that can't be a file
+        store.save(&player("../Notch"));
+        assert!(store.load("../Notch").is_none());
+    }
+
+    #[test]
+    fn unreadable_saves_are_ignored() {
+        let store = store("corrupt");
+        fs::create_dir_all(&store.directory).unwrap();
+        fs::write(
+            store.directory.join("Notch.json"),
+            "{\"format\": \"player\", \"ver",
+        )
+        .unwrap();
+        let loaded = store.load("Notch");
+        let _ = fs::remove_dir_all(&store.directory);
+        assert!(loaded.is_none());
+    }
+}
@@ -0,0 +1,200 @@
+use super::packet::{
+    Packet, PeerPositionAndLookDelta, PeerPositionDelta, PlayerPosition, PlayerPositionAndLook,
+};
+
+// Moving players send their position every tick, which makes up most of what goes down an anchor.
+// Anchors send how far the player's moved instead, in 1/DELTA_SCALE of a block like vanilla's
+// relative moves, which takes a third of the space. The absolute position still goes every
+// KEYFRAME_INTERVAL updates, and whenever the player's moved too far for a delta to carry
+const DELTA_SCALE: f64 = 4096.0;
+const KEYFRAME_INTERVAL: u32 = 20;
+
+type Coordinates = (f64, f64, f64);
+
+// Kept by the anchor for the player it's passing on. The position it works from is the one the
+// peer will have worked out, rounding and all, so the two never drift apart
+#[derive(Debug, Clone, Default)]
+pub struct DeltaEncoder {
+    last: Option<Coordinates>,
+    since_keyframe: u32,
+}
+
+impl DeltaEncoder {
+    // Anything that isn't a position is passed through as it is
+    pub fn encode(&mut self, packet: Packet) -> Packet {
+        let position = match &packet {
+            Packet::PlayerPosition(packet) => (packet.x, packet.feet_y, packet.z),
+            Packet::PlayerPositionAndLook(packet) => (packet.x, packet.feet_y, packet.z),
+            _ => return packet,
+        };
+        let delta = match self.last {
+            Some(last) if self.since_keyframe < KEYFRAME_INTERVAL => delta(last, position),
+            _ => None,
+        };
+        let (delta_x, delta_y, delta_z) = match (self.last, delta) {
+            (Some(last), Some(delta)) => {
+                self.last = Some(moved(last, delta));
+                self.since_keyframe += 1;
+                delta
+            }
+            _ => {
+                self.last = Some(position);
+                self.since_keyframe = 0;
+                return packet;
+            }
+        };
+        match packet {
+            Packet::PlayerPosition(packet) => Packet::PeerPositionDelta(PeerPositionDelta {
+                delta_x,
+                delta_y,
+                delta_z,
+                on_ground: packet.on_ground,
+            }),
+            Packet::PlayerPositionAndLook(packet) => {
+                Packet::PeerPositionAndLookDelta(PeerPositionAndLookDelta {
+                    delta_x,
+                    delta_y,
+                    delta_z,
+                    yaw: packet.yaw,
+                    pitch: packet.pitch,
+                    on_ground: packet.on_ground,
+                })
+            }
+            packet => packet,
+        }
+    }
+}
+
+// Kept by the peer for each anchor, turning deltas back into the positions the player sent
+#[derive(Debug, Clone, Default)]
+pub struct DeltaDecoder {
+    last: Option<Coordinates>,
+}
+
+impl DeltaDecoder {
+    // A delta is no use without a position to start from, so one that comes before any is dropped
+    pub fn decode(&mut self, packet: Packet) -> Option<Packet> {
+        match packet {
+            Packet::PlayerPosition(packet) => {
+                self.last = Some((packet.x, packet.feet_y, packet.z));
+                Some(Packet::PlayerPosition(packet))
+            }
+            Packet::PlayerPositionAndLook(packet) => {
+                self.last = Some((packet.x, packet.feet_y, packet.z));
+                Some(Packet::PlayerPositionAndLook(packet))
+            }
+            Packet::PeerPositionDelta(packet) => {
+                let (x, feet_y, z) =
+                    self.advance((packet.delta_x, packet.delta_y, packet.delta_z))?;
+                Some(Packet::PlayerPosition(PlayerPosition {
+                    x,
+                    feet_y,
+                    z,
+                    on_ground: packet.on_ground,
+                }))
+            }
+            Packet::PeerPositionAndLookDelta(packet) => {
+                let (x, feet_y, z) =
+                    self.advance((packet.delta_x, packet.delta_y, packet.delta_z))?;
+                Some(Packet::PlayerPositionAndLook(PlayerPositionAndLook {
+                    x,
+                    feet_y,
+                    z,
+                    yaw: packet.yaw,
+                    pitch: packet.pitch,
+                    on_ground: packet.on_ground,
+                }))
+            }
+            packet => Some(packet),
+        }
+    }
+
+    fn advance(&mut self, delta: (i16, i16, i16)) -> Option<Coordinates> {
+        let position = moved(self.last?, delta);
+        self.last = Some(position);
+        Some(position)
+    }
+}
+
+fn delta(from: Coordinates, to: Coordinates) -> Option<(i16, i16, i16)> {
+    let axis = |from: f64, to: f64| {
+        let delta = ((to - from) * DELTA_SCALE).round();
+        (delta >= f64::from(i16::MIN) && delta <= f64::from(i16::MAX)).then_some(delta as i16)
+    };
+    Some((
+        axis(from.0, to.0)?,
+        axis(from.1, to.1)?,
+        axis(from.2, to.2)?,
+    ))
+}
+
+fn moved(from: Coordinates, delta: (i16, i16, i16)) -> Coordinates {
+    (
+        from.0 + f64::from(delta.0) / DELTA_SCALE,
+        from.1 + f64::from(delta.1) / DELTA_SCALE,
+        from.2 + f64::from(delta.2) / DELTA_SCALE,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(x: f64, z: f64) -> Packet {
+        Packet::PlayerPosition(PlayerPosition {
+            x,
+            feet_y: 64.0,
+            z,
+            on_ground: true,
+        })
+    }
+
+    fn coordinates(packet: &Packet) -> (f64, f64) {
+        match packet {
+            Packet::PlayerPosition(packet) => (packet.x, packet.z),
+            _ => panic!("{:?} isn't a position", packet),
+        }
+    }
+
+    #[test]
+    fn walks_are_sent_as_deltas_between_keyframes() {
+        let mut encoder = DeltaEncoder::default();
+        let mut decoder = DeltaDecoder::default();
+        let mut deltas = 0;
+        for step in 0..100 {
+            let sent = position(0.3 + f64::from(step) * 0.21, -5.0 - f64::from(step) * 0.07);
+            let encoded = encoder.encode(sent.clone());
+            if let Packet::PeerPositionDelta(_) = encoded {
+                deltas += 1;
+            }
+            let (x, z) = coordinates(&decoder.decode(encoded).unwrap());
+            let (sent_x, sent_z) = coordinates(&sent);
+            assert!((x - sent_x).abs() < 1.0 / DELTA_SCALE);
+            assert!((z - sent_z).abs() < 1.0 / DELTA_SCALE);
+        }
+        assert_eq!(deltas, 100 - 100 / (KEYFRAME_INTERVAL as usize + 1) - 1);
+    }
+
+    #[test]
+    fn long_jumps_are_sent_as_they_are() {
+        let mut encoder = DeltaEncoder::default();
+        encoder.encode(position(0.0, 0.0));
+        assert!(matches!(
+            encoder.encode(position(100.0, 0.0)),
+            Packet::PlayerPosition(_)
+        ));
+    }
+
+    #[test]
+    fn deltas_without_a_keyframe_are_dropped() {
+        let mut decoder = DeltaDecoder::default();
+        assert!(decoder
+            .decode(Packet::PeerPositionDelta(PeerPositionDelta {
+                delta_x: 1,
+                delta_y: 0,
+                delta_z: 0,
+                on_ground: true,
+            }))
+            .is_none());
+    }
+}
@@ -0,0 +1,525 @@
+use super::block_registry::{block_registry, BlockRegistry};
+use super::constants::{SERVER_PROTOCOL, SERVER_VERSION};
+use super::item_registry::{item_registry, ItemRegistry};
+use super::minecraft_protocol::{
+    write_block_ids, MinecraftProtocolReader, MinecraftProtocolWriter,
+};
+use super::minecraft_types::{ItemStack, Location};
+use super::packet::{self, ChunkData, Packet, PacketError, StatusResponse, Unknown};
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::sync::OnceLock;
+
+// Everything inside patchwork, and everything sent between peers, uses the packets of
+// SERVER_PROTOCOL. Clients can be on any version that has an adapter here, which maps packets
+// between that model and the ids and layouts of the client's version as they're read and written.
+// Block and item ids are mapped through the registries of both versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtocolAdapter {
+    #[default]
+    Protocol404,
+    Protocol498,
+}
+
+// 1.14.4 clients drop chunks further than this from the chunk they were last told to center on,
+// which only happens when they're teleported
+const PROTOCOL_498_VIEW_DISTANCE: i32 = 32;
+
+// The object type of items in SpawnObject, and the entity type 1.14.4 spawns them as
+const ITEM_OBJECT_TYPE: i8 = 2;
+const PROTOCOL_498_ITEM_ENTITY_TYPE: i32 = 34;
+
+// Ids of the blocks and items we know in their 1.14.4 counterparts, once its reports are loaded
+#[derive(Default)]
+struct IdMaps {
+    blocks: Option<HashMap<i32, i32>>,
+    items: Option<HashMap<i32, i32>>,
+    items_back: Option<HashMap<i32, i32>>,
+}
+
+static PROTOCOL_498_IDS: OnceLock<IdMaps> = OnceLock::new();
+
+// Called at startup, after our own registries are set. Without 1.14.4's reports its clients are
+// sent our ids as they are
+pub fn load_registries(directory: &str) {
+    let mut ids = IdMaps::default();
+    match BlockRegistry::read_report(directory, 498) {
+        Ok(registry) => ids.blocks = Some(block_registry().id_map(&registry)),
+        Err(e) => warn!("{}, 1.14.4 clients will see the wrong blocks", e),
+    }
+    match ItemRegistry::read_report(directory, 498) {
+        Ok(registry) => {
+            ids.items = Some(item_registry().id_map(&registry));
+            ids.items_back = Some(registry.id_map(item_registry()));
+        }
+        Err(e) => warn!("{}, 1.14.4 clients will see the wrong items", e),
+    }
+    if PROTOCOL_498_IDS.set(ids).is_err() {
+        warn!("Protocol adapter registries are already loaded");
+    }
+}
+
+impl ProtocolAdapter {
+    pub fn for_protocol(protocol: i32) -> Option<ProtocolAdapter> {
+        match protocol {
+            404 => Some(ProtocolAdapter::Protocol404),
+            498 => Some(ProtocolAdapter::Protocol498),
+            _ => None,
+        }
+    }
+
+    pub fn supported_versions() -> Vec<&'static str> {
+        vec![
+            ProtocolAdapter::Protocol404.version(),
+            ProtocolAdapter::Protocol498.version(),
+        ]
+    }
+
+    pub fn protocol(self) -> i32 {
+        match self {
+            ProtocolAdapter::Protocol404 => SERVER_PROTOCOL as i32,
+            ProtocolAdapter::Protocol498 => 498,
+        }
+    }
+
+    pub fn version(self) -> &'static str {
+        match self {
+            ProtocolAdapter::Protocol404 => SERVER_VERSION,
+            ProtocolAdapter::Protocol498 => "1.14.4",
+        }
+    }
+
+    pub fn read<S: MinecraftProtocolReader + Read>(
+        self,
+        stream: &mut S,
+        state: i32,
+    ) -> Result<Packet, PacketError> {
+        match self {
+            ProtocolAdapter::Protocol404 => packet::read(stream, state),
+            // Only play packets changed ids. Of the ones we read, creative inventory actions carry
+            // 1.14.4's item ids and digging packed its location differently. Packets we don't
+            // know keep the client's id, as they're only any use to someone who speaks the
+            // client's version
+            ProtocolAdapter::Protocol498 if state == 3 => {
+                let id = stream.read_var_int().map_err(PacketError::Io)?;
+                let data = stream.read_remaining_bytes().map_err(PacketError::Io)?;
+                let our_id = match serverbound_498(id) {
+                    Some(our_id) => our_id,
+                    None => return Ok(Packet::Unknown(Unknown { id, data })),
+                };
+                let mut cursor = Cursor::new(Vec::new());
+                cursor.write_var_int(our_id);
+                cursor.write_bytes(data.clone());
+                cursor.set_position(0);
+                Ok(match packet::read(&mut cursor, state)? {
+                    Packet::Unknown(_) => Packet::Unknown(Unknown { id, data }),
+                    Packet::CreativeInventoryAction(mut action) => {
+                        action.clicked_item = action
+                            .clicked_item
+                            .map(|item| map_item(item, &protocol_498_ids().items_back));
+                        Packet::CreativeInventoryAction(action)
+                    }
+                    // Its location was unpacked the way 1.13 packs them, so pack it back up that
+                    // way before unpacking it as 1.14.4 does
+                    Packet::PlayerDigging(mut digging) => {
+                        let mut packed = Cursor::new(Vec::new());
+                        packed.write_location(digging.location);
+                        packed.set_position(0);
+                        digging.location =
+                            location_from_498(packed.read_long().map_err(PacketError::Io)?);
+                        Packet::PlayerDigging(digging)
+                    }
+                    packet => packet,
+                })
+            }
+            ProtocolAdapter::Protocol498 => packet::read(stream, state),
+        }
+    }
+
+    pub fn write<S: MinecraftProtocolWriter + Write>(self, stream: &mut S, packet: Packet) {
+        match self {
+            ProtocolAdapter::Protocol404 => packet::write(stream, packet),
+            ProtocolAdapter::Protocol498 => write_498(stream, packet),
+        }
+    }
+}
+
+fn protocol_498_ids() -> &'static IdMaps {
+    PROTOCOL_498_IDS.get_or_init(IdMaps::default)
+}
+
+// Ids that aren't in the other registry become air
+fn map_id(ids: &Option<HashMap<i32, i32>>, id: i32) -> i32 {
+    match ids {
+        Some(ids) => ids.get(&id).copied().unwrap_or(0),
+        None => id,
+    }
+}
+
+fn map_item(item: ItemStack, ids: &Option<HashMap<i32, i32>>) -> ItemStack {
+    ItemStack {
+        item_id: map_id(ids, item.item_id),
+        ..item
+    }
+}
+
+fn write_498<S: MinecraftProtocolWriter + Write>(stream: &mut S, packet: Packet) {
+    let ids = protocol_498_ids();
+    match packet {
+        // Status and login packets kept their ids and layouts
+        Packet::StatusResponse(response) => packet::write(
+            stream,
+            Packet::StatusResponse(with_version(response, ProtocolAdapter::Protocol498)),
+        ),
+        Packet::Pong(_)
+        | Packet::LoginDisconnect(_)
+        | Packet::LoginSuccess(_)
+        | Packet::LoginPluginRequest(_) => packet::write(stream, packet),
+        // Difficulty moved to its own packet, and the client is told its view distance. Respawn
+        // lost its difficulty too
+        Packet::JoinGame(join_game) => {
+            let mut body = Cursor::new(Vec::new());
+            body.write_int(join_game.entity_id);
+            body.write_u_byte(join_game.gamemode);
+            body.write_int(join_game.dimension);
+            body.write_u_byte(join_game.max_players);
+            body.write_string(join_game.level_type);
+            body.write_var_int(PROTOCOL_498_VIEW_DISTANCE);
+            body.write_boolean(join_game.reduced_debug_info);
+            write_frame(stream, 0x25, body.into_inner());
+        }
+        Packet::Respawn(respawn) => {
+            let mut body = Cursor::new(Vec::new());
+            body.write_int(respawn.dimension);
+            body.write_u_byte(respawn.gamemode);
+            body.write_string(respawn.level_type);
+            write_frame(stream, 0x3A, body.into_inner());
+        }
+        Packet::ServerDifficulty(difficulty) => {
+            let mut body = Cursor::new(Vec::new());
+            body.write_u_byte(difficulty.difficulty);
+            body.write_boolean(false); // not locked
+            write_frame(stream, 0x0D, body.into_inner());
+        }
+        Packet::BlockChange(block_change) => {
+            let mut body = Cursor::new(Vec::new());
+            body.write_long(location_498(block_change.location));
+            body.write_var_int(map_id(&ids.blocks, block_change.block_id));
+            write_frame(stream, 0x0B, body.into_inner());
+        }
+        Packet::SpawnPosition(spawn_position) => {
+            let mut body = Cursor::new(Vec::new());
+            body.write_long(location_498(spawn_position.location));
+            write_frame(stream, 0x4D, body.into_inner());
+        }
+        Packet::MultiBlockChange(mut multi_block_change) => {
+            multi_block_change
+                .records
+                .iter_mut()
+                .for_each(|record| record.block_id = map_id(&ids.blocks, record.block_id));
+            write_remapped_498(stream, Packet::MultiBlockChange(multi_block_change));
+        }
+        Packet::SetSlot(mut set_slot) => {
+            set_slot.slot_data = set_slot.slot_data.map(|item| map_item(item, &ids.items));
+            write_remapped_498(stream, Packet::SetSlot(set_slot));
+        }
+        Packet::ChunkData(chunk_data) => write_chunk_data_498(stream, chunk_data, ids),
+        // Objects became entities, with an entity type in place of the object type. Items are the
+        // only objects we spawn
+        Packet::SpawnObject(spawn_object) => {
+            if spawn_object.object_type != ITEM_OBJECT_TYPE {
+                trace!(
+                    "1.14.4 has no counterpart for object {:?}, dropping it",
+                    spawn_object
+                );
+                return;
+            }
+            let mut body = Cursor::new(Vec::new());
+            body.write_var_int(spawn_object.entity_id);
+            body.write_u_128(spawn_object.uuid);
+            body.write_var_int(PROTOCOL_498_ITEM_ENTITY_TYPE);
+            body.write_double(spawn_object.x);
+            body.write_double(spawn_object.y);
+            body.write_double(spawn_object.z);
+            body.write_u_byte(spawn_object.pitch);
+            body.write_u_byte(spawn_object.yaw);
+            body.write_int(spawn_object.data);
+            body.write_short(spawn_object.velocity_x);
+            body.write_short(spawn_object.velocity_y);
+            body.write_short(spawn_object.velocity_z);
+            write_frame(stream, 0x00, body.into_inner());
+        }
+        // Entities gained a metadata field before the item's, which moved the item up one, and a
+        // metadata type before slots
+        Packet::EntityMetadata(mut metadata) => {
+            metadata.index += 1;
+            metadata.metadata_type += 1;
+            metadata.item = metadata.item.map(|item| map_item(item, &ids.items));
+            write_remapped_498(stream, Packet::EntityMetadata(metadata));
+        }
+        Packet::Unknown(unknown) => write_frame(stream, unknown.id, unknown.data),
+        // The client only keeps chunks around the one it's told to center on
+        Packet::ClientboundPlayerPositionAndLook(position) => {
+            if position.flags & 0b101 == 0 {
+                let mut body = Cursor::new(Vec::new());
+                body.write_var_int((position.x.floor() as i32) >> 4);
+                body.write_var_int((position.z.floor() as i32) >> 4);
+                write_frame(stream, 0x40, body.into_inner());
+            }
+            write_remapped_498(stream, Packet::ClientboundPlayerPositionAndLook(position));
+        }
+        packet => write_remapped_498(stream, packet),
+    }
+}
+
+// For play packets whose layout didn't change, only their id
+fn write_remapped_498<S: Write>(stream: &mut S, packet: Packet) {
+    let mut canonical = Cursor::new(Vec::new());
+    packet::write(&mut canonical, packet);
+    canonical.set_position(0);
+    canonical.read_var_int().unwrap(); // length
+    let id = canonical.read_var_int().unwrap();
+    match clientbound_498(id) {
+        Some(id) => write_frame(stream, id, canonical.read_remaining_bytes().unwrap()),
+        None => trace!("1.14.4 has no packet for {:#x}, dropping it", id),
+    }
+}
+
+// Light moved out of the chunk sections into its own packet, and chunks carry heightmaps
+fn write_chunk_data_498<S: Write>(stream: &mut S, chunk_data: ChunkData, ids: &IdMaps) {
+    let block_ids: Vec<i32> = chunk_data
+        .data
+        .block_ids
+        .iter()
+        .map(|id| map_id(&ids.blocks, *id))
+        .collect();
+
+    // Bit 0 is the section below the world, so every section is one bit up from the chunk's mask
+    let mut light = Cursor::new(Vec::new());
+    light.write_var_int(chunk_data.chunk_x);
+    light.write_var_int(chunk_data.chunk_z);
+    light.write_var_int(0x3FFFF);
+    light.write_var_int(chunk_data.primary_bit_mask << 1);
+    light.write_var_int(0);
+    light.write_var_int(0);
+    let block_light_sections = (chunk_data.primary_bit_mask << 1).count_ones();
+    (0..18 + block_light_sections).for_each(|_| {
+        light.write_var_int(2048);
+        light.write_bytes(vec![0xFF; 2048]);
+    });
+    write_frame(stream, 0x24, light.into_inner());
+
+    let mut data = Cursor::new(Vec::new());
+    data.write_short(block_ids.iter().filter(|id| **id != 0).count() as i16);
+    data.write_u_byte(chunk_data.data.bits_per_block);
+    data.write_var_int(chunk_data.data.data_array_length);
+    write_block_ids(&mut data, &block_ids);
+    chunk_data
+        .biomes
+        .into_iter()
+        .for_each(|biome| data.write_int(biome));
+    let data = data.into_inner();
+
+    let mut body = Cursor::new(Vec::new());
+    body.write_int(chunk_data.chunk_x);
+    body.write_int(chunk_data.chunk_z);
+    body.write_boolean(chunk_data.full_chunk);
+    body.write_var_int(chunk_data.primary_bit_mask);
+    body.write_bytes(empty_heightmaps());
+    body.write_var_int(data.len() as i32);
+    body.write_bytes(data);
+    body.write_var_int(chunk_data.number_of_block_entities);
+    write_frame(stream, 0x21, body.into_inner());
+}
+
+// An unnamed compound holding a MOTION_BLOCKING long array of 256 9 bit heights, all zero. The
+// client only uses it to decide where rain stops
+fn empty_heightmaps() -> Vec<u8> {
+    let mut nbt = Cursor::new(Vec::new());
+    nbt.write_u_byte(10); // TAG_Compound
+    nbt.write_short(0);
+    nbt.write_u_byte(12); // TAG_Long_Array
+    nbt.write_short("MOTION_BLOCKING".len() as i16);
+    nbt.write_bytes(b"MOTION_BLOCKING".to_vec());
+    nbt.write_int(36);
+    (0..36).for_each(|_| nbt.write_long(0));
+    nbt.write_u_byte(0); // TAG_End
+    nbt.into_inner()
+}
+
+// The status we give is worded for our own version, clients want to see theirs
+fn with_version(response: StatusResponse, adapter: ProtocolAdapter) -> StatusResponse {
+    let mut json: serde_json::Value = match serde_json::from_str(&response.json_response) {
+        Ok(json) => json,
+        Err(_) => return response,
+    };
+    json["version"]["name"] = adapter.version().into();
+    json["version"]["protocol"] = adapter.protocol().into();
+    StatusResponse {
+        json_response: json.to_string(),
+    }
+}
+
+fn write_frame<S: Write>(stream: &mut S, id: i32, body: Vec<u8>) {
+    let mut packet = Cursor::new(Vec::new());
+    packet.write_var_int(id);
+    packet.write_bytes(body);
+    let packet = packet.into_inner();
+
+    let mut frame = Cursor::new(Vec::new());
+    frame.write_var_int(packet.len() as i32);
+    frame.write_bytes(packet);
+    stream.write_all(&frame.into_inner()).unwrap_or_else(|e| {
+        warn!("Failed to write packet: {:?}", e);
+    });
+}
+
+// Block positions are packed with y in the lowest bits rather than in the middle
+fn location_498(location: Location) -> i64 {
+    ((location.x as i64 & 0x3FFFFFF) << 38)
+        | ((location.z as i64 & 0x3FFFFFF) << 12)
+        | (location.y as i64 & 0xFFF)
+}
+
+fn location_from_498(position: i64) -> Location {
+    Location {
+        x: (position >> 38) as i32,
+        y: ((position << 52) >> 52) as i32,
+        z: ((position << 26) >> 38) as i32,
+    }
+}
+
+// 1.14.4's id for each of protocol 404's clientbound play packets. Use Bed was removed
+fn clientbound_498(id: i32) -> Option<i32> {
+    Some(match id {
+        0x00..=0x13 => id,
+        0x14 => 0x2E,
+        0x15..=0x1C => id - 1,
+        0x1D => 0x54,
+        0x1E..=0x20 => id - 2,
+        0x21..=0x24 => id - 1,
+        0x25 | 0x26 => id,
+        0x27 => 0x2B,
+        0x28..=0x2A => id,
+        0x2B => 0x2C,
+        0x2C..=0x32 => id + 3,
+        0x33 => return None,
+        0x34..=0x3D => id + 2,
+        0x3E..=0x4D => id + 4,
+        0x4E => 0x53,
+        0x4F => 0x55,
+        0x50..=0x55 => id + 6,
+        _ => return None,
+    })
+}
+
+// Protocol 404's id for each of 1.14.4's serverbound play packets. Set Difficulty, Lock
+// Difficulty and Update Jigsaw Block are new
+fn serverbound_498(id: i32) -> Option<i32> {
+    Some(match id {
+        0x00 | 0x01 => id,
+        0x03..=0x0F => id - 1,
+        0x11..=0x13 => id - 1,
+        0x14 => 0x0F,
+        0x15..=0x26 => id - 2,
+        0x28..=0x2D => id - 3,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::packet::{BlockChange, KeepAlive, PlayerDigging, SpawnPosition};
+
+    fn frame_id(bytes: &[u8]) -> (i32, Cursor<Vec<u8>>) {
+        let mut cursor = Cursor::new(bytes.to_vec());
+        cursor.read_var_int().unwrap();
+        let id = cursor.read_var_int().unwrap();
+        (id, cursor)
+    }
+
+    #[test]
+    fn protocol_498_packets_take_their_own_ids_and_layouts() {
+        let mut written = Vec::new();
+        ProtocolAdapter::Protocol498.write(&mut written, Packet::KeepAlive(KeepAlive { id: 7 }));
+        let (id, mut cursor) = frame_id(&written);
+        assert_eq!(id, 0x20);
+        assert_eq!(cursor.read_long().unwrap(), 7);
+
+        let mut written = Vec::new();
+        ProtocolAdapter::Protocol498.write(
+            &mut written,
+            Packet::BlockChange(BlockChange {
+                location: Location { x: -2, y: 70, z: 5 },
+                block_id: 1,
+            }),
+        );
+        let (id, mut cursor) = frame_id(&written);
+        assert_eq!(id, 0x0B);
+        let position = cursor.read_long().unwrap();
+        assert_eq!(position >> 38, -2);
+        assert_eq!((position << 26) >> 38, 5);
+        assert_eq!(position & 0xFFF, 70);
+
+        let mut written = Vec::new();
+        ProtocolAdapter::Protocol498.write(
+            &mut written,
+            Packet::SpawnPosition(SpawnPosition {
+                location: Location { x: 5, y: 16, z: -5 },
+            }),
+        );
+        let (id, mut cursor) = frame_id(&written);
+        assert_eq!(id, 0x4D);
+        let position = cursor.read_long().unwrap();
+        assert_eq!(position >> 38, 5);
+        assert_eq!((position << 26) >> 38, -5);
+        assert_eq!(position & 0xFFF, 16);
+
+        let location = Location {
+            x: -30_000_000,
+            y: -1,
+            z: 29_999_999,
+        };
+        assert_eq!(location_from_498(location_498(location)), location);
+
+        let mut body = Cursor::new(Vec::new());
+        body.write_var_int(0x1A); // 1.14.4's player digging
+        body.write_var_int(0);
+        body.write_long(location_498(Location { x: -2, y: 70, z: 5 }));
+        body.write_byte(1);
+        let mut cursor = Cursor::new(body.into_inner());
+        match ProtocolAdapter::Protocol498.read(&mut cursor, 3).unwrap() {
+            Packet::PlayerDigging(PlayerDigging { location, face, .. }) => {
+                assert_eq!(location, Location { x: -2, y: 70, z: 5 });
+                assert_eq!(face, 1);
+            }
+            packet => panic!("Read {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn protocol_498_play_packets_are_read_as_ours() {
+        let mut body = Cursor::new(Vec::new());
+        body.write_var_int(0x03); // 1.14.4's chat message
+        body.write_string(String::from("hello"));
+        let mut cursor = Cursor::new(body.into_inner());
+        match ProtocolAdapter::Protocol498.read(&mut cursor, 3).unwrap() {
+            Packet::ChatMessage(chat) => assert_eq!(chat.message, "hello"),
+            packet => panic!("Read {:?}", packet),
+        }
+
+        let mut body = Cursor::new(Vec::new());
+        body.write_var_int(0x10); // Lock Difficulty, which we don't have
+        body.write_boolean(true);
+        let mut cursor = Cursor::new(body.into_inner());
+        match ProtocolAdapter::Protocol498.read(&mut cursor, 3).unwrap() {
+            Packet::Unknown(unknown) => {
+                assert_eq!(unknown.id, 0x10);
+                assert_eq!(unknown.data, vec![1]);
+            }
+            packet => panic!("Read {:?}", packet),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use super::config::PeerQuotas;
+use super::token_bucket::TokenBucket;
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::time::Instant;
+use uuid::Uuid;
+
+// What a peer can use up of ours
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Resource {
+    // Packets from its map that we pass on to our players or other peers
+    RelayedPackets,
+    // Its players that are anchored here while they're on our map
+    AnchoredPlayers,
+    // Times it's asked for our whole state, every chunk included
+    StateReports,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    // Dropped, the peer can try again once it's slowed down
+    Throttled,
+    // Turned away for good
+    Rejected,
+}
+
+// Everything one peer instance is using, across all of its connections
+#[derive(Debug, Clone)]
+pub struct PeerUsage {
+    quotas: PeerQuotas,
+    relayed: TokenBucket,
+    state_reports: TokenBucket,
+    anchors: BTreeSet<Uuid>,
+}
+
+impl PeerUsage {
+    pub fn new(quotas: PeerQuotas, now: Instant) -> PeerUsage {
+        PeerUsage {
+            quotas,
+            relayed: TokenBucket::new(quotas.relayed_burst, quotas.relayed_per_second, now),
+            state_reports: TokenBucket::new(
+                quotas.state_report_burst,
+                quotas.state_reports_per_minute / 60.0,
+                now,
+            ),
+            anchors: BTreeSet::new(),
+        }
+    }
+
+    // Anchors are held by their connection until it's released
+    pub fn charge(&mut self, conn_id: Uuid, resource: Resource, now: Instant) -> Admission {
+        let allowed = match resource {
+            Resource::RelayedPackets => self.relayed.take(now),
+            Resource::StateReports => self.state_reports.take(now),
+            Resource::AnchoredPlayers => {
+                if self.anchors.len() >= self.quotas.anchored_players {
+                    return Admission::Rejected;
+                }
+                self.anchors.insert(conn_id);
+                true
+            }
+        };
+        if allowed {
+            Admission::Allowed
+        } else {
+            Admission::Throttled
+        }
+    }
+
+    pub fn release(&mut self, conn_id: &Uuid) {
+        self.anchors.remove(conn_id);
+    }
+
+    pub fn anchored_players(&self) -> usize {
+        self.anchors.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn quotas() -> PeerQuotas {
+        PeerQuotas {
+            relayed_per_second: 10.0,
+            relayed_burst: 5,
+            anchored_players: 2,
+            state_reports_per_minute: 1.0,
+            state_report_burst: 1,
+        }
+    }
+
+    #[test]
+    fn relays_past_the_burst_are_throttled_until_the_peer_slows_down() {
+        let start = Instant::now();
+        let mut usage = PeerUsage::new(quotas(), start);
+        let conn_id = Uuid::new_v4();
+        for _ in 0..5 {
+            assert_eq!(
+                usage.charge(conn_id, Resource::RelayedPackets, start),
+                Admission::Allowed
+            );
+        }
+        assert_eq!(
+            usage.charge(conn_id, Resource::RelayedPackets, start),
+            Admission::Throttled
+        );
+        let later = start + Duration::from_millis(100);
+        assert_eq!(
+            usage.charge(conn_id, Resource::RelayedPackets, later),
+            Admission::Allowed
+        );
+        assert_eq!(
+            usage.charge(conn_id, Resource::RelayedPackets, later),
+            Admission::Throttled
+        );
+
+        assert_eq!(
+            usage.charge(conn_id, Resource::StateReports, start),
+            Admission::Allowed
+        );
+        assert_eq!(
+            usage.charge(conn_id, Resource::StateReports, later),
+            Admission::Throttled
+        );
+        assert_eq!(
+            usage.charge(
+                conn_id,
+                Resource::StateReports,
+                start + Duration::from_secs(60)
+            ),
+            Admission::Allowed
+        );
+    }
+
+    #[test]
+    fn anchors_past_the_quota_are_rejected_until_one_is_released() {
+        let now = Instant::now();
+        let mut usage = PeerUsage::new(quotas(), now);
+        let anchors: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        assert_eq!(
+            usage.charge(anchors[0], Resource::AnchoredPlayers, now),
+            Admission::Allowed
+        );
+        assert_eq!(
+            usage.charge(anchors[1], Resource::AnchoredPlayers, now),
+            Admission::Allowed
+        );
+        assert_eq!(
+            usage.charge(anchors[2], Resource::AnchoredPlayers, now),
+            Admission::Rejected
+        );
+        usage.release(&anchors[0]);
+        assert_eq!(
+            usage.charge(anchors[2], Resource::AnchoredPlayers, now),
+            Admission::Allowed
+        );
+        assert_eq!(usage.anchored_players(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use super::config::{PacketRate, PacketRateLimits};
+use super::packet::Packet;
+use super::token_bucket::TokenBucket;
+
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Movement,
+    Interaction,
+    Other,
+}
+
+impl Category {
+    // Keep alive answers are never limited, or a client that's flooding something else would also
+    // be timed out for not answering
+    pub fn of(packet: &Packet) -> Option<Category> {
+        match packet {
+            Packet::ServerboundKeepAlive(_) => None,
+            Packet::PlayerPosition(_)
+            | Packet::PlayerPositionAndLook(_)
+            | Packet::PlayerLook(_) => Some(Category::Movement),
+            Packet::PlayerDigging(_)
+            | Packet::HeldItemChange(_)
+            | Packet::CreativeInventoryAction(_) => Some(Category::Interaction),
+            _ => Some(Category::Other),
+        }
+    }
+}
+
+// What to do with a packet a client just sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    Dropped,
+    Kicked,
+}
+
+#[derive(Debug, Clone)]
+struct Limit {
+    bucket: TokenBucket,
+    drops: u32,
+}
+
+impl Limit {
+    fn new(rate: PacketRate, now: Instant) -> Limit {
+        Limit {
+            bucket: TokenBucket::new(rate.burst, rate.per_second, now),
+            drops: 0,
+        }
+    }
+}
+
+// A token bucket for each category of packet one client sends. Drops are only forgotten once the
+// category's bucket has filled back up, so a client sending just over the limit is still kicked
+// eventually
+#[derive(Debug, Clone)]
+pub struct ConnectionRateLimiter {
+    drops_before_kick: u32,
+    movement: Limit,
+    interaction: Limit,
+    other: Limit,
+}
+
+impl ConnectionRateLimiter {
+    pub fn new(limits: PacketRateLimits, now: Instant) -> ConnectionRateLimiter {
+        ConnectionRateLimiter {
+            drops_before_kick: limits.drops_before_kick,
+            movement: Limit::new(limits.movement, now),
+            interaction: Limit::new(limits.interaction, now),
+            other: Limit::new(limits.other, now),
+        }
+    }
+
+    pub fn check(&mut self, packet: &Packet, now: Instant) -> Verdict {
+        let limit = match Category::of(packet) {
+            Some(Category::Movement) => &mut self.movement,
+            Some(Category::Interaction) => &mut self.interaction,
+            Some(Category::Other) => &mut self.other,
+            None => return Verdict::Allowed,
+        };
+        if limit.bucket.take(now) {
+            if limit.bucket.was_full() {
+                limit.drops = 0;
+            }
+            return Verdict::Allowed;
+        }
+        limit.drops += 1;
+        if limit.drops >= self.drops_before_kick {
+            return Verdict::Kicked;
+        }
+        Verdict::Dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::packet::{PlayerPosition, ServerboundKeepAlive};
+    use std::time::Duration;
+
+    fn limits() -> PacketRateLimits {
+        let rate = PacketRate {
+            burst: 2,
+            per_second: 1.0,
+        };
+        PacketRateLimits {
+            movement: rate,
+            interaction: rate,
+            other: rate,
+            drops_before_kick: 3,
+        }
+    }
+
+    fn movement() -> Packet {
+        Packet::PlayerPosition(PlayerPosition {
+            x: 0.0,
+            feet_y: 0.0,
+            z: 0.0,
+            on_ground: true,
+        })
+    }
+
+    #[test]
+    fn floods_are_dropped_then_kicked() {
+        let start = Instant::now();
+        let mut limiter = ConnectionRateLimiter::new(limits(), start);
+        assert_eq!(limiter.check(&movement(), start), Verdict::Allowed);
+        assert_eq!(limiter.check(&movement(), start), Verdict::Allowed);
+        assert_eq!(limiter.check(&movement(), start), Verdict::Dropped);
+        assert_eq!(limiter.check(&movement(), start), Verdict::Dropped);
+
+        // Keeping up with the rate isn't enough to be forgiven
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check(&movement(), later), Verdict::Allowed);
+        assert_eq!(limiter.check(&movement(), later), Verdict::Kicked);
+    }
+
+    #[test]
+    fn drops_are_forgotten_once_the_client_slows_down() {
+        let start = Instant::now();
+        let mut limiter = ConnectionRateLimiter::new(limits(), start);
+        limiter.check(&movement(), start);
+        limiter.check(&movement(), start);
+        assert_eq!(limiter.check(&movement(), start), Verdict::Dropped);
+        assert_eq!(limiter.check(&movement(), start), Verdict::Dropped);
+
+        let rested = start + Duration::from_secs(2);
+        limiter.check(&movement(), rested);
+        limiter.check(&movement(), rested);
+        assert_eq!(limiter.check(&movement(), rested), Verdict::Dropped);
+        assert_eq!(limiter.check(&movement(), rested), Verdict::Dropped);
+    }
+
+    #[test]
+    fn keep_alive_answers_are_never_dropped() {
+        let start = Instant::now();
+        let mut limiter = ConnectionRateLimiter::new(limits(), start);
+        for _ in 0..10 {
+            assert_eq!(
+                limiter.check(
+                    &Packet::ServerboundKeepAlive(ServerboundKeepAlive { id: 1 }),
+                    start
+                ),
+                Verdict::Allowed
+            );
+        }
+    }
+}
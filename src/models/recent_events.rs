@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// What's happened lately, for catching up whoever turns up just after. Events are kept for max_age
+// and no more than limit of them at once, oldest first
+#[derive(Debug, Clone)]
+pub struct RecentEvents<T> {
+    max_age: Duration,
+    limit: usize,
+    events: VecDeque<(Instant, T)>,
+}
+
+impl<T: Clone> RecentEvents<T> {
+    pub fn new(max_age: Duration, limit: usize) -> RecentEvents<T> {
+        RecentEvents {
+            max_age,
+            limit,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: T, now: Instant) {
+        if self.events.len() == self.limit {
+            self.events.pop_front();
+        }
+        self.events.push_back((now, event));
+        self.forget_old(now);
+    }
+
+    // In the order they happened
+    pub fn recent(&mut self, now: Instant) -> Vec<T> {
+        self.forget_old(now);
+        self.events.iter().map(|(_, event)| event.clone()).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    fn forget_old(&mut self, now: Instant) {
+        while let Some((at, _)) = self.events.front() {
+            if now.duration_since(*at) < self.max_age {
+                break;
+            }
+            self.events.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_latest_few_events_are_kept() {
+        let start = Instant::now();
+        let mut events = RecentEvents::new(Duration::from_secs(10), 3);
+        (0..4).for_each(|event| events.push(event, start));
+        assert_eq!(events.recent(start), vec![1, 2, 3]);
+
+        events.push(4, start + Duration::from_secs(5));
+        assert_eq!(events.recent(start + Duration::from_secs(10)), vec![4]);
+        assert!(events.recent(start + Duration::from_secs(15)).is_empty());
+    }
+}
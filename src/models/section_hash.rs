@@ -0,0 +1,82 @@
+use super::world_generator::CHUNK_BLOCKS;
+
+// A hash of each chunk section's blocks, kept up to date as blocks change rather than worked out
+// again from the whole section. Each block's hash depends on where it is and what it is, and a
+// section's hash is all of its blocks' hashes xored together, so a block's old hash can be taken
+// back out and its new one put in. Sections with the same blocks hash the same wherever they are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionHashes {
+    hashes: Vec<u64>,
+}
+
+impl SectionHashes {
+    pub fn new(block_ids: &[i32]) -> SectionHashes {
+        SectionHashes {
+            hashes: block_ids.chunks(CHUNK_BLOCKS).map(section_hash).collect(),
+        }
+    }
+
+    pub fn get(&self, section: usize) -> u64 {
+        self.hashes[section]
+    }
+
+    pub fn all(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    // A single block in the section changing, by its index within the section
+    pub fn change(&mut self, section: usize, index: usize, from: i32, to: i32) {
+        self.hashes[section] ^= block_hash(index, from) ^ block_hash(index, to);
+    }
+
+    // The whole section changing at once
+    pub fn replace(&mut self, section: usize, block_ids: &[i32]) {
+        self.hashes[section] = section_hash(block_ids);
+    }
+}
+
+fn section_hash(block_ids: &[i32]) -> u64 {
+    block_ids
+        .iter()
+        .enumerate()
+        .fold(0, |hash, (index, block_id)| {
+            hash ^ block_hash(index, *block_id)
+        })
+}
+
+// splitmix64's finaliser, so that blocks next to each other or with similar ids don't cancel out
+fn block_hash(index: usize, block_id: i32) -> u64 {
+    let mut hash =
+        ((index as u64) << 32 | block_id as u32 as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_hashed_the_same_as_starting_over() {
+        let mut block_ids = vec![0; CHUNK_BLOCKS * 2];
+        let mut hashes = SectionHashes::new(&block_ids);
+        assert_eq!(hashes.get(0), hashes.get(1));
+
+        block_ids[CHUNK_BLOCKS + 5] = 9;
+        hashes.change(1, 5, 0, 9);
+        assert_ne!(hashes.get(0), hashes.get(1));
+        assert_eq!(hashes, SectionHashes::new(&block_ids));
+
+        // The same block somewhere else in the section isn't the same section
+        let mut moved = vec![0; CHUNK_BLOCKS];
+        moved[6] = 9;
+        hashes.replace(0, &moved);
+        assert_ne!(hashes.get(0), hashes.get(1));
+
+        hashes.change(1, 5, 9, 0);
+        block_ids[CHUNK_BLOCKS + 5] = 0;
+        hashes.replace(0, &block_ids[..CHUNK_BLOCKS]);
+        assert_eq!(hashes.all(), &[hashes.get(1); 2]);
+    }
+}
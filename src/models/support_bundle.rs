@@ -0,0 +1,238 @@
+use super::config::Config;
+use super::constants::{SERVER_PROTOCOL, SERVER_VERSION};
+use super::link_watermarks::LinkAlert;
+use super::topology::Topology;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+
+// Everything needed to make sense of a bug report from one node, gathered at a single point in time
+pub struct SupportBundle {
+    pub topology: Topology,
+    pub queue_depths: Vec<(&'static str, usize)>,
+    pub link_alerts: Vec<LinkAlert>,
+    pub flight_recorder: Vec<String>,
+    pub config: Config,
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    name: &'static str,
+    version: &'static str,
+    minecraft_version: &'static str,
+    protocol: u16,
+    os: &'static str,
+    arch: &'static str,
+}
+
+impl SupportBundle {
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let version = VersionInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            minecraft_version: SERVER_VERSION,
+            protocol: SERVER_PROTOCOL,
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+        };
+        let queue_depths: BTreeMap<&str, usize> = self.queue_depths.iter().copied().collect();
+        let files = vec![
+            (
+                "version.json",
+                serde_json::to_string_pretty(&version).unwrap(),
+            ),
+            (
+                "topology.json",
+                serde_json::to_string_pretty(&self.topology).unwrap(),
+            ),
+            (
+                "queue_depths.json",
+                serde_json::to_string_pretty(&queue_depths).unwrap(),
+            ),
+            (
+                "link_alerts.json",
+                serde_json::to_string_pretty(&self.link_alerts).unwrap(),
+            ),
+            ("flight_recorder.log", self.flight_recorder.join("\n")),
+            (
+                "config.json",
+                serde_json::to_string_pretty(&self.config.redacted()).unwrap(),
+            ),
+        ];
+        fs::write(path, zip(files))
+            .map_err(|e| format!("Failed to write support bundle {}: {:?}", path, e))
+    }
+}
+
+// A zip archive of deflated entries, which every unzip tool can read. Flight recorder logs shrink a
+// lot, which makes bundles easier to attach to a bug report
+fn zip(files: Vec<(&str, String)>) -> Vec<u8> {
+    const VERSION: u16 = 20;
+    const DEFLATED: u16 = 8;
+    const DOS_DATE: u16 = (1 << 5) | 1; // 1980-01-01
+
+    let mut archive = Vec::new();
+    let mut central_directory = Vec::new();
+    for (name, contents) in files.iter() {
+        let offset = archive.len() as u32;
+        let crc = crc32(contents.as_bytes());
+        let size = contents.len() as u32;
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents.as_bytes()).unwrap();
+        let deflated = encoder.finish().unwrap();
+        let compressed_size = deflated.len() as u32;
+
+        archive.write_u32::<LittleEndian>(0x0403_4b50).unwrap();
+        archive.write_u16::<LittleEndian>(VERSION).unwrap();
+        archive.write_u16::<LittleEndian>(0).unwrap(); // flags
+        archive.write_u16::<LittleEndian>(DEFLATED).unwrap();
+        archive.write_u16::<LittleEndian>(0).unwrap(); // time
+        archive.write_u16::<LittleEndian>(DOS_DATE).unwrap();
+        archive.write_u32::<LittleEndian>(crc).unwrap();
+        archive.write_u32::<LittleEndian>(compressed_size).unwrap();
+        archive.write_u32::<LittleEndian>(size).unwrap();
+        archive
+            .write_u16::<LittleEndian>(name.len() as u16)
+            .unwrap();
+        archive.write_u16::<LittleEndian>(0).unwrap(); // extra field length
+        archive.extend(name.as_bytes());
+        archive.extend(deflated);
+
+        central_directory
+            .write_u32::<LittleEndian>(0x0201_4b50)
+            .unwrap();
+        central_directory
+            .write_u16::<LittleEndian>(VERSION)
+            .unwrap(); // made by
+        central_directory
+            .write_u16::<LittleEndian>(VERSION)
+            .unwrap(); // needed
+        central_directory.write_u16::<LittleEndian>(0).unwrap(); // flags
+        central_directory
+            .write_u16::<LittleEndian>(DEFLATED)
+            .unwrap();
+        central_directory.write_u16::<LittleEndian>(0).unwrap(); // time
+        central_directory
+            .write_u16::<LittleEndian>(DOS_DATE)
+            .unwrap();
+        central_directory.write_u32::<LittleEndian>(crc).unwrap();
+        central_directory
+            .write_u32::<LittleEndian>(compressed_size)
+            .unwrap();
+        central_directory.write_u32::<LittleEndian>(size).unwrap();
+        central_directory
+            .write_u16::<LittleEndian>(name.len() as u16)
+            .unwrap();
+        // extra field length, comment length, disk, internal and external attributes
+        central_directory.write_u16::<LittleEndian>(0).unwrap();
+        central_directory.write_u16::<LittleEndian>(0).unwrap();
+        central_directory.write_u16::<LittleEndian>(0).unwrap();
+        central_directory.write_u16::<LittleEndian>(0).unwrap();
+        central_directory.write_u32::<LittleEndian>(0).unwrap();
+        central_directory.write_u32::<LittleEndian>(offset).unwrap();
+        central_directory.extend(name.as_bytes());
+    }
+
+    let central_directory_offset = archive.len() as u32;
+    let central_directory_size = central_directory.len() as u32;
+    archive.extend(central_directory);
+    archive.write_u32::<LittleEndian>(0x0605_4b50).unwrap();
+    archive.write_u16::<LittleEndian>(0).unwrap(); // disk
+    archive.write_u16::<LittleEndian>(0).unwrap(); // disk with the central directory
+    archive
+        .write_u16::<LittleEndian>(files.len() as u16)
+        .unwrap();
+    archive
+        .write_u16::<LittleEndian>(files.len() as u16)
+        .unwrap();
+    archive
+        .write_u32::<LittleEndian>(central_directory_size)
+        .unwrap();
+    archive
+        .write_u32::<LittleEndian>(central_directory_offset)
+        .unwrap();
+    archive.write_u16::<LittleEndian>(0).unwrap(); // comment length
+    archive
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::ReadBytesExt;
+    use flate2::read::DeflateDecoder;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn checksums_are_the_ones_zip_uses() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn the_central_directory_points_at_each_entry() {
+        let log = "Peer link is falling behind\n".repeat(50);
+        let archive = zip(vec![
+            ("version.json", String::from("{}")),
+            ("log", log.clone()),
+        ]);
+        let u16_at = |at: usize| {
+            Cursor::new(&archive[at..])
+                .read_u16::<LittleEndian>()
+                .unwrap()
+        };
+        let u32_at = |at: usize| {
+            Cursor::new(&archive[at..])
+                .read_u32::<LittleEndian>()
+                .unwrap()
+        };
+
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50);
+        assert_eq!(u16_at(end + 10), 2);
+        let mut entry = u32_at(end + 16) as usize;
+        let mut contents = vec![];
+        for _ in 0..2 {
+            assert_eq!(u32_at(entry), 0x0201_4b50);
+            let name_length = u16_at(entry + 28) as usize;
+            let name = &archive[entry + 46..entry + 46 + name_length];
+            let local = u32_at(entry + 42) as usize;
+            assert_eq!(u32_at(local), 0x0403_4b50);
+            assert_eq!(u16_at(local + 26) as usize, name_length);
+            assert_eq!(&archive[local + 30..local + 30 + name_length], name);
+            assert_eq!(u32_at(local + 14), u32_at(entry + 16));
+
+            let data = local + 30 + name_length;
+            let compressed_size = u32_at(local + 18) as usize;
+            let mut inflated = String::new();
+            DeflateDecoder::new(&archive[data..data + compressed_size])
+                .read_to_string(&mut inflated)
+                .unwrap();
+            assert_eq!(crc32(inflated.as_bytes()), u32_at(local + 14));
+            assert_eq!(inflated.len(), u32_at(local + 22) as usize);
+            contents.push(inflated);
+            entry += 46 + name_length;
+        }
+        assert_eq!(contents, vec![String::from("{}"), log.clone()]);
+        assert!(archive.len() < log.len());
+    }
+}
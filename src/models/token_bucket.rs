@@ -0,0 +1,37 @@
+use std::time::Instant;
+
+// Lets through bursts of up to burst at once, then per_second on average
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    burst: f64,
+    per_second: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(burst: u32, per_second: f64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            burst: f64::from(burst),
+            per_second,
+            tokens: f64::from(burst),
+            refilled_at: now,
+        }
+    }
+
+    pub fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    // Whether the bucket had filled all the way back up before it was last taken from
+    pub fn was_full(&self) -> bool {
+        self.tokens + 1.0 >= self.burst
+    }
+}
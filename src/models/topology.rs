@@ -0,0 +1,147 @@
+use super::config::{Config, GeneratorRegion};
+use super::map::{Dimension, Peer, Position};
+use super::versioned;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+// A quilt layout that can be written out from one running node and used to recreate the same
+// layout on a fresh set of nodes. Positions are in map units with the exporting node at the origin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Topology {
+    pub maps: Vec<TopologyMap>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopologyMap {
+    pub name: String,
+    pub owner: Peer,
+    pub position: Position,
+    // None for maps whose owner hasn't told us how it generates them
+    pub seed: Option<Seed>,
+}
+
+// How a map's chunks are generated. Generators don't take a seed of their own, so the ones picked
+// for each part of the map are all it takes for a fresh node to generate the same chunks again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Seed {
+    pub generator: String,
+    pub generator_regions: Vec<GeneratorRegion>,
+}
+
+impl Seed {
+    pub fn configured(config: &Config) -> Seed {
+        Seed {
+            generator: config.generator.clone(),
+            generator_regions: config.generator_regions.clone(),
+        }
+    }
+
+    // The config with our map generated from this seed instead
+    pub fn apply(&self, config: Config) -> Config {
+        Config {
+            generator: self.generator.clone(),
+            generator_regions: self.generator_regions.clone(),
+            ..config
+        }
+    }
+}
+
+impl Topology {
+    pub fn load(path: &str) -> Result<Topology, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read topology file {}: {:?}", path, e))?;
+        versioned::TOPOLOGY
+            .from_str(&contents)
+            .map_err(|e| format!("Failed to parse topology file {}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let contents = versioned::TOPOLOGY.to_string_pretty(self)?;
+        fs::write(path, contents)
+            .map_err(|e| format!("Failed to write topology file {}: {:?}", path, e))
+    }
+
+    // How the given node's map is generated, if the topology says
+    pub fn seed(&self, owner: &Peer) -> Option<&Seed> {
+        self.maps
+            .iter()
+            .find(|map| map.owner == *owner)
+            .and_then(|map| map.seed.as_ref())
+    }
+
+    // The dimension of the given node's map
+    pub fn dimension(&self, owner: &Peer) -> Dimension {
+        self.maps
+            .iter()
+            .find(|map| map.owner == *owner)
+            .map_or(Dimension::default(), |map| map.position.dimension)
+    }
+
+    // The same layout as seen from the given node, which is placed at the origin
+    pub fn rebase(&self, local_peer: &Peer) -> Option<Topology> {
+        let origin = self
+            .maps
+            .iter()
+            .find(|map| map.owner == *local_peer)?
+            .position;
+        Some(Topology {
+            maps: self
+                .maps
+                .iter()
+                .map(|map| TopologyMap {
+                    position: Position {
+                        x: map.position.x - origin.x,
+                        z: map.position.z - origin.z,
+                        ..map.position
+                    },
+                    ..map.clone()
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_are_kept_and_older_topologies_go_without() {
+        let topology = Topology {
+            maps: vec![TopologyMap {
+                name: String::from("spawn"),
+                owner: Peer {
+                    address: String::from("10.0.0.2"),
+                    port: 25565,
+                },
+                position: Position::default(),
+                seed: Some(Seed {
+                    generator: String::from("flat"),
+                    generator_regions: vec![GeneratorRegion {
+                        generator: String::from("empty"),
+                        from_chunk_x: 0,
+                        from_chunk_z: 0,
+                        to_chunk_x: 1,
+                        to_chunk_z: 1,
+                    }],
+                }),
+            }],
+        };
+        let written = versioned::TOPOLOGY.to_string(&topology).unwrap();
+        let read: Topology = versioned::TOPOLOGY.from_str(&written).unwrap();
+        assert_eq!(
+            read.seed(&topology.maps[0].owner),
+            topology.maps[0].seed.as_ref()
+        );
+
+        let version_1 = r#"{"format": "topology", "version": 1, "data": {"maps": [{
+            "name": "spawn",
+            "owner": {"address": "10.0.0.2", "port": 25565},
+            "position": {"x": 0, "z": 0}
+        }]}}"#;
+        let read: Topology = versioned::TOPOLOGY.from_str(version_1).unwrap();
+        assert_eq!(read.maps[0].name, "spawn");
+        assert_eq!(read.seed(&topology.maps[0].owner), None);
+    }
+}
@@ -1,11 +1,16 @@
+use super::constants::{ANCHORED_PLAYER_ENTITY_ID_START, ENTITY_ID_BLOCK_SIZE};
 use super::map::{Map, Position};
 
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
 #[derive(Debug)]
 pub enum TranslationUpdates {
     State(i32),
     EntityIdBlock(i32),
+    EntityIds(EntityIdTable),
     XOrigin(i32),
-    NoChange,
+    ZOrigin(i32),
 }
 
 #[derive(Debug, Clone)]
@@ -14,11 +19,17 @@ pub struct TranslationInfo {
     pub map: Map,
 }
 
+impl Default for TranslationInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TranslationInfo {
     pub fn new() -> TranslationInfo {
         TranslationInfo {
             state: 0,
-            map: Map::new(Position { x: 0, z: 0 }, 0),
+            map: Map::new(Position::default(), 0),
         }
     }
 
@@ -30,10 +41,327 @@ impl TranslationInfo {
             TranslationUpdates::EntityIdBlock(block) => {
                 self.map.entity_id_block = *block;
             }
+            TranslationUpdates::EntityIds(entity_ids) => {
+                self.map.entity_ids = entity_ids.clone();
+            }
             TranslationUpdates::XOrigin(x) => {
                 self.map.position.x = *x;
             }
-            TranslationUpdates::NoChange => {}
+            TranslationUpdates::ZOrigin(z) => {
+                self.map.position.z = *z;
+            }
+        }
+    }
+}
+
+// Remembers which of our entity ids each entity id a peer sends us was translated to, so that ids
+// can be mapped back when we talk to the peer about its entities. Entries go when the peer destroys
+// the entity or the link closes, and past a block's worth of ids the oldest go first, so a peer that
+// never destroys anything can't grow it without end. Shared between the patchwork state and the
+// inbound packet processor, which is the only one adding to it
+#[derive(Debug, Clone, Default)]
+pub struct EntityIdTable {
+    mappings: Arc<Mutex<EntityIdMappings>>,
+}
+
+#[derive(Debug, Default)]
+struct EntityIdMappings {
+    peer_to_local: HashMap<i32, i32>,
+    local_to_peer: HashMap<i32, i32>,
+    // Peer ids, oldest first
+    added: VecDeque<i32>,
+}
+
+impl EntityIdTable {
+    pub fn new() -> EntityIdTable {
+        EntityIdTable::default()
+    }
+
+    pub fn to_local(&self, peer_id: i32, entity_id_block: i32) -> i32 {
+        let mut mappings = self.mappings.lock().unwrap();
+        if let Some(local_id) = mappings.peer_to_local.get(&peer_id) {
+            return *local_id;
+        }
+        // Ids in the peer's anchored player range belong to our own players anchored on the peer
+        let local_id = if peer_id % ENTITY_ID_BLOCK_SIZE >= ANCHORED_PLAYER_ENTITY_ID_START {
+            peer_id % ENTITY_ID_BLOCK_SIZE - ANCHORED_PLAYER_ENTITY_ID_START
+        } else {
+            peer_id + entity_id_block * ENTITY_ID_BLOCK_SIZE
+        };
+        while mappings.peer_to_local.len() >= ENTITY_ID_BLOCK_SIZE as usize {
+            match mappings.added.pop_front() {
+                Some(oldest) => mappings.forget_peer(oldest),
+                None => break,
+            }
+        }
+        mappings.peer_to_local.insert(peer_id, local_id);
+        mappings.local_to_peer.insert(local_id, peer_id);
+        mappings.added.push_back(peer_id);
+        local_id
+    }
+
+    // Ids the peer hasn't told us about yet can only be its own entities, in its block
+    pub fn to_peer(&self, local_id: i32, entity_id_block: i32) -> i32 {
+        let mappings = self.mappings.lock().unwrap();
+        match mappings.local_to_peer.get(&local_id) {
+            Some(peer_id) => *peer_id,
+            None if local_id / ENTITY_ID_BLOCK_SIZE == entity_id_block => {
+                local_id % ENTITY_ID_BLOCK_SIZE
+            }
+            None => local_id,
+        }
+    }
+
+    pub fn forget_local(&self, local_id: i32) {
+        let mut mappings = self.mappings.lock().unwrap();
+        if let Some(peer_id) = mappings.local_to_peer.remove(&local_id) {
+            mappings.peer_to_local.remove(&peer_id);
+            mappings.added.retain(|added| *added != peer_id);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut mappings = self.mappings.lock().unwrap();
+        mappings.peer_to_local.clear();
+        mappings.local_to_peer.clear();
+        mappings.added.clear();
+    }
+}
+
+impl EntityIdMappings {
+    fn forget_peer(&mut self, peer_id: i32) {
+        // Unless the local id has been taken over by a newer peer id since
+        if let Some(local_id) = self.peer_to_local.remove(&peer_id) {
+            if self.local_to_peer.get(&local_id) == Some(&peer_id) {
+                self.local_to_peer.remove(&local_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::minecraft_protocol::MinecraftProtocolReader;
+    use super::super::minecraft_types::{ItemStack, Location};
+    use super::super::packet::{
+        self, BlockChange, DestroyEntities, ItemTransfer, Packet, SpawnPlayer,
+    };
+    use super::*;
+    use std::io::Cursor;
+
+    fn peer_translation() -> TranslationInfo {
+        let mut translation_info = TranslationInfo::new();
+        translation_info.update(&TranslationUpdates::EntityIdBlock(2));
+        translation_info.update(&TranslationUpdates::XOrigin(1));
+        translation_info.update(&TranslationUpdates::ZOrigin(-2));
+        translation_info
+    }
+
+    #[test]
+    fn peer_ids_round_trip() {
+        let table = EntityIdTable::new();
+        for peer_id in [0, 7, 499, 500, 948] {
+            let local_id = table.to_local(peer_id, 2);
+            assert_eq!(local_id, 2000 + peer_id);
+            assert_eq!(table.to_peer(local_id, 2), peer_id);
+        }
+    }
+
+    #[test]
+    fn the_oldest_ids_go_once_a_blocks_worth_are_remembered() {
+        let table = EntityIdTable::new();
+        (0..ENTITY_ID_BLOCK_SIZE + 10).for_each(|peer_id| {
+            table.to_local(peer_id, 2);
+        });
+        let mappings = table.mappings.lock().unwrap();
+        assert_eq!(mappings.peer_to_local.len(), ENTITY_ID_BLOCK_SIZE as usize);
+        assert_eq!(mappings.local_to_peer.len(), ENTITY_ID_BLOCK_SIZE as usize);
+        assert!(!mappings.peer_to_local.contains_key(&9));
+        assert!(mappings.peer_to_local.contains_key(&10));
+    }
+
+    #[test]
+    fn anchored_player_ids_round_trip() {
+        let table = EntityIdTable::new();
+        let local_id = table.to_local(953, 2);
+        assert_eq!(local_id, 4);
+        assert_eq!(table.to_peer(4, 2), 953);
+        assert_eq!(table.to_local(953, 2), 4);
+    }
+
+    #[test]
+    fn forgotten_ids_fall_back_to_the_peer_block() {
+        let table = EntityIdTable::new();
+        table.to_local(953, 2);
+        table.forget_local(4);
+        assert_eq!(table.to_peer(4, 2), 4);
+        assert_eq!(table.to_peer(2005, 2), 5);
+        assert!(table.mappings.lock().unwrap().added.is_empty());
+    }
+
+    #[test]
+    fn cleared_tables_forget_everything() {
+        let table = EntityIdTable::new();
+        table.to_local(953, 2);
+        table.to_local(7, 2);
+        table.clear();
+        assert_eq!(table.to_peer(4, 2), 4);
+        let mappings = table.mappings.lock().unwrap();
+        assert!(mappings.peer_to_local.is_empty() && mappings.added.is_empty());
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let translation_info = peer_translation();
+        let spawn = SpawnPlayer {
+            entity_id: 42,
+            uuid: 1,
+            x: 3.5,
+            y: 64.0,
+            z: 2.0,
+            yaw: 0,
+            pitch: 0,
+            entity_metadata_terminator: 0xff,
+        };
+
+        let incoming = packet::translate(Packet::SpawnPlayer(spawn), translation_info.clone());
+        let incoming = match incoming {
+            Packet::SpawnPlayer(incoming) => incoming,
+            _ => panic!("Translation changed the packet type"),
+        };
+        assert_eq!(incoming.entity_id, 2042);
+        assert_eq!(incoming.x, 19.5);
+        assert_eq!(incoming.z, -30.0);
+
+        let outgoing =
+            packet::translate_outgoing(Packet::SpawnPlayer(incoming), translation_info.clone());
+        match outgoing {
+            Packet::SpawnPlayer(outgoing) => {
+                assert_eq!(outgoing.entity_id, 42);
+                assert_eq!(outgoing.x, 3.5);
+                assert_eq!(outgoing.z, 2.0);
+            }
+            _ => panic!("Translation changed the packet type"),
+        }
+    }
+
+    #[test]
+    fn destroyed_ids_translate_element_wise() {
+        let translation_info = peer_translation();
+        let destroy = DestroyEntities {
+            entity_ids: vec![1, 951],
+        };
+        match packet::translate(Packet::DestroyEntities(destroy), translation_info.clone()) {
+            Packet::DestroyEntities(destroy) => assert_eq!(destroy.entity_ids, vec![2001, 2]),
+            _ => panic!("Translation changed the packet type"),
+        }
+        match packet::translate_outgoing(
+            Packet::DestroyEntities(DestroyEntities {
+                entity_ids: vec![2001, 2],
+            }),
+            translation_info,
+        ) {
+            Packet::DestroyEntities(destroy) => assert_eq!(destroy.entity_ids, vec![1, 951]),
+            _ => panic!("Translation changed the packet type"),
+        }
+    }
+    #[test]
+    fn block_changes_land_in_the_peers_map() {
+        let translation_info = peer_translation();
+        let change = BlockChange {
+            location: Location { x: 3, y: 12, z: 5 },
+            block_id: 1,
+        };
+        let mut bytes = Vec::new();
+        packet::write(
+            &mut bytes,
+            packet::translate_outgoing(Packet::BlockChange(change), translation_info.clone()),
+        );
+        let mut cursor = Cursor::new(bytes);
+        cursor.read_var_int().unwrap();
+        match packet::read(&mut cursor, 5).unwrap() {
+            Packet::BlockChange(change) => {
+                assert_eq!(
+                    change.location,
+                    Location {
+                        x: -13,
+                        y: 12,
+                        z: 37
+                    }
+                );
+                match packet::translate(Packet::BlockChange(change), translation_info) {
+                    Packet::BlockChange(change) => {
+                        assert_eq!(change.location, Location { x: 3, y: 12, z: 5 })
+                    }
+                    _ => panic!("Translation changed the packet type"),
+                }
+            }
+            _ => panic!("Block change didn't survive the wire"),
+        }
+    }
+
+    #[test]
+    fn block_changes_are_written_the_way_vanilla_writes_them() {
+        // wiki.vg's example position, packed x << 38 | y << 26 | z as it is up to 1.13
+        let change = BlockChange {
+            location: Location {
+                x: 18357644,
+                y: 831,
+                z: -20882616,
+            },
+            block_id: 1,
+        };
+        let mut bytes = Vec::new();
+        packet::write(&mut bytes, Packet::BlockChange(change.clone()));
+        assert_eq!(
+            bytes,
+            vec![10, 0x0B, 0x46, 0x07, 0x63, 0x0C, 0xFE, 0xC1, 0x5B, 0x48, 1]
+        );
+        let mut cursor = Cursor::new(bytes);
+        cursor.read_var_int().unwrap();
+        match packet::read(&mut cursor, 5).unwrap() {
+            Packet::BlockChange(read) => assert_eq!(read.location, change.location),
+            _ => panic!("Vanilla's block change read as something else"),
+        }
+    }
+
+    #[test]
+    fn items_are_handed_over_where_they_crossed() {
+        let transfer = ItemTransfer {
+            uuid: 9,
+            item: Some(ItemStack {
+                item_id: 1,
+                count: 3,
+                nbt: None,
+            }),
+            x: 16.25,
+            y: 17.5,
+            z: -30.0,
+            velocity_x: 0.2,
+            velocity_y: -0.1,
+            velocity_z: 0.0,
+            floor_y: 16.0,
+            pickup_delay: 12,
+            age: 28,
+            thrower: 7,
+        };
+        let mut bytes = Vec::new();
+        packet::write(
+            &mut bytes,
+            packet::translate_outgoing(Packet::ItemTransfer(transfer), peer_translation()),
+        );
+        let mut cursor = Cursor::new(bytes);
+        cursor.read_var_int().unwrap();
+        match packet::read(&mut cursor, 6).unwrap() {
+            Packet::ItemTransfer(transfer) => {
+                assert_eq!((transfer.x, transfer.y, transfer.z), (0.25, 17.5, 2.0));
+                assert_eq!(transfer.velocity_x, 0.2);
+                assert_eq!(transfer.floor_y, 16.0);
+                assert_eq!((transfer.pickup_delay, transfer.age), (12, 28));
+                assert_eq!((transfer.uuid, transfer.thrower), (9, 7));
+                assert_eq!(transfer.item.map(|item| item.count), Some(3));
+            }
+            _ => panic!("Item transfer didn't survive the wire"),
         }
     }
 }
@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+// Where new players' and connections' uuids come from. Nodes under test count up from 1 instead,
+// so they can be checked against exact packets. Entity ids don't need anything like it, as the
+// entity id allocator already hands them out in order
+#[derive(Debug, Clone)]
+pub enum UuidSource {
+    Random,
+    Sequential(Arc<AtomicU64>),
+}
+
+impl UuidSource {
+    pub fn sequential() -> UuidSource {
+        UuidSource::Sequential(Arc::new(AtomicU64::new(0)))
+    }
+
+    pub fn next(&self) -> Uuid {
+        match self {
+            UuidSource::Random => Uuid::new_v4(),
+            UuidSource::Sequential(count) => {
+                Uuid::from_u128(count.fetch_add(1, Ordering::Relaxed) as u128 + 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential_uuids_are_shared_between_clones() {
+        let uuids = UuidSource::sequential();
+        let clone = uuids.clone();
+        assert_eq!(uuids.next(), Uuid::from_u128(1));
+        assert_eq!(clone.next(), Uuid::from_u128(2));
+        assert_eq!(uuids.next(), Uuid::from_u128(3));
+    }
+}
@@ -0,0 +1,161 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// Everything we keep between runs is written with a header naming its format and the version of it,
+// so that a later release can still read what an earlier one left behind. Each format has a list
+// of migrations, the nth of which upgrades version n to n + 1 as plain JSON, and its current
+// version is how many there are. Files from before there were headers are version 0
+pub struct Format {
+    pub name: &'static str,
+    pub migrations: &'static [Migration],
+}
+
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+// The world, as the block state's block ids in order
+pub const WORLD: Format = Format {
+    name: "world",
+    migrations: &[add_header],
+};
+
+// A player's saved state, see player_store
+pub const PLAYER: Format = Format {
+    name: "player",
+    migrations: &[add_header, add_dimension],
+};
+
+// A quilt layout, see topology
+pub const TOPOLOGY: Format = Format {
+    name: "topology",
+    migrations: &[add_header, add_seeds],
+};
+
+// What we register with the peer registry. Consul keeps it rather than us, so only the version's
+// written, in the registration's metadata, for nodes to pass over peers newer than they understand
+pub const PEER_REGISTRATION: Format = Format {
+    name: "peer_registration",
+    migrations: &[add_header],
+};
+
+// The first version is what was written before there were headers, with one
+fn add_header(data: Value) -> Result<Value, String> {
+    Ok(data)
+}
+
+// Version 1 didn't say which dimension players were saved in, and they come back in the overworld
+fn add_dimension(mut data: Value) -> Result<Value, String> {
+    data.as_object_mut()
+        .ok_or("A saved player isn't an object")?
+        .insert(String::from("dimension"), json!("overworld"));
+    Ok(data)
+}
+
+// Version 1 didn't say how maps were generated, and they're generated however their owners are
+// configured
+fn add_seeds(mut data: Value) -> Result<Value, String> {
+    data.get_mut("maps")
+        .and_then(Value::as_array_mut)
+        .ok_or("A topology has no maps")?
+        .iter_mut()
+        .filter_map(Value::as_object_mut)
+        .for_each(|map| {
+            map.insert(String::from("seed"), Value::Null);
+        });
+    Ok(data)
+}
+
+impl Format {
+    pub fn version(&self) -> usize {
+        self.migrations.len()
+    }
+
+    pub fn to_string<T: Serialize>(&self, data: &T) -> Result<String, String> {
+        serde_json::to_string(&self.header(data)?).map_err(|e| format!("{:?}", e))
+    }
+
+    pub fn to_string_pretty<T: Serialize>(&self, data: &T) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.header(data)?).map_err(|e| format!("{:?}", e))
+    }
+
+    // Upgraded to the current version first, if it's in an older one
+    pub fn from_str<T: DeserializeOwned>(&self, contents: &str) -> Result<T, String> {
+        let value: Value = serde_json::from_str(contents).map_err(|e| format!("{:?}", e))?;
+        let (version, mut data) = match value {
+            Value::Object(mut header) if header.get("format") == Some(&json!(self.name)) => {
+                let version = header
+                    .get("version")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| format!("The {} header has no version", self.name))?;
+                (
+                    version as usize,
+                    header.remove("data").unwrap_or(Value::Null),
+                )
+            }
+            value => (0, value),
+        };
+        if version > self.version() {
+            return Err(format!(
+                "This is {} version {}, but we only know up to version {}",
+                self.name,
+                version,
+                self.version()
+            ));
+        }
+        for migration in &self.migrations[version..] {
+            data = migration(data)?;
+        }
+        serde_json::from_value(data).map_err(|e| format!("{:?}", e))
+    }
+
+    fn header<T: Serialize>(&self, data: &T) -> Result<Value, String> {
+        Ok(json!({
+            "format": self.name,
+            "version": self.version(),
+            "data": serde_json::to_value(data).map_err(|e| format!("{:?}", e))?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Version 1 had a single name, which version 2 split into first and last
+    fn split_name(mut data: Value) -> Result<Value, String> {
+        let name = data["name"].as_str().ok_or("No name")?.to_string();
+        let (first, last) = name.split_once(' ').ok_or("No last name")?;
+        data["first"] = json!(first);
+        data["last"] = json!(last);
+        Ok(data)
+    }
+
+    const PERSON: Format = Format {
+        name: "person",
+        migrations: &[add_header, split_name],
+    };
+
+    #[derive(Debug, PartialEq, serde::Deserialize, Serialize)]
+    struct Person {
+        first: String,
+        last: String,
+    }
+
+    #[test]
+    fn old_versions_are_migrated_and_newer_ones_refused() {
+        let person = Person {
+            first: String::from("Alex"),
+            last: String::from("Smith"),
+        };
+        let written = PERSON.to_string(&person).unwrap();
+        assert_eq!(PERSON.from_str::<Person>(&written).unwrap(), person);
+
+        let headerless = r#"{"name": "Alex Smith"}"#;
+        assert_eq!(PERSON.from_str::<Person>(headerless).unwrap(), person);
+        let version_1 = r#"{"format": "person", "version": 1, "data": {"name": "Alex Smith"}}"#;
+        assert_eq!(PERSON.from_str::<Person>(version_1).unwrap(), person);
+
+        let version_3 = r#"{"format": "person", "version": 3, "data": {}}"#;
+        assert!(PERSON.from_str::<Person>(version_3).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+// Whether a reading is over a watermark. It's raised once the reading reaches the watermark, and
+// only cleared again once the reading has fallen back to half of it, so that a reading hovering
+// around the line isn't raised and cleared over and over
+#[derive(Debug, Clone, Copy)]
+pub struct Watermark {
+    level: u64,
+    raised: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossing {
+    Raised,
+    Cleared,
+}
+
+impl Watermark {
+    pub fn new(level: u64) -> Watermark {
+        Watermark {
+            level,
+            raised: false,
+        }
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.raised
+    }
+
+    pub fn check(&mut self, reading: u64) -> Option<Crossing> {
+        if !self.raised && reading >= self.level {
+            self.raised = true;
+            return Some(Crossing::Raised);
+        }
+        if self.raised && reading <= self.level / 2 {
+            self.raised = false;
+            return Some(Crossing::Cleared);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readings_around_the_watermark_only_raise_it_once() {
+        let mut watermark = Watermark::new(100);
+        assert_eq!(watermark.check(99), None);
+        assert_eq!(watermark.check(100), Some(Crossing::Raised));
+        assert_eq!(watermark.check(99), None);
+        assert_eq!(watermark.check(120), None);
+        assert_eq!(watermark.check(51), None);
+        assert!(watermark.is_raised());
+        assert_eq!(watermark.check(50), Some(Crossing::Cleared));
+        assert_eq!(watermark.check(99), None);
+    }
+}
@@ -0,0 +1,166 @@
+use super::config::{TickRange, WeatherConfig};
+use super::packet::{ChangeGameState, Packet};
+
+// Change Game State's reasons for the weather. Rain and thunder levels go from 0 to 1, and the
+// client only shows rain above 0.2
+const END_RAINING: u8 = 1;
+const BEGIN_RAINING: u8 = 2;
+const RAIN_LEVEL: u8 = 7;
+const THUNDER_LEVEL: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeatherKind {
+    Clear,
+    Rain,
+    Thunder,
+}
+
+// The weather, and how many more ticks it'll last. Every node works out the next spell from the
+// world's age when the last one ends, so nodes whose clocks agree agree on the weather too
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weather {
+    pub kind: WeatherKind,
+    pub remaining: i64,
+}
+
+impl WeatherKind {
+    pub fn id(self) -> u8 {
+        match self {
+            WeatherKind::Clear => 0,
+            WeatherKind::Rain => 1,
+            WeatherKind::Thunder => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<WeatherKind> {
+        match id {
+            0 => Some(WeatherKind::Clear),
+            1 => Some(WeatherKind::Rain),
+            2 => Some(WeatherKind::Thunder),
+            _ => None,
+        }
+    }
+}
+
+impl Weather {
+    // New worlds start out clear
+    pub fn new(config: &WeatherConfig) -> Weather {
+        Weather {
+            kind: WeatherKind::Clear,
+            remaining: pick(&config.clear, roll(0)),
+        }
+    }
+
+    // Returns whether it's started or stopped raining
+    pub fn tick(&mut self, world_age: i64, config: &WeatherConfig) -> bool {
+        if !config.cycle {
+            let changed = self.kind != WeatherKind::Clear;
+            self.kind = WeatherKind::Clear;
+            return changed;
+        }
+        if self.remaining > 1 {
+            self.remaining -= 1;
+            return false;
+        }
+        let roll = roll(world_age);
+        let (kind, range) = match self.kind {
+            WeatherKind::Clear if roll % 100 < u64::from(config.thunder_percent) => {
+                (WeatherKind::Thunder, &config.thunder)
+            }
+            WeatherKind::Clear => (WeatherKind::Rain, &config.rain),
+            WeatherKind::Rain | WeatherKind::Thunder => (WeatherKind::Clear, &config.clear),
+        };
+        self.kind = kind;
+        self.remaining = pick(range, roll >> 8);
+        true
+    }
+
+    // What to tell players when it starts or stops raining
+    pub fn change_packets(&self) -> Vec<Packet> {
+        let reason = match self.kind {
+            WeatherKind::Clear => END_RAINING,
+            WeatherKind::Rain | WeatherKind::Thunder => BEGIN_RAINING,
+        };
+        let mut packets = vec![change_game_state(reason, 0.0)];
+        packets.extend(self.level_packets());
+        packets
+    }
+
+    // Sent now and then as well, for players who've joined since it started
+    pub fn level_packets(&self) -> Vec<Packet> {
+        let (rain, thunder) = match self.kind {
+            WeatherKind::Clear => (0.0, 0.0),
+            WeatherKind::Rain => (1.0, 0.0),
+            WeatherKind::Thunder => (1.0, 1.0),
+        };
+        vec![
+            change_game_state(RAIN_LEVEL, rain),
+            change_game_state(THUNDER_LEVEL, thunder),
+        ]
+    }
+}
+
+fn change_game_state(reason: u8, value: f32) -> Packet {
+    Packet::ChangeGameState(ChangeGameState { reason, value })
+}
+
+fn pick(range: &TickRange, roll: u64) -> i64 {
+    let span = (range.max - range.min).max(0) as u64 + 1;
+    range.min.max(1) + (roll % span) as i64
+}
+
+// splitmix64, so that the same world age always gives the same weather
+fn roll(world_age: i64) -> u64 {
+    let mut hash = (world_age as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spells_follow_each_other_the_same_way_everywhere() {
+        let config = WeatherConfig {
+            clear: TickRange { min: 3, max: 3 },
+            rain: TickRange { min: 2, max: 2 },
+            thunder_percent: 0,
+            ..WeatherConfig::default()
+        };
+        let mut weather = Weather::new(&config);
+        let mut other = weather;
+        let changes: Vec<_> = (1..=10)
+            .map(|world_age| {
+                other.tick(world_age, &config);
+                weather.tick(world_age, &config).then_some(weather.kind)
+            })
+            .collect();
+        assert_eq!(weather, other);
+        assert_eq!(
+            changes,
+            [
+                None,
+                None,
+                Some(WeatherKind::Rain),
+                None,
+                Some(WeatherKind::Clear),
+                None,
+                None,
+                Some(WeatherKind::Rain),
+                None,
+                Some(WeatherKind::Clear),
+            ]
+        );
+
+        let off = WeatherConfig {
+            cycle: false,
+            ..config
+        };
+        weather.kind = WeatherKind::Thunder;
+        assert!(weather.tick(11, &off));
+        assert_eq!(weather.kind, WeatherKind::Clear);
+        assert!(!weather.tick(12, &off));
+    }
+}
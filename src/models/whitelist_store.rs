@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+use std::fs;
+use uuid::Uuid;
+
+// Players who are let in while the whitelist is on, each by name or by uuid, kept in a JSON list
+#[derive(Debug, Clone, Default)]
+pub struct WhitelistStore {
+    path: String,
+    entries: BTreeSet<String>,
+}
+
+impl WhitelistStore {
+    // A missing file is an empty whitelist, which is written out the first time it's changed
+    pub fn load(path: &str) -> WhitelistStore {
+        let entries = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                error!("Failed to parse whitelist {}: {:?}", path, e);
+                BTreeSet::new()
+            }),
+            Err(_) => BTreeSet::new(),
+        };
+        WhitelistStore {
+            path: String::from(path),
+            entries,
+        }
+    }
+
+    // Names are matched whatever their case, as players can't have two that only differ by it
+    pub fn allows(&self, name: &str, uuid: Uuid) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| match Uuid::parse_str(entry) {
+                Ok(entry) => entry == uuid,
+                Err(_) => entry.eq_ignore_ascii_case(name),
+            })
+    }
+
+    // Returns whether the entry is new
+    pub fn add(&mut self, entry: &str) -> bool {
+        let added = self.entries.insert(normalize(entry));
+        if added {
+            self.save();
+        }
+        added
+    }
+
+    // Returns whether the entry was there to remove
+    pub fn remove(&mut self, entry: &str) -> bool {
+        let removed = self.entries.remove(&normalize(entry));
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.iter().cloned().collect()
+    }
+
+    fn save(&self) {
+        let contents = serde_json::to_string_pretty(&self.entries).unwrap();
+        if let Err(e) = fs::write(&self.path, contents) {
+            error!("Failed to save whitelist {}: {:?}", self.path, e);
+        }
+    }
+}
+
+// Uuids are kept hyphenated and names in lower case, so each player is only ever in there once
+fn normalize(entry: &str) -> String {
+    match Uuid::parse_str(entry) {
+        Ok(uuid) => uuid.to_hyphenated().to_string(),
+        Err(_) => entry.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn players_are_let_in_by_name_or_uuid_and_remembered() {
+        let path = env::temp_dir()
+            .join(format!("patchwork-whitelist-{}.json", process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let uuid = Uuid::from_u128(42);
+        let mut whitelist = WhitelistStore::load(&path);
+        assert!(whitelist.add("Steve"));
+        assert!(!whitelist.add("steve"));
+        assert!(whitelist.add(&uuid.to_simple().to_string()));
+
+        let whitelist = WhitelistStore::load(&path);
+        let _ = fs::remove_file(&path);
+        assert!(whitelist.allows("STEVE", Uuid::nil()));
+        assert!(whitelist.allows("alex", uuid));
+        assert!(!whitelist.allows("alex", Uuid::nil()));
+        assert_eq!(whitelist.entries().len(), 2);
+    }
+}
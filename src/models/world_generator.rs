@@ -0,0 +1,101 @@
+use super::block_registry::block_registry;
+use super::constants::CHUNK_SIZE;
+use super::map::{map_size, map_width};
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+pub const CHUNK_BLOCKS: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+// Fills in one chunk section of our map at a time. Chunk coordinates are relative to the map, and
+// the block ids are returned y first, then z, then x, like they're sent to clients
+pub trait WorldGenerator: Debug + Send + Sync {
+    fn generate(&self, chunk_x: i32, chunk_z: i32) -> Vec<i32>;
+}
+
+pub const DEFAULT_GENERATOR: &str = "checkerboard";
+
+// The generators that ship with patchwork, registered with the block state at startup like any
+// other
+pub fn builtin_generators() -> HashMap<String, Box<dyn WorldGenerator>> {
+    let mut generators = HashMap::<String, Box<dyn WorldGenerator>>::new();
+    generators.insert(String::from(DEFAULT_GENERATOR), Box::new(Checkerboard));
+    generators.insert(String::from("flat"), Box::new(Flat));
+    generators.insert(String::from("empty"), Box::new(Empty));
+    generators
+}
+
+// A checkerboard floor the height of the chunk, walled off at the edges of the map
+#[derive(Debug)]
+pub struct Checkerboard;
+
+impl WorldGenerator for Checkerboard {
+    fn generate(&self, chunk_x: i32, chunk_z: i32) -> Vec<i32> {
+        let edge = map_width() - 1;
+        let wall = block("birch_leaves", &[("distance", "5"), ("persistent", "true")]);
+        let light = block("stripped_jungle_log", &[("axis", "y")]);
+        let dark = block("stripped_dark_oak_log", &[("axis", "y")]);
+        (0..CHUNK_BLOCKS as i32)
+            .map(|index| {
+                let x = chunk_x * CHUNK_SIZE + index % CHUNK_SIZE;
+                let z = chunk_z * CHUNK_SIZE + (index / CHUNK_SIZE) % CHUNK_SIZE;
+                if x == 0 || x == edge || z == 0 || z == edge {
+                    wall
+                } else if (x + z) % 2 == 0 {
+                    light
+                } else {
+                    dark
+                }
+            })
+            .collect()
+    }
+}
+
+// Bedrock, two layers of dirt and grass on top
+#[derive(Debug)]
+pub struct Flat;
+
+impl WorldGenerator for Flat {
+    fn generate(&self, _chunk_x: i32, _chunk_z: i32) -> Vec<i32> {
+        let bedrock = block("bedrock", &[]);
+        let dirt = block("dirt", &[]);
+        let grass = block("grass_block", &[("snowy", "false")]);
+        (0..CHUNK_BLOCKS as i32)
+            .map(|index| match index / (CHUNK_SIZE * CHUNK_SIZE) {
+                0 => bedrock,
+                1 | 2 => dirt,
+                3 => grass,
+                _ => 0,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Empty;
+
+impl WorldGenerator for Empty {
+    fn generate(&self, _chunk_x: i32, _chunk_z: i32) -> Vec<i32> {
+        vec![0; CHUNK_BLOCKS]
+    }
+}
+
+// Chunk coordinates of every chunk in our map, in the order their blocks are stored
+pub fn map_chunks() -> Vec<(i32, i32)> {
+    (0..map_size() * map_size())
+        .map(|chunk| (chunk % map_size(), chunk / map_size()))
+        .collect()
+}
+
+// Air if the registry doesn't know the block, so a missing report leaves holes rather than garbage
+fn block(name: &str, properties: &[(&str, &str)]) -> i32 {
+    block_registry()
+        .state_id(name, properties)
+        .unwrap_or_else(|| {
+            warn!(
+                "Block {} {:?} isn't in the block registry",
+                name, properties
+            );
+            0
+        })
+}
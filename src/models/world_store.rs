@@ -0,0 +1,18 @@
+use super::versioned;
+
+use std::fs;
+
+// The map's blocks are saved when the server shuts down and loaded back when it starts, as one JSON
+// array of block ids in the order the block state keeps them
+pub fn save(path: &str, block_ids: &[i32]) -> Result<(), String> {
+    let contents = versioned::WORLD.to_string(&block_ids)?;
+    fs::write(path, contents).map_err(|e| format!("{:?}", e))
+}
+
+pub fn load(path: &str) -> Option<Vec<i32>> {
+    let contents = fs::read_to_string(path).ok()?;
+    versioned::WORLD
+        .from_str(&contents)
+        .map_err(|e| error!("Failed to parse saved world {:?}: {}", path, e))
+        .ok()
+}
@@ -0,0 +1,422 @@
+use super::admin_api;
+use super::config::Config;
+use super::constants::SERVER_PROTOCOL;
+use super::error::OrLog;
+use super::interfaces;
+use super::interfaces::block::BlockState;
+use super::interfaces::patchwork::PatchworkState;
+use super::message_log;
+use super::metrics;
+use super::models;
+use super::models::identity::Identity;
+use super::models::map::Peer;
+use super::models::packet::Packet;
+use super::models::uuid_source::UuidSource;
+use super::query;
+use super::server;
+use super::services;
+use super::services::instance::{self, ServiceInstance};
+use super::shutdown;
+use super::stress;
+
+use std::sync::mpsc::Sender;
+use std::thread::JoinHandle;
+
+// Sets what every node in the process shares: the map size, spawn and registries, which have to match
+// across the whole quilt anyway, and how peers are reached
+pub fn configure(config: &Config) {
+    models::map::set_map_size(config.map_size);
+    interfaces::player::set_spawn(config.spawn);
+    models::block_registry::set_block_registry(models::block_registry::BlockRegistry::load(
+        &config.registry_directory,
+        SERVER_PROTOCOL,
+    ));
+    models::item_registry::set_item_registry(models::item_registry::ItemRegistry::load(
+        &config.registry_directory,
+        SERVER_PROTOCOL,
+    ));
+    models::protocol_adapter::load_registries(&config.registry_directory);
+    if let Some(address) = config.outbound_bind_address {
+        server::set_outbound_bind_address(address);
+    }
+    if let Some(proxy) = config.peer_proxy.clone() {
+        server::set_peer_proxy(proxy);
+    }
+    services::instance::set_pinned_threads(config.pinned_threads.clone());
+}
+
+// A running patchwork server, listening on its local peer's port
+pub struct Node {
+    pub messenger: Sender<interfaces::messenger::Operations>,
+    pub player_state: Sender<interfaces::player::Operations>,
+    pub block_state: Sender<interfaces::block::Operations>,
+    pub patchwork_state: Sender<interfaces::patchwork::Operations>,
+    pub command_service: Sender<interfaces::command::Operations>,
+    config: Config,
+    port: u16,
+    listener: JoinHandle<()>,
+}
+
+// Starts every service, loads the saved world and lays out the quilt, either from the topology file
+// or with the peer's map next to ours. Several nodes can run in one process, as long as they're
+// configured to save to different files
+pub fn start(config: Config, local_peer: Peer, peer: Option<Peer>) -> Node {
+    start_services(config, local_peer, peer, None, UuidSource::Random)
+}
+
+// Feeds a patchwork service message log, recorded with message_log_directory set, back to a fresh
+// patchwork service and describes what it did with each message
+pub fn replay_patchwork(
+    config: Config,
+    local_peer: Peer,
+    path: &str,
+) -> Result<Vec<String>, String> {
+    let messages = message_log::load(path)?;
+    Ok(services::patchwork::replay(config, local_peer, messages))
+}
+
+fn start_services(
+    config: Config,
+    local_peer: Peer,
+    peer: Option<Peer>,
+    test_sender: Option<Sender<(i32, Packet)>>,
+    uuids: UuidSource,
+) -> Node {
+    let config = if config.offline {
+        offline(config)
+    } else {
+        config
+    };
+    // Our map's generated however the topology says it was where it was exported from, so the quilt
+    // comes out the same
+    let topology = config
+        .topology_file
+        .as_ref()
+        .map(|path| models::topology::Topology::load(path).unwrap_or_else(|e| panic!("{}", e)));
+    let config = match topology
+        .as_ref()
+        .and_then(|topology| topology.seed(&local_peer))
+    {
+        Some(seed) => seed.apply(config),
+        None => config,
+    };
+    let port = local_peer.port;
+    let instance = Identity::load(
+        &config.instance_id_file,
+        config.instance_name.clone(),
+        &local_peer,
+    );
+    info!("Running as instance {}", instance);
+    let tracking_ranges = config.tracking_ranges;
+    let rate_limits = config.rate_limits;
+    let forwarding = config.forwarding.clone();
+    let weather = config.weather;
+    let peer_quotas = config.peer_quotas;
+    let peer_link_watermarks = config.peer_link_watermarks;
+    define_services!(
+        (
+            module: services::player::start,
+            name: player_state,
+            dependencies: [messenger, entity_ids, interest, entity_state, chat],
+            extras: [config],
+            restart: on_panic
+        ),
+        (
+            module: services::block::start,
+            name: block_state,
+            dependencies: [messenger],
+            extras: [config]
+        ),
+        (
+            module: services::patchwork::start,
+            name: patchwork_state,
+            dependencies: [messenger, inbound_packet_processor, player_state, entity_state, command_service, block_state, peer_auth, chat],
+            extras: [local_peer, config, uuids]
+        ),
+        (
+            module: services::messenger::start,
+            name: messenger,
+            dependencies: [inbound_packet_processor],
+            restart: on_panic
+        ),
+        (
+            module: services::packet_processor::start_inbound,
+            name: inbound_packet_processor,
+            dependencies: [messenger, player_state, block_state, patchwork_state, entity_state, game_rules, peer_auth, keep_alive, whitelist, bans, world_time],
+            extras: [test_sender, uuids, instance, rate_limits, forwarding, peer_quotas, peer_link_watermarks]
+        ),
+        (
+            module: services::connection::start,
+            name: connection_service,
+            dependencies: [messenger, player_state, patchwork_state, inbound_packet_processor]
+        ),
+        (
+            module: services::chat::start,
+            name: chat,
+            dependencies: [messenger],
+            extras: [local_peer, config]
+        ),
+        (
+            module: services::keep_alive::start,
+            name: keep_alive,
+            dependencies: [messenger],
+            extras: [config]
+        ),
+        (
+            module: services::entity::start,
+            name: entity_state,
+            dependencies: [messenger, entity_ids, interest, player_state, patchwork_state]
+        ),
+        (
+            module: services::interest::start,
+            name: interest,
+            dependencies: [messenger],
+            extras: [tracking_ranges]
+        ),
+        (
+            module: services::command::start,
+            name: command_service,
+            dependencies: [messenger, patchwork_state, game_rules, block_state, hud, player_state, whitelist, bans, chat, inbound_packet_processor],
+            extras: [config]
+        ),
+        (
+            module: services::peer_heartbeat::start,
+            name: peer_heartbeat,
+            dependencies: [patchwork_state]
+        ),
+        (
+            module: services::game_rules::start,
+            name: game_rules,
+            dependencies: [messenger]
+        ),
+        (
+            module: services::world_time::start,
+            name: world_time,
+            dependencies: [messenger, game_rules],
+            extras: [weather]
+        ),
+        (
+            module: services::hud::start,
+            name: hud,
+            dependencies: [messenger, player_state, patchwork_state, inbound_packet_processor]
+        ),
+        (
+            module: services::gossip::start,
+            name: gossip,
+            dependencies: [patchwork_state]
+        ),
+        (
+            module: services::peer_registry::start,
+            name: peer_registry,
+            dependencies: [patchwork_state],
+            extras: [config, local_peer]
+        ),
+        (
+            module: services::config_reload::start,
+            name: config_reload,
+            dependencies: [patchwork_state],
+            extras: [config]
+        ),
+        (
+            module: services::peer_auth::start,
+            name: peer_auth,
+            dependencies: [],
+            extras: [config, instance]
+        ),
+        (
+            module: services::bans::start,
+            name: bans,
+            dependencies: [messenger],
+            extras: [config]
+        ),
+        (
+            module: services::whitelist::start,
+            name: whitelist,
+            dependencies: [],
+            extras: [config]
+        ),
+        (
+            module: services::load_monitor::start,
+            name: load_monitor,
+            dependencies: [patchwork_state]
+        ),
+        (
+            module: services::entity_ids::start,
+            name: entity_ids,
+            dependencies: []
+        )
+    );
+
+    trace!("Services Started");
+
+    if config.local_map {
+        if let Some(block_ids) = models::world_store::load(&config.world_file) {
+            info!("Loading world from {:?}", config.world_file);
+            block_state
+                .sender()
+                .load(config.dimension, block_ids)
+                .or_log();
+        }
+        models::world_generator::builtin_generators()
+            .into_iter()
+            .for_each(|(name, generator)| {
+                block_state
+                    .sender()
+                    .register_generator(name, generator)
+                    .or_log()
+            });
+    }
+
+    match (topology, peer) {
+        _ if config.offline => info!("Running offline, on our own map alone"),
+        (Some(topology), _) => patchwork_state.sender().import_topology(topology).or_log(),
+        (None, Some(peer)) => patchwork_state.sender().new_map(peer).or_log(),
+        (None, None) if !config.peers.is_empty() => {}
+        (None, None) => panic!("Either a topology file or a peer is needed to lay out the quilt"),
+    }
+    config
+        .peers
+        .iter()
+        .for_each(|peer| patchwork_state.sender().new_map(peer.clone()).or_log());
+
+    if let Some(metrics_port) = config.metrics_port {
+        metrics::serve(metrics_port);
+    }
+    if let Some(query_port) = config.query_port {
+        query::serve(
+            query_port,
+            local_peer.clone(),
+            player_state.sender(),
+            patchwork_state.sender(),
+        );
+    }
+    if let Some(admin_port) = config.admin_port {
+        admin_api::serve(
+            admin_port,
+            player_state.sender(),
+            patchwork_state.sender(),
+            messenger.sender(),
+            peer_auth.sender(),
+        );
+    }
+
+    let inbound_packet_processor_sender = inbound_packet_processor.sender();
+    let connection_service_sender = connection_service.sender();
+    let messenger_sender = messenger.sender();
+    let connection_limits = config.connection_limits.clone();
+    let listener = instance::spawn(&format!("listener-{}", port), move || {
+        if let Err(e) = server::listen(
+            port,
+            inbound_packet_processor_sender,
+            connection_service_sender,
+            messenger_sender,
+            uuids,
+            connection_limits,
+        ) {
+            error!("Stopped listening on port {:?}: {}", port, e);
+        }
+    });
+
+    if let (Some(stress), true) = (config.stress, config.local_map) {
+        stress::start(stress, local_peer.clone(), entity_state.sender());
+    }
+
+    Node {
+        messenger: messenger.sender(),
+        player_state: player_state.sender(),
+        block_state: block_state.sender(),
+        patchwork_state: patchwork_state.sender(),
+        command_service: command_service.sender(),
+        config,
+        port,
+        listener,
+    }
+}
+
+// Offline nodes have a map of their own and nothing else, so anything that would bring peers into
+// it is turned off
+fn offline(config: Config) -> Config {
+    if !config.local_map {
+        panic!("Offline nodes need a map of their own, local_map can't be turned off");
+    }
+    if config.topology_file.is_some()
+        || !config.peers.is_empty()
+        || config.peer_registry.is_some()
+        || config.split.is_some()
+    {
+        warn!("Ignoring the topology file, peers, peer registry and split settings while offline");
+    }
+    Config {
+        topology_file: None,
+        peers: Vec::new(),
+        peer_registry: None,
+        split: None,
+        ..config
+    }
+}
+
+impl Node {
+    // Saves players and the world without stopping anything, as shutting down would
+    pub fn save(&self) {
+        shutdown::save(
+            self.player_state.clone(),
+            self.block_state.clone(),
+            &self.config,
+        );
+    }
+
+    // Saves everything and disconnects everyone, then waits for the listener to stop
+    pub fn shut_down(self) {
+        shutdown::shut_down(
+            self.port,
+            self.messenger,
+            self.player_state,
+            self.block_state,
+            self.patchwork_state,
+            &self.config,
+        );
+        let _ = self.listener.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config, flight_recorder};
+    use std::env;
+    use tracing::level_filters::LevelFilter;
+
+    fn start_trace() {
+        flight_recorder::init(LevelFilter::TRACE).unwrap();
+    }
+
+    #[test]
+    fn test() {
+        start_trace();
+
+        // Since servers handle connection in their own thread, create a channel
+        // to retrieve information
+        let (router_sender, router_receiver) = std::sync::mpsc::channel();
+        let config = config::load();
+        configure(&config);
+        let local_peer = Peer {
+            port: env::var("PORT").unwrap().parse::<u16>().unwrap(),
+            address: config.advertise_address.clone(),
+        };
+        let peer = env::var("PEER_PORT").ok().map(|port| Peer {
+            port: port.parse::<u16>().unwrap(),
+            address: String::from("127.0.0.1"),
+        });
+        let _node = start_services(
+            config,
+            local_peer,
+            peer,
+            Some(router_sender),
+            UuidSource::sequential(),
+        );
+
+        while let Ok((state, packet)) = router_receiver.recv() {
+            trace!("==[Received]== {:?}, {:?}", state, packet);
+        }
+    }
+}
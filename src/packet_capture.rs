@@ -0,0 +1,144 @@
+use super::models::packet::Packet;
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+// Every packet read from or written to any connection, one JSON object per line, so that what two
+// peers said to each other can be diffed after the fact. Packets we can decode are written out
+// with their fields, and the rest as the bytes we got. Turned on and off with /capture, and like
+// the flight recorder it's shared by every node in the process
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    timestamp_ms: u64,
+    conn_id: String,
+    direction: Direction,
+    packet: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hex: Option<String>,
+}
+
+// Starts a new capture, ending whichever one was already going
+pub fn start(path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    *CAPTURE.lock().unwrap() = Some(LineWriter::new(file));
+    CAPTURING.store(true, Ordering::Release);
+    Ok(())
+}
+
+// Whether there was a capture to stop
+pub fn stop() -> bool {
+    CAPTURING.store(false, Ordering::Release);
+    match CAPTURE.lock().unwrap().take() {
+        Some(mut capture) => {
+            let _ = capture.flush();
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn packet(conn_id: Uuid, direction: Direction, packet: &Packet) {
+    if CAPTURING.load(Ordering::Acquire) {
+        write(&entry(conn_id, direction, packet));
+    }
+}
+
+// Bytes that didn't make a packet we could read
+pub fn malformed(conn_id: Uuid, bytes: &[u8]) {
+    if CAPTURING.load(Ordering::Acquire) {
+        write(&Entry {
+            hex: Some(hex::encode(bytes)),
+            ..new_entry(conn_id, Direction::Inbound, "Malformed")
+        });
+    }
+}
+
+fn new_entry(conn_id: Uuid, direction: Direction, packet: &'static str) -> Entry {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Entry {
+        timestamp_ms: timestamp.as_millis() as u64,
+        conn_id: conn_id.to_string(),
+        direction,
+        packet,
+        id: None,
+        fields: None,
+        hex: None,
+    }
+}
+
+fn entry(conn_id: Uuid, direction: Direction, packet: &Packet) -> Entry {
+    match packet {
+        Packet::Unknown(unknown) => Entry {
+            id: Some(unknown.id),
+            hex: Some(hex::encode(&unknown.data)),
+            ..new_entry(conn_id, direction, packet.name())
+        },
+        _ => Entry {
+            fields: Some(format!("{:?}", packet)),
+            ..new_entry(conn_id, direction, packet.name())
+        },
+    }
+}
+
+fn write(entry: &Entry) {
+    let line = match serde_json::to_string(entry) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Failed to capture {} packet: {}", entry.packet, e);
+            return;
+        }
+    };
+    if let Some(capture) = CAPTURE.lock().unwrap().as_mut() {
+        if let Err(e) = writeln!(capture, "{}", line) {
+            warn!("Failed to capture {} packet: {}", entry.packet, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::packet::{KeepAlive, Unknown};
+
+    #[test]
+    fn unknown_packets_are_captured_as_bytes() {
+        let conn_id = Uuid::new_v4();
+        let unknown = Packet::Unknown(Unknown {
+            id: 0x2a,
+            data: vec![0xde, 0xad],
+        });
+        let captured = serde_json::to_value(entry(conn_id, Direction::Inbound, &unknown)).unwrap();
+        assert_eq!(captured["direction"], "inbound");
+        assert_eq!(captured["conn_id"], conn_id.to_string());
+        assert_eq!(captured["id"], 0x2a);
+        assert_eq!(captured["hex"], "dead");
+        assert!(captured.get("fields").is_none());
+
+        let known = Packet::KeepAlive(KeepAlive { id: 7 });
+        let captured = serde_json::to_value(entry(conn_id, Direction::Outbound, &known)).unwrap();
+        assert_eq!(captured["packet"], "KeepAlive");
+        assert_eq!(captured["fields"], "KeepAlive(KeepAlive { id: 7 })");
+        assert!(captured.get("hex").is_none());
+    }
+}
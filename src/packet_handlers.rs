@@ -4,14 +4,25 @@
 // Example: the gameplay router provides the route_packet method which handles packets from clients
 // in the play state
 
+pub mod connection_updates;
 pub mod gameplay_router;
 pub mod initiation_protocols;
 pub mod packet_router;
 pub mod peer_subscription;
 
+use super::config;
 use super::constants;
+use super::error;
+use super::models::ban_store;
+use super::models::forwarding;
+use super::models::identity;
+use super::models::map;
 use super::models::minecraft_types;
 use super::models::packet;
+use super::models::protocol_adapter;
+use super::models::topology;
 use super::models::translation;
+use super::models::uuid_source;
+use super::models::weather;
 
 use super::interfaces;
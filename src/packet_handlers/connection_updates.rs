@@ -0,0 +1,27 @@
+use super::forwarding::ForwardedPlayer;
+use super::identity::Identity;
+use super::interfaces::messenger::{ConnectionClass, SubscriberType};
+use super::protocol_adapter::ProtocolAdapter;
+use super::translation::TranslationUpdates;
+
+// A change that handling a packet makes to the connection it came in on. Handlers return a list of
+// them, and the packet processor applies the whole list before it reads the connection's next
+// packet, so handlers don't need to reach into the messenger or the translation data themselves
+#[derive(Debug)]
+pub enum ConnectionUpdate {
+    State(i32),
+    // Which version's packets the client speaks, set by the handshake
+    Protocol(ProtocolAdapter),
+    // What the connection turned out to be, set by the handshake
+    Class(ConnectionClass),
+    Translation(TranslationUpdates),
+    Subscribe(SubscriberType),
+    // Which instance a peer is, once it's told us
+    Identity(Identity),
+    // Who's really connecting, as told by the proxy they came through
+    Forwarded(ForwardedPlayer),
+    // Closes the connection without a word, for peers. Anything else in the list is dropped
+    Close,
+    // Disconnects a client, telling them why. Anything else in the list is dropped
+    Kick(String),
+}
@@ -1,47 +1,181 @@
+use super::error::{OrLog, PatchworkError};
+use super::interfaces::command::CommandService;
 use super::interfaces::player::{Angle, PlayerState, Position};
 use super::packet::Packet;
 use uuid::Uuid;
 
-pub fn route_packet<P: PlayerState>(p: Packet, conn_id: Uuid, player_state: P) {
+// The slot of a click outside the inventory window
+pub const OUTSIDE_WINDOW: i16 = -1;
+
+// What PlayerDigging's status is when the player drops what they're holding, rather than digging
+pub const DROP_ITEM_STACK: i32 = 3;
+pub const DROP_ITEM: i32 = 4;
+
+// Packets that aren't meant for gameplay are handed back as an error, and otherwise dropped
+pub fn route_packet<P: PlayerState, C: CommandService>(
+    p: Packet,
+    conn_id: Uuid,
+    player_state: P,
+    command_service: C,
+) -> Result<(), PatchworkError> {
     match p {
         Packet::PlayerPosition(player_position) => {
-            player_state.move_and_look(
-                conn_id,
-                Some(Position {
-                    x: player_position.x,
-                    y: player_position.feet_y,
-                    z: player_position.z,
-                }),
-                None,
-            );
+            player_state
+                .move_and_look(
+                    conn_id,
+                    Some(Position {
+                        x: player_position.x,
+                        y: player_position.feet_y,
+                        z: player_position.z,
+                    }),
+                    None,
+                )
+                .or_log();
         }
         Packet::PlayerPositionAndLook(player_position_and_look) => {
-            player_state.move_and_look(
-                conn_id,
-                Some(Position {
-                    x: player_position_and_look.x,
-                    y: player_position_and_look.feet_y,
-                    z: player_position_and_look.z,
-                }),
-                Some(Angle {
-                    yaw: player_position_and_look.yaw,
-                    pitch: player_position_and_look.pitch,
-                }),
-            );
+            player_state
+                .move_and_look(
+                    conn_id,
+                    Some(Position {
+                        x: player_position_and_look.x,
+                        y: player_position_and_look.feet_y,
+                        z: player_position_and_look.z,
+                    }),
+                    Some(Angle {
+                        yaw: player_position_and_look.yaw,
+                        pitch: player_position_and_look.pitch,
+                    }),
+                )
+                .or_log();
         }
         Packet::PlayerLook(player_look) => {
-            player_state.move_and_look(
-                conn_id,
-                None,
-                Some(Angle {
-                    yaw: player_look.yaw,
-                    pitch: player_look.pitch,
-                }),
-            );
-        }
-        Packet::Unknown => (),
+            player_state
+                .move_and_look(
+                    conn_id,
+                    None,
+                    Some(Angle {
+                        yaw: player_look.yaw,
+                        pitch: player_look.pitch,
+                    }),
+                )
+                .or_log();
+        }
+        Packet::HeldItemChange(held_item_change) => {
+            player_state
+                .hold_item(conn_id, held_item_change.slot)
+                .or_log();
+        }
+        // Items dragged out of the creative inventory window are thrown
+        Packet::CreativeInventoryAction(creative_inventory_action) => match (
+            creative_inventory_action.slot,
+            creative_inventory_action.clicked_item,
+        ) {
+            (OUTSIDE_WINDOW, Some(item)) => player_state.throw_item(conn_id, item),
+            (slot, item) => player_state.set_slot(conn_id, slot, item),
+        }
+        .or_log(),
+        Packet::PlayerDigging(player_digging) => match player_digging.status {
+            DROP_ITEM_STACK => player_state.drop_item(conn_id, true, true).or_log(),
+            DROP_ITEM => player_state.drop_item(conn_id, false, true).or_log(),
+            _ => (),
+        },
+        Packet::ChatMessage(chat_message) => {
+            if chat_message.message.starts_with('/') {
+                command_service
+                    .execute(conn_id, chat_message.message)
+                    .or_log();
+            }
+        }
+        // Respawning is seen to by patchwork state before packets get here, and there aren't any
+        // statistics to ask for
+        Packet::ClientStatus(_) => (),
+        // Already seen to by the keep-alive service
+        Packet::ServerboundKeepAlive(_) => (),
+        Packet::Unknown(_) => (),
         _ => {
-            panic!("Gameplay router received unexpected packet {:?}", p);
+            return Err(PatchworkError::UnexpectedPacket {
+                conn_id,
+                packet: p.name(),
+            })
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::command::Operations as CommandOperations;
+    use crate::interfaces::player::Operations as PlayerOperations;
+    use crate::interfaces::{MockCommandService, MockPlayerState};
+    use crate::models::minecraft_types::Location;
+    use crate::models::packet::{ChatMessage, KeepAlive, PlayerDigging};
+
+    #[test]
+    fn only_commands_are_run_and_dropping_items_tells_player_state_how_many() {
+        let player_state = MockPlayerState::new();
+        let command_service = MockCommandService::new();
+        let conn_id = Uuid::new_v4();
+        let route = |packet| {
+            route_packet(
+                packet,
+                conn_id,
+                player_state.clone(),
+                command_service.clone(),
+            )
+            .unwrap()
+        };
+
+        route(Packet::ChatMessage(ChatMessage {
+            message: String::from("hello"),
+        }));
+        route(Packet::ChatMessage(ChatMessage {
+            message: String::from("/tp 0 0"),
+        }));
+        [DROP_ITEM, DROP_ITEM_STACK].iter().for_each(|status| {
+            route(Packet::PlayerDigging(PlayerDigging {
+                status: *status,
+                location: Location { x: 0, y: 0, z: 0 },
+                face: 0,
+            }))
+        });
+
+        match &command_service.take()[..] {
+            [CommandOperations::Execute(msg)] => assert_eq!(msg.command, "/tp 0 0"),
+            sent => panic!("Expected one command, got {:?}", sent),
         }
+        let drops: Vec<bool> = player_state
+            .take()
+            .into_iter()
+            .map(|msg| match msg {
+                PlayerOperations::DropItem(msg) => msg.whole_stack,
+                msg => panic!("Expected a drop, got {:?}", msg),
+            })
+            .collect();
+        assert_eq!(drops, vec![false, true]);
+    }
+
+    #[test]
+    fn packets_that_are_not_for_gameplay_are_dropped_with_an_error() {
+        let player_state = MockPlayerState::new();
+        let command_service = MockCommandService::new();
+        let conn_id = Uuid::new_v4();
+
+        let routed = route_packet(
+            Packet::KeepAlive(KeepAlive { id: 1 }),
+            conn_id,
+            player_state.clone(),
+            command_service.clone(),
+        );
+
+        assert!(matches!(
+            routed,
+            Err(PatchworkError::UnexpectedPacket {
+                packet: "KeepAlive",
+                ..
+            })
+        ));
+        assert!(player_state.take().is_empty());
+        assert!(command_service.take().is_empty());
     }
 }
@@ -9,9 +9,17 @@ pub mod border_cross_login;
 pub mod client_ping;
 pub mod handshake;
 pub mod login;
+pub mod peer_auth;
 
+use super::config;
+use super::connection_updates;
 use super::constants;
+use super::error;
+use super::forwarding;
+use super::identity;
 use super::interfaces;
+use super::map;
 use super::minecraft_types;
 use super::packet;
-use super::translation;
+use super::protocol_adapter;
+use super::uuid_source;
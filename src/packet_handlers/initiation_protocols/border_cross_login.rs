@@ -1,21 +1,27 @@
-use super::interfaces::player::{Angle, Player, PlayerState, Position};
+use super::connection_updates::ConnectionUpdate;
+use super::constants::ANCHORED_PLAYER_ENTITY_ID_START;
+use super::error::OrLog;
+use super::interfaces::player::{
+    Angle, Experience, Health, Player, PlayerState, Position, Velocity, PLAYER_INVENTORY_SLOTS,
+};
+use super::map::Dimension;
 use super::packet::Packet;
-use super::translation::TranslationUpdates;
 use uuid::Uuid;
 
 pub fn border_cross_login<P: PlayerState>(
     p: Packet,
     conn_id: Uuid,
     player_state: P,
-) -> TranslationUpdates {
+) -> Vec<ConnectionUpdate> {
     match p {
         Packet::BorderCrossLogin(packet) => {
+            // The player picks up exactly where they crossed, already translated into our map
             let player = Player {
                 conn_id,
-                uuid: Uuid::new_v4(),
+                uuid: Uuid::from_u128(packet.uuid),
                 name: packet.username,
                 //Hardcoded to assume that 950-1000 is the range used for this peer's anchors
-                entity_id: 950 + packet.entity_id,
+                entity_id: ANCHORED_PLAYER_ENTITY_ID_START + packet.entity_id,
                 position: Position {
                     x: packet.x,
                     y: packet.feet_y,
@@ -25,12 +31,164 @@ pub fn border_cross_login<P: PlayerState>(
                     pitch: packet.pitch,
                     yaw: packet.yaw,
                 },
+                velocity: Velocity {
+                    x: packet.velocity_x,
+                    y: packet.velocity_y,
+                    z: packet.velocity_z,
+                },
+                inventory: serde_json::from_str(&packet.inventory).unwrap_or_else(|e| {
+                    warn!("Failed to parse inventory of crossing player: {:?}", e);
+                    vec![None; PLAYER_INVENTORY_SLOTS]
+                }),
+                held_item_slot: packet.held_item_slot,
+                health: Health {
+                    health: packet.health,
+                    food: packet.food,
+                    food_saturation: packet.food_saturation,
+                },
+                experience: Experience {
+                    bar: packet.experience_bar,
+                    level: packet.level,
+                    total: packet.total_experience,
+                },
+                dimension: Dimension::default(),
             };
 
             //update the gamestate with this new player
-            player_state.new_player(conn_id, player);
-            TranslationUpdates::State(3)
+            player_state.new_player(conn_id, player).or_log();
+            vec![ConnectionUpdate::State(3)]
         }
-        _ => TranslationUpdates::NoChange,
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::interfaces::player::Operations;
+    use super::super::packet::{self, translate_outgoing};
+    use super::*;
+    use crate::models::map::{self, Map};
+    use crate::models::minecraft_protocol::MinecraftProtocolReader;
+    use crate::models::minecraft_types::ItemStack;
+    use crate::models::translation::TranslationInfo;
+    use std::io::Cursor;
+    use std::sync::mpsc::channel;
+
+    // Sends the player over an anchor to the peer whose map sits at map_position, the way
+    // patchwork state does, and returns them as the peer sees them
+    fn cross(player: &Player, map_position: map::Position) -> Player {
+        let translation_info = TranslationInfo {
+            state: 4,
+            map: Map::new(map_position, 0),
+        };
+        let mut bytes = Vec::new();
+        packet::write(
+            &mut bytes,
+            translate_outgoing(
+                Packet::BorderCrossLogin(player.border_cross_login()),
+                translation_info,
+            ),
+        );
+        let mut cursor = Cursor::new(bytes);
+        cursor.read_var_int().unwrap();
+        let received = packet::read(&mut cursor, 4).unwrap();
+
+        let (sender, receiver) = channel();
+        border_cross_login(received, Uuid::new_v4(), sender);
+        match receiver.try_recv() {
+            Ok(Operations::New(msg)) => msg.player,
+            _ => panic!("Crossing the border didn't create a player"),
+        }
+    }
+
+    fn walk(player: &mut Player, x: f64, z: f64) {
+        player.move_and_look(
+            Some(Position {
+                x,
+                y: player.position.y,
+                z,
+            }),
+            Some(Angle {
+                pitch: 10.0,
+                yaw: 90.0,
+            }),
+        );
+    }
+
+    #[test]
+    fn players_cross_back_and_forth_where_they_stepped_over() {
+        let map_width = map::map_width() as f64;
+        let mut player = Player {
+            conn_id: Uuid::new_v4(),
+            uuid: Uuid::new_v4(),
+            name: String::from("walker"),
+            entity_id: 7,
+            position: Position {
+                x: map_width - 0.5,
+                y: 16.0,
+                z: 3.0,
+            },
+            angle: Angle {
+                pitch: 0.0,
+                yaw: 0.0,
+            },
+            velocity: Velocity::default(),
+            inventory: vec![None; PLAYER_INVENTORY_SLOTS],
+            held_item_slot: 0,
+            health: Health::default(),
+            experience: Experience::default(),
+            dimension: Dimension::Overworld,
+        };
+        player.inventory[36] = Some(ItemStack {
+            item_id: 1,
+            count: 64,
+            nbt: Some(vec![
+                10, 0, 0, 3, 0, 6, b'D', b'a', b'm', b'a', b'g', b'e', 0, 0, 0, 5, 0,
+            ]),
+        });
+        player.held_item_slot = 4;
+        player.health.health = 13.5;
+        player.experience = Experience {
+            bar: 0.25,
+            level: 3,
+            total: 40,
+        };
+
+        // Over the east border onto the peer's map, which starts where ours ends
+        walk(&mut player, map_width + 0.25, 3.5);
+        let mut remote = cross(
+            &player,
+            map::Position {
+                x: 1,
+                ..map::Position::default()
+            },
+        );
+        assert_eq!(remote.uuid, player.uuid);
+        assert_eq!(remote.name, player.name);
+        assert_eq!(remote.entity_id, ANCHORED_PLAYER_ENTITY_ID_START + 7);
+        assert_eq!((remote.position.x, remote.position.z), (0.25, 3.5));
+        assert_eq!(remote.position.y, 16.0);
+        assert_eq!((remote.angle.pitch, remote.angle.yaw), (10.0, 90.0));
+        assert_eq!(remote.velocity, player.velocity);
+        assert_eq!((remote.velocity.x, remote.velocity.z), (0.75, 0.5));
+        assert_eq!(remote.inventory, player.inventory);
+        assert_eq!(remote.held_item_slot, 4);
+        assert_eq!(remote.health, player.health);
+        assert_eq!(remote.experience, player.experience);
+
+        // And back over the same border from the peer's side, where our map is to the west
+        walk(&mut remote, -0.5, 3.0);
+        let local = cross(
+            &remote,
+            map::Position {
+                x: -1,
+                ..map::Position::default()
+            },
+        );
+        assert_eq!(local.uuid, player.uuid);
+        assert_eq!((local.position.x, local.position.z), (map_width - 0.5, 3.0));
+        assert_eq!((local.velocity.x, local.velocity.z), (-0.75, -0.5));
+        assert_eq!(local.inventory, player.inventory);
+        assert_eq!(local.health, player.health);
     }
 }
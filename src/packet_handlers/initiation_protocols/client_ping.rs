@@ -1,10 +1,11 @@
+use super::connection_updates::ConnectionUpdate;
 use super::constants::{SERVER_DESCRIPTION, SERVER_PROTOCOL, SERVER_VERSION};
+use super::error::OrLog;
 use super::interfaces::messenger::Messenger;
 use super::interfaces::player::PlayerState;
 use super::minecraft_types::{Description, Version};
 use super::packet;
 use super::packet::Packet;
-use super::translation::TranslationUpdates;
 use uuid::Uuid;
 
 // Called when client pings the server
@@ -13,7 +14,7 @@ pub fn handle_client_ping_packet<M: Messenger, P: PlayerState>(
     conn_id: Uuid,
     messenger: M,
     player_state: P,
-) -> TranslationUpdates {
+) -> Vec<ConnectionUpdate> {
     match p {
         Packet::StatusRequest(_) => {
             let version = Version {
@@ -24,15 +25,17 @@ pub fn handle_client_ping_packet<M: Messenger, P: PlayerState>(
                 text: SERVER_DESCRIPTION.to_string(),
             };
 
-            player_state.status_response(conn_id, version, description);
+            player_state
+                .status_response(conn_id, version, description)
+                .or_log();
         }
         Packet::Ping(ping) => {
             let pong = packet::Pong {
                 payload: ping.payload,
             };
-            messenger.send_packet(conn_id, Packet::Pong(pong));
+            messenger.send_packet(conn_id, Packet::Pong(pong)).or_log();
         }
         _ => {}
     }
-    TranslationUpdates::NoChange
+    Vec::new()
 }
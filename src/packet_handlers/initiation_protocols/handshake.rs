@@ -1,10 +1,157 @@
+use super::config::ForwardingConfig;
+use super::connection_updates::ConnectionUpdate;
+use super::constants::UNFORWARDED_MESSAGE;
+use super::forwarding::{self, ForwardingMode};
+use super::interfaces::messenger::ConnectionClass;
 use super::packet::Packet;
-use super::translation::TranslationUpdates;
+use super::protocol_adapter::ProtocolAdapter;
 
 // Called upon handshake
-pub fn handle_handshake_packet(p: Packet) -> TranslationUpdates {
-    TranslationUpdates::State(match p.clone() {
-        Packet::Handshake(handshake) => handshake.next_state,
-        _ => panic!("Invalid packet {:?}", p),
-    })
+pub fn handle_handshake_packet(
+    p: Packet,
+    forwarding: Option<&ForwardingConfig>,
+) -> Vec<ConnectionUpdate> {
+    match p {
+        // Behind a BungeeCord proxy, players logging in have to have come through it
+        Packet::Handshake(handshake)
+            if handshake.next_state == 2
+                && forwarding.map(|forwarding| forwarding.mode) == Some(ForwardingMode::Legacy) =>
+        {
+            match forwarding::parse_legacy(&handshake.server_address) {
+                Ok(player) => {
+                    let mut updates = vec![ConnectionUpdate::Forwarded(player)];
+                    updates.extend(handle_handshake_packet(Packet::Handshake(handshake), None));
+                    updates
+                }
+                Err(e) => {
+                    warn!(
+                        "Turning away a player who didn't come through the proxy: {}",
+                        e
+                    );
+                    vec![ConnectionUpdate::Kick(String::from(UNFORWARDED_MESSAGE))]
+                }
+            }
+        }
+        // Clients on a version we have no adapter for would desync as soon as they joined, so
+        // they're turned away while logging in. They can still ping us to find out which versions
+        // we speak
+        Packet::Handshake(handshake) if handshake.next_state == 1 || handshake.next_state == 2 => {
+            match ProtocolAdapter::for_protocol(handshake.protocol_version) {
+                Some(adapter) => vec![
+                    ConnectionUpdate::Protocol(adapter),
+                    ConnectionUpdate::State(handshake.next_state),
+                ],
+                None if handshake.next_state == 1 => {
+                    vec![ConnectionUpdate::State(handshake.next_state)]
+                }
+                None => {
+                    let versions = ProtocolAdapter::supported_versions().join(" or ");
+                    let reason =
+                        if handshake.protocol_version < ProtocolAdapter::default().protocol() {
+                            format!("Outdated client! Please use {}", versions)
+                        } else {
+                            format!("Outdated server! I'm still on {}", versions)
+                        };
+                    vec![ConnectionUpdate::Kick(reason)]
+                }
+            }
+        }
+        // Peers authenticate before entering any of the peer states, and nobody can skip straight
+        // to any other
+        Packet::Handshake(handshake) => match handshake.next_state {
+            4 | 6 => vec![
+                ConnectionUpdate::Class(ConnectionClass::PeerLink),
+                ConnectionUpdate::State(7),
+            ],
+            next_state => {
+                warn!("Closing a connection that asked for state {}", next_state);
+                vec![ConnectionUpdate::Close]
+            }
+        },
+        p => {
+            warn!(
+                "Expected a handshake, got {:?}, closing",
+                p.debug_print_type()
+            );
+            vec![ConnectionUpdate::Close]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::packet::{Handshake, PeerShutdown};
+    use uuid::Uuid;
+
+    fn handshake(next_state: i32) -> Packet {
+        forwarded_handshake(next_state, "localhost")
+    }
+
+    fn forwarded_handshake(next_state: i32, server_address: &str) -> Packet {
+        Packet::Handshake(Handshake {
+            protocol_version: ProtocolAdapter::default().protocol(),
+            server_address: String::from(server_address),
+            server_port: 25565,
+            next_state,
+        })
+    }
+
+    #[test]
+    fn connections_cant_skip_past_logging_in_or_authenticating() {
+        assert!(matches!(
+            handle_handshake_packet(handshake(2), None)[..],
+            [ConnectionUpdate::Protocol(_), ConnectionUpdate::State(2)]
+        ));
+        assert!(matches!(
+            handle_handshake_packet(handshake(6), None)[..],
+            [ConnectionUpdate::Class(_), ConnectionUpdate::State(7)]
+        ));
+        for next_state in [3, 5, 7, 99] {
+            assert!(matches!(
+                handle_handshake_packet(handshake(next_state), None)[..],
+                [ConnectionUpdate::Close]
+            ));
+        }
+        assert!(matches!(
+            handle_handshake_packet(
+                Packet::PeerShutdown(PeerShutdown {
+                    peer_address: String::from("127.0.0.1"),
+                    peer_port: 8000,
+                }),
+                None
+            )[..],
+            [ConnectionUpdate::Close]
+        ));
+    }
+    #[test]
+    fn players_behind_a_bungeecord_proxy_have_to_come_through_it() {
+        let forwarding = ForwardingConfig {
+            mode: ForwardingMode::Legacy,
+            secret: String::new(),
+        };
+        let uuid = Uuid::from_u128(42);
+        let server_address = format!("localhost\x0010.0.0.1\x00{}", uuid.to_simple());
+        match &handle_handshake_packet(forwarded_handshake(2, &server_address), Some(&forwarding))[..]
+        {
+            [ConnectionUpdate::Forwarded(player), ConnectionUpdate::Protocol(_), ConnectionUpdate::State(2)] =>
+            {
+                assert_eq!(player.uuid, uuid);
+                assert_eq!(
+                    player.address,
+                    "10.0.0.1".parse::<std::net::IpAddr>().unwrap()
+                );
+            }
+            updates => panic!("Expected a forwarded login, got {:?}", updates),
+        }
+        assert!(matches!(
+            handle_handshake_packet(handshake(2), Some(&forwarding))[..],
+            [ConnectionUpdate::Kick(_)]
+        ));
+        // Pings and peers don't go through the proxy
+        assert!(matches!(
+            handle_handshake_packet(handshake(1), Some(&forwarding))[..],
+            [ConnectionUpdate::Protocol(_), ConnectionUpdate::State(1)]
+        ));
+    }
 }
@@ -1,17 +1,36 @@
+use super::config::ForwardingConfig;
+use super::connection_updates::ConnectionUpdate;
+use super::constants::UNFORWARDED_MESSAGE;
+use super::error::OrLog;
+use super::forwarding::{
+    self, ForwardedPlayer, ForwardingMode, MODERN_FORWARDING_CHANNEL, MODERN_FORWARDING_MESSAGE_ID,
+    MODERN_FORWARDING_VERSION,
+};
+use super::interfaces::bans::BanList;
 use super::interfaces::block::BlockState;
 use super::interfaces::messenger::{Messenger, SubscriberType};
 use super::interfaces::patchwork::PatchworkState;
-use super::interfaces::player::{Angle, Player, PlayerState, Position};
+use super::interfaces::player::{
+    spawn_position, Angle, Experience, Health, Player, PlayerState, Velocity,
+    PLAYER_INVENTORY_SLOTS,
+};
+use super::interfaces::whitelist::Whitelist;
+use super::map::Dimension;
 use super::packet;
 use super::packet::Packet;
-use super::translation::TranslationUpdates;
+use super::uuid_source::UuidSource;
+use std::net::IpAddr;
+use std::sync::mpsc::channel;
 use uuid::Uuid;
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_login_packet<
     M: Messenger + Clone,
     P: PlayerState + Clone,
     PA: PatchworkState + Clone,
     B: BlockState + Clone,
+    W: Whitelist,
+    BL: BanList,
 >(
     p: Packet,
     conn_id: Uuid,
@@ -19,63 +38,209 @@ pub fn handle_login_packet<
     player_state: P,
     block_state: B,
     patchwork_state: PA,
-) -> TranslationUpdates {
-    match p {
-        Packet::LoginStart(login_start) => {
-            confirm_login(
+    whitelist: W,
+    bans: BL,
+    address: Option<IpAddr>,
+    forwarding: Option<&ForwardingConfig>,
+    forwarded: Option<&ForwardedPlayer>,
+    uuids: &UuidSource,
+) -> Vec<ConnectionUpdate> {
+    let modern = forwarding.filter(|forwarding| forwarding.mode == ForwardingMode::Modern);
+    match (p, modern) {
+        // Behind Velocity, the proxy's asked who the player really is before they're let in
+        (Packet::LoginStart(_), Some(_)) => {
+            messenger
+                .send_packet(
+                    conn_id,
+                    Packet::LoginPluginRequest(packet::LoginPluginRequest {
+                        message_id: MODERN_FORWARDING_MESSAGE_ID,
+                        channel: String::from(MODERN_FORWARDING_CHANNEL),
+                        data: vec![MODERN_FORWARDING_VERSION],
+                    }),
+                )
+                .or_log();
+            Vec::new()
+        }
+        // Clients that aren't behind the proxy don't know the channel, and say so
+        (Packet::LoginPluginResponse(response), Some(forwarding))
+            if response.message_id == MODERN_FORWARDING_MESSAGE_ID =>
+        {
+            let player = match response.successful {
+                true => forwarding::parse_modern(&response.data, &forwarding.secret),
+                false => Err(String::from("No forwarded player info")),
+            };
+            match player {
+                Ok(player) => {
+                    let name = player.name.clone().unwrap_or_default();
+                    let (address, uuid) = (player.address, player.uuid);
+                    let mut updates = vec![ConnectionUpdate::Forwarded(player)];
+                    updates.extend(log_in(
+                        name,
+                        conn_id,
+                        messenger,
+                        player_state,
+                        block_state,
+                        patchwork_state,
+                        whitelist,
+                        bans,
+                        Some(address),
+                        Some(uuid),
+                        uuids,
+                    ));
+                    updates
+                }
+                Err(e) => {
+                    warn!(
+                        "Turning away a player who didn't come through the proxy: {}",
+                        e
+                    );
+                    vec![ConnectionUpdate::Kick(String::from(UNFORWARDED_MESSAGE))]
+                }
+            }
+        }
+        (Packet::LoginStart(login_start), None) => log_in(
+            login_start.username,
+            conn_id,
+            messenger,
+            player_state,
+            block_state,
+            patchwork_state,
+            whitelist,
+            bans,
+            address,
+            forwarded.map(|forwarded| forwarded.uuid),
+            uuids,
+        ),
+        (p, _) => {
+            warn!(
+                "Expected {:?} to log in, got {:?}",
                 conn_id,
-                messenger,
-                login_start,
-                player_state,
-                block_state,
-                patchwork_state,
+                p.debug_print_type()
             );
-            TranslationUpdates::State(3)
-        }
-        _ => {
-            panic!("Login failed");
+            vec![ConnectionUpdate::Kick(String::from("Login failed"))]
         }
     }
 }
 
-fn confirm_login<
+// Lets the player in, unless they're banned or not on the whitelist. A proxy in front of us knows
+// better than we do what their uuid is
+#[allow(clippy::too_many_arguments)]
+fn log_in<
     M: Messenger + Clone,
     P: PlayerState + Clone,
     PA: PatchworkState + Clone,
     B: BlockState + Clone,
+    W: Whitelist,
+    BL: BanList,
 >(
+    name: String,
     conn_id: Uuid,
     messenger: M,
-    login_start: packet::LoginStart,
     player_state: P,
     block_state: B,
     patchwork_state: PA,
-) {
-    let player = Player {
+    whitelist: W,
+    bans: BL,
+    address: Option<IpAddr>,
+    uuid: Option<Uuid>,
+    uuids: &UuidSource,
+) -> Vec<ConnectionUpdate> {
+    let mut player = new_player(conn_id, name, player_state.clone(), uuids);
+    if let Some(uuid) = uuid {
+        player.uuid = uuid;
+    }
+    // Nobody's let in unless the ban list and whitelist say so, even if they can't be asked
+    let (bans_sender, bans_receiver) = channel();
+    bans.check(player.name.clone(), player.uuid, address, bans_sender)
+        .or_log();
+    let (whitelist_sender, whitelist_receiver) = channel();
+    whitelist
+        .check(player.name.clone(), player.uuid, whitelist_sender)
+        .or_log();
+    for refusal in [bans_receiver.recv(), whitelist_receiver.recv()] {
+        match refusal {
+            Ok(None) => {}
+            Ok(Some(message)) => return vec![ConnectionUpdate::Kick(message)],
+            Err(_) => return vec![ConnectionUpdate::Kick(String::from("Login failed"))],
+        }
+    }
+    confirm_login(
         conn_id,
-        uuid: Uuid::new_v4(),
-        name: login_start.username,
+        messenger,
+        player,
+        player_state,
+        block_state,
+        patchwork_state,
+    );
+    vec![
+        ConnectionUpdate::State(3),
+        ConnectionUpdate::Subscribe(SubscriberType::All),
+    ]
+}
+
+fn new_player<P: PlayerState>(
+    conn_id: Uuid,
+    name: String,
+    player_state: P,
+    uuids: &UuidSource,
+) -> Player {
+    let mut player = Player {
+        conn_id,
+        uuid: uuids.next(),
+        name,
         entity_id: 0, // replaced by player state
-        position: Position {
-            x: 5.0,
-            y: 16.0,
-            z: 5.0,
-        },
+        position: spawn_position(),
         angle: Angle {
             pitch: 0.0,
             yaw: 0.0,
         },
+        velocity: Velocity::default(),
+        inventory: vec![None; PLAYER_INVENTORY_SLOTS],
+        held_item_slot: 0,
+        health: Health::default(),
+        experience: Experience::default(),
+        dimension: Dimension::Overworld,
     };
+    // Players who've been here before rejoin where they left off
+    let (reply_sender, reply_receiver) = channel();
+    player_state
+        .saved_player(player.name.clone(), reply_sender)
+        .or_log();
+    if let Ok(Some(saved)) = reply_receiver.recv() {
+        trace!("Restoring saved player {:?}", player.name);
+        saved.apply_to(&mut player);
+    }
+    player
+}
 
+fn confirm_login<
+    M: Messenger + Clone,
+    P: PlayerState + Clone,
+    PA: PatchworkState + Clone,
+    B: BlockState + Clone,
+>(
+    conn_id: Uuid,
+    messenger: M,
+    player: Player,
+    player_state: P,
+    block_state: B,
+    patchwork_state: PA,
+) {
     //protocol
     login_success(conn_id, messenger.clone(), player.clone());
+    // Subscribed ahead of the connection's own updates, so that chunks generated for the block
+    // report below aren't broadcast before the player can hear about them
+    messenger.subscribe(conn_id, SubscriberType::All).or_log();
 
     //update the gamestate with this new player
-    player_state.new_player(conn_id, player);
-    block_state.report(conn_id);
-    messenger.subscribe(conn_id, SubscriberType::All);
-    player_state.report(conn_id);
-    patchwork_state.report();
+    let (position, dimension) = (player.position, player.dimension);
+    player_state.new_player(conn_id, player).or_log();
+    block_state.report(conn_id).or_log();
+    player_state.list_players(conn_id).or_log();
+    patchwork_state
+        .place_player(conn_id, position, dimension)
+        .or_log();
+    patchwork_state.report(conn_id).or_log();
 }
 
 fn login_success<M: Messenger>(conn_id: Uuid, messenger: M, player: Player) {
@@ -83,5 +248,297 @@ fn login_success<M: Messenger>(conn_id: Uuid, messenger: M, player: Player) {
         uuid: player.uuid.to_hyphenated().to_string(),
         username: player.name,
     };
-    messenger.send_packet(conn_id, Packet::LoginSuccess(login_success));
+    messenger
+        .send_packet(conn_id, Packet::LoginSuccess(login_success))
+        .or_log();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::bans::Operations as BanOperations;
+    use crate::interfaces::player::{Operations as PlayerOperations, Position};
+    use crate::interfaces::whitelist::Operations as WhitelistOperations;
+    use crate::interfaces::{
+        block, messenger, patchwork, MockBanList, MockBlockState, MockMessenger,
+        MockPatchworkState, MockPlayerState, MockWhitelist,
+    };
+
+    // Answers every check the same way
+    fn whitelist(refusal: Option<&'static str>) -> MockWhitelist {
+        MockWhitelist::responding(move |msg| {
+            if let WhitelistOperations::Check(msg) = msg {
+                let _ = msg.reply.send(refusal.map(String::from));
+            }
+        })
+    }
+
+    fn bans(refusal: Option<&'static str>) -> MockBanList {
+        MockBanList::responding(move |msg| {
+            if let BanOperations::Check(msg) = msg {
+                let _ = msg.reply.send(refusal.map(String::from));
+            }
+        })
+    }
+    use crate::models::player_store::SavedPlayer;
+
+    #[test]
+    fn returning_players_log_in_where_they_left_off() {
+        let saved_uuid = Uuid::from_u128(7);
+        let messenger = MockMessenger::new();
+        let player_state = MockPlayerState::responding(move |msg| {
+            if let PlayerOperations::Saved(msg) = msg {
+                let _ = msg.reply.send(Some(SavedPlayer {
+                    uuid: saved_uuid.to_string(),
+                    position: Position {
+                        x: 1.0,
+                        y: 2.0,
+                        z: 3.0,
+                    },
+                    angle: Angle {
+                        pitch: 0.0,
+                        yaw: 0.0,
+                    },
+                    inventory: vec![None; PLAYER_INVENTORY_SLOTS],
+                    held_item_slot: 4,
+                    health: Health::default(),
+                    experience: Experience::default(),
+                    dimension: Dimension::Nether,
+                }));
+            }
+        });
+        let block_state = MockBlockState::new();
+        let patchwork_state = MockPatchworkState::new();
+        let conn_id = Uuid::new_v4();
+
+        let updates = handle_login_packet(
+            Packet::LoginStart(packet::LoginStart {
+                username: String::from("returning"),
+            }),
+            conn_id,
+            messenger.clone(),
+            player_state.clone(),
+            block_state.clone(),
+            patchwork_state.clone(),
+            whitelist(None),
+            bans(None),
+            None,
+            None,
+            None,
+            &UuidSource::sequential(),
+        );
+
+        assert!(matches!(
+            updates[..],
+            [
+                ConnectionUpdate::State(3),
+                ConnectionUpdate::Subscribe(SubscriberType::All)
+            ]
+        ));
+        match &messenger.take()[..] {
+            [messenger::Operations::Send(msg), messenger::Operations::Subscribe(_)] => {
+                match &msg.packet {
+                    Packet::LoginSuccess(login_success) => {
+                        assert_eq!(msg.conn_id, conn_id);
+                        assert_eq!(login_success.uuid, saved_uuid.to_hyphenated().to_string());
+                    }
+                    packet => panic!("Expected login success, got {:?}", packet),
+                }
+            }
+            sent => panic!("Expected login success, got {:?}", sent),
+        }
+        let player = player_state
+            .take()
+            .into_iter()
+            .find_map(|msg| match msg {
+                PlayerOperations::New(msg) => Some(msg.player),
+                _ => None,
+            })
+            .expect("Logging in didn't create a player");
+        assert_eq!(player.uuid, saved_uuid);
+        assert_eq!(
+            (player.position.x, player.position.y, player.position.z),
+            (1.0, 2.0, 3.0)
+        );
+        assert_eq!(player.held_item_slot, 4);
+        assert_eq!(player.dimension, Dimension::Nether);
+        assert!(matches!(
+            block_state.take()[..],
+            [block::Operations::Report(_)]
+        ));
+        match &patchwork_state.take()[..] {
+            [patchwork::Operations::PlacePlayer(msg), patchwork::Operations::Report(_)] => {
+                assert_eq!(msg.conn_id, conn_id);
+                assert_eq!((msg.position.x, msg.position.z), (1.0, 3.0));
+                assert_eq!(msg.dimension, Dimension::Nether);
+            }
+            sent => panic!("Expected the player to be placed, got {:?}", sent),
+        }
+    }
+
+    #[test]
+    fn players_who_arent_whitelisted_are_kicked_before_joining() {
+        let messenger = MockMessenger::new();
+        let player_state = MockPlayerState::responding(|msg| {
+            if let PlayerOperations::Saved(msg) = msg {
+                let _ = msg.reply.send(None);
+            }
+        });
+        let block_state = MockBlockState::new();
+        let conn_id = Uuid::new_v4();
+
+        let updates = handle_login_packet(
+            Packet::LoginStart(packet::LoginStart {
+                username: String::from("stranger"),
+            }),
+            conn_id,
+            messenger.clone(),
+            player_state.clone(),
+            block_state.clone(),
+            MockPatchworkState::new(),
+            whitelist(Some("Members only")),
+            bans(None),
+            None,
+            None,
+            None,
+            &UuidSource::sequential(),
+        );
+
+        match &updates[..] {
+            [ConnectionUpdate::Kick(message)] => assert_eq!(message, "Members only"),
+            updates => panic!("Expected a kick, got {:?}", updates),
+        }
+        assert!(messenger.take().is_empty());
+        assert!(!player_state
+            .take()
+            .iter()
+            .any(|msg| matches!(msg, PlayerOperations::New(_))));
+        assert!(block_state.take().is_empty());
+    }
+
+    #[test]
+    fn banned_players_are_kicked_with_the_reason() {
+        let messenger = MockMessenger::new();
+        let player_state = MockPlayerState::responding(|msg| {
+            if let PlayerOperations::Saved(msg) = msg {
+                let _ = msg.reply.send(None);
+            }
+        });
+        let bans = bans(Some("Banned: griefing"));
+
+        let updates = handle_login_packet(
+            Packet::LoginStart(packet::LoginStart {
+                username: String::from("griefer"),
+            }),
+            Uuid::new_v4(),
+            messenger.clone(),
+            player_state,
+            MockBlockState::new(),
+            MockPatchworkState::new(),
+            whitelist(None),
+            bans.clone(),
+            Some("10.0.0.1".parse().unwrap()),
+            None,
+            None,
+            &UuidSource::sequential(),
+        );
+
+        match &updates[..] {
+            [ConnectionUpdate::Kick(message)] => assert_eq!(message, "Banned: griefing"),
+            updates => panic!("Expected a kick, got {:?}", updates),
+        }
+        match &bans.take()[..] {
+            [BanOperations::Check(msg)] => {
+                assert_eq!(msg.name, "griefer");
+                assert_eq!(msg.address, Some("10.0.0.1".parse().unwrap()));
+            }
+            sent => panic!("Expected a ban check, got {:?}", sent),
+        }
+        assert!(messenger.take().is_empty());
+    }
+    #[test]
+    fn players_behind_velocity_log_in_as_who_it_says_they_are() {
+        use crate::models::minecraft_protocol::MinecraftProtocolWriter;
+        use hmac::{Hmac, Mac};
+        use sha2::Sha256;
+
+        let forwarding = ForwardingConfig {
+            mode: ForwardingMode::Modern,
+            secret: String::from("secret"),
+        };
+        let messenger = MockMessenger::new();
+        let player_state = MockPlayerState::responding(|msg| {
+            if let PlayerOperations::Saved(msg) = msg {
+                let _ = msg.reply.send(None);
+            }
+        });
+        let log_in = |packet| {
+            handle_login_packet(
+                packet,
+                Uuid::new_v4(),
+                messenger.clone(),
+                player_state.clone(),
+                MockBlockState::new(),
+                MockPatchworkState::new(),
+                whitelist(None),
+                bans(None),
+                Some("10.0.0.100".parse().unwrap()),
+                Some(&forwarding),
+                None,
+                &UuidSource::sequential(),
+            )
+        };
+
+        assert!(log_in(Packet::LoginStart(packet::LoginStart {
+            username: String::from("alex"),
+        }))
+        .is_empty());
+        match &messenger.take()[..] {
+            [messenger::Operations::Send(msg)] => match &msg.packet {
+                Packet::LoginPluginRequest(request) => {
+                    assert_eq!(request.channel, MODERN_FORWARDING_CHANNEL)
+                }
+                packet => panic!("Expected a player info request, got {:?}", packet),
+            },
+            sent => panic!("Expected a player info request, got {:?}", sent),
+        }
+        let response = |successful, data| {
+            Packet::LoginPluginResponse(packet::LoginPluginResponse {
+                message_id: MODERN_FORWARDING_MESSAGE_ID,
+                successful,
+                data,
+            })
+        };
+        match &log_in(response(false, vec![]))[..] {
+            [ConnectionUpdate::Kick(message)] => assert_eq!(message, UNFORWARDED_MESSAGE),
+            updates => panic!("Expected a kick, got {:?}", updates),
+        }
+
+        let uuid = Uuid::from_u128(42);
+        let mut info = vec![];
+        info.write_var_int(i32::from(MODERN_FORWARDING_VERSION));
+        info.write_string(String::from("10.0.0.1"));
+        info.write_u_128(uuid.as_u128());
+        info.write_string(String::from("alex"));
+        info.write_var_int(0);
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(&info);
+        let data = [&mac.finalize().into_bytes()[..], &info].concat();
+        match &log_in(response(true, data))[..] {
+            [ConnectionUpdate::Forwarded(player), ConnectionUpdate::State(3), ConnectionUpdate::Subscribe(_)] =>
+            {
+                assert_eq!(player.address, "10.0.0.1".parse::<IpAddr>().unwrap())
+            }
+            updates => panic!("Expected a forwarded login, got {:?}", updates),
+        }
+        let player = player_state
+            .take()
+            .into_iter()
+            .find_map(|msg| match msg {
+                PlayerOperations::New(msg) => Some(msg.player),
+                _ => None,
+            })
+            .expect("Logging in didn't create a player");
+        assert_eq!((player.name.as_str(), player.uuid), ("alex", uuid));
+    }
 }
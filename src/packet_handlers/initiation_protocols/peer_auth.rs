@@ -0,0 +1,88 @@
+use super::connection_updates::ConnectionUpdate;
+use super::error::OrLog;
+use super::identity::Identity;
+use super::interfaces::messenger::Messenger;
+use super::interfaces::peer_auth::{PeerAuth, PeerAuthToken};
+use super::map::map_size;
+use super::packet::{Packet, PeerAuthChallenge, PeerIdentity};
+
+use std::sync::mpsc::channel;
+use uuid::Uuid;
+
+// Peers asking for a peer state are sent a challenge straight away, which their credentials have to
+// be signed over
+pub fn challenge<M: Messenger, A: PeerAuth>(conn_id: Uuid, messenger: M, peer_auth: A) {
+    let (reply_sender, reply_receiver) = channel();
+    peer_auth.challenge(conn_id, reply_sender).or_log();
+    if let Ok(challenge) = reply_receiver.recv() {
+        messenger
+            .send_packet(
+                conn_id,
+                Packet::PeerAuthChallenge(PeerAuthChallenge {
+                    challenge,
+                    map_size: map_size(),
+                }),
+            )
+            .or_log();
+    }
+}
+
+// Peers have to authenticate before they can subscribe or anchor players, and have maps the same size
+// as ours. Peers that subscribe are told who we are in return, as they've no other way of knowing
+// which instance they reached
+pub fn handle_peer_auth_packet<M: Messenger, A: PeerAuth>(
+    p: Packet,
+    conn_id: Uuid,
+    messenger: M,
+    peer_auth: A,
+    instance: &Identity,
+) -> Vec<ConnectionUpdate> {
+    let token = match p {
+        Packet::PeerAuth(packet) if packet.map_size != map_size() => {
+            warn!(
+                "Peer {:?} has maps {} chunks wide, ours are {}, closing",
+                conn_id,
+                packet.map_size,
+                map_size()
+            );
+            return vec![ConnectionUpdate::Close];
+        }
+        Packet::PeerAuth(packet) => PeerAuthToken {
+            next_state: packet.next_state,
+            key_id: packet.key_id,
+            timestamp: packet.timestamp,
+            mac: packet.mac,
+            instance: Identity {
+                id: Uuid::from_u128(packet.instance_id),
+                name: packet.instance_name,
+            },
+        },
+        _ => {
+            warn!("Peer {:?} did not authenticate, closing", conn_id);
+            return vec![ConnectionUpdate::Close];
+        }
+    };
+    let (next_state, peer_instance) = (token.next_state, token.instance.clone());
+    let (reply_sender, reply_receiver) = channel();
+    peer_auth.verify(conn_id, token, reply_sender).or_log();
+    match (reply_receiver.recv(), next_state) {
+        (Ok(true), 4) | (Ok(true), 6) => {
+            if next_state == 6 {
+                messenger
+                    .send_packet(
+                        conn_id,
+                        Packet::PeerIdentity(PeerIdentity {
+                            instance_id: instance.id.as_u128(),
+                            instance_name: instance.name.clone(),
+                        }),
+                    )
+                    .or_log();
+            }
+            vec![
+                ConnectionUpdate::State(next_state),
+                ConnectionUpdate::Identity(peer_instance),
+            ]
+        }
+        _ => vec![ConnectionUpdate::Close],
+    }
+}
@@ -1,20 +1,39 @@
+use super::error::{OrLog, PatchworkError};
+use super::interfaces::bans::BanList;
 use super::interfaces::block::BlockState;
+use super::interfaces::entity::EntityState;
+use super::interfaces::game_rules::GameRuleState;
 use super::interfaces::messenger::Messenger;
 use super::interfaces::patchwork::PatchworkState;
+use super::interfaces::peer_auth::PeerAuth;
 use super::interfaces::player::PlayerState;
+use super::interfaces::whitelist::Whitelist;
+use super::interfaces::world_time::WorldTimeState;
 
-use super::initiation_protocols::{border_cross_login, client_ping, handshake, login};
+use super::config::ForwardingConfig;
+use super::connection_updates::ConnectionUpdate;
+use super::forwarding::ForwardedPlayer;
+use super::identity::Identity;
+use super::initiation_protocols::{border_cross_login, client_ping, handshake, login, peer_auth};
 use super::packet::Packet;
 use super::peer_subscription;
-use super::translation::TranslationUpdates;
+use super::uuid_source::UuidSource;
+use std::net::IpAddr;
 use uuid::Uuid;
 
 // Routes the packet to the corresponding service according to the connection state
+#[allow(clippy::too_many_arguments)]
 pub fn route_packet<
     M: Messenger + Clone,
     P: PlayerState + Clone,
     PA: PatchworkState + Clone,
     B: BlockState + Clone,
+    E: EntityState + Clone,
+    G: GameRuleState + Clone,
+    A: PeerAuth,
+    W: Whitelist,
+    BL: BanList,
+    T: WorldTimeState,
 >(
     packet: Packet,
     state: i32,
@@ -23,10 +42,36 @@ pub fn route_packet<
     player_state: P,
     block_state: B,
     patchwork_state: PA,
-) -> TranslationUpdates {
-    let st = Status::from_i32(state);
+    entity_state: E,
+    game_rules: G,
+    peer_auth: A,
+    whitelist: W,
+    bans: BL,
+    world_time: T,
+    address: Option<IpAddr>,
+    forwarding: Option<&ForwardingConfig>,
+    forwarded: Option<&ForwardedPlayer>,
+    uuids: &UuidSource,
+    instance: &Identity,
+) -> Vec<ConnectionUpdate> {
+    let st = match Status::from_i32(state) {
+        Some(st) => st,
+        None => {
+            error!("{}", PatchworkError::UnknownState { conn_id, state });
+            return Vec::new();
+        }
+    };
     match st {
-        Status::Handshake => handshake::handle_handshake_packet(packet),
+        Status::Handshake => {
+            let updates = handshake::handle_handshake_packet(packet, forwarding);
+            let authenticating = updates
+                .iter()
+                .any(|update| matches!(update, ConnectionUpdate::State(7)));
+            if authenticating {
+                peer_auth::challenge(conn_id, messenger, peer_auth);
+            }
+            updates
+        }
         Status::Login => login::handle_login_packet(
             packet,
             conn_id,
@@ -34,29 +79,49 @@ pub fn route_packet<
             player_state,
             block_state,
             patchwork_state,
+            whitelist,
+            bans,
+            address,
+            forwarding,
+            forwarded,
+            uuids,
         ),
         Status::ClientPing => {
             client_ping::handle_client_ping_packet(packet, conn_id, messenger, player_state)
         }
         Status::Play => {
-            patchwork_state.route_player_packet(packet, conn_id);
-            TranslationUpdates::NoChange
+            patchwork_state
+                .route_player_packet(packet, conn_id)
+                .or_log();
+            Vec::new()
         }
         Status::BorderCrossLogin => {
             border_cross_login::border_cross_login(packet, conn_id, player_state)
         }
-        Status::InPeerSub => {
-            peer_subscription::handle_peer_packet(packet, messenger, player_state);
-            TranslationUpdates::NoChange
-        }
-        Status::OutPeerSub => {
-            peer_subscription::handle_subscriber_packet(
-                conn_id,
-                messenger,
-                player_state,
-                block_state,
-            );
-            TranslationUpdates::NoChange
+        Status::InPeerSub => peer_subscription::handle_peer_packet(
+            packet,
+            conn_id,
+            messenger,
+            player_state,
+            patchwork_state,
+            game_rules,
+            bans,
+            world_time,
+        ),
+        Status::OutPeerSub => peer_subscription::handle_subscriber_packet(
+            packet,
+            conn_id,
+            messenger,
+            player_state,
+            block_state,
+            entity_state,
+            game_rules,
+            patchwork_state,
+            bans,
+            world_time,
+        ),
+        Status::PeerAuth => {
+            peer_auth::handle_peer_auth_packet(packet, conn_id, messenger, peer_auth, instance)
         }
     }
 }
@@ -69,19 +134,21 @@ enum Status {
     BorderCrossLogin,
     InPeerSub,
     OutPeerSub,
+    PeerAuth,
 }
 
 impl Status {
-    fn from_i32(status: i32) -> Status {
+    fn from_i32(status: i32) -> Option<Status> {
         match status {
-            0 => Status::Handshake,
-            1 => Status::ClientPing,
-            2 => Status::Login,
-            3 => Status::Play,
-            4 => Status::BorderCrossLogin,
-            5 => Status::InPeerSub,
-            6 => Status::OutPeerSub,
-            _ => panic!("Bad state"),
+            0 => Some(Status::Handshake),
+            1 => Some(Status::ClientPing),
+            2 => Some(Status::Login),
+            3 => Some(Status::Play),
+            4 => Some(Status::BorderCrossLogin),
+            5 => Some(Status::InPeerSub),
+            6 => Some(Status::OutPeerSub),
+            7 => Some(Status::PeerAuth),
+            _ => None,
         }
     }
 }
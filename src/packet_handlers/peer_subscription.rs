@@ -1,19 +1,182 @@
-use super::interfaces::messenger::{Messenger, SubscriberType};
-use super::packet::Packet;
+use super::connection_updates::ConnectionUpdate;
+use super::error::OrLog;
+use super::interfaces::messenger::{Messenger, Origin, SubscriberType};
+use super::packet::{ClientboundChatMessage, EntityOwnerReply, Packet, PeerChatMessage};
+use std::sync::mpsc::channel;
 use uuid::Uuid;
 
-use super::interfaces::block::BlockState;
-use super::interfaces::player::PlayerState;
+use super::ban_store::Ban;
+use super::identity::Identity;
+use super::interfaces::bans::BanList;
+use super::interfaces::block::{BlockPosition, BlockState};
+use super::interfaces::entity::{DroppedItem, EntityState};
+use super::interfaces::game_rules::{GameRule, GameRuleState};
+use super::interfaces::patchwork::PatchworkState;
+use super::interfaces::player::{PlayerState, Position};
+use super::interfaces::world_time::WorldTimeState;
+use super::map::{Dimension, GossipedMap, Peer, Position as MapPosition};
+use super::topology::Topology;
+use super::weather::{Weather, WeatherKind};
 
-pub fn handle_peer_packet<M: Messenger, P: PlayerState>(
+#[allow(clippy::too_many_arguments)]
+pub fn handle_peer_packet<
+    M: Messenger,
+    P: PlayerState,
+    PA: PatchworkState,
+    G: GameRuleState,
+    BL: BanList,
+    T: WorldTimeState,
+>(
     packet: Packet,
+    conn_id: Uuid,
     messenger: M,
     player_state: P,
-) {
+    patchwork_state: PA,
+    game_rules: G,
+    bans: BL,
+    world_time: T,
+) -> Vec<ConnectionUpdate> {
     match packet.clone() {
+        Packet::GameRuleUpdate(packet) => match GameRule::from_name(&packet.rule) {
+            Some(rule) => game_rules
+                .peer_update(rule, packet.value, packet.version)
+                .or_log(),
+            None => warn!("Peer sent unknown game rule {:?}", packet.rule),
+        },
+        Packet::PeerTimeUpdate(packet) => match WeatherKind::from_id(packet.weather) {
+            Some(kind) => world_time
+                .peer_update(
+                    packet.world_age,
+                    packet.time_of_day,
+                    Weather {
+                        kind,
+                        remaining: packet.weather_remaining,
+                    },
+                )
+                .or_log(),
+            None => warn!("Peer sent unknown weather {:?}", packet.weather),
+        },
+        Packet::PeerBan(packet) => bans
+            .peer_update(Ban {
+                target: packet.target,
+                reason: packet.reason,
+                expires_at: Some(packet.expires_at as u64).filter(|expires_at| *expires_at > 0),
+                version: packet.version,
+                active: packet.active,
+            })
+            .or_log(),
+        Packet::PeerHeartbeat(_) => {
+            patchwork_state.heartbeat_ack(conn_id).or_log();
+        }
+        // The peer we've subscribed to saying who it is
+        Packet::PeerIdentity(packet) => {
+            return vec![ConnectionUpdate::Identity(Identity {
+                id: Uuid::from_u128(packet.instance_id),
+                name: packet.instance_name,
+            })];
+        }
+        Packet::PeerShutdown(packet) => {
+            patchwork_state
+                .remove_map(Peer {
+                    address: packet.peer_address,
+                    port: packet.peer_port,
+                })
+                .or_log();
+        }
+        Packet::PeerPluginMessage(packet) => {
+            patchwork_state
+                .relay_plugin_message(
+                    Origin {
+                        node: Peer {
+                            address: packet.origin_address,
+                            port: packet.origin_port,
+                        },
+                        hops: packet.hops.saturating_add(1),
+                        sequence: packet.sequence,
+                    },
+                    conn_id,
+                    packet.channel,
+                    packet.data,
+                )
+                .or_log();
+        }
+        //Chat is passed on to every peer, and shown to our players the first time it gets here
+        Packet::PeerChatMessage(packet) => {
+            let origin = Origin {
+                node: Peer {
+                    address: packet.origin_address.clone(),
+                    port: packet.origin_port,
+                },
+                hops: packet.hops.saturating_add(1),
+                sequence: packet.sequence,
+            };
+            messenger
+                .relay(
+                    Packet::PeerChatMessage(PeerChatMessage {
+                        hops: origin.hops,
+                        ..packet.clone()
+                    }),
+                    origin,
+                    Some(conn_id),
+                    SubscriberType::Remote,
+                    Some(Box::new(Packet::ClientboundChatMessage(
+                        ClientboundChatMessage {
+                            json_data: packet.json_data,
+                            position: 0, // chat
+                        },
+                    ))),
+                )
+                .or_log();
+        }
+        Packet::EntityOwnerReply(packet) => {
+            patchwork_state
+                .entity_owner_reply(
+                    conn_id,
+                    packet.query_id,
+                    Some(packet.entity_id).filter(|_| packet.found),
+                )
+                .or_log();
+        }
+        // Sent to every peer subscribed to the map's owner, so it's up to patchwork state whether
+        // the player's one of ours and on that map
+        Packet::PeerKickback(packet) => {
+            patchwork_state
+                .kick_back(
+                    conn_id,
+                    Uuid::from_u128(packet.uuid),
+                    Some(packet.reason).filter(|reason| !reason.is_empty()),
+                )
+                .or_log();
+        }
+        Packet::MapOwnerChange(packet) => {
+            patchwork_state
+                .change_map_owner(
+                    conn_id,
+                    Peer {
+                        address: packet.peer_address,
+                        port: packet.peer_port,
+                    },
+                )
+                .or_log();
+        }
+        Packet::MapPositionAgreement(packet) => {
+            patchwork_state
+                .agree_map_position(
+                    conn_id,
+                    MapPosition {
+                        x: packet.x,
+                        z: packet.z,
+                        dimension: Dimension::from_id(packet.dimension).unwrap_or_default(),
+                    },
+                    packet.entity_id_block,
+                )
+                .or_log();
+        }
         Packet::SpawnPlayer(packet) => {
             if packet.entity_id >= 1000 {
-                messenger.broadcast(Packet::SpawnPlayer(packet), None, SubscriberType::Local);
+                messenger
+                    .broadcast(Packet::SpawnPlayer(packet), None, SubscriberType::Local)
+                    .or_log();
             }
         }
         Packet::DestroyEntities(packet) => {
@@ -22,7 +185,9 @@ pub fn handle_peer_packet<M: Messenger, P: PlayerState>(
                 "Cannot handle entity destroy packets from peers with multiple ids"
             );
             if packet.entity_ids[0] >= 1000 {
-                messenger.broadcast(Packet::DestroyEntities(packet), None, SubscriberType::Local);
+                messenger
+                    .broadcast(Packet::DestroyEntities(packet), None, SubscriberType::Local)
+                    .or_log();
             }
         }
         //We really don't want to have to do this for every type of packet that has an entity id
@@ -31,26 +196,215 @@ pub fn handle_peer_packet<M: Messenger, P: PlayerState>(
         //have an entity id in them
         Packet::EntityLookAndMove(packet) => {
             let entity_id = packet.entity_id;
-            player_state.broadcast_anchored_event(entity_id, Packet::EntityLookAndMove(packet));
+            player_state
+                .broadcast_anchored_event(entity_id, Packet::EntityLookAndMove(packet))
+                .or_log();
+        }
+        Packet::EntityRelativeMove(packet) => {
+            let entity_id = packet.entity_id;
+            player_state
+                .broadcast_anchored_event(entity_id, Packet::EntityRelativeMove(packet))
+                .or_log();
+        }
+        Packet::EntityLook(packet) => {
+            let entity_id = packet.entity_id;
+            player_state
+                .broadcast_anchored_event(entity_id, Packet::EntityLook(packet))
+                .or_log();
+        }
+        Packet::EntityTeleport(packet) => {
+            let entity_id = packet.entity_id;
+            player_state
+                .broadcast_anchored_event(entity_id, Packet::EntityTeleport(packet))
+                .or_log();
         }
         _ => {
-            messenger.broadcast(packet, None, SubscriberType::Local);
+            messenger
+                .broadcast(packet, None, SubscriberType::Local)
+                .or_log();
         }
     }
+    Vec::new()
 }
 
-pub fn handle_subscriber_packet<M: Messenger, P: PlayerState, B: BlockState>(
+#[allow(clippy::too_many_arguments)]
+pub fn handle_subscriber_packet<
+    M: Messenger,
+    P: PlayerState,
+    B: BlockState,
+    E: EntityState,
+    G: GameRuleState,
+    PA: PatchworkState,
+    BL: BanList,
+    T: WorldTimeState,
+>(
+    packet: Packet,
     conn_id: Uuid,
     messenger: M,
     player_state: P,
     block_state: B,
-) {
-    //Everytime a subscriber sends us a packet, we subscribe them to our messages and report our
-    //state to them
+    entity_state: E,
+    game_rules: G,
+    patchwork_state: PA,
+    bans: BL,
+    world_time: T,
+) -> Vec<ConnectionUpdate> {
+    match packet {
+        Packet::PeerHeartbeat(packet) => {
+            messenger
+                .send_packet(conn_id, Packet::PeerHeartbeat(packet))
+                .or_log();
+        }
+        //The subscriber is going away, so stop sending it players
+        Packet::PeerShutdown(packet) => {
+            patchwork_state
+                .remove_map(Peer {
+                    address: packet.peer_address,
+                    port: packet.peer_port,
+                })
+                .or_log();
+        }
+        Packet::MapPositionProposal(packet) => {
+            patchwork_state
+                .propose_map_position(
+                    conn_id,
+                    Peer {
+                        address: packet.peer_address,
+                        port: packet.peer_port,
+                    },
+                    MapPosition {
+                        x: packet.x,
+                        z: packet.z,
+                        dimension: Dimension::from_id(packet.dimension).unwrap_or_default(),
+                    },
+                    packet.entity_id_block,
+                )
+                .or_log();
+        }
+        //The subscriber is handing its map over to us
+        Packet::MapHandoff(packet) => match serde_json::from_str::<Topology>(&packet.topology) {
+            Ok(topology) => {
+                let peer = Peer {
+                    address: packet.peer_address,
+                    port: packet.peer_port,
+                };
+                block_state
+                    .load(topology.dimension(&peer), packet.block_ids)
+                    .or_log();
+                patchwork_state
+                    .adopt_map(Some(peer), topology, None)
+                    .or_log();
+            }
+            Err(e) => warn!("Failed to parse handoff from {:?}: {:?}", conn_id, e),
+        },
+        //The subscriber has recruited us to take half its map, and the players on it, onto a map next
+        //to its own
+        Packet::MapSplit(packet) => match serde_json::from_str::<Topology>(&packet.topology) {
+            Ok(topology) => {
+                block_state
+                    .load(
+                        Dimension::from_id(packet.dimension).unwrap_or_default(),
+                        packet.block_ids,
+                    )
+                    .or_log();
+                let recruiter = Peer {
+                    address: packet.peer_address,
+                    port: packet.peer_port,
+                };
+                patchwork_state
+                    .adopt_map(None, topology, Some((recruiter, packet.entity_id_block)))
+                    .or_log();
+            }
+            Err(e) => warn!("Failed to parse split from {:?}: {:?}", conn_id, e),
+        },
+        Packet::PeerGossip(packet) => {
+            match serde_json::from_str::<Vec<GossipedMap>>(&packet.maps) {
+                Ok(maps) => patchwork_state.merge_gossip(maps).or_log(),
+                Err(e) => warn!("Failed to parse gossip from {:?}: {:?}", conn_id, e),
+            }
+        }
+        //Subscribers can ask us to manage entities on our map on their behalf
+        Packet::SummonEntity(packet) => {
+            entity_state
+                .summon(
+                    packet.entity_type,
+                    Position {
+                        x: packet.x,
+                        y: packet.y,
+                        z: packet.z,
+                    },
+                )
+                .or_log();
+        }
+        Packet::KillEntity(packet) => {
+            entity_state.kill(packet.entity_id).or_log();
+        }
+        //Items carried over the seam onto our map are ours from here on
+        Packet::ItemTransfer(packet) => match DroppedItem::from_item_transfer(packet) {
+            Some(item) => entity_state.drop_item(item).or_log(),
+            None => warn!("Peer {:?} handed over an item without an item", conn_id),
+        },
+        //Subscribers looking for the owner of an entity are told whether we have it either way
+        Packet::EntityOwnerQuery(packet) => {
+            let entity_id =
+                find_local_entity(Uuid::from_u128(packet.uuid), &player_state, &entity_state);
+            messenger
+                .send_packet(
+                    conn_id,
+                    Packet::EntityOwnerReply(EntityOwnerReply {
+                        query_id: packet.query_id,
+                        found: entity_id.is_some(),
+                        entity_id: entity_id.unwrap_or_default(),
+                    }),
+                )
+                .or_log();
+        }
+        Packet::FillBlocks(packet) => {
+            block_state
+                .fill(
+                    BlockPosition {
+                        x: packet.from_x,
+                        y: packet.from_y,
+                        z: packet.from_z,
+                    },
+                    BlockPosition {
+                        x: packet.to_x,
+                        y: packet.to_y,
+                        z: packet.to_z,
+                    },
+                    packet.block_id,
+                )
+                .or_log();
+        }
+        //Everytime a subscriber sends us any other packet, we subscribe them to our messages and
+        //report our state to them
+        _ => {
+            trace!("Reporting state to peer {:?}", conn_id);
 
-    trace!("Reporting state to peer {:?}", conn_id);
+            player_state.report(conn_id).or_log();
+            block_state.report(conn_id).or_log();
+            entity_state.report(conn_id).or_log();
+            game_rules.report(conn_id).or_log();
+            bans.report(conn_id).or_log();
+            world_time.report(conn_id).or_log();
+            return vec![ConnectionUpdate::Subscribe(SubscriberType::Remote)];
+        }
+    }
+    Vec::new()
+}
 
-    messenger.subscribe(conn_id, SubscriberType::Remote);
-    player_state.report(conn_id);
-    block_state.report(conn_id);
+// The id of the player or entity with this UUID, if it's one of ours
+pub fn find_local_entity<P: PlayerState, E: EntityState>(
+    uuid: Uuid,
+    player_state: &P,
+    entity_state: &E,
+) -> Option<i32> {
+    let (reply_sender, reply_receiver) = channel();
+    player_state.find_player(uuid, reply_sender).or_log();
+    if let Ok(Some(entity_id)) = reply_receiver.recv() {
+        return Some(entity_id);
+    }
+    let (reply_sender, reply_receiver) = channel();
+    entity_state.find_entity(uuid, reply_sender).or_log();
+    reply_receiver.recv().ok().flatten()
 }
@@ -0,0 +1,87 @@
+use super::config::PeerQuotas;
+use super::models::identity::Identity;
+use super::models::packet::Packet;
+use super::models::quota::{Admission, PeerUsage, Resource};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+use uuid::Uuid;
+
+// Keeps one busy neighbour from taking over a small instance. What each peer instance costs us is
+// counted across all of its connections, so reconnecting or opening more links doesn't get it any
+// more: packets from its map we pass on, its players anchored here, and how often it asks for our
+// whole state. Relays and state reports over quota are dropped until the peer slows down, and
+// anchors over quota are turned away. Going over and coming back under are both logged. Each node's
+// inbound packet processor keeps its own
+pub struct PeerQuotaLedger {
+    quotas: PeerQuotas,
+    peers: BTreeMap<Uuid, PeerQuota>,
+}
+
+struct PeerQuota {
+    usage: PeerUsage,
+    over: BTreeSet<Resource>,
+}
+
+// What a packet from a peer uses up, if anything. Only relays that are passed along, or that the
+// next one makes up for, are ever dropped. Relative moves add up, so losing one would leave the
+// entity out of place for good, and spawns and despawns can't be missed either
+pub fn resource(state: i32, packet: &Packet) -> Option<Resource> {
+    match (state, packet) {
+        (4, Packet::BorderCrossLogin(_)) => Some(Resource::AnchoredPlayers),
+        (
+            5,
+            Packet::PeerChatMessage(_)
+            | Packet::PeerPluginMessage(_)
+            | Packet::EntityLook(_)
+            | Packet::EntityTeleport(_)
+            | Packet::EntityHeadLook(_)
+            | Packet::EntityVelocity(_),
+        ) => Some(Resource::RelayedPackets),
+        // Subscribers handshake to be sent everything we have, see Map::report
+        (6, Packet::Handshake(_)) => Some(Resource::StateReports),
+        _ => None,
+    }
+}
+
+impl PeerQuotaLedger {
+    pub fn new(quotas: PeerQuotas) -> PeerQuotaLedger {
+        PeerQuotaLedger {
+            quotas,
+            peers: BTreeMap::new(),
+        }
+    }
+
+    pub fn charge(&mut self, instance: &Identity, conn_id: Uuid, resource: Resource) -> Admission {
+        let now = Instant::now();
+        let quotas = self.quotas;
+        let peer = self.peers.entry(instance.id).or_insert_with(|| PeerQuota {
+            usage: PeerUsage::new(quotas, now),
+            over: BTreeSet::new(),
+        });
+        let admission = peer.usage.charge(conn_id, resource, now);
+        match admission {
+            Admission::Allowed => {
+                if peer.over.remove(&resource) {
+                    info!("{} is back within its {:?} quota", instance, resource);
+                }
+            }
+            Admission::Throttled | Admission::Rejected => {
+                if peer.over.insert(resource) {
+                    warn!(
+                        "{} is over its {:?} quota, {:?} until it's back under",
+                        instance, resource, admission
+                    );
+                }
+            }
+        }
+        admission
+    }
+
+    // Lets go of whatever the connection was holding on to
+    pub fn release(&mut self, conn_id: &Uuid) {
+        self.peers
+            .values_mut()
+            .for_each(|peer| peer.usage.release(conn_id));
+    }
+}
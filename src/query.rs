@@ -0,0 +1,287 @@
+use super::constants::{SERVER_DESCRIPTION, SERVER_MAX_CAPACITY, SERVER_VERSION};
+use super::error::OrLog;
+use super::interfaces::patchwork::{self, PatchworkState};
+use super::interfaces::player::{self, PlayerState};
+use super::models::map::Peer;
+use super::services::instance;
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use uuid::Uuid;
+
+// The UDP query protocol (GameSpy4) that server lists and hosting panels use to see who's on, see
+// https://wiki.vg/Query. Each address is handed a challenge token, which it has to send back with
+// its stat requests for a while, so that we can't be used to bounce replies at someone else
+const MAGIC: [u8; 2] = [0xfe, 0xfd];
+const HANDSHAKE: u8 = 9;
+const STAT: u8 = 0;
+
+// How long a challenge token can be used for
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+
+// How long to wait on the services for who's on, before answering with what we have
+const STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    Handshake {
+        session_id: i32,
+    },
+    // Full stat requests are padded out to tell them apart
+    Stat {
+        session_id: i32,
+        token: i32,
+        full: bool,
+    },
+}
+
+// What we tell anyone who asks
+#[derive(Debug, Clone)]
+struct Status {
+    motd: String,
+    map: String,
+    players: Vec<String>,
+    // Whoever owns the maps around ours, in the plugins field as there's nowhere better for them
+    peers: Vec<String>,
+    host_ip: String,
+    host_port: u16,
+}
+
+// Answers queries on the port, one at a time like metrics scrapes. Bound to the same address as the
+// game listener, as the full stat gives away who our peers are
+pub fn serve(
+    port: u16,
+    local_peer: Peer,
+    player_state: Sender<player::Operations>,
+    patchwork_state: Sender<patchwork::Operations>,
+) {
+    let socket = match UdpSocket::bind(("127.0.0.1", port)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("Failed to answer queries on port {}: {}", port, e);
+            return;
+        }
+    };
+    info!("Answering queries on port {}", port);
+    instance::spawn("query", move || {
+        let mut challenges = HashMap::<SocketAddr, (i32, Instant)>::new();
+        let mut buffer = [0; 1500];
+        loop {
+            let (length, address) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) => {
+                    trace!("Failed to receive query: {}", e);
+                    continue;
+                }
+            };
+            let now = Instant::now();
+            challenges.retain(|_, (_, issued)| now.duration_since(*issued) < CHALLENGE_LIFETIME);
+            let reply = match parse(&buffer[..length]) {
+                Some(Request::Handshake { session_id }) => {
+                    let token = Uuid::new_v4().as_u128() as i32;
+                    challenges.insert(address, (token, now));
+                    handshake(session_id, token)
+                }
+                Some(Request::Stat {
+                    session_id,
+                    token,
+                    full,
+                }) if challenges.get(&address).map(|(issued, _)| *issued) == Some(token) => {
+                    let status = status(&local_peer, &player_state, &patchwork_state);
+                    match full {
+                        true => full_stat(session_id, &status),
+                        false => basic_stat(session_id, &status),
+                    }
+                }
+                _ => continue,
+            };
+            if let Err(e) = socket.send_to(&reply, address) {
+                trace!("Failed to answer query from {}: {}", address, e);
+            }
+        }
+    });
+}
+
+fn parse(bytes: &[u8]) -> Option<Request> {
+    let mut cursor = Cursor::new(bytes);
+    let mut magic = [0; 2];
+    cursor.read_exact(&mut magic).ok()?;
+    if magic != MAGIC {
+        return None;
+    }
+    let kind = cursor.read_u8().ok()?;
+    let session_id = cursor.read_i32::<BigEndian>().ok()?;
+    match kind {
+        HANDSHAKE => Some(Request::Handshake { session_id }),
+        STAT => Some(Request::Stat {
+            session_id,
+            token: cursor.read_i32::<BigEndian>().ok()?,
+            full: bytes.len() - cursor.position() as usize >= 4,
+        }),
+        _ => None,
+    }
+}
+
+fn status<P: PlayerState, PA: PatchworkState>(
+    local_peer: &Peer,
+    player_state: &P,
+    patchwork_state: &PA,
+) -> Status {
+    let (reply_sender, reply_receiver) = channel();
+    player_state.online_players(reply_sender).or_log();
+    let online = reply_receiver
+        .recv_timeout(STATUS_TIMEOUT)
+        .unwrap_or_default();
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.describe_maps(reply_sender).or_log();
+    let maps = reply_receiver
+        .recv_timeout(STATUS_TIMEOUT)
+        .unwrap_or_default();
+    let map = maps
+        .iter()
+        .find(|map| map.owner.is_none())
+        .and_then(|map| map.name.clone())
+        .unwrap_or_else(|| String::from("world"));
+    let mut peers: Vec<_> = maps
+        .iter()
+        .filter_map(|map| map.owner.as_ref())
+        .map(|owner| owner.to_string())
+        .collect();
+    peers.sort();
+    peers.dedup();
+    Status {
+        motd: String::from(SERVER_DESCRIPTION),
+        map,
+        players: online.into_iter().map(|(_, name, _, _)| name).collect(),
+        peers,
+        host_ip: local_peer.address.clone(),
+        host_port: local_peer.port,
+    }
+}
+
+// The token's sent back as a decimal string, but comes back in stat requests as an int
+fn handshake(session_id: i32, token: i32) -> Vec<u8> {
+    let mut reply = header(HANDSHAKE, session_id);
+    push_string(&mut reply, &token.to_string());
+    reply
+}
+
+fn basic_stat(session_id: i32, status: &Status) -> Vec<u8> {
+    let mut reply = header(STAT, session_id);
+    for value in [
+        status.motd.as_str(),
+        "SMP",
+        &status.map,
+        &status.players.len().to_string(),
+        &SERVER_MAX_CAPACITY.to_string(),
+    ] {
+        push_string(&mut reply, value);
+    }
+    reply.write_u16::<LittleEndian>(status.host_port).unwrap();
+    push_string(&mut reply, &status.host_ip);
+    reply
+}
+
+// Keys and values, then the players. The padding's fixed, and there only for clients that expect it
+fn full_stat(session_id: i32, status: &Status) -> Vec<u8> {
+    let mut reply = header(STAT, session_id);
+    reply.extend_from_slice(b"splitnum\0\x80\0");
+    let plugins = match status.peers.is_empty() {
+        true => String::from("Patchwork"),
+        false => format!("Patchwork: {}", status.peers.join("; ")),
+    };
+    for (key, value) in [
+        ("hostname", status.motd.clone()),
+        ("gametype", String::from("SMP")),
+        ("game_id", String::from("MINECRAFT")),
+        ("version", String::from(SERVER_VERSION)),
+        ("plugins", plugins),
+        ("map", status.map.clone()),
+        ("numplayers", status.players.len().to_string()),
+        ("maxplayers", SERVER_MAX_CAPACITY.to_string()),
+        ("hostport", status.host_port.to_string()),
+        ("hostip", status.host_ip.clone()),
+    ] {
+        push_string(&mut reply, key);
+        push_string(&mut reply, &value);
+    }
+    reply.extend_from_slice(b"\0\x01player_\0\0");
+    for player in &status.players {
+        push_string(&mut reply, player);
+    }
+    reply.push(0);
+    reply
+}
+
+fn header(kind: u8, session_id: i32) -> Vec<u8> {
+    let mut header = vec![kind];
+    header.write_i32::<BigEndian>(session_id).unwrap();
+    header
+}
+
+fn push_string(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_requests_are_answered_in_the_layout_asked_for() {
+        let handshake_request = [0xfe, 0xfd, 9, 0, 0, 0, 1];
+        assert_eq!(
+            parse(&handshake_request),
+            Some(Request::Handshake { session_id: 1 })
+        );
+        assert_eq!(handshake(1, -42), b"\x09\0\0\0\x01-42\0");
+
+        let basic_request = [0xfe, 0xfd, 0, 0, 0, 0, 1, 0, 0, 0, 7];
+        let full_request = [&basic_request[..], &[0; 4]].concat();
+        assert_eq!(
+            parse(&basic_request),
+            Some(Request::Stat {
+                session_id: 1,
+                token: 7,
+                full: false
+            })
+        );
+        assert_eq!(
+            parse(&full_request),
+            Some(Request::Stat {
+                session_id: 1,
+                token: 7,
+                full: true
+            })
+        );
+        assert_eq!(parse(&[0xfe, 0xfd, 3, 0, 0, 0, 1]), None);
+
+        let status = Status {
+            motd: String::from("Hi"),
+            map: String::from("spawn"),
+            players: vec![String::from("alex"), String::from("steve")],
+            peers: vec![String::from("10.0.0.2:25565")],
+            host_ip: String::from("10.0.0.1"),
+            host_port: 25565,
+        };
+        assert_eq!(
+            basic_stat(1, &status),
+            [
+                &b"\0\0\0\0\x01Hi\0SMP\0spawn\x002\0"[..],
+                SERVER_MAX_CAPACITY.to_string().as_bytes(),
+                b"\0\xdd\x6310.0.0.1\0"
+            ]
+            .concat()
+        );
+        let full = full_stat(1, &status);
+        let full = String::from_utf8_lossy(&full);
+        assert!(full.contains("plugins\0Patchwork: 10.0.0.2:25565\0"));
+        assert!(full.ends_with("\x01player_\0\0alex\0steve\0\0"));
+    }
+}
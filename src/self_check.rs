@@ -0,0 +1,243 @@
+use super::config::Config;
+use super::constants::SERVER_MAX_CAPACITY;
+use super::interfaces::entity_ids::EntityIdRange;
+use super::models::topology::Topology;
+
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::Path;
+
+// Everything wrong with the config that would otherwise only turn up once a service trips over it,
+// each worded so it says what to change. Nothing's changed by checking, apart from briefly binding
+// the outbound address
+pub fn check(config: &Config) -> Vec<String> {
+    let mut problems = vec![];
+    if config.map_size < 1 {
+        problems.push(format!(
+            "map_size is {}, maps have to be at least one chunk wide",
+            config.map_size
+        ));
+    }
+    if config.offline && !config.local_map {
+        problems.push(String::from(
+            "offline is on but local_map is off, offline nodes only have their own map so turn local_map on",
+        ));
+    }
+    check_files(config, &mut problems);
+    check_entity_ids(config, &mut problems);
+    check_pinned_threads(config, &mut problems);
+    check_weather(config, &mut problems);
+    if let Some(address) = config.outbound_bind_address {
+        if let Err(e) = TcpListener::bind((address, 0)) {
+            problems.push(format!(
+                "outbound_bind_address {} can't be bound ({}), it has to be one of this host's",
+                address, e
+            ));
+        }
+    }
+    if let Some(topology_file) = &config.topology_file {
+        match Topology::load(topology_file) {
+            Ok(topology) => check_topology(&topology, &mut problems),
+            Err(e) => problems.push(e),
+        }
+    }
+    problems
+}
+
+// Files are created as they're saved, but the directories they go in have to be there already
+fn check_files(config: &Config, problems: &mut Vec<String>) {
+    let files = [
+        ("world_file", &config.world_file),
+        ("advancements_file", &config.advancements_file),
+        ("instance_id_file", &config.instance_id_file),
+        ("players_directory", &config.players_directory),
+        ("bans_file", &config.bans_file),
+        ("operators_file", &config.operators_file),
+        ("whitelist.file", &config.whitelist.file),
+    ];
+    for (setting, file) in files {
+        let directory = match Path::new(file).parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => continue,
+        };
+        if !directory.is_dir() {
+            problems.push(format!(
+                "{} {} is in {}, which doesn't exist",
+                setting,
+                file,
+                directory.display()
+            ));
+        }
+    }
+}
+
+// Each node's block of entity ids is split into ranges, which mustn't overlap or be outgrown
+fn check_entity_ids(config: &Config, problems: &mut Vec<String>) {
+    let ranges = EntityIdRange::all();
+    for (index, range) in ranges.iter().enumerate() {
+        let (start, end) = range.bounds();
+        for other in &ranges[index + 1..] {
+            let (other_start, other_end) = other.bounds();
+            if start < other_end && other_start < end {
+                problems.push(format!(
+                    "Entity ids for {:?} ({}..{}) overlap those for {:?} ({}..{})",
+                    range, start, end, other, other_start, other_end
+                ));
+            }
+        }
+    }
+    let size = |range: EntityIdRange| {
+        let (start, end) = range.bounds();
+        (end - start) as usize
+    };
+    if usize::from(SERVER_MAX_CAPACITY) > size(EntityIdRange::Player) {
+        problems.push(format!(
+            "There are only {} player entity ids for {} players",
+            size(EntityIdRange::Player),
+            SERVER_MAX_CAPACITY
+        ));
+    }
+    if config.peer_quotas.anchored_players > size(EntityIdRange::AnchoredPlayer) {
+        problems.push(format!(
+            "peer_quotas.anchored_players is {}, but there are only {} anchored player entity ids",
+            config.peer_quotas.anchored_players,
+            size(EntityIdRange::AnchoredPlayer)
+        ));
+    }
+}
+
+fn check_pinned_threads(config: &Config, problems: &mut Vec<String>) {
+    let cores = core_affinity::get_core_ids().map_or(0, |cores| cores.len());
+    for (thread, core) in &config.pinned_threads {
+        if *core >= cores {
+            problems.push(format!(
+                "pinned_threads has {} on core {}, but there are only {} cores to pin to",
+                thread, core, cores
+            ));
+        }
+    }
+}
+
+fn check_weather(config: &Config, problems: &mut Vec<String>) {
+    let weather = &config.weather;
+    for (spell, range) in [
+        ("clear", weather.clear),
+        ("rain", weather.rain),
+        ("thunder", weather.thunder),
+    ] {
+        if range.min < 1 || range.max < range.min {
+            problems.push(format!(
+                "weather.{} lasts from {} to {} ticks, it has to be at least 1 and min can't be over max",
+                spell, range.min, range.max
+            ));
+        }
+    }
+    if weather.thunder_percent > 100 {
+        problems.push(format!(
+            "weather.thunder_percent is {}, it can't be over 100",
+            weather.thunder_percent
+        ));
+    }
+}
+
+// Every map needs a place of its own, and every node can only own one map
+fn check_topology(topology: &Topology, problems: &mut Vec<String>) {
+    let mut names = HashMap::new();
+    let mut positions = HashMap::new();
+    let mut owners = HashMap::new();
+    for map in &topology.maps {
+        if let Some(other) = names.insert(&map.name, map) {
+            problems.push(format!(
+                "Maps owned by {} and {} are both called {}, rename one of them",
+                other.owner, map.owner, map.name
+            ));
+        }
+        if let Some(other) = positions.insert((map.position.x, map.position.z), map) {
+            problems.push(format!(
+                "Maps {} and {} are both at {:?}, move one of them",
+                other.name, map.name, map.position
+            ));
+        }
+        if let Some(other) = owners.insert(&map.owner, map) {
+            problems.push(format!(
+                "{} owns both {} and {}, give one of them to another node",
+                map.owner, other.name, map.name
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WeatherConfig;
+    use crate::models::map::{Peer, Position};
+    use crate::models::topology::TopologyMap;
+
+    fn map(name: &str, port: u16, x: i32) -> TopologyMap {
+        TopologyMap {
+            name: String::from(name),
+            owner: Peer {
+                address: String::from("127.0.0.1"),
+                port,
+            },
+            position: Position {
+                x,
+                ..Position::default()
+            },
+            seed: None,
+        }
+    }
+
+    #[test]
+    fn the_default_config_is_fine() {
+        assert_eq!(check(&Config::default()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn mistakes_are_all_reported_at_once() {
+        let config = Config {
+            map_size: 0,
+            world_file: String::from("/nowhere/at/all/world.json"),
+            weather: WeatherConfig {
+                thunder_percent: 101,
+                ..WeatherConfig::default()
+            },
+            ..Config::default()
+        };
+        let problems = check(&config);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("map_size"));
+        assert!(problems[1].starts_with("world_file"));
+        assert!(problems[2].contains("thunder_percent"));
+    }
+
+    #[test]
+    fn offline_nodes_need_a_map_of_their_own() {
+        let config = Config {
+            offline: true,
+            local_map: false,
+            ..Config::default()
+        };
+        let problems = check(&config);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("offline"));
+        assert!(check(&Config {
+            local_map: true,
+            ..config
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn maps_cant_share_a_position_or_an_owner() {
+        let topology = Topology {
+            maps: vec![map("a", 1, 0), map("b", 2, 0), map("c", 2, 1)],
+        };
+        let mut problems = vec![];
+        check_topology(&topology, &mut problems);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("both at"));
+        assert!(problems[1].contains("owns both b and c"));
+    }
+}
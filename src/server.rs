@@ -1,17 +1,39 @@
+use super::config::{ConnectionLimits, ProxyConfig, ProxyProtocol};
+use super::constants::{
+    PLAYER_MAX_PACKET_LENGTH, SERVER_DESCRIPTION, SERVER_FULL_MESSAGE, SERVER_MAX_CAPACITY,
+    SERVER_PROTOCOL, SERVER_VERSION,
+};
+use super::error::{OrLog, PatchworkError};
 use super::interfaces::connection::ConnectionService;
-use super::interfaces::messenger::Messenger;
+use super::interfaces::messenger::{ConnectionClass, Messenger};
 use super::interfaces::packet_processor::PacketProcessor;
 
+use super::models::compression;
+use super::models::map::map_size;
 use super::models::minecraft_protocol::MinecraftProtocolReader;
+use super::models::minecraft_types::{self, ChatComponent, Description, PingPlayersInfo, Version};
+use super::models::packet::{self, LoginDisconnect, Packet, StatusResponse};
+use super::models::uuid_source::UuidSource;
+use super::services::instance;
 
-use std::env;
-use std::io::ErrorKind::{ConnectionReset, UnexpectedEof};
-use std::io::{Cursor, Error, Read};
-use std::net::{TcpListener, TcpStream};
-use std::thread;
+use std::cmp::min;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind::{
+    AddrNotAvailable, ConnectionRefused, ConnectionReset, InvalidData, InvalidInput, UnexpectedEof,
+};
+use std::io::{self, Cursor, Error, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, sync_channel, SyncSender};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread::sleep;
 use std::time;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use socket2::{Domain, Protocol, Socket, Type};
 use uuid::Uuid;
 
 pub fn listen<
@@ -19,31 +41,191 @@ pub fn listen<
     PP: 'static + PacketProcessor + Clone + Send,
     CS: 'static + ConnectionService + Clone + Send,
 >(
+    port: u16,
     inbound_packet_processor: PP,
     connection_service: CS,
     messenger: M,
-) {
-    let connection_string = format!("127.0.0.1:{}", env::var("PORT").unwrap());
-    let listener = TcpListener::bind(connection_string.clone()).unwrap();
+    uuids: UuidSource,
+    limits: ConnectionLimits,
+) -> Result<(), PatchworkError> {
+    let connection_string = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(connection_string.clone())?;
 
     trace!("Listening on {:?}", connection_string);
+    let counts = Arc::new(Mutex::new(ConnectionCounts::default()));
+    let refusals = start_refusing();
 
     for stream in listener.incoming() {
-        let stream = stream.unwrap();
+        if STOPPED_LISTENING.lock().unwrap().contains(&port) {
+            trace!("No longer listening on {:?}", connection_string);
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(
+                    "Failed to accept a connection on {:?}: {:?}",
+                    connection_string, e
+                );
+                continue;
+            }
+        };
+        let address = match stream.peer_addr() {
+            Ok(address) => address.ip(),
+            Err(_) => continue,
+        };
+        let slot = match counts.lock().unwrap().admit(address, &limits) {
+            Ok(()) => ConnectionSlot {
+                address,
+                counts: counts.clone(),
+            },
+            Err(reason) => {
+                warn!("Refusing a connection from {}: {}", address, reason);
+                // The refuser's already got plenty to get through, so this one isn't told why
+                let _ = refusals.try_send(stream);
+                continue;
+            }
+        };
         let inbound_packet_processor_clone = inbound_packet_processor.clone();
         let messenger_clone = messenger.clone();
         let closure_connection_service = connection_service.clone();
-        let conn_id = Uuid::new_v4();
-        thread::spawn(move || {
+        let conn_id = uuids.next();
+        // Connections can't go anywhere without the packet processor, so there's no point taking
+        // any more
+        inbound_packet_processor.accept(conn_id, address)?;
+        let name = format!("player-{}", &conn_id.to_simple().to_string()[..8]);
+        instance::spawn(&name, move || {
+            let _slot = slot;
             handle_connection(
                 stream,
                 inbound_packet_processor_clone,
                 messenger_clone,
                 conn_id,
-                || closure_connection_service.close(conn_id),
+                ConnectionClass::Player,
+                || closure_connection_service.close(conn_id).or_log(),
             );
         });
     }
+    Ok(())
+}
+
+// Open connections, in all and from each address, so that a flood of them can't spawn a thread each
+// without end
+#[derive(Debug, Default)]
+struct ConnectionCounts {
+    total: usize,
+    per_address: HashMap<IpAddr, usize>,
+}
+
+impl ConnectionCounts {
+    fn admit(&mut self, address: IpAddr, limits: &ConnectionLimits) -> Result<(), String> {
+        let from_address = self.per_address.get(&address).copied().unwrap_or(0);
+        if self.total >= limits.max_connections {
+            return Err(format!("{} connections are open", self.total));
+        }
+        let unlimited = address.is_loopback() || limits.unlimited_addresses.contains(&address);
+        if !unlimited && from_address >= limits.max_per_address {
+            return Err(format!("{} connections are open from it", from_address));
+        }
+        self.total += 1;
+        self.per_address.insert(address, from_address + 1);
+        Ok(())
+    }
+
+    fn release(&mut self, address: IpAddr) {
+        self.total = self.total.saturating_sub(1);
+        if let Some(count) = self.per_address.get_mut(&address) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_address.remove(&address);
+            }
+        }
+    }
+}
+
+// Held for as long as the connection's open
+struct ConnectionSlot {
+    address: IpAddr,
+    counts: Arc<Mutex<ConnectionCounts>>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.counts.lock().unwrap().release(self.address);
+    }
+}
+
+// Connections over the limits are told so by a single thread of their own, however many there are.
+// Those it can't get to are closed straight away
+const REFUSAL_BACKLOG: usize = 32;
+const REFUSAL_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+fn start_refusing() -> SyncSender<TcpStream> {
+    let (refusals, streams) = sync_channel::<TcpStream>(REFUSAL_BACKLOG);
+    instance::spawn("refuser", move || {
+        for stream in streams {
+            let _ = refuse(stream);
+        }
+    });
+    refusals
+}
+
+// Players logging in are disconnected with the reason, and players pinging us see it in the server
+// list. Anyone else, peers included, only finds the connection closed
+fn refuse(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REFUSAL_TIMEOUT))?;
+    stream.set_write_timeout(Some(REFUSAL_TIMEOUT))?;
+    let length = stream.read_var_int()?;
+    if !(0..=PLAYER_MAX_PACKET_LENGTH).contains(&length) {
+        return Ok(());
+    }
+    let mut handshake = vec![0; length as usize];
+    stream.read_exact(&mut handshake)?;
+    let next_state = match packet::read(&mut Cursor::new(handshake), 0) {
+        Ok(Packet::Handshake(handshake)) => handshake.next_state,
+        _ => return Ok(()),
+    };
+    match next_state {
+        1 => {
+            let status = minecraft_types::StatusResponse {
+                version: Version {
+                    name: String::from(SERVER_VERSION),
+                    protocol: SERVER_PROTOCOL,
+                },
+                players: PingPlayersInfo {
+                    max: SERVER_MAX_CAPACITY,
+                    online: SERVER_MAX_CAPACITY,
+                    sample: vec![],
+                },
+                description: Description {
+                    text: String::from(SERVER_FULL_MESSAGE),
+                },
+            };
+            packet::write(
+                &mut stream,
+                Packet::StatusResponse(StatusResponse {
+                    json_response: serde_json::to_string(&status).unwrap(),
+                }),
+            );
+        }
+        2 => packet::write(
+            &mut stream,
+            Packet::LoginDisconnect(LoginDisconnect {
+                reason: ChatComponent::new(SERVER_FULL_MESSAGE).to_json(),
+            }),
+        ),
+        _ => {}
+    }
+    stream.flush()
+}
+
+// Ports whose node is shutting down. The listener only notices once it accepts its next connection,
+// so it's given one to wake it up
+static STOPPED_LISTENING: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+
+pub fn stop_listening(port: u16) {
+    STOPPED_LISTENING.lock().unwrap().push(port);
+    let _ = TcpStream::connect(format!("127.0.0.1:{}", port));
 }
 
 pub fn handle_connection<M: Messenger, PP: PacketProcessor, F: Fn()>(
@@ -51,59 +233,768 @@ pub fn handle_connection<M: Messenger, PP: PacketProcessor, F: Fn()>(
     inbound_packet_processor: PP,
     messenger: M,
     conn_id: Uuid,
+    class: ConnectionClass,
     on_closure: F,
 ) {
-    let stream_clone = stream.try_clone().unwrap();
-    messenger.new_connection(conn_id, stream_clone);
+    let stream_clone = match stream.try_clone() {
+        Ok(stream_clone) => stream_clone,
+        Err(source) => {
+            warn!("{}", PatchworkError::Connection { conn_id, source });
+            on_closure();
+            return;
+        }
+    };
+    if class == ConnectionClass::Player && is_legacy_ping(&stream) {
+        let (reply, online) = channel();
+        inbound_packet_processor.legacy_ping(reply).or_log();
+        let online = online
+            .recv_timeout(LEGACY_PING_TIMEOUT)
+            .map_or(0, |players| players.len());
+        if let Err(e) = answer_legacy_ping(&mut stream, online) {
+            trace!(
+                "Failed to answer legacy ping from conn_id {:?}: {}",
+                conn_id,
+                e
+            );
+        }
+        on_closure();
+        return;
+    }
+    messenger
+        .new_connection(conn_id, stream_clone, class)
+        .or_log();
+    let mut class = class;
+    let mut handshaken = false;
     loop {
-        match stream.try_read_var_int() {
-            Ok(length) => {
-                let vec: Vec<u8> = (&stream)
-                    .bytes()
-                    .take(length as usize)
-                    .map(|r: Result<u8, _>| {
-                        r.expect("packet was smaller than length field indicated!")
-                    })
-                    .collect();
-                let cursor = Cursor::new(vec);
-                inbound_packet_processor.inbound(conn_id, cursor);
+        let max_length = class.policy().max_packet_length;
+        let length = match stream.read_var_int() {
+            Ok(length) if !(0..=max_length).contains(&length) => {
+                reject(
+                    &mut stream,
+                    &inbound_packet_processor,
+                    conn_id,
+                    format!("Packet length {} is out of bounds", length),
+                );
+                break;
+            }
+            Ok(length) => length,
+            Err(e) if e.kind() == InvalidData => {
+                reject(
+                    &mut stream,
+                    &inbound_packet_processor,
+                    conn_id,
+                    e.to_string(),
+                );
+                break;
             }
             Err(e) => {
-                match e.kind() {
-                    UnexpectedEof => on_closure(),
-                    ConnectionReset => on_closure(),
-                    _ => {
-                        panic!("conn closed due to {:?}", e);
-                    }
-                };
+                if e.kind() != UnexpectedEof && e.kind() != ConnectionReset {
+                    warn!(
+                        "Closing conn_id {:?} after failing to read from it: {:?}",
+                        conn_id, e
+                    );
+                }
                 break;
             }
+        };
+        let mut packet = Vec::new();
+        if let Err(e) = (&mut stream).take(length as u64).read_to_end(&mut packet) {
+            if e.kind() != ConnectionReset {
+                warn!(
+                    "Closing conn_id {:?} after failing to read from it: {:?}",
+                    conn_id, e
+                );
+            }
+            break;
+        }
+        if packet.len() < length as usize {
+            // The connection's gone, and with it the rest of the packet
+            break;
+        }
+        // Handshakes are never compressed, as that's what decides whether the rest will be
+        if class.policy().compression_threshold.is_some() {
+            packet = match compression::decompress(packet, max_length) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    reject(
+                        &mut stream,
+                        &inbound_packet_processor,
+                        conn_id,
+                        e.to_string(),
+                    );
+                    break;
+                }
+            };
+        }
+        // Peers connecting to us only say that's what they are in their handshake. The messenger's
+        // told straight away, so that nothing's written to them uncompressed after it
+        if !handshaken {
+            handshaken = true;
+            if class == ConnectionClass::Player && is_peer_handshake(&packet) {
+                class = ConnectionClass::PeerLink;
+                messenger.classify(conn_id, class).or_log();
+            }
+        }
+        if let Err(e) =
+            inbound_packet_processor.inbound(conn_id, Cursor::new(packet), time::Instant::now())
+        {
+            error!("Closing conn_id {:?}: {}", conn_id, e);
+            break;
         }
     }
+    on_closure();
+}
+
+fn is_peer_handshake(packet: &[u8]) -> bool {
+    matches!(
+        packet::read(&mut Cursor::new(packet), 0),
+        Ok(Packet::Handshake(handshake)) if matches!(handshake.next_state, 4 | 6)
+    )
+}
+
+// Clients from before 1.7, and some monitoring tools, open with 0xFE rather than a packet length.
+// Those from 1.4 on follow it with 0x01, and are told more than the older ones
+const LEGACY_PING: u8 = 0xFE;
+const LEGACY_PING_EXTENDED: u8 = 0x01;
+// They're answered with a kick, whose reason is the server's status
+const LEGACY_KICK: u8 = 0xFF;
+const LEGACY_PING_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+fn is_legacy_ping(stream: &TcpStream) -> bool {
+    let mut first = [0];
+    matches!(stream.peek(&mut first), Ok(1) if first[0] == LEGACY_PING)
+}
+
+fn answer_legacy_ping(stream: &mut TcpStream, online: usize) -> io::Result<()> {
+    let mut ping = [0; 2];
+    stream.read_exact(&mut ping[..1])?;
+    // The oldest clients send nothing after 0xFE, so we don't wait long for the rest
+    stream.set_read_timeout(Some(LEGACY_PING_TIMEOUT))?;
+    let extended = matches!(stream.read(&mut ping[1..]), Ok(1) if ping[1] == LEGACY_PING_EXTENDED);
+    stream.write_all(&legacy_status(extended, online))?;
+    stream.flush()
+}
+
+// A kick packet, with its reason as a length in UTF-16 code units and then the code units
+fn legacy_status(extended: bool, online: usize) -> Vec<u8> {
+    let status = if extended {
+        format!(
+            "\u{a7}1\0{}\0{}\0{}\0{}\0{}",
+            SERVER_PROTOCOL, SERVER_VERSION, SERVER_DESCRIPTION, online, SERVER_MAX_CAPACITY
+        )
+    } else {
+        format!(
+            "{}\u{a7}{}\u{a7}{}",
+            SERVER_DESCRIPTION, online, SERVER_MAX_CAPACITY
+        )
+    };
+    let units: Vec<u16> = status.encode_utf16().collect();
+    let mut kick = vec![LEGACY_KICK];
+    kick.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    units
+        .iter()
+        .for_each(|unit| kick.extend_from_slice(&unit.to_be_bytes()));
+    kick
 }
 
-//Just doing a simple linear backoff for now, probably want something a little more sophisticated
-//eventually
-pub fn wait_for_connection<F: FnOnce(TcpStream)>(
+// Once a connection's sent something we can't make sense of, we can't tell where its next packet
+// starts, so nothing else is read from it. The packet processor decides how to let it go, and we
+// wait for the socket to be shut before the connection is cleaned up
+fn reject<PP: PacketProcessor>(
+    stream: &mut TcpStream,
+    inbound_packet_processor: &PP,
+    conn_id: Uuid,
+    reason: String,
+) {
+    inbound_packet_processor.reject(conn_id, reason).or_log();
+    let _ = io::copy(stream, &mut io::sink());
+}
+
+pub struct RetryPolicy {
+    pub initial_backoff: time::Duration,
+    pub max_backoff: time::Duration,
+    pub max_attempts: Option<u32>,
+}
+
+// Peers may take a while to come up, so keep trying for a few minutes before giving up
+pub const PEER_MAP_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    initial_backoff: time::Duration::from_secs(1),
+    max_backoff: time::Duration::from_secs(30),
+    max_attempts: Some(12),
+};
+
+// Anchors are established while a player is waiting, so don't hold them up for long
+pub const ANCHOR_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    initial_backoff: time::Duration::from_millis(50),
+    max_backoff: time::Duration::from_millis(200),
+    max_attempts: Some(3),
+};
+
+// Players whose anchor couldn't be established are routed locally meanwhile, and it's tried again
+// for as long as they stay on the map
+pub const REANCHOR_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    initial_backoff: time::Duration::from_secs(1),
+    max_backoff: time::Duration::from_secs(30),
+    max_attempts: None,
+};
+
+impl RetryPolicy {
+    // Exponential backoff capped at max_backoff, plus up to 50% random jitter so that several
+    // nodes retrying against the same peer don't all hit it at the same moment
+    pub fn backoff(&self, attempt: u32) -> time::Duration {
+        let backoff = self
+            .initial_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_backoff, |backoff| min(backoff, self.max_backoff));
+        let jitter_range = backoff.as_millis() as u64 / 2 + 1;
+        let jitter = RandomState::new().build_hasher().finish() % jitter_range;
+        backoff + time::Duration::from_millis(jitter)
+    }
+}
+
+pub fn wait_for_connection<F: FnOnce(TcpStream), G: FnOnce(Error)>(
     peer_address: String,
     peer_port: u16,
+    policy: &RetryPolicy,
     on_connection: F,
+    on_failure: G,
 ) {
-    let backoff = 1;
-    loop {
-        if let Ok(connection) = new_connection(peer_address.clone(), peer_port) {
+    match connect_with_retry(peer_address, peer_port, policy) {
+        Ok(connection) => {
             trace!("Connection Established");
             on_connection(connection);
-            break;
-        } else {
-            let backoff = if backoff < 10 { backoff + 1 } else { backoff };
-            trace!("Failed to connect- retrying in {:?}s", backoff);
-            sleep(time::Duration::from_secs(backoff));
         }
+        Err(e) => on_failure(e),
     }
 }
 
+pub fn connect_with_retry(
+    peer_address: String,
+    peer_port: u16,
+    policy: &RetryPolicy,
+) -> Result<TcpStream, Error> {
+    let mut attempt = 0;
+    loop {
+        match new_connection(peer_address.clone(), peer_port) {
+            Ok(connection) => return Ok(connection),
+            Err(e) => {
+                attempt += 1;
+                match policy.max_attempts {
+                    Some(max_attempts) if attempt >= max_attempts => return Err(e),
+                    _ => {}
+                }
+                let backoff = policy.backoff(attempt - 1);
+                trace!("Failed to connect- retrying in {:?}", backoff);
+                sleep(backoff);
+            }
+        }
+    }
+}
+
+// How long a peer gets to send us a challenge once we've asked it for a peer state
+const PEER_CHALLENGE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+// Asks the peer on the other end of a new connection for a peer state, returning the challenge it
+// sends back for our credentials to be signed over, as long as its maps are the same size as ours.
+// Done on the stream itself, before anything else gets to read from it
+pub fn request_peer_state(stream: &mut TcpStream, next_state: i32) -> Result<u128, Error> {
+    packet::write(
+        stream,
+        Packet::Handshake(packet::Handshake {
+            protocol_version: SERVER_PROTOCOL as i32,
+            server_address: String::new(),
+            server_port: 0,
+            next_state,
+        }),
+    );
+    stream.flush()?;
+    stream.set_read_timeout(Some(PEER_CHALLENGE_TIMEOUT))?;
+    let length = stream.read_var_int()?;
+    if !(0..=PLAYER_MAX_PACKET_LENGTH).contains(&length) {
+        return Err(Error::new(
+            InvalidData,
+            format!("Challenge length {} is out of bounds", length),
+        ));
+    }
+    let mut challenge = vec![0; length as usize];
+    stream.read_exact(&mut challenge)?;
+    stream.set_read_timeout(None)?;
+    // Everything after our handshake comes compressed, like the rest of the link
+    let challenge = compression::decompress(challenge, PLAYER_MAX_PACKET_LENGTH)?;
+    match packet::read(&mut Cursor::new(challenge), 5) {
+        Ok(Packet::PeerAuthChallenge(packet)) if packet.map_size != map_size() => Err(Error::new(
+            InvalidData,
+            format!(
+                "Peer has maps {} chunks wide, ours are {}",
+                packet.map_size,
+                map_size()
+            ),
+        )),
+        Ok(Packet::PeerAuthChallenge(packet)) => Ok(packet.challenge),
+        _ => Err(Error::new(InvalidData, "Peer did not send a challenge")),
+    }
+}
+
+// On multi-homed hosts the peer network can live on a different interface than the one players
+// connect through, so outbound connections can be bound to a specific local address. Set once at
+// startup
+static OUTBOUND_BIND_ADDRESS: OnceLock<IpAddr> = OnceLock::new();
+
+pub fn set_outbound_bind_address(address: IpAddr) {
+    if OUTBOUND_BIND_ADDRESS.set(address).is_err() {
+        warn!("Outbound bind address is already set");
+    }
+}
+
+// Peers inside restricted networks may only be reachable through a bastion, so outbound peer
+// connections can be tunnelled through a SOCKS5 or HTTP CONNECT proxy. Set once at startup
+static PEER_PROXY: OnceLock<ProxyConfig> = OnceLock::new();
+
+pub fn set_peer_proxy(proxy: ProxyConfig) {
+    if PEER_PROXY.set(proxy).is_err() {
+        warn!("Peer proxy is already set");
+    }
+}
+
+// How long a proxy gets to connect us through to a peer before we give up on it
+const PROXY_HANDSHAKE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
 pub fn new_connection(peer_address: String, peer_port: u16) -> Result<TcpStream, Error> {
-    let peer_info = format!("{}:{}", peer_address, peer_port.to_string());
-    TcpStream::connect(peer_info)
+    let proxy = match PEER_PROXY.get() {
+        Some(proxy) => proxy,
+        None => return new_direct_connection(peer_address, peer_port),
+    };
+    let mut stream =
+        new_direct_connection_within(proxy.address.clone(), proxy.port, PROXY_HANDSHAKE_TIMEOUT)?;
+    match proxy.protocol {
+        ProxyProtocol::Socks5 => socks5_connect(&mut stream, proxy, &peer_address, peer_port)?,
+        ProxyProtocol::Http => http_connect(&mut stream, proxy, &peer_address, peer_port)?,
+    }
+    // From here on it's a peer connection like any other, which can go quiet for as long as it likes
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    trace!(
+        "Tunnelled to {}:{} through proxy {}:{}",
+        peer_address,
+        peer_port,
+        proxy.address,
+        proxy.port
+    );
+    Ok(stream)
+}
+
+// Everything SOCKS5 sends the length of, it sends in a single byte
+fn socks5_field(field: &str, name: &str) -> Result<Vec<u8>, Error> {
+    let length = u8::try_from(field.len()).map_err(|_| {
+        Error::new(
+            InvalidInput,
+            format!("SOCKS5 {} can be at most 255 bytes long", name),
+        )
+    })?;
+    let mut bytes = vec![length];
+    bytes.extend(field.as_bytes());
+    Ok(bytes)
+}
+
+// See RFC 1928 and RFC 1929
+fn socks5_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    peer_address: &str,
+    peer_port: u16,
+) -> Result<(), Error> {
+    let credentials = match (&proxy.username, &proxy.password) {
+        (Some(username), Some(password)) => Some((username, password)),
+        _ => None,
+    };
+    match credentials {
+        Some(_) => stream.write_all(&[5, 2, 0, 2])?,
+        None => stream.write_all(&[5, 1, 0])?,
+    }
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    match (reply, credentials) {
+        ([5, 0], _) => {}
+        ([5, 2], Some((username, password))) => {
+            let mut request = vec![1];
+            request.extend(socks5_field(username, "usernames")?);
+            request.extend(socks5_field(password, "passwords")?);
+            stream.write_all(&request)?;
+            stream.read_exact(&mut reply)?;
+            if reply[1] != 0 {
+                return Err(Error::new(
+                    ConnectionRefused,
+                    "SOCKS5 proxy rejected our credentials",
+                ));
+            }
+        }
+        _ => {
+            return Err(Error::new(
+                ConnectionRefused,
+                "SOCKS5 proxy accepted none of our authentication methods",
+            ))
+        }
+    }
+
+    let mut request = vec![5, 1, 0];
+    match peer_address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) => {
+            request.push(1);
+            request.extend(&address.octets());
+        }
+        Ok(IpAddr::V6(address)) => {
+            request.push(4);
+            request.extend(&address.octets());
+        }
+        Err(_) => {
+            request.push(3);
+            request.extend(socks5_field(peer_address, "domain names")?);
+        }
+    }
+    request.extend(&peer_port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(Error::new(
+            ConnectionRefused,
+            format!("SOCKS5 proxy failed to connect with reply {}", reply[1]),
+        ));
+    }
+    // Skip over the address the proxy bound for us, we have no use for it
+    let bound_address_length = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut length = [0; 1];
+            stream.read_exact(&mut length)?;
+            length[0] as usize
+        }
+        _ => {
+            return Err(Error::new(
+                InvalidData,
+                "SOCKS5 proxy replied with an unknown address type",
+            ))
+        }
+    };
+    stream.read_exact(&mut vec![0; bound_address_length + 2])
+}
+
+fn http_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    peer_address: &str,
+    peer_port: u16,
+) -> Result<(), Error> {
+    let authority = match peer_address.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]:{}", peer_address, peer_port),
+        _ => format!("{}:{}", peer_address, peer_port),
+    };
+    let authorization = match (&proxy.username, &proxy.password) {
+        (Some(username), Some(password)) => format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            BASE64.encode(format!("{}:{}", username, password))
+        ),
+        _ => String::new(),
+    };
+    let request = format!(
+        "CONNECT {} HTTP/1.1\r\nHost: {}\r\n{}\r\n",
+        authority, authority, authorization
+    );
+    stream.write_all(request.as_bytes())?;
+
+    // Read the response a byte at a time so we don't swallow anything the peer sends after it
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte)?;
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        _ => Err(Error::new(
+            ConnectionRefused,
+            format!("HTTP proxy refused to connect: {}", status_line),
+        )),
+    }
+}
+
+pub fn new_direct_connection(peer_address: String, peer_port: u16) -> Result<TcpStream, Error> {
+    direct_connection(peer_address, peer_port, None)
+}
+
+// Like new_direct_connection, but gives up on connecting, reading and writing after the timeout,
+// for services that can't afford to hang on whoever's at the other end
+pub fn new_direct_connection_within(
+    peer_address: String,
+    peer_port: u16,
+    timeout: time::Duration,
+) -> Result<TcpStream, Error> {
+    let stream = direct_connection(peer_address, peer_port, Some(timeout))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+fn direct_connection(
+    peer_address: String,
+    peer_port: u16,
+    timeout: Option<time::Duration>,
+) -> Result<TcpStream, Error> {
+    let peer_info = format!("{}:{}", peer_address, peer_port);
+    let bind_address = OUTBOUND_BIND_ADDRESS.get().copied();
+    if bind_address.is_none() && timeout.is_none() {
+        return TcpStream::connect(peer_info);
+    }
+    let mut last_error = Error::new(AddrNotAvailable, "Peer address did not resolve");
+    for address in peer_info.to_socket_addrs()? {
+        if bind_address.is_some_and(|bind_address| address.is_ipv4() != bind_address.is_ipv4()) {
+            continue;
+        }
+        let result = Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )
+        .and_then(|socket| {
+            if let Some(bind_address) = bind_address {
+                socket.bind(&SocketAddr::new(bind_address, 0).into())?;
+            }
+            match timeout {
+                Some(timeout) => socket.connect_timeout(&address.into(), timeout)?,
+                None => socket.connect(&address.into())?,
+            }
+            Ok(socket.into())
+        });
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::protocol_adapter::ProtocolAdapter;
+    use std::net::Ipv4Addr;
+    use std::thread;
+
+    // Connects to a proxy that goes through the script on its end of the connection
+    fn scripted_proxy<F: FnOnce(TcpStream) + Send + 'static>(script: F) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || script(listener.accept().unwrap().0));
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    fn proxy_config(protocol: ProxyProtocol, username: &str) -> ProxyConfig {
+        ProxyConfig {
+            protocol,
+            address: String::from("127.0.0.1"),
+            port: 0,
+            username: Some(String::from(username)),
+            password: Some(String::from("secret")),
+        }
+    }
+
+    fn expect(stream: &mut TcpStream, expected: &[u8]) {
+        let mut received = vec![0; expected.len()];
+        stream.read_exact(&mut received).unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn socks5_proxies_are_logged_into_and_asked_to_connect() {
+        let mut stream = scripted_proxy(|mut proxy| {
+            expect(&mut proxy, &[5, 2, 0, 2]);
+            proxy.write_all(&[5, 2]).unwrap();
+            expect(&mut proxy, b"\x01\x05alice\x06secret");
+            proxy.write_all(&[1, 0]).unwrap();
+            expect(&mut proxy, b"\x05\x01\x00\x03\x0cpeer.example\x63\xdd");
+            proxy.write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0, 80]).unwrap();
+            proxy.write_all(b"peer").unwrap();
+        });
+        let proxy = proxy_config(ProxyProtocol::Socks5, "alice");
+        socks5_connect(&mut stream, &proxy, "peer.example", 25565).unwrap();
+        // Whatever the peer sends straight away is left for us to read
+        expect(&mut stream, b"peer");
+
+        let mut stream = scripted_proxy(|mut proxy| {
+            expect(&mut proxy, &[5, 2, 0, 2]);
+            proxy.write_all(&[5, 2]).unwrap();
+            let _ = proxy.read(&mut [0; 1]);
+        });
+        let proxy = proxy_config(ProxyProtocol::Socks5, &"a".repeat(256));
+        assert_eq!(
+            socks5_connect(&mut stream, &proxy, "peer.example", 25565)
+                .unwrap_err()
+                .kind(),
+            InvalidInput
+        );
+
+        let mut stream = scripted_proxy(|mut proxy| {
+            expect(&mut proxy, &[5, 2, 0, 2]);
+            proxy.write_all(&[5, 0xFF]).unwrap();
+        });
+        let proxy = proxy_config(ProxyProtocol::Socks5, "alice");
+        assert_eq!(
+            socks5_connect(&mut stream, &proxy, "10.0.0.2", 25565)
+                .unwrap_err()
+                .kind(),
+            ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn http_proxies_are_asked_to_connect() {
+        let request = |reply: &'static [u8]| {
+            scripted_proxy(move |mut proxy| {
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0; 1];
+                    proxy.read_exact(&mut byte).unwrap();
+                    request.push(byte[0]);
+                }
+                assert_eq!(
+                    String::from_utf8(request).unwrap(),
+                    "CONNECT [::1]:25565 HTTP/1.1\r\nHost: [::1]:25565\r\n\
+                     Proxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n"
+                );
+                proxy.write_all(reply).unwrap();
+            })
+        };
+        let proxy = proxy_config(ProxyProtocol::Http, "alice");
+
+        let mut stream = request(b"HTTP/1.1 200 Connection established\r\n\r\npeer");
+        http_connect(&mut stream, &proxy, "::1", 25565).unwrap();
+        expect(&mut stream, b"peer");
+
+        let mut stream = request(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+        assert_eq!(
+            http_connect(&mut stream, &proxy, "::1", 25565)
+                .unwrap_err()
+                .kind(),
+            ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn connections_are_let_in_up_to_the_limits() {
+        let limits = ConnectionLimits {
+            max_connections: 3,
+            max_per_address: 2,
+            unlimited_addresses: Vec::new(),
+        };
+        let first = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let mut counts = ConnectionCounts::default();
+        assert!(counts.admit(first, &limits).is_ok());
+        assert!(counts.admit(first, &limits).is_ok());
+        assert!(counts.admit(first, &limits).is_err());
+        assert!(counts.admit(second, &limits).is_ok());
+        assert!(counts.admit(second, &limits).is_err());
+
+        counts.release(first);
+        assert!(counts.admit(second, &limits).is_ok());
+        assert!(counts.admit(first, &limits).is_err());
+    }
+
+    #[test]
+    fn loopback_and_unlimited_addresses_only_count_towards_the_total() {
+        let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9));
+        let limits = ConnectionLimits {
+            max_connections: 4,
+            max_per_address: 1,
+            unlimited_addresses: vec![proxy],
+        };
+        let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut counts = ConnectionCounts::default();
+        assert!(counts.admit(loopback, &limits).is_ok());
+        assert!(counts.admit(loopback, &limits).is_ok());
+        assert!(counts.admit(proxy, &limits).is_ok());
+        assert!(counts.admit(proxy, &limits).is_ok());
+        assert!(counts.admit(loopback, &limits).is_err());
+    }
+
+    #[test]
+    fn peers_are_let_send_longer_packets_than_players() {
+        let handshake = |next_state| {
+            let mut bytes = Vec::new();
+            packet::write(
+                &mut bytes,
+                Packet::Handshake(packet::Handshake {
+                    protocol_version: SERVER_PROTOCOL as i32,
+                    server_address: String::new(),
+                    server_port: 0,
+                    next_state,
+                }),
+            );
+            let mut cursor = Cursor::new(bytes);
+            cursor.read_var_int().unwrap();
+            cursor.get_ref()[cursor.position() as usize..].to_vec()
+        };
+        assert!(is_peer_handshake(&handshake(6)));
+        assert!(is_peer_handshake(&handshake(4)));
+        assert!(!is_peer_handshake(&handshake(2)));
+        assert!(!is_peer_handshake(&[0xFF]));
+        assert_eq!(ConnectionClass::Player.policy().max_packet_length, 1 << 21);
+        assert_eq!(
+            ConnectionClass::PeerLink.policy().max_packet_length,
+            1 << 24
+        );
+    }
+
+    #[test]
+    fn peers_only_get_a_peer_state_with_maps_the_same_size_as_ours() {
+        let challenged = |map_size| {
+            let mut stream = scripted_proxy(move |mut peer| {
+                let mut handshake = vec![0; peer.read_var_int().unwrap() as usize];
+                peer.read_exact(&mut handshake).unwrap();
+                compression::write(
+                    &mut peer,
+                    ProtocolAdapter::default(),
+                    Packet::PeerAuthChallenge(packet::PeerAuthChallenge {
+                        challenge: 7,
+                        map_size,
+                    }),
+                    ConnectionClass::PeerLink.policy().compression_threshold,
+                )
+            });
+            request_peer_state(&mut stream, 6)
+        };
+        assert_eq!(challenged(map_size()).unwrap(), 7);
+        assert_eq!(challenged(map_size() + 1).unwrap_err().kind(), InvalidData);
+    }
+
+    #[test]
+    fn legacy_pings_are_answered_in_the_format_the_client_knows() {
+        let decode = |kick: Vec<u8>| {
+            assert_eq!(kick[0], LEGACY_KICK);
+            let length = u16::from_be_bytes([kick[1], kick[2]]) as usize;
+            let units: Vec<u16> = kick[3..]
+                .chunks(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect();
+            assert_eq!(units.len(), length);
+            String::from_utf16(&units).unwrap()
+        };
+        let extended = decode(legacy_status(true, 3));
+        let fields: Vec<_> = extended.split('\0').collect();
+        assert_eq!(fields[0], "\u{a7}1");
+        assert_eq!(fields[2], SERVER_VERSION);
+        assert_eq!(fields[4], "3");
+        assert_eq!(
+            decode(legacy_status(false, 3)),
+            format!("{}\u{a7}3\u{a7}{}", SERVER_DESCRIPTION, SERVER_MAX_CAPACITY)
+        );
+    }
 }
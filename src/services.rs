@@ -7,21 +7,70 @@
 pub mod instance;
 #[macro_use]
 pub mod messenger;
+pub mod bans;
 pub mod block;
+pub mod chat;
+pub mod command;
+pub mod config_reload;
 pub mod connection;
+pub mod entity;
+pub mod entity_ids;
+pub mod game_rules;
+pub mod gossip;
+pub mod hud;
+pub mod interest;
 pub mod keep_alive;
+pub mod load_monitor;
 pub mod packet_processor;
 pub mod patchwork;
+pub mod peer_auth;
+pub mod peer_heartbeat;
+pub mod peer_registry;
 pub mod player;
+pub mod whitelist;
+pub mod world_time;
 
+use super::chunk_gen_pool;
+use super::config;
 use super::constants;
+use super::error;
+use super::flight_recorder;
+use super::link_watermarks;
+use super::message_log;
+use super::metrics;
+use super::packet_capture;
+use super::peer_quotas;
 
+use super::models::advancements;
+use super::models::ban_store;
+use super::models::block_registry;
+use super::models::chat_limiter;
+use super::models::chunk_cache;
+use super::models::compression;
+use super::models::forwarding;
+use super::models::identity;
 use super::models::map;
 use super::models::minecraft_types;
+use super::models::operator_store;
 use super::models::packet;
+use super::models::player_store;
+use super::models::position_delta;
+use super::models::protocol_adapter;
+use super::models::quota;
+use super::models::rate_limiter;
+use super::models::recent_events;
+use super::models::section_hash;
+use super::models::support_bundle;
+use super::models::topology;
 use super::models::translation;
+use super::models::uuid_source;
+use super::models::versioned;
+use super::models::weather;
+use super::models::whitelist_store;
+use super::models::world_generator;
 
 use super::interfaces;
 
 use super::packet_handlers;
 use super::server;
+use super::transform_pool;
@@ -0,0 +1,98 @@
+use super::ban_store::{Ban, BanStore};
+use super::config::Config;
+use super::constants::BANNED_MESSAGE;
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::bans::Operations;
+use super::interfaces::messenger::{Messenger, SubscriberType};
+use super::packet::{Packet, PeerBan};
+
+use std::sync::mpsc::Sender;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bans are shared by every node in the patchwork, like game rules are. Each change made here is
+// sent to our peers, who keep it and pass it on to theirs if it's newer than what they have, so a
+// player banned anywhere is banned everywhere. Peers catch up on everything when they subscribe
+pub fn start<M: Messenger>(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    messenger: M,
+    config: Config,
+) {
+    let mut bans = BanStore::load(&config.bans_file);
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Check(msg) => {
+                let refusal = bans
+                    .refusal(&msg.name, msg.uuid, msg.address, now())
+                    .map(|ban| {
+                        info!("Turning away {:?}, banned as {}", msg.name, ban.target);
+                        refusal_message(ban)
+                    });
+                let _ = msg.reply.send(refusal);
+            }
+            Operations::Add(msg) => {
+                let expires_at = msg.duration.map(|duration| now() + duration);
+                let ban = bans.ban(&msg.target, &msg.reason, expires_at);
+                info!("Banned {}: {}", ban.target, ban.reason);
+                messenger
+                    .broadcast(peer_ban(&ban), None, SubscriberType::Remote)
+                    .or_log();
+            }
+            Operations::Pardon(msg) => {
+                let pardon = bans.pardon(&msg.target);
+                if let Some(pardon) = &pardon {
+                    info!("Pardoned {}", pardon.target);
+                    messenger
+                        .broadcast(peer_ban(pardon), None, SubscriberType::Remote)
+                        .or_log();
+                }
+                let _ = msg.reply.send(pardon.is_some());
+            }
+            Operations::List(msg) => {
+                let _ = msg.reply.send(bans.in_force(now()));
+            }
+            Operations::Report(msg) => {
+                trace!("Reporting bans to {:?}", msg.conn_id);
+                bans.all().iter().for_each(|ban| {
+                    messenger.send_packet(msg.conn_id, peer_ban(ban)).or_log();
+                });
+            }
+            Operations::PeerUpdate(msg) => {
+                if bans.merge(msg.ban.clone()) {
+                    trace!("Syncing ban on {} from peer", msg.ban.target);
+                    messenger
+                        .broadcast(peer_ban(&msg.ban), None, SubscriberType::Remote)
+                        .or_log();
+                }
+            }
+        }
+    }
+}
+
+fn peer_ban(ban: &Ban) -> Packet {
+    Packet::PeerBan(PeerBan {
+        target: ban.target.clone(),
+        reason: ban.reason.clone(),
+        expires_at: ban.expires_at.unwrap_or(0) as i64,
+        version: ban.version,
+        active: ban.active,
+    })
+}
+
+fn refusal_message(ban: &Ban) -> String {
+    let mut message = format!("{}: {}", BANNED_MESSAGE, ban.reason);
+    if let Some(expires_at) = ban.expires_at {
+        let minutes = expires_at.saturating_sub(now()).div_ceil(60);
+        message.push_str(&format!(" (for another {} minutes)", minutes));
+    }
+    message
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
@@ -1,61 +1,696 @@
-use super::interfaces::block::Operations;
-use super::interfaces::messenger::Messenger;
-use super::minecraft_types::ChunkSection;
-use super::packet::{ChunkData, Packet};
+use super::block_registry::block_registry;
+use super::chunk_gen_pool::{ChunkGenPool, Priority};
+use super::config::Config;
+use super::constants::{
+    CHUNK_GEN_WORKERS, CHUNK_SIZE, RECENT_EVENT_LIMIT, RECENT_EVENT_WINDOW, REPEATED_REPORT_WINDOW,
+};
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::block::{BlockPosition, BlockState, Operations};
+use super::interfaces::messenger::{Messenger, SubscriberType};
+use super::map::{map_size, map_width, Dimension};
+use super::minecraft_types::{BlockChangeRecord, ChatComponent, ChunkSection, Location};
+use super::packet::{BlockChange, ChunkData, ClientboundChatMessage, MultiBlockChange, Packet};
+use super::recent_events::RecentEvents;
+use super::section_hash::SectionHashes;
+use super::world_generator::{map_chunks, WorldGenerator, CHUNK_BLOCKS};
 
-use std::sync::mpsc::{Receiver, Sender};
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::mem;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
-// We don't really have any meaningful block state yet- it cannot be changed or be particularly
-// complicated. We can build this up later
-fn fill_dummy_block_ids(ids: &mut Vec<i32>) {
-    while ids.len() < 4096 {
-        let xz_pos = ids.len() % 256;
-        let z_pos = xz_pos / 16;
-        let x_pos = xz_pos % 16;
-        if x_pos == 0 || x_pos == 15 || z_pos == 0 || z_pos == 15 {
-            ids.push(180)
-        } else {
-            match (x_pos + z_pos) % 2 {
-                0 => ids.push(97),
-                1 => ids.push(103),
-                _ => panic!("math has failed us."),
-            }
-        }
-    }
+const MULTI_BLOCK_CHANGE_LIMIT: usize = 64;
+
+// Block ids are stored a chunk section at a time, with the chunks in rows along x
+fn map_blocks() -> usize {
+    (map_size() * map_size()) as usize * CHUNK_BLOCKS
 }
 
 pub fn start<M: Messenger>(
-    receiver: Receiver<Operations>,
-    _sender: Sender<Operations>,
+    receiver: Queue<Operations>,
+    sender: Sender<Operations>,
     messenger: M,
+    config: Config,
 ) {
+    let mut generation = ChunkGeneration {
+        generators: HashMap::new(),
+        placeholder_chunks: (0..map_chunks().len()).collect(),
+        wanted_chunks: HashMap::new(),
+        missing_generators: HashSet::new(),
+        pregeneration: None,
+        pool: ChunkGenPool::new(CHUNK_GEN_WORKERS, move |chunk, block_ids| {
+            sender.chunk_generated(chunk, block_ids).or_log()
+        }),
+    };
+    // The blocks of the map we're serving, which is in our configured dimension until we're handed
+    // one in another
+    let mut dimension = config.dimension;
+    let mut block_ids = vec![0; map_blocks()];
+    let mut hashes = SectionHashes::new(&block_ids);
+    let mut stores: HashMap<Dimension, ChunkStore> = HashMap::new();
+    let mut reports = ChunkReports {
+        reported: HashMap::new(),
+    };
+    // Changes made while a report's on its way could otherwise be missed by whoever it's for, as
+    // they may not be subscribed yet. Replaying them in order after the report ends up at the same
+    // blocks as we have
+    let mut recent_changes =
+        RecentEvents::new(Duration::from_secs(RECENT_EVENT_WINDOW), RECENT_EVENT_LIMIT);
+    // Changes are only forwarded to peers along the edges there's a peer's map across
+    let mut neighbours = Vec::new();
+
     while let Ok(msg) = receiver.recv() {
         match msg {
+            // Proxies' players are sent chunks by the peers whose maps they're on
+            Operations::Report(_) if !config.local_map => {}
             Operations::Report(msg) => {
                 trace!("Reporting block state to {:?}", msg.conn_id);
-                //Just send a hardcoded simple chunk pillar
-                let mut block_ids = Vec::new();
-                fill_dummy_block_ids(&mut block_ids);
-                messenger.send_packet(
-                    msg.conn_id,
-                    Packet::ChunkData(ChunkData {
-                        chunk_x: 0,
-                        chunk_z: 0,
-                        full_chunk: true,
-                        primary_bit_mask: 1,
-                        size: 12291, //I just calculated the length of this hardcoded chunk section
-                        data: ChunkSection {
-                            bits_per_block: 14,
-                            data_array_length: 896,
-                            block_ids,
-                            block_light: Vec::new(),
-                            sky_light: Vec::new(),
-                        },
-                        biomes: vec![127; 256],
-                        number_of_block_entities: 0,
-                    }),
+                // Chunks that haven't been generated yet are broadcast once they are
+                let unchanged = reports.reported(msg.conn_id, &hashes);
+                (0..map_chunks().len()).for_each(|chunk| {
+                    if generation.placeholder_chunks.contains(&chunk) {
+                        generation.want(chunk, Priority::Requested, &config);
+                    } else if unchanged.as_ref().map(|hashes| hashes[chunk])
+                        != Some(hashes.get(chunk))
+                    {
+                        messenger
+                            .send_packet(
+                                msg.conn_id,
+                                Packet::ChunkData(chunk_data_packet(chunk, &block_ids)),
+                            )
+                            .or_log();
+                    }
+                });
+                recent_changes
+                    .recent(Instant::now())
+                    .into_iter()
+                    .for_each(|packet| messenger.send_packet(msg.conn_id, packet).or_log());
+            }
+            Operations::Fill(msg) => {
+                trace!(
+                    "Filling blocks from {:?} to {:?} with {:?}",
+                    msg.from,
+                    msg.to,
+                    msg.block_id
+                );
+                let changes = fill(&mut block_ids, &mut hashes, msg.from, msg.to, msg.block_id);
+                changes.into_iter().for_each(|(chunk, records)| {
+                    // Past a certain point it's cheaper to just resend the whole chunk
+                    let packet = if records.len() > MULTI_BLOCK_CHANGE_LIMIT {
+                        Packet::ChunkData(chunk_data_packet(chunk, &block_ids))
+                    } else if records.len() == 1 {
+                        Packet::BlockChange(block_change_packet(chunk, &records[0]))
+                    } else {
+                        Packet::MultiBlockChange(MultiBlockChange {
+                            chunk_x: chunk as i32 % map_size(),
+                            chunk_z: chunk as i32 / map_size(),
+                            records,
+                        })
+                    };
+                    // Peers only need to hear about the chunks their players can see over the seam.
+                    // Their side translates the coordinates into their own map
+                    let subscriber_type = if is_seam_chunk(chunk, config.seam_width, &neighbours) {
+                        SubscriberType::All
+                    } else {
+                        SubscriberType::Local
+                    };
+                    recent_changes.push(packet.clone(), Instant::now());
+                    messenger.broadcast(packet, None, subscriber_type).or_log();
+                });
+            }
+            Operations::Export(msg) => {
+                let _ = msg.reply.send(block_ids.clone());
+            }
+            Operations::GetBlock(msg) => {
+                let _ = msg
+                    .reply
+                    .send(block_index(msg.position).map(|index| block_ids[index]));
+            }
+            Operations::IsSolid(msg) => {
+                let _ = msg.reply.send(
+                    block_index(msg.position)
+                        .map(|index| block_registry().is_solid(block_ids[index])),
                 );
             }
+            Operations::GetHeight(msg) => {
+                let column = |y| {
+                    block_index(BlockPosition {
+                        x: msg.x,
+                        y,
+                        z: msg.z,
+                    })
+                };
+                let height = column(0).map(|_| {
+                    (0..CHUNK_SIZE)
+                        .rev()
+                        .find(|&y| block_registry().is_solid(block_ids[column(y).unwrap()]))
+                        .map_or(0, |y| y + 1)
+                });
+                let _ = msg.reply.send(height);
+            }
+            Operations::RegisterGenerator(msg) => {
+                trace!("Registering world generator {:?}", msg.name);
+                generation.register(msg.name, Arc::from(msg.generator), &config, &neighbours);
+            }
+            // Seams with new neighbours are generated ahead of time, like they are when the
+            // generator's registered
+            Operations::SetNeighbours(msg) => {
+                trace!("Neighbouring maps are now {:?}", msg.neighbours);
+                neighbours = msg.neighbours;
+                (0..map_chunks().len())
+                    .filter(|chunk| is_seam_chunk(*chunk, config.seam_width, &neighbours))
+                    .for_each(|chunk| {
+                        generation.want(chunk, Priority::BorderPrefetch, &config);
+                    });
+            }
+            Operations::ChunkGenerated(msg) => {
+                // Chunks can be loaded over while they're being generated
+                if generation.generated(msg.chunk) {
+                    trace!("Generated chunk {:?}", msg.chunk);
+                    hashes.replace(msg.chunk, &msg.block_ids);
+                    block_ids.splice(
+                        msg.chunk * CHUNK_BLOCKS..(msg.chunk + 1) * CHUNK_BLOCKS,
+                        msg.block_ids,
+                    );
+                    messenger
+                        .broadcast(
+                            Packet::ChunkData(chunk_data_packet(msg.chunk, &block_ids)),
+                            None,
+                            SubscriberType::All,
+                        )
+                        .or_log();
+                }
+                generation.continue_pregeneration(&config, &messenger);
+            }
+            Operations::Regenerate(msg) => {
+                trace!("Regenerating {:?} chunks", msg.chunks.len());
+                msg.chunks.into_iter().for_each(|chunk| {
+                    generation.placeholder_chunks.insert(chunk);
+                    generation.want(chunk, Priority::Requested, &config);
+                });
+            }
+            Operations::Pregenerate(msg) => {
+                trace!(
+                    "Pregenerating {:?} chunks, {:?} at a time",
+                    generation.placeholder_chunks.len(),
+                    msg.batch_size
+                );
+                let mut chunks: Vec<usize> =
+                    generation.placeholder_chunks.iter().copied().collect();
+                // Chunks are handed out from the back, so start from the corner like the map does
+                chunks.sort_unstable_by(|a, b| b.cmp(a));
+                generation.pregeneration = Some(Pregeneration {
+                    conn_id: msg.conn_id,
+                    total: chunks.len(),
+                    queued: chunks,
+                    in_flight: HashSet::new(),
+                    without_generator: Vec::new(),
+                    batch_size: max(msg.batch_size, 1),
+                    reported_tenths: 0,
+                });
+                generation.continue_pregeneration(&config, &messenger);
+            }
+            Operations::Load(msg) => {
+                if msg.block_ids.len() != map_blocks() {
+                    warn!(
+                        "Cannot load {:?} blocks into a map of {:?}, are map sizes the same?",
+                        msg.block_ids.len(),
+                        map_blocks()
+                    );
+                    continue;
+                }
+                trace!(
+                    "Loading {:?} blocks in the {:?}",
+                    msg.block_ids.len(),
+                    msg.dimension
+                );
+                // What we served in the other dimension is kept, and what we last served in this
+                // one is what everyone still in it has been sent
+                let served_before =
+                    msg.dimension == dimension || stores.contains_key(&msg.dimension);
+                if msg.dimension != dimension {
+                    let previous = stores
+                        .remove(&msg.dimension)
+                        .unwrap_or_else(ChunkStore::empty);
+                    stores.insert(
+                        dimension,
+                        ChunkStore {
+                            block_ids: mem::replace(&mut block_ids, previous.block_ids),
+                            hashes: mem::replace(&mut hashes, previous.hashes),
+                        },
+                    );
+                    dimension = msg.dimension;
+                }
+                block_ids = msg.block_ids;
+                let loaded = SectionHashes::new(&block_ids);
+                // Chunks everyone's already been sent as they are don't need sending again
+                (0..map_chunks().len()).for_each(|chunk| {
+                    if !served_before
+                        || loaded.get(chunk) != hashes.get(chunk)
+                        || generation.placeholder_chunks.contains(&chunk)
+                    {
+                        messenger
+                            .broadcast(
+                                Packet::ChunkData(chunk_data_packet(chunk, &block_ids)),
+                                None,
+                                SubscriberType::All,
+                            )
+                            .or_log();
+                    }
+                });
+                hashes = loaded;
+                // Replaying changes to the blocks we had would undo what's been loaded
+                recent_changes.clear();
+                generation.placeholder_chunks.clear();
+                generation.wanted_chunks.clear();
+                generation.pregeneration = None;
+            }
+        }
+    }
+}
+
+// A map's blocks in one of the dimensions, see Load
+struct ChunkStore {
+    block_ids: Vec<i32>,
+    hashes: SectionHashes,
+}
+
+impl ChunkStore {
+    fn empty() -> ChunkStore {
+        let block_ids = vec![0; map_blocks()];
+        ChunkStore {
+            hashes: SectionHashes::new(&block_ids),
+            block_ids,
+        }
+    }
+}
+
+// Peers ask for our whole map again every time one of their players logs in, so when several do at
+// once the same chunks would go out over the link back to back. Chunks are only sent again within
+// REPEATED_REPORT_WINDOW of the last report to a connection if their blocks have changed since.
+// Peers keep what we've sent them and catch their new players up from that, see ChunkCache
+struct ChunkReports {
+    // The section hashes each connection was last reported
+    reported: HashMap<Uuid, (Instant, Vec<u64>)>,
+}
+
+impl ChunkReports {
+    // The section hashes last reported to the connection, if it was recently enough to go by.
+    // Either way the connection's chunks are taken as reported as they are now
+    fn reported(&mut self, conn_id: Uuid, hashes: &SectionHashes) -> Option<Vec<u64>> {
+        let now = Instant::now();
+        let window = Duration::from_secs(REPEATED_REPORT_WINDOW);
+        self.reported
+            .retain(|_, (reported_at, _)| now.duration_since(*reported_at) < window);
+        self.reported
+            .insert(conn_id, (now, hashes.all().to_vec()))
+            .map(|(_, hashes)| hashes)
+    }
+}
+
+// Generators are registered through the block state, built in ones included. Until a chunk's
+// generator has been registered and the chunk generated, the chunk is left empty
+struct ChunkGeneration {
+    generators: HashMap<String, Arc<dyn WorldGenerator>>,
+    placeholder_chunks: HashSet<usize>,
+    // The placeholder chunks that have been asked for, at the most urgent priority they were asked
+    // for at
+    wanted_chunks: HashMap<usize, Priority>,
+    // Generators can be registered whenever, so ones that chunks are waiting on are only warned
+    // about, once each until they turn up
+    missing_generators: HashSet<String>,
+    pool: ChunkGenPool,
+    pregeneration: Option<Pregeneration>,
+}
+
+// Pregeneration hands the pool a few chunks at a time so that it never crowds out the chunks players
+// are waiting on, and tells whoever asked for it how it's getting on
+struct Pregeneration {
+    conn_id: Uuid,
+    total: usize,
+    queued: Vec<usize>,
+    in_flight: HashSet<usize>,
+    // Chunks whose generator hasn't been registered, which are left until it is
+    without_generator: Vec<usize>,
+    batch_size: usize,
+    reported_tenths: usize,
+}
+
+impl ChunkGeneration {
+    // Whether the chunk's generator has been registered, so that it's on its way
+    fn want(&mut self, chunk: usize, priority: Priority, config: &Config) -> bool {
+        let chunk_position = map_chunks()[chunk];
+        let name = generator_name(config, chunk_position.0, chunk_position.1);
+        let generator = self.generators.get(name).cloned();
+        if self.placeholder_chunks.contains(&chunk)
+            && self.wanted_chunks.get(&chunk) < Some(&priority)
+        {
+            self.wanted_chunks.insert(chunk, priority);
+            match &generator {
+                Some(generator) => {
+                    self.pool
+                        .submit(priority, chunk, chunk_position, generator.clone())
+                }
+                None if self.missing_generators.insert(String::from(name)) => warn!(
+                    "Chunks are waiting on a generator called {:?}, which hasn't been registered",
+                    name
+                ),
+                None => {}
+            }
+        }
+        generator.is_some()
+    }
+
+    // Anything asked for before the generator was around can be generated now, and the seams are
+    // generated ahead of time for the peers next to us
+    fn register(
+        &mut self,
+        name: String,
+        generator: Arc<dyn WorldGenerator>,
+        config: &Config,
+        neighbours: &[(i32, i32)],
+    ) {
+        let chunks: Vec<(usize, (i32, i32))> = map_chunks()
+            .into_iter()
+            .enumerate()
+            .filter(|(chunk, (chunk_x, chunk_z))| {
+                self.placeholder_chunks.contains(chunk)
+                    && generator_name(config, *chunk_x, *chunk_z) == name
+            })
+            .collect();
+        self.missing_generators.remove(&name);
+        self.generators.insert(name, generator.clone());
+        chunks.into_iter().for_each(|(chunk, chunk_position)| {
+            match self.wanted_chunks.get(&chunk) {
+                Some(priority) => {
+                    self.pool
+                        .submit(*priority, chunk, chunk_position, generator.clone())
+                }
+                None if is_seam_chunk(chunk, config.seam_width, neighbours) => {
+                    self.want(chunk, Priority::BorderPrefetch, config);
+                }
+                None => {}
+            }
+        });
+    }
+
+    // Whether the chunk was still waiting to be generated
+    fn generated(&mut self, chunk: usize) -> bool {
+        if let Some(pregeneration) = &mut self.pregeneration {
+            pregeneration.in_flight.remove(&chunk);
+        }
+        self.wanted_chunks.remove(&chunk);
+        self.placeholder_chunks.remove(&chunk)
+    }
+
+    fn continue_pregeneration<M: Messenger>(&mut self, config: &Config, messenger: &M) {
+        let mut pregeneration = match self.pregeneration.take() {
+            Some(pregeneration) => pregeneration,
+            None => return,
+        };
+        // Chunks players asked for in the meantime don't need doing again
+        while pregeneration.in_flight.len() < pregeneration.batch_size {
+            match pregeneration.queued.pop() {
+                Some(chunk) if self.placeholder_chunks.contains(&chunk) => {
+                    if self.want(chunk, Priority::Background, config) {
+                        pregeneration.in_flight.insert(chunk);
+                    } else {
+                        pregeneration.without_generator.push(chunk);
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        let done = pregeneration.total
+            - pregeneration.queued.len()
+            - pregeneration.in_flight.len()
+            - pregeneration.without_generator.len();
+        let tenths = (done * 10).checked_div(pregeneration.total).unwrap_or(10);
+        if tenths > pregeneration.reported_tenths {
+            pregeneration.reported_tenths = tenths;
+            tell(
+                messenger,
+                pregeneration.conn_id,
+                &format!("Pregenerated {}/{} chunks", done, pregeneration.total),
+            );
+        }
+        if !pregeneration.queued.is_empty() || !pregeneration.in_flight.is_empty() {
+            self.pregeneration = Some(pregeneration);
+        } else if !pregeneration.without_generator.is_empty() {
+            let missing: BTreeSet<&str> = pregeneration
+                .without_generator
+                .iter()
+                .map(|chunk| {
+                    let (chunk_x, chunk_z) = map_chunks()[*chunk];
+                    generator_name(config, chunk_x, chunk_z)
+                })
+                .collect();
+            warn!(
+                "Couldn't pregenerate {:?} chunks, no generator is registered as {:?}",
+                pregeneration.without_generator.len(),
+                missing
+            );
+            tell(
+                messenger,
+                pregeneration.conn_id,
+                &format!(
+                    "{} chunks weren't pregenerated, no generator is registered as {}",
+                    pregeneration.without_generator.len(),
+                    missing.into_iter().collect::<Vec<&str>>().join(", ")
+                ),
+            );
+        }
+    }
+}
+
+fn tell<M: Messenger>(messenger: &M, conn_id: Uuid, text: &str) {
+    messenger
+        .send_packet(
+            conn_id,
+            Packet::ClientboundChatMessage(ClientboundChatMessage {
+                json_data: ChatComponent::new(text).to_json(),
+                position: 1, // system message
+            }),
+        )
+        .or_log();
+}
+
+fn generator_name(config: &Config, chunk_x: i32, chunk_z: i32) -> &str {
+    config
+        .generator_regions
+        .iter()
+        .find(|region| region.contains(chunk_x, chunk_z))
+        .map_or(&config.generator, |region| &region.generator)
+}
+
+// Sets every block in the (inclusive) region to block_id, clipped to the blocks we store. Changes
+// are returned grouped by the chunk they're in
+fn fill(
+    block_ids: &mut [i32],
+    hashes: &mut SectionHashes,
+    from: BlockPosition,
+    to: BlockPosition,
+    block_id: i32,
+) -> BTreeMap<usize, Vec<BlockChangeRecord>> {
+    let mut changes = BTreeMap::<usize, Vec<BlockChangeRecord>>::new();
+    for y in max(min(from.y, to.y), 0)..=min(max(from.y, to.y), CHUNK_SIZE - 1) {
+        for z in max(min(from.z, to.z), 0)..=min(max(from.z, to.z), map_width() - 1) {
+            for x in max(min(from.x, to.x), 0)..=min(max(from.x, to.x), map_width() - 1) {
+                let index = block_index(BlockPosition { x, y, z }).unwrap();
+                let (chunk, section_index) = (index / CHUNK_BLOCKS, index % CHUNK_BLOCKS);
+                let (x, z) = (x % CHUNK_SIZE, z % CHUNK_SIZE);
+                if block_ids[index] != block_id {
+                    hashes.change(chunk, section_index, block_ids[index], block_id);
+                    block_ids[index] = block_id;
+                    changes.entry(chunk).or_default().push(BlockChangeRecord {
+                        horizontal_position: ((x << 4) | z) as u8,
+                        y: y as u8,
+                        block_id,
+                    });
+                }
+            }
+        }
+    }
+    changes
+}
+
+// Where the block is in block_ids, if it's on the map
+fn block_index(position: BlockPosition) -> Option<usize> {
+    let BlockPosition { x, y, z } = position;
+    if !(0..map_width()).contains(&x) || !(0..map_width()).contains(&z) {
+        return None;
+    }
+    if !(0..CHUNK_SIZE).contains(&y) {
+        return None;
+    }
+    let chunk = ((z / CHUNK_SIZE) * map_size() + x / CHUNK_SIZE) as usize;
+    let (x, z) = (x % CHUNK_SIZE, z % CHUNK_SIZE);
+    Some(chunk * CHUNK_BLOCKS + (y * CHUNK_SIZE * CHUNK_SIZE + z * CHUNK_SIZE + x) as usize)
+}
+
+// Whether any of the chunk is within seam_width blocks of an edge of the map with a neighbour across
+// it. Diagonal neighbours only share the corner
+fn is_seam_chunk(chunk: usize, seam_width: i32, neighbours: &[(i32, i32)]) -> bool {
+    let chunk = chunk as i32;
+    let near_edge = |start: i32, direction: i32| match direction {
+        -1 => start < seam_width,
+        1 => start + CHUNK_SIZE > map_width() - seam_width,
+        _ => true,
+    };
+    neighbours.iter().any(|(x, z)| {
+        near_edge(chunk % map_size() * CHUNK_SIZE, *x)
+            && near_edge(chunk / map_size() * CHUNK_SIZE, *z)
+    })
+}
+
+fn block_change_packet(chunk: usize, record: &BlockChangeRecord) -> BlockChange {
+    let chunk = chunk as i32;
+    BlockChange {
+        location: Location {
+            x: chunk % map_size() * CHUNK_SIZE + (record.horizontal_position >> 4) as i32,
+            y: record.y as i32,
+            z: chunk / map_size() * CHUNK_SIZE + (record.horizontal_position & 0xF) as i32,
+        },
+        block_id: record.block_id,
+    }
+}
+
+//Just send a simple chunk pillar
+fn chunk_data_packet(chunk: usize, block_ids: &[i32]) -> ChunkData {
+    let block_ids = block_ids[chunk * CHUNK_BLOCKS..(chunk + 1) * CHUNK_BLOCKS].to_vec();
+    ChunkData {
+        chunk_x: chunk as i32 % map_size(),
+        chunk_z: chunk as i32 / map_size(),
+        full_chunk: true,
+        primary_bit_mask: 1,
+        size: 12291, //I just calculated the length of this hardcoded chunk section
+        data: ChunkSection {
+            bits_per_block: 14,
+            data_array_length: 896,
+            block_ids,
+            block_light: Vec::new(),
+            sky_light: Vec::new(),
+        },
+        biomes: vec![127; 256],
+        number_of_block_entities: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::messenger::Operations as MessengerOperations;
+    use crate::interfaces::MockMessenger;
+    use crate::models::world_generator::Flat;
+    use std::sync::mpsc::channel;
+
+    fn generation(on_generated: Sender<(usize, Vec<i32>)>) -> ChunkGeneration {
+        ChunkGeneration {
+            generators: HashMap::new(),
+            placeholder_chunks: (0..map_chunks().len()).collect(),
+            wanted_chunks: HashMap::new(),
+            missing_generators: HashSet::new(),
+            pregeneration: None,
+            pool: ChunkGenPool::new(1, move |chunk, block_ids| {
+                let _ = on_generated.send((chunk, block_ids));
+            }),
         }
     }
+
+    fn pregenerate(generation: &mut ChunkGeneration, conn_id: Uuid) {
+        generation.pregeneration = Some(Pregeneration {
+            conn_id,
+            total: generation.placeholder_chunks.len(),
+            queued: generation.placeholder_chunks.iter().copied().collect(),
+            in_flight: HashSet::new(),
+            without_generator: Vec::new(),
+            batch_size: 1,
+            reported_tenths: 0,
+        });
+    }
+
+    fn chat(messenger: &MockMessenger) -> Vec<String> {
+        messenger
+            .take()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                MessengerOperations::Send(msg) => match msg.packet {
+                    Packet::ClientboundChatMessage(chat) => Some(chat.json_data),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pregeneration_reports_progress_until_every_chunk_is_generated() {
+        let config = Config::default();
+        let (sender, receiver) = channel();
+        let mut generation = generation(sender);
+        generation.register(String::from("flat"), Arc::new(Flat), &config, &[]);
+        let config = Config {
+            generator: String::from("flat"),
+            ..config
+        };
+        let messenger = MockMessenger::new();
+        let conn_id = Uuid::new_v4();
+        pregenerate(&mut generation, conn_id);
+
+        generation.continue_pregeneration(&config, &messenger);
+        assert!(chat(&messenger).is_empty());
+        let pregeneration = generation.pregeneration.as_ref().unwrap();
+        assert_eq!(pregeneration.in_flight.len(), 1);
+
+        let (chunk, _) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(generation.generated(chunk));
+        generation.continue_pregeneration(&config, &messenger);
+        let total = map_chunks().len();
+        let chat = chat(&messenger);
+        assert!(
+            chat.last()
+                .unwrap()
+                .contains(&format!("Pregenerated {}/{} chunks", total, total)),
+            "{:?}",
+            chat
+        );
+        assert!(generation.pregeneration.is_none());
+    }
+
+    #[test]
+    fn pregeneration_finishes_and_says_so_when_a_generator_is_missing() {
+        let config = Config {
+            generator: String::from("missing"),
+            ..Config::default()
+        };
+        let (sender, _receiver) = channel();
+        let mut generation = generation(sender);
+        let messenger = MockMessenger::new();
+        pregenerate(&mut generation, Uuid::new_v4());
+
+        generation.continue_pregeneration(&config, &messenger);
+        assert!(generation.pregeneration.is_none());
+        let chat = chat(&messenger);
+        assert!(
+            chat.iter()
+                .any(|message| message.contains("no generator is registered as missing")),
+            "{:?}",
+            chat
+        );
+        // The chunks are still generated once the generator turns up
+        assert!(generation.placeholder_chunks.contains(&0));
+        assert!(generation.wanted_chunks.contains_key(&0));
+    }
+
+    #[test]
+    fn only_edges_with_a_neighbour_across_them_are_seams() {
+        assert!(!is_seam_chunk(0, 4, &[]));
+        assert!(is_seam_chunk(0, 4, &[(1, 0)]));
+        assert!(is_seam_chunk(0, 4, &[(-1, 1)]));
+    }
 }
@@ -0,0 +1,180 @@
+use super::chat_limiter::{ChatLimiter, Verdict};
+use super::config::Config;
+use super::constants::{RECENT_EVENT_LIMIT, RECENT_EVENT_WINDOW};
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::chat::Operations;
+use super::interfaces::messenger::{Messenger, Origin, SubscriberType};
+use super::map::Peer;
+use super::minecraft_types::ChatComponent;
+use super::packet::{ClientboundChatMessage, Packet, PeerChatMessage};
+use super::recent_events::RecentEvents;
+
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const SPAM_KICK_MESSAGE: &str = "Kicked for spamming";
+
+// Players chat with everyone on the quilt. Each player's messages are rate limited here, on the
+// node they're connected to, before they go out to our players or on to any peer. What's been said
+// on our map lately is replayed to players joining it, so they don't join halfway through a
+// conversation
+pub fn start<M: Messenger>(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    messenger: M,
+    local_peer: Peer,
+    config: Config,
+) {
+    let mut players = HashMap::<Uuid, Chatter>::new();
+    let mut recent =
+        RecentEvents::new(Duration::from_secs(RECENT_EVENT_WINDOW), RECENT_EVENT_LIMIT);
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Join(msg) => {
+                players.insert(
+                    msg.conn_id,
+                    Chatter {
+                        name: msg.name,
+                        limiter: ChatLimiter::new(config.chat_limit, Instant::now()),
+                    },
+                );
+            }
+            Operations::Leave(msg) => {
+                players.remove(&msg.conn_id);
+            }
+            Operations::Replay(msg) => {
+                recent
+                    .recent(Instant::now())
+                    .into_iter()
+                    .for_each(|json_data| {
+                        messenger
+                            .send_packet(
+                                msg.conn_id,
+                                Packet::ClientboundChatMessage(ClientboundChatMessage {
+                                    json_data,
+                                    position: 0, // chat
+                                }),
+                            )
+                            .or_log()
+                    })
+            }
+            Operations::Say(msg) => {
+                let chatter = match players.get_mut(&msg.conn_id) {
+                    Some(chatter) => chatter,
+                    None => {
+                        trace!("Ignoring chat from unknown conn_id {:?}", msg.conn_id);
+                        continue;
+                    }
+                };
+                match chatter.limiter.check(Instant::now()) {
+                    Verdict::Allowed => send_everywhere(
+                        &format!("<{}> {}", chatter.name, msg.message),
+                        &local_peer,
+                        &messenger,
+                        &mut recent,
+                    ),
+                    Verdict::Warned => tell(
+                        msg.conn_id,
+                        "You're sending messages too quickly",
+                        &messenger,
+                    ),
+                    Verdict::Muted(duration) => {
+                        trace!("Muting {:?} for {:?}", chatter.name, duration);
+                        tell(
+                            msg.conn_id,
+                            &format!(
+                                "You've been muted for {} seconds for spamming",
+                                duration.as_secs()
+                            ),
+                            &messenger,
+                        )
+                    }
+                    Verdict::StillMuted(remaining) => tell(
+                        msg.conn_id,
+                        &format!(
+                            "You're muted for another {} seconds",
+                            remaining.as_secs() + 1
+                        ),
+                        &messenger,
+                    ),
+                    Verdict::Kicked => {
+                        warn!("Kicking {:?} for spamming", chatter.name);
+                        messenger
+                            .kick(msg.conn_id, String::from(SPAM_KICK_MESSAGE))
+                            .or_log();
+                    }
+                }
+            }
+            Operations::Announce(msg) => match players.get(&msg.conn_id) {
+                Some(chatter) => send_everywhere(
+                    &format!("[{}] {}", chatter.name, msg.message),
+                    &local_peer,
+                    &messenger,
+                    &mut recent,
+                ),
+                None => trace!(
+                    "Ignoring announcement from unknown conn_id {:?}",
+                    msg.conn_id
+                ),
+            },
+        }
+    }
+}
+
+struct Chatter {
+    name: String,
+    limiter: ChatLimiter,
+}
+
+// To our players, and on to every peer for theirs
+fn send_everywhere<M: Messenger>(
+    text: &str,
+    local_peer: &Peer,
+    messenger: &M,
+    recent: &mut RecentEvents<String>,
+) {
+    let json_data = ChatComponent::new(text).to_json();
+    recent.push(json_data.clone(), Instant::now());
+    let origin = Origin::local(local_peer.clone());
+    messenger
+        .relay(
+            Packet::PeerChatMessage(PeerChatMessage {
+                origin_address: origin.node.address.clone(),
+                origin_port: origin.node.port,
+                hops: origin.hops,
+                sequence: origin.sequence,
+                json_data: json_data.clone(),
+            }),
+            origin,
+            None,
+            SubscriberType::Remote,
+            None,
+        )
+        .or_log();
+    messenger
+        .broadcast(
+            Packet::ClientboundChatMessage(ClientboundChatMessage {
+                json_data,
+                position: 0, // chat
+            }),
+            None,
+            SubscriberType::Local,
+        )
+        .or_log();
+}
+
+fn tell<M: Messenger>(conn_id: Uuid, text: &str, messenger: &M) {
+    messenger
+        .send_packet(
+            conn_id,
+            Packet::ClientboundChatMessage(ClientboundChatMessage {
+                json_data: ChatComponent::new(text).to_json(),
+                position: 1, // system message
+            }),
+        )
+        .or_log();
+}
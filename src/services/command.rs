@@ -0,0 +1,854 @@
+use super::block_registry::block_registry;
+use super::config::Config;
+use super::constants::{ENTITY_OWNER_QUERY_TIMEOUT, PREGENERATION_BATCH_SIZE};
+use super::error::OrLog;
+use super::flight_recorder;
+use super::instance::{self, Queue};
+use super::interfaces::bans::BanList;
+use super::interfaces::block::{BlockPosition, BlockState};
+use super::interfaces::chat::ChatService;
+use super::interfaces::command::Operations;
+use super::interfaces::game_rules::{GameRule, GameRuleState};
+use super::interfaces::hud::Hud;
+use super::interfaces::messenger::Messenger;
+use super::interfaces::packet_processor::PacketProcessor;
+use super::interfaces::patchwork::{EntityOwner, EntityQuery, PatchworkState};
+use super::interfaces::player::{PlayerState, Position};
+use super::interfaces::whitelist::Whitelist;
+use super::map::{map_width, Peer, Position as MapPosition};
+use super::minecraft_types::ChatComponent;
+use super::operator_store::MAX_PERMISSION_LEVEL;
+use super::packet::{ClientboundChatMessage, Packet};
+use super::packet_capture;
+use super::support_bundle::SupportBundle;
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+use uuid::Uuid;
+
+const KICK_MESSAGE: &str = "Kicked by an operator";
+
+// Commands arrive as the raw chat message (including the leading slash) from the gameplay router
+#[allow(clippy::too_many_arguments)]
+pub fn start<
+    M: Messenger,
+    PA: PatchworkState,
+    G: GameRuleState,
+    B: BlockState,
+    H: Hud,
+    P: PlayerState,
+    W: Whitelist,
+    BL: BanList,
+    C: ChatService,
+    PP: PacketProcessor,
+>(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    messenger: M,
+    patchwork_state: PA,
+    game_rules: G,
+    block_state: B,
+    hud: H,
+    player_state: P,
+    whitelist: W,
+    bans: BL,
+    chat: C,
+    inbound_packet_processor: PP,
+    config: Config,
+) {
+    while let Ok(msg) = receiver.recv() {
+        let (conn_id, command, level, reply) = match msg {
+            Operations::Execute(msg) => {
+                let (reply_sender, reply_receiver) = channel();
+                player_state
+                    .permission_level(msg.conn_id, reply_sender)
+                    .or_log();
+                let level = reply_receiver.recv().unwrap_or(0);
+                (msg.conn_id, msg.command, level, None)
+            }
+            // Nobody's connection is behind these
+            Operations::Run(msg) => (
+                Uuid::nil(),
+                msg.command,
+                msg.permission_level,
+                Some(msg.reply),
+            ),
+        };
+        trace!(
+            "Executing command {:?} for conn_id {:?}",
+            loggable(&command),
+            conn_id
+        );
+        let args: Vec<&str> = command.trim_start_matches('/').split_whitespace().collect();
+        let feedback = match args.split_first() {
+            Some((command, _)) if level < required_level(command) => {
+                Err(format!("You don't have permission to use /{}", command))
+            }
+            Some((&"summon", args)) => summon(args, &patchwork_state),
+            Some((&"kill", args)) => kill(args, &patchwork_state),
+            Some((&"owner", args)) => owner(args, &patchwork_state),
+            Some((&"setblock", args)) => setblock(args, &patchwork_state),
+            Some((&"fill", args)) => fill(args, &patchwork_state),
+            Some((&"pregenerate", args)) => pregenerate(args, conn_id, &block_state),
+            Some((&"gamerule", args)) => gamerule(args, &game_rules),
+            Some((&"topology", args)) => topology(args, &patchwork_state),
+            Some((&"handoff", args)) => handoff(args, &patchwork_state),
+            Some((&"report", args)) => {
+                report(args, &patchwork_state, &inbound_packet_processor, &config)
+            }
+            Some((&"hud", args)) => toggle_hud(args, conn_id, &hud),
+            Some((&"capture", args)) => capture(args),
+            Some((&"kick", args)) => kick(args, &player_state, &messenger),
+            Some((&"kickback", args)) => kickback(args, &player_state),
+            Some((&"whitelist", args)) => whitelist_command(args, &whitelist),
+            Some((&"ban", args)) => ban(args, &bans),
+            Some((&"pardon", args)) => pardon(args, &bans),
+            Some((&"banlist", args)) => banlist(args, &bans),
+            Some((&"op", args)) => op(args, &player_state),
+            Some((&"deop", args)) => deop(args, &player_state),
+            Some((&"tp", args)) => tp(args, conn_id, &player_state),
+            Some((&"say", args)) => say(args, conn_id, &chat),
+            Some((&"list", args)) => list(args, &player_state, &patchwork_state),
+            Some((&"patchwork", args)) => describe_patchwork(args, &patchwork_state),
+            Some((command, _)) => Err(format!("Unknown command: {}", command)),
+            None => Err(String::from("Empty command")),
+        };
+        match reply {
+            Some(reply) => {
+                let _ = reply.send(feedback);
+            }
+            None => send_feedback(conn_id, &messenger, feedback),
+        }
+    }
+}
+
+// Commands whose arguments can hold secrets, which mustn't end up in the logs (and from there in
+// support bundles). Peer keys are staged through the admin API now, but anyone still trying it
+// from chat would have theirs logged as an unknown command
+const SECRET_COMMANDS: [&str; 1] = ["peerkey"];
+
+// The command as it can be logged, without the arguments of any that can hold secrets
+fn loggable(command: &str) -> String {
+    let name = command
+        .trim_start_matches('/')
+        .split_whitespace()
+        .next()
+        .unwrap_or_default();
+    if SECRET_COMMANDS.contains(&name) {
+        format!("/{} <redacted>", name)
+    } else {
+        String::from(command)
+    }
+}
+
+// The permission level a command needs, going by what it can do: 2 for cheating in the world, 3 for
+// managing players and 4 for anything that changes the server or the patchwork. Commands we don't
+// know are let through, to be reported as unknown
+pub fn required_level(command: &str) -> u8 {
+    match command {
+        "hud" | "list" | "patchwork" => 0,
+        "summon" | "kill" | "owner" | "setblock" | "fill" | "gamerule" | "tp" | "portal"
+        | "say" => 2,
+        "kick" | "kickback" | "whitelist" | "ban" | "pardon" | "banlist" => 3,
+        "topology" | "handoff" | "report" | "capture" | "pregenerate" | "op" | "deop" => {
+            MAX_PERMISSION_LEVEL
+        }
+        _ => 0,
+    }
+}
+
+// /summon <entity type> <x> <y> <z>
+fn summon<PA: PatchworkState>(args: &[&str], patchwork_state: &PA) -> Result<String, String> {
+    if args.len() != 4 {
+        return Err(String::from("Usage: /summon <entity type> <x> <y> <z>"));
+    }
+    let entity_type = parse_entity_type(args[0])?;
+    let position = Position {
+        x: parse_coordinate(args[1])?,
+        y: parse_coordinate(args[2])?,
+        z: parse_coordinate(args[3])?,
+    };
+    patchwork_state
+        .summon_entity(entity_type, position)
+        .or_log();
+    Ok(format!(
+        "Summoned {} at {} {} {}",
+        args[0], position.x, position.y, position.z
+    ))
+}
+
+// /kill <entity id>
+fn kill<PA: PatchworkState>(args: &[&str], patchwork_state: &PA) -> Result<String, String> {
+    if args.len() != 1 {
+        return Err(String::from("Usage: /kill <entity id>"));
+    }
+    let entity_id = args[0]
+        .parse::<i32>()
+        .map_err(|_| format!("Invalid entity id: {}", args[0]))?;
+    patchwork_state.kill_entity(entity_id).or_log();
+    Ok(format!("Killed entity {}", entity_id))
+}
+
+// /owner <entity id or uuid>
+fn owner<PA: PatchworkState>(args: &[&str], patchwork_state: &PA) -> Result<String, String> {
+    let entity = match args {
+        [entity] => entity,
+        _ => return Err(String::from("Usage: /owner <entity id or uuid>")),
+    };
+    let query = match entity.parse::<i32>() {
+        Ok(entity_id) => EntityQuery::Id(entity_id),
+        Err(_) => EntityQuery::Uuid(
+            Uuid::parse_str(entity)
+                .map_err(|_| format!("Invalid entity id or uuid: {}", entity))?,
+        ),
+    };
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.locate_entity(query, reply_sender).or_log();
+    match reply_receiver.recv_timeout(Duration::from_secs(ENTITY_OWNER_QUERY_TIMEOUT)) {
+        Ok(EntityOwner::Local { entity_id }) => Ok(format!(
+            "Entity {} is ours, with entity id {}",
+            entity, entity_id
+        )),
+        Ok(EntityOwner::Peer { peer, entity_id }) => Ok(format!(
+            "Entity {} belongs to {}:{}, with entity id {}",
+            entity, peer.address, peer.port, entity_id
+        )),
+        Ok(EntityOwner::Unknown) | Err(_) => Err(format!("No one owns entity {}", entity)),
+    }
+}
+
+// /setblock <x> <y> <z> <block>
+fn setblock<PA: PatchworkState>(args: &[&str], patchwork_state: &PA) -> Result<String, String> {
+    if args.len() != 4 {
+        return Err(String::from("Usage: /setblock <x> <y> <z> <block>"));
+    }
+    let position = parse_block_position(&args[0..3])?;
+    let block_id = parse_block_id(args[3])?;
+    patchwork_state
+        .fill_blocks(position, position, block_id)
+        .or_log();
+    Ok(format!(
+        "Set block at {} {} {} to {}",
+        position.x,
+        position.y,
+        position.z,
+        describe_block(block_id)
+    ))
+}
+
+// /pregenerate [chunks at a time]
+fn pregenerate<B: BlockState>(
+    args: &[&str],
+    conn_id: Uuid,
+    block_state: &B,
+) -> Result<String, String> {
+    let batch_size = match args {
+        [] => PREGENERATION_BATCH_SIZE,
+        [batch_size] => batch_size
+            .parse::<usize>()
+            .ok()
+            .filter(|batch_size| *batch_size > 0)
+            .ok_or_else(|| format!("Invalid number of chunks: {}", batch_size))?,
+        _ => return Err(String::from("Usage: /pregenerate [chunks at a time]")),
+    };
+    block_state.pregenerate(conn_id, batch_size).or_log();
+    Ok(format!(
+        "Generating the rest of the map in the background, {} chunks at a time",
+        batch_size
+    ))
+}
+
+// /fill <x1> <y1> <z1> <x2> <y2> <z2> <block>
+fn fill<PA: PatchworkState>(args: &[&str], patchwork_state: &PA) -> Result<String, String> {
+    if args.len() != 7 {
+        return Err(String::from(
+            "Usage: /fill <x1> <y1> <z1> <x2> <y2> <z2> <block>",
+        ));
+    }
+    let from = parse_block_position(&args[0..3])?;
+    let to = parse_block_position(&args[3..6])?;
+    let block_id = parse_block_id(args[6])?;
+    patchwork_state.fill_blocks(from, to, block_id).or_log();
+    Ok(format!(
+        "Filled {} {} {} to {} {} {} with {}",
+        from.x,
+        from.y,
+        from.z,
+        to.x,
+        to.y,
+        to.z,
+        describe_block(block_id)
+    ))
+}
+
+// /hud
+fn toggle_hud<H: Hud>(args: &[&str], conn_id: Uuid, hud: &H) -> Result<String, String> {
+    if !args.is_empty() {
+        return Err(String::from("Usage: /hud"));
+    }
+    let (reply_sender, reply_receiver) = channel();
+    hud.toggle(conn_id, reply_sender).or_log();
+    match reply_receiver.recv() {
+        Ok(true) => Ok(String::from("HUD on")),
+        Ok(false) => Ok(String::from("HUD off")),
+        Err(_) => Err(String::from("The HUD is unavailable")),
+    }
+}
+
+// /gamerule <rule> [true|false]
+fn gamerule<G: GameRuleState>(args: &[&str], game_rules: &G) -> Result<String, String> {
+    let rule = match args.first() {
+        Some(name) => {
+            GameRule::from_name(name).ok_or_else(|| format!("Unknown game rule: {}", name))?
+        }
+        None => return Err(String::from("Usage: /gamerule <rule> [true|false]")),
+    };
+    match args.get(1) {
+        Some(value) => {
+            let value = value
+                .parse::<bool>()
+                .map_err(|_| format!("Invalid value: {}", value))?;
+            game_rules.set(rule, value).or_log();
+            Ok(format!("Game rule {} set to {}", rule.name(), value))
+        }
+        None => {
+            let (reply_sender, reply_receiver) = channel();
+            game_rules.get(rule, reply_sender).or_log();
+            let value = reply_receiver
+                .recv()
+                .map_err(|_| String::from("Game rules are unavailable"))?;
+            Ok(format!("Game rule {} is {}", rule.name(), value))
+        }
+    }
+}
+
+// /topology export <file>
+fn topology<PA: PatchworkState>(args: &[&str], patchwork_state: &PA) -> Result<String, String> {
+    let path = match args {
+        ["export", path] => data_file(path)?,
+        _ => return Err(String::from("Usage: /topology export <file>")),
+    };
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.export_topology(reply_sender).or_log();
+    let topology = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Patchwork state is unavailable"))?;
+    topology.save(path)?;
+    Ok(format!("Exported {} maps to {}", topology.maps.len(), path))
+}
+
+// Files written for operators go where the rest of ours do, in the directory we're run from, so
+// anywhere outside it is refused rather than overwriting whatever's there
+fn data_file(path: &str) -> Result<&str, String> {
+    let inside = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    match inside && !path.is_empty() {
+        true => Ok(path),
+        false => Err(format!(
+            "{} is outside the directory we're run from, use a path inside it without ..",
+            path
+        )),
+    }
+}
+
+// /report <file>
+fn report<PA: PatchworkState, PP: PacketProcessor>(
+    args: &[&str],
+    patchwork_state: &PA,
+    inbound_packet_processor: &PP,
+    config: &Config,
+) -> Result<String, String> {
+    let path = match args {
+        [path] => data_file(path)?,
+        _ => return Err(String::from("Usage: /report <file>")),
+    };
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.export_topology(reply_sender).or_log();
+    let topology = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Patchwork state is unavailable"))?;
+    let (reply_sender, reply_receiver) = channel();
+    inbound_packet_processor.link_alerts(reply_sender).or_log();
+    let link_alerts = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Inbound packet processor is unavailable"))?;
+    // Read these as soon as the topology and alerts come back so they describe the same moment
+    let bundle = SupportBundle {
+        topology,
+        queue_depths: instance::queue_depths(),
+        link_alerts,
+        flight_recorder: flight_recorder::entries(),
+        config: config.clone(),
+    };
+    bundle.save(path)?;
+    Ok(format!("Wrote support bundle to {}", path))
+}
+
+// /capture <file>
+// /capture stop
+fn capture(args: &[&str]) -> Result<String, String> {
+    match args {
+        ["stop"] if packet_capture::stop() => Ok(String::from("Stopped capturing packets")),
+        ["stop"] => Err(String::from("Packets aren't being captured")),
+        [path] => {
+            packet_capture::start(path)?;
+            Ok(format!("Capturing packets to {}", path))
+        }
+        _ => Err(String::from("Usage: /capture <file>|stop")),
+    }
+}
+
+// /kick <player> [reason]
+fn kick<P: PlayerState, M: Messenger>(
+    args: &[&str],
+    player_state: &P,
+    messenger: &M,
+) -> Result<String, String> {
+    let (name, reason) = match args.split_first() {
+        Some((name, reason)) => (name, reason.join(" ")),
+        None => return Err(String::from("Usage: /kick <player> [reason]")),
+    };
+    let reason = match reason {
+        reason if reason.is_empty() => String::from(KICK_MESSAGE),
+        reason => reason,
+    };
+    let (reply_sender, reply_receiver) = channel();
+    player_state.online_players(reply_sender).or_log();
+    let online = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Player state is unavailable"))?;
+    match online
+        .iter()
+        .find(|(_, player, _, _)| player.eq_ignore_ascii_case(name))
+    {
+        Some((conn_id, name, _, _)) => {
+            messenger.kick(*conn_id, reason).or_log();
+            Ok(format!("Kicked {}", name))
+        }
+        None => Err(format!("{} isn't playing here", name)),
+    }
+}
+
+// /kickback <player> [reason]
+// Players visiting our map from a peer are sent back to it, or disconnected by it with the reason
+fn kickback<P: PlayerState>(args: &[&str], player_state: &P) -> Result<String, String> {
+    let (name, reason) = match args.split_first() {
+        Some((name, reason)) => (name, reason.join(" ")),
+        None => return Err(String::from("Usage: /kickback <player> [reason]")),
+    };
+    let reason = Some(reason).filter(|reason| !reason.is_empty());
+    let disconnecting = reason.is_some();
+    let (reply_sender, reply_receiver) = channel();
+    player_state
+        .kick_visitor(String::from(*name), reason, reply_sender)
+        .or_log();
+    match reply_receiver.recv() {
+        Ok(true) if disconnecting => Ok(format!("Asked for {} to be disconnected", name)),
+        Ok(true) => Ok(format!("Asked for {} to be pulled back", name)),
+        Ok(false) => Err(format!("{} isn't visiting from a peer", name)),
+        Err(_) => Err(String::from("Player state is unavailable")),
+    }
+}
+
+// /whitelist add|remove <player or uuid>
+// /whitelist list
+// /whitelist on|off
+fn whitelist_command<W: Whitelist>(args: &[&str], whitelist: &W) -> Result<String, String> {
+    let (reply_sender, reply_receiver) = channel();
+    let unavailable = |_| String::from("The whitelist is unavailable");
+    match args {
+        ["add", entry] => {
+            whitelist.add(String::from(*entry), reply_sender).or_log();
+            match reply_receiver.recv().map_err(unavailable)? {
+                true => Ok(format!("Added {} to the whitelist", entry)),
+                false => Err(format!("{} is already whitelisted", entry)),
+            }
+        }
+        ["remove", entry] => {
+            whitelist
+                .remove(String::from(*entry), reply_sender)
+                .or_log();
+            match reply_receiver.recv().map_err(unavailable)? {
+                true => Ok(format!("Removed {} from the whitelist", entry)),
+                false => Err(format!("{} isn't whitelisted", entry)),
+            }
+        }
+        ["list"] => {
+            let (reply_sender, reply_receiver) = channel();
+            whitelist.list(reply_sender).or_log();
+            let entries = reply_receiver.recv().map_err(unavailable)?;
+            Ok(format!(
+                "{} whitelisted: {}",
+                entries.len(),
+                entries.join(", ")
+            ))
+        }
+        ["on"] => {
+            whitelist.enable(true).or_log();
+            Ok(String::from("Whitelist on"))
+        }
+        ["off"] => {
+            whitelist.enable(false).or_log();
+            Ok(String::from("Whitelist off"))
+        }
+        _ => Err(String::from(
+            "Usage: /whitelist add|remove <player or uuid> | /whitelist list | /whitelist on|off",
+        )),
+    }
+}
+
+// /ban <player, uuid or address> [duration] [reason]
+// Durations are a number of minutes, hours or days, such as 30m, 12h or 7d
+fn ban<BL: BanList>(args: &[&str], bans: &BL) -> Result<String, String> {
+    let (target, rest) = match args.split_first() {
+        Some((target, rest)) => (target, rest),
+        None => {
+            return Err(String::from(
+                "Usage: /ban <player, uuid or address> [duration] [reason]",
+            ))
+        }
+    };
+    let (duration, reason) = match rest.split_first() {
+        Some((duration, reason)) if parse_duration(duration).is_some() => {
+            (parse_duration(duration), reason)
+        }
+        _ => (None, rest),
+    };
+    let reason = match reason.join(" ") {
+        reason if reason.is_empty() => String::from("Banned by an operator"),
+        reason => reason,
+    };
+    bans.add(String::from(*target), reason, duration).or_log();
+    match duration {
+        Some(_) => Ok(format!("Banned {} for {}", target, rest[0])),
+        None => Ok(format!("Banned {}", target)),
+    }
+}
+
+// /pardon <player, uuid or address>
+fn pardon<BL: BanList>(args: &[&str], bans: &BL) -> Result<String, String> {
+    let target = match args {
+        [target] => target,
+        _ => return Err(String::from("Usage: /pardon <player, uuid or address>")),
+    };
+    let (reply_sender, reply_receiver) = channel();
+    bans.pardon(String::from(*target), reply_sender).or_log();
+    match reply_receiver.recv() {
+        Ok(true) => Ok(format!("Pardoned {}", target)),
+        Ok(false) => Err(format!("{} isn't banned", target)),
+        Err(_) => Err(String::from("The ban list is unavailable")),
+    }
+}
+
+// /banlist
+fn banlist<BL: BanList>(args: &[&str], bans: &BL) -> Result<String, String> {
+    if !args.is_empty() {
+        return Err(String::from("Usage: /banlist"));
+    }
+    let (reply_sender, reply_receiver) = channel();
+    bans.list(reply_sender).or_log();
+    let bans = reply_receiver
+        .recv()
+        .map_err(|_| String::from("The ban list is unavailable"))?;
+    let targets: Vec<_> = bans.iter().map(|ban| ban.target.as_str()).collect();
+    Ok(format!("{} banned: {}", targets.len(), targets.join(", ")))
+}
+
+// /tp <x> <y> <z>
+// /tp <player> <x> <y> <z>
+// /tp <player> <other player>
+// Only our own players can be teleported, or teleported to
+fn tp<P: PlayerState>(args: &[&str], conn_id: Uuid, player_state: &P) -> Result<String, String> {
+    let (reply_sender, reply_receiver) = channel();
+    player_state.online_players(reply_sender).or_log();
+    let online = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Player state is unavailable"))?;
+    let find = |name: &str| {
+        online
+            .iter()
+            .find(|(_, player, _, _)| player.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("{} isn't playing here", name))
+    };
+    let coordinates = |args: &[&str]| -> Result<Position, String> {
+        Ok(Position {
+            x: parse_coordinate(args[0])?,
+            y: parse_coordinate(args[1])?,
+            z: parse_coordinate(args[2])?,
+        })
+    };
+    let (target, position) = match args {
+        [_, _, _] => (conn_id, coordinates(args)?),
+        [name, ..] if args.len() == 4 => (find(name)?.0, coordinates(&args[1..])?),
+        [name, other] => (find(name)?.0, find(other)?.2),
+        _ => {
+            return Err(String::from(
+                "Usage: /tp [player] <x> <y> <z> | /tp <player> <other player>",
+            ))
+        }
+    };
+    player_state.teleport(target, position).or_log();
+    let name = online
+        .iter()
+        .find(|(player_conn_id, _, _, _)| *player_conn_id == target)
+        .map_or("You", |(_, name, _, _)| name.as_str());
+    Ok(format!(
+        "Teleported {} to {:.1} {:.1} {:.1}",
+        name, position.x, position.y, position.z
+    ))
+}
+
+// /say <message>
+fn say<C: ChatService>(args: &[&str], conn_id: Uuid, chat: &C) -> Result<String, String> {
+    if args.is_empty() {
+        return Err(String::from("Usage: /say <message>"));
+    }
+    chat.announce(conn_id, args.join(" ")).or_log();
+    Ok(String::new())
+}
+
+// /list
+// Our own players, by the map they're on
+fn list<P: PlayerState, PA: PatchworkState>(
+    args: &[&str],
+    player_state: &P,
+    patchwork_state: &PA,
+) -> Result<String, String> {
+    if !args.is_empty() {
+        return Err(String::from("Usage: /list"));
+    }
+    let (reply_sender, reply_receiver) = channel();
+    player_state.online_players(reply_sender).or_log();
+    let online = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Player state is unavailable"))?;
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.export_topology(reply_sender).or_log();
+    let topology = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Patchwork state is unavailable"))?;
+    let mut maps = BTreeMap::<String, Vec<&str>>::new();
+    for (_, name, position, dimension) in &online {
+        let map_position = MapPosition {
+            x: (position.x / map_width() as f64).floor() as i32,
+            z: (position.z / map_width() as f64).floor() as i32,
+            dimension: *dimension,
+        };
+        let map = topology
+            .maps
+            .iter()
+            .find(|map| map.position == map_position)
+            .map_or_else(|| format!("{:?}", map_position), |map| map.name.clone());
+        maps.entry(map).or_default().push(name);
+    }
+    let maps: Vec<_> = maps
+        .iter()
+        .map(|(map, names)| format!("{}: {}", map, names.join(", ")))
+        .collect();
+    Ok(format!(
+        "{} players online. {}",
+        online.len(),
+        maps.join(". ")
+    ))
+}
+
+// /patchwork
+// Every map we know of, and who owns it
+fn describe_patchwork<PA: PatchworkState>(
+    args: &[&str],
+    patchwork_state: &PA,
+) -> Result<String, String> {
+    if !args.is_empty() {
+        return Err(String::from("Usage: /patchwork"));
+    }
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.export_topology(reply_sender).or_log();
+    let topology = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Patchwork state is unavailable"))?;
+    let maps: Vec<_> = topology
+        .maps
+        .iter()
+        .map(|map| {
+            format!(
+                "{} at {}, {} owned by {}",
+                map.name, map.position.x, map.position.z, map.owner
+            )
+        })
+        .collect();
+    Ok(format!("{} maps. {}", maps.len(), maps.join(". ")))
+}
+
+// /op <player> [level]
+// Operators are at the highest level unless told otherwise
+fn op<P: PlayerState>(args: &[&str], player_state: &P) -> Result<String, String> {
+    let (name, level) = match args {
+        [name] => (name, MAX_PERMISSION_LEVEL),
+        [name, level] => match level.parse::<u8>() {
+            Ok(level) if (1..=MAX_PERMISSION_LEVEL).contains(&level) => (name, level),
+            _ => {
+                return Err(format!(
+                    "Levels go from 1 to {}, not {}",
+                    MAX_PERMISSION_LEVEL, level
+                ))
+            }
+        },
+        _ => return Err(String::from("Usage: /op <player> [level]")),
+    };
+    match set_operator(name, level, player_state)? {
+        true => Ok(format!("Made {} an operator at level {}", name, level)),
+        false => Err(format!("{} is already at level {}", name, level)),
+    }
+}
+
+// /deop <player>
+fn deop<P: PlayerState>(args: &[&str], player_state: &P) -> Result<String, String> {
+    let name = match args {
+        [name] => name,
+        _ => return Err(String::from("Usage: /deop <player>")),
+    };
+    match set_operator(name, 0, player_state)? {
+        true => Ok(format!("{} is no longer an operator", name)),
+        false => Err(format!("{} isn't an operator", name)),
+    }
+}
+
+fn set_operator<P: PlayerState>(name: &str, level: u8, player_state: &P) -> Result<bool, String> {
+    let (reply_sender, reply_receiver) = channel();
+    player_state
+        .set_operator(String::from(name), level, reply_sender)
+        .or_log();
+    reply_receiver
+        .recv()
+        .map_err(|_| String::from("Player state is unavailable"))
+}
+
+// In seconds
+fn parse_duration(arg: &str) -> Option<u64> {
+    let unit = match arg.chars().last()? {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let count = arg[..arg.len() - 1].parse::<u64>().ok()?;
+    Some(count * unit)
+}
+
+// /handoff <address> <port>
+fn handoff<PA: PatchworkState>(args: &[&str], patchwork_state: &PA) -> Result<String, String> {
+    if args.len() != 2 {
+        return Err(String::from("Usage: /handoff <address> <port>"));
+    }
+    let port = args[1]
+        .parse::<u16>()
+        .map_err(|_| format!("Invalid port: {}", args[1]))?;
+    patchwork_state
+        .hand_off(Peer {
+            address: String::from(args[0]),
+            port,
+        })
+        .or_log();
+    Ok(format!("Handing off map to {}:{}", args[0], port))
+}
+
+fn parse_block_position(args: &[&str]) -> Result<BlockPosition, String> {
+    let coordinates = args
+        .iter()
+        .map(|arg| {
+            arg.parse::<i32>()
+                .map_err(|_| format!("Invalid block coordinate: {}", arg))
+        })
+        .collect::<Result<Vec<i32>, String>>()?;
+    Ok(BlockPosition {
+        x: coordinates[0],
+        y: coordinates[1],
+        z: coordinates[2],
+    })
+}
+
+// Either a block state id or the name of a block, in its default state
+fn parse_block_id(arg: &str) -> Result<i32, String> {
+    arg.parse::<i32>()
+        .ok()
+        .or_else(|| block_registry().id(arg))
+        .ok_or_else(|| format!("Invalid block: {}", arg))
+}
+
+fn describe_block(block_id: i32) -> String {
+    match block_registry().name(block_id) {
+        Some(state) => format!("{} ({})", state.name, block_id),
+        None => block_id.to_string(),
+    }
+}
+
+fn parse_coordinate(arg: &str) -> Result<f64, String> {
+    arg.parse::<f64>()
+        .map_err(|_| format!("Invalid coordinate: {}", arg))
+}
+
+// Entity type ids as of protocol 404. Numeric ids are accepted for anything not listed here
+fn parse_entity_type(arg: &str) -> Result<i32, String> {
+    match arg.trim_start_matches("minecraft:") {
+        "bat" => Ok(3),
+        "blaze" => Ok(4),
+        "cave_spider" => Ok(6),
+        "chicken" => Ok(7),
+        "cod" => Ok(8),
+        "cow" => Ok(9),
+        "creeper" => Ok(10),
+        "donkey" => Ok(11),
+        "dolphin" => Ok(12),
+        "drowned" => Ok(14),
+        "enderman" => Ok(18),
+        "endermite" => Ok(19),
+        "evoker" => Ok(21),
+        other => other
+            .parse::<i32>()
+            .map_err(|_| format!("Unknown entity type: {}", other)),
+    }
+}
+
+// Commands whose effect speaks for itself, like /say, have no feedback to send
+pub fn send_feedback<M: Messenger>(conn_id: Uuid, messenger: &M, feedback: Result<String, String>) {
+    let text = match feedback {
+        Ok(text) if text.is_empty() => return,
+        Ok(text) => text,
+        Err(text) => text,
+    };
+    messenger
+        .send_packet(
+            conn_id,
+            Packet::ClientboundChatMessage(ClientboundChatMessage {
+                json_data: ChatComponent::new(&text).to_json(),
+                position: 1, // system message
+            }),
+        )
+        .or_log();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_left_out_of_logged_commands() {
+        assert_eq!(loggable("/peerkey stage k2 hunter2"), "/peerkey <redacted>");
+        assert_eq!(loggable("/tp 1 2 3"), "/tp 1 2 3");
+    }
+
+    #[test]
+    fn files_can_only_be_written_in_the_directory_were_run_from() {
+        assert_eq!(data_file("topology.json"), Ok("topology.json"));
+        assert_eq!(
+            data_file("./reports/today.json"),
+            Ok("./reports/today.json")
+        );
+        assert!(data_file("/etc/passwd").is_err());
+        assert!(data_file("../world.json").is_err());
+        assert!(data_file("reports/../../world.json").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use super::config::{self, Config};
+use super::constants::CONFIG_RELOAD_PERIOD;
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::patchwork::PatchworkState;
+use super::map::Peer;
+
+use signal_hook::consts::SIGHUP;
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{self, SystemTime};
+
+// Reads the config file again whenever it's written to or we're sent SIGHUP, and brings the quilt
+// in line with its peers: maps are added for new ones, and removed ones have their players brought
+// back to us and their maps marked unavailable. Everything else in the config needs a restart
+pub fn start<PA: PatchworkState>(
+    receiver: Queue<i32>,
+    _: Sender<i32>,
+    patchwork_state: PA,
+    config: Config,
+) {
+    let path = match config::path() {
+        Some(path) if !config.offline => path,
+        _ => return,
+    };
+    let hangup = Arc::new(AtomicBool::new(false));
+    if let Err(e) = signal_hook::flag::register(SIGHUP, hangup.clone()) {
+        warn!(
+            "Failed to listen for SIGHUP, only reloading on changes: {}",
+            e
+        );
+    }
+    let mut peers: BTreeSet<Peer> = config.peers.into_iter().collect();
+    let mut modified = modified_at(&path);
+
+    while let Err(RecvTimeoutError::Timeout) =
+        receiver.recv_timeout(time::Duration::from_secs(CONFIG_RELOAD_PERIOD))
+    {
+        let last_modified = modified_at(&path);
+        if !hangup.swap(false, Ordering::Relaxed) && last_modified == modified {
+            continue;
+        }
+        modified = last_modified;
+        match config::read(&path) {
+            Ok(reloaded) => {
+                info!("Reloaded config file {}", path);
+                peers = apply_peers(&peers, reloaded.peers, &patchwork_state);
+            }
+            // Keep going with what we had, it's likely only half written
+            Err(e) => warn!("Not reloading config: {}", e),
+        }
+    }
+}
+
+fn modified_at(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Returns the peers we have now
+fn apply_peers<PA: PatchworkState>(
+    known: &BTreeSet<Peer>,
+    peers: Vec<Peer>,
+    patchwork_state: &PA,
+) -> BTreeSet<Peer> {
+    let peers: BTreeSet<Peer> = peers.into_iter().collect();
+    peers.difference(known).for_each(|peer| {
+        info!("Adding {}'s map from the config", peer);
+        patchwork_state.new_map(peer.clone()).or_log();
+    });
+    known.difference(&peers).for_each(|peer| {
+        info!("Removing {}'s map, it's no longer in the config", peer);
+        patchwork_state.remove_map(peer.clone()).or_log();
+    });
+    peers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::patchwork::Operations;
+    use crate::interfaces::MockPatchworkState;
+
+    fn peer(port: u16) -> Peer {
+        Peer {
+            address: String::from("127.0.0.1"),
+            port,
+        }
+    }
+
+    #[test]
+    fn only_peers_that_came_or_went_are_changed() {
+        let patchwork_state = MockPatchworkState::new();
+        let known = BTreeSet::from([peer(1), peer(2)]);
+        let peers = apply_peers(&known, vec![peer(2), peer(3), peer(3)], &patchwork_state);
+        assert_eq!(peers, BTreeSet::from([peer(2), peer(3)]));
+        match &patchwork_state.take()[..] {
+            [Operations::New(added), Operations::Remove(removed)] => {
+                assert_eq!(added.peer, peer(3));
+                assert_eq!(removed.peer, peer(1));
+            }
+            sent => panic!("Unexpected messages {:?}", sent),
+        }
+    }
+}
@@ -1,10 +1,12 @@
+use super::error::OrLog;
+use super::instance::Queue;
 use super::interfaces::connection::Operations;
 use super::interfaces::messenger::Messenger;
 use super::interfaces::packet_processor::PacketProcessor;
 use super::interfaces::patchwork::PatchworkState;
 use super::interfaces::player::PlayerState;
 
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::Sender;
 
 pub fn start<
     M: Messenger + Clone,
@@ -12,18 +14,20 @@ pub fn start<
     PA: PatchworkState + Clone,
     PP: 'static + PacketProcessor + Clone + Send,
 >(
-    receiver: Receiver<Operations>,
+    receiver: Queue<Operations>,
     _sender: Sender<Operations>,
     messenger: M,
     player_state: P,
-    _patchwork_state: PA,
-    _packet_processor: PP,
+    patchwork_state: PA,
+    packet_processor: PP,
 ) {
     while let Ok(msg) = receiver.recv() {
         match msg {
             Operations::Close(msg) => {
-                messenger.close(msg.conn_id);
-                player_state.delete_player(msg.conn_id);
+                messenger.close(msg.conn_id).or_log();
+                packet_processor.close(msg.conn_id).or_log();
+                player_state.delete_player(msg.conn_id).or_log();
+                patchwork_state.remove_player(msg.conn_id).or_log();
             }
         }
     }
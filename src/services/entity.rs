@@ -0,0 +1,490 @@
+use super::constants::{ENTITY_TICK_PERIOD, ITEM_DESPAWN_AGE};
+use super::error::OrLog;
+use super::instance::{self, Queue};
+use super::interfaces::entity::{DroppedItem, EntityState, Operations};
+use super::interfaces::entity_ids::{EntityIdAllocator, EntityIdRange};
+use super::interfaces::interest::{EntityKind, InterestManager};
+use super::interfaces::messenger::{Messenger, SubscriberType};
+use super::interfaces::patchwork::PatchworkState;
+use super::interfaces::player::{PlayerState, Position};
+use super::map::map_width;
+use super::packet::{
+    CollectItem, DestroyEntities, EntityMetadata, EntityTeleport, Packet, SpawnMob, SpawnObject,
+};
+
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Vanilla's item physics, in blocks per tick. Items slow down a lot faster on the ground, and come
+// to a stop once they're slower than ITEM_REST_SPEED
+const ITEM_GRAVITY: f64 = 0.04;
+const ITEM_DRAG: f64 = 0.98;
+const ITEM_GROUND_FRICTION: f64 = 0.6;
+const ITEM_REST_SPEED: f64 = 0.003;
+
+// Players pick up items within this many blocks of them sideways, and from a little below their
+// feet to a little above their head
+const ITEM_PICKUP_REACH: f64 = 1.3;
+const ITEM_PICKUP_BELOW: f64 = 0.5;
+const ITEM_PICKUP_ABOVE: f64 = 2.3;
+
+// How far circling mobs go every tick, in blocks
+const CIRCLING_SPEED: f64 = 0.2;
+
+// The object type of items, and where their item stack is in their metadata
+const ITEM_OBJECT_TYPE: i8 = 2;
+const ITEM_METADATA_INDEX: u8 = 6;
+const SLOT_METADATA_TYPE: i32 = 5;
+
+// Our own players see entities through the interest manager, peers are sent all of them. Items are
+// moved along every tick, and handed to whichever peer owns the map they end up over
+pub fn start<
+    M: Messenger,
+    I: EntityIdAllocator,
+    IM: InterestManager,
+    P: PlayerState,
+    PA: PatchworkState,
+>(
+    receiver: Queue<Operations>,
+    sender: Sender<Operations>,
+    messenger: M,
+    entity_ids: I,
+    interest: IM,
+    player_state: P,
+    patchwork_state: PA,
+) {
+    let mut entities = HashMap::<i32, Entity>::new();
+    let mut items = HashMap::<i32, DroppedItem>::new();
+    instance::spawn("entity-ticks", move || loop {
+        thread::sleep(Duration::from_millis(ENTITY_TICK_PERIOD));
+        sender.tick().or_log();
+    });
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Report(msg) => {
+                trace!("Reporting entity state to {:?}", msg.conn_id);
+                entities.values().for_each(|entity| {
+                    messenger
+                        .send_packet(msg.conn_id, Packet::SpawnMob(entity.spawn_mob_packet()))
+                        .or_log();
+                });
+                items.iter().for_each(|(&entity_id, item)| {
+                    item_introduction(entity_id, item)
+                        .into_iter()
+                        .for_each(|packet| messenger.send_packet(msg.conn_id, packet).or_log());
+                });
+            }
+            Operations::Summon(msg) => {
+                if let Some(entity) = summon(
+                    msg.entity_type,
+                    msg.position,
+                    None,
+                    &messenger,
+                    &entity_ids,
+                    &interest,
+                ) {
+                    entities.insert(entity.entity_id, entity);
+                }
+            }
+            Operations::SummonCircling(msg) => {
+                let orbit = Orbit {
+                    center: msg.center,
+                    radius: msg.radius,
+                    angle: msg.angle,
+                };
+                if let Some(entity) = summon(
+                    msg.entity_type,
+                    orbit.position(),
+                    Some(orbit),
+                    &messenger,
+                    &entity_ids,
+                    &interest,
+                ) {
+                    entities.insert(entity.entity_id, entity);
+                }
+            }
+            Operations::DropItem(msg) => {
+                let (reply_sender, reply_receiver) = channel();
+                entity_ids.lease(EntityIdRange::Mob, reply_sender).or_log();
+                let entity_id = match reply_receiver.recv() {
+                    Ok(Some(entity_id)) => entity_id,
+                    _ => {
+                        warn!("Cannot drop item: no entity ids left");
+                        continue;
+                    }
+                };
+                trace!("Dropping item {:?} as entity {:?}", msg.item, entity_id);
+                interest
+                    .track(
+                        entity_id,
+                        EntityKind::Item,
+                        msg.item.position,
+                        None,
+                        item_introduction(entity_id, &msg.item),
+                    )
+                    .or_log();
+                item_introduction(entity_id, &msg.item)
+                    .into_iter()
+                    .for_each(|packet| {
+                        messenger
+                            .broadcast(packet, None, SubscriberType::Remote)
+                            .or_log()
+                    });
+                items.insert(entity_id, msg.item);
+            }
+            Operations::Tick(_) => {
+                entities
+                    .values_mut()
+                    .filter(|entity| entity.orbit.is_some())
+                    .for_each(|entity| circle(entity, &messenger, &interest));
+                let mut gone = Vec::new();
+                for (&entity_id, item) in items.iter_mut() {
+                    item.age += 1;
+                    item.pickup_delay = (item.pickup_delay - 1).max(0);
+                    if item.age >= ITEM_DESPAWN_AGE {
+                        trace!("Item {:?} despawned", item.uuid);
+                        gone.push(entity_id);
+                        continue;
+                    }
+                    let from = item.position;
+                    if !fall(item) {
+                        continue;
+                    }
+                    let teleport = Packet::EntityTeleport(item_teleport_packet(entity_id, item));
+                    interest
+                        .move_entity(
+                            entity_id,
+                            item.position,
+                            vec![teleport.clone()],
+                            item_introduction(entity_id, item),
+                        )
+                        .or_log();
+                    messenger
+                        .broadcast(teleport, None, SubscriberType::Remote)
+                        .or_log();
+                    // Only the peer is told about the item until we've let go of it, so it's never
+                    // picked up twice
+                    if map_cell(from) != map_cell(item.position) {
+                        let (reply_sender, reply_receiver) = channel();
+                        patchwork_state
+                            .transfer_item(item.clone(), reply_sender)
+                            .or_log();
+                        if reply_receiver.recv() == Ok(true) {
+                            gone.push(entity_id);
+                        }
+                    }
+                }
+                gone.into_iter().for_each(|entity_id| {
+                    items.remove(&entity_id);
+                    remove_entity(entity_id, &messenger, &entity_ids, &interest);
+                });
+                pick_up_items(
+                    &mut items,
+                    &messenger,
+                    &entity_ids,
+                    &interest,
+                    &player_state,
+                );
+            }
+            Operations::Kill(msg) => match entities.remove(&msg.entity_id) {
+                Some(entity) => {
+                    trace!("Killing entity {:?}", entity);
+                    remove_entity(entity.entity_id, &messenger, &entity_ids, &interest);
+                }
+                None => trace!("No entity with id {:?} to kill", msg.entity_id),
+            },
+            Operations::Find(msg) => {
+                let _ = msg.reply.send(
+                    entities
+                        .values()
+                        .find(|entity| entity.uuid == msg.uuid)
+                        .map(|entity| entity.entity_id)
+                        .or_else(|| {
+                            items
+                                .iter()
+                                .find(|(_, item)| item.uuid == msg.uuid)
+                                .map(|(&entity_id, _)| entity_id)
+                        }),
+                );
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entity {
+    pub entity_id: i32,
+    pub uuid: Uuid,
+    pub entity_type: i32,
+    pub position: Position,
+    // Only set for mobs summoned to go in circles, the rest stand still
+    pub orbit: Option<Orbit>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Orbit {
+    center: Position,
+    radius: f64,
+    // In radians from +x
+    angle: f64,
+}
+
+impl Orbit {
+    fn position(&self) -> Position {
+        Position {
+            x: self.center.x + self.radius * self.angle.cos(),
+            y: self.center.y,
+            z: self.center.z + self.radius * self.angle.sin(),
+        }
+    }
+}
+
+impl Entity {
+    fn spawn_mob_packet(&self) -> SpawnMob {
+        SpawnMob {
+            entity_id: self.entity_id,
+            uuid: self.uuid.as_u128(),
+            entity_type: self.entity_type,
+            x: self.position.x,
+            y: self.position.y,
+            z: self.position.z,
+            yaw: 0,
+            pitch: 0,
+            head_pitch: 0,
+            velocity_x: 0,
+            velocity_y: 0,
+            velocity_z: 0,
+            entity_metadata_terminator: 0xff,
+        }
+    }
+}
+
+fn summon<M: Messenger, I: EntityIdAllocator, IM: InterestManager>(
+    entity_type: i32,
+    position: Position,
+    orbit: Option<Orbit>,
+    messenger: &M,
+    entity_ids: &I,
+    interest: &IM,
+) -> Option<Entity> {
+    let (reply_sender, reply_receiver) = channel();
+    entity_ids.lease(EntityIdRange::Mob, reply_sender).or_log();
+    let entity_id = match reply_receiver.recv() {
+        Ok(Some(entity_id)) => entity_id,
+        _ => {
+            warn!("Cannot summon entity: no entity ids left");
+            return None;
+        }
+    };
+    let entity = Entity {
+        entity_id,
+        uuid: Uuid::new_v4(),
+        entity_type,
+        position,
+        orbit,
+    };
+    trace!("Summoning entity {:?}", entity);
+    interest
+        .track(
+            entity.entity_id,
+            EntityKind::Mob,
+            entity.position,
+            None,
+            vec![Packet::SpawnMob(entity.spawn_mob_packet())],
+        )
+        .or_log();
+    messenger
+        .broadcast(
+            Packet::SpawnMob(entity.spawn_mob_packet()),
+            None,
+            SubscriberType::Remote,
+        )
+        .or_log();
+    Some(entity)
+}
+
+// Moves a circling mob on by CIRCLING_SPEED, whatever the size of its circle
+fn circle<M: Messenger, IM: InterestManager>(entity: &mut Entity, messenger: &M, interest: &IM) {
+    let orbit = match entity.orbit.as_mut() {
+        Some(orbit) => orbit,
+        None => return,
+    };
+    orbit.angle += CIRCLING_SPEED / orbit.radius.max(1.0);
+    entity.position = orbit.position();
+    let teleport = Packet::EntityTeleport(EntityTeleport {
+        entity_id: entity.entity_id,
+        x: entity.position.x,
+        y: entity.position.y,
+        z: entity.position.z,
+        yaw: 0,
+        pitch: 0,
+        on_ground: true,
+    });
+    interest
+        .move_entity(
+            entity.entity_id,
+            entity.position,
+            vec![teleport.clone()],
+            vec![Packet::SpawnMob(entity.spawn_mob_packet())],
+        )
+        .or_log();
+    messenger
+        .broadcast(teleport, None, SubscriberType::Remote)
+        .or_log();
+}
+
+fn remove_entity<M: Messenger, I: EntityIdAllocator, IM: InterestManager>(
+    entity_id: i32,
+    messenger: &M,
+    entity_ids: &I,
+    interest: &IM,
+) {
+    entity_ids.release(entity_id).or_log();
+    interest.untrack(entity_id).or_log();
+    messenger
+        .broadcast(
+            Packet::DestroyEntities(DestroyEntities {
+                entity_ids: vec![entity_id],
+            }),
+            None,
+            SubscriberType::Remote,
+        )
+        .or_log();
+}
+
+// Items that can be picked up go to the first of our players close enough with room for them
+fn pick_up_items<M: Messenger, I: EntityIdAllocator, IM: InterestManager, P: PlayerState>(
+    items: &mut HashMap<i32, DroppedItem>,
+    messenger: &M,
+    entity_ids: &I,
+    interest: &IM,
+    player_state: &P,
+) {
+    if items.values().all(|item| item.pickup_delay > 0) {
+        return;
+    }
+    let (reply_sender, reply_receiver) = channel();
+    player_state.positions(reply_sender).or_log();
+    let positions = reply_receiver.recv().unwrap_or_default();
+    let collected: Vec<(i32, i32)> = items
+        .iter()
+        .filter(|(_, item)| item.pickup_delay == 0)
+        .filter_map(|(&entity_id, item)| {
+            positions
+                .iter()
+                .filter(|(_, position)| within_reach(position, &item.position))
+                .find_map(|(conn_id, _)| {
+                    let (reply_sender, reply_receiver) = channel();
+                    player_state
+                        .pick_up(*conn_id, item.item.clone(), reply_sender)
+                        .or_log();
+                    reply_receiver.recv().ok().flatten()
+                })
+                .map(|collector| (entity_id, collector))
+        })
+        .collect();
+    collected.into_iter().for_each(|(entity_id, collector)| {
+        if let Some(item) = items.remove(&entity_id) {
+            trace!("Entity {:?} picked up item {:?}", collector, item.uuid);
+            let collect = Packet::CollectItem(CollectItem {
+                collected_entity_id: entity_id,
+                collector_entity_id: collector,
+                count: item.item.count as i32,
+            });
+            messenger
+                .broadcast(collect, None, SubscriberType::All)
+                .or_log();
+            remove_entity(entity_id, messenger, entity_ids, interest);
+        }
+    });
+}
+
+fn within_reach(player: &Position, item: &Position) -> bool {
+    (player.x - item.x).abs() <= ITEM_PICKUP_REACH
+        && (player.z - item.z).abs() <= ITEM_PICKUP_REACH
+        && item.y >= player.y - ITEM_PICKUP_BELOW
+        && item.y <= player.y + ITEM_PICKUP_ABOVE
+}
+
+// Moves the item along by a tick, returning whether it moved at all
+fn fall(item: &mut DroppedItem) -> bool {
+    let velocity = item.velocity;
+    let was_on_ground = item.position.y <= item.floor_y;
+    if !was_on_ground {
+        item.velocity.y -= ITEM_GRAVITY;
+    }
+    item.position.x += item.velocity.x;
+    item.position.y += item.velocity.y;
+    item.position.z += item.velocity.z;
+    let on_ground = item.position.y <= item.floor_y;
+    if on_ground {
+        item.position.y = item.floor_y;
+        item.velocity.y = 0.0;
+    }
+    let friction = if on_ground {
+        ITEM_DRAG * ITEM_GROUND_FRICTION
+    } else {
+        ITEM_DRAG
+    };
+    item.velocity.x *= friction;
+    item.velocity.y *= ITEM_DRAG;
+    item.velocity.z *= friction;
+    if on_ground
+        && item.velocity.x.abs() < ITEM_REST_SPEED
+        && item.velocity.z.abs() < ITEM_REST_SPEED
+    {
+        item.velocity.x = 0.0;
+        item.velocity.z = 0.0;
+    }
+    velocity.x != 0.0 || velocity.z != 0.0 || !was_on_ground
+}
+
+fn map_cell(position: Position) -> (i32, i32) {
+    (
+        (position.x / map_width() as f64).floor() as i32,
+        (position.z / map_width() as f64).floor() as i32,
+    )
+}
+
+// The item shows up as an object, which is then told what item it is
+fn item_introduction(entity_id: i32, item: &DroppedItem) -> Vec<Packet> {
+    vec![
+        Packet::SpawnObject(SpawnObject {
+            entity_id,
+            uuid: item.uuid.as_u128(),
+            object_type: ITEM_OBJECT_TYPE,
+            x: item.position.x,
+            y: item.position.y,
+            z: item.position.z,
+            pitch: 0,
+            yaw: 0,
+            data: 1,
+            velocity_x: (item.velocity.x * 8000.0) as i16,
+            velocity_y: (item.velocity.y * 8000.0) as i16,
+            velocity_z: (item.velocity.z * 8000.0) as i16,
+        }),
+        Packet::EntityMetadata(EntityMetadata {
+            entity_id,
+            index: ITEM_METADATA_INDEX,
+            metadata_type: SLOT_METADATA_TYPE,
+            item: Some(item.item.clone()),
+            terminator: 0xff,
+        }),
+    ]
+}
+
+fn item_teleport_packet(entity_id: i32, item: &DroppedItem) -> EntityTeleport {
+    EntityTeleport {
+        entity_id,
+        x: item.position.x,
+        y: item.position.y,
+        z: item.position.z,
+        yaw: 0,
+        pitch: 0,
+        on_ground: item.position.y <= item.floor_y,
+    }
+}
@@ -0,0 +1,104 @@
+use super::instance::Queue;
+use super::interfaces::entity_ids::{EntityIdRange, Operations};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
+
+pub fn start(receiver: Queue<Operations>, _sender: Sender<Operations>) {
+    let mut allocator = Allocator::new();
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Lease(msg) => {
+                let entity_id = allocator.lease(msg.range);
+                match entity_id {
+                    Some(entity_id) => trace!("Leased entity id {:?}", entity_id),
+                    None => error!("Entity ids for {:?} are exhausted", msg.range),
+                }
+                let _ = msg.reply.send(entity_id);
+            }
+            Operations::Claim(msg) => {
+                let claimed = allocator.claim(msg.entity_id);
+                if !claimed {
+                    error!("Cannot claim entity id {:?}", msg.entity_id);
+                }
+                let _ = msg.reply.send(claimed);
+            }
+            Operations::Release(msg) => {
+                trace!("Released entity id {:?}", msg.entity_id);
+                allocator.release(msg.entity_id);
+            }
+        }
+    }
+}
+
+// Hands out ids from the bottom of each range, reusing released ids before new ones
+struct Allocator {
+    next: HashMap<EntityIdRange, i32>,
+    released: HashMap<EntityIdRange, Vec<i32>>,
+    in_use: HashSet<i32>,
+}
+
+impl Allocator {
+    fn new() -> Allocator {
+        Allocator {
+            next: EntityIdRange::all()
+                .into_iter()
+                .map(|range| (range, range.bounds().0))
+                .collect(),
+            released: HashMap::new(),
+            in_use: HashSet::new(),
+        }
+    }
+
+    fn lease(&mut self, range: EntityIdRange) -> Option<i32> {
+        let entity_id = match self.released.entry(range).or_default().pop() {
+            Some(entity_id) => entity_id,
+            None => {
+                let next = self.next.get_mut(&range).unwrap();
+                if *next >= range.bounds().1 {
+                    return None;
+                }
+                *next += 1;
+                *next - 1
+            }
+        };
+        self.in_use.insert(entity_id);
+        Some(entity_id)
+    }
+
+    fn claim(&mut self, entity_id: i32) -> bool {
+        EntityIdRange::of(entity_id) == Some(EntityIdRange::AnchoredPlayer)
+            && self.in_use.insert(entity_id)
+    }
+
+    fn release(&mut self, entity_id: i32) {
+        if !self.in_use.remove(&entity_id) {
+            return;
+        }
+        match EntityIdRange::of(entity_id) {
+            Some(EntityIdRange::AnchoredPlayer) | None => {}
+            Some(range) => self.released.entry(range).or_default().push(entity_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{ANCHORED_PLAYER_ENTITY_ID_START, SERVER_MAX_CAPACITY};
+
+    #[test]
+    fn players_are_never_leased_the_id_that_means_they_need_one() {
+        let mut allocator = Allocator::new();
+        let leased: Vec<_> =
+            std::iter::from_fn(|| allocator.lease(EntityIdRange::Player)).collect();
+        assert_eq!(leased.len(), usize::from(SERVER_MAX_CAPACITY));
+        assert!(!leased.contains(&0));
+
+        // Their ids on our peers have to land in the peers' anchored player range
+        assert!(leased.iter().all(|entity_id| EntityIdRange::of(
+            ANCHORED_PLAYER_ENTITY_ID_START + entity_id
+        ) == Some(EntityIdRange::AnchoredPlayer)));
+    }
+}
@@ -0,0 +1,84 @@
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::game_rules::{GameRule, Operations};
+use super::interfaces::messenger::{Messenger, SubscriberType};
+use super::packet::{GameRuleUpdate, Packet};
+
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+// Game rules are shared by every node in the patchwork. Each rule carries a version that is bumped
+// whenever it is set locally, and updates from peers are only applied if they are newer than what
+// we have, so nodes converge on the most recent value regardless of the order reports arrive in
+pub fn start<M: Messenger>(receiver: Queue<Operations>, _sender: Sender<Operations>, messenger: M) {
+    let mut rules: HashMap<GameRule, VersionedValue> = GameRule::all()
+        .into_iter()
+        .map(|rule| {
+            (
+                rule,
+                VersionedValue {
+                    value: rule.default_value(),
+                    version: 0,
+                },
+            )
+        })
+        .collect();
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Report(msg) => {
+                trace!("Reporting game rules to {:?}", msg.conn_id);
+                rules.iter().for_each(|(rule, value)| {
+                    messenger
+                        .send_packet(msg.conn_id, value.update_packet(*rule))
+                        .or_log();
+                });
+            }
+            Operations::Get(msg) => {
+                let value = rules
+                    .get(&msg.rule)
+                    .map_or(msg.rule.default_value(), |value| value.value);
+                if msg.reply.send(value).is_err() {
+                    trace!("Game rule query for {:?} was abandoned", msg.rule);
+                }
+            }
+            Operations::Set(msg) => {
+                trace!("Setting game rule {:?} to {:?}", msg.rule, msg.value);
+                let entry = rules.get_mut(&msg.rule).unwrap();
+                entry.value = msg.value;
+                entry.version += 1;
+                messenger
+                    .broadcast(entry.update_packet(msg.rule), None, SubscriberType::Remote)
+                    .or_log();
+            }
+            Operations::PeerUpdate(msg) => {
+                let entry = rules.get_mut(&msg.rule).unwrap();
+                if msg.version > entry.version {
+                    trace!(
+                        "Syncing game rule {:?} to {:?} from peer",
+                        msg.rule,
+                        msg.value
+                    );
+                    entry.value = msg.value;
+                    entry.version = msg.version;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct VersionedValue {
+    value: bool,
+    version: i64,
+}
+
+impl VersionedValue {
+    fn update_packet(&self, rule: GameRule) -> Packet {
+        Packet::GameRuleUpdate(GameRuleUpdate {
+            rule: rule.name().to_string(),
+            value: self.value,
+            version: self.version,
+        })
+    }
+}
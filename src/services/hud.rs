@@ -0,0 +1,313 @@
+use super::constants::HUD_PERIOD;
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::hud::Operations;
+use super::interfaces::messenger::Messenger;
+use super::interfaces::packet_processor::PacketProcessor;
+use super::interfaces::patchwork::{MapDescription, PatchworkState};
+use super::interfaces::player::PlayerState;
+use super::link_watermarks::{LinkAlert, Pressure};
+use super::map::{map_width, Position as MapPosition};
+use super::minecraft_types::ChatComponent;
+use super::packet::{Packet, Title};
+
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const ACTION_BAR: i32 = 2;
+
+// Shows players who turn it on which map they're standing on, who owns it and how far away that
+// peer is, in the action bar above their hotbar. Handy for walking seams by hand
+pub fn start<M: Messenger, P: PlayerState, PA: PatchworkState, PP: PacketProcessor>(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    messenger: M,
+    player_state: P,
+    patchwork_state: PA,
+    inbound_packet_processor: PP,
+) {
+    let period = Duration::from_millis(HUD_PERIOD);
+    let mut watching = HashSet::<Uuid>::new();
+    let mut next_refresh = Instant::now() + period;
+
+    loop {
+        match receiver.recv_timeout(next_refresh.saturating_duration_since(Instant::now())) {
+            Ok(Operations::Toggle(msg)) => {
+                let on = watching.insert(msg.conn_id);
+                if !on {
+                    watching.remove(&msg.conn_id);
+                    messenger.send_packet(msg.conn_id, action_bar("")).or_log();
+                }
+                trace!("HUD for {:?} turned on: {:?}", msg.conn_id, on);
+                let _ = msg.reply.send(on);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if !watching.is_empty() {
+                    refresh(
+                        &mut watching,
+                        &messenger,
+                        &player_state,
+                        &patchwork_state,
+                        &inbound_packet_processor,
+                    );
+                }
+                next_refresh = Instant::now() + period;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+fn refresh<M: Messenger, P: PlayerState, PA: PatchworkState, PP: PacketProcessor>(
+    watching: &mut HashSet<Uuid>,
+    messenger: &M,
+    player_state: &P,
+    patchwork_state: &PA,
+    inbound_packet_processor: &PP,
+) {
+    let timeout = Duration::from_millis(HUD_PERIOD);
+    let (reply_sender, reply_receiver) = channel();
+    player_state.positions(reply_sender).or_log();
+    let positions = match reply_receiver.recv_timeout(timeout) {
+        Ok(positions) => positions,
+        Err(_) => return,
+    };
+    let (reply_sender, reply_receiver) = channel();
+    patchwork_state.describe_maps(reply_sender).or_log();
+    let maps = match reply_receiver.recv_timeout(timeout) {
+        Ok(maps) => maps,
+        Err(_) => return,
+    };
+
+    let (reply_sender, reply_receiver) = channel();
+    inbound_packet_processor.link_alerts(reply_sender).or_log();
+    let alerts = reply_receiver.recv_timeout(timeout).unwrap_or_default();
+
+    // Players who have left stop being watched
+    watching.retain(|conn_id| positions.iter().any(|(player, _)| player == conn_id));
+    positions
+        .into_iter()
+        .filter(|(conn_id, _)| watching.contains(conn_id))
+        .for_each(|(conn_id, position)| {
+            let map_position = MapPosition {
+                x: (position.x / map_width() as f64).floor() as i32,
+                z: (position.z / map_width() as f64).floor() as i32,
+                ..MapPosition::default()
+            };
+            let map = maps.iter().find(|map| map.position == map_position);
+            messenger
+                .send_packet(conn_id, action_bar(&describe(map_position, map, &alerts)))
+                .or_log();
+        });
+}
+
+fn describe(position: MapPosition, map: Option<&MapDescription>, alerts: &[LinkAlert]) -> String {
+    let map = match map {
+        Some(map) => map,
+        None => return format!("Map ({}, {}) | nobody's", position.x, position.z),
+    };
+    let name = map.name.clone().unwrap_or_else(|| String::from("Map"));
+    let owner = match &map.owner {
+        Some(peer) => peer,
+        None => return format!("{} ({}, {}) | this server", name, position.x, position.z),
+    };
+    let mut link = match (map.connected, map.latency) {
+        (false, _) => String::from("disconnected"),
+        (true, Some(latency)) => format!("{} ms", latency.as_millis()),
+        (true, None) => String::from("? ms"),
+    };
+    // Links to the owner that are falling behind
+    alerts
+        .iter()
+        .filter(|alert| alert.peer.as_ref() == Some(owner))
+        .for_each(|alert| {
+            link.push_str(&match alert.pressure {
+                Pressure::OutboundBacklog => format!(" | backlog {}", alert.reading),
+                Pressure::InboundLag => format!(" | lag {} ms", alert.reading),
+            })
+        });
+    format!(
+        "{} ({}, {}) | {}:{} | {}",
+        name, position.x, position.z, owner.address, owner.port, link
+    )
+}
+
+fn action_bar(text: &str) -> Packet {
+    Packet::Title(Title {
+        action: ACTION_BAR,
+        text: ChatComponent::new(text).to_json(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::hud::Hud;
+    use crate::interfaces::messenger::Operations as MessengerOperations;
+    use crate::interfaces::packet_processor::Operations as PacketProcessorOperations;
+    use crate::interfaces::patchwork::Operations as PatchworkOperations;
+    use crate::interfaces::player::{Operations as PlayerOperations, Position};
+    use crate::interfaces::{
+        MockMessenger, MockPacketProcessor, MockPatchworkState, MockPlayerState,
+    };
+    use crate::models::map::Peer;
+    use std::sync::mpsc::Receiver;
+    use std::thread;
+
+    fn owned_by(port: u16) -> MapDescription {
+        MapDescription {
+            position: MapPosition {
+                x: 1,
+                ..MapPosition::default()
+            },
+            name: Some(String::from("east")),
+            owner: Some(Peer {
+                address: String::from("10.0.0.2"),
+                port,
+            }),
+            connected: true,
+            latency: Some(Duration::from_millis(12)),
+            entity_id_block: 1,
+        }
+    }
+
+    #[test]
+    fn players_are_told_whose_map_theyre_on_and_how_the_link_to_it_is() {
+        let position = MapPosition {
+            x: 1,
+            ..MapPosition::default()
+        };
+        assert_eq!(describe(position, None, &[]), "Map (1, 0) | nobody's");
+
+        let ours = MapDescription {
+            owner: None,
+            ..owned_by(25565)
+        };
+        assert_eq!(
+            describe(position, Some(&ours), &[]),
+            "east (1, 0) | this server"
+        );
+
+        let map = owned_by(25566);
+        assert_eq!(
+            describe(position, Some(&map), &[]),
+            "east (1, 0) | 10.0.0.2:25566 | 12 ms"
+        );
+        let alerts = [
+            LinkAlert {
+                conn_id: String::new(),
+                peer: map.owner.clone(),
+                pressure: Pressure::OutboundBacklog,
+                reading: 300,
+            },
+            LinkAlert {
+                conn_id: String::new(),
+                peer: owned_by(25567).owner,
+                pressure: Pressure::InboundLag,
+                reading: 900,
+            },
+        ];
+        assert_eq!(
+            describe(position, Some(&map), &alerts),
+            "east (1, 0) | 10.0.0.2:25566 | 12 ms | backlog 300"
+        );
+        let disconnected = MapDescription {
+            connected: false,
+            ..owned_by(25566)
+        };
+        assert_eq!(
+            describe(position, Some(&disconnected), &[]),
+            "east (1, 0) | 10.0.0.2:25566 | disconnected"
+        );
+    }
+
+    // Starts the HUD for one player standing on the map owned by a peer, returning when each action
+    // bar was sent to them and what it said
+    fn hud(conn_id: Uuid) -> (Sender<Operations>, Receiver<(Instant, String)>) {
+        let (sender, receiver) = channel();
+        let (sent_sender, sent) = channel();
+        let messenger = MockMessenger::responding(move |msg| {
+            if let MessengerOperations::Send(msg) = msg {
+                if let Packet::Title(title) = &msg.packet {
+                    let _ = sent_sender.send((Instant::now(), title.text.clone()));
+                }
+            }
+        });
+        let player_state = MockPlayerState::responding(move |msg| {
+            if let PlayerOperations::Positions(msg) = msg {
+                let position = Position {
+                    x: map_width() as f64 + 1.0,
+                    y: 64.0,
+                    z: 1.0,
+                };
+                let _ = msg.reply.send(vec![(conn_id, position)]);
+            }
+        });
+        let patchwork_state = MockPatchworkState::responding(|msg| {
+            if let PatchworkOperations::DescribeMaps(msg) = msg {
+                let _ = msg.reply.send(vec![owned_by(25566)]);
+            }
+        });
+        let inbound_packet_processor = MockPacketProcessor::responding(|msg| {
+            if let PacketProcessorOperations::LinkAlerts(msg) = msg {
+                let _ = msg.reply.send(vec![LinkAlert {
+                    conn_id: String::new(),
+                    peer: owned_by(25566).owner,
+                    pressure: Pressure::OutboundBacklog,
+                    reading: 300,
+                }]);
+            }
+        });
+        let own_sender = sender.clone();
+        thread::spawn(move || {
+            start(
+                Queue::uncounted(receiver),
+                own_sender,
+                messenger,
+                player_state,
+                patchwork_state,
+                inbound_packet_processor,
+            )
+        });
+        (sender, sent)
+    }
+
+    #[test]
+    fn the_hud_is_refreshed_every_period_until_its_turned_off() {
+        let period = Duration::from_millis(HUD_PERIOD);
+        let conn_id = Uuid::new_v4();
+        let (hud, sent) = hud(conn_id);
+        let toggle = || {
+            let (reply_sender, reply_receiver) = channel();
+            hud.toggle(conn_id, reply_sender).unwrap();
+            reply_receiver.recv_timeout(period).unwrap()
+        };
+
+        assert!(toggle());
+        let refreshes: Vec<(Instant, String)> = (0..3)
+            .map(|_| sent.recv_timeout(period * 2).unwrap())
+            .collect();
+        assert!(refreshes
+            .iter()
+            .all(|(_, text)| text.contains("east (1, 0) | 10.0.0.2:25566 | 12 ms | backlog 300")));
+        refreshes.windows(2).for_each(|pair| {
+            let between = pair[1].0 - pair[0].0;
+            assert!(
+                between > period * 3 / 4 && between < period * 2,
+                "{:?} between refreshes",
+                between
+            );
+        });
+
+        // Turning it off clears the action bar, and nothing's sent after that
+        assert!(!toggle());
+        while let Ok((_, text)) = sent.recv_timeout(period / 4) {
+            if text == ChatComponent::new("").to_json() {
+                break;
+            }
+        }
+        assert!(sent.recv_timeout(period * 2).is_err());
+    }
+}
@@ -1,14 +1,206 @@
-use std::sync::mpsc::channel;
-use std::sync::mpsc::{Receiver, Sender};
+use super::constants::{SERVICE_MAX_RESTARTS, SERVICE_RESTART_WINDOW};
+use super::interfaces::{Queued, Restartable};
+
+use core_affinity::CoreId;
+use std::cmp::min;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel};
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, Sender, SyncSender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 pub struct ServiceInstance<O> {
     pub receiver: Option<Receiver<O>>,
     sender: Sender<O>,
 }
 
-impl<O> ServiceInstance<O> {
-    pub fn new() -> ServiceInstance<O> {
+type QueueDepth = fn() -> usize;
+
+// Every running service, so that their queue depths can be reported
+static QUEUES: Mutex<Vec<(&'static str, QueueDepth)>> = Mutex::new(Vec::new());
+
+pub fn queue_depths() -> Vec<(&'static str, usize)> {
+    QUEUES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, depth)| (*name, depth()))
+        .collect()
+}
+
+// Every service's thread, so that shutdown can wait for them to finish
+static THREADS: Mutex<Vec<(&'static str, JoinHandle<()>)>> = Mutex::new(Vec::new());
+
+// Once set, services stop being handed messages, which ends their event loops
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+// The cores threads are pinned to, by thread name
+static PINNED_THREADS: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+// How often a service's queue checks whether it should stop while nothing is arriving
+const STOP_POLL_PERIOD: Duration = Duration::from_millis(100);
+
+// Shared by every node in the process, like the services themselves
+pub fn set_pinned_threads(pinned_threads: BTreeMap<String, usize>) {
+    *PINNED_THREADS.lock().unwrap() = pinned_threads;
+}
+
+// Every thread we start goes through here so that it's named, which is how it shows up in top -H
+// and debuggers, and pinned to a core if the config asks for it. Linux only shows the first 15
+// bytes of a name, so names keep what tells them apart up front
+pub fn spawn<T, F>(name: &str, run: F) -> JoinHandle<T>
+where
+    T: 'static + Send,
+    F: 'static + Send + FnOnce() -> T,
+{
+    let core = PINNED_THREADS.lock().unwrap().get(name).copied();
+    let name = String::from(name);
+    thread::Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            if let Some(core) = core {
+                if !core_affinity::set_for_current(CoreId { id: core }) {
+                    warn!("Couldn't pin {} to core {}", name, core);
+                }
+            }
+            run()
+        })
+        .unwrap()
+}
+
+pub fn track(name: &'static str, thread: JoinHandle<()>) {
+    THREADS.lock().unwrap().push((name, thread));
+}
+
+// Stops every service and waits up to timeout for their threads to finish, returning the names of
+// the ones that haven't
+pub fn stop_services(timeout: Duration) -> Vec<&'static str> {
+    STOPPING.store(true, Ordering::Release);
+    let threads: Vec<(&'static str, JoinHandle<()>)> = THREADS.lock().unwrap().drain(..).collect();
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline && threads.iter().any(|(_, thread)| !thread.is_finished()) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    threads
+        .into_iter()
+        .filter_map(|(name, thread)| {
+            if thread.is_finished() {
+                let _ = thread.join();
+                None
+            } else {
+                Some(name)
+            }
+        })
+        .collect()
+}
+
+// How many times a service that keeps panicking is restarted before it's left stopped, as by then
+// it's probably not getting past whatever's making it panic
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    pub max_restarts: usize,
+    pub window: Duration,
+}
+
+impl RestartPolicy {
+    pub fn on_panic() -> RestartPolicy {
+        RestartPolicy {
+            max_restarts: SERVICE_MAX_RESTARTS,
+            window: Duration::from_secs(SERVICE_RESTART_WINDOW),
+        }
+    }
+
+    fn allows(&self, restarts: &mut VecDeque<Instant>, now: Instant) -> bool {
+        while restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) > self.window)
+        {
+            restarts.pop_front();
+        }
+        if restarts.len() >= self.max_restarts {
+            return false;
+        }
+        restarts.push_back(now);
+        true
+    }
+}
+
+fn queue_depth<O: Queued>() -> usize {
+    O::queue_depth().load(Ordering::Relaxed)
+}
+
+// A service's end of its queue. Messages stay counted in the queue depth until the service actually
+// takes them, and once we're stopping the service is told there's nothing more to come
+pub struct Queue<O> {
+    receiver: Receiver<O>,
+    // Supervised services are handed messages that their supervisor has already counted
+    counted: bool,
+}
+
+impl<O: Queued> Queue<O> {
+    pub fn new(receiver: Receiver<O>) -> Queue<O> {
+        Queue {
+            receiver,
+            counted: true,
+        }
+    }
+
+    // For messages that were never counted, or have been already
+    pub fn uncounted(receiver: Receiver<O>) -> Queue<O> {
+        Queue {
+            receiver,
+            counted: false,
+        }
+    }
+
+    pub fn recv(&self) -> Result<O, RecvError> {
+        loop {
+            match self.recv_timeout(STOP_POLL_PERIOD) {
+                Ok(msg) => return Ok(msg),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvError),
+            }
+        }
+    }
+
+    // Checks whether we're stopping at least every STOP_POLL_PERIOD while it waits
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<O, RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            if STOPPING.load(Ordering::Acquire) {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let wait = deadline.map_or(STOP_POLL_PERIOD, |deadline| {
+                min(
+                    deadline.saturating_duration_since(Instant::now()),
+                    STOP_POLL_PERIOD,
+                )
+            });
+            match self.receiver.recv_timeout(wait) {
+                Ok(msg) => {
+                    if self.counted {
+                        O::queue_depth().fetch_sub(1, Ordering::Relaxed);
+                    }
+                    return Ok(msg);
+                }
+                Err(RecvTimeoutError::Timeout)
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) =>
+                {
+                    return Err(RecvTimeoutError::Timeout)
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            }
+        }
+    }
+}
+
+impl<O: 'static + Queued + Send> ServiceInstance<O> {
+    pub fn new(name: &'static str) -> ServiceInstance<O> {
         let (sender, receiver) = channel();
+        QUEUES.lock().unwrap().push((name, queue_depth::<O>));
         ServiceInstance {
             receiver: Some(receiver),
             sender,
@@ -19,7 +211,11 @@ impl<O> ServiceInstance<O> {
         self.sender.clone()
     }
 
-    pub fn receiver(&mut self) -> Receiver<O> {
+    pub fn receiver(&mut self) -> Queue<O> {
+        Queue::new(self.take_queue())
+    }
+
+    fn take_queue(&mut self) -> Receiver<O> {
         match self.receiver.take() {
             Some(receiver) => receiver,
             _ => {
@@ -29,19 +225,209 @@ impl<O> ServiceInstance<O> {
     }
 }
 
+impl<O: 'static + Queued + Restartable + Send> ServiceInstance<O> {
+    // Runs the service like any other, but starts it over whenever it panics, for as long as the
+    // policy allows. The queue outlives each run of the service, so whoever talks to it carries on
+    // with the sender they have, and only the message it was being handed when it died is lost.
+    // Everything recorded in the snapshot is replayed to the new run before it's handed anything
+    // else
+    pub fn supervise<F>(&mut self, name: &'static str, policy: RestartPolicy, run: F)
+    where
+        F: 'static + Send + Clone + Fn(Queue<O>),
+    {
+        let queue = self.take_queue();
+        track(
+            name,
+            spawn(&format!("{}-supervisor", name), move || {
+                let mut snapshot = O::Snapshot::default();
+                let mut restarts = VecDeque::new();
+                let (mut sender, mut service) = launch(name, &run);
+                while !STOPPING.load(Ordering::Acquire) {
+                    // Services only return once their queue is gone, so one that's finished
+                    // before then has panicked
+                    if service.is_finished() {
+                        if !policy.allows(&mut restarts, Instant::now()) {
+                            error!(
+                                "{} panicked {} times in {:?}, leaving it stopped",
+                                name,
+                                restarts.len() + 1,
+                                policy.window
+                            );
+                            break;
+                        }
+                        let replayed = O::replay(&snapshot);
+                        warn!(
+                            "{} panicked, restarting it with {} messages replayed",
+                            name,
+                            replayed.len()
+                        );
+                        let (new_sender, new_service) = launch(name, &run);
+                        sender = new_sender;
+                        service = new_service;
+                        for msg in replayed {
+                            if sender.send(msg).is_err() {
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                    match queue.recv_timeout(STOP_POLL_PERIOD) {
+                        Ok(msg) => {
+                            msg.record(&mut snapshot);
+                            O::queue_depth().fetch_sub(1, Ordering::Relaxed);
+                            if sender.send(msg).is_err() {
+                                warn!("Dropped a message to {}, which has panicked", name);
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                drop(sender);
+                let _ = service.join();
+            }),
+        );
+    }
+}
+
+fn launch<O, F>(name: &str, run: &F) -> (SyncSender<O>, JoinHandle<()>)
+where
+    O: 'static + Queued + Send,
+    F: 'static + Send + Clone + Fn(Queue<O>),
+{
+    let (sender, receiver) = sync_channel(0);
+    let run = run.clone();
+    (sender, spawn(name, move || run(Queue::uncounted(receiver))))
+}
+
+// Timer services are never sent anything, they just wait on their queue between ticks so that they
+// stop along with everything else
+impl Queued for i32 {
+    fn queue_depth() -> &'static AtomicUsize {
+        static QUEUE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+        &QUEUE_DEPTH
+    }
+}
+
 // 1. Create the service instance struct (which creates a channel for you)
-// 2. Run the service event loop method with a clone of the sender of all services it depends on
+// 2. Run the service event loop method with a clone of the sender of all services it depends on, on
+//    a thread that's tracked so shutdown can wait for it. Services with a restart policy are
+//    supervised instead, and get fresh clones each time they're restarted
 macro_rules! define_services {
-    ($( (module: $service:path, name: $service_instance:ident, dependencies: [$($dependency:ident),*] $(, extras: [$($extra:ident),*])?)),*) => (
-        $(let mut $service_instance = ServiceInstance::new();)*
+    ($( (module: $service:path, name: $service_instance:ident, dependencies: [$($dependency:ident),*] $(, extras: [$($extra:ident),*])? $(, restart: $policy:ident)?)),*) => (
+        $(let mut $service_instance = ServiceInstance::new(stringify!($service_instance));)*
         $(
             paste::expr! {
                 $(let [<$dependency _clone>] = $dependency.sender();)*
                 $($(let [<$extra _clone>] = $extra.clone();)*)?
                 let sender = $service_instance.sender();
-                let receiver = $service_instance.receiver();
-                thread::spawn(move || $service(receiver, sender $(, {[<$dependency _clone>]})* $(, $({[<$extra _clone>]})*)? ));
+                let run = move |receiver| $service(receiver, sender.clone() $(, {[<$dependency _clone>].clone()})* $(, $({[<$extra _clone>].clone()}),*)? );
+                start_service!($service_instance, run $(, $policy)?);
             }
         )*
     );
 }
+
+macro_rules! start_service {
+    ($service_instance:ident, $run:ident) => {
+        let receiver = $service_instance.receiver();
+        $crate::services::instance::track(
+            stringify!($service_instance),
+            $crate::services::instance::spawn(stringify!($service_instance), move || {
+                $run(receiver)
+            }),
+        );
+    };
+    ($service_instance:ident, $run:ident, $policy:ident) => {
+        $service_instance.supervise(
+            stringify!($service_instance),
+            $crate::services::instance::RestartPolicy::$policy(),
+            $run,
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_are_refused_once_too_many_fall_within_the_window() {
+        let policy = RestartPolicy {
+            max_restarts: 2,
+            window: Duration::from_secs(10),
+        };
+        let start = Instant::now();
+        let mut restarts = VecDeque::new();
+        assert!(policy.allows(&mut restarts, start));
+        assert!(policy.allows(&mut restarts, start + Duration::from_secs(5)));
+        assert!(!policy.allows(&mut restarts, start + Duration::from_secs(8)));
+        assert_eq!(restarts.len(), 2);
+
+        // The first restart has left the window, so there's room for one more
+        assert!(policy.allows(&mut restarts, start + Duration::from_secs(11)));
+        assert_eq!(
+            restarts,
+            [
+                start + Duration::from_secs(5),
+                start + Duration::from_secs(11)
+            ]
+        );
+        assert!(!policy.allows(&mut restarts, start + Duration::from_secs(12)));
+    }
+
+    enum Counter {
+        Add(u32),
+        Panic,
+    }
+
+    impl Queued for Counter {
+        fn queue_depth() -> &'static AtomicUsize {
+            static QUEUE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+            &QUEUE_DEPTH
+        }
+    }
+
+    impl Restartable for Counter {
+        type Snapshot = Vec<u32>;
+
+        fn record(&self, snapshot: &mut Vec<u32>) {
+            if let Counter::Add(value) = self {
+                snapshot.push(*value);
+            }
+        }
+
+        fn replay(snapshot: &Vec<u32>) -> Vec<Counter> {
+            snapshot.iter().map(|value| Counter::Add(*value)).collect()
+        }
+    }
+
+    #[test]
+    fn panicking_services_are_restarted_with_their_snapshot_replayed_first() {
+        const TIMEOUT: Duration = Duration::from_secs(5);
+
+        let (seen_sender, seen) = channel();
+        let mut instance = ServiceInstance::<Counter>::new("counter");
+        instance.supervise("counter", RestartPolicy::on_panic(), move |receiver| {
+            while let Ok(msg) = receiver.recv() {
+                match msg {
+                    Counter::Add(value) => seen_sender.send(value).unwrap(),
+                    Counter::Panic => panic!("asked to"),
+                }
+            }
+        });
+        let counter = instance.sender();
+
+        counter.send(Counter::Add(1)).unwrap();
+        counter.send(Counter::Add(2)).unwrap();
+        assert_eq!(seen.recv_timeout(TIMEOUT), Ok(1));
+        assert_eq!(seen.recv_timeout(TIMEOUT), Ok(2));
+
+        counter.send(Counter::Panic).unwrap();
+        assert_eq!(seen.recv_timeout(TIMEOUT), Ok(1));
+        assert_eq!(seen.recv_timeout(TIMEOUT), Ok(2));
+
+        counter.send(Counter::Add(3)).unwrap();
+        assert_eq!(seen.recv_timeout(TIMEOUT), Ok(3));
+    }
+}
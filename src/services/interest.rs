@@ -0,0 +1,170 @@
+use super::config::TrackingRanges;
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::interest::{EntityKind, Operations};
+use super::interfaces::messenger::Messenger;
+use super::interfaces::player::Position;
+use super::packet::{DestroyEntities, Packet};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
+use uuid::Uuid;
+
+// Decides which of our players can see which entities. Entities are only introduced to players
+// within their kind's tracking range, and taken back once they're out of it, so players far apart
+// aren't sent each other's every move. Players are both entities and viewers: when they move, the
+// entities around them come into and go out of view too
+pub fn start<M: Messenger>(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    messenger: M,
+    ranges: TrackingRanges,
+) {
+    let mut entities = HashMap::<i32, Tracked>::new();
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Track(msg) => {
+                trace!("Tracking {:?} entity {:?}", msg.kind, msg.entity_id);
+                entities.insert(
+                    msg.entity_id,
+                    Tracked {
+                        kind: msg.kind,
+                        position: msg.position,
+                        viewer: msg.viewer,
+                        introduction: msg.introduction,
+                        seen_by: HashSet::new(),
+                    },
+                );
+                update_interest(msg.entity_id, &[], &mut entities, &ranges, &messenger);
+            }
+            Operations::Move(msg) => {
+                if let Some(entity) = entities.get_mut(&msg.entity_id) {
+                    entity.position = msg.position;
+                    entity.introduction = msg.introduction;
+                    update_interest(
+                        msg.entity_id,
+                        &msg.packets,
+                        &mut entities,
+                        &ranges,
+                        &messenger,
+                    );
+                }
+            }
+            Operations::Untrack(msg) => {
+                if let Some(entity) = entities.remove(&msg.entity_id) {
+                    trace!("No longer tracking entity {:?}", msg.entity_id);
+                    entity
+                        .seen_by
+                        .iter()
+                        .for_each(|conn_id| forget(msg.entity_id, *conn_id, &messenger));
+                    if let Some(viewer) = entity.viewer {
+                        entities.values_mut().for_each(|other| {
+                            other.seen_by.remove(&viewer);
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Tracked {
+    kind: EntityKind,
+    position: Position,
+    // The player's connection, for entities that are players of ours
+    viewer: Option<Uuid>,
+    // What's sent to players the entity comes into view of
+    introduction: Vec<Packet>,
+    seen_by: HashSet<Uuid>,
+}
+
+// Works out who can see the entity now that it's moved, sending them the packets that go with the
+// move, and if it's a player, what it can see
+fn update_interest<M: Messenger>(
+    entity_id: i32,
+    packets: &[Packet],
+    entities: &mut HashMap<i32, Tracked>,
+    ranges: &TrackingRanges,
+    messenger: &M,
+) {
+    let viewers: Vec<(Uuid, Position)> = entities
+        .values()
+        .filter_map(|entity| Some((entity.viewer?, entity.position)))
+        .collect();
+    let entity = entities.get_mut(&entity_id).unwrap();
+    let entity_range = range(ranges, entity.kind);
+    let own_viewer = entity.viewer;
+    viewers
+        .iter()
+        .filter(|(conn_id, _)| Some(*conn_id) != own_viewer)
+        .for_each(|(conn_id, position)| {
+            let in_range = distance(entity.position, *position) <= entity_range;
+            match (entity.seen_by.contains(conn_id), in_range) {
+                (true, true) => packets
+                    .iter()
+                    .for_each(|packet| messenger.send_packet(*conn_id, packet.clone()).or_log()),
+                (false, true) => {
+                    introduce(&entity.introduction, *conn_id, messenger);
+                    entity.seen_by.insert(*conn_id);
+                }
+                (true, false) => {
+                    forget(entity_id, *conn_id, messenger);
+                    entity.seen_by.remove(conn_id);
+                }
+                (false, false) => {}
+            }
+        });
+
+    let (viewer, position) = match entity.viewer {
+        Some(viewer) => (viewer, entity.position),
+        None => return,
+    };
+    entities
+        .iter_mut()
+        .filter(|(other_id, _)| **other_id != entity_id)
+        .for_each(|(other_id, other)| {
+            let in_range = distance(other.position, position) <= range(ranges, other.kind);
+            match (other.seen_by.contains(&viewer), in_range) {
+                (false, true) => {
+                    introduce(&other.introduction, viewer, messenger);
+                    other.seen_by.insert(viewer);
+                }
+                (true, false) => {
+                    forget(*other_id, viewer, messenger);
+                    other.seen_by.remove(&viewer);
+                }
+                _ => {}
+            }
+        });
+}
+
+fn range(ranges: &TrackingRanges, kind: EntityKind) -> f64 {
+    f64::from(match kind {
+        EntityKind::Player => ranges.players,
+        EntityKind::Mob => ranges.mobs,
+        EntityKind::Item => ranges.items,
+    })
+}
+
+// Vanilla tracks entities within a square around the player rather than a circle, and ignores height
+fn distance(a: Position, b: Position) -> f64 {
+    (a.x - b.x).abs().max((a.z - b.z).abs())
+}
+
+fn introduce<M: Messenger>(introduction: &[Packet], conn_id: Uuid, messenger: &M) {
+    introduction
+        .iter()
+        .for_each(|packet| messenger.send_packet(conn_id, packet.clone()).or_log());
+}
+
+fn forget<M: Messenger>(entity_id: i32, conn_id: Uuid, messenger: &M) {
+    messenger
+        .send_packet(
+            conn_id,
+            Packet::DestroyEntities(DestroyEntities {
+                entity_ids: vec![entity_id],
+            }),
+        )
+        .or_log();
+}
@@ -1,21 +1,132 @@
-use super::interfaces::messenger::{Messenger, SubscriberType};
+use super::config::{Config, KeepAlivePolicy};
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::keep_alive::{KeepAliveKind, Operations};
+use super::interfaces::messenger::Messenger;
 use super::packet::{KeepAlive, Packet};
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread::sleep;
-use std::time;
 
-const KEEP_ALIVE_PERIOD: u64 = 15;
-const KEEP_ALIVE_VALUE: i64 = 16;
+use std::collections::HashMap;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const TIMEOUT_MESSAGE: &str = "Timed out";
+
+// How often deadlines are checked when nothing's arriving
+const CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+struct Watched {
+    kind: KeepAliveKind,
+    // The keep-alive we're waiting on an answer to, if any
+    waiting_on: Option<i64>,
+    next_ping: Instant,
+    // When we give up on hearing back
+    deadline: Instant,
+    misses: u32,
+}
+
+// Connections in play are kept alive according to what they are: clients are sent keep-alives
+// and have to answer them, and anchors from our peers have to pass their player's answers along.
+// Either is dropped once it's missed more in a row than its policy allows
+pub fn start<M: Messenger>(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    messenger: M,
+    config: Config,
+) {
+    let policy = |kind| match kind {
+        KeepAliveKind::Client => config.keep_alive.clients,
+        KeepAliveKind::Anchor => config.keep_alive.anchors,
+    };
+    let mut watching = HashMap::<Uuid, Watched>::new();
 
-pub fn start<M: Messenger>(_: Receiver<i32>, _: Sender<i32>, messenger: M) {
     loop {
-        sleep(time::Duration::from_secs(KEEP_ALIVE_PERIOD));
-        messenger.broadcast(
-            Packet::KeepAlive(KeepAlive {
-                id: KEEP_ALIVE_VALUE,
-            }),
-            None,
-            SubscriberType::Local,
-        );
+        match receiver.recv_timeout(CHECK_PERIOD) {
+            Ok(Operations::Watch(msg)) => {
+                let now = Instant::now();
+                watching.insert(
+                    msg.conn_id,
+                    Watched {
+                        kind: msg.kind,
+                        waiting_on: None,
+                        next_ping: now,
+                        deadline: now + allowance(policy(msg.kind)),
+                        misses: 0,
+                    },
+                );
+            }
+            Ok(Operations::Unwatch(msg)) => {
+                watching.remove(&msg.conn_id);
+            }
+            Ok(Operations::Answer(msg)) => {
+                if let Some(watched) = watching.get_mut(&msg.conn_id) {
+                    // Anchors pass on answers to keep-alives we never sent
+                    if watched.kind == KeepAliveKind::Anchor || watched.waiting_on == Some(msg.id) {
+                        watched.waiting_on = None;
+                        watched.misses = 0;
+                        watched.deadline = Instant::now() + allowance(policy(watched.kind));
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let now = Instant::now();
+        watching.retain(|conn_id, watched| {
+            let policy = policy(watched.kind);
+            let missed = match watched.kind {
+                KeepAliveKind::Client => watched.waiting_on.is_some() && now >= watched.deadline,
+                KeepAliveKind::Anchor => now >= watched.deadline,
+            };
+            if missed {
+                watched.misses += 1;
+                watched.waiting_on = None;
+                watched.deadline = now + Duration::from_secs(policy.period_seconds);
+                trace!(
+                    "{:?} {:?} missed {} keep-alives",
+                    watched.kind,
+                    conn_id,
+                    watched.misses
+                );
+                if watched.misses > policy.allowed_misses {
+                    warn!("Dropping {:?} {:?}, which timed out", watched.kind, conn_id);
+                    match watched.kind {
+                        KeepAliveKind::Client => messenger
+                            .kick(*conn_id, String::from(TIMEOUT_MESSAGE))
+                            .or_log(),
+                        KeepAliveKind::Anchor => messenger.close(*conn_id).or_log(),
+                    }
+                    return false;
+                }
+            }
+            // Like vanilla, clients only have one keep-alive to answer at a time
+            if watched.kind == KeepAliveKind::Client
+                && watched.waiting_on.is_none()
+                && now >= watched.next_ping
+            {
+                let id = ping_id();
+                messenger
+                    .send_packet(*conn_id, Packet::KeepAlive(KeepAlive { id }))
+                    .or_log();
+                watched.waiting_on = Some(id);
+                watched.deadline = now + Duration::from_secs(policy.timeout_seconds);
+                watched.next_ping = now + Duration::from_secs(policy.period_seconds);
+            }
+            true
+        });
     }
 }
+
+// How long an anchor can go without passing on an answer
+fn allowance(policy: KeepAlivePolicy) -> Duration {
+    Duration::from_secs(policy.period_seconds + policy.timeout_seconds)
+}
+
+// Like vanilla, keep-alives carry the time they were sent
+fn ping_id() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
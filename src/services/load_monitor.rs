@@ -0,0 +1,16 @@
+use super::constants::LOAD_CHECK_PERIOD;
+use super::instance::Queue;
+use super::interfaces::patchwork::PatchworkState;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time;
+
+pub fn start<PA: PatchworkState>(receiver: Queue<i32>, _: Sender<i32>, patchwork_state: PA) {
+    while let Err(RecvTimeoutError::Timeout) =
+        receiver.recv_timeout(time::Duration::from_secs(LOAD_CHECK_PERIOD))
+    {
+        if let Err(e) = patchwork_state.check_load() {
+            error!("Stopping load checks: {}", e);
+            break;
+        }
+    }
+}
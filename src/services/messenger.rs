@@ -1,34 +1,95 @@
-use super::super::interfaces::messenger::{Operations, SubscriberType};
-use super::packet::{translate_outgoing, write, Packet};
+use super::super::interfaces::messenger::{ConnectionClass, Operations, Origin, SubscriberType};
+use super::super::interfaces::packet_processor::PacketProcessor;
+use super::compression;
+use super::constants::{
+    KICK_FLUSH_TIMEOUT, MAX_RELAY_HOPS, RELAY_SEEN_LIMIT, SHUTDOWN_TIMEOUT, TRANSFORM_POOL_WORKERS,
+};
+use super::error::{OrLog, PatchworkError};
+use super::identity::Identity;
+use super::instance::Queue;
+use super::map::Peer;
+use super::metrics;
+use super::minecraft_types::ChatComponent;
+use super::packet::{translate_outgoing, Disconnect, LoginDisconnect, Packet};
+use super::packet_capture::{self, Direction};
+use super::protocol_adapter::ProtocolAdapter;
+use super::transform_pool::{is_expensive, link_channel, Channel, TransformPool};
 use super::translation::TranslationInfo;
 
-use std::collections::{HashMap, HashSet};
-use std::net::TcpStream;
-use std::sync::mpsc::{Receiver, Sender};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::field;
 use uuid::Uuid;
 
-pub fn start(receiver: Receiver<Operations>, _sender: Sender<Operations>) {
-    let mut connection_map = HashMap::<Uuid, TcpStream>::new();
+struct Connection {
+    socket: TcpStream,
+    in_flight: Arc<AtomicUsize>,
+    adapter: ProtocolAdapter,
+    class: ConnectionClass,
+    // What the inbound packet processor was last told was waiting to be sent, for peer links
+    reported_backlog: Cell<usize>,
+}
+
+pub fn start<PP: PacketProcessor>(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    inbound_packet_processor: PP,
+) {
+    let mut connection_map = HashMap::<Uuid, Connection>::new();
+    let transform_pool = TransformPool::new(TRANSFORM_POOL_WORKERS);
     let mut subscriber_list = SubscriberList::new();
     let mut translation_data = HashMap::<Uuid, TranslationInfo>::new();
+    // Which peer is at the other end of each peer connection, in either direction
+    let mut peer_nodes = HashMap::<Uuid, Peer>::new();
+    // Which instance each peer is, as told to us on any connection with it. A peer's anchors and
+    // subscription share its identity
+    let mut instances = HashMap::<Uuid, Identity>::new();
+    let mut peer_instances = HashMap::<Peer, Identity>::new();
+    let mut seen_relays = SeenRelays::new(RELAY_SEEN_LIMIT);
 
     while let Ok(msg) = receiver.recv() {
+        let _entered = msg.span().clone().entered();
         match msg {
             Operations::Send(msg) => {
-                trace!(
-                    "Sending packet {:?} to conn_id {:?}",
-                    msg.packet.debug_print_type(),
-                    msg.conn_id
-                );
-                if let Some(socket) = connection_map.get(&msg.conn_id) {
-                    let mut socket_clone = socket.try_clone().unwrap();
-                    let translated_packet = match translation_data.get(&msg.conn_id) {
-                        Some(translation_data) => {
-                            translate_outgoing(msg.packet, translation_data.clone())
-                        }
-                        None => msg.packet,
-                    };
-                    write(&mut socket_clone, translated_packet);
+                let send = trace_span!(
+                    "send",
+                    conn_id = %msg.conn_id,
+                    packet = msg.packet.name(),
+                    peer = field::Empty,
+                    instance = field::Empty
+                )
+                .entered();
+                let peer = peer_nodes.get(&msg.conn_id);
+                if let Some(peer) = peer {
+                    send.record("peer", field::display(peer));
+                }
+                let instance = instances
+                    .get(&msg.conn_id)
+                    .or_else(|| peer_instances.get(peer?));
+                if let Some(instance) = instance {
+                    send.record("instance", field::display(instance));
+                }
+                if let Some(connection) = connection_map.get(&msg.conn_id) {
+                    trace!(
+                        "Sending packet {:?} to {} conn_id {:?}",
+                        msg.packet.debug_print_type(),
+                        connection.class.policy().label,
+                        msg.conn_id
+                    );
+                    dispatch(
+                        msg.conn_id,
+                        connection,
+                        msg.packet,
+                        translation_data.get(&msg.conn_id).cloned(),
+                        &transform_pool,
+                        &inbound_packet_processor,
+                    );
                     trace!("Send successful");
                 } else {
                     trace!("Connection ID not found");
@@ -47,11 +108,100 @@ pub fn start(receiver: Receiver<Operations>, _sender: Sender<Operations>) {
                         .filter(|conn_id| **conn_id != source)
                         .copied()
                         .collect();
-                    broadcast(msg.packet, filtered_receipients, &connection_map)
+                    broadcast(
+                        msg.packet,
+                        filtered_receipients,
+                        &connection_map,
+                        &transform_pool,
+                        &inbound_packet_processor,
+                    )
                 } else {
-                    broadcast(msg.packet, receipients, &connection_map)
+                    broadcast(
+                        msg.packet,
+                        receipients,
+                        &connection_map,
+                        &transform_pool,
+                        &inbound_packet_processor,
+                    )
                 }
             }
+            // Packets that came from a peer are never sent back over a connection to the peer they
+            // started from, or to the peer that passed them to us. Relayed packets only mean
+            // anything to peers, so players subscribed to everything aren't sent them. Every peer
+            // passes everything on, so most packets reach us more than once and all but the first
+            // copy are dropped
+            Operations::Relay(msg) => {
+                if !seen_relays.first_sighting(&msg.origin) {
+                    trace!(
+                        "Dropping packet {:?} from {:?} numbered {:?}, which we've seen already",
+                        msg.packet.debug_print_type(),
+                        msg.origin.node,
+                        msg.origin.sequence
+                    );
+                    continue;
+                }
+                if let Some(local) = msg.local {
+                    let receipients = subscriber_list.receipients(SubscriberType::Local);
+                    broadcast(
+                        *local,
+                        receipients,
+                        &connection_map,
+                        &transform_pool,
+                        &inbound_packet_processor,
+                    );
+                }
+                if msg.origin.hops > MAX_RELAY_HOPS {
+                    trace!(
+                        "Dropping packet {:?} from {:?} after {:?} hops",
+                        msg.packet.debug_print_type(),
+                        msg.origin.node,
+                        msg.origin.hops
+                    );
+                    continue;
+                }
+                let (via, origin) = (msg.via, &msg.origin.node);
+                let via_node = via.and_then(|via| peer_nodes.get(&via));
+                let receipients: HashSet<Uuid> = subscriber_list
+                    .receipients(msg.subscriber_type)
+                    .into_iter()
+                    .filter(|conn_id| Some(*conn_id) != via)
+                    .filter(|conn_id| {
+                        connection_map
+                            .get(conn_id)
+                            .map(|connection| connection.class)
+                            == Some(ConnectionClass::PeerLink)
+                    })
+                    .filter(|conn_id| match peer_nodes.get(conn_id) {
+                        Some(node) => node != origin && Some(node) != via_node,
+                        None => true,
+                    })
+                    .collect();
+                broadcast(
+                    msg.packet,
+                    receipients,
+                    &connection_map,
+                    &transform_pool,
+                    &inbound_packet_processor,
+                );
+            }
+            Operations::IdentifyPeer(msg) => {
+                trace!("Connection {:?} is to peer {:?}", msg.conn_id, msg.peer);
+                if let Some(instance) = instances.get(&msg.conn_id) {
+                    peer_instances.insert(msg.peer.clone(), instance.clone());
+                }
+                peer_nodes.insert(msg.conn_id, msg.peer);
+            }
+            Operations::IdentifyInstance(msg) => {
+                trace!(
+                    "Connection {:?} is to instance {}",
+                    msg.conn_id,
+                    msg.instance
+                );
+                if let Some(peer) = peer_nodes.get(&msg.conn_id) {
+                    peer_instances.insert(peer.clone(), msg.instance.clone());
+                }
+                instances.insert(msg.conn_id, msg.instance);
+            }
             Operations::Subscribe(msg) => {
                 trace!(
                     "Subscribing conn_id {:?} with type {:?}",
@@ -71,19 +221,92 @@ pub fn start(receiver: Receiver<Operations>, _sender: Sender<Operations>) {
                     }
                 }
             }
+            // Whoever's reading from the socket stops once it's shut, if they haven't already
             Operations::Close(msg) => {
                 trace!("Closing connection {:?}", msg.conn_id);
-                connection_map.remove(&msg.conn_id);
+                if let Some(connection) = connection_map.remove(&msg.conn_id) {
+                    let _ = connection.socket.shutdown(Shutdown::Both);
+                }
                 translation_data.remove(&msg.conn_id);
                 subscriber_list.remove(&msg.conn_id);
+                peer_nodes.remove(&msg.conn_id);
+                instances.remove(&msg.conn_id);
+                publish_connections(&connection_map);
+            }
+            // Clients that are playing get the play state's disconnect packet, anyone else is still
+            // logging in. Closing the socket has the connection closed like any other
+            Operations::Kick(msg) => {
+                trace!("Kicking conn_id {:?}: {}", msg.conn_id, msg.reason);
+                if let Some(connection) = connection_map.get(&msg.conn_id) {
+                    let reason = ChatComponent::new(&msg.reason).to_json();
+                    let packet = if subscriber_list.local_subscribers.contains(&msg.conn_id) {
+                        Packet::Disconnect(Disconnect { reason })
+                    } else {
+                        Packet::LoginDisconnect(LoginDisconnect { reason })
+                    };
+                    flush(&[connection], Duration::from_secs(KICK_FLUSH_TIMEOUT));
+                    connection.adapter.write(&mut &connection.socket, packet);
+                    let _ = connection.socket.shutdown(Shutdown::Both);
+                }
+            }
+            // Packets still with the transform pool are let through before the sockets are shut,
+            // so that clients get to see why they were disconnected
+            Operations::CloseAll(msg) => {
+                trace!("Closing all {:?} connections", connection_map.len());
+                flush(
+                    &connection_map.values().collect::<Vec<&Connection>>(),
+                    Duration::from_secs(SHUTDOWN_TIMEOUT),
+                );
+                connection_map.drain().for_each(|(_, connection)| {
+                    let _ = connection.socket.shutdown(Shutdown::Both);
+                });
+                translation_data.clear();
+                subscriber_list = SubscriberList::new();
+                peer_nodes.clear();
+                instances.clear();
+                peer_instances.clear();
+                publish_connections(&connection_map);
+                let _ = msg.reply.send(());
             }
             Operations::New(msg) => {
                 trace!(
-                    "New Connection with conn_id {:?} on socket {:?}",
+                    "New {} connection with conn_id {:?} on socket {:?}",
+                    msg.class.policy().label,
                     msg.conn_id,
                     msg.socket
                 );
-                connection_map.insert(msg.conn_id, msg.socket);
+                connection_map.insert(
+                    msg.conn_id,
+                    Connection {
+                        socket: msg.socket,
+                        in_flight: Arc::new(AtomicUsize::new(0)),
+                        adapter: ProtocolAdapter::default(),
+                        class: msg.class,
+                        reported_backlog: Cell::new(0),
+                    },
+                );
+                publish_connections(&connection_map);
+            }
+            Operations::Classify(msg) => {
+                trace!(
+                    "Connection {:?} is a {} connection",
+                    msg.conn_id,
+                    msg.class.policy().label
+                );
+                if let Some(connection) = connection_map.get_mut(&msg.conn_id) {
+                    connection.class = msg.class;
+                }
+                publish_connections(&connection_map);
+            }
+            Operations::SetProtocol(msg) => {
+                trace!(
+                    "Connection {:?} speaks protocol {:?}",
+                    msg.conn_id,
+                    msg.adapter.protocol()
+                );
+                if let Some(connection) = connection_map.get_mut(&msg.conn_id) {
+                    connection.adapter = msg.adapter;
+                }
             }
             Operations::UpdateTranslation(msg) => {
                 trace!(
@@ -103,20 +326,150 @@ pub fn start(receiver: Receiver<Operations>, _sender: Sender<Operations>) {
     }
 }
 
-fn broadcast<'a, I: IntoIterator<Item = Uuid>>(
+fn publish_connections(connection_map: &HashMap<Uuid, Connection>) {
+    let mut connections = BTreeMap::new();
+    connection_map.values().for_each(|connection| {
+        *connections
+            .entry(connection.class.policy().label)
+            .or_insert(0) += 1
+    });
+    metrics::set_connections(connections);
+}
+
+fn broadcast<I: IntoIterator<Item = Uuid>, PP: PacketProcessor>(
     packet: Packet,
     conn_ids: I,
-    connection_map: &'a HashMap<Uuid, TcpStream>,
+    connection_map: &HashMap<Uuid, Connection>,
+    transform_pool: &TransformPool,
+    inbound_packet_processor: &PP,
 ) {
     conn_ids.into_iter().for_each(|conn_id| {
-        if let Some(socket) = connection_map.get(&conn_id) {
-            let mut socket_clone = socket.try_clone().unwrap();
-            let packet_clone = packet.clone();
-            write(&mut socket_clone, packet_clone);
+        if let Some(connection) = connection_map.get(&conn_id) {
+            dispatch(
+                conn_id,
+                connection,
+                packet.clone(),
+                None,
+                transform_pool,
+                inbound_packet_processor,
+            );
         }
     });
 }
 
+// Waits for the transform pool to finish writing to the connections, up to timeout
+fn flush(connections: &[&Connection], timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline
+        && connections
+            .iter()
+            .any(|connection| connection.in_flight.load(Ordering::Acquire) > 0)
+    {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Cheap packets like keep-alives are written straight away, unless the connection still has
+// packets waiting on the transform pool that they would otherwise jump ahead of. A connection that
+// has more waiting than its class allows isn't keeping up, and is shut rather than left to use up
+// ever more memory. Whoever's reading from it then closes it like any other
+fn dispatch<PP: PacketProcessor>(
+    conn_id: Uuid,
+    connection: &Connection,
+    packet: Packet,
+    translation: Option<TranslationInfo>,
+    transform_pool: &TransformPool,
+    inbound_packet_processor: &PP,
+) {
+    let in_flight = connection.in_flight.load(Ordering::Acquire);
+    let policy = connection.class.policy();
+    // Most of the time there's nothing waiting, which it doesn't need telling about again
+    if connection.class == ConnectionClass::PeerLink
+        && connection.reported_backlog.replace(in_flight) != in_flight
+    {
+        inbound_packet_processor
+            .observe_backlog(conn_id, in_flight as u64)
+            .or_log();
+    }
+    if in_flight >= policy.max_queued {
+        warn!(
+            "Shutting {} conn_id {:?}, which has {:?} packets waiting to be sent",
+            policy.label, conn_id, in_flight
+        );
+        let _ = connection.socket.shutdown(Shutdown::Both);
+        return;
+    }
+    metrics::packet_written(&packet);
+    let mut socket_clone = match connection.socket.try_clone() {
+        Ok(socket_clone) => socket_clone,
+        Err(source) => {
+            warn!("{}", PatchworkError::Connection { conn_id, source });
+            let _ = connection.socket.shutdown(Shutdown::Both);
+            return;
+        }
+    };
+    if is_expensive(&packet) || in_flight > 0 {
+        let channel = match connection.class {
+            ConnectionClass::PeerLink => link_channel(&packet),
+            ConnectionClass::Player => Channel::Bulk,
+        };
+        transform_pool.submit(
+            channel,
+            conn_id,
+            socket_clone,
+            packet,
+            translation,
+            connection.adapter,
+            policy.compression_threshold,
+            connection.in_flight.clone(),
+        );
+        return;
+    }
+    let packet = match translation {
+        Some(translation) => translate_outgoing(packet, translation),
+        None => packet,
+    };
+    packet_capture::packet(conn_id, Direction::Outbound, &packet);
+    compression::write(
+        &mut socket_clone,
+        connection.adapter,
+        packet,
+        policy.compression_threshold,
+    );
+}
+
+// The relayed packets that have reached us lately, by where they started and their number there
+struct SeenRelays {
+    limit: usize,
+    order: VecDeque<(Peer, i64)>,
+    seen: HashSet<(Peer, i64)>,
+}
+
+impl SeenRelays {
+    fn new(limit: usize) -> SeenRelays {
+        SeenRelays {
+            limit,
+            order: VecDeque::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    // Whether this is the first copy of the packet, remembering it if so
+    fn first_sighting(&mut self, origin: &Origin) -> bool {
+        let id = (origin.node.clone(), origin.sequence);
+        if !self.seen.insert(id.clone()) {
+            return false;
+        }
+        if self.order.len() == self.limit {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.order.push_back(id);
+        true
+    }
+}
+
 struct SubscriberList {
     remote_subscribers: HashSet<Uuid>,
     local_subscribers: HashSet<Uuid>,
@@ -155,3 +508,60 @@ impl SubscriberList {
         self.remote_subscribers.remove(uuid);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::PLAYER_MAX_QUEUED;
+    use crate::interfaces::MockPacketProcessor;
+    use crate::models::packet::KeepAlive;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn connected(in_flight: usize) -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (socket, _) = listener.accept().unwrap();
+        let connection = Connection {
+            socket,
+            in_flight: Arc::new(AtomicUsize::new(in_flight)),
+            adapter: ProtocolAdapter::default(),
+            class: ConnectionClass::Player,
+            reported_backlog: Cell::new(0),
+        };
+        (connection, client)
+    }
+
+    #[test]
+    fn connections_with_too_much_waiting_are_shut() {
+        let transform_pool = TransformPool::new(1);
+        let keep_alive = || Packet::KeepAlive(KeepAlive { id: 1 });
+
+        let (connection, mut client) = connected(0);
+        dispatch(
+            Uuid::new_v4(),
+            &connection,
+            keep_alive(),
+            None,
+            &transform_pool,
+            &MockPacketProcessor::new(),
+        );
+        let mut written = [0; 16];
+        assert!(client.read(&mut written).unwrap() > 0);
+
+        let (connection, mut client) = connected(PLAYER_MAX_QUEUED);
+        dispatch(
+            Uuid::new_v4(),
+            &connection,
+            keep_alive(),
+            None,
+            &transform_pool,
+            &MockPacketProcessor::new(),
+        );
+        let mut written = Vec::new();
+        assert_eq!(client.read_to_end(&mut written).unwrap(), 0);
+    }
+}
@@ -1,87 +1,480 @@
+use super::chunk_cache::ChunkCache;
+use super::config::{ForwardingConfig, PacketRateLimits, PeerLinkWatermarks, PeerQuotas};
+use super::constants::{MALFORMED_PACKET_MESSAGE, RATE_LIMITED_MESSAGE};
+use super::error::OrLog;
+use super::forwarding::ForwardedPlayer;
+use super::identity::Identity;
+use super::instance::Queue;
+use super::interfaces::bans::BanList;
 use super::interfaces::block::BlockState;
+use super::interfaces::entity::EntityState;
+use super::interfaces::game_rules::GameRuleState;
+use super::interfaces::keep_alive::{KeepAliveKind, KeepAliveService};
 use super::interfaces::messenger::Messenger;
 use super::interfaces::packet_processor::Operations;
 use super::interfaces::patchwork::PatchworkState;
+use super::interfaces::peer_auth::PeerAuth;
 use super::interfaces::player::PlayerState;
+use super::interfaces::whitelist::Whitelist;
+use super::interfaces::world_time::WorldTimeState;
+use super::link_watermarks::{LinkWatermarks, Pressure};
+use super::metrics;
+use super::packet_capture::{self, Direction};
+use super::peer_quotas::{self, PeerQuotaLedger};
+use super::position_delta::DeltaDecoder;
+use super::quota::Admission;
+use super::rate_limiter::{ConnectionRateLimiter, Verdict};
 
-use super::packet::{read, translate, Packet};
+use super::packet::{translate, Packet};
+use super::packet_handlers::connection_updates::ConnectionUpdate;
 use super::packet_handlers::packet_router;
+use super::protocol_adapter::ProtocolAdapter;
 use super::translation::{TranslationInfo, TranslationUpdates};
-use std::collections::HashMap;
+use super::uuid_source::UuidSource;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::Sender;
+use tracing::field;
 use uuid::Uuid;
 
+// Border crossings, and subscriptions in either direction
+const PEER_LINK_STATES: [i32; 3] = [4, 5, 6];
+// The states inbound peer connections are put in once they've authenticated. Our own connections
+// out to peers are put in state 5 by us
+const AUTHENTICATED_STATES: [i32; 2] = [4, 6];
+
+#[allow(clippy::too_many_arguments)]
 pub fn start_inbound<
     M: Messenger + Clone,
     P: PlayerState + Clone,
     PA: PatchworkState + Clone,
     B: BlockState + Clone,
+    E: EntityState + Clone,
+    G: GameRuleState + Clone,
+    A: PeerAuth + Clone,
+    K: KeepAliveService,
+    W: Whitelist + Clone,
+    BL: BanList + Clone,
+    T: WorldTimeState + Clone,
 >(
-    receiver: Receiver<Operations>,
+    receiver: Queue<Operations>,
     _sender: Sender<Operations>,
     messenger: M,
     player_state: P,
     block_state: B,
     patchwork_state: PA,
+    entity_state: E,
+    game_rules: G,
+    peer_auth: A,
+    keep_alive: K,
+    whitelist: W,
+    bans: BL,
+    world_time: T,
     test_sender: Option<std::sync::mpsc::Sender<(i32, Packet)>>,
+    uuids: UuidSource,
+    instance: Identity,
+    rate_limits: PacketRateLimits,
+    forwarding: Option<ForwardingConfig>,
+    peer_quotas: PeerQuotas,
+    peer_link_watermarks: PeerLinkWatermarks,
 ) {
     let mut translation_data = HashMap::<Uuid, TranslationInfo>::new();
+    // Connections we've closed or kicked can still have packets on the way, which are dropped
+    // until the connection is gone
+    let mut closing = HashSet::<Uuid>::new();
+    // Connections that aren't in here speak our own version, as peers always do
+    let mut adapters = HashMap::<Uuid, ProtocolAdapter>::new();
+    // Which instance is at the other end of each peer connection that's told us
+    let mut instances = HashMap::<Uuid, Identity>::new();
+    // Clients in play, each held to the rate limits on its own
+    let mut limiters = HashMap::<Uuid, ConnectionRateLimiter>::new();
+    // Anchors in play, each with where its player was last
+    let mut anchor_positions = HashMap::<Uuid, DeltaDecoder>::new();
+    // Where each player's connection is from, which peers' connections to us aren't told
+    let mut addresses = HashMap::<Uuid, IpAddr>::new();
+    // Players who came through a proxy, as it told us they really are
+    let mut forwarded = HashMap::<Uuid, ForwardedPlayer>::new();
+    // What each peer's map has sent us of its chunks, by our subscription to it
+    let mut chunk_caches = HashMap::<Uuid, ChunkCache>::new();
+    let mut quotas = PeerQuotaLedger::new(peer_quotas);
+    let mut link_watermarks = LinkWatermarks::new(peer_link_watermarks);
 
     while let Ok(msg) = receiver.recv() {
+        let _entered = msg.span().clone().entered();
         match msg {
             Operations::Inbound(msg) => {
-                trace!("Received packet from conn_id {:?}", msg.conn_id);
-                let translation_data = translation_data
-                    .entry(msg.conn_id)
-                    .or_insert_with(TranslationInfo::new);
+                // Everything done on the packet's behalf, here and in whichever services it's
+                // passed on to, happens inside this span
+                let span = trace_span!(
+                    "packet",
+                    conn_id = %msg.conn_id,
+                    packet = field::Empty,
+                    instance = field::Empty
+                );
+                let _packet = span.enter();
+                if let Some(instance) = instances.get(&msg.conn_id) {
+                    span.record("instance", field::display(instance));
+                }
+                if closing.contains(&msg.conn_id) {
+                    trace!("Dropping packet from closing conn_id {:?}", msg.conn_id);
+                    continue;
+                }
+                trace!("Received packet");
+                let connection = translation_data.entry(msg.conn_id).or_default();
+                if PEER_LINK_STATES.contains(&connection.state) {
+                    link_watermarks.observe(
+                        msg.conn_id,
+                        Pressure::InboundLag,
+                        msg.received_at.elapsed().as_millis() as u64,
+                    );
+                }
 
-                let packet = read(&mut msg.cursor.clone(), translation_data.state);
-                let packet = translate(packet, translation_data.clone());
+                let adapter = adapters.get(&msg.conn_id).copied().unwrap_or_default();
+                let packet = match adapter.read(&mut msg.cursor.clone(), connection.state) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        packet_capture::malformed(msg.conn_id, msg.cursor.get_ref());
+                        warn!("Rejecting conn_id {:?}: {}", msg.conn_id, e);
+                        let rejection = rejection(connection.state);
+                        apply_updates(
+                            msg.conn_id,
+                            vec![rejection],
+                            &mut translation_data,
+                            &mut adapters,
+                            &mut instances,
+                            &messenger,
+                        );
+                        closing.insert(msg.conn_id);
+                        continue;
+                    }
+                };
+                packet_capture::packet(msg.conn_id, Direction::Inbound, &packet);
+                // Peer states can only be reached by authenticating, so a connection in one
+                // without an identity got there some other way
+                let authenticated = instances.contains_key(&msg.conn_id);
+                if AUTHENTICATED_STATES.contains(&connection.state) && !authenticated {
+                    warn!(
+                        "Closing conn_id {:?}, which is in state {} without authenticating",
+                        msg.conn_id, connection.state
+                    );
+                    apply_updates(
+                        msg.conn_id,
+                        vec![ConnectionUpdate::Close],
+                        &mut translation_data,
+                        &mut adapters,
+                        &mut instances,
+                        &messenger,
+                    );
+                    closing.insert(msg.conn_id);
+                    continue;
+                }
+                // Our own connections out to peers are trusted, as we chose who they're to
+                if !authenticated && connection.state != 5 && peer_only(&packet) {
+                    warn!(
+                        "Dropping {} from conn_id {:?}, which isn't an authenticated peer",
+                        packet.name(),
+                        msg.conn_id
+                    );
+                    continue;
+                }
+                // Positions are worked out before they're translated, like they were sent
+                let packet = if connection.state == 3 && instances.contains_key(&msg.conn_id) {
+                    match anchor_positions
+                        .entry(msg.conn_id)
+                        .or_default()
+                        .decode(packet)
+                    {
+                        Some(packet) => packet,
+                        None => {
+                            trace!(
+                                "Dropping position delta from before the anchor's first position"
+                            );
+                            continue;
+                        }
+                    }
+                } else {
+                    packet
+                };
+                let packet = translate(packet, connection.clone());
+                span.record("packet", packet.name());
+                if connection.state == 5 {
+                    chunk_caches.entry(msg.conn_id).or_default().keep(&packet);
+                }
+                metrics::packet_read(&packet);
+                // Answers still go wherever the player's other packets go, so that anchors pass
+                // them on to their peer
+                if let Packet::ServerboundKeepAlive(answer) = &packet {
+                    keep_alive.answer(msg.conn_id, answer.id).or_log();
+                }
+                let state_before = connection.state;
+                // Destroyed entities won't come up again, so stop tracking their ids
+                if let Packet::DestroyEntities(destroyed) = &packet {
+                    destroyed
+                        .entity_ids
+                        .iter()
+                        .for_each(|entity_id| connection.map.entity_ids.forget_local(*entity_id));
+                }
+
+                // Anchors in play are peers, which only pass on what their player sends
+                if connection.state == 3 && !instances.contains_key(&msg.conn_id) {
+                    let verdict = limiters
+                        .entry(msg.conn_id)
+                        .or_insert_with(|| ConnectionRateLimiter::new(rate_limits, msg.received_at))
+                        .check(&packet, msg.received_at);
+                    match verdict {
+                        Verdict::Allowed => {}
+                        Verdict::Dropped => {
+                            trace!("Dropping packet over the rate limit");
+                            continue;
+                        }
+                        Verdict::Kicked => {
+                            warn!("Kicking conn_id {:?} for flooding packets", msg.conn_id);
+                            apply_updates(
+                                msg.conn_id,
+                                vec![ConnectionUpdate::Kick(String::from(RATE_LIMITED_MESSAGE))],
+                                &mut translation_data,
+                                &mut adapters,
+                                &mut instances,
+                                &messenger,
+                            );
+                            closing.insert(msg.conn_id);
+                            continue;
+                        }
+                    }
+                }
+
+                // Peers only get so much of us, whichever of their connections it's asked for on
+                let admission = match (
+                    peer_quotas::resource(connection.state, &packet),
+                    instances.get(&msg.conn_id),
+                ) {
+                    (Some(resource), Some(instance)) => {
+                        quotas.charge(instance, msg.conn_id, resource)
+                    }
+                    _ => Admission::Allowed,
+                };
+                match admission {
+                    Admission::Allowed => {}
+                    Admission::Throttled => {
+                        trace!("Dropping packet over its peer's quota");
+                        continue;
+                    }
+                    Admission::Rejected => {
+                        apply_updates(
+                            msg.conn_id,
+                            vec![ConnectionUpdate::Close],
+                            &mut translation_data,
+                            &mut adapters,
+                            &mut instances,
+                            &messenger,
+                        );
+                        closing.insert(msg.conn_id);
+                        continue;
+                    }
+                }
 
                 // Send raw packet info if we provided a channel
-                let test_sender_clone = test_sender.clone();
-                if let Some(test_sender_clone) = test_sender_clone {
-                    test_sender_clone
-                        .send((translation_data.state, packet.clone()))
-                        .expect("Failed to send packet to channel");
+                // A test that's stopped listening is no reason to stop processing packets
+                if let Some(test_sender) = &test_sender {
+                    let _ = test_sender.send((connection.state, packet.clone()));
                 }
 
-                let translation_update = packet_router::route_packet(
+                let updates = packet_router::route_packet(
                     packet,
-                    translation_data.state,
+                    connection.state,
                     msg.conn_id,
                     messenger.clone(),
                     player_state.clone(),
                     block_state.clone(),
                     patchwork_state.clone(),
+                    entity_state.clone(),
+                    game_rules.clone(),
+                    peer_auth.clone(),
+                    whitelist.clone(),
+                    bans.clone(),
+                    world_time.clone(),
+                    addresses.get(&msg.conn_id).copied(),
+                    forwarding.as_ref(),
+                    forwarded.get(&msg.conn_id),
+                    &uuids,
+                    &instance,
                 );
-                match translation_update {
-                    TranslationUpdates::NoChange => {}
-                    _ => {
-                        trace!(
-                            "Incoming translation update {:?} for conn_id {:?}",
-                            translation_update,
-                            msg.conn_id
-                        );
+                // The proxy's word on where the player's from stands in for its own address
+                for update in &updates {
+                    if let ConnectionUpdate::Forwarded(player) = update {
+                        addresses.insert(msg.conn_id, player.address);
+                        forwarded.insert(msg.conn_id, player.clone());
                     }
                 }
-                translation_data.update(&translation_update);
+                if apply_updates(
+                    msg.conn_id,
+                    updates,
+                    &mut translation_data,
+                    &mut adapters,
+                    &mut instances,
+                    &messenger,
+                ) {
+                    closing.insert(msg.conn_id);
+                } else if let Some(kind) = entered_play(
+                    state_before,
+                    translation_data.get(&msg.conn_id).map(|c| c.state),
+                ) {
+                    keep_alive.watch(msg.conn_id, kind).or_log();
+                }
+                metrics::packet_handled(msg.received_at.elapsed());
+            }
+            Operations::Accept(msg) => {
+                addresses.insert(msg.conn_id, msg.address);
             }
             Operations::SetTranslationData(msg) => {
-                trace!(
-                    "Applying translation updates {:?} to {:?}",
-                    msg.updates,
-                    msg.conn_id
+                apply_updates(
+                    msg.conn_id,
+                    msg.updates
+                        .into_iter()
+                        .map(ConnectionUpdate::Translation)
+                        .collect(),
+                    &mut translation_data,
+                    &mut adapters,
+                    &mut instances,
+                    &messenger,
                 );
-                let data = translation_data
-                    .entry(msg.conn_id)
-                    .or_insert_with(TranslationInfo::new);
-
-                msg.updates.iter().for_each(|update| {
-                    data.update(update);
-                })
             }
+            Operations::LegacyPing(msg) => player_state.online_players(msg.reply).or_log(),
+            Operations::CatchUp(msg) => chunk_caches
+                .values()
+                .flat_map(ChunkCache::packets)
+                .for_each(|packet| messenger.send_packet(msg.conn_id, packet.clone()).or_log()),
+            // Backlogs can still be reported for links that have just closed, which are let go
+            Operations::ObserveBacklog(msg) => {
+                if translation_data.contains_key(&msg.conn_id) {
+                    link_watermarks.observe(msg.conn_id, Pressure::OutboundBacklog, msg.backlog);
+                }
+            }
+            Operations::IdentifyLink(msg) => link_watermarks.identify(msg.conn_id, msg.peer),
+            Operations::LinkAlerts(msg) => {
+                let _ = msg.reply.send(link_watermarks.alerts());
+            }
+            Operations::Reject(msg) => {
+                if closing.contains(&msg.conn_id) {
+                    continue;
+                }
+                warn!("Rejecting conn_id {:?}: {}", msg.conn_id, msg.reason);
+                let state = translation_data
+                    .get(&msg.conn_id)
+                    .map_or(0, |connection| connection.state);
+                apply_updates(
+                    msg.conn_id,
+                    vec![rejection(state)],
+                    &mut translation_data,
+                    &mut adapters,
+                    &mut instances,
+                    &messenger,
+                );
+                closing.insert(msg.conn_id);
+            }
+            Operations::Close(msg) => {
+                // The peer's entities are gone along with the link
+                if let Some(translation) = translation_data.remove(&msg.conn_id) {
+                    if translation.state == 5 {
+                        translation.map.entity_ids.clear();
+                    }
+                }
+                adapters.remove(&msg.conn_id);
+                instances.remove(&msg.conn_id);
+                closing.remove(&msg.conn_id);
+                link_watermarks.forget(&msg.conn_id);
+                quotas.release(&msg.conn_id);
+                limiters.remove(&msg.conn_id);
+                anchor_positions.remove(&msg.conn_id);
+                addresses.remove(&msg.conn_id);
+                forwarded.remove(&msg.conn_id);
+                chunk_caches.remove(&msg.conn_id);
+                keep_alive.unwatch(msg.conn_id).or_log();
+            }
+        }
+    }
+}
+
+// Packets that are read in whichever state they're sent in, or in play alongside the player's own,
+// but that only a peer has any business sending
+fn peer_only(packet: &Packet) -> bool {
+    matches!(
+        packet,
+        Packet::BorderCrossLogin(_)
+            | Packet::PeerHeartbeat(_)
+            | Packet::PeerShutdown(_)
+            | Packet::PeerPositionDelta(_)
+            | Packet::PeerPositionAndLookDelta(_)
+    )
+}
+
+// Players log in to play, and anchors cross the border into it
+fn entered_play(state_before: i32, state: Option<i32>) -> Option<KeepAliveKind> {
+    match (state_before, state) {
+        (2, Some(3)) => Some(KeepAliveKind::Client),
+        (4, Some(3)) => Some(KeepAliveKind::Anchor),
+        _ => None,
+    }
+}
+
+// Players can be told why they're being let go. Anyone else, a peer or a client that's only pinging
+// us, doesn't get a say in what's sent to them, so they're just closed
+fn rejection(state: i32) -> ConnectionUpdate {
+    match state {
+        2 | 3 => ConnectionUpdate::Kick(String::from(MALFORMED_PACKET_MESSAGE)),
+        _ => ConnectionUpdate::Close,
+    }
+}
+
+// Everything a packet handler asks for is applied together, before the connection's next packet is
+// read. Returns whether the connection is being closed
+fn apply_updates<M: Messenger>(
+    conn_id: Uuid,
+    updates: Vec<ConnectionUpdate>,
+    translation_data: &mut HashMap<Uuid, TranslationInfo>,
+    adapters: &mut HashMap<Uuid, ProtocolAdapter>,
+    instances: &mut HashMap<Uuid, Identity>,
+    messenger: &M,
+) -> bool {
+    if updates.is_empty() {
+        return false;
+    }
+    trace!("Applying connection updates {:?} to {:?}", updates, conn_id);
+    // A closed connection won't send anything else, so there's nothing left to update
+    let closing = updates.iter().find_map(|update| match update {
+        ConnectionUpdate::Close => Some(None),
+        ConnectionUpdate::Kick(reason) => Some(Some(reason.clone())),
+        _ => None,
+    });
+    if let Some(reason) = closing {
+        match reason {
+            Some(reason) => messenger.kick(conn_id, reason),
+            None => messenger.close(conn_id),
         }
+        .or_log();
+        translation_data.remove(&conn_id);
+        return true;
     }
+    let connection = translation_data.entry(conn_id).or_default();
+    updates.into_iter().for_each(|update| match update {
+        ConnectionUpdate::State(state) => connection.update(&TranslationUpdates::State(state)),
+        ConnectionUpdate::Translation(update) => connection.update(&update),
+        ConnectionUpdate::Protocol(adapter) => {
+            adapters.insert(conn_id, adapter);
+            messenger.set_protocol(conn_id, adapter).or_log();
+        }
+        ConnectionUpdate::Class(class) => messenger.classify(conn_id, class).or_log(),
+        ConnectionUpdate::Subscribe(typ) => messenger.subscribe(conn_id, typ).or_log(),
+        ConnectionUpdate::Identity(instance) => {
+            messenger
+                .identify_instance(conn_id, instance.clone())
+                .or_log();
+            instances.insert(conn_id, instance);
+        }
+        // Kept by the packet processor itself, alongside the connection's address
+        ConnectionUpdate::Forwarded(_) => {}
+        ConnectionUpdate::Close | ConnectionUpdate::Kick(_) => {}
+    });
+    false
 }
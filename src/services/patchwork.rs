@@ -1,134 +1,1715 @@
-use super::interfaces::messenger::Messenger;
+use super::command;
+use super::config::Config;
+use super::constants::{
+    CHUNK_SIZE, ENTITY_ID_BLOCK_SIZE, ENTITY_OWNER_QUERY_TIMEOUT, PEER_HEARTBEAT_MISS_THRESHOLD,
+    REPLAY_POLL_PERIOD,
+};
+use super::error::{OrLog, PatchworkError};
+use super::instance::{self, Queue};
+use super::interfaces;
+use super::interfaces::block::BlockState;
+use super::interfaces::chat::ChatService;
+use super::interfaces::command::CommandService;
+use super::interfaces::entity::EntityState;
+use super::interfaces::messenger::{ConnectionClass, Messenger, Origin, SubscriberType};
 use super::interfaces::packet_processor::PacketProcessor;
-use super::interfaces::patchwork::Operations;
-use super::interfaces::player::{PlayerState, Position as PlayerPosition};
-use super::map::{Map, Peer, PeerConnection, Position};
+use super::interfaces::patchwork::{
+    EntityOwner, EntityQuery, MapDescription, Operations, PatchworkState,
+};
+use super::interfaces::peer_auth::PeerAuth;
+use super::interfaces::player::{spawn_position, PlayerState, Position as PlayerPosition};
+use super::map::{
+    map_size, map_width, Dimension, GossipedMap, Map, Peer, PeerConnection, Position,
+};
+use super::message_log::MessageLog;
+use super::metrics;
 use super::packet;
 use super::packet::Packet;
 use super::packet_handlers::gameplay_router;
+use super::packet_handlers::peer_subscription::find_local_entity;
+use super::position_delta::DeltaEncoder;
 use super::server;
+use super::topology::{Seed, Topology, TopologyMap};
+use super::translation::{TranslationInfo, TranslationUpdates};
+use super::uuid_source::UuidSource;
+use super::world_generator::CHUNK_BLOCKS;
 
-use std::collections::HashMap;
-use std::io;
-use std::sync::mpsc::{Receiver, Sender};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::Span;
 
 use uuid::Uuid;
 
+const MAX_BUFFERED_ANCHOR_PACKETS: usize = 256;
+// The action on ClientStatus a client sends when the player clicks respawn
+const PERFORM_RESPAWN: i32 = 0;
+// Every block in the nether is this many in the overworld
+const NETHER_SCALE: f64 = 8.0;
+// What players kicked back to a proxy are disconnected with, as it has no map to put them on
+const KICKED_BACK_MESSAGE: &str = "Sent back by the map you were on";
+
+#[allow(clippy::too_many_arguments)]
 pub fn start<
     M: 'static + Messenger + Clone + Send,
-    P: PlayerState + Clone,
+    P: 'static + PlayerState + Clone + Send,
     PP: 'static + PacketProcessor + Clone + Send,
+    E: EntityState,
+    C: CommandService + Clone,
+    B: BlockState,
+    A: PeerAuth,
+    CH: ChatService,
 >(
-    receiver: Receiver<Operations>,
+    receiver: Queue<Operations>,
     sender: Sender<Operations>,
     messenger: M,
     inbound_packet_processor: PP,
     player_state: P,
+    entity_state: E,
+    command_service: C,
+    block_state: B,
+    peer_auth: A,
+    chat: CH,
+    local_peer: Peer,
+    config: Config,
+    uuids: UuidSource,
 ) {
-    let mut patchwork = Patchwork::new();
+    let mut patchwork = Patchwork::new(config.local_map);
+    if config.local_map {
+        patchwork.maps[0].position.dimension = config.dimension;
+        patchwork.map_seeds.insert(0, Seed::configured(&config));
+    }
+    let mut message_log = config
+        .message_log_directory
+        .as_deref()
+        .and_then(|directory| MessageLog::create(directory, "patchwork_state"));
+    // What the block state was last told of the maps around ours
+    let mut neighbours = Vec::new();
 
     while let Ok(msg) = receiver.recv() {
+        // Maps are placed, moved and removed by all sorts of messages, so the block state hears
+        // about our neighbours as they stand after the ones before this
+        if patchwork.neighbours() != neighbours {
+            neighbours = patchwork.neighbours();
+            block_state.set_neighbours(neighbours.clone()).or_log();
+        }
+        let _entered = msg.span().clone().entered();
+        if let Some(message_log) = message_log.as_mut() {
+            message_log.record(&msg);
+        }
         match msg {
             Operations::New(msg) => {
+                if patchwork.has_peer(&msg.peer) {
+                    trace!("Peer {:?} already has a map", msg.peer);
+                    continue;
+                }
                 trace!("Adding Peer Map for peer {:?}", msg.peer);
+                let position = patchwork.next_position();
                 patchwork.add_peer_map(
                     msg.peer,
+                    position,
+                    patchwork.next_entity_id_block(),
                     messenger.clone(),
                     inbound_packet_processor.clone(),
                     sender.clone(),
                 )
             }
+            Operations::Remove(msg) => {
+                trace!("Removing Peer Map for peer {:?}", msg.peer);
+                patchwork.remove_peer_map(msg.peer, messenger.clone(), player_state.clone());
+            }
+            // Returning players pick up on whichever map they left off on, in whichever dimension,
+            // unless it's gone since, in which case they start over at the spawn
+            Operations::PlacePlayer(msg) => {
+                let map_index = match patchwork
+                    .find_map_index(map_position(msg.position, msg.dimension))
+                {
+                    Some(map_index) => {
+                        patchwork
+                            .player_dimensions
+                            .insert(msg.conn_id, msg.dimension);
+                        map_index
+                    }
+                    None => match patchwork
+                        .position_map_index(map_position(spawn_position(), Dimension::Overworld))
+                    {
+                        Ok(map_index) => {
+                            trace!("Moving conn_id {:?} to the spawn", msg.conn_id);
+                            if msg.dimension == Dimension::Overworld {
+                                player_state
+                                    .teleport(msg.conn_id, spawn_position())
+                                    .or_log();
+                            } else {
+                                // They joined into the dimension they left, which has to be undone
+                                let (reply_sender, reply_receiver) = channel();
+                                player_state
+                                    .respawn(
+                                        msg.conn_id,
+                                        spawn_position(),
+                                        Dimension::Overworld,
+                                        reply_sender,
+                                    )
+                                    .or_log();
+                                let _ = reply_receiver.recv();
+                            }
+                            patchwork.player_dimensions.remove(&msg.conn_id);
+                            map_index
+                        }
+                        Err(e) => {
+                            error!("Cannot place conn_id {:?}: {}", msg.conn_id, e);
+                            continue;
+                        }
+                    },
+                };
+                trace!("Placing conn_id {:?} on map {:?}", msg.conn_id, map_index);
+                let anchor = anchor_to(
+                    &patchwork,
+                    msg.conn_id,
+                    map_index,
+                    &messenger,
+                    &sender,
+                    &uuids,
+                );
+                patchwork.player_anchors.insert(msg.conn_id, anchor);
+            }
+            Operations::RemovePlayer(msg) => {
+                if let Some(anchor) = patchwork.player_anchors.remove(&msg.conn_id) {
+                    anchor.disconnect(messenger.clone());
+                }
+                patchwork.player_dimensions.remove(&msg.conn_id);
+            }
             Operations::ConnectMap(msg) => {
-                patchwork.connect_map(msg.map_index, msg.peer_connection, messenger.clone());
+                if patchwork.map_peers.contains_key(&msg.map_index) {
+                    let conn_id = msg.peer_connection.conn_id;
+                    messenger
+                        .identify_peer(conn_id, msg.peer_connection.peer.clone())
+                        .or_log();
+                    inbound_packet_processor
+                        .identify_link(conn_id, msg.peer_connection.peer.clone())
+                        .or_log();
+                    patchwork.connect_map(
+                        msg.map_index,
+                        msg.peer_connection,
+                        msg.challenge,
+                        &local_peer,
+                        messenger.clone(),
+                        &peer_auth,
+                    );
+                    if let Some(split) = patchwork.pending_splits.get_mut(&msg.map_index) {
+                        let split = split.clone();
+                        match hand_over_split(
+                            &patchwork,
+                            msg.map_index,
+                            conn_id,
+                            &split,
+                            &local_peer,
+                            &messenger,
+                            &block_state,
+                        ) {
+                            Ok(()) => {
+                                if let Some(split) =
+                                    patchwork.pending_splits.get_mut(&msg.map_index)
+                                {
+                                    split.handed_over = true;
+                                }
+                            }
+                            Err(e) => {
+                                error!("Failed to hand half of our map over: {:?}", e);
+                                patchwork.pending_splits.remove(&msg.map_index);
+                            }
+                        }
+                    }
+                } else {
+                    trace!(
+                        "Map {:?} was removed, dropping its connection",
+                        msg.map_index
+                    );
+                    messenger.close(msg.peer_connection.conn_id).or_log();
+                }
+            }
+            // Plugin messages don't belong to any map, so they skip anchoring altogether
+            Operations::RoutePlayerPacket(msg)
+                if matches!(msg.packet, Packet::PluginMessage(_)) =>
+            {
+                if let Packet::PluginMessage(plugin_message) = msg.packet {
+                    relay_plugin_message(
+                        Some(msg.conn_id),
+                        Origin::local(local_peer.clone()),
+                        None,
+                        plugin_message.channel,
+                        plugin_message.data,
+                        &config,
+                        &messenger,
+                    );
+                }
+            }
+            Operations::RelayPluginMessage(msg) => {
+                if msg.origin.node == local_peer {
+                    continue;
+                }
+                relay_plugin_message(
+                    None,
+                    msg.origin,
+                    Some(msg.via),
+                    msg.channel,
+                    msg.data,
+                    &config,
+                    &messenger,
+                );
+            }
+            // Chat goes out to the whole quilt from here rather than from wherever the player's
+            // anchored. Commands are run on the map they're on, like anything else they do
+            Operations::RoutePlayerPacket(msg) if is_chat(&msg.packet) => {
+                if let Packet::ChatMessage(chat_message) = msg.packet {
+                    chat.say(msg.conn_id, chat_message.message).or_log();
+                }
+            }
+            // Players respawn on whichever map has the spawn point, which needn't be the one they died
+            // on, or the dimension either
+            Operations::RoutePlayerPacket(msg) if is_respawn(&msg.packet) => {
+                respawn(
+                    &mut patchwork,
+                    msg.conn_id,
+                    spawn_position(),
+                    Dimension::Overworld,
+                    &messenger,
+                    &sender,
+                    &uuids,
+                    &player_state,
+                    &block_state,
+                );
+            }
+            // Portals are taken here rather than on the map the player's on, as only the node
+            // they're connected to can move them into another dimension, whoever serves it
+            Operations::RoutePlayerPacket(msg) if is_portal(&msg.packet) => {
+                match take_portal(&patchwork, &msg.packet, msg.conn_id, &player_state) {
+                    Ok((position, dimension)) => respawn(
+                        &mut patchwork,
+                        msg.conn_id,
+                        position,
+                        dimension,
+                        &messenger,
+                        &sender,
+                        &uuids,
+                        &player_state,
+                        &block_state,
+                    ),
+                    Err(e) => command::send_feedback(msg.conn_id, &messenger, Err(e)),
+                }
             }
             Operations::RoutePlayerPacket(msg) => {
-                let patchwork_clone = patchwork.clone();
+                // Players who wander off the edge of the quilt stay anchored where they were
+                let dimension = patchwork.player_dimension(msg.conn_id);
+                let new_map_index =
+                    extract_map_position(msg.packet.clone(), dimension).and_then(|position| {
+                        match patchwork.position_map_index(position) {
+                            Ok(map_index) => Some(map_index),
+                            Err(e) => {
+                                trace!("Not re-anchoring conn_id {:?}: {}", msg.conn_id, e);
+                                None
+                            }
+                        }
+                    });
+                // Once our own map has been handed off, or if we never had one, new players need
+                // anchoring like any other
+                let default_anchor = if patchwork.local_map
+                    && patchwork.maps[0].peer_connection.is_none()
+                    && patchwork.maps[0].position.dimension == dimension
+                {
+                    Anchor::local(0)
+                } else {
+                    Anchor::unplaced()
+                };
                 let anchor = patchwork
                     .player_anchors
                     .entry(msg.conn_id)
-                    .or_insert(Anchor {
-                        map_index: 0,
-                        conn_id: None,
-                    });
-                match &patchwork.maps[anchor.map_index].peer_connection {
-                    Some(_) => match msg.packet {
-                        Packet::Unknown => {}
-                        _ => {
-                            trace!(
-                                "Routing packet from conn_id {:?} through anchor",
-                                msg.conn_id
-                            );
-                            player_state.anchored_move_and_look(
+                    .or_insert(default_anchor);
+                let _map = map_span(&patchwork.maps, anchor.map_index).entered();
+                // Packets we don't know are passed on as they are, for whoever owns the map to make
+                // sense of
+                match (anchor.pending, anchor.conn_id) {
+                    (true, _) => {
+                        trace!(
+                            "Buffering packet from conn_id {:?} until anchor is ready",
+                            msg.conn_id
+                        );
+                        player_state
+                            .anchored_move_and_look(
                                 msg.conn_id,
-                                extract_player_position((&msg.packet).clone()),
+                                extract_player_position(msg.packet.clone()),
                                 None,
-                            );
-                            messenger.send_packet(anchor.conn_id.unwrap(), msg.packet.clone());
-                        }
-                    },
-                    None => {
+                            )
+                            .or_log();
+                        track_inventory(&msg.packet, msg.conn_id, &player_state);
+                        anchor.buffer(msg.packet.clone());
+                    }
+                    (false, Some(anchor_conn_id)) => {
+                        trace!(
+                            "Routing packet from conn_id {:?} through anchor",
+                            msg.conn_id
+                        );
+                        player_state
+                            .anchored_move_and_look(
+                                msg.conn_id,
+                                extract_player_position(msg.packet.clone()),
+                                None,
+                            )
+                            .or_log();
+                        track_inventory(&msg.packet, msg.conn_id, &player_state);
+                        messenger
+                            .send_packet(
+                                anchor_conn_id,
+                                anchor.positions.encode(msg.packet.clone()),
+                            )
+                            .or_log();
+                    }
+                    (false, None) => {
                         trace!("Routing packet from conn_id {:?} locally", msg.conn_id);
                         gameplay_router::route_packet(
                             msg.packet.clone(),
                             msg.conn_id,
                             player_state.clone(),
-                        );
+                            command_service.clone(),
+                        )
+                        .or_log();
                     }
                 }
-                if let Some(position) = extract_map_position((&msg.packet).clone()) {
-                    let new_map_index = patchwork_clone.position_map_index(position);
+                if let Some(new_map_index) = new_map_index {
                     if new_map_index != anchor.map_index {
+                        metrics::border_crossed(
+                            msg.conn_id,
+                            patchwork.maps[anchor.map_index].position,
+                            patchwork.maps[new_map_index].position,
+                        );
                         anchor.disconnect(messenger.clone());
                         *anchor = match &patchwork.maps[new_map_index].peer_connection {
-                            Some(peer_connection) => Anchor::connect(
-                                peer_connection.peer.clone(),
-                                msg.conn_id,
-                                new_map_index,
-                                patchwork.maps[new_map_index].position.x,
-                                messenger.clone(),
-                                player_state.clone(),
-                            )
-                            .unwrap(),
+                            Some(peer_connection) => {
+                                Anchor::connect(
+                                    peer_connection.peer.clone(),
+                                    msg.conn_id,
+                                    new_map_index,
+                                    patchwork.maps[new_map_index].position,
+                                    messenger.clone(),
+                                    sender.clone(),
+                                    uuids.clone(),
+                                );
+                                Anchor::pending(new_map_index)
+                            }
                             None => {
                                 gameplay_router::route_packet(
                                     msg.packet.clone(),
                                     msg.conn_id,
                                     player_state.clone(),
-                                );
-                                if patchwork.maps[anchor.map_index].peer_connection.is_some() {
-                                    player_state.reintroduce(msg.conn_id);
-                                }
-                                Anchor {
-                                    conn_id: None,
-                                    map_index: new_map_index,
+                                    command_service.clone(),
+                                )
+                                .or_log();
+                                if anchor.conn_id.is_some() {
+                                    player_state.reintroduce(msg.conn_id).or_log();
                                 }
+                                Anchor::disconnected(new_map_index, patchwork.local_map)
                             }
                         }
                     }
                 }
             }
-            Operations::Report(_) => {
+            Operations::AnchorReady(msg) => match patchwork.player_anchors.get_mut(&msg.conn_id) {
+                Some(anchor) if anchor.pending && anchor.map_index == msg.map_index => {
+                    trace!(
+                        "Anchor {:?} ready for conn_id {:?}, flushing {:?} buffered packets",
+                        msg.anchor_conn_id,
+                        msg.conn_id,
+                        anchor.buffered_packets.len()
+                    );
+                    anchor.pending = false;
+                    anchor.conn_id = Some(msg.anchor_conn_id);
+                    authenticate(msg.anchor_conn_id, 4, msg.challenge, &messenger, &peer_auth);
+                    player_state
+                        .cross_border(msg.conn_id, msg.anchor_conn_id)
+                        .or_log();
+                    anchor.buffered_packets.drain(..).for_each(|packet| {
+                        messenger.send_packet(msg.anchor_conn_id, packet).or_log()
+                    });
+                }
+                _ => {
+                    trace!("Discarding stale anchor {:?}", msg.anchor_conn_id);
+                    messenger.close(msg.anchor_conn_id).or_log();
+                }
+            },
+            // The player's routed locally until the anchor's tried again, backing off a little more
+            // every time it fails
+            Operations::AnchorFailed(msg) => {
+                if let Some(anchor) = patchwork.player_anchors.get_mut(&msg.conn_id) {
+                    if anchor.pending && anchor.map_index == msg.map_index {
+                        let failures = anchor.failures + 1;
+                        let backoff = server::REANCHOR_RETRY_POLICY.backoff(failures - 1);
+                        warn!(
+                            "Failed to anchor conn_id {:?} to map {:?}, routing locally for {:?}",
+                            msg.conn_id, msg.map_index, backoff
+                        );
+                        anchor.buffered_packets.drain(..).for_each(|packet| {
+                            gameplay_router::route_packet(
+                                packet,
+                                msg.conn_id,
+                                player_state.clone(),
+                                command_service.clone(),
+                            )
+                            .or_log()
+                        });
+                        *anchor = Anchor {
+                            failures,
+                            ..Anchor::disconnected(msg.map_index, patchwork.local_map)
+                        };
+                        let (conn_id, map_index, sender) =
+                            (msg.conn_id, msg.map_index, sender.clone());
+                        instance::spawn("anchor-retry", move || {
+                            thread::sleep(backoff);
+                            sender.retry_anchor(conn_id, map_index).or_log();
+                        });
+                    }
+                }
+            }
+            // Only if the player's still routed locally on the map the anchor failed for. Anyone who's
+            // moved on since has been anchored afresh
+            Operations::RetryAnchor(msg) => {
+                let failures = match patchwork.player_anchors.get(&msg.conn_id) {
+                    Some(anchor)
+                        if anchor.map_index == msg.map_index
+                            && anchor.failures > 0
+                            && anchor.conn_id.is_none()
+                            && !anchor.pending =>
+                    {
+                        anchor.failures
+                    }
+                    _ => continue,
+                };
+                trace!(
+                    "Trying again to anchor conn_id {:?} to map {:?}",
+                    msg.conn_id,
+                    msg.map_index
+                );
+                let anchor = anchor_to(
+                    &patchwork,
+                    msg.conn_id,
+                    msg.map_index,
+                    &messenger,
+                    &sender,
+                    &uuids,
+                );
+                patchwork
+                    .player_anchors
+                    .insert(msg.conn_id, Anchor { failures, ..anchor });
+            }
+            Operations::FillBlocks(msg) => {
+                for (map_index, map) in patchwork.maps.iter().enumerate() {
+                    if let Some((from, to)) = map.clip_region(msg.from, msg.to) {
+                        match &map.peer_connection {
+                            Some(peer_connection) => {
+                                trace!("Forwarding fill to peer {:?}", peer_connection.peer);
+                                messenger
+                                    .send_packet(
+                                        peer_connection.conn_id,
+                                        Packet::FillBlocks(packet::FillBlocks {
+                                            from_x: from.x,
+                                            from_y: from.y,
+                                            from_z: from.z,
+                                            to_x: to.x,
+                                            to_y: to.y,
+                                            to_z: to.z,
+                                            block_id: msg.block_id,
+                                        }),
+                                    )
+                                    .or_log();
+                            }
+                            None if map_index == 0 && patchwork.local_map => {
+                                block_state.fill(from, to, msg.block_id).or_log()
+                            }
+                            None => {
+                                warn!("Cannot fill blocks: map {:?} is not connected", map_index)
+                            }
+                        }
+                    }
+                }
+            }
+            Operations::PeerUnreachable(msg) => {
+                error!(
+                    "Peer {:?} for map {:?} never came up ({}), players will be routed locally",
+                    msg.peer, msg.map_index, msg.reason
+                );
+            }
+            Operations::Gossip(_) => {
+                let gossip = patchwork.gossip(local_peer.clone());
+                trace!("Gossiping {:?} maps to peers", gossip.len());
+                let gossip = match serde_json::to_string(&gossip) {
+                    Ok(gossip) => gossip,
+                    Err(e) => {
+                        error!("Cannot gossip: {}", PatchworkError::from(e));
+                        continue;
+                    }
+                };
+                patchwork
+                    .maps
+                    .iter()
+                    .filter_map(|map| map.peer_connection.as_ref())
+                    .for_each(|peer_connection| {
+                        messenger
+                            .send_packet(
+                                peer_connection.conn_id,
+                                Packet::PeerGossip(packet::PeerGossip {
+                                    maps: gossip.clone(),
+                                }),
+                            )
+                            .or_log();
+                    });
+            }
+            // Gossip is laid out from the gossiping peer's point of view, which is shifted onto ours
+            // through a map we both know. Maps whose place is already taken here, or that can't be
+            // lined up, go on the end of the row and are sorted out by negotiation once connected
+            Operations::MergeGossip(msg) => {
+                let offset = patchwork.gossip_offset(&msg.maps, &local_peer);
+                for map in msg.maps {
+                    if map.peer == local_peer {
+                        continue;
+                    }
+                    if let Some(map_index) = patchwork.peer_map_index(&map.peer) {
+                        if let Some(seed) = map.seed {
+                            patchwork.map_seeds.insert(map_index, seed);
+                        }
+                        continue;
+                    }
+                    trace!(
+                        "Discovered peer {:?} with map at {:?} through gossip",
+                        map.peer,
+                        map.position
+                    );
+                    let position = offset
+                        .map(|(x, z)| Position {
+                            x: map.position.x + x,
+                            z: map.position.z + z,
+                            ..map.position
+                        })
+                        .filter(|position| patchwork.find_map_index(*position).is_none())
+                        .unwrap_or_else(|| patchwork.next_position());
+                    patchwork.add_peer_map(
+                        map.peer,
+                        position,
+                        patchwork.next_entity_id_block(),
+                        messenger.clone(),
+                        inbound_packet_processor.clone(),
+                        sender.clone(),
+                    );
+                    if let Some(seed) = map.seed {
+                        patchwork.map_seeds.insert(patchwork.maps.len() - 1, seed);
+                    }
+                }
+            }
+            Operations::Heartbeat(_) => {
+                patchwork.publish_metrics();
+                patchwork
+                    .check_heartbeats(messenger.clone())
+                    .into_iter()
+                    .for_each(|map_index| {
+                        patchwork.fail_map(
+                            map_index,
+                            messenger.clone(),
+                            inbound_packet_processor.clone(),
+                            player_state.clone(),
+                            sender.clone(),
+                        )
+                    });
+            }
+            Operations::HeartbeatAck(msg) => {
+                patchwork.heartbeat_ack(msg.conn_id);
+            }
+            // A peer has connected to us and placed our map at msg.position relative to its own,
+            // with entity id block msg.entity_id_block. Where the two layouts disagree, the peer
+            // that sorts first gets its way. Each side gives the other's map the same block, so
+            // both number each other's entities the same way, see settled_entity_id_block
+            Operations::ProposeMapPosition(_) if !patchwork.local_map => {
+                warn!("A peer tried to place our map, but we're a proxy and don't have one");
+            }
+            Operations::ProposeMapPosition(msg) => {
+                messenger
+                    .identify_peer(msg.conn_id, msg.peer.clone())
+                    .or_log();
+                inbound_packet_processor
+                    .identify_link(msg.conn_id, msg.peer.clone())
+                    .or_log();
+                let expected = Position {
+                    x: -msg.position.x,
+                    z: -msg.position.z,
+                    dimension: msg.position.dimension,
+                };
+                let map_index = match patchwork.peer_map_index(&msg.peer) {
+                    Some(map_index) => map_index,
+                    None => {
+                        let position = match patchwork.find_map_index(expected) {
+                            Some(_) => patchwork.next_position(),
+                            None => expected,
+                        };
+                        let entity_id_block = patchwork.settled_entity_id_block(
+                            patchwork.next_entity_id_block(),
+                            msg.entity_id_block,
+                        );
+                        trace!("Adding map for proposing peer {:?}", msg.peer);
+                        patchwork.add_peer_map(
+                            msg.peer.clone(),
+                            position,
+                            entity_id_block,
+                            messenger.clone(),
+                            inbound_packet_processor.clone(),
+                            sender.clone(),
+                        );
+                        patchwork.maps.len() - 1
+                    }
+                };
+                if patchwork.maps[map_index].position != expected
+                    && msg.peer < local_peer
+                    && patchwork.find_map_index(expected).is_none()
+                {
+                    patchwork.move_map(
+                        map_index,
+                        expected,
+                        messenger.clone(),
+                        inbound_packet_processor.clone(),
+                        player_state.clone(),
+                    );
+                }
+                let entity_id_block = patchwork.settled_entity_id_block(
+                    patchwork.maps[map_index].entity_id_block,
+                    msg.entity_id_block,
+                );
+                if patchwork.maps[map_index].entity_id_block != entity_id_block {
+                    patchwork.reblock_map(
+                        map_index,
+                        entity_id_block,
+                        messenger.clone(),
+                        inbound_packet_processor.clone(),
+                        player_state.clone(),
+                    );
+                }
+                let agreed = patchwork.maps[map_index].position;
+                let origin = patchwork.maps[0].position;
+                // Maps in different dimensions never share a seam, however they line up
+                if agreed.dimension == origin.dimension {
+                    player_state.add_seam(msg.conn_id, agreed).or_log();
+                }
+                messenger
+                    .send_packet(
+                        msg.conn_id,
+                        Packet::MapPositionAgreement(packet::MapPositionAgreement {
+                            x: -agreed.x,
+                            z: -agreed.z,
+                            dimension: origin.dimension.id(),
+                            entity_id_block: patchwork.maps[map_index].entity_id_block,
+                        }),
+                    )
+                    .or_log();
+            }
+            Operations::AgreeMapPosition(msg) => {
+                let map_index = match patchwork.connection_map_index(msg.conn_id) {
+                    Some(map_index) => map_index,
+                    None => continue,
+                };
+                if patchwork.maps[map_index].position == msg.position {
+                    trace!("Peer agreed to map {:?} at {:?}", map_index, msg.position);
+                } else if patchwork.find_map_index(msg.position).is_some() {
+                    error!(
+                        "Peer placed map {:?} at {:?}, which is already taken",
+                        map_index, msg.position
+                    );
+                } else {
+                    patchwork.move_map(
+                        map_index,
+                        msg.position,
+                        messenger.clone(),
+                        inbound_packet_processor.clone(),
+                        player_state.clone(),
+                    );
+                }
+                let entity_id_block = patchwork.settled_entity_id_block(
+                    patchwork.maps[map_index].entity_id_block,
+                    msg.entity_id_block,
+                );
+                if patchwork.maps[map_index].entity_id_block != entity_id_block {
+                    patchwork.reblock_map(
+                        map_index,
+                        entity_id_block,
+                        messenger.clone(),
+                        inbound_packet_processor.clone(),
+                        player_state.clone(),
+                    );
+                }
+                if entity_id_block == msg.entity_id_block {
+                    trace!(
+                        "Peer agreed to entity id block {:?} for map {:?}",
+                        entity_id_block,
+                        map_index
+                    );
+                } else {
+                    // Theirs was taken here, so they're asked again with one that isn't
+                    patchwork.propose_position(map_index, &local_peer, &messenger);
+                }
+                // A recruit places our map once it's adopted the half we handed it
+                if patchwork
+                    .pending_splits
+                    .get(&map_index)
+                    .is_some_and(|split| split.handed_over)
+                {
+                    let split = patchwork.pending_splits.remove(&map_index).unwrap();
+                    complete_split(&patchwork, map_index, split, &player_state, &block_state);
+                }
+            }
+            // Hands our map over to a connected peer: it gets our blocks and layout, everyone
+            // subscribed to us is pointed at it, and we keep serving our own clients as a proxy
+            Operations::HandOff(msg) => {
+                let peer_connection = match patchwork
+                    .peer_map_index(&msg.peer)
+                    .and_then(|map_index| patchwork.maps[map_index].peer_connection.clone())
+                {
+                    Some(peer_connection) => peer_connection,
+                    None => {
+                        error!("Cannot hand off map: peer {:?} is not connected", msg.peer);
+                        continue;
+                    }
+                };
+                let (reply_sender, reply_receiver) = channel();
+                block_state.export(reply_sender).or_log();
+                let block_ids = match reply_receiver.recv() {
+                    Ok(block_ids) => block_ids,
+                    Err(_) => {
+                        error!("Cannot hand off map: block state is unavailable");
+                        continue;
+                    }
+                };
+                let topology = match serde_json::to_string(&patchwork.topology(local_peer.clone()))
+                {
+                    Ok(topology) => topology,
+                    Err(e) => {
+                        error!("Cannot hand off map: {}", PatchworkError::from(e));
+                        continue;
+                    }
+                };
+                info!("Handing off map to {:?}", msg.peer);
+                messenger
+                    .send_packet(
+                        peer_connection.conn_id,
+                        Packet::MapHandoff(packet::MapHandoff {
+                            peer_address: local_peer.address.clone(),
+                            peer_port: local_peer.port,
+                            topology,
+                            block_ids,
+                        }),
+                    )
+                    .or_log();
+                messenger
+                    .broadcast(
+                        Packet::MapOwnerChange(packet::MapOwnerChange {
+                            peer_address: msg.peer.address.clone(),
+                            peer_port: msg.peer.port,
+                        }),
+                        None,
+                        SubscriberType::Remote,
+                    )
+                    .or_log();
+                patchwork.remove_peer_map(
+                    msg.peer.clone(),
+                    messenger.clone(),
+                    player_state.clone(),
+                );
+                patchwork.replace_map_owner(
+                    0,
+                    msg.peer,
+                    messenger.clone(),
+                    inbound_packet_processor.clone(),
+                    player_state.clone(),
+                    sender.clone(),
+                );
+            }
+            // A peer handed its map over to us, so we take its place in its layout
+            // or a peer split its map and recruited us to take the place next to it
+            Operations::AdoptMap(msg) => {
+                info!("Adopting layout, previous owner {:?}", msg.previous_owner);
+                if let Some(previous_owner) = &msg.previous_owner {
+                    patchwork.remove_peer_map(
+                        previous_owner.clone(),
+                        messenger.clone(),
+                        player_state.clone(),
+                    );
+                }
+                // A recruit's map goes in the dimension of the map it was split off from
+                if let Some(map) = msg.topology.maps.iter().find(|map| map.owner == local_peer) {
+                    if patchwork.local_map {
+                        patchwork.maps[0].position.dimension = map.position.dimension;
+                    }
+                }
+                for map in msg.topology.maps {
+                    if map.owner == local_peer || Some(&map.owner) == msg.previous_owner.as_ref() {
+                        continue;
+                    }
+                    match patchwork.peer_map_index(&map.owner) {
+                        Some(map_index) if patchwork.maps[map_index].position == map.position => {}
+                        _ if patchwork.find_map_index(map.position).is_some() => warn!(
+                            "Cannot place map for {:?} at {:?}: position is taken",
+                            map.owner, map.position
+                        ),
+                        Some(map_index) => patchwork.move_map(
+                            map_index,
+                            map.position,
+                            messenger.clone(),
+                            inbound_packet_processor.clone(),
+                            player_state.clone(),
+                        ),
+                        None => {
+                            // The recruiter's map gets the same block as it's given ours, see
+                            // settled_entity_id_block
+                            let entity_id_block = match &msg.recruiter {
+                                Some((recruiter, entity_id_block)) if *recruiter == map.owner => {
+                                    patchwork.settled_entity_id_block(
+                                        patchwork.next_entity_id_block(),
+                                        *entity_id_block,
+                                    )
+                                }
+                                _ => patchwork.next_entity_id_block(),
+                            };
+                            patchwork.add_peer_map(
+                                map.owner,
+                                map.position,
+                                entity_id_block,
+                                messenger.clone(),
+                                inbound_packet_processor.clone(),
+                                sender.clone(),
+                            )
+                        }
+                    }
+                }
+            }
+            // Recruits a standby peer onto a free position next to our map once too many players
+            // are on it. Once the peer is connected, it's handed the half of our map nearest the
+            // seam with it, and once it's adopted that the players standing there follow, see
+            // hand_over_split and complete_split
+            Operations::CheckLoad(_) => {
+                let split = match &config.split {
+                    Some(split) if config.local_map => split,
+                    _ => continue,
+                };
+                let (reply_sender, reply_receiver) = channel();
+                player_state.positions(reply_sender).or_log();
+                let crowd: Vec<Uuid> = match reply_receiver.recv() {
+                    Ok(positions) => positions
+                        .into_iter()
+                        .filter(|(conn_id, position)| {
+                            let dimension = patchwork.player_dimension(*conn_id);
+                            patchwork.find_map_index(map_position(*position, dimension)) == Some(0)
+                        })
+                        .map(|(conn_id, _)| conn_id)
+                        .collect(),
+                    Err(_) => continue,
+                };
+                trace!("{:?} players on our map", crowd.len());
+                if crowd.len() <= split.max_players || !patchwork.pending_splits.is_empty() {
+                    continue;
+                }
+                let recruit = match split
+                    .standby_peers
+                    .iter()
+                    .find(|peer| !patchwork.has_peer(peer))
+                {
+                    Some(recruit) => recruit.clone(),
+                    None => {
+                        warn!("Our map is crowded but there are no standby peers left to split to");
+                        continue;
+                    }
+                };
+                if map_size() < 2 {
+                    warn!("Our map is crowded but is a single chunk, which can't be split");
+                    continue;
+                }
+                let position = match patchwork.free_neighbour(0) {
+                    Some(position) => position,
+                    None => {
+                        warn!("Our map is crowded but has no free neighbouring position");
+                        continue;
+                    }
+                };
+                let origin = patchwork.maps[0].position;
+                let seam = (position.x - origin.x, position.z - origin.z);
+                info!(
+                    "Splitting our map, recruiting {:?} at {:?}",
+                    recruit, position
+                );
+                let mut topology = patchwork.topology(local_peer.clone());
+                topology.maps.push(TopologyMap {
+                    name: format!("split-{}-{}", position.x, position.z),
+                    owner: recruit.clone(),
+                    position,
+                    seed: None,
+                });
+                patchwork.add_peer_map(
+                    recruit.clone(),
+                    position,
+                    patchwork.next_entity_id_block(),
+                    messenger.clone(),
+                    inbound_packet_processor.clone(),
+                    sender.clone(),
+                );
+                patchwork.pending_splits.insert(
+                    patchwork.maps.len() - 1,
+                    PendingSplit {
+                        topology: topology.rebase(&recruit).unwrap(),
+                        seam,
+                        handed_over: false,
+                    },
+                );
+            }
+            Operations::ChangeMapOwner(msg) => {
+                let map_index = match patchwork.connection_map_index(msg.conn_id) {
+                    Some(map_index) => map_index,
+                    None => continue,
+                };
+                info!("Map {:?} has been handed off to {:?}", map_index, msg.peer);
+                // The new owner's previous map is now the one it took over
+                patchwork.remove_peer_map(
+                    msg.peer.clone(),
+                    messenger.clone(),
+                    player_state.clone(),
+                );
+                patchwork.replace_map_owner(
+                    map_index,
+                    msg.peer,
+                    messenger.clone(),
+                    inbound_packet_processor.clone(),
+                    player_state.clone(),
+                    sender.clone(),
+                );
+            }
+            // Only the owner of the map a player's anchored to gets a say. Once they're pulled back,
+            // the next position they send anchors them to our map as if they'd walked there
+            Operations::KickBack(msg) => {
+                let map_index = match patchwork.connection_map_index(msg.conn_id) {
+                    Some(map_index) => map_index,
+                    None => {
+                        trace!("Ignoring kickback from unknown conn_id {:?}", msg.conn_id);
+                        continue;
+                    }
+                };
+                let (reply_sender, reply_receiver) = channel();
+                player_state
+                    .find_connection(msg.uuid, reply_sender)
+                    .or_log();
+                let conn_id = match reply_receiver.recv() {
+                    Ok(Some(conn_id)) => conn_id,
+                    _ => continue,
+                };
+                match patchwork.player_anchors.get(&conn_id) {
+                    Some(anchor)
+                        if anchor.map_index == map_index
+                            && (anchor.pending || anchor.conn_id.is_some()) => {}
+                    _ => {
+                        warn!(
+                            "Map {:?} tried to kick back conn_id {:?}, who isn't anchored to it",
+                            map_index, conn_id
+                        );
+                        continue;
+                    }
+                }
+                let _map = map_span(&patchwork.maps, map_index).entered();
+                let own_map = patchwork.local_map && patchwork.maps[0].peer_connection.is_none();
+                match msg.reason {
+                    Some(reason) => {
+                        info!("Disconnecting conn_id {:?} at the map's request", conn_id);
+                        messenger.kick(conn_id, reason).or_log();
+                    }
+                    None if own_map => {
+                        info!("Pulling conn_id {:?} back at the map's request", conn_id);
+                        player_state
+                            .pull_back(conn_id, patchwork.maps[0].position)
+                            .or_log();
+                    }
+                    // Proxies have nowhere to pull them back to
+                    None => messenger
+                        .kick(conn_id, String::from(KICKED_BACK_MESSAGE))
+                        .or_log(),
+                }
+            }
+            Operations::ExportTopology(msg) => {
+                let _ = msg.reply.send(patchwork.topology(local_peer.clone()));
+            }
+            // Proxies have no map to lay the others out around, so take the layout as it is
+            Operations::ImportTopology(msg) => {
+                let topology = if config.local_map {
+                    msg.topology.rebase(&local_peer)
+                } else {
+                    Some(msg.topology)
+                };
+                let topology = match topology {
+                    Some(topology) => topology,
+                    None => {
+                        error!(
+                            "Cannot import topology: no map is owned by {:?}",
+                            local_peer
+                        );
+                        continue;
+                    }
+                };
+                for map in topology.maps {
+                    if map.owner == local_peer {
+                        if config.local_map {
+                            patchwork.map_names.insert(0, map.name);
+                        } else {
+                            warn!("Cannot import map {:?}: we're a proxy", map.name);
+                        }
+                        continue;
+                    }
+                    if patchwork.has_peer(&map.owner) {
+                        trace!("Peer {:?} already has a map", map.owner);
+                        continue;
+                    }
+                    if patchwork.find_map_index(map.position).is_some() {
+                        warn!(
+                            "Cannot import map {:?}: position {:?} is already taken",
+                            map.name, map.position
+                        );
+                        continue;
+                    }
+                    trace!("Importing map {:?} for peer {:?}", map.name, map.owner);
+                    patchwork.add_peer_map(
+                        map.owner,
+                        map.position,
+                        patchwork.next_entity_id_block(),
+                        messenger.clone(),
+                        inbound_packet_processor.clone(),
+                        sender.clone(),
+                    );
+                    patchwork
+                        .map_names
+                        .insert(patchwork.maps.len() - 1, map.name);
+                    if let Some(seed) = map.seed {
+                        patchwork.map_seeds.insert(patchwork.maps.len() - 1, seed);
+                    }
+                }
+            }
+            // Both the peers we're subscribed to and the ones subscribed to us drop our map rather
+            // than waiting for our heartbeats to stop
+            Operations::ShutDown(msg) => {
+                info!("Telling peers we're shutting down");
+                let packet = Packet::PeerShutdown(packet::PeerShutdown {
+                    peer_address: local_peer.address.clone(),
+                    peer_port: local_peer.port,
+                });
+                patchwork
+                    .maps
+                    .iter()
+                    .filter_map(|map| map.peer_connection.as_ref())
+                    .for_each(|peer_connection| {
+                        messenger
+                            .send_packet(peer_connection.conn_id, packet.clone())
+                            .or_log()
+                    });
+                messenger
+                    .broadcast(packet, None, SubscriberType::Remote)
+                    .or_log();
+                let _ = msg.reply.send(());
+            }
+            Operations::Report(msg) => {
                 trace!("Reporting patchwork state");
                 patchwork.clone().report(messenger.clone());
+                inbound_packet_processor.catch_up(msg.conn_id).or_log();
+            }
+            Operations::SummonEntity(msg) => {
+                let position = map_position(msg.position, patchwork.local_dimension());
+                match patchwork.find_map_index(position) {
+                    Some(map_index) => match &patchwork.maps[map_index].peer_connection {
+                        Some(peer_connection) => {
+                            trace!("Forwarding summon to peer {:?}", peer_connection.peer);
+                            let packet = Packet::SummonEntity(packet::SummonEntity {
+                                entity_type: msg.entity_type,
+                                x: msg.position.x,
+                                y: msg.position.y,
+                                z: msg.position.z,
+                            });
+                            messenger
+                                .send_packet(
+                                    peer_connection.conn_id,
+                                    packet::translate_outgoing(
+                                        packet,
+                                        TranslationInfo {
+                                            state: 0,
+                                            map: patchwork.maps[map_index].clone(),
+                                        },
+                                    ),
+                                )
+                                .or_log();
+                        }
+                        None if map_index == 0 && patchwork.local_map => {
+                            entity_state.summon(msg.entity_type, msg.position).or_log();
+                        }
+                        None => warn!("Cannot summon entity: map {:?} is not connected", map_index),
+                    },
+                    None => warn!("Cannot summon entity: no map at {:?}", position),
+                }
+            }
+            // Items that land somewhere nobody can take them, or on a peer we've lost, stay with us
+            // rather than vanishing
+            Operations::TransferItem(msg) => {
+                let position = map_position(msg.item.position, patchwork.local_dimension());
+                let map = patchwork
+                    .find_map_index(position)
+                    .map(|map_index| &patchwork.maps[map_index]);
+                let _ = msg.reply.send(match map {
+                    Some(
+                        map @ Map {
+                            peer_connection: Some(peer_connection),
+                            ..
+                        },
+                    ) => {
+                        trace!(
+                            "Handing item {:?} over to peer {:?}",
+                            msg.item.uuid,
+                            peer_connection.peer
+                        );
+                        messenger
+                            .send_packet(
+                                peer_connection.conn_id,
+                                packet::translate_outgoing(
+                                    Packet::ItemTransfer(msg.item.item_transfer_packet()),
+                                    TranslationInfo {
+                                        state: 0,
+                                        map: map.clone(),
+                                    },
+                                ),
+                            )
+                            .or_log();
+                        true
+                    }
+                    _ => {
+                        trace!("Keeping item {:?} at {:?}", msg.item.uuid, position);
+                        false
+                    }
+                });
+            }
+            Operations::KillEntity(msg) => match patchwork.entity_id_map_index(msg.entity_id) {
+                Some(map_index) => match &patchwork.maps[map_index].peer_connection {
+                    Some(peer_connection) => {
+                        trace!("Forwarding kill to peer {:?}", peer_connection.peer);
+                        let map = &patchwork.maps[map_index];
+                        messenger
+                            .send_packet(
+                                peer_connection.conn_id,
+                                Packet::KillEntity(packet::KillEntity {
+                                    entity_id: map
+                                        .entity_ids
+                                        .to_peer(msg.entity_id, map.entity_id_block),
+                                }),
+                            )
+                            .or_log();
+                    }
+                    None if map_index == 0 && patchwork.local_map => {
+                        entity_state.kill(msg.entity_id).or_log()
+                    }
+                    None => warn!("Cannot kill entity: map {:?} is not connected", map_index),
+                },
+                None => warn!("Cannot kill entity {:?}: no owning map", msg.entity_id),
+            },
+            Operations::DescribeMaps(msg) => {
+                let _ = msg.reply.send(patchwork.describe_maps());
             }
+            Operations::LocateEntity(msg) => match msg.query {
+                EntityQuery::Id(entity_id) => {
+                    let _ = msg.reply.send(patchwork.entity_id_owner(entity_id));
+                }
+                EntityQuery::Uuid(uuid) => {
+                    match find_local_entity(uuid, &player_state, &entity_state) {
+                        Some(entity_id) => {
+                            let _ = msg.reply.send(EntityOwner::Local { entity_id });
+                        }
+                        None => patchwork.ask_peers_for_entity(uuid, msg.reply, messenger.clone()),
+                    }
+                }
+            },
+            Operations::EntityOwnerReply(msg) => {
+                patchwork.entity_owner_reply(msg.conn_id, msg.query_id, msg.entity_id)
+            }
+        }
+    }
+}
+
+// Feeds recorded messages to a fresh patchwork service in order, waiting for it to be done with
+// each before handing it the next, and describes what it sent the other services along the way.
+// Whatever it sends itself is already in the log, in the order it really arrived, so it's left
+// unread. Peers named in the log are still connected to, so replay somewhere they can't be reached
+pub fn replay(config: Config, local_peer: Peer, messages: Vec<Operations>) -> Vec<String> {
+    let (sender, receiver) = channel();
+    let (own_sender, _unread) = channel();
+    let (messenger, messenger_sent) = channel::<interfaces::messenger::Operations>();
+    let (processor, processor_sent) = channel::<interfaces::packet_processor::Operations>();
+    let (player_state, player_state_sent) = channel::<interfaces::player::Operations>();
+    let (entity_state, entity_state_sent) = channel::<interfaces::entity::Operations>();
+    let (command_service, command_service_sent) = channel::<interfaces::command::Operations>();
+    let (block_state, block_state_sent) = channel::<interfaces::block::Operations>();
+    let (peer_auth, peer_auth_sent) = channel::<interfaces::peer_auth::Operations>();
+    let (chat, chat_sent) = channel::<interfaces::chat::Operations>();
+    let config = Config {
+        message_log_directory: None,
+        ..config
+    };
+    let service = instance::spawn("patchwork-replay", move || {
+        start(
+            Queue::uncounted(receiver),
+            own_sender,
+            messenger,
+            processor,
+            player_state,
+            entity_state,
+            command_service,
+            block_state,
+            peer_auth,
+            chat,
+            local_peer,
+            config,
+            UuidSource::sequential(),
+        )
+    });
+
+    // What the service sent while handling each message, kept apart by who it was sent to so
+    // it comes out in the same order every time
+    let mut sent: [Vec<String>; 8] = Default::default();
+    let take_sent = |sent: &mut [Vec<String>; 8]| {
+        describe_sent("messenger", &messenger_sent, &mut sent[0]);
+        describe_sent("inbound_packet_processor", &processor_sent, &mut sent[1]);
+        describe_sent("player_state", &player_state_sent, &mut sent[2]);
+        describe_sent("entity_state", &entity_state_sent, &mut sent[3]);
+        describe_sent("command_service", &command_service_sent, &mut sent[4]);
+        describe_sent("block_state", &block_state_sent, &mut sent[5]);
+        describe_sent("peer_auth", &peer_auth_sent, &mut sent[6]);
+        describe_sent("chat", &chat_sent, &mut sent[7]);
+    };
+    let mut described = Vec::new();
+    for (number, msg) in messages.into_iter().enumerate() {
+        let handled = format!("{}: {:?}", number + 1, msg);
+        let _ = sender.send(msg);
+        // Nothing else changes for having the maps described, so it doubles as a marker for when
+        // the message before it has been handled. Anything the service is waiting on an answer to
+        // is dropped along the way, so that it carries on
+        let (reply_sender, reply_receiver) = channel();
+        sender.describe_maps(reply_sender).or_log();
+        let handled_by = loop {
+            match reply_receiver.recv_timeout(Duration::from_millis(REPLAY_POLL_PERIOD)) {
+                Ok(_) => break true,
+                Err(RecvTimeoutError::Timeout) => take_sent(&mut sent),
+                Err(RecvTimeoutError::Disconnected) => break false,
+            }
+        };
+        take_sent(&mut sent);
+        described.push(handled);
+        described.extend(sent.iter_mut().flat_map(|sent| sent.drain(..)));
+        if !handled_by {
+            described.push(String::from("The service stopped"));
+            break;
         }
     }
+    drop(sender);
+    let _ = service.join();
+    described
+}
+
+fn describe_sent<O: Debug>(service: &str, sent: &Receiver<O>, described: &mut Vec<String>) {
+    described.extend(
+        sent.try_iter()
+            .map(|msg| format!("    {} <- {:?}", service, msg)),
+    );
 }
 
-fn extract_map_position(packet: Packet) -> Option<Position> {
+// Inventory changes made while anchored elsewhere are kept track of here too, so that players
+// still have their things when they cross back onto our map
+fn track_inventory<P: PlayerState>(packet: &Packet, conn_id: Uuid, player_state: &P) {
     match packet {
-        Packet::PlayerPosition(packet) => Some(Position {
-            x: (packet.x / 16.0) as i32,
-            z: (packet.z / 16.0) as i32,
-        }),
-        Packet::PlayerPositionAndLook(packet) => Some(Position {
-            x: (packet.x / 16.0) as i32,
-            z: (packet.z / 16.0) as i32,
+        Packet::HeldItemChange(packet) => player_state.hold_item(conn_id, packet.slot).or_log(),
+        Packet::CreativeInventoryAction(packet) => player_state
+            .set_slot(conn_id, packet.slot, packet.clicked_item.clone())
+            .or_log(),
+        Packet::PlayerDigging(packet) => match packet.status {
+            gameplay_router::DROP_ITEM_STACK => {
+                player_state.drop_item(conn_id, true, false).or_log()
+            }
+            gameplay_router::DROP_ITEM => player_state.drop_item(conn_id, false, false).or_log(),
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+// Messages go to our players and on to every peer, tagged with where they came from so the
+// messenger can keep them from bouncing back around the quilt. Our players are only sent those from
+// peers by the messenger, the first time they get here
+fn relay_plugin_message<M: Messenger>(
+    source_conn_id: Option<Uuid>,
+    origin: Origin,
+    via: Option<Uuid>,
+    channel: String,
+    data: Vec<u8>,
+    config: &Config,
+    messenger: &M,
+) {
+    if !config.plugin_channels.contains(&channel) {
+        trace!(
+            "Ignoring message on unregistered plugin channel {:?}",
+            channel
+        );
+        return;
+    }
+    trace!(
+        "Relaying message on plugin channel {:?} from {:?}",
+        channel,
+        origin
+    );
+    let peer_packet = Packet::PeerPluginMessage(packet::PeerPluginMessage {
+        origin_address: origin.node.address.clone(),
+        origin_port: origin.node.port,
+        hops: origin.hops,
+        sequence: origin.sequence,
+        channel: channel.clone(),
+        data: data.clone(),
+    });
+    let local =
+        Packet::ClientboundPluginMessage(packet::ClientboundPluginMessage { channel, data });
+    if via.is_some() {
+        messenger
+            .relay(
+                peer_packet,
+                origin,
+                via,
+                SubscriberType::Remote,
+                Some(Box::new(local)),
+            )
+            .or_log();
+    } else {
+        messenger
+            .relay(peer_packet, origin, via, SubscriberType::Remote, None)
+            .or_log();
+        messenger
+            .broadcast(local, source_conn_id, SubscriberType::Local)
+            .or_log();
+    }
+}
+
+fn authenticate<M: Messenger, A: PeerAuth>(
+    conn_id: Uuid,
+    next_state: i32,
+    challenge: u128,
+    messenger: &M,
+    peer_auth: &A,
+) {
+    let (reply_sender, reply_receiver) = channel();
+    peer_auth.sign(next_state, challenge, reply_sender).or_log();
+    if let Ok(token) = reply_receiver.recv() {
+        messenger
+            .send_packet(
+                conn_id,
+                Packet::PeerAuth(packet::PeerAuth {
+                    next_state: token.next_state,
+                    key_id: token.key_id,
+                    timestamp: token.timestamp,
+                    mac: token.mac,
+                    instance_id: token.instance.id.as_u128(),
+                    instance_name: token.instance.name,
+                    map_size: map_size(),
+                }),
+            )
+            .or_log();
+    }
+}
+
+fn map_position(position: PlayerPosition, dimension: Dimension) -> Position {
+    Position {
+        x: (position.x / map_width() as f64).floor() as i32,
+        z: (position.z / map_width() as f64).floor() as i32,
+        dimension,
+    }
+}
+
+// Whatever the player was anchored to is let go, and they're placed all over again on the map at
+// the given position, which may well be in another dimension from the one they were in
+#[allow(clippy::too_many_arguments)]
+fn respawn<M: 'static + Messenger + Clone + Send, P: PlayerState, B: BlockState>(
+    patchwork: &mut Patchwork,
+    conn_id: Uuid,
+    position: PlayerPosition,
+    dimension: Dimension,
+    messenger: &M,
+    sender: &Sender<Operations>,
+    uuids: &UuidSource,
+    player_state: &P,
+    block_state: &B,
+) {
+    let map_index = match patchwork.position_map_index(map_position(position, dimension)) {
+        Ok(map_index) => map_index,
+        Err(e) => {
+            error!("Cannot respawn conn_id {:?}: {}", conn_id, e);
+            return;
+        }
+    };
+    trace!(
+        "Respawning conn_id {:?} on map {:?} in the {:?}",
+        conn_id,
+        map_index,
+        dimension
+    );
+    let was_anchored = match patchwork.player_anchors.remove(&conn_id) {
+        Some(anchor) => {
+            anchor.disconnect(messenger.clone());
+            anchor.pending || anchor.conn_id.is_some()
+        }
+        None => false,
+    };
+    // Chunks sent before the client has been told to respawn would be thrown away with the rest
+    let (reply_sender, reply_receiver) = channel();
+    player_state
+        .respawn(conn_id, position, dimension, reply_sender)
+        .or_log();
+    let _ = reply_receiver.recv();
+    let anchor = anchor_to(patchwork, conn_id, map_index, messenger, sender, uuids);
+    if was_anchored && !anchor.pending {
+        player_state.reintroduce(conn_id).or_log();
+    }
+    patchwork.player_anchors.insert(conn_id, anchor);
+    patchwork.player_dimensions.insert(conn_id, dimension);
+    block_state.report(conn_id).or_log();
+    sender.report(conn_id).or_log();
+}
+
+// Hands the half of our map nearest the seam over to a recruit that's just connected, laid against
+// the recruit's side of the seam. The recruit's map gets the entity id block we've given it, for it
+// to give ours in turn. Our half is left as it is until the recruit has adopted it, see
+// complete_split
+fn hand_over_split<M: Messenger, B: BlockState>(
+    patchwork: &Patchwork,
+    map_index: usize,
+    conn_id: Uuid,
+    split: &PendingSplit,
+    local_peer: &Peer,
+    messenger: &M,
+    block_state: &B,
+) -> Result<(), PatchworkError> {
+    let (reply_sender, reply_receiver) = channel();
+    block_state.export(reply_sender)?;
+    let block_ids = reply_receiver
+        .recv()
+        .map_err(|_| PatchworkError::ServiceStopped("block state"))?;
+    let topology = serde_json::to_string(&split.topology)?;
+    let map = &patchwork.maps[map_index];
+    messenger.send_packet(
+        conn_id,
+        Packet::MapSplit(packet::MapSplit {
+            topology,
+            block_ids: split_blocks(&block_ids, split.seam, map_size()),
+            dimension: map.position.dimension.id(),
+            peer_address: local_peer.address.clone(),
+            peer_port: local_peer.port,
+            entity_id_block: map.entity_id_block,
         }),
-        _ => None,
+    )
+}
+
+// Once the recruit has adopted its half and placed our map, the players standing on that half are
+// carried over to it by as far as the chunks were, and the chunks they left are generated afresh
+fn complete_split<P: PlayerState, B: BlockState>(
+    patchwork: &Patchwork,
+    map_index: usize,
+    split: PendingSplit,
+    player_state: &P,
+    block_state: &B,
+) {
+    let origin = patchwork.maps[0].position;
+    let shift = map_size() / 2 * CHUNK_SIZE;
+    let (reply_sender, reply_receiver) = channel();
+    player_state.positions(reply_sender).or_log();
+    let players: Vec<(Uuid, PlayerPosition)> = reply_receiver
+        .recv()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(conn_id, position)| {
+            let dimension = patchwork.player_dimension(*conn_id);
+            let chunk_x = (position.x.floor() as i32 - origin.x * map_width()) / CHUNK_SIZE;
+            let chunk_z = (position.z.floor() as i32 - origin.z * map_width()) / CHUNK_SIZE;
+            patchwork.find_map_index(map_position(*position, dimension)) == Some(0)
+                && on_seam_half(chunk_x, split.seam.0, map_size())
+                && on_seam_half(chunk_z, split.seam.1, map_size())
+        })
+        .collect();
+    info!(
+        "{:?} adopted half of our map, moving {:?} players onto it",
+        patchwork.maps[map_index]
+            .peer_connection
+            .as_ref()
+            .map(|connection| &connection.peer),
+        players.len()
+    );
+    players.into_iter().for_each(|(conn_id, position)| {
+        player_state
+            .teleport(
+                conn_id,
+                PlayerPosition {
+                    x: position.x + f64::from(split.seam.0 * shift),
+                    z: position.z + f64::from(split.seam.1 * shift),
+                    ..position
+                },
+            )
+            .or_log()
+    });
+    block_state
+        .regenerate(seam_half_chunks(split.seam, map_size()))
+        .or_log();
+}
+
+// The chunks of a map size chunks wide on the half of it nearest the seam in the given direction
+fn seam_half_chunks(seam: (i32, i32), size: i32) -> Vec<usize> {
+    (0..size * size)
+        .filter(|chunk| {
+            on_seam_half(chunk % size, seam.0, size) && on_seam_half(chunk / size, seam.1, size)
+        })
+        .map(|chunk| chunk as usize)
+        .collect()
+}
+
+// The half of a map's chunks nearest the seam in the given direction, laid out against the other
+// side of the seam on an otherwise empty map
+fn split_blocks(block_ids: &[i32], seam: (i32, i32), size: i32) -> Vec<i32> {
+    let half = size / 2;
+    let mut handed_over = vec![0; block_ids.len()];
+    seam_half_chunks(seam, size).into_iter().for_each(|chunk| {
+        let (chunk_x, chunk_z) = (chunk as i32 % size, chunk as i32 / size);
+        let to = (chunk_z - seam.1 * (size - half)) * size + chunk_x - seam.0 * (size - half);
+        let to = to as usize * CHUNK_BLOCKS;
+        handed_over[to..to + CHUNK_BLOCKS]
+            .copy_from_slice(&block_ids[chunk * CHUNK_BLOCKS..(chunk + 1) * CHUNK_BLOCKS]);
+    });
+    handed_over
+}
+
+// Whether a coordinate along one axis of a map, extent wide, is on the half of it nearest a seam in
+// the given direction. Seams along the other axis split nothing along this one
+fn on_seam_half(coordinate: i32, direction: i32, extent: i32) -> bool {
+    match direction {
+        1 => coordinate >= extent - extent / 2,
+        -1 => coordinate < extent / 2,
+        _ => true,
+    }
+}
+
+// Anchors the player to the map, through a new connection to its peer unless it's ours
+fn anchor_to<M: 'static + Messenger + Clone + Send>(
+    patchwork: &Patchwork,
+    conn_id: Uuid,
+    map_index: usize,
+    messenger: &M,
+    sender: &Sender<Operations>,
+    uuids: &UuidSource,
+) -> Anchor {
+    match &patchwork.maps[map_index].peer_connection {
+        Some(peer_connection) => {
+            Anchor::connect(
+                peer_connection.peer.clone(),
+                conn_id,
+                map_index,
+                patchwork.maps[map_index].position,
+                messenger.clone(),
+                sender.clone(),
+                uuids.clone(),
+            );
+            Anchor::pending(map_index)
+        }
+        None => Anchor::disconnected(map_index, patchwork.local_map),
+    }
+}
+
+// /portal <dimension>
+// Where the player comes out, worked out the way a vanilla portal would, if there's a map there
+fn take_portal<P: PlayerState>(
+    patchwork: &Patchwork,
+    packet: &Packet,
+    conn_id: Uuid,
+    player_state: &P,
+) -> Result<(PlayerPosition, Dimension), String> {
+    let (reply_sender, reply_receiver) = channel();
+    player_state
+        .permission_level(conn_id, reply_sender)
+        .or_log();
+    if reply_receiver.recv().unwrap_or(0) < command::required_level("portal") {
+        return Err(String::from("You don't have permission to use /portal"));
+    }
+    let args: Vec<&str> = match packet {
+        Packet::ChatMessage(chat_message) => chat_message.message.split_whitespace().collect(),
+        _ => Vec::new(),
+    };
+    let to = match args[..] {
+        [_, name] => {
+            Dimension::from_name(name).ok_or_else(|| format!("Unknown dimension: {}", name))?
+        }
+        _ => return Err(String::from("Usage: /portal <overworld|nether|the_end>")),
+    };
+    let from = patchwork.player_dimension(conn_id);
+    if from == to {
+        return Err(format!("You're already in the {:?}", to));
+    }
+    let (reply_sender, reply_receiver) = channel();
+    player_state.positions(reply_sender).or_log();
+    let position = reply_receiver
+        .recv()
+        .map_err(|_| String::from("Player state is unavailable"))?
+        .into_iter()
+        .find(|(player_conn_id, _)| *player_conn_id == conn_id)
+        .map(|(_, position)| portal_destination(position, from, to))
+        .ok_or_else(|| String::from("You aren't playing here"))?;
+    patchwork
+        .position_map_index(map_position(position, to))
+        .map_err(|e| format!("There's nowhere to come out: {}", e))?;
+    Ok((position, to))
+}
+
+// The nether is an eighth the size of the overworld, and the end's the same size as either
+fn portal_destination(position: PlayerPosition, from: Dimension, to: Dimension) -> PlayerPosition {
+    let scale = match (from, to) {
+        (Dimension::Overworld, Dimension::Nether) => 1.0 / NETHER_SCALE,
+        (Dimension::Nether, Dimension::Overworld) => NETHER_SCALE,
+        _ => 1.0,
+    };
+    PlayerPosition {
+        x: position.x * scale,
+        z: position.z * scale,
+        ..position
+    }
+}
+
+fn is_portal(packet: &Packet) -> bool {
+    match packet {
+        Packet::ChatMessage(chat_message) => {
+            chat_message.message.split_whitespace().next() == Some("/portal")
+        }
+        _ => false,
+    }
+}
+
+fn is_chat(packet: &Packet) -> bool {
+    match packet {
+        Packet::ChatMessage(chat_message) => !chat_message.message.starts_with('/'),
+        _ => false,
     }
 }
 
+fn is_respawn(packet: &Packet) -> bool {
+    match packet {
+        Packet::ClientStatus(client_status) => client_status.action == PERFORM_RESPAWN,
+        _ => false,
+    }
+}
+
+// Rounds down rather than towards zero so that maps at negative coordinates line up
+fn extract_map_position(packet: Packet, dimension: Dimension) -> Option<Position> {
+    extract_player_position(packet).map(|position| map_position(position, dimension))
+}
+
 fn extract_player_position(packet: Packet) -> Option<PlayerPosition> {
     match packet {
         Packet::PlayerPosition(packet) => Some(PlayerPosition {
@@ -145,84 +1726,575 @@ fn extract_player_position(packet: Packet) -> Option<PlayerPosition> {
     }
 }
 
+// The map a packet is headed for, and the peer that owns it if it isn't ours. Unplaced anchors
+// aren't headed anywhere yet
+fn map_span(maps: &[Map], map_index: usize) -> Span {
+    match maps.get(map_index) {
+        Some(map) => trace_span!(
+            "map",
+            map_index,
+            peer = %map
+                .peer_connection
+                .as_ref()
+                .map_or(String::from("local"), |connection| connection.peer.to_string())
+        ),
+        None => trace_span!("map", map_index = "unplaced"),
+    }
+}
+
+// An anchor is pending while its connection to the peer is being established in the background.
+// Packets from the player are held until it's ready so they aren't routed to the wrong map
 #[derive(Debug, Clone)]
 struct Anchor {
     map_index: usize,
     conn_id: Option<Uuid>,
+    pending: bool,
+    buffered_packets: Vec<Packet>,
+    positions: DeltaEncoder,
+    // How many times in a row connecting to the map's peer has failed
+    failures: u32,
 }
 
 impl Anchor {
-    pub fn connect<M: Messenger, P: PlayerState>(
+    pub fn local(map_index: usize) -> Anchor {
+        Anchor {
+            map_index,
+            conn_id: None,
+            pending: false,
+            buffered_packets: Vec::new(),
+            positions: DeltaEncoder::default(),
+            failures: 0,
+        }
+    }
+
+    // Not on any map yet, so the next position packet anchors the player wherever they are
+    pub fn unplaced() -> Anchor {
+        Anchor::local(usize::MAX)
+    }
+
+    // Players on a map whose peer isn't connected are routed locally until it is. Proxies have no
+    // map to route them on, so they're left unplaced to be anchored again the next time they move
+    pub fn disconnected(map_index: usize, local_map: bool) -> Anchor {
+        if local_map {
+            Anchor::local(map_index)
+        } else {
+            Anchor::unplaced()
+        }
+    }
+
+    pub fn pending(map_index: usize) -> Anchor {
+        Anchor {
+            pending: true,
+            ..Anchor::local(map_index)
+        }
+    }
+
+    pub fn buffer(&mut self, packet: Packet) {
+        if self.buffered_packets.len() < MAX_BUFFERED_ANCHOR_PACKETS {
+            self.buffered_packets.push(packet);
+        } else {
+            trace!(
+                "Anchor buffer full, dropping packet {:?}",
+                packet.debug_print_type()
+            );
+        }
+    }
+
+    // Connects to the peer on a separate thread, notifying patchwork state once the anchor is
+    // ready (or the connection failed) so that the routing loop is never blocked on the network
+    pub fn connect<M: 'static + Messenger + Send, PA: 'static + PatchworkState + Send>(
         peer: Peer,
         local_conn_id: Uuid,
         map_index: usize,
-        x_origin: i32,
+        origin: Position,
         messenger: M,
-        player_state: P,
-    ) -> Result<Anchor, io::Error> {
-        let conn_id = Uuid::new_v4();
-        let stream = server::new_connection(peer.address.clone(), peer.port)?;
-        messenger.new_connection(conn_id, stream.try_clone().unwrap());
-        messenger.update_translation(conn_id, Map::new(Position { x: x_origin, z: 0 }, 0));
-        messenger.send_packet(
-            conn_id,
-            Packet::Handshake(packet::Handshake {
-                protocol_version: 404,
-                server_address: String::from(""), //Neither of these fields are actually used
-                server_port: 0,
-                next_state: 4,
-            }),
-        );
-        player_state.cross_border(local_conn_id, conn_id);
-        Ok(Anchor {
-            map_index,
-            conn_id: Some(conn_id),
-        })
+        patchwork_state: PA,
+        uuids: UuidSource,
+    ) {
+        instance::spawn("anchor-connect", move || {
+            let connected = server::connect_with_retry(
+                peer.address.clone(),
+                peer.port,
+                &server::ANCHOR_RETRY_POLICY,
+            )
+            .and_then(|mut stream| {
+                let challenge = server::request_peer_state(&mut stream, 4)?;
+                Ok((stream, challenge))
+            });
+            let (stream, challenge) = match connected {
+                Ok(connected) => connected,
+                Err(e) => {
+                    trace!("Failed to connect anchor to peer {:?}: {:?}", peer, e);
+                    patchwork_state
+                        .anchor_failed(local_conn_id, map_index)
+                        .or_log();
+                    return;
+                }
+            };
+            let conn_id = uuids.next();
+            messenger
+                .new_connection(conn_id, stream, ConnectionClass::PeerLink)
+                .or_log();
+            messenger
+                .update_translation(conn_id, Map::new(origin, 0))
+                .or_log();
+            patchwork_state
+                .anchor_ready(local_conn_id, map_index, conn_id, challenge)
+                .or_log();
+        });
     }
 
     pub fn disconnect<M: Messenger>(&self, messenger: M) {
         if let Some(conn_id) = self.conn_id {
-            messenger.close(conn_id);
+            messenger.close(conn_id).or_log();
         }
     }
 }
 
+// A UUID lookup we've asked our peers about, which is answered as soon as one of them has the
+// entity or all of them have said they don't. Peers that go before answering leave it unanswered,
+// so queries are dropped once they're old enough that whoever asked has given up
+#[derive(Debug, Clone)]
+struct PendingEntityQuery {
+    reply: Sender<EntityOwner>,
+    asked_at: Instant,
+    // The connections to the peers we're still waiting to hear from
+    waiting_on: HashSet<Uuid>,
+}
+
+// A map we're splitting onto a recruited peer, waiting for the peer to connect and then to adopt
+// the half it's handed
+#[derive(Debug, Clone)]
+struct PendingSplit {
+    topology: Topology,
+    // Which way the recruit's map is from ours, one of x and z being 0
+    seam: (i32, i32),
+    handed_over: bool,
+}
+
 #[derive(Debug, Clone)]
 struct Patchwork {
     pub maps: Vec<Map>,
     pub player_anchors: HashMap<Uuid, Anchor>,
+    // Players who've been through a portal or respawned out of one. Everyone else is in the
+    // overworld
+    pub player_dimensions: HashMap<Uuid, Dimension>,
+    // Heartbeats sent to each connected peer map that have not been answered yet
+    pub missed_heartbeats: HashMap<usize, u32>,
+    // When the last heartbeat went out to each connected peer map, and how long the last answered
+    // one took to come back
+    pub heartbeats_sent: HashMap<usize, Instant>,
+    pub link_latencies: HashMap<usize, Duration>,
+    // The peer responsible for each map other than our own, whether it's connected or not
+    pub map_peers: HashMap<usize, Peer>,
+    // Names given to maps by an imported topology
+    pub map_names: HashMap<usize, String>,
+    // How maps are generated, for the ones whose owners have told us through gossip or a topology
+    pub map_seeds: HashMap<usize, Seed>,
+    pub pending_splits: HashMap<usize, PendingSplit>,
+    pub pending_entity_queries: HashMap<i64, PendingEntityQuery>,
+    pub next_entity_query_id: i64,
+    // Whether map 0 started out as ours. Proxies' map 0 is just the first peer map they heard of
+    pub local_map: bool,
 }
 
 impl Patchwork {
-    pub fn new() -> Patchwork {
+    pub fn new(local_map: bool) -> Patchwork {
         let mut patchwork = Patchwork {
             maps: Vec::new(),
             player_anchors: HashMap::new(),
+            player_dimensions: HashMap::new(),
+            missed_heartbeats: HashMap::new(),
+            heartbeats_sent: HashMap::new(),
+            link_latencies: HashMap::new(),
+            map_peers: HashMap::new(),
+            map_names: HashMap::new(),
+            map_seeds: HashMap::new(),
+            pending_splits: HashMap::new(),
+            pending_entity_queries: HashMap::new(),
+            next_entity_query_id: 0,
+            local_map,
         };
-        patchwork.create_local_map();
+        if local_map {
+            patchwork.create_local_map();
+        }
         patchwork
     }
 
+    // Our map's entities share entity id block 0 with our players
     pub fn create_local_map(&mut self) {
-        self.maps
-            .push(Map::new(self.next_position(), self.next_entity_id_block()));
+        self.maps.push(Map::new(self.next_position(), 0));
     }
 
-    pub fn position_map_index(self, position: Position) -> usize {
-        self.maps
+    pub fn free_neighbour(&self, map_index: usize) -> Option<Position> {
+        let position = self.maps[map_index].position;
+        vec![(1, 0), (-1, 0), (0, 1), (0, -1)]
             .into_iter()
-            .position(|map| map.position == position)
-            .expect("Could not find map for position")
+            .map(|(x, z)| Position {
+                x: position.x + x,
+                z: position.z + z,
+                ..position
+            })
+            .find(|neighbour| self.find_map_index(*neighbour).is_none())
     }
 
-    pub fn connect_map<M: Messenger + Clone>(
+    // Which ways around our map, in maps, there's another map to share a seam with
+    pub fn neighbours(&self) -> Vec<(i32, i32)> {
+        if !self.local_map {
+            return Vec::new();
+        }
+        let origin = self.maps[0].position;
+        (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| (x, z)))
+            .filter(|&(x, z)| {
+                (x, z) != (0, 0)
+                    && self
+                        .find_map_index(Position {
+                            x: origin.x + x,
+                            z: origin.z + z,
+                            ..origin
+                        })
+                        .is_some()
+            })
+            .collect()
+    }
+
+    pub fn find_map_index(&self, position: Position) -> Option<usize> {
+        self.maps.iter().position(|map| map.position == position)
+    }
+
+    pub fn player_dimension(&self, conn_id: Uuid) -> Dimension {
+        self.player_dimensions
+            .get(&conn_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // The dimension our own map's in, where the entities we look after are
+    pub fn local_dimension(&self) -> Dimension {
+        self.maps
+            .first()
+            .map_or(Dimension::Overworld, |map| map.position.dimension)
+    }
+
+    pub fn position_map_index(&self, position: Position) -> Result<usize, PatchworkError> {
+        self.find_map_index(position)
+            .ok_or(PatchworkError::NoMap(position))
+    }
+
+    pub fn connect_map<M: Messenger + Clone, A: PeerAuth>(
         &mut self,
         map_index: usize,
         peer_connection: PeerConnection,
+        challenge: u128,
+        local_peer: &Peer,
         messenger: M,
+        peer_auth: &A,
     ) {
+        let conn_id = peer_connection.conn_id;
+        authenticate(conn_id, 6, challenge, &messenger, peer_auth);
         self.maps[map_index].peer_connection = Some(peer_connection);
-        self.maps[map_index].report(messenger);
+        self.missed_heartbeats.insert(map_index, 0);
+        self.maps[map_index].report(messenger.clone());
+        // Tell the peer where we put its map so it can lay ours out the same way. Proxies keep
+        // quiet, or the peer would add a map for us that players could be sent to
+        if !self.local_map {
+            return;
+        }
+        self.propose_position(map_index, local_peer, &messenger);
+    }
+
+    fn propose_position<M: Messenger>(&self, map_index: usize, local_peer: &Peer, messenger: &M) {
+        let map = &self.maps[map_index];
+        let peer_connection = match &map.peer_connection {
+            Some(peer_connection) => peer_connection,
+            None => return,
+        };
+        messenger
+            .send_packet(
+                peer_connection.conn_id,
+                Packet::MapPositionProposal(packet::MapPositionProposal {
+                    peer_address: local_peer.address.clone(),
+                    peer_port: local_peer.port,
+                    x: map.position.x,
+                    z: map.position.z,
+                    dimension: self.local_dimension().id(),
+                    entity_id_block: map.entity_id_block,
+                }),
+            )
+            .or_log();
+    }
+
+    // Moves a peer's map to a new position, pointing translation for its connection at the new
+    // origin. Anchors to the old position are dropped and re-established as players move
+    pub fn move_map<M: Messenger + Clone, PP: PacketProcessor, P: PlayerState>(
+        &mut self,
+        map_index: usize,
+        position: Position,
+        messenger: M,
+        inbound_packet_processor: PP,
+        player_state: P,
+    ) {
+        trace!(
+            "Moving map {:?} from {:?} to {:?}",
+            map_index,
+            self.maps[map_index].position,
+            position
+        );
+        self.maps[map_index].position = position;
+        if let Some(peer_connection) = &self.maps[map_index].peer_connection {
+            inbound_packet_processor
+                .set_translation_data(
+                    peer_connection.conn_id,
+                    vec![
+                        TranslationUpdates::XOrigin(position.x),
+                        TranslationUpdates::ZOrigin(position.z),
+                    ],
+                )
+                .or_log();
+        }
+        self.release_anchors(map_index, messenger, player_state);
+    }
+
+    // Gives a peer's map a new entity id block, pointing translation for its connection at it.
+    // Anchored players knew the map's entities by their old ids, so they're dropped and
+    // re-established as players move
+    pub fn reblock_map<M: Messenger + Clone, PP: PacketProcessor, P: PlayerState>(
+        &mut self,
+        map_index: usize,
+        entity_id_block: i32,
+        messenger: M,
+        inbound_packet_processor: PP,
+        player_state: P,
+    ) {
+        trace!(
+            "Moving map {:?} from entity id block {:?} to {:?}",
+            map_index,
+            self.maps[map_index].entity_id_block,
+            entity_id_block
+        );
+        self.maps[map_index].entity_id_block = entity_id_block;
+        self.maps[map_index].entity_ids.clear();
+        if let Some(peer_connection) = &self.maps[map_index].peer_connection {
+            inbound_packet_processor
+                .set_translation_data(
+                    peer_connection.conn_id,
+                    vec![TranslationUpdates::EntityIdBlock(entity_id_block)],
+                )
+                .or_log();
+        }
+        self.release_anchors(map_index, messenger, player_state);
+    }
+
+    pub fn peer_map_index(&self, peer: &Peer) -> Option<usize> {
+        self.map_peers
+            .iter()
+            .find(|(_, map_peer)| *map_peer == peer)
+            .map(|(map_index, _)| *map_index)
+    }
+
+    pub fn connection_map_index(&self, conn_id: Uuid) -> Option<usize> {
+        self.maps.iter().position(|map| match &map.peer_connection {
+            Some(peer_connection) => peer_connection.conn_id == conn_id,
+            None => false,
+        })
+    }
+
+    // Sends a heartbeat to every connected peer, returning the indices of maps whose peers have
+    // missed too many heartbeats in a row
+    // Gauges are only brought up to date every heartbeat, rather than on every packet that moves
+    // a player
+    pub fn publish_metrics(&self) {
+        let mut map_players = BTreeMap::new();
+        self.player_anchors
+            .values()
+            .filter_map(|anchor| self.maps.get(anchor.map_index))
+            .for_each(|map| {
+                *map_players
+                    .entry((map.position.x, map.position.z))
+                    .or_insert(0) += 1
+            });
+        metrics::set_map_players(map_players);
+        metrics::maps_described(self.describe_maps());
+        metrics::set_anchors(
+            self.player_anchors
+                .values()
+                .filter(|anchor| anchor.conn_id.is_some())
+                .count(),
+        );
+    }
+
+    pub fn check_heartbeats<M: Messenger>(&mut self, messenger: M) -> Vec<usize> {
+        let mut failed_maps = Vec::new();
+        for (map_index, map) in self.maps.iter().enumerate() {
+            if let Some(peer_connection) = &map.peer_connection {
+                let missed = self.missed_heartbeats.entry(map_index).or_insert(0);
+                if *missed >= PEER_HEARTBEAT_MISS_THRESHOLD {
+                    failed_maps.push(map_index);
+                } else {
+                    *missed += 1;
+                    self.heartbeats_sent.insert(map_index, Instant::now());
+                    messenger
+                        .send_packet(
+                            peer_connection.conn_id,
+                            Packet::PeerHeartbeat(packet::PeerHeartbeat {
+                                id: map_index as i64,
+                            }),
+                        )
+                        .or_log();
+                }
+            }
+        }
+        failed_maps
+    }
+
+    pub fn describe_maps(&self) -> Vec<MapDescription> {
+        self.maps
+            .iter()
+            .enumerate()
+            .map(|(map_index, map)| MapDescription {
+                position: map.position,
+                name: self.map_names.get(&map_index).cloned(),
+                owner: self.map_peers.get(&map_index).cloned(),
+                connected: map.peer_connection.is_some()
+                    || !self.map_peers.contains_key(&map_index),
+                latency: self.link_latencies.get(&map_index).copied(),
+                entity_id_block: map.entity_id_block,
+            })
+            .collect()
+    }
+
+    pub fn heartbeat_ack(&mut self, conn_id: Uuid) {
+        if let Some(map_index) = self.connection_map_index(conn_id) {
+            self.missed_heartbeats.insert(map_index, 0);
+            if let Some(sent) = self.heartbeats_sent.get(&map_index) {
+                self.link_latencies.insert(map_index, sent.elapsed());
+            }
+        }
+    }
+
+    // Marks the map as unavailable, moves any players anchored there back to local routing and
+    // starts reconnecting to the peer in the background
+    pub fn fail_map<
+        M: 'static + Messenger + Send + Clone,
+        PP: 'static + PacketProcessor + Send + Clone,
+        P: PlayerState,
+    >(
+        &mut self,
+        map_index: usize,
+        messenger: M,
+        inbound_packet_processor: PP,
+        player_state: P,
+        patchwork_state: Sender<Operations>,
+    ) {
+        let peer_connection = match self.maps[map_index].peer_connection.take() {
+            Some(peer_connection) => peer_connection,
+            None => return,
+        };
+        warn!(
+            "Peer {:?} missed {:?} heartbeats, marking map {:?} as unavailable",
+            peer_connection.peer, PEER_HEARTBEAT_MISS_THRESHOLD, map_index
+        );
+        self.missed_heartbeats.remove(&map_index);
+        self.heartbeats_sent.remove(&map_index);
+        self.link_latencies.remove(&map_index);
+        messenger.close(peer_connection.conn_id).or_log();
+        self.release_anchors(map_index, messenger.clone(), player_state);
+        self.maps[map_index].connect(
+            messenger,
+            inbound_packet_processor,
+            peer_connection.peer,
+            patchwork_state,
+            map_index,
+        );
+    }
+
+    // Points a map at a new peer. Players anchored to the old owner are moved off the map and get
+    // anchored to the new one the next time they move
+    pub fn replace_map_owner<
+        M: 'static + Messenger + Send + Clone,
+        PP: 'static + PacketProcessor + Send + Clone,
+        P: PlayerState,
+    >(
+        &mut self,
+        map_index: usize,
+        peer: Peer,
+        messenger: M,
+        inbound_packet_processor: PP,
+        player_state: P,
+        patchwork_state: Sender<Operations>,
+    ) {
+        if let Some(peer_connection) = self.maps[map_index].peer_connection.take() {
+            messenger.close(peer_connection.conn_id).or_log();
+        }
+        self.missed_heartbeats.remove(&map_index);
+        self.heartbeats_sent.remove(&map_index);
+        self.link_latencies.remove(&map_index);
+        self.release_anchors(map_index, messenger.clone(), player_state);
+        if map_index == 0 {
+            // Our own players are on this map too and will all need anchoring to the new owner,
+            // whose entities get a fresh block so they can't collide with our players' ids
+            self.player_anchors
+                .retain(|_, anchor| anchor.map_index != 0);
+            self.maps[0].entity_id_block = self.next_entity_id_block();
+        }
+        self.map_peers.insert(map_index, peer.clone());
+        self.maps[map_index].connect(
+            messenger,
+            inbound_packet_processor,
+            peer,
+            patchwork_state,
+            map_index,
+        );
+    }
+
+    // The map keeps its position and entity id block so other indices stay valid, but it is no
+    // longer connected, gossiped or reconnected to
+    pub fn remove_peer_map<M: Messenger + Clone, P: PlayerState>(
+        &mut self,
+        peer: Peer,
+        messenger: M,
+        player_state: P,
+    ) {
+        let map_index = match self.peer_map_index(&peer) {
+            Some(map_index) => map_index,
+            None => return,
+        };
+        self.map_peers.remove(&map_index);
+        self.missed_heartbeats.remove(&map_index);
+        self.heartbeats_sent.remove(&map_index);
+        self.link_latencies.remove(&map_index);
+        if let Some(peer_connection) = self.maps[map_index].peer_connection.take() {
+            messenger.close(peer_connection.conn_id).or_log();
+        }
+        self.release_anchors(map_index, messenger, player_state);
+    }
+
+    // Moves every player anchored (or being anchored) to the map back to local routing
+    fn release_anchors<M: Messenger + Clone, P: PlayerState>(
+        &mut self,
+        map_index: usize,
+        messenger: M,
+        player_state: P,
+    ) {
+        let local_map = self.local_map;
+        self.player_anchors
+            .iter_mut()
+            .filter(|(_, anchor)| {
+                anchor.map_index == map_index && (anchor.conn_id.is_some() || anchor.pending)
+            })
+            .for_each(|(conn_id, anchor)| {
+                trace!("Routing conn_id {:?} back to local", conn_id);
+                anchor.disconnect(messenger.clone());
+                if anchor.conn_id.is_some() {
+                    player_state.reintroduce(*conn_id).or_log();
+                }
+                *anchor = Anchor::disconnected(map_index, local_map);
+            });
+    }
+
+    pub fn has_peer(&self, peer: &Peer) -> bool {
+        self.map_peers.values().any(|map_peer| map_peer == peer)
     }
 
     pub fn add_peer_map<
@@ -231,12 +2303,15 @@ impl Patchwork {
     >(
         &mut self,
         peer: Peer,
+        position: Position,
+        entity_id_block: i32,
         messenger: M,
         inbound_packet_processor: PP,
         patchwork_state: Sender<Operations>,
     ) {
-        let map = Map::new(self.next_position(), self.next_entity_id_block());
+        let map = Map::new(position, entity_id_block);
         self.maps.push(map.clone());
+        self.map_peers.insert(self.maps.len() - 1, peer.clone());
         map.connect(
             messenger,
             inbound_packet_processor,
@@ -246,20 +2321,351 @@ impl Patchwork {
         );
     }
 
+    // Our own map belongs to us unless it has been handed off to a peer
+    fn map_owner(&self, map_index: usize, local_peer: &Peer) -> Option<Peer> {
+        match self.map_peers.get(&map_index) {
+            Some(peer) => Some(peer.clone()),
+            None if map_index == 0 => Some(local_peer.clone()),
+            None => None,
+        }
+    }
+
+    // Every map we know of, including our own
+    pub fn gossip(&self, local_peer: Peer) -> Vec<GossipedMap> {
+        self.maps
+            .iter()
+            .enumerate()
+            .filter_map(|(map_index, map)| {
+                self.map_owner(map_index, &local_peer)
+                    .map(|peer| GossipedMap {
+                        peer,
+                        position: map.position,
+                        seed: self.map_seeds.get(&map_index).cloned(),
+                    })
+            })
+            .collect()
+    }
+
+    // How far gossip from a peer is shifted from our layout, going by the first map in it that we
+    // know too
+    fn gossip_offset(&self, maps: &[GossipedMap], local_peer: &Peer) -> Option<(i32, i32)> {
+        maps.iter().find_map(|map| {
+            let map_index = if map.peer == *local_peer && self.local_map {
+                0
+            } else {
+                self.peer_map_index(&map.peer)?
+            };
+            let ours = self.maps[map_index].position;
+            Some((ours.x - map.position.x, ours.z - map.position.z))
+        })
+    }
+
+    // Every map we know the owner of, with names for the ones that weren't given one
+    pub fn topology(&self, local_peer: Peer) -> Topology {
+        Topology {
+            maps: self
+                .maps
+                .iter()
+                .enumerate()
+                .filter_map(|(map_index, map)| {
+                    self.map_owner(map_index, &local_peer)
+                        .map(|owner| TopologyMap {
+                            name: self
+                                .map_names
+                                .get(&map_index)
+                                .cloned()
+                                .unwrap_or_else(|| format!("map-{}", map_index)),
+                            owner,
+                            position: map.position,
+                            seed: self.map_seeds.get(&map_index).cloned(),
+                        })
+                })
+                .collect(),
+        }
+    }
+
     pub fn report<M: Messenger + Clone>(self, messenger: M) {
         self.maps
             .into_iter()
             .for_each(|map| map.report(messenger.clone()));
     }
 
-    // get the next block of size 1000 entity ids assigned to this map
+    // Every map is given its own block of entity ids, so an id's block tells us which map it's on
+    pub fn entity_id_map_index(&self, entity_id: i32) -> Option<usize> {
+        let entity_id_block = entity_id / ENTITY_ID_BLOCK_SIZE;
+        self.maps
+            .iter()
+            .position(|map| map.entity_id_block == entity_id_block)
+    }
+
+    pub fn entity_id_owner(&self, entity_id: i32) -> EntityOwner {
+        let map_index = match self.entity_id_map_index(entity_id) {
+            Some(map_index) => map_index,
+            None => {
+                warn!(
+                    "Entity id {:?} is outside every known entity id block",
+                    entity_id
+                );
+                return EntityOwner::Unknown;
+            }
+        };
+        match (&self.maps[map_index].peer_connection, map_index) {
+            (Some(peer_connection), _) => EntityOwner::Peer {
+                peer: peer_connection.peer.clone(),
+                entity_id,
+            },
+            (None, 0) => EntityOwner::Local { entity_id },
+            (None, _) => match self.map_peers.get(&map_index) {
+                Some(peer) => EntityOwner::Peer {
+                    peer: peer.clone(),
+                    entity_id,
+                },
+                None => EntityOwner::Unknown,
+            },
+        }
+    }
+
+    pub fn ask_peers_for_entity<M: Messenger>(
+        &mut self,
+        uuid: Uuid,
+        reply: Sender<EntityOwner>,
+        messenger: M,
+    ) {
+        let now = Instant::now();
+        self.pending_entity_queries.retain(|_, query| {
+            now.duration_since(query.asked_at) < Duration::from_secs(ENTITY_OWNER_QUERY_TIMEOUT)
+        });
+        let conn_ids: Vec<Uuid> = self
+            .maps
+            .iter()
+            .filter_map(|map| map.peer_connection.as_ref())
+            .map(|peer_connection| peer_connection.conn_id)
+            .collect();
+        if conn_ids.is_empty() {
+            let _ = reply.send(EntityOwner::Unknown);
+            return;
+        }
+        let query_id = self.next_entity_query_id;
+        self.next_entity_query_id += 1;
+        conn_ids.iter().for_each(|conn_id| {
+            messenger
+                .send_packet(
+                    *conn_id,
+                    Packet::EntityOwnerQuery(packet::EntityOwnerQuery {
+                        query_id,
+                        uuid: uuid.as_u128(),
+                    }),
+                )
+                .or_log()
+        });
+        self.pending_entity_queries.insert(
+            query_id,
+            PendingEntityQuery {
+                reply,
+                asked_at: now,
+                waiting_on: conn_ids.into_iter().collect(),
+            },
+        );
+    }
+
+    pub fn entity_owner_reply(&mut self, conn_id: Uuid, query_id: i64, entity_id: Option<i32>) {
+        let peer = self
+            .connection_map_index(conn_id)
+            .and_then(|map_index| self.maps[map_index].peer_connection.as_ref())
+            .map(|peer_connection| peer_connection.peer.clone());
+        let waiting_on = match self.pending_entity_queries.get_mut(&query_id) {
+            Some(query) => &mut query.waiting_on,
+            None => return,
+        };
+        if !waiting_on.remove(&conn_id) {
+            return;
+        }
+        let owner = match (entity_id, peer) {
+            (Some(entity_id), Some(peer)) => EntityOwner::Peer { peer, entity_id },
+            _ if waiting_on.is_empty() => EntityOwner::Unknown,
+            _ => return,
+        };
+        if let Some(query) = self.pending_entity_queries.remove(&query_id) {
+            let _ = query.reply.send(owner);
+        }
+    }
+
+    // get the next block of size 1000 entity ids assigned to this map. Block 0 is always our own,
+    // even on proxies, since our players' ids come from it
     fn next_entity_id_block(&self) -> i32 {
-        self.maps.len() as i32
+        self.maps
+            .iter()
+            .map(|map| map.entity_id_block + 1)
+            .max()
+            .unwrap_or(1)
     }
 
-    // For now, just line up all the maps in a row
+    // The block to give a peer's map, given the one it's given ours. Whichever's higher wins, moving
+    // on to the next block that's free here if that's taken. Blocks only ever go up, so two peers
+    // going back and forth settle on one that's free for both
+    fn settled_entity_id_block(&self, ours: i32, theirs: i32) -> i32 {
+        if theirs <= ours {
+            return ours;
+        }
+        (theirs..)
+            .find(|entity_id_block| self.entity_id_block_free(*entity_id_block))
+            .unwrap_or(ours)
+    }
+
+    // Block 0 is our players', so it's never free for a peer's map
+    fn entity_id_block_free(&self, entity_id_block: i32) -> bool {
+        entity_id_block > 0
+            && self
+                .maps
+                .iter()
+                .all(|map| map.entity_id_block != entity_id_block)
+    }
+
+    // For now, just line up all the maps in a row after the last one
     fn next_position(&self) -> Position {
-        let len = self.maps.len() as i32;
-        Position { x: len, z: 0 }
+        let x = self
+            .maps
+            .iter()
+            .map(|map| map.position.x + 1)
+            .max()
+            .unwrap_or(0);
+        Position {
+            x,
+            z: 0,
+            dimension: Dimension::Overworld,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::MockMessenger;
+
+    // Each chunk's blocks are its index, so where a chunk ends up shows where it came from
+    fn numbered_chunks(size: i32) -> Vec<i32> {
+        (0..size * size)
+            .flat_map(|chunk| vec![chunk + 1; CHUNK_BLOCKS])
+            .collect()
+    }
+
+    fn chunk_order(block_ids: &[i32]) -> Vec<i32> {
+        block_ids
+            .chunks(CHUNK_BLOCKS)
+            .map(|chunk| chunk[0])
+            .collect()
+    }
+
+    #[test]
+    fn seam_halves_take_the_side_nearest_the_seam() {
+        assert!(on_seam_half(2, 1, 4));
+        assert!(!on_seam_half(1, 1, 4));
+        assert!(on_seam_half(1, -1, 4));
+        assert!(!on_seam_half(2, -1, 4));
+        assert!(on_seam_half(3, 0, 4));
+        // The middle of an odd map stays where it is
+        assert!(!on_seam_half(1, 1, 3));
+        assert!(!on_seam_half(1, -1, 3));
+        assert!(!on_seam_half(0, 1, 1));
+    }
+
+    #[test]
+    fn split_blocks_lay_the_seam_half_against_the_recruits_side() {
+        let block_ids = numbered_chunks(2);
+        // Chunks are in rows along x: 1 2 / 3 4
+        assert_eq!(
+            chunk_order(&split_blocks(&block_ids, (1, 0), 2)),
+            vec![2, 0, 4, 0]
+        );
+        assert_eq!(
+            chunk_order(&split_blocks(&block_ids, (-1, 0), 2)),
+            vec![0, 1, 0, 3]
+        );
+        assert_eq!(
+            chunk_order(&split_blocks(&block_ids, (0, 1), 2)),
+            vec![3, 4, 0, 0]
+        );
+        assert_eq!(
+            chunk_order(&split_blocks(&block_ids, (0, -1), 2)),
+            vec![0, 0, 1, 2]
+        );
+        assert_eq!(block_ids, numbered_chunks(2));
+    }
+
+    #[test]
+    fn split_blocks_leave_the_middle_of_odd_maps() {
+        let block_ids = numbered_chunks(3);
+        // 1 2 3 / 4 5 6 / 7 8 9
+        assert_eq!(
+            chunk_order(&split_blocks(&block_ids, (1, 0), 3)),
+            vec![3, 0, 0, 6, 0, 0, 9, 0, 0]
+        );
+        assert_eq!(
+            chunk_order(&split_blocks(&block_ids, (0, -1), 3)),
+            vec![0, 0, 0, 0, 0, 0, 1, 2, 3]
+        );
+        assert_eq!(seam_half_chunks((1, 0), 3), vec![2, 5, 8]);
+        assert_eq!(seam_half_chunks((0, -1), 3), vec![0, 1, 2]);
+        assert!(seam_half_chunks((1, 0), 1).is_empty());
+    }
+
+    // Our own map with a connected peer map on either side
+    fn connected_patchwork() -> (Patchwork, Vec<(Peer, Uuid)>) {
+        let mut patchwork = Patchwork::new(true);
+        let peers: Vec<(Peer, Uuid)> = (1..=2)
+            .map(|port| {
+                (
+                    Peer {
+                        address: String::from("10.0.0.2"),
+                        port,
+                    },
+                    Uuid::new_v4(),
+                )
+            })
+            .collect();
+        for (peer, conn_id) in &peers {
+            let mut map = Map::new(patchwork.next_position(), patchwork.next_entity_id_block());
+            map.peer_connection = Some(PeerConnection {
+                peer: peer.clone(),
+                conn_id: *conn_id,
+            });
+            patchwork.maps.push(map);
+        }
+        (patchwork, peers)
+    }
+
+    #[test]
+    fn uuid_lookups_end_with_the_first_peer_that_has_the_entity() {
+        let (mut patchwork, peers) = connected_patchwork();
+        let (reply_sender, reply_receiver) = channel();
+        patchwork.ask_peers_for_entity(Uuid::new_v4(), reply_sender, MockMessenger::new());
+        let query_id = patchwork.next_entity_query_id - 1;
+
+        patchwork.entity_owner_reply(peers[1].1, query_id, Some(2042));
+        assert_eq!(
+            reply_receiver.try_recv(),
+            Ok(EntityOwner::Peer {
+                peer: peers[1].0.clone(),
+                entity_id: 2042
+            })
+        );
+        assert!(patchwork.pending_entity_queries.is_empty());
+    }
+
+    #[test]
+    fn uuid_lookups_end_once_every_peer_has_said_it_doesnt_have_the_entity() {
+        let (mut patchwork, peers) = connected_patchwork();
+        let (reply_sender, reply_receiver) = channel();
+        patchwork.ask_peers_for_entity(Uuid::new_v4(), reply_sender, MockMessenger::new());
+        let query_id = patchwork.next_entity_query_id - 1;
+
+        patchwork.entity_owner_reply(peers[0].1, query_id, None);
+        // Saying so twice doesn't count for the other peer
+        patchwork.entity_owner_reply(peers[0].1, query_id, None);
+        assert!(reply_receiver.try_recv().is_err());
+
+        patchwork.entity_owner_reply(peers[1].1, query_id, None);
+        assert_eq!(reply_receiver.try_recv(), Ok(EntityOwner::Unknown));
+        assert!(patchwork.pending_entity_queries.is_empty());
     }
 }
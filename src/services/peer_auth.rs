@@ -0,0 +1,243 @@
+use super::config::{Config, PeerKey};
+use super::constants::PEER_AUTH_MAX_CLOCK_SKEW;
+use super::identity::Identity;
+use super::instance::Queue;
+use super::interfaces::peer_auth::{Operations, PeerAuthToken};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Keys are rotated in two steps so the quilt never has to go down: a new key is first staged on
+// every node, which only makes it acceptable, then promoted on every node, which makes it the key
+// we sign with. Keys other than the promoted one stay acceptable until the grace period runs out.
+// With no keys at all, peers are not authenticated. Our instance id is signed along with everything
+// else, so that nobody can claim to be us without a key. So is a random challenge from whoever we're
+// connecting to, which they only accept once, so that credentials seen on the wire are no use to
+// anyone else
+pub fn start(
+    receiver: Queue<Operations>,
+    _sender: Sender<Operations>,
+    config: Config,
+    instance: Identity,
+) {
+    let mut key_ring = KeyRing::new(config.peer_keys);
+    // The challenge sent on each connection that's yet to answer it, and when it was sent
+    let mut challenges = HashMap::<Uuid, (u128, i64)>::new();
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Challenge(msg) => {
+                // Peers that never answer don't get to keep their challenge around
+                let now = now();
+                challenges.retain(|_, (_, sent_at)| now - *sent_at <= PEER_AUTH_MAX_CLOCK_SKEW);
+                let challenge = Uuid::new_v4().as_u128();
+                challenges.insert(msg.conn_id, (challenge, now));
+                let _ = msg.reply.send(challenge);
+            }
+            Operations::Sign(msg) => {
+                let _ = msg
+                    .reply
+                    .send(key_ring.sign(msg.next_state, msg.challenge, &instance));
+            }
+            Operations::Verify(msg) => {
+                let challenge = challenges
+                    .remove(&msg.conn_id)
+                    .map(|(challenge, _)| challenge);
+                let verified = key_ring.verify(&msg.token, challenge);
+                if !verified {
+                    warn!("Rejected peer credentials for key {:?}", msg.token.key_id);
+                }
+                let _ = msg.reply.send(verified);
+            }
+            Operations::Stage(msg) => {
+                info!("Staging peer key {:?}", msg.key.id);
+                key_ring.stage(msg.key);
+            }
+            Operations::Promote(msg) => {
+                info!(
+                    "Promoting peer key {:?}, retiring other keys in {:?}s",
+                    msg.key_id, msg.grace_period
+                );
+                if !key_ring.promote(&msg.key_id, msg.grace_period) {
+                    warn!("Cannot promote unknown peer key {:?}", msg.key_id);
+                }
+            }
+        }
+    }
+}
+
+struct Key {
+    key: PeerKey,
+    expires_at: Option<i64>,
+}
+
+// The first key is the one we sign with
+struct KeyRing {
+    keys: Vec<Key>,
+}
+
+impl KeyRing {
+    fn new(keys: Vec<PeerKey>) -> KeyRing {
+        KeyRing {
+            keys: keys
+                .into_iter()
+                .map(|key| Key {
+                    key,
+                    expires_at: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn sign(&mut self, next_state: i32, challenge: u128, instance: &Identity) -> PeerAuthToken {
+        self.expire();
+        let timestamp = now();
+        match self.keys.first() {
+            Some(key) => PeerAuthToken {
+                next_state,
+                key_id: key.key.id.clone(),
+                timestamp,
+                mac: hex::encode(
+                    mac(&key.key, next_state, timestamp, challenge, instance)
+                        .finalize()
+                        .into_bytes(),
+                ),
+                instance: instance.clone(),
+            },
+            None => PeerAuthToken {
+                next_state,
+                key_id: String::new(),
+                timestamp,
+                mac: String::new(),
+                instance: instance.clone(),
+            },
+        }
+    }
+
+    // Tokens have to answer the challenge we sent, there's nothing to check them against otherwise
+    fn verify(&mut self, token: &PeerAuthToken, challenge: Option<u128>) -> bool {
+        self.expire();
+        if self.keys.is_empty() {
+            return true;
+        }
+        let challenge = match challenge {
+            Some(challenge) => challenge,
+            None => return false,
+        };
+        if (now() - token.timestamp).abs() > PEER_AUTH_MAX_CLOCK_SKEW {
+            return false;
+        }
+        let key = match self.keys.iter().find(|key| key.key.id == token.key_id) {
+            Some(key) => key,
+            None => return false,
+        };
+        match hex::decode(&token.mac) {
+            Ok(tag) => mac(
+                &key.key,
+                token.next_state,
+                token.timestamp,
+                challenge,
+                &token.instance,
+            )
+            .verify_slice(&tag)
+            .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn stage(&mut self, key: PeerKey) {
+        self.keys.retain(|existing| existing.key.id != key.id);
+        self.keys.push(Key {
+            key,
+            expires_at: None,
+        });
+    }
+
+    fn promote(&mut self, key_id: &str, grace_period: u64) -> bool {
+        let index = match self.keys.iter().position(|key| key.key.id == key_id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let mut key = self.keys.remove(index);
+        key.expires_at = None;
+        let expires_at = now() + grace_period as i64;
+        self.keys
+            .iter_mut()
+            .for_each(|key| key.expires_at = Some(expires_at));
+        self.keys.insert(0, key);
+        true
+    }
+
+    fn expire(&mut self) {
+        let now = now();
+        self.keys.retain(|key| match key.expires_at {
+            Some(expires_at) => expires_at > now,
+            None => true,
+        });
+    }
+}
+
+fn mac(
+    key: &PeerKey,
+    next_state: i32,
+    timestamp: i64,
+    challenge: u128,
+    instance: &Identity,
+) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(key.secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(
+        format!(
+            "{}:{}:{}:{:032x}:{}",
+            key.id, next_state, timestamp, challenge, instance.id
+        )
+        .as_bytes(),
+    );
+    mac
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_only_answer_the_challenge_they_were_signed_for() {
+        let mut key_ring = KeyRing::new(vec![PeerKey {
+            id: String::from("k1"),
+            secret: String::from("secret"),
+        }]);
+        let instance = Identity {
+            id: Uuid::from_u128(1),
+            name: String::from("node-1"),
+        };
+        let token = key_ring.sign(6, 42, &instance);
+        assert!(key_ring.verify(&token, Some(42)));
+        // Replayed to a connection that was sent a different challenge, or none at all
+        assert!(!key_ring.verify(&token, Some(43)));
+        assert!(!key_ring.verify(&token, None));
+        assert!(!key_ring.verify(
+            &PeerAuthToken {
+                next_state: 4,
+                ..token.clone()
+            },
+            Some(42)
+        ));
+
+        // Without keys, peers aren't authenticated at all
+        let mut key_ring = KeyRing::new(Vec::new());
+        assert!(key_ring.verify(&token, None));
+    }
+}
@@ -0,0 +1,16 @@
+use super::constants::PEER_HEARTBEAT_PERIOD;
+use super::instance::Queue;
+use super::interfaces::patchwork::PatchworkState;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time;
+
+pub fn start<PA: PatchworkState>(receiver: Queue<i32>, _: Sender<i32>, patchwork_state: PA) {
+    while let Err(RecvTimeoutError::Timeout) =
+        receiver.recv_timeout(time::Duration::from_secs(PEER_HEARTBEAT_PERIOD))
+    {
+        if let Err(e) = patchwork_state.heartbeat() {
+            error!("Stopping heartbeats: {}", e);
+            break;
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use super::config::{Config, PeerRegistryConfig};
+use super::error::OrLog;
+use super::instance::Queue;
+use super::interfaces::patchwork::PatchworkState;
+use super::map::Peer;
+use super::server;
+use super::versioned;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time;
+
+// How long to wait on the Consul agent before giving up on a request, so a hung agent can't stop us
+// renewing our check
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+// Registers this node as a Consul service with a TTL check, then watches the passing instances of
+// that service and adds or removes peer maps as nodes join and leave the quilt. Nodes that stop
+// passing their check (including ones that crash) drop out of the healthy list on their own.
+// Consul is the only registry spoken to for now, etcd isn't supported
+pub fn start<PA: PatchworkState>(
+    receiver: Queue<i32>,
+    _: Sender<i32>,
+    patchwork_state: PA,
+    config: Config,
+    local_peer: Peer,
+) {
+    let registry = match config.peer_registry {
+        Some(registry) => registry,
+        None => return,
+    };
+    let local_peer = Peer {
+        address: registry
+            .advertise_address
+            .clone()
+            .unwrap_or(local_peer.address),
+        port: local_peer.port,
+    };
+    let service_id = format!(
+        "{}-{}-{}",
+        registry.service_name, local_peer.address, local_peer.port
+    );
+    let mut known_peers = HashSet::<Peer>::new();
+    let mut registered = false;
+
+    loop {
+        // Proxies have no map for other nodes to find, so they only look for theirs
+        if !config.local_map {
+            trace!("Not registering with peer registry, we have no map");
+        } else if !registered {
+            registered = match register(&registry, &service_id, &local_peer) {
+                Ok(()) => {
+                    info!("Registered with peer registry as {}", service_id);
+                    true
+                }
+                Err(e) => {
+                    warn!("Failed to register with peer registry: {}", e);
+                    false
+                }
+            };
+        } else if let Err(e) = pass_check(&registry, &service_id) {
+            // Consul forgets services whose agent restarted, so register again
+            warn!("Failed to renew peer registry check: {}", e);
+            registered = false;
+        }
+
+        match healthy_peers(&registry) {
+            Ok(peers) => {
+                let peers: HashSet<Peer> = peers
+                    .into_iter()
+                    .filter(|peer| *peer != local_peer)
+                    .collect();
+                peers.difference(&known_peers).for_each(|peer| {
+                    trace!("Peer {:?} joined the registry", peer);
+                    patchwork_state.new_map(peer.clone()).or_log();
+                });
+                known_peers.difference(&peers).for_each(|peer| {
+                    trace!("Peer {:?} left the registry", peer);
+                    patchwork_state.remove_map(peer.clone()).or_log();
+                });
+                known_peers = peers;
+            }
+            Err(e) => warn!("Failed to query peer registry: {}", e),
+        }
+
+        if let Err(RecvTimeoutError::Disconnected) =
+            receiver.recv_timeout(time::Duration::from_secs(registry.poll_period))
+        {
+            break;
+        }
+    }
+
+    // Shutting down, so leave the registry now rather than once our check goes critical
+    if registered {
+        match deregister(&registry, &service_id) {
+            Ok(()) => info!("Deregistered {} from peer registry", service_id),
+            Err(e) => warn!("Failed to deregister from peer registry: {}", e),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceRegistration {
+    #[serde(rename = "ID")]
+    id: String,
+    name: String,
+    address: String,
+    port: u16,
+    check: ServiceCheck,
+    meta: HashMap<String, String>,
+}
+
+// Where the registration's format version is kept in its metadata
+const FORMAT_VERSION_KEY: &str = "patchwork_format_version";
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceCheck {
+    #[serde(rename = "TTL")]
+    ttl: String,
+    deregister_critical_service_after: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HealthEntry {
+    service: HealthService,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HealthService {
+    address: String,
+    port: u16,
+    #[serde(default)]
+    meta: Option<HashMap<String, String>>,
+}
+
+fn register(
+    registry: &PeerRegistryConfig,
+    service_id: &str,
+    local_peer: &Peer,
+) -> Result<(), String> {
+    // Allow a couple of missed polls before the check goes critical
+    let ttl = registry.poll_period * 3;
+    let registration = ServiceRegistration {
+        id: String::from(service_id),
+        name: registry.service_name.clone(),
+        address: local_peer.address.clone(),
+        port: local_peer.port,
+        check: ServiceCheck {
+            ttl: format!("{}s", ttl),
+            deregister_critical_service_after: format!("{}s", ttl * 10),
+        },
+        meta: HashMap::from([(
+            String::from(FORMAT_VERSION_KEY),
+            versioned::PEER_REGISTRATION.version().to_string(),
+        )]),
+    };
+    http_request(
+        registry,
+        "PUT",
+        "/v1/agent/service/register",
+        Some(serde_json::to_string(&registration).unwrap()),
+    )?;
+    pass_check(registry, service_id)
+}
+
+fn deregister(registry: &PeerRegistryConfig, service_id: &str) -> Result<(), String> {
+    http_request(
+        registry,
+        "PUT",
+        &format!("/v1/agent/service/deregister/{}", service_id),
+        None,
+    )
+    .map(|_| ())
+}
+
+fn pass_check(registry: &PeerRegistryConfig, service_id: &str) -> Result<(), String> {
+    http_request(
+        registry,
+        "PUT",
+        &format!("/v1/agent/check/pass/service:{}", service_id),
+        None,
+    )
+    .map(|_| ())
+}
+
+fn healthy_peers(registry: &PeerRegistryConfig) -> Result<Vec<Peer>, String> {
+    let body = http_request(
+        registry,
+        "GET",
+        &format!("/v1/health/service/{}?passing", registry.service_name),
+        None,
+    )?;
+    let entries: Vec<HealthEntry> =
+        serde_json::from_str(&body).map_err(|e| format!("Invalid health response: {:?}", e))?;
+    Ok(entries
+        .into_iter()
+        .filter(|entry| {
+            let version = entry
+                .service
+                .meta
+                .as_ref()
+                .and_then(|meta| meta.get(FORMAT_VERSION_KEY))
+                .and_then(|version| version.parse().ok())
+                .unwrap_or(0);
+            if version > versioned::PEER_REGISTRATION.version() {
+                warn!(
+                    "Passing over {}:{}, which registered with a newer format (version {})",
+                    entry.service.address, entry.service.port, version
+                );
+            }
+            version <= versioned::PEER_REGISTRATION.version()
+        })
+        .map(|entry| Peer {
+            address: entry.service.address,
+            port: entry.service.port,
+        })
+        .collect())
+}
+
+// HTTP/1.0 so the agent closes the connection after the response and never chunks it. The agent
+// runs alongside us, so this never goes through the peer proxy
+fn http_request(
+    registry: &PeerRegistryConfig,
+    method: &str,
+    path: &str,
+    body: Option<String>,
+) -> Result<String, String> {
+    let mut stream = server::new_direct_connection_within(
+        registry.consul_address.clone(),
+        registry.consul_port,
+        REQUEST_TIMEOUT,
+    )
+    .map_err(|e| format!("{:?}", e))?;
+    let body = body.unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        registry.consul_address,
+        body.len(),
+        body
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("{:?}", e))?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| format!("{:?}", e))?;
+
+    let (head, body) = match response.find("\r\n\r\n") {
+        Some(index) => (&response[..index], &response[index + 4..]),
+        None => return Err(String::from("Malformed response")),
+    };
+    let status_line = head.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(String::from(body)),
+        _ => Err(format!(
+            "{} {} returned {}: {}",
+            method, path, status_line, body
+        )),
+    }
+}
@@ -1,83 +1,657 @@
-use super::constants::SERVER_MAX_CAPACITY;
+use super::advancements;
+use super::advancements::AdvancementStore;
+use super::config::Config;
+use super::constants::{
+    ANCHORED_PLAYER_ENTITY_ID_START, ITEM_PICKUP_DELAY, PLAYER_AUTOSAVE_PERIOD,
+    PLAYER_STATE_SHARDS, SERVER_MAX_CAPACITY, VOID_DEPTH,
+};
+use super::error::{OrLog, PatchworkError};
+use super::instance::{self, Queue};
+use super::interfaces::chat::ChatService;
+use super::interfaces::entity::{DroppedItem, EntityState};
+use super::interfaces::entity_ids::{EntityIdAllocator, EntityIdRange};
+use super::interfaces::interest::{EntityKind, InterestManager};
 use super::interfaces::messenger::{Messenger, SubscriberType};
-use super::interfaces::player::{Angle, Operations, Player, Position};
+use super::interfaces::player::{
+    spawn_position, AddSeam, Angle, Autosave, Delete, Find, FindConnection, Health, KickVisitor,
+    ListPlayers, Online, Operations, Player, PlayerState, Position, Positions, Report, SaveAll,
+    StatusResponse as StatusResponseOperation, Velocity, HOTBAR_END, HOTBAR_START,
+    MAIN_INVENTORY_START,
+};
+use super::map::{map_width, Dimension, Position as MapPosition};
+use super::message_log::MessageLog;
 use super::minecraft_types;
-use super::minecraft_types::float_to_angle;
+use super::minecraft_types::{float_to_angle, ItemStack, Location};
+use super::operator_store::OperatorStore;
 use super::packet::{
-    BorderCrossLogin, ClientboundPlayerPositionAndLook, DestroyEntities, EntityHeadLook,
-    EntityLookAndMove, JoinGame, Packet, PlayerInfo, SpawnPlayer, StatusResponse,
+    Advancements, BorderCrossLogin, ClientboundHeldItemChange, ClientboundPlayerPositionAndLook,
+    DestroyEntities, EntityHeadLook, EntityLookAndMove, EntityStatus, EntityVelocity, JoinGame,
+    Packet, PeerKickback, PlayerInfo, Respawn, ServerDifficulty, SetExperience, SetSlot,
+    SpawnPlayer, SpawnPosition, StatusResponse, UpdateHealth,
 };
+use super::player_store::PlayerStore;
 use std::collections::HashMap;
 
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use uuid::Uuid;
 
-pub fn start<M: Messenger + Clone>(
-    receiver: Receiver<Operations>,
-    _sender: Sender<Operations>,
+// Set on the gamemode byte of JoinGame to put the client in hardcore mode
+const HARDCORE_FLAG: u8 = 0x8;
+
+// The entity status that tells a player they're at permission level 0. Each level after is the next
+const OP_PERMISSION_LEVEL_0_STATUS: i8 = 24;
+
+// Items are thrown from this far above the player's feet, at this speed in blocks per tick, and
+// lifted a little on top of that
+const ITEM_THROW_HEIGHT: f64 = 1.3;
+const ITEM_THROW_SPEED: f64 = 0.3;
+const ITEM_THROW_LIFT: f64 = 0.1;
+
+// Every item stacks up to this many, as we don't know which ones stack less
+const MAX_STACK_SIZE: i32 = 64;
+
+// How far in from the edge of their own map players are put when they're pulled back onto it
+const PULL_BACK_MARGIN: f64 = 1.5;
+
+// Players are spread over a few shards by conn_id, each with its own event loop, so that one
+// player's expensive operation doesn't hold up movement for everyone else. Operations on a single
+// player go to the shard that player lives on, and anything about every player is asked of all of
+// them
+#[allow(clippy::too_many_arguments)]
+pub fn start<
+    M: 'static + Messenger + Clone + Send,
+    I: 'static + EntityIdAllocator + Clone + Send,
+    IM: 'static + InterestManager + Clone + Send,
+    E: 'static + EntityState + Clone + Send,
+    CH: 'static + ChatService + Clone + Send,
+>(
+    receiver: Queue<Operations>,
+    sender: Sender<Operations>,
     messenger: M,
+    entity_ids: I,
+    interest: IM,
+    entity_state: E,
+    chat: CH,
+    config: Config,
 ) {
-    let mut players = HashMap::<Uuid, Player>::new();
-    let mut entity_conn_ids = HashMap::<i32, Uuid>::new();
-    let mut entity_id = 0;
+    let shared = Arc::new(SharedState {
+        entity_conn_ids: Mutex::new(HashMap::new()),
+        advancement_store: Mutex::new(AdvancementStore::load(&config.advancements_file)),
+        player_store: PlayerStore::new(&config.players_directory),
+        operators: Mutex::new(OperatorStore::load(&config.operators_file)),
+    });
+    // Goes with this run of the service, should it be restarted
+    let running = Arc::new(());
+    let still_running = Arc::downgrade(&running);
+    instance::spawn("player-autosave", move || loop {
+        thread::sleep(Duration::from_secs(PLAYER_AUTOSAVE_PERIOD));
+        if still_running.upgrade().is_none() {
+            break;
+        }
+        sender.autosave().or_log();
+    });
+    let shards: Vec<Sender<ShardMessage>> = (0..PLAYER_STATE_SHARDS)
+        .map(|shard| {
+            let (shard_sender, shard_receiver) = channel();
+            let messenger = messenger.clone();
+            let entity_ids = entity_ids.clone();
+            let interest = interest.clone();
+            let entity_state = entity_state.clone();
+            let config = config.clone();
+            let chat = chat.clone();
+            let shared = shared.clone();
+            instance::spawn(&format!("player-shard-{}", shard), move || {
+                run_shard(
+                    shard_receiver,
+                    messenger,
+                    entity_ids,
+                    interest,
+                    entity_state,
+                    chat,
+                    config,
+                    shared,
+                )
+            });
+            shard_sender
+        })
+        .collect();
+    let mut message_log = config
+        .message_log_directory
+        .as_deref()
+        .and_then(|directory| MessageLog::create(directory, "player_state"));
 
     while let Ok(msg) = receiver.recv() {
-        handle_message(
-            msg,
-            &mut players,
-            &mut entity_conn_ids,
-            &mut entity_id,
-            messenger.clone(),
+        if let Some(message_log) = message_log.as_mut() {
+            message_log.record(&msg);
+        }
+        match msg {
+            Operations::Report(msg) => all_shards(&shards, || {
+                Operations::Report(Report {
+                    conn_id: msg.conn_id,
+                    span: msg.span.clone(),
+                })
+            }),
+            Operations::ListPlayers(msg) => all_shards(&shards, || {
+                Operations::ListPlayers(ListPlayers {
+                    conn_id: msg.conn_id,
+                    span: msg.span.clone(),
+                })
+            }),
+            Operations::Delete(msg) => {
+                chat.leave(msg.conn_id).or_log();
+                all_shards(&shards, || {
+                    Operations::Delete(Delete {
+                        conn_id: msg.conn_id,
+                        span: msg.span.clone(),
+                    })
+                })
+            }
+            Operations::Autosave(msg) => all_shards(&shards, || {
+                Operations::Autosave(Autosave {
+                    span: msg.span.clone(),
+                })
+            }),
+            Operations::SaveAll(msg) => {
+                let span = msg.span.clone();
+                gather(
+                    &shards,
+                    |reply| {
+                        ShardMessage::Operation(Operations::SaveAll(SaveAll {
+                            reply,
+                            span: span.clone(),
+                        }))
+                    },
+                    move |saved: Vec<usize>| {
+                        let _ = msg.reply.send(saved.into_iter().sum());
+                    },
+                )
+            }
+            Operations::Saved(msg) => {
+                let _ = msg.reply.send(shared.player_store.load(&msg.name));
+            }
+            Operations::SetOperator(msg) => {
+                let changed = shared.operators.lock().unwrap().set(&msg.name, msg.level);
+                if changed {
+                    info!("{} is now at permission level {}", msg.name, msg.level);
+                    shards.iter().for_each(|shard| {
+                        send_to_shard(shard, ShardMessage::LevelChanged(msg.name.clone()))
+                    });
+                }
+                let _ = msg.reply.send(changed);
+            }
+            Operations::AddSeam(msg) => all_shards(&shards, || {
+                Operations::AddSeam(AddSeam {
+                    conn_id: msg.conn_id,
+                    neighbour: msg.neighbour,
+                    span: msg.span.clone(),
+                })
+            }),
+            Operations::Positions(msg) => {
+                let span = msg.span.clone();
+                gather(
+                    &shards,
+                    |reply| {
+                        ShardMessage::Operation(Operations::Positions(Positions {
+                            reply,
+                            span: span.clone(),
+                        }))
+                    },
+                    move |positions: Vec<Vec<(Uuid, Position)>>| {
+                        let _ = msg.reply.send(positions.into_iter().flatten().collect());
+                    },
+                )
+            }
+            Operations::Online(msg) => {
+                let span = msg.span.clone();
+                gather(
+                    &shards,
+                    |reply| {
+                        ShardMessage::Operation(Operations::Online(Online {
+                            reply,
+                            span: span.clone(),
+                        }))
+                    },
+                    move |online: Vec<Vec<(Uuid, String, Position, Dimension)>>| {
+                        let _ = msg.reply.send(online.concat());
+                    },
+                )
+            }
+            Operations::Find(msg) => {
+                let uuid = msg.uuid;
+                let span = msg.span.clone();
+                gather(
+                    &shards,
+                    |reply| {
+                        ShardMessage::Operation(Operations::Find(Find {
+                            uuid,
+                            reply,
+                            span: span.clone(),
+                        }))
+                    },
+                    move |found: Vec<Option<i32>>| {
+                        let _ = msg.reply.send(found.into_iter().flatten().next());
+                    },
+                )
+            }
+            Operations::FindConnection(msg) => {
+                let uuid = msg.uuid;
+                let span = msg.span.clone();
+                gather(
+                    &shards,
+                    |reply| {
+                        ShardMessage::Operation(Operations::FindConnection(FindConnection {
+                            uuid,
+                            reply,
+                            span: span.clone(),
+                        }))
+                    },
+                    move |found: Vec<Option<Uuid>>| {
+                        let _ = msg.reply.send(found.into_iter().flatten().next());
+                    },
+                )
+            }
+            Operations::KickVisitor(msg) => {
+                let (name, reason, span) = (msg.name.clone(), msg.reason.clone(), msg.span.clone());
+                gather(
+                    &shards,
+                    |reply| {
+                        ShardMessage::Operation(Operations::KickVisitor(KickVisitor {
+                            name: name.clone(),
+                            reason: reason.clone(),
+                            reply,
+                            span: span.clone(),
+                        }))
+                    },
+                    move |found: Vec<bool>| {
+                        let _ = msg.reply.send(found.into_iter().any(|found| found));
+                    },
+                )
+            }
+            Operations::StatusResponse(msg) => {
+                let messenger = messenger.clone();
+                gather(
+                    &shards,
+                    ShardMessage::Roster,
+                    move |rosters: Vec<Vec<(Uuid, String)>>| {
+                        send_status_response(msg, rosters.concat(), &messenger)
+                    },
+                )
+            }
+            //When we get a message from a peer that comes from one of our anchored players we want
+            //to make sure they don't get the result packets.
+            Operations::BroadcastAnchoredEvent(msg) => {
+                let source = shared
+                    .entity_conn_ids
+                    .lock()
+                    .unwrap()
+                    .get(&msg.entity_id)
+                    .copied();
+                if let Some(conn_id) = source {
+                    trace!("Appending conn_id {:?} to anchored event", conn_id);
+                }
+                messenger
+                    .broadcast(msg.packet, source, SubscriberType::Local)
+                    .or_log();
+            }
+            Operations::New(msg) => {
+                chat.join(msg.conn_id, msg.player.name.clone()).or_log();
+                send_to_shard(
+                    &shards[msg.conn_id.as_u128() as usize % shards.len()],
+                    ShardMessage::Operation(Operations::New(msg)),
+                );
+            }
+            msg => {
+                let conn_id = player_conn_id(&msg);
+                send_to_shard(
+                    &shards[conn_id.as_u128() as usize % shards.len()],
+                    ShardMessage::Operation(msg),
+                );
+            }
+        }
+    }
+}
+
+// Held by the shards between them
+struct SharedState {
+    entity_conn_ids: Mutex<HashMap<i32, Uuid>>,
+    advancement_store: Mutex<AdvancementStore>,
+    player_store: PlayerStore,
+    operators: Mutex<OperatorStore>,
+}
+
+// Nearly everything sent to a shard is an operation, so boxing them to keep the odd roster
+// request small would cost more than it saves
+#[allow(clippy::large_enum_variant)]
+enum ShardMessage {
+    Operation(Operations),
+    // The names of every player on the shard, for status pings
+    Roster(Sender<Vec<(Uuid, String)>>),
+    // Someone's permission level changed, which they're told if they're on the shard
+    LevelChanged(String),
+}
+
+fn player_conn_id(msg: &Operations) -> Uuid {
+    match msg {
+        Operations::MoveAndLook(msg) => msg.conn_id,
+        Operations::AnchoredMoveAndLook(msg) => msg.conn_id,
+        Operations::CrossBorder(msg) => msg.local_conn_id,
+        Operations::Reintroduce(msg) => msg.conn_id,
+        Operations::Teleport(msg) => msg.conn_id,
+        Operations::PullBack(msg) => msg.conn_id,
+        Operations::Respawn(msg) => msg.conn_id,
+        Operations::HoldItem(msg) => msg.conn_id,
+        Operations::SetSlot(msg) => msg.conn_id,
+        Operations::DropItem(msg) => msg.conn_id,
+        Operations::ThrowItem(msg) => msg.conn_id,
+        Operations::PickUp(msg) => msg.conn_id,
+        Operations::PermissionLevel(msg) => msg.conn_id,
+        _ => unreachable!("Operation isn't about a single player"),
+    }
+}
+
+// Shards only stop when they panic, which takes the rest of player state down with them so that it's
+// restarted, rather than carrying on without that shard's players
+fn send_to_shard(shard: &Sender<ShardMessage>, msg: ShardMessage) {
+    if shard.send(msg).is_err() {
+        panic!("{}", PatchworkError::ServiceStopped("a player state shard"));
+    }
+}
+
+fn all_shards<F: Fn() -> Operations>(shards: &[Sender<ShardMessage>], operation: F) {
+    shards
+        .iter()
+        .for_each(|shard| send_to_shard(shard, ShardMessage::Operation(operation())));
+}
+
+// Asks every shard, then waits for their answers on another thread so that a busy shard doesn't
+// hold up the rest
+fn gather<T, F, G>(shards: &[Sender<ShardMessage>], ask: F, finish: G)
+where
+    T: 'static + Send,
+    F: Fn(Sender<T>) -> ShardMessage,
+    G: 'static + Send + FnOnce(Vec<T>),
+{
+    let receivers: Vec<Receiver<T>> = shards
+        .iter()
+        .map(|shard| {
+            let (reply_sender, reply_receiver) = channel();
+            send_to_shard(shard, ask(reply_sender));
+            reply_receiver
+        })
+        .collect();
+    instance::spawn("player-gather", move || {
+        finish(
+            receivers
+                .into_iter()
+                .filter_map(|receiver| receiver.recv().ok())
+                .collect(),
         )
+    });
+}
+
+fn send_status_response<M: Messenger>(
+    msg: StatusResponseOperation,
+    players: Vec<(Uuid, String)>,
+    messenger: &M,
+) {
+    trace!(
+        "Building and sending status ping response for conn_id {:?}",
+        msg.conn_id
+    );
+    let status_response_object = minecraft_types::StatusResponse {
+        version: msg.version,
+        players: minecraft_types::PingPlayersInfo {
+            max: SERVER_MAX_CAPACITY,
+            online: players.len() as u16,
+            sample: players
+                .into_iter()
+                .map(|(id, name)| minecraft_types::PingSamplePlayer {
+                    name,
+                    id: id.to_string(),
+                })
+                .collect(),
+        },
+        description: msg.description,
+    };
+    let status_response = StatusResponse {
+        json_response: serde_json::to_string(&status_response_object).unwrap(),
+    };
+    messenger
+        .send_packet(msg.conn_id, Packet::StatusResponse(status_response))
+        .or_log();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_shard<
+    M: Messenger + Clone,
+    I: EntityIdAllocator,
+    IM: InterestManager,
+    E: EntityState,
+    CH: ChatService,
+>(
+    receiver: Receiver<ShardMessage>,
+    messenger: M,
+    entity_ids: I,
+    interest: IM,
+    entity_state: E,
+    chat: CH,
+    config: Config,
+    shared: Arc<SharedState>,
+) {
+    let mut players = HashMap::<Uuid, Player>::new();
+    let mut seams = HashMap::<Uuid, Seam>::new();
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            ShardMessage::Operation(msg) => handle_message(
+                msg,
+                &mut players,
+                &mut seams,
+                &entity_ids,
+                &interest,
+                &entity_state,
+                &chat,
+                messenger.clone(),
+                &config,
+                &shared,
+            ),
+            ShardMessage::Roster(reply) => {
+                let _ = reply.send(
+                    players
+                        .iter()
+                        .map(|(conn_id, player)| (*conn_id, player.name.clone()))
+                        .collect(),
+                );
+            }
+            ShardMessage::LevelChanged(name) => players
+                .iter()
+                .filter(|(_, player)| player.name.eq_ignore_ascii_case(&name))
+                .for_each(|(conn_id, player)| {
+                    let level = shared.operators.lock().unwrap().level(&player.name);
+                    messenger
+                        .send_packet(
+                            *conn_id,
+                            Packet::EntityStatus(player.permission_status_packet(level)),
+                        )
+                        .or_log();
+                }),
+        }
     }
 }
 
-fn handle_message<M: Messenger>(
+#[allow(clippy::too_many_arguments)]
+fn handle_message<
+    M: Messenger,
+    I: EntityIdAllocator,
+    IM: InterestManager,
+    E: EntityState,
+    CH: ChatService,
+>(
     msg: Operations,
     players: &mut HashMap<Uuid, Player>,
-    entity_conn_ids: &mut HashMap<i32, Uuid>,
-    entity_id: &mut i32,
+    seams: &mut HashMap<Uuid, Seam>,
+    entity_ids: &I,
+    interest: &IM,
+    entity_state: &E,
+    chat: &CH,
     messenger: M,
+    config: &Config,
+    shared: &SharedState,
 ) {
     match msg {
         Operations::New(msg) => {
             let mut player = msg.player;
-            if player.entity_id == 0 {
-                player.entity_id = *entity_id;
-                *entity_id += 1;
-            }
+            // Players logging in directly are leased an id, as they come with 0, which no player is
+            // leased. Anchored players bring theirs along
+            let (reply_sender, reply_receiver) = channel();
+            let entity_id = if player.entity_id == 0 {
+                entity_ids
+                    .lease(EntityIdRange::Player, reply_sender)
+                    .or_log();
+                reply_receiver.recv().ok().flatten()
+            } else {
+                let (claim_sender, claim_receiver) = channel();
+                entity_ids.claim(player.entity_id, claim_sender).or_log();
+                match claim_receiver.recv() {
+                    Ok(true) => Some(player.entity_id),
+                    _ => None,
+                }
+            };
+            player.entity_id = match entity_id {
+                Some(entity_id) => entity_id,
+                None => {
+                    error!(
+                        "No entity id available for player {:?}, closing conn_id {:?}",
+                        player.name, msg.conn_id
+                    );
+                    messenger
+                        .kick(msg.conn_id, String::from("The server is full"))
+                        .or_log();
+                    return;
+                }
+            };
             trace!(
                 "Creating new player {:?} for conn_id {:?}",
                 player,
                 msg.conn_id
             );
-            messenger.send_packet(msg.conn_id, Packet::JoinGame(player.join_game_packet()));
-            messenger.send_packet(
-                msg.conn_id,
-                Packet::ClientboundPlayerPositionAndLook(player.pos_and_look_packet()),
-            );
-            messenger.broadcast(
-                Packet::PlayerInfo(player.player_info_packet()),
-                Some(msg.conn_id),
-                SubscriberType::All,
-            );
-            messenger.broadcast(
-                Packet::SpawnPlayer(player.spawn_player_packet()),
-                Some(msg.conn_id),
-                SubscriberType::All,
-            );
-            entity_conn_ids.insert(player.entity_id, msg.conn_id);
+            messenger
+                .send_packet(
+                    msg.conn_id,
+                    Packet::JoinGame(player.join_game_packet(config)),
+                )
+                .or_log();
+            messenger
+                .send_packet(msg.conn_id, spawn_position_packet())
+                .or_log();
+            messenger
+                .send_packet(
+                    msg.conn_id,
+                    Packet::ServerDifficulty(ServerDifficulty {
+                        difficulty: config.difficulty.id(),
+                    }),
+                )
+                .or_log();
+            let level = shared.operators.lock().unwrap().level(&player.name);
+            messenger
+                .send_packet(
+                    msg.conn_id,
+                    Packet::EntityStatus(player.permission_status_packet(level)),
+                )
+                .or_log();
+            messenger
+                .send_packet(
+                    msg.conn_id,
+                    Packet::ClientboundPlayerPositionAndLook(player.pos_and_look_packet()),
+                )
+                .or_log();
+            messenger
+                .send_packet(
+                    msg.conn_id,
+                    Packet::Advancements(Advancements {
+                        data: advancements::tree(
+                            &shared
+                                .advancement_store
+                                .lock()
+                                .unwrap()
+                                .granted(&player.name),
+                        ),
+                    }),
+                )
+                .or_log();
+            for packet in player.inventory_packets() {
+                messenger.send_packet(msg.conn_id, packet).or_log();
+            }
+            // Only once the client's in the game, so that it has somewhere to show the chat
+            if player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START {
+                chat.replay(msg.conn_id).or_log();
+            }
+            messenger
+                .broadcast(
+                    Packet::PlayerInfo(player.player_info_packet()),
+                    Some(msg.conn_id),
+                    SubscriberType::Local,
+                )
+                .or_log();
+            // Players anchored here from a peer are moved around by that peer, so only see through
+            // its eyes
+            let viewer =
+                Some(msg.conn_id).filter(|_| player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START);
+            interest
+                .track(
+                    player.entity_id,
+                    EntityKind::Player,
+                    player.position,
+                    viewer,
+                    player.introduction(),
+                )
+                .or_log();
+            seams
+                .iter()
+                .filter(|(_, seam)| seam.contains(player.position))
+                .for_each(|(peer_conn_id, _)| player.introduce(*peer_conn_id, &messenger));
+            // Players crossing the border from a peer keep the momentum they crossed with
+            if player.velocity != Velocity::default() {
+                messenger
+                    .broadcast(
+                        Packet::EntityVelocity(player.entity_velocity_packet()),
+                        Some(msg.conn_id),
+                        SubscriberType::All,
+                    )
+                    .or_log();
+            }
+            shared
+                .entity_conn_ids
+                .lock()
+                .unwrap()
+                .insert(player.entity_id, msg.conn_id);
             players.insert(msg.conn_id, player);
         }
         Operations::Delete(msg) => {
+            seams.remove(&msg.conn_id);
             if let Some(player) = players.remove(&msg.conn_id) {
-                messenger.broadcast(
-                    Packet::DestroyEntities(DestroyEntities {
-                        entity_ids: vec![player.entity_id],
-                    }),
-                    None,
-                    SubscriberType::All,
-                );
+                if player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START {
+                    shared.player_store.save(&player);
+                }
+                shared
+                    .entity_conn_ids
+                    .lock()
+                    .unwrap()
+                    .remove(&player.entity_id);
+                entity_ids.release(player.entity_id).or_log();
+                interest.untrack(player.entity_id).or_log();
+                messenger
+                    .broadcast(
+                        Packet::DestroyEntities(DestroyEntities {
+                            entity_ids: vec![player.entity_id],
+                        }),
+                        None,
+                        SubscriberType::Remote,
+                    )
+                    .or_log();
             }
         }
         Operations::MoveAndLook(msg) => {
@@ -87,20 +661,45 @@ fn handle_message<M: Messenger>(
                 msg.new_angle,
                 msg.conn_id
             );
-            players.entry(msg.conn_id).and_modify(|player| {
-                messenger.broadcast(
-                    Packet::EntityLookAndMove(
-                        player.move_and_look(msg.new_position, msg.new_angle),
-                    ),
-                    Some(player.conn_id),
-                    SubscriberType::All,
-                );
-                messenger.broadcast(
-                    Packet::EntityHeadLook(player.entity_head_look()),
-                    Some(player.conn_id),
-                    SubscriberType::All,
-                );
-            });
+            if let Some(player) = players.get_mut(&msg.conn_id) {
+                let old_position = player.position;
+                let look_and_move = player.move_and_look(msg.new_position, msg.new_angle);
+                let head_look = player.entity_head_look();
+                interest
+                    .move_entity(
+                        player.entity_id,
+                        player.position,
+                        vec![
+                            Packet::EntityLookAndMove(look_and_move.clone()),
+                            Packet::EntityHeadLook(head_look.clone()),
+                        ],
+                        player.introduction(),
+                    )
+                    .or_log();
+                player.fall_out_of_world(&messenger);
+                // Peers see players walk into and out of view as they cross into their seam
+                seams.iter().for_each(|(peer_conn_id, seam)| {
+                    match (seam.contains(old_position), seam.contains(player.position)) {
+                        (true, true) => {
+                            messenger
+                                .send_packet(
+                                    *peer_conn_id,
+                                    Packet::EntityLookAndMove(look_and_move.clone()),
+                                )
+                                .or_log();
+                            messenger
+                                .send_packet(
+                                    *peer_conn_id,
+                                    Packet::EntityHeadLook(head_look.clone()),
+                                )
+                                .or_log();
+                        }
+                        (false, true) => player.introduce(*peer_conn_id, &messenger),
+                        (true, false) => player.forget(*peer_conn_id, &messenger),
+                        (false, false) => {}
+                    }
+                });
+            }
         }
         Operations::AnchoredMoveAndLook(msg) => {
             trace!(
@@ -109,89 +708,403 @@ fn handle_message<M: Messenger>(
                 msg.new_angle,
                 msg.conn_id
             );
-            players.entry(msg.conn_id).and_modify(|player| {
+            // What they're seen doing comes from the peer, see BroadcastAnchoredEvent
+            if let Some(player) = players.get_mut(&msg.conn_id) {
                 player.move_and_look(msg.new_position, msg.new_angle);
-            });
+                interest
+                    .move_entity(
+                        player.entity_id,
+                        player.position,
+                        Vec::new(),
+                        player.introduction(),
+                    )
+                    .or_log();
+                player.fall_out_of_world(&messenger);
+            }
         }
         Operations::Report(msg) => players.iter().for_each(|(conn_id, player)| {
             trace!("Reporting Player State to conn_id {:?}", conn_id);
-            if *conn_id != msg.conn_id {
-                messenger.send_packet(msg.conn_id, Packet::PlayerInfo(player.player_info_packet()));
-                messenger.send_packet(
-                    msg.conn_id,
-                    Packet::SpawnPlayer(player.spawn_player_packet()),
-                );
+            let in_view = match seams.get(&msg.conn_id) {
+                Some(seam) => seam.contains(player.position),
+                None => true,
+            };
+            if *conn_id != msg.conn_id && in_view {
+                player.introduce(msg.conn_id, &messenger);
             }
         }),
-        //When we get a message from a peer that comes from one of our anchored players we want to
-        //make sure they don't get the result packets.
-        Operations::BroadcastAnchoredEvent(msg) => {
-            if let Some(entity_id) = entity_conn_ids.get(&msg.entity_id) {
-                trace!("Appending entity id {:?} to anchored event", entity_id);
-            }
-            messenger.broadcast(
-                msg.packet,
-                entity_conn_ids.get(&msg.entity_id).copied(),
-                SubscriberType::Local,
+        // Players are only seen once they're in range, but everyone's on the player list
+        Operations::ListPlayers(msg) => players
+            .iter()
+            .filter(|(conn_id, _)| **conn_id != msg.conn_id)
+            .for_each(|(_, player)| {
+                messenger
+                    .send_packet(msg.conn_id, Packet::PlayerInfo(player.player_info_packet()))
+                    .or_log()
+            }),
+        // The peer may already have been told about everyone, so take back whoever isn't in view
+        Operations::AddSeam(msg) => {
+            trace!(
+                "Adding seam with neighbour {:?} for conn_id {:?}",
+                msg.neighbour,
+                msg.conn_id
             );
+            let seam = Seam {
+                neighbour: msg.neighbour,
+                width: f64::from(config.tracking_ranges.players),
+            };
+            players
+                .values()
+                .filter(|player| !seam.contains(player.position))
+                .for_each(|player| player.forget(msg.conn_id, &messenger));
+            seams.insert(msg.conn_id, seam);
         }
         Operations::CrossBorder(msg) => {
             trace!("Crossing Border for conn_id {:?}", msg.local_conn_id);
-            let player = players
-                .get(&msg.local_conn_id)
-                .expect("Could not cross border: player not found");
-            messenger.broadcast(
-                Packet::DestroyEntities(DestroyEntities {
-                    entity_ids: vec![player.entity_id],
-                }),
-                None,
-                SubscriberType::Remote,
-            );
-            messenger.send_packet(
-                msg.remote_conn_id,
-                Packet::BorderCrossLogin(player.border_cross_login()),
-            );
+            let player = match players.get(&msg.local_conn_id) {
+                Some(player) => player,
+                None => {
+                    warn!(
+                        "Could not cross border: {}",
+                        PatchworkError::NoPlayer(msg.local_conn_id)
+                    );
+                    return;
+                }
+            };
+            messenger
+                .broadcast(
+                    Packet::DestroyEntities(DestroyEntities {
+                        entity_ids: vec![player.entity_id],
+                    }),
+                    None,
+                    SubscriberType::Remote,
+                )
+                .or_log();
+            messenger
+                .send_packet(
+                    msg.remote_conn_id,
+                    Packet::BorderCrossLogin(player.border_cross_login()),
+                )
+                .or_log();
+            let granted = shared
+                .advancement_store
+                .lock()
+                .unwrap()
+                .grant(&player.name, advancements::QUILT_WALKER);
+            if granted {
+                messenger
+                    .send_packet(
+                        msg.local_conn_id,
+                        Packet::Advancements(Advancements {
+                            data: advancements::grant(advancements::QUILT_WALKER),
+                        }),
+                    )
+                    .or_log();
+            }
         }
         Operations::Reintroduce(msg) => {
             trace!("Reintroducing player for conn_id {:?}", msg.conn_id);
-            let player = players
-                .get(&msg.conn_id)
-                .expect("Could not reintroduce: player not found");
-            messenger.broadcast(
-                Packet::SpawnPlayer(player.spawn_player_packet()),
-                None,
-                SubscriberType::Remote,
+            let player = match players.get(&msg.conn_id) {
+                Some(player) => player,
+                None => {
+                    warn!(
+                        "Could not reintroduce: {}",
+                        PatchworkError::NoPlayer(msg.conn_id)
+                    );
+                    return;
+                }
+            };
+            messenger
+                .broadcast(
+                    Packet::SpawnPlayer(player.spawn_player_packet()),
+                    None,
+                    SubscriberType::Remote,
+                )
+                .or_log();
+        }
+        Operations::Positions(msg) => {
+            let _ = msg.reply.send(
+                players
+                    .iter()
+                    .map(|(conn_id, player)| (*conn_id, player.position))
+                    .collect(),
             );
         }
-        Operations::StatusResponse(msg) => {
+        Operations::Online(msg) => {
+            let _ = msg.reply.send(
+                players
+                    .iter()
+                    .filter(|(_, player)| player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START)
+                    .map(|(conn_id, player)| {
+                        (
+                            *conn_id,
+                            player.name.clone(),
+                            player.position,
+                            player.dimension,
+                        )
+                    })
+                    .collect(),
+            );
+        }
+        Operations::Teleport(msg) => {
             trace!(
-                "Building and sending status ping response for conn_id {:?}",
-                msg.conn_id
+                "Teleporting conn_id {:?} to {:?}",
+                msg.conn_id,
+                msg.position
             );
-            let status_response_object = minecraft_types::StatusResponse {
-                version: msg.version,
-                players: minecraft_types::PingPlayersInfo {
-                    max: SERVER_MAX_CAPACITY,
-                    online: players.len() as u16,
-                    sample: players
-                        .iter()
-                        .map(|(id, player)| minecraft_types::PingSamplePlayer {
-                            name: player.name.clone(),
-                            id: id.to_string(),
+            if let Some(player) = players.get_mut(&msg.conn_id) {
+                teleport(player, msg.position, &messenger, interest);
+            }
+        }
+        Operations::PullBack(msg) => {
+            if let Some(player) = players.get_mut(&msg.conn_id) {
+                let position = player.nearest_on(msg.onto);
+                trace!("Pulling conn_id {:?} back to {:?}", msg.conn_id, position);
+                teleport(player, position, &messenger, interest);
+            }
+        }
+        // The client forgets the world and everything in it when it respawns, so it's tracked again
+        // from scratch, which also takes the body away from whoever saw it die
+        Operations::Respawn(msg) => {
+            trace!("Respawning conn_id {:?} at {:?}", msg.conn_id, msg.position);
+            if let Some(player) = players.get_mut(&msg.conn_id) {
+                player.position = msg.position;
+                player.dimension = msg.dimension;
+                player.velocity = Velocity::default();
+                player.health = Health::default();
+                messenger
+                    .send_packet(msg.conn_id, Packet::Respawn(player.respawn_packet(config)))
+                    .or_log();
+                messenger
+                    .send_packet(msg.conn_id, spawn_position_packet())
+                    .or_log();
+                messenger
+                    .send_packet(
+                        msg.conn_id,
+                        Packet::ClientboundPlayerPositionAndLook(player.pos_and_look_packet()),
+                    )
+                    .or_log();
+                for packet in player.inventory_packets() {
+                    messenger.send_packet(msg.conn_id, packet).or_log();
+                }
+                interest.untrack(player.entity_id).or_log();
+                interest
+                    .track(
+                        player.entity_id,
+                        EntityKind::Player,
+                        player.position,
+                        Some(msg.conn_id),
+                        player.introduction(),
+                    )
+                    .or_log();
+            }
+            let _ = msg.reply.send(());
+        }
+        Operations::HoldItem(msg) => {
+            if let Some(player) = players.get_mut(&msg.conn_id) {
+                player.held_item_slot = msg.slot;
+            }
+        }
+        Operations::SetSlot(msg) => {
+            if let Some(player) = players.get_mut(&msg.conn_id) {
+                match player.inventory.get_mut(msg.slot as usize) {
+                    Some(slot) => {
+                        trace!(
+                            "{:?} put {:?} in slot {:?}",
+                            player.name,
+                            msg.item
+                                .as_ref()
+                                .map(|item| item.name().unwrap_or("unknown item")),
+                            msg.slot
+                        );
+                        *slot = msg.item
+                    }
+                    None => trace!("Ignoring item put in slot {:?}", msg.slot),
+                }
+            }
+        }
+        Operations::DropItem(msg) => {
+            if let Some(player) = players.get_mut(&msg.conn_id) {
+                let slot = HOTBAR_START + player.held_item_slot as usize;
+                let dropped = match player.inventory.get_mut(slot) {
+                    Some(Some(held)) if msg.whole_stack || held.count <= 1 => {
+                        player.inventory[slot].take()
+                    }
+                    Some(Some(held)) => {
+                        held.count -= 1;
+                        Some(ItemStack {
+                            count: 1,
+                            ..held.clone()
                         })
-                        .collect(),
-                },
-                description: msg.description,
-            };
-            let status_response = StatusResponse {
-                json_response: serde_json::to_string(&status_response_object).unwrap(),
-            };
-            messenger.send_packet(msg.conn_id, Packet::StatusResponse(status_response));
+                    }
+                    _ => None,
+                };
+                match dropped {
+                    Some(item) if msg.spawn => {
+                        trace!("{:?} dropped {:?}", player.name, item);
+                        entity_state.drop_item(player.thrown_item(item)).or_log();
+                    }
+                    Some(_) => (),
+                    None => trace!("{:?} has nothing in hand to drop", player.name),
+                }
+            }
+        }
+        // Items dragged out of the creative inventory come from nowhere
+        Operations::ThrowItem(msg) => {
+            if let Some(player) = players.get(&msg.conn_id) {
+                trace!("{:?} threw {:?}", player.name, msg.item);
+                entity_state
+                    .drop_item(player.thrown_item(msg.item))
+                    .or_log();
+            }
+        }
+        // Players anchored here from a peer keep their inventory with that peer, so they can't
+        // pick anything up here
+        Operations::PickUp(msg) => {
+            let (conn_id, item) = (msg.conn_id, msg.item);
+            let collector = players
+                .get_mut(&conn_id)
+                .filter(|player| player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START)
+                .and_then(|player| {
+                    let slot = player.give(item)?;
+                    messenger
+                        .send_packet(
+                            conn_id,
+                            Packet::SetSlot(SetSlot {
+                                window_id: 0,
+                                slot: slot as i16,
+                                slot_data: player.inventory[slot].clone(),
+                            }),
+                        )
+                        .or_log();
+                    Some(player.entity_id)
+                });
+            let _ = msg.reply.send(collector);
+        }
+        Operations::PermissionLevel(msg) => {
+            let level = players.get(&msg.conn_id).map_or(0, |player| {
+                shared.operators.lock().unwrap().level(&player.name)
+            });
+            let _ = msg.reply.send(level);
+        }
+        // Players anchored here from a peer belong to that peer, so they're left out
+        Operations::Find(msg) => {
+            let _ = msg.reply.send(
+                players
+                    .values()
+                    .find(|player| {
+                        player.uuid == msg.uuid
+                            && player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START
+                    })
+                    .map(|player| player.entity_id),
+            );
+        }
+        Operations::FindConnection(msg) => {
+            let _ = msg.reply.send(
+                players
+                    .values()
+                    .find(|player| {
+                        player.uuid == msg.uuid
+                            && player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START
+                    })
+                    .map(|player| player.conn_id),
+            );
+        }
+        // The peer they came from is subscribed to us like every other, and only it has them
+        Operations::KickVisitor(msg) => {
+            let visitor = players.values().find(|player| {
+                player.name == msg.name && player.entity_id >= ANCHORED_PLAYER_ENTITY_ID_START
+            });
+            if let Some(visitor) = visitor {
+                info!("Asking for {:?} to be kicked back", visitor.name);
+                messenger
+                    .broadcast(
+                        Packet::PeerKickback(PeerKickback {
+                            uuid: visitor.uuid.as_u128(),
+                            reason: msg.reason.unwrap_or_default(),
+                        }),
+                        None,
+                        SubscriberType::Remote,
+                    )
+                    .or_log();
+            }
+            let _ = msg.reply.send(visitor.is_some());
+        }
+        Operations::Autosave(_) => {
+            save_players(players, shared);
+        }
+        Operations::SaveAll(msg) => {
+            let _ = msg.reply.send(save_players(players, shared));
+        }
+        Operations::BroadcastAnchoredEvent(_)
+        | Operations::StatusResponse(_)
+        | Operations::Saved(_)
+        | Operations::SetOperator(_) => {
+            unreachable!("Answered by the player state router")
         }
     }
 }
 
+fn teleport<M: Messenger, IM: InterestManager>(
+    player: &mut Player,
+    position: Position,
+    messenger: &M,
+    interest: &IM,
+) {
+    player.position = position;
+    messenger
+        .send_packet(
+            player.conn_id,
+            Packet::ClientboundPlayerPositionAndLook(player.pos_and_look_packet()),
+        )
+        .or_log();
+    interest
+        .move_entity(
+            player.entity_id,
+            player.position,
+            Vec::new(),
+            player.introduction(),
+        )
+        .or_log();
+}
+
+// Like with Find, players anchored here from a peer are saved by that peer
+fn save_players(players: &HashMap<Uuid, Player>, shared: &SharedState) -> usize {
+    players
+        .values()
+        .filter(|player| player.entity_id < ANCHORED_PLAYER_ENTITY_ID_START)
+        .map(|player| shared.player_store.save(player))
+        .count()
+}
+
+// Where the client points compasses, which it forgets along with everything else when it respawns
+fn spawn_position_packet() -> Packet {
+    let spawn = spawn_position();
+    Packet::SpawnPosition(SpawnPosition {
+        location: Location {
+            x: spawn.x.floor() as i32,
+            y: spawn.y.floor() as i32,
+            z: spawn.z.floor() as i32,
+        },
+    })
+}
+
 impl Player {
+    // Where they'd be if they stepped straight onto the map, a little way in from its edge
+    pub fn nearest_on(&self, map: MapPosition) -> Position {
+        let clamp = |coordinate: f64, map_coordinate: i32| {
+            let start = f64::from(map_coordinate * map_width());
+            coordinate.clamp(
+                start + PULL_BACK_MARGIN,
+                start + f64::from(map_width()) - PULL_BACK_MARGIN,
+            )
+        };
+        Position {
+            x: clamp(self.position.x, map.x),
+            y: self.position.y,
+            z: clamp(self.position.z, map.z),
+        }
+    }
+
     pub fn border_cross_login(&self) -> BorderCrossLogin {
         BorderCrossLogin {
             x: self.position.x,
@@ -200,8 +1113,20 @@ impl Player {
             yaw: self.angle.yaw,
             pitch: self.angle.pitch,
             on_ground: false,
+            velocity_x: self.velocity.x,
+            velocity_y: self.velocity.y,
+            velocity_z: self.velocity.z,
             username: self.name.clone(),
+            uuid: self.uuid.as_u128(),
             entity_id: self.entity_id,
+            inventory: serde_json::to_string(&self.inventory).unwrap(),
+            held_item_slot: self.held_item_slot,
+            health: self.health.health,
+            food: self.health.food,
+            food_saturation: self.health.food_saturation,
+            experience_bar: self.experience.bar,
+            level: self.experience.level,
+            total_experience: self.experience.total,
         }
     }
 
@@ -215,20 +1140,119 @@ impl Player {
         }
         let update_packet = self.entity_look_and_move_packet(new_position);
         if let Some(new_position) = new_position {
+            self.velocity = Velocity {
+                x: new_position.x - self.position.x,
+                y: new_position.y - self.position.y,
+                z: new_position.z - self.position.z,
+            };
             self.position = new_position;
         }
         update_packet
     }
 
-    pub fn join_game_packet(&self) -> JoinGame {
+    // Thrown from eye height the way the player is looking, the way vanilla does
+    fn thrown_item(&self, item: ItemStack) -> DroppedItem {
+        let yaw = (self.angle.yaw as f64).to_radians();
+        let pitch = (self.angle.pitch as f64).to_radians();
+        DroppedItem {
+            uuid: Uuid::new_v4(),
+            item,
+            position: Position {
+                y: self.position.y + ITEM_THROW_HEIGHT,
+                ..self.position
+            },
+            velocity: Velocity {
+                x: -yaw.sin() * pitch.cos() * ITEM_THROW_SPEED,
+                y: -pitch.sin() * ITEM_THROW_SPEED + ITEM_THROW_LIFT,
+                z: yaw.cos() * pitch.cos() * ITEM_THROW_SPEED,
+            },
+            floor_y: self.position.y,
+            pickup_delay: ITEM_PICKUP_DELAY,
+            age: 0,
+            thrower: Some(self.uuid),
+        }
+    }
+
+    // Stacks the item onto a matching stack with room for all of it, or puts it in the first empty
+    // slot, hotbar first. Returns the slot it went into
+    fn give(&mut self, item: ItemStack) -> Option<usize> {
+        let slots = (HOTBAR_START..HOTBAR_END).chain(MAIN_INVENTORY_START..HOTBAR_START);
+        let slot = slots.clone().find(|&slot| match &self.inventory[slot] {
+            Some(stack) => {
+                stack.item_id == item.item_id
+                    && stack.nbt == item.nbt
+                    && stack.count as i32 + item.count as i32 <= MAX_STACK_SIZE
+            }
+            None => false,
+        });
+        let slot = slot.or_else(|| {
+            slots
+                .into_iter()
+                .find(|&slot| self.inventory[slot].is_none())
+        })?;
+        match &mut self.inventory[slot] {
+            Some(stack) => stack.count += item.count,
+            empty => *empty = Some(item),
+        }
+        Some(slot)
+    }
+
+    // Players of ours who fall out of the world die, and stay dead until they ask to respawn. Players
+    // anchored here from a peer live or die by that peer
+    fn fall_out_of_world<M: Messenger>(&mut self, messenger: &M) {
+        if self.entity_id >= ANCHORED_PLAYER_ENTITY_ID_START
+            || self.position.y >= VOID_DEPTH
+            || self.health.health <= 0.0
+        {
+            return;
+        }
+        trace!("{:?} fell out of the world", self.name);
+        self.health.health = 0.0;
+        messenger
+            .send_packet(
+                self.conn_id,
+                Packet::UpdateHealth(UpdateHealth {
+                    health: self.health.health,
+                    food: self.health.food,
+                    food_saturation: self.health.food_saturation,
+                }),
+            )
+            .or_log();
+    }
+
+    // Respawning into the dimension the client's already in has it throw away its chunks all the same
+    pub fn respawn_packet(&self, config: &Config) -> Respawn {
+        let join_game = self.join_game_packet(config);
+        Respawn {
+            dimension: join_game.dimension,
+            difficulty: join_game.difficulty,
+            gamemode: join_game.gamemode,
+            level_type: join_game.level_type,
+        }
+    }
+
+    pub fn join_game_packet(&self, config: &Config) -> JoinGame {
+        let gamemode = 1;
         JoinGame {
             entity_id: self.entity_id,
-            gamemode: 1,
-            dimension: 0,
-            difficulty: 0,
+            gamemode: if config.hardcore {
+                gamemode | HARDCORE_FLAG
+            } else {
+                gamemode
+            },
+            dimension: self.dimension.id(),
+            difficulty: config.difficulty.id(),
             max_players: 2,
             level_type: String::from("default"),
-            reduced_debug_info: false,
+            reduced_debug_info: config.reduced_debug_info,
+        }
+    }
+
+    // Lets the client know which commands it can use, and whether it can switch game modes itself
+    pub fn permission_status_packet(&self, level: u8) -> EntityStatus {
+        EntityStatus {
+            entity_id: self.entity_id,
+            status: OP_PERMISSION_LEVEL_0_STATUS + level as i8,
         }
     }
 
@@ -237,8 +1261,8 @@ impl Player {
             x: self.position.x,
             y: self.position.y,
             z: self.position.z,
-            yaw: 0.0,
-            pitch: 0.0,
+            yaw: self.angle.yaw,
+            pitch: self.angle.pitch,
             flags: 0,
             teleport_id: 0,
         }
@@ -264,6 +1288,75 @@ impl Player {
         }
     }
 
+    // Adds the player to the tab list and the world for whoever's on the other end of conn_id
+    fn introduce<M: Messenger>(&self, conn_id: Uuid, messenger: &M) {
+        self.introduction()
+            .into_iter()
+            .for_each(|packet| messenger.send_packet(conn_id, packet).or_log());
+    }
+
+    // Clients only show a player they already have on their player list
+    fn introduction(&self) -> Vec<Packet> {
+        vec![
+            Packet::PlayerInfo(self.player_info_packet()),
+            Packet::SpawnPlayer(self.spawn_player_packet()),
+        ]
+    }
+
+    fn forget<M: Messenger>(&self, conn_id: Uuid, messenger: &M) {
+        messenger
+            .send_packet(
+                conn_id,
+                Packet::DestroyEntities(DestroyEntities {
+                    entity_ids: vec![self.entity_id],
+                }),
+            )
+            .or_log();
+    }
+
+    // Everything the client needs to show the player's inventory, health and experience
+    fn inventory_packets(&self) -> Vec<Packet> {
+        let mut packets: Vec<Packet> = self
+            .inventory
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.is_some())
+            .map(|(slot, item)| {
+                Packet::SetSlot(SetSlot {
+                    window_id: 0,
+                    slot: slot as i16,
+                    slot_data: item.clone(),
+                })
+            })
+            .collect();
+        packets.push(Packet::ClientboundHeldItemChange(
+            ClientboundHeldItemChange {
+                slot: self.held_item_slot as i8,
+            },
+        ));
+        packets.push(Packet::UpdateHealth(UpdateHealth {
+            health: self.health.health,
+            food: self.health.food,
+            food_saturation: self.health.food_saturation,
+        }));
+        packets.push(Packet::SetExperience(SetExperience {
+            experience_bar: self.experience.bar,
+            level: self.experience.level,
+            total_experience: self.experience.total,
+        }));
+        packets
+    }
+
+    // Velocity is sent in 1/8000ths of a block per tick
+    fn entity_velocity_packet(&self) -> EntityVelocity {
+        EntityVelocity {
+            entity_id: self.entity_id,
+            velocity_x: (self.velocity.x * 8000.0) as i16,
+            velocity_y: (self.velocity.y * 8000.0) as i16,
+            velocity_z: (self.velocity.z * 8000.0) as i16,
+        }
+    }
+
     fn player_info_packet(&self) -> PlayerInfo {
         PlayerInfo {
             action: 0,
@@ -284,13 +1377,36 @@ impl Player {
             x: self.position.x,
             y: self.position.y,
             z: self.position.z,
-            yaw: 0,
-            pitch: 0,
+            yaw: float_to_angle(self.angle.yaw),
+            pitch: float_to_angle(self.angle.pitch),
             entity_metadata_terminator: 0xff,
         }
     }
 }
 
+// The strip of our map within width blocks of a neighbouring peer's map. Peers subscribed to us
+// only hear about the players standing in their seam
+#[derive(Debug, Clone, Copy)]
+struct Seam {
+    // Where the peer's map is in the quilt, in maps, as players' positions are
+    neighbour: MapPosition,
+    width: f64,
+}
+
+impl Seam {
+    fn contains(&self, position: Position) -> bool {
+        let map_width = f64::from(map_width());
+        let distance = |coordinate: f64, neighbour: i32| {
+            let start = f64::from(neighbour) * map_width;
+            (start - coordinate)
+                .max(coordinate - start - map_width)
+                .max(0.0)
+        };
+        distance(position.x, self.neighbour.x) <= self.width
+            && distance(position.z, self.neighbour.z) <= self.width
+    }
+}
+
 #[derive(Debug, Clone)]
 struct PositionDelta {
     pub x: i16,
@@ -310,3 +1426,73 @@ impl PositionDelta {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::map::Dimension;
+
+    fn seam(x: i32, z: i32) -> Seam {
+        Seam {
+            neighbour: MapPosition {
+                x,
+                z,
+                dimension: Dimension::Overworld,
+            },
+            width: 4.0,
+        }
+    }
+
+    // Positions on our map at (3, -2), given in maps
+    fn at(x: f64, z: f64) -> Position {
+        let width = f64::from(map_width());
+        Position {
+            x: x * width,
+            y: 64.0,
+            z: z * width,
+        }
+    }
+
+    #[test]
+    fn seams_cover_the_strip_along_the_neighbour_wherever_our_map_is() {
+        let width = f64::from(map_width());
+        let east = seam(4, -2);
+        assert!(east.contains(Position {
+            x: 4.0 * width - 2.0,
+            ..at(3.5, -1.5)
+        }));
+        assert!(!east.contains(Position {
+            x: 3.0 * width + 1.0,
+            ..at(3.5, -1.5)
+        }));
+
+        let north = seam(3, -3);
+        assert!(north.contains(Position {
+            z: -2.0 * width + 2.0,
+            ..at(3.5, -1.5)
+        }));
+        assert!(!north.contains(Position {
+            z: -width - 1.0,
+            ..at(3.5, -1.5)
+        }));
+    }
+
+    #[test]
+    fn diagonal_seams_only_cover_the_shared_corner() {
+        let width = f64::from(map_width());
+        let south_west = seam(2, -1);
+        assert!(south_west.contains(Position {
+            x: 3.0 * width + 1.0,
+            z: -width - 1.0,
+            ..at(3.5, -1.5)
+        }));
+        assert!(!south_west.contains(Position {
+            x: 3.0 * width + 1.0,
+            ..at(3.5, -1.5)
+        }));
+        assert!(!south_west.contains(Position {
+            z: -width - 1.0,
+            ..at(3.5, -1.5)
+        }));
+    }
+}
@@ -0,0 +1,42 @@
+use super::config::Config;
+use super::instance::Queue;
+use super::interfaces::whitelist::Operations;
+use super::whitelist_store::WhitelistStore;
+
+use std::sync::mpsc::Sender;
+
+// Players logging in are checked against the whitelist while it's on. Changes to who's on it are
+// saved straight away, but turning it on or off only lasts until we restart
+pub fn start(receiver: Queue<Operations>, _sender: Sender<Operations>, config: Config) {
+    let mut whitelist = WhitelistStore::load(&config.whitelist.file);
+    let mut enabled = config.whitelist.enabled;
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Check(msg) => {
+                let refusal = (enabled && !whitelist.allows(&msg.name, msg.uuid))
+                    .then(|| config.whitelist.message.clone());
+                if refusal.is_some() {
+                    info!("Turning away {:?}, who isn't whitelisted", msg.name);
+                }
+                let _ = msg.reply.send(refusal);
+            }
+            Operations::Add(msg) => {
+                let _ = msg.reply.send(whitelist.add(&msg.entry));
+            }
+            Operations::Remove(msg) => {
+                let _ = msg.reply.send(whitelist.remove(&msg.entry));
+            }
+            Operations::List(msg) => {
+                let _ = msg.reply.send(whitelist.entries());
+            }
+            Operations::Enable(msg) => {
+                info!(
+                    "Whitelist turned {}",
+                    if msg.enabled { "on" } else { "off" }
+                );
+                enabled = msg.enabled;
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use super::config::WeatherConfig;
+use super::constants::{PEER_TIME_SYNC_TICKS, TIME_UPDATE_TICKS, WORLD_TICK_PERIOD};
+use super::error::OrLog;
+use super::instance::{self, Queue};
+use super::interfaces::game_rules::{GameRule, GameRuleState};
+use super::interfaces::messenger::{Messenger, SubscriberType};
+use super::interfaces::world_time::{Operations, WorldTimeState};
+use super::packet::{Packet, PeerTimeUpdate, TimeUpdate};
+use super::weather::Weather;
+
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+// The world's age and the time of day, in ticks. The time of day only moves along while the
+// doDaylightCycle game rule's on, and is left to grow past a day as in vanilla
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Clock {
+    world_age: i64,
+    time_of_day: i64,
+}
+
+impl Clock {
+    fn tick(&mut self, daylight_cycle: bool) {
+        self.world_age += 1;
+        if daylight_cycle {
+            self.time_of_day += 1;
+        }
+    }
+
+    // The older world's time wins, so every node ends up with the time of whichever has been
+    // running longest, and they stay together from then on as they all tick at the same rate
+    fn merge(&mut self, peer: Clock) -> bool {
+        let newer = peer.world_age > self.world_age;
+        if newer {
+            *self = peer;
+        }
+        newer
+    }
+
+    fn time_update(&self, daylight_cycle: bool) -> Packet {
+        Packet::TimeUpdate(TimeUpdate {
+            world_age: self.world_age,
+            time_of_day: match daylight_cycle {
+                true => self.time_of_day,
+                false => -self.time_of_day.max(1),
+            },
+        })
+    }
+
+    fn peer_update(&self, weather: &Weather) -> Packet {
+        Packet::PeerTimeUpdate(PeerTimeUpdate {
+            world_age: self.world_age,
+            time_of_day: self.time_of_day,
+            weather: weather.kind.id(),
+            weather_remaining: weather.remaining,
+        })
+    }
+}
+
+// Ticks the world's time and weather along, telling our players every second so that the sun keeps
+// its place even if their client drifts, and our peers every few so that the sun's in the same
+// place and the same storms are out on every map
+pub fn start<M: Messenger, G: GameRuleState>(
+    receiver: Queue<Operations>,
+    sender: Sender<Operations>,
+    messenger: M,
+    game_rules: G,
+    weather_config: WeatherConfig,
+) {
+    let mut clock = Clock::default();
+    let mut weather = Weather::new(&weather_config);
+    let tell_players = |packets: Vec<Packet>| {
+        packets.into_iter().for_each(|packet| {
+            messenger
+                .broadcast(packet, None, SubscriberType::Local)
+                .or_log()
+        })
+    };
+    let mut daylight_cycle = GameRule::DoDaylightCycle.default_value();
+    instance::spawn("world-ticks", move || loop {
+        thread::sleep(Duration::from_millis(WORLD_TICK_PERIOD));
+        sender.tick().or_log();
+    });
+
+    while let Ok(msg) = receiver.recv() {
+        match msg {
+            Operations::Tick(_) => {
+                clock.tick(daylight_cycle);
+                if weather.tick(clock.world_age, &weather_config) {
+                    trace!("The weather's now {:?}", weather);
+                    tell_players(weather.change_packets());
+                }
+                if clock.world_age % TIME_UPDATE_TICKS == 0 {
+                    // Only looked up as often as players are told, as it's seldom changed
+                    let (reply_sender, reply_receiver) = channel();
+                    game_rules
+                        .get(GameRule::DoDaylightCycle, reply_sender)
+                        .or_log();
+                    daylight_cycle = reply_receiver.recv().unwrap_or(daylight_cycle);
+                    tell_players(vec![clock.time_update(daylight_cycle)]);
+                    tell_players(weather.level_packets());
+                }
+                if clock.world_age % PEER_TIME_SYNC_TICKS == 0 {
+                    messenger
+                        .broadcast(clock.peer_update(&weather), None, SubscriberType::Remote)
+                        .or_log();
+                }
+            }
+            Operations::Report(msg) => {
+                trace!("Reporting world time to {:?}", msg.conn_id);
+                messenger
+                    .send_packet(msg.conn_id, clock.peer_update(&weather))
+                    .or_log();
+            }
+            Operations::PeerUpdate(msg) => {
+                let peer = Clock {
+                    world_age: msg.world_age,
+                    time_of_day: msg.time_of_day,
+                };
+                if clock.merge(peer) {
+                    trace!("Syncing world time to {:?} from peer", peer);
+                    if msg.weather.kind != weather.kind {
+                        tell_players(msg.weather.change_packets());
+                    }
+                    weather = msg.weather;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_oldest_world_sets_the_time() {
+        let mut clock = Clock::default();
+        clock.tick(true);
+        clock.tick(false);
+        assert_eq!(
+            clock,
+            Clock {
+                world_age: 2,
+                time_of_day: 1
+            }
+        );
+
+        let older = Clock {
+            world_age: 100,
+            time_of_day: 6000,
+        };
+        assert!(clock.merge(older));
+        assert!(!clock.merge(Clock {
+            world_age: 50,
+            time_of_day: 18000,
+        }));
+        assert_eq!(clock, older);
+
+        match clock.time_update(false) {
+            Packet::TimeUpdate(update) => assert_eq!(update.time_of_day, -6000),
+            packet => panic!("Unexpected packet {:?}", packet),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use super::config::Config;
+use super::constants::{SHUTDOWN_MESSAGE, SHUTDOWN_TIMEOUT};
+use super::error::OrLog;
+use super::interfaces::block::BlockState;
+use super::interfaces::messenger::{Messenger, SubscriberType};
+use super::interfaces::patchwork::PatchworkState;
+use super::interfaces::player::PlayerState;
+use super::models::minecraft_types::ChatComponent;
+use super::models::packet::{Disconnect, Packet};
+use super::models::world_store;
+use super::server;
+use super::services::instance;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::process;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+// Blocks until we're asked to stop with SIGINT or SIGTERM. Shutting down can take a while, so a
+// second signal exits straight away
+pub fn wait_for_signal() {
+    let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap();
+    if let Some(signal) = signals.forever().next() {
+        info!("Received signal {:?}, shutting down", signal);
+    }
+    thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            warn!("Received another signal, exiting without finishing shutdown");
+            process::exit(1);
+        }
+    });
+}
+
+// Stops taking connections, saves players and the map, tells clients and peers we're going away,
+// then waits for every service to stop
+pub fn shut_down<M: Messenger, P: PlayerState, B: BlockState, PA: PatchworkState>(
+    port: u16,
+    messenger: M,
+    player_state: P,
+    block_state: B,
+    patchwork_state: PA,
+    config: &Config,
+) {
+    server::stop_listening(port);
+    save(player_state, block_state, config);
+
+    messenger
+        .broadcast(
+            Packet::Disconnect(Disconnect {
+                reason: ChatComponent::new(SHUTDOWN_MESSAGE).to_json(),
+            }),
+            None,
+            SubscriberType::Local,
+        )
+        .or_log();
+    if ask(|reply| patchwork_state.shut_down(reply).or_log()).is_none() {
+        warn!("Patchwork state didn't tell peers we're shutting down in time");
+    }
+    if ask(|reply| messenger.close_all(reply).or_log()).is_none() {
+        warn!("Messenger didn't close connections in time");
+    }
+
+    let running = instance::stop_services(Duration::from_secs(SHUTDOWN_TIMEOUT));
+    if running.is_empty() {
+        info!("Shut down");
+    } else {
+        warn!("Shut down without waiting for {:?}", running);
+    }
+}
+
+// Saves every player and, if we have a map of our own, the world
+pub fn save<P: PlayerState, B: BlockState>(player_state: P, block_state: B, config: &Config) {
+    match ask(|reply| player_state.save_all(reply).or_log()) {
+        Some(saved) => info!("Saved {:?} players", saved),
+        None => error!("Player state didn't save players in time"),
+    }
+    if config.local_map {
+        match ask(|reply| block_state.export(reply).or_log()) {
+            Some(block_ids) => match world_store::save(&config.world_file, &block_ids) {
+                Ok(()) => info!("Saved world to {:?}", config.world_file),
+                Err(e) => error!("Failed to save world to {:?}: {}", config.world_file, e),
+            },
+            None => error!("Block state didn't export the world in time"),
+        }
+    }
+}
+
+fn ask<T, F: FnOnce(Sender<T>)>(send: F) -> Option<T> {
+    let (reply_sender, reply_receiver) = channel();
+    send(reply_sender);
+    reply_receiver
+        .recv_timeout(Duration::from_secs(SHUTDOWN_TIMEOUT))
+        .ok()
+}
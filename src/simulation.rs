@@ -0,0 +1,420 @@
+use super::config::{Config, WhitelistConfig};
+use super::interfaces::patchwork::PatchworkState;
+use super::interfaces::player::{PlayerState, Position};
+use super::models::map::{map_width, Peer, Position as MapPosition};
+use super::models::player_store::SavedPlayer;
+use super::models::topology::{Topology, TopologyMap};
+use super::models::versioned;
+use super::node::{self, Node};
+use super::test_client::TestClient;
+
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long nodes get to find each other, and clients to turn up where they're expected
+const TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_PERIOD: Duration = Duration::from_millis(200);
+
+static SIMULATIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Several complete nodes running in this one process on loopback ports, with their maps in a row
+// along x so that each one borders the next. Services are shared by every node in the process and
+// only stop with it, so nodes are left running once the simulation is dropped and only their files
+// are cleaned up. Everything a node saves goes in the simulation's own directory, but the map size,
+// metrics and flight recorder are process-wide, so every simulation in a process has to use the
+// same map size and tests shouldn't count on metrics or recorded entries being theirs alone
+pub struct Simulation {
+    pub nodes: Vec<SimulatedNode>,
+    directory: PathBuf,
+}
+
+pub struct SimulatedNode {
+    pub node: Node,
+    pub peer: Peer,
+    // Where the node's map is in the world, in map units
+    pub position: MapPosition,
+}
+
+impl Simulation {
+    // The base config is shared by every node, apart from where they save and how the quilt's
+    // laid out
+    pub fn start(base: &Config, nodes: usize) -> Simulation {
+        let directory = env::temp_dir().join(format!(
+            "patchwork-simulation-{}-{}",
+            process::id(),
+            SIMULATIONS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        node::configure(base);
+
+        let peers: Vec<Peer> = (0..nodes).map(|_| free_peer()).collect();
+        let topology = Topology {
+            maps: peers
+                .iter()
+                .enumerate()
+                .map(|(index, peer)| TopologyMap {
+                    name: format!("node-{}", index),
+                    owner: peer.clone(),
+                    position: MapPosition {
+                        x: index as i32,
+                        z: 0,
+                        dimension: base.dimension,
+                    },
+                    seed: None,
+                })
+                .collect(),
+        };
+        let topology_file = directory
+            .join("topology.json")
+            .to_string_lossy()
+            .into_owned();
+        topology.save(&topology_file).unwrap();
+
+        let nodes = topology
+            .maps
+            .into_iter()
+            .map(|map| {
+                let file = |suffix: &str| {
+                    directory
+                        .join(format!("{}-{}", map.name, suffix))
+                        .to_string_lossy()
+                        .into_owned()
+                };
+                let config = Config {
+                    world_file: file("world.json"),
+                    players_directory: file("players"),
+                    advancements_file: file("advancements.json"),
+                    instance_id_file: file("instance_id"),
+                    message_log_directory: base
+                        .message_log_directory
+                        .as_ref()
+                        .map(|_| file("messages")),
+                    bans_file: file("bans.json"),
+                    operators_file: file("ops.json"),
+                    whitelist: WhitelistConfig {
+                        file: file("whitelist.json"),
+                        ..base.whitelist.clone()
+                    },
+                    instance_name: Some(map.name.clone()),
+                    topology_file: Some(topology_file.clone()),
+                    peer_registry: None,
+                    split: None,
+                    ..base.clone()
+                };
+                SimulatedNode {
+                    node: node::start(config, map.owner.clone(), None),
+                    peer: map.owner,
+                    position: map.position,
+                }
+            })
+            .collect();
+        Simulation { nodes, directory }
+    }
+
+    // Waits for every node to be connected to every other node's map
+    pub fn wait_for_links(&self) -> Result<(), String> {
+        for simulated in &self.nodes {
+            wait_for(|| {
+                let (reply, maps) = channel();
+                simulated.node.patchwork_state.describe_maps(reply).unwrap();
+                let connected = maps
+                    .recv_timeout(TIMEOUT)
+                    .ok()?
+                    .into_iter()
+                    .filter(|map| map.connected && map.owner.is_some())
+                    .count();
+                (connected == self.nodes.len() - 1).then_some(())
+            })
+            .ok_or_else(|| format!("{} never connected to every map", simulated.peer))?;
+        }
+        Ok(())
+    }
+
+    // Logs a test client in to a node, returning once it's been told where it spawned. Nodes
+    // start listening in the background, so the first clients might have to wait for them
+    pub fn join(&self, node: usize, name: &str) -> Result<TestClient, String> {
+        let client = wait_for(|| TestClient::join(&self.nodes[node].peer, name).ok())
+            .ok_or_else(|| format!("{} couldn't connect", name))?;
+        wait_for(|| client.position()).ok_or_else(|| format!("{} never spawned", name))?;
+        Ok(client)
+    }
+
+    // Has a node remember a player as if they'd logged out there, for them to come back to
+    pub fn save_player(&self, node: usize, name: &str, saved: &SavedPlayer) {
+        let directory = self.directory.join(format!("node-{}-players", node));
+        fs::create_dir_all(&directory).unwrap();
+        let contents = versioned::PLAYER.to_string(saved).unwrap();
+        fs::write(directory.join(format!("{}.json", name)), contents).unwrap();
+    }
+
+    // Where the players on a node are
+    pub fn positions(&self, node: usize) -> Vec<Position> {
+        let (reply, positions) = channel();
+        self.nodes[node].node.player_state.positions(reply).unwrap();
+        positions
+            .recv_timeout(TIMEOUT)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, position)| position)
+            .collect()
+    }
+
+    // The middle of a node's map, in world coordinates
+    pub fn map_center(&self, node: usize) -> (f64, f64) {
+        let position = self.nodes[node].position;
+        (
+            f64::from(position.x * map_width() + map_width() / 2),
+            f64::from(position.z * map_width() + map_width() / 2),
+        )
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+fn free_peer() -> Peer {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    Peer {
+        address: String::from("127.0.0.1"),
+        port: listener.local_addr().unwrap().port(),
+    }
+}
+
+// Polls until the check finds something, giving up after TIMEOUT
+pub fn wait_for<T, F: Fn() -> Option<T>>(check: F) -> Option<T> {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if let Some(found) = check() {
+            return Some(found);
+        }
+        thread::sleep(POLL_PERIOD);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interfaces::player::{Angle, Experience, Health, PLAYER_INVENTORY_SLOTS};
+    use crate::models::map::{map_size, Dimension};
+    use crate::models::packet::{Packet, PluginMessage};
+    use crate::test_client::Step;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    #[test]
+    fn clients_cross_every_border_in_a_row() {
+        let simulation = Simulation::start(&Config::default(), 3);
+        simulation.wait_for_links().unwrap();
+
+        let client = simulation.join(0, "walker").unwrap();
+        let (x, z) = simulation.map_center(2);
+        client.run(&[Step::WalkTo { x, z }]);
+
+        wait_for(|| simulation.positions(2).into_iter().next())
+            .expect("the client never turned up on the last node");
+        assert!(client.received("ChunkData") > 0);
+    }
+
+    #[test]
+    fn linked_nodes_give_each_others_maps_the_same_entity_id_block() {
+        let simulation = Simulation::start(&Config::default(), 3);
+        simulation.wait_for_links().unwrap();
+
+        // The block node i gives each peer's map, by peer
+        let blocks = || -> Vec<HashMap<Peer, i32>> {
+            simulation
+                .nodes
+                .iter()
+                .map(|simulated| {
+                    let (reply, maps) = channel();
+                    simulated.node.patchwork_state.describe_maps(reply).unwrap();
+                    maps.recv_timeout(TIMEOUT)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|map| Some((map.owner?, map.entity_id_block)))
+                        .collect()
+                })
+                .collect()
+        };
+        let agreed = wait_for(|| {
+            let blocks = blocks();
+            let nodes = &simulation.nodes;
+            (0..nodes.len())
+                .flat_map(|i| (0..nodes.len()).map(move |j| (i, j)))
+                .filter(|(i, j)| i != j)
+                .all(|(i, j)| {
+                    let given = blocks[i].get(&nodes[j].peer);
+                    given.is_some() && given == blocks[j].get(&nodes[i].peer)
+                })
+                .then_some(blocks)
+        })
+        .expect("the nodes never agreed on their entity id blocks");
+
+        for blocks in agreed {
+            let mut distinct: Vec<i32> = blocks.values().copied().collect();
+            distinct.sort_unstable();
+            distinct.dedup();
+            assert_eq!(distinct.len(), blocks.len(), "{:?}", blocks);
+        }
+    }
+
+    #[test]
+    fn plugin_messages_reach_every_player_once_across_a_full_mesh() {
+        let channel_name = String::from("patchwork:test");
+        let simulation = Simulation::start(
+            &Config {
+                plugin_channels: vec![channel_name.clone()],
+                ..Config::default()
+            },
+            3,
+        );
+        simulation.wait_for_links().unwrap();
+
+        let sender = simulation.join(0, "sender").unwrap();
+        let listeners = [
+            simulation.join(1, "first").unwrap(),
+            simulation.join(2, "second").unwrap(),
+        ];
+        sender.send(Packet::PluginMessage(PluginMessage {
+            channel: channel_name.clone(),
+            data: vec![1, 2, 3],
+        }));
+
+        // Copies passed on by the third node would turn up just after the one sent directly
+        for listener in &listeners {
+            let mut deliveries = 0;
+            listener.expect(Duration::from_secs(3), |packet| {
+                if matches!(packet, Packet::ClientboundPluginMessage(message) if message.channel == channel_name)
+                {
+                    deliveries += 1;
+                }
+                None::<()>
+            });
+            assert_eq!(
+                deliveries, 1,
+                "{} was sent the wrong number of copies",
+                listener.name
+            );
+        }
+    }
+
+    #[test]
+    fn returning_players_are_anchored_back_to_the_map_they_left_in_its_dimension() {
+        let simulation = Simulation::start(
+            &Config {
+                dimension: Dimension::Nether,
+                ..Config::default()
+            },
+            2,
+        );
+        simulation.wait_for_links().unwrap();
+
+        let (x, z) = simulation.map_center(1);
+        simulation.save_player(
+            0,
+            "returning",
+            &SavedPlayer {
+                uuid: Uuid::new_v4().to_string(),
+                position: Position { x, y: 64.0, z },
+                angle: Angle {
+                    pitch: 0.0,
+                    yaw: 0.0,
+                },
+                inventory: vec![None; PLAYER_INVENTORY_SLOTS],
+                held_item_slot: 0,
+                health: Health::default(),
+                experience: Experience::default(),
+                dimension: Dimension::Nether,
+            },
+        );
+        let client = simulation.join(0, "returning").unwrap();
+        let joined_into = client.expect(TIMEOUT, |packet| match packet {
+            Packet::JoinGame(join_game) => Some(join_game.dimension),
+            _ => None,
+        });
+        assert_eq!(joined_into, Some(Dimension::Nether.id()));
+        wait_for(|| simulation.positions(1).into_iter().next())
+            .expect("the player wasn't anchored to the second node's map");
+    }
+
+    // Peers only resend the chunks that have changed when they're asked to report again soon after
+    #[test]
+    fn players_joining_just_after_another_are_sent_the_peers_chunks_too() {
+        let simulation = Simulation::start(&Config::default(), 2);
+        simulation.wait_for_links().unwrap();
+        let peers_chunk = |packet: &Packet| match packet {
+            Packet::ChunkData(chunk) if chunk.chunk_x >= map_size() => Some(()),
+            _ => None,
+        };
+
+        let first = simulation.join(0, "first").unwrap();
+        first
+            .expect(TIMEOUT, peers_chunk)
+            .expect("first was never sent the peer's chunks");
+        let second = simulation.join(0, "second").unwrap();
+        second
+            .expect(TIMEOUT, peers_chunk)
+            .expect("second was never sent the peer's chunks");
+    }
+
+    #[test]
+    fn offline_nodes_save_their_players_and_world() {
+        let simulation = Simulation::start(
+            &Config {
+                offline: true,
+                ..Config::default()
+            },
+            1,
+        );
+        let client = simulation.join(0, "saved").unwrap();
+        wait_for(|| (client.received("ChunkData") > 0).then_some(()))
+            .expect("saved was never sent the map");
+
+        simulation.nodes[0].node.save();
+        let saved = |file: &str| simulation.directory.join(format!("node-0-{}", file));
+        assert!(saved("players").join("saved.json").is_file());
+        assert!(saved("world.json").is_file());
+    }
+
+    #[test]
+    fn visitors_are_pulled_back_when_the_map_they_are_on_asks() {
+        let simulation = Simulation::start(&Config::default(), 2);
+        simulation.wait_for_links().unwrap();
+
+        let client = simulation.join(0, "visitor").unwrap();
+        let (x, z) = simulation.map_center(1);
+        client.run(&[Step::WalkTo { x, z }]);
+        wait_for(|| simulation.positions(1).into_iter().next())
+            .expect("the client never turned up on the second node");
+
+        let (reply, kicked) = channel();
+        simulation.nodes[1]
+            .node
+            .player_state
+            .kick_visitor(String::from("visitor"), None, reply)
+            .unwrap();
+        assert!(kicked.recv_timeout(TIMEOUT).unwrap());
+        let pulled_back = wait_for(|| {
+            client
+                .position()
+                .filter(|position| position.x < f64::from(map_width()))
+        })
+        .expect("the client was never pulled back");
+
+        // Moving on from where they were put lets go of the anchor on the second node
+        client.move_to(pulled_back);
+        wait_for(|| simulation.positions(1).is_empty().then_some(()))
+            .expect("the client stayed on the second node");
+    }
+}
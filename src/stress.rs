@@ -0,0 +1,144 @@
+// A stress map: our own map filled with mobs going round in rings and bots walking in circles, all
+// moving every tick, so that broadcasting, translation and relaying to peers can be profiled under
+// load by setting the stress config alone. Neighbouring peers see the mobs and the bots near the
+// seams like any others, so they're put through their paces too
+use super::bot::Bot;
+use super::config::StressConfig;
+use super::constants::ENTITY_TICK_PERIOD;
+use super::error::OrLog;
+use super::interfaces::entity::{EntityState, Operations};
+use super::interfaces::player::{spawn_position, Position};
+use super::models::map::Peer;
+use super::services::instance;
+
+use std::f64::consts::TAU;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Pigs, which vanilla clients are quick to draw
+const STRESS_MOB_TYPE: i32 = 67;
+// Mobs are spread over this many rings, each further out than the last
+const MOB_RINGS: usize = 4;
+// Bots walk circles this many blocks across, at this many blocks a tick
+const BOT_CIRCLE_RADIUS: f64 = 4.0;
+const BOT_SPEED: f64 = 0.25;
+// How long bots have to connect and spawn before they give up
+const BOT_SPAWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Everything's set going in the background, bots once the node's listening
+pub fn start(stress: StressConfig, local_peer: Peer, entity_state: Sender<Operations>) {
+    info!(
+        "Stress map: {} circling mobs and {} bots",
+        stress.mobs, stress.players
+    );
+    summon_mobs(&stress, &entity_state);
+    for index in 0..stress.players {
+        let peer = local_peer.clone();
+        instance::spawn(&format!("stress-bot-{}", index), move || {
+            if let Err(e) = run_bot(index, &stress, &peer) {
+                warn!("Stress bot {} stopped: {}", index, e);
+            }
+        });
+    }
+}
+
+fn summon_mobs(stress: &StressConfig, entity_state: &Sender<Operations>) {
+    let per_ring = stress.mobs.div_ceil(MOB_RINGS).max(1);
+    for index in 0..stress.mobs {
+        let ring = index / per_ring;
+        let radius = stress.radius * (ring + 1) as f64 / MOB_RINGS as f64;
+        // Every other ring goes the other way round, so mobs keep passing each other
+        let angle = TAU * (index % per_ring) as f64 / per_ring as f64;
+        let radius = if ring.is_multiple_of(2) {
+            radius
+        } else {
+            -radius
+        };
+        entity_state
+            .summon_circling(STRESS_MOB_TYPE, spawn_position(), radius, angle)
+            .or_log();
+    }
+}
+
+// Each bot walks a circle of its own, the bots' circles spread evenly around the outermost ring.
+// They keep going for as long as the process runs, like the nodes do
+fn run_bot(index: usize, stress: &StressConfig, peer: &Peer) -> Result<(), String> {
+    // The node may not be listening just yet
+    let started = Instant::now();
+    let bot = loop {
+        match Bot::connect(peer) {
+            Ok(bot) => break bot,
+            Err(e) if started.elapsed() > BOT_SPAWN_TIMEOUT => return Err(e.to_string()),
+            Err(_) => thread::sleep(Duration::from_millis(ENTITY_TICK_PERIOD)),
+        }
+    };
+    bot.login(&format!("stress_{}", index));
+    while bot.position().is_none() {
+        if started.elapsed() > BOT_SPAWN_TIMEOUT {
+            return Err(String::from("never spawned"));
+        }
+        thread::sleep(Duration::from_millis(ENTITY_TICK_PERIOD));
+    }
+    let place = TAU * index as f64 / stress.players as f64;
+    let spawn = spawn_position();
+    let center = Position {
+        x: spawn.x + stress.radius * place.cos(),
+        y: spawn.y,
+        z: spawn.z + stress.radius * place.sin(),
+    };
+    let mut angle: f64 = 0.0;
+    loop {
+        angle += BOT_SPEED / BOT_CIRCLE_RADIUS;
+        bot.move_to(Position {
+            x: center.x + BOT_CIRCLE_RADIUS * angle.cos(),
+            y: center.y,
+            z: center.z + BOT_CIRCLE_RADIUS * angle.sin(),
+        });
+        thread::sleep(Duration::from_millis(ENTITY_TICK_PERIOD));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::models::packet::Packet;
+    use crate::simulation::Simulation;
+    use std::collections::HashSet;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    // The test client can't read mob packets, which are numbered for peers, so only the bots are
+    // looked for
+    #[test]
+    fn bots_join_and_keep_walking() {
+        let simulation = Simulation::start(
+            &Config {
+                offline: true,
+                stress: Some(StressConfig {
+                    mobs: 8,
+                    players: 2,
+                    radius: 8.0,
+                }),
+                ..Config::default()
+            },
+            1,
+        );
+        let client = simulation.join(0, "profiler").unwrap();
+        let (mut bots, mut moved) = (HashSet::new(), HashSet::new());
+        let seen = client.expect(TIMEOUT, |packet| {
+            match packet {
+                Packet::SpawnPlayer(spawn) => {
+                    bots.insert(spawn.entity_id);
+                }
+                Packet::EntityLookAndMove(movement) if bots.contains(&movement.entity_id) => {
+                    moved.insert(movement.entity_id);
+                }
+                _ => {}
+            }
+            Some(()).filter(|_| moved.len() == 2)
+        });
+        assert!(seen.is_some(), "{:?} of {:?} moved", moved, bots);
+    }
+}
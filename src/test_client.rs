@@ -0,0 +1,185 @@
+use super::bot::Bot;
+use super::interfaces::player::Position;
+use super::models::map::Peer;
+use super::models::packet::Packet;
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Test clients walk this many blocks every step
+const STEP: f64 = 0.5;
+const STEP_PERIOD: Duration = Duration::from_millis(50);
+
+// A bot for joining a running node from tests, which keeps everything it's sent for whoever's
+// asking with expect
+pub struct TestClient {
+    pub name: String,
+    bot: Bot,
+    received: Arc<Mutex<HashMap<&'static str, usize>>>,
+    packets: Receiver<Packet>,
+}
+
+// Something for a test client to do, one after the other
+#[derive(Debug, Clone)]
+pub enum Step {
+    // Walks in a straight line from wherever the client is
+    WalkTo { x: f64, z: f64 },
+    Chat(String),
+    Wait(Duration),
+}
+
+impl TestClient {
+    pub fn join(peer: &Peer, name: &str) -> io::Result<TestClient> {
+        let bot = Bot::connect(peer)?;
+        let received = Arc::new(Mutex::new(HashMap::new()));
+        let (packet_sender, packets) = channel();
+        let counts = received.clone();
+        bot.on_packet(move |packet| {
+            *counts.lock().unwrap().entry(packet.name()).or_insert(0) += 1;
+            // Nobody's listening once the client's been dropped
+            let _ = packet_sender.send(packet.clone());
+        });
+        bot.login(name);
+        Ok(TestClient {
+            name: String::from(name),
+            bot,
+            received,
+            packets,
+        })
+    }
+
+    // Where the client is, once it's been told where it spawned
+    pub fn position(&self) -> Option<Position> {
+        self.bot.position()
+    }
+
+    // How many of the named packet the client's been sent
+    pub fn received(&self, packet: &str) -> usize {
+        self.received
+            .lock()
+            .unwrap()
+            .get(packet)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Goes through what the client's been sent, in order, until the check picks one out. Packets
+    // passed over on the way are gone for good
+    pub fn expect<T, F: FnMut(&Packet) -> Option<T>>(
+        &self,
+        timeout: Duration,
+        mut check: F,
+    ) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            let packet = self.packets.recv_timeout(remaining).ok()?;
+            if let Some(found) = check(&packet) {
+                return Some(found);
+            }
+        }
+    }
+
+    pub fn send(&self, packet: Packet) {
+        self.bot.send(packet);
+    }
+
+    pub fn move_to(&self, position: Position) {
+        self.bot.move_to(position);
+    }
+
+    pub fn chat(&self, message: &str) {
+        self.bot.send_chat(message);
+    }
+
+    pub fn run(&self, script: &[Step]) {
+        for step in script {
+            match step {
+                Step::WalkTo { x, z } => self.walk_to(*x, *z),
+                Step::Chat(message) => self.chat(message),
+                Step::Wait(duration) => thread::sleep(*duration),
+            }
+        }
+    }
+
+    fn walk_to(&self, x: f64, z: f64) {
+        let from = match self.position() {
+            Some(position) => position,
+            None => return,
+        };
+        let distance = ((x - from.x).powi(2) + (z - from.z).powi(2)).sqrt();
+        let steps = (distance / STEP).ceil() as i32;
+        for step in 1..=steps {
+            let progress = f64::from(step) / f64::from(steps);
+            self.move_to(Position {
+                x: from.x + (x - from.x) * progress,
+                y: from.y,
+                z: from.z + (z - from.z) * progress,
+            });
+            thread::sleep(STEP_PERIOD);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::simulation::{self, Simulation};
+
+    // How long each look through what a client's been sent lasts, while waiting on a condition
+    const POLL: Duration = Duration::from_millis(100);
+
+    // Waits for a chat message containing the text to reach the client, returning all of it
+    fn chat_containing(client: &TestClient, text: &str) -> Option<String> {
+        simulation::wait_for(|| {
+            client.expect(POLL, |packet| match packet {
+                Packet::ClientboundChatMessage(chat) if chat.json_data.contains(text) => {
+                    Some(chat.json_data.clone())
+                }
+                _ => None,
+            })
+        })
+    }
+
+    #[test]
+    fn players_are_sent_the_map_and_each_others_chat() {
+        let simulation = Simulation::start(
+            &Config {
+                offline: true,
+                ..Config::default()
+            },
+            1,
+        );
+        let first = simulation.join(0, "first").unwrap();
+        simulation::wait_for(|| (first.received("ChunkData") > 0).then_some(()))
+            .expect("first was never sent the map");
+
+        let second = simulation.join(0, "second").unwrap();
+        second.chat("hello");
+        let chat = chat_containing(&first, "hello").expect("second's chat never reached first");
+        assert!(chat.contains("second"));
+    }
+
+    #[test]
+    fn players_joining_late_are_caught_up_on_chat() {
+        let simulation = Simulation::start(
+            &Config {
+                offline: true,
+                ..Config::default()
+            },
+            1,
+        );
+        let early = simulation.join(0, "early").unwrap();
+        early.chat("anyone there?");
+        chat_containing(&early, "anyone").expect("early's chat never came back to them");
+
+        let late = simulation.join(0, "late").unwrap();
+        let chat = chat_containing(&late, "anyone").expect("late was never caught up on chat");
+        assert!(chat.contains("early"));
+    }
+}
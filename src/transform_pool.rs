@@ -0,0 +1,204 @@
+use super::error::PatchworkError;
+use super::models::compression;
+use super::models::packet::{translate_outgoing, Packet};
+use super::models::protocol_adapter::ProtocolAdapter;
+use super::models::translation::TranslationInfo;
+use super::packet_capture::{self, Direction};
+use super::services::instance;
+
+use std::collections::VecDeque;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use uuid::Uuid;
+
+// Translating, serializing and compressing the bigger packets (and, once it's supported,
+// encrypting them) is slow enough to hold up every other connection if the messenger did it on
+// its own thread. Those packets are handed to a small pool of workers instead. Every connection
+// is pinned to one worker, so its packets still go out in the order they were sent, apart from
+// peer links' control packets, which go ahead of any bulk ones still waiting
+pub struct TransformPool {
+    workers: Vec<Sender<Job>>,
+}
+
+// Peer links carry two kinds of packet over the one socket. Control packets keep the link and the
+// quilt going (heartbeats, border crossings, authentication and topology changes) and are small, so
+// they're written before anything bulky, like a state report's chunks, that's queued ahead of them.
+// Each kind still goes out in the order it was sent. Everything sent to clients is bulk, as they
+// expect packets in the order we sent them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Control,
+    Bulk,
+}
+
+struct Job {
+    channel: Channel,
+    conn_id: Uuid,
+    socket: TcpStream,
+    packet: Packet,
+    translation: Option<TranslationInfo>,
+    adapter: ProtocolAdapter,
+    compression_threshold: Option<usize>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl TransformPool {
+    pub fn new(size: usize) -> TransformPool {
+        let workers = (0..size)
+            .map(|index| {
+                let (sender, receiver) = channel::<Job>();
+                instance::spawn(&format!("transform-{}", index), move || {
+                    let mut backlog = Backlog::default();
+                    loop {
+                        // Everything that's arrived is looked at before picking what to write next,
+                        // so that control packets can go ahead of bulk ones
+                        if backlog.is_empty() {
+                            match receiver.recv() {
+                                Ok(job) => backlog.push(job.channel, job),
+                                Err(_) => break,
+                            }
+                        }
+                        while let Ok(job) = receiver.try_recv() {
+                            backlog.push(job.channel, job);
+                        }
+                        if let Some(job) = backlog.pop() {
+                            write(job);
+                        }
+                    }
+                });
+                sender
+            })
+            .collect();
+        TransformPool { workers }
+    }
+
+    // in_flight is the connection's count of packets still waiting on the pool. Anything sent
+    // while it is above zero has to go through the pool too, or it would overtake them
+    #[allow(clippy::too_many_arguments)]
+    pub fn submit(
+        &self,
+        channel: Channel,
+        conn_id: Uuid,
+        socket: TcpStream,
+        packet: Packet,
+        translation: Option<TranslationInfo>,
+        adapter: ProtocolAdapter,
+        compression_threshold: Option<usize>,
+        in_flight: Arc<AtomicUsize>,
+    ) {
+        in_flight.fetch_add(1, Ordering::AcqRel);
+        let worker = conn_id.as_u128() as usize % self.workers.len();
+        let sent = self.workers[worker].send(Job {
+            channel,
+            conn_id,
+            socket,
+            packet,
+            translation,
+            adapter,
+            compression_threshold,
+            in_flight,
+        });
+        if let Err(unsent) = sent {
+            unsent.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+            error!(
+                "Dropped packet for conn_id {:?}: {}",
+                conn_id,
+                PatchworkError::ServiceStopped("transform pool worker")
+            );
+        }
+    }
+}
+
+fn write(mut job: Job) {
+    let packet = match job.translation {
+        Some(translation) => translate_outgoing(job.packet, translation),
+        None => job.packet,
+    };
+    packet_capture::packet(job.conn_id, Direction::Outbound, &packet);
+    compression::write(
+        &mut job.socket,
+        job.adapter,
+        packet,
+        job.compression_threshold,
+    );
+    job.in_flight.fetch_sub(1, Ordering::AcqRel);
+}
+
+// What's waiting on a worker, control first
+struct Backlog<T> {
+    control: VecDeque<T>,
+    bulk: VecDeque<T>,
+}
+
+impl<T> Default for Backlog<T> {
+    fn default() -> Backlog<T> {
+        Backlog {
+            control: VecDeque::new(),
+            bulk: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> Backlog<T> {
+    fn push(&mut self, channel: Channel, item: T) {
+        match channel {
+            Channel::Control => self.control.push_back(item),
+            Channel::Bulk => self.bulk.push_back(item),
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.control.pop_front().or_else(|| self.bulk.pop_front())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.control.is_empty() && self.bulk.is_empty()
+    }
+}
+
+pub fn is_expensive(packet: &Packet) -> bool {
+    matches!(packet, Packet::ChunkData(_) | Packet::Advancements(_))
+}
+
+// Which channel a packet goes out on when it's sent over a peer link
+pub fn link_channel(packet: &Packet) -> Channel {
+    match packet {
+        Packet::PeerHeartbeat(_)
+        | Packet::PeerShutdown(_)
+        | Packet::PeerAuth(_)
+        | Packet::PeerAuthChallenge(_)
+        | Packet::PeerIdentity(_)
+        | Packet::BorderCrossLogin(_)
+        | Packet::PeerKickback(_)
+        | Packet::PeerGossip(_)
+        | Packet::PeerTimeUpdate(_)
+        | Packet::MapPositionProposal(_)
+        | Packet::MapPositionAgreement(_)
+        | Packet::MapOwnerChange(_)
+        | Packet::EntityOwnerQuery(_)
+        | Packet::EntityOwnerReply(_) => Channel::Control,
+        _ => Channel::Bulk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_goes_ahead_of_bulk_but_each_keeps_its_order() {
+        let mut backlog = Backlog::default();
+        backlog.push(Channel::Bulk, "chunk 1");
+        backlog.push(Channel::Bulk, "chunk 2");
+        backlog.push(Channel::Control, "heartbeat");
+        backlog.push(Channel::Control, "border crossing");
+        let order: Vec<_> = std::iter::from_fn(|| backlog.pop()).collect();
+        assert_eq!(
+            order,
+            ["heartbeat", "border crossing", "chunk 1", "chunk 2"]
+        );
+        assert!(backlog.is_empty());
+    }
+}